@@ -0,0 +1,272 @@
+use anyhow::{anyhow, Result};
+use clap::{Parser, Subcommand};
+use orca::{
+    bench::{self, BenchReport},
+    bundle::BundleBuilder,
+    llm::{
+        bert::Bert,
+        quantized::{Model, Quantized},
+    },
+    pipeline::ingest::IngestPipeline,
+    record::{html::HTML, pdf::Pdf, Content, Record, Spin},
+};
+
+#[derive(Parser, Debug)]
+#[command(name = "orca", author, version, about, long_about = None)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Measure the throughput of local models and print it as JSON
+    Bench {
+        #[command(subcommand)]
+        target: Target,
+
+        #[clap(long, default_value_t = 3)]
+        /// The number of timed runs of each benchmark
+        runs: usize,
+
+        #[clap(long)]
+        /// The file to save the JSON report to
+        output: Option<String>,
+
+        #[clap(long)]
+        /// A previous JSON report to compare with, failing on regressions
+        baseline: Option<String>,
+
+        #[clap(long, default_value_t = 0.1)]
+        /// The drop of throughput from the baseline tolerated, e.g. 0.1 for 10%
+        tolerance: f64,
+    },
+
+    /// Index PDF, HTML and text files into a bundle that can be searched offline
+    Pack {
+        /// The files to index
+        files: Vec<String>,
+
+        #[clap(long)]
+        /// The directory to write the bundle to
+        output: String,
+
+        #[clap(long, default_value = "docs")]
+        /// The name of the collection of the bundle
+        collection: String,
+
+        #[clap(long)]
+        /// The Hugging Face id of the embedding model, all-MiniLM-L6-v2 by default
+        model_id: Option<String>,
+
+        #[clap(long, default_value_t = 399)]
+        /// The maximum number of tokens of the embedded chunks
+        chunk_tokens: usize,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum Target {
+    /// Embeddings per second of a Bert model
+    Embeddings {
+        #[clap(long)]
+        /// The Hugging Face id of the model, all-MiniLM-L6-v2 by default
+        model_id: Option<String>,
+
+        #[clap(long, value_delimiter = ',', default_value = "1,8,32")]
+        /// The batch sizes to measure
+        batch_sizes: Vec<usize>,
+
+        #[clap(long, value_delimiter = ',', default_value = "cpu")]
+        /// The devices to measure, cpu and/or gpu
+        devices: Vec<String>,
+
+        #[clap(long, default_value_t = 64)]
+        /// The number of texts embedded in each run
+        texts: usize,
+    },
+
+    /// Tokens per second of a quantized model
+    Generation {
+        #[clap(long)]
+        /// The path to the GGUF or GGML model file
+        model_path: String,
+
+        #[clap(long, default_value = "mistral7b-instruct")]
+        /// The model, e.g. llama7b-chat or mistral7b-instruct
+        model: String,
+
+        #[clap(long, default_value_t = 64)]
+        /// The number of tokens to generate
+        sample_len: usize,
+
+        #[clap(long, value_delimiter = ',', default_value = "0,0.8")]
+        /// The sampling temperatures to measure
+        temperatures: Vec<f64>,
+
+        #[clap(long)]
+        /// The nucleus sampling probability cutoff
+        top_p: Option<f64>,
+
+        #[clap(long, default_value_t = 1.0)]
+        /// The penalty applied to repeated tokens
+        repeat_penalty: f32,
+
+        #[clap(long, default_value = "Write a short story about a pod of orcas.")]
+        /// The prompt to generate from
+        prompt: String,
+    },
+}
+
+fn parse_model(name: &str) -> Result<Model> {
+    Ok(match name {
+        "llama7b" => Model::L7b,
+        "llama13b" => Model::L13b,
+        "llama70b" => Model::L70b,
+        "llama7b-chat" => Model::L7bChat,
+        "llama13b-chat" => Model::L13bChat,
+        "llama70b-chat" => Model::L70bChat,
+        "codellama7b" => Model::L7bCode,
+        "codellama13b" => Model::L13bCode,
+        "codellama34b" => Model::L34bCode,
+        "mistral7b" => Model::Mistral7b,
+        "mistral7b-instruct" => Model::Mistral7bInstruct,
+        _ => return Err(anyhow!("Unknown model {}", name)),
+    })
+}
+
+/// Read a file as a record, by extension.
+fn read_record(path: &str) -> Result<Record> {
+    match std::path::Path::new(path).extension().and_then(|extension| extension.to_str()) {
+        Some("pdf") => Pdf::from_file(path, false)?.spin(),
+        Some("html") | Some("htm") => HTML::from_file(path)?.spin(),
+        _ => Ok(Record::new(Content::String(std::fs::read_to_string(path)?))),
+    }
+}
+
+async fn pack(
+    files: Vec<String>,
+    output: String,
+    collection: String,
+    model_id: Option<String>,
+    chunk_tokens: usize,
+) -> Result<()> {
+    let model_id = model_id.unwrap_or_else(|| "sentence-transformers/all-MiniLM-L6-v2".to_string());
+    let bert = Bert::new().with_model_id(&model_id).build_model_and_tokenizer().await?;
+    let records = files.iter().map(|file| read_record(file)).collect::<Result<Vec<_>>>()?;
+    let bundle = BundleBuilder::new(&output, &collection)?.with_embedding_model(&model_id);
+    IngestPipeline::new(bundle.store(), &bert, bundle.collection())
+        .with_parents(bundle.docs(), chunk_tokens)
+        .ingest(records)
+        .await?;
+    let manifest = bundle.finish()?;
+    println!(
+        "Packed {} documents ({} chunks) into {}",
+        manifest.documents, manifest.points, output
+    );
+    Ok(())
+}
+
+async fn run(target: Target, runs: usize, report: &mut BenchReport) -> Result<()> {
+    match target {
+        Target::Embeddings {
+            model_id,
+            batch_sizes,
+            devices,
+            texts,
+        } => {
+            let texts: Vec<String> =
+                (0..texts).map(|i| format!("Orcas are the largest dolphins, and pod {} hunts seals.", i)).collect();
+            for device in devices {
+                let bert = match device.as_str() {
+                    "cpu" => Bert::new(),
+                    "gpu" => Bert::new().with_cpu(),
+                    _ => return Err(anyhow!("Unknown device {}, use cpu or gpu", device)),
+                };
+                let bert = match &model_id {
+                    Some(model_id) => bert.with_model_id(model_id),
+                    None => bert,
+                };
+                let bert = bert.build_model_and_tokenizer().await?;
+                for &batch_size in &batch_sizes {
+                    let measurement = bench::embeddings_per_sec(&bert, &texts, batch_size, runs).await?;
+                    report.push(measurement.with_param("device", device.as_str()));
+                }
+            }
+        }
+        Target::Generation {
+            model_path,
+            model,
+            sample_len,
+            temperatures,
+            top_p,
+            repeat_penalty,
+            prompt,
+        } => {
+            let model = parse_model(&model)?;
+            for temperature in temperatures {
+                let quantized = Quantized::new()
+                    .with_model(model)
+                    .with_sample_len(sample_len)
+                    .with_temperature(temperature)
+                    .with_repeat_penalty(repeat_penalty, 64);
+                let quantized = match top_p {
+                    Some(top_p) => quantized.with_top_p(top_p),
+                    None => quantized,
+                };
+                let quantized = quantized.load_model_from_path(&model_path)?.build_model()?;
+                report.push(bench::tokens_per_sec(&quantized, &prompt, runs).await?);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+
+    // init logger
+    env_logger::init();
+
+    match args.command {
+        Command::Bench {
+            target,
+            runs,
+            output,
+            baseline,
+            tolerance,
+        } => {
+            let mut report = BenchReport::new();
+            run(target, runs, &mut report).await?;
+            println!("{}", report.to_json()?);
+            if let Some(output) = output {
+                report.save(output)?;
+            }
+
+            if let Some(baseline) = baseline {
+                let regressions = report.regressions(&BenchReport::load(baseline)?, tolerance);
+                for regression in &regressions {
+                    eprintln!(
+                        "{}: {:.2} -> {:.2} ({:+.1}%)",
+                        regression.key,
+                        regression.baseline,
+                        regression.current,
+                        (regression.current / regression.baseline - 1.0) * 100.0
+                    );
+                }
+                if !regressions.is_empty() {
+                    return Err(anyhow!("{} benchmarks regressed", regressions.len()));
+                }
+            }
+        }
+        Command::Pack {
+            files,
+            output,
+            collection,
+            model_id,
+            chunk_tokens,
+        } => pack(files, output, collection, model_id, chunk_tokens).await?,
+    }
+    Ok(())
+}
@@ -1,15 +1,21 @@
 use anyhow::{Context, Result};
 use clap::Parser;
 use orca::{
-    llm::{bert::Bert, quantized::Quantized, Embedding},
+    llm::{
+        bert::Bert,
+        cohere::{Cohere, InputType},
+        quantized::Quantized,
+        Embedding, LLM,
+    },
     memory::Buffer,
+    pipeline::assembler::ContextAssembler,
+    pipeline::ingest::IngestPipeline,
     pipeline::simple::LLMPipeline,
     pipeline::Pipeline,
     prompt,
     prompt::context::Context as OrcaContext,
-    prompts,
     qdrant::Qdrant,
-    record::{pdf::Pdf, Spin},
+    record::{pdf::Pdf, Record, Spin},
 };
 use rand::Rng;
 use serde_json::json;
@@ -24,6 +30,14 @@ struct Args {
     #[clap(long)]
     /// The prompt to use to query the index
     prompt: String,
+
+    #[clap(long)]
+    /// Resume an interrupted indexing of the file, retrying the chunks that failed
+    resume: bool,
+
+    #[clap(long)]
+    /// Embed and answer with Cohere, reading the API key from COHERE_API_KEY, instead of local models
+    cohere: bool,
 }
 
 #[tokio::main]
@@ -39,8 +53,31 @@ async fn main() -> Result<()> {
         .context("Failed to process PDF spin")?
         .split(399);
 
+    if args.cohere {
+        // Cohere embeds documents and queries differently
+        let cohere = Cohere::new();
+        let queries = cohere.clone().with_input_type(InputType::SearchQuery);
+        return run(&args, pdf_records, &cohere, &queries, &cohere).await;
+    }
+
     let bert = Bert::new().build_model_and_tokenizer().await?;
+    let mistral = Quantized::new()
+        .with_model(orca::llm::quantized::Model::Mistral7bInstruct)
+        .with_sample_len(4000)
+        .with_seed(rand::thread_rng().gen_range(0..100))
+        .load_model_from_path("../../weights/mistral-7b-instruct-v0.1.Q4_K_M.gguf")?
+        .build_model()?;
+    run(&args, pdf_records, &bert, &bert, &mistral).await
+}
 
+/// Index the records with the document embedder, then answer the prompts with the LLM from the records found
+/// with the query embedder.
+async fn run<E, Q, M>(args: &Args, pdf_records: Vec<Record>, embedder: &E, queries: &Q, llm: &M) -> Result<()>
+where
+    E: Embedding + Send + Sync,
+    Q: Embedding + Send + Sync,
+    M: LLM + Clone + 'static,
+{
     let collection = std::path::Path::new(&args.file)
         .file_stem()
         .and_then(|name| name.to_str())
@@ -49,15 +86,31 @@ async fn main() -> Result<()> {
 
     // Initialize Qdrant
     let qdrant = Qdrant::new("http://localhost:6334")?;
-    qdrant.create_collection(&collection, 384).await?;
 
-    // Generate embeddings and insert into Qdrant
-    let embeddings = bert.generate_embeddings(prompts!(&pdf_records)).await?;
-    qdrant.insert_many(&collection, embeddings.to_vec2()?, pdf_records).await?;
+    // Generate embeddings and insert into Qdrant, saving the progress to resume interrupted runs
+    let checkpoint = format!("{}.checkpoint.json", collection);
+    if !args.resume {
+        // Start over from an empty collection
+        if qdrant.collection_dimensions(&collection).await?.is_some() {
+            qdrant.delete_collection(&collection).await?;
+        }
+        match std::fs::remove_file(&checkpoint) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    let report = IngestPipeline::new(&qdrant, embedder, &collection)
+        .with_checkpoint(&checkpoint)
+        .ingest_source(&args.file, pdf_records)
+        .await?;
+    println!(
+        "Indexed {} chunks ({} already indexed, {} failed; rerun with --resume to retry them)",
+        report.stored, report.skipped, report.failed
+    );
 
     // Use prompt to query Qdrant
-    let query_embedding = bert.generate_embedding(prompt!(args.prompt)).await?;
-    let result = qdrant.search(&collection, query_embedding.to_vec()?.clone(), 1, None).await?;
+    let query_embedding = queries.generate_embedding(prompt!(args.prompt.clone())).await?;
+    let result = qdrant.search(&collection, query_embedding.to_vec()?, 5, None, Some(0.3)).await?;
 
     let prompt_for_model = r#"
     {{#chat}}
@@ -72,10 +125,8 @@ async fn main() -> Result<()> {
 
         {{#system}}
         Based on the retrieved information from the PDF, here are the relevant excerpts:
-        
-        {{#each payloads}}
-        {{this}}
-        {{/each}}
+
+        {{documents}}
 
         Please provide a comprehensive answer to the user's question, integrating insights from these excerpts and your general knowledge.
         {{/system}}
@@ -83,26 +134,13 @@ async fn main() -> Result<()> {
     {{/chat}}
     "#;
 
+    let records = result.iter().filter_map(|found_point| found_point.to_record()).collect();
     let context = json!({
         "user_prompt": args.prompt,
-        "payloads": result
-            .iter()
-            .filter_map(|found_point| {
-                found_point.payload.as_ref().map(|payload| {
-                    serde_json::to_string(payload).unwrap_or_else(|_| "{}".to_string())
-                })
-            })
-            .collect::<Vec<String>>()
+        "documents": ContextAssembler::new().with_max_tokens(2000).format(records),
     });
 
-    let mistral = Quantized::new()
-        .with_model(orca::llm::quantized::Model::Mistral7bInstruct)
-        .with_sample_len(4000)
-        .with_seed(rand::thread_rng().gen_range(0..100))
-        .load_model_from_path("../../weights/mistral-7b-instruct-v0.1.Q4_K_M.gguf")?
-        .build_model()?;
-
-    let pipe = LLMPipeline::new(&mistral)
+    let pipe = LLMPipeline::new(llm)
         .load_template("query", prompt_for_model)?
         .load_context(&OrcaContext::new(context)?)?
         .load_memory(Buffer::new());
@@ -0,0 +1,36 @@
+//! Embeddings per second of Bert by batch size, on CPU and, with `ORCA_BENCH_GPU` set, on GPU.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use orca_core::llm::bert::Bert;
+use orca_core::llm::Embedding;
+use orca_core::prompts;
+
+const BATCH_SIZES: [usize; 3] = [1, 8, 32];
+
+fn texts(count: usize) -> Vec<String> {
+    (0..count).map(|i| format!("Orcas are the largest dolphins, and pod {} hunts seals.", i)).collect()
+}
+
+fn embeddings(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut devices = vec![("cpu", Bert::new())];
+    if std::env::var("ORCA_BENCH_GPU").is_ok() {
+        devices.push(("gpu", Bert::new().with_cpu()));
+    }
+
+    let mut group = c.benchmark_group("embeddings");
+    for (device, bert) in devices {
+        let bert = runtime.block_on(bert.build_model_and_tokenizer()).unwrap();
+        for batch_size in BATCH_SIZES {
+            let batch = texts(batch_size);
+            group.throughput(Throughput::Elements(batch_size as u64));
+            group.bench_with_input(BenchmarkId::new(device, batch_size), &batch, |b, batch| {
+                b.to_async(&runtime).iter(|| async { bert.generate_embeddings(prompts!(batch)).await.unwrap() })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, embeddings);
+criterion_main!(benches);
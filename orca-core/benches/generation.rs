@@ -0,0 +1,44 @@
+//! Generation latency of a quantized model loaded from `ORCA_BENCH_MODEL`, e.g. a Mistral 7B Instruct
+//! GGUF file, with greedy and nucleus sampling. Skipped when the variable is not set.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use orca_core::llm::quantized::{Model, Quantized};
+use orca_core::llm::LLM;
+use orca_core::prompt;
+
+const SAMPLE_LEN: usize = 32;
+
+fn generation(c: &mut Criterion) {
+    let Ok(path) = std::env::var("ORCA_BENCH_MODEL") else {
+        eprintln!("ORCA_BENCH_MODEL is not set, skipping the generation benches");
+        return;
+    };
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let samplings = [
+        ("greedy", Quantized::new().with_temperature(0.)),
+        ("top_p", Quantized::new().with_temperature(0.8).with_top_p(0.9)),
+        (
+            "repeat_penalty",
+            Quantized::new().with_temperature(0.8).with_repeat_penalty(1.1, 64),
+        ),
+    ];
+
+    let mut group = c.benchmark_group("generation");
+    group.sample_size(10);
+    for (sampling, model) in samplings {
+        let model = model
+            .with_model(Model::Mistral7bInstruct)
+            .with_sample_len(SAMPLE_LEN)
+            .load_model_from_path(&path)
+            .and_then(Quantized::build_model)
+            .unwrap();
+        group.bench_function(BenchmarkId::new(sampling, SAMPLE_LEN), |b| {
+            b.to_async(&runtime)
+                .iter(|| async { model.generate(prompt!("Where do orcas live?")).await.unwrap() })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, generation);
+criterion_main!(benches);
@@ -0,0 +1,228 @@
+//! Throughput benchmarks of local models.
+//!
+//! The functions of this module measure the embeddings per second of an embedding model and the
+//! tokens per second of a quantized model, and `BenchReport` collects the measurements as JSON. Reports
+//! saved before upgrading candle can be compared with later ones to catch performance regressions, as
+//! the `orca bench` command and the criterion benches of this crate do.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Instant;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::llm::quantized::Quantized;
+use crate::llm::{Embedding, LLM};
+use crate::prompts;
+
+/// Throughput of a benchmark run with given parameters.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Measurement {
+    /// Name of the benchmark, e.g. `embeddings`.
+    pub name: String,
+
+    /// Parameters of the run, e.g. the batch size and device.
+    pub params: BTreeMap<String, Value>,
+
+    /// Number of timed runs.
+    pub runs: usize,
+
+    /// Number of items processed over all runs, e.g. embeddings or generated tokens.
+    pub items: usize,
+
+    /// Time taken by all runs, in seconds.
+    pub seconds: f64,
+
+    /// Items processed per second.
+    pub throughput: f64,
+
+    /// Unit of the throughput, e.g. `embeddings/s`.
+    pub unit: String,
+}
+
+impl Measurement {
+    fn new(name: &str, unit: &str, runs: usize, items: usize, seconds: f64) -> Self {
+        Measurement {
+            name: name.to_string(),
+            params: BTreeMap::new(),
+            runs,
+            items,
+            seconds,
+            throughput: if seconds > 0.0 { items as f64 / seconds } else { 0.0 },
+            unit: unit.to_string(),
+        }
+    }
+
+    /// Add a parameter of the run, e.g. the device.
+    pub fn with_param<T: Into<Value>>(mut self, name: &str, value: T) -> Self {
+        self.params.insert(name.to_string(), value.into());
+        self
+    }
+
+    /// Key identifying the benchmark and its parameters across reports.
+    pub fn key(&self) -> String {
+        let params: Vec<String> = self.params.iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+        format!("{}[{}]", self.name, params.join(","))
+    }
+}
+
+/// Measurement slower than the one with the same key in a baseline report.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Regression {
+    /// Key of the measurements.
+    pub key: String,
+
+    /// Throughput in the baseline report.
+    pub baseline: f64,
+
+    /// Throughput in the current report.
+    pub current: f64,
+}
+
+/// Measurements of a benchmark session.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BenchReport {
+    /// Version of orca the measurements were taken with.
+    pub version: String,
+
+    /// The measurements.
+    pub measurements: Vec<Measurement>,
+}
+
+impl BenchReport {
+    /// Create an empty report for the current version of orca.
+    pub fn new() -> Self {
+        BenchReport {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            measurements: Vec::new(),
+        }
+    }
+
+    /// Add a measurement to the report.
+    pub fn push(&mut self, measurement: Measurement) {
+        self.measurements.push(measurement);
+    }
+
+    /// Load a report from a JSON file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    }
+
+    /// Save the report to a JSON file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        std::fs::write(path, self.to_json()?)?;
+        Ok(())
+    }
+
+    /// The report as pretty printed JSON.
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Measurements whose throughput dropped by more than `tolerance`, e.g. `0.1` for 10%, from the
+    /// measurement with the same key in the baseline. Measurements missing from the baseline are ignored.
+    pub fn regressions(&self, baseline: &BenchReport, tolerance: f64) -> Vec<Regression> {
+        let baseline: BTreeMap<String, f64> = baseline
+            .measurements
+            .iter()
+            .map(|measurement| (measurement.key(), measurement.throughput))
+            .collect();
+        self.measurements
+            .iter()
+            .filter_map(|measurement| {
+                let key = measurement.key();
+                let before = *baseline.get(&key)?;
+                (measurement.throughput < before * (1.0 - tolerance)).then_some(Regression {
+                    key,
+                    baseline: before,
+                    current: measurement.throughput,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Measure the embeddings per second of a model embedding texts in batches, over `runs` runs after a
+/// warm-up batch. The measurement has the batch size as parameter.
+pub async fn embeddings_per_sec<E: Embedding + ?Sized>(
+    embedder: &E,
+    texts: &[String],
+    batch_size: usize,
+    runs: usize,
+) -> Result<Measurement> {
+    if texts.is_empty() {
+        return Err(anyhow!("No texts to embed"));
+    }
+    let batch_size = batch_size.max(1);
+    embedder.generate_embeddings(prompts!(&texts[..batch_size.min(texts.len())])).await?;
+
+    let start = Instant::now();
+    for _ in 0..runs {
+        for batch in texts.chunks(batch_size) {
+            embedder.generate_embeddings(prompts!(batch)).await?;
+        }
+    }
+    let seconds = start.elapsed().as_secs_f64();
+    Ok(
+        Measurement::new("embeddings", "embeddings/s", runs, texts.len() * runs, seconds)
+            .with_param("batch_size", batch_size)
+            .with_param("dimensions", embedder.dimensions()),
+    )
+}
+
+/// Measure the tokens per second a quantized model generates from a prompt, over `runs` runs. The
+/// measurement has the model and sampling parameters as parameters.
+pub async fn tokens_per_sec(model: &Quantized, prompt: &str, runs: usize) -> Result<Measurement> {
+    let mut tokens = 0;
+    let mut seconds = 0.0;
+    for _ in 0..runs {
+        let start = Instant::now();
+        let response = model.generate(crate::prompt!(prompt)).await?;
+        seconds += start.elapsed().as_secs_f64();
+        tokens += model.count_tokens(&response.to_string()).await?;
+    }
+    let mut measurement = Measurement::new("generation", "tokens/s", runs, tokens, seconds);
+    measurement.params.extend(model.sample_params());
+    Ok(measurement)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::ConstantEmbedder;
+
+    #[tokio::test]
+    async fn test_embeddings_per_sec() {
+        let texts: Vec<String> = (0..10).map(|i| i.to_string()).collect();
+        let measurement =
+            embeddings_per_sec(&ConstantEmbedder(1.0), &texts, 4, 3).await.unwrap().with_param("device", "cpu");
+        assert_eq!(measurement.items, 30);
+        assert_eq!(
+            measurement.key(),
+            "embeddings[batch_size=4,device=\"cpu\",dimensions=1]"
+        );
+        assert!(embeddings_per_sec(&ConstantEmbedder(1.0), &[], 4, 3).await.is_err());
+    }
+
+    #[test]
+    fn test_regressions() {
+        let measurement = |batch_size: usize, seconds: f64| {
+            Measurement::new("embeddings", "embeddings/s", 1, 100, seconds).with_param("batch_size", batch_size)
+        };
+        let mut baseline = BenchReport::new();
+        baseline.push(measurement(1, 1.0));
+        baseline.push(measurement(8, 1.0));
+        let mut current = BenchReport::new();
+        current.push(measurement(1, 1.05));
+        current.push(measurement(8, 2.0));
+        current.push(measurement(32, 9.0));
+
+        let current: BenchReport = serde_json::from_str(&current.to_json().unwrap()).unwrap();
+        let regressions = current.regressions(&baseline, 0.1);
+        assert_eq!(regressions.len(), 1);
+        assert_eq!(regressions[0].key, "embeddings[batch_size=8]");
+        assert_eq!((regressions[0].baseline, regressions[0].current), (100.0, 50.0));
+    }
+}
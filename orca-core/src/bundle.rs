@@ -0,0 +1,335 @@
+//! Self-contained index bundles for offline retrieval.
+//!
+//! An `IndexBundle` is a directory holding everything a retrieval pipeline needs besides the embedding
+//! model: the vectors of a collection, as a `MemoryStore` snapshot, the full records in a `FileDocStore`,
+//! and a manifest describing the collection with the SHA-256 checksums of every file. A `BundleBuilder`
+//! is filled by an `IngestPipeline` and written once; the bundle is then opened read-only, without any
+//! database or network access, so that an assistant over a fixed corpus can be shipped as files, as is or
+//! archived with any tool.
+//!
+//! ```text
+//! bundle/
+//! ├── manifest.json
+//! ├── vectors.json
+//! └── docs/
+//!     └── <record id>.json
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::docstore::{DocStore, FileDocStore};
+use crate::llm::source::verify_sha256;
+use crate::llm::Embedding;
+use crate::pipeline::parent::ParentDocumentRetriever;
+use crate::record::Record;
+use crate::vectorstore::hnsw::HnswConfig;
+use crate::vectorstore::memory::MemoryStore;
+
+/// Version of the bundle format written by this crate.
+pub const BUNDLE_VERSION: u32 = 1;
+
+/// File of a bundle holding its manifest.
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// File of a bundle holding its vectors.
+pub const VECTORS_FILE: &str = "vectors.json";
+
+/// Directory of a bundle holding its records.
+pub const DOCS_DIR: &str = "docs";
+
+/// Description of the contents of a bundle.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Version of the bundle format.
+    pub version: u32,
+
+    /// Name of the collection of the vectors.
+    pub collection: String,
+
+    /// Model that embedded the vectors, which must also embed the queries, e.g.
+    /// `sentence-transformers/all-MiniLM-L6-v2`.
+    pub embedding_model: String,
+
+    /// Number of dimensions of the vectors.
+    pub dimensions: usize,
+
+    /// Number of points in the collection.
+    pub points: usize,
+
+    /// Number of records in the document store.
+    pub documents: usize,
+
+    /// Time the bundle was written, in seconds since the Unix epoch.
+    pub created: u64,
+
+    /// SHA-256 checksums of the files of the bundle, in hexadecimal, by path relative to the bundle.
+    pub checksums: BTreeMap<String, String>,
+}
+
+/// Builder of a bundle, whose store and document store are filled by an ingestion pipeline before the
+/// bundle is written by `BundleBuilder::finish`.
+pub struct BundleBuilder {
+    /// Directory of the bundle.
+    dir: PathBuf,
+
+    /// Name of the collection of the vectors.
+    collection: String,
+
+    /// Model that embeds the records.
+    embedding_model: String,
+
+    /// Store holding the vectors until they are saved.
+    store: MemoryStore,
+
+    /// Document store writing the records to the bundle.
+    docs: FileDocStore,
+}
+
+impl BundleBuilder {
+    /// Create a builder writing a bundle to the given directory, which must not hold a bundle already.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use orca_core::bundle::BundleBuilder;
+    /// # use orca_core::llm::bert::Bert;
+    /// # use orca_core::pipeline::ingest::IngestPipeline;
+    /// # use orca_core::record::{pdf::Pdf, Spin};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let bert = Bert::new().build_model_and_tokenizer().await?;
+    /// let bundle = BundleBuilder::new("./manual", "manual")?
+    ///     .with_embedding_model("sentence-transformers/all-MiniLM-L6-v2");
+    /// IngestPipeline::new(bundle.store(), &bert, bundle.collection())
+    ///     .with_parents(bundle.docs(), 399)
+    ///     .ingest(vec![Pdf::from_file("manual.pdf", false).spin()?])
+    ///     .await?;
+    /// let manifest = bundle.finish()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new<P: AsRef<Path>>(dir: P, collection: &str) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        if dir.join(MANIFEST_FILE).exists() {
+            return Err(anyhow!("{} already holds a bundle", dir.display()));
+        }
+        let docs = FileDocStore::new(dir.join(DOCS_DIR))?;
+        Ok(BundleBuilder {
+            dir,
+            collection: collection.to_string(),
+            embedding_model: String::new(),
+            store: MemoryStore::new(),
+            docs,
+        })
+    }
+
+    /// Set the name of the model embedding the records, recorded in the manifest.
+    pub fn with_embedding_model(mut self, model: &str) -> Self {
+        self.embedding_model = model.to_string();
+        self
+    }
+
+    /// Index the vectors in an HNSW graph saved with the bundle, for large corpora. Must be set before
+    /// anything is ingested.
+    pub fn with_hnsw(mut self, config: HnswConfig) -> Self {
+        self.store = MemoryStore::new().with_hnsw(config);
+        self
+    }
+
+    /// Name of the collection to ingest the records into.
+    pub fn collection(&self) -> &str {
+        &self.collection
+    }
+
+    /// Store to ingest the records into.
+    pub fn store(&self) -> &MemoryStore {
+        &self.store
+    }
+
+    /// Document store to keep the full records in, see `IngestPipeline::with_parents`.
+    pub fn docs(&self) -> &FileDocStore {
+        &self.docs
+    }
+
+    /// Write the vectors and the manifest, returning the manifest. The manifest is written last, so that
+    /// an interrupted build does not leave a bundle that can be opened.
+    pub fn finish(self) -> Result<Manifest> {
+        let dimensions = self
+            .store
+            .dimensions(&self.collection)
+            .ok_or_else(|| anyhow!("Nothing was ingested into the collection {}", self.collection))?;
+        self.store.save(self.dir.join(VECTORS_FILE))?;
+
+        let mut checksums = BTreeMap::new();
+        checksums.insert(VECTORS_FILE.to_string(), sha256(&self.dir.join(VECTORS_FILE))?);
+        for entry in std::fs::read_dir(self.dir.join(DOCS_DIR))? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            checksums.insert(format!("{}/{}", DOCS_DIR, name), sha256(&entry.path())?);
+        }
+
+        let manifest = Manifest {
+            version: BUNDLE_VERSION,
+            collection: self.collection.clone(),
+            embedding_model: self.embedding_model,
+            dimensions,
+            points: self.store.len(&self.collection).unwrap_or(0),
+            documents: checksums.len() - 1,
+            created: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            checksums,
+        };
+        let path = self.dir.join(MANIFEST_FILE);
+        let temporary = path.with_extension("tmp");
+        std::fs::write(&temporary, serde_json::to_vec_pretty(&manifest)?)?;
+        std::fs::rename(temporary, path)?;
+        Ok(manifest)
+    }
+}
+
+/// SHA-256 checksum of a file, in hexadecimal.
+fn sha256(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Bundle opened for retrieval. Its files are never written: the store is kept in memory, and the
+/// document store refuses changes.
+pub struct IndexBundle {
+    manifest: Manifest,
+    store: MemoryStore,
+    docs: BundleDocs,
+}
+
+impl IndexBundle {
+    /// Open the bundle in the given directory, after checking its version and the checksums of its files.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use orca_core::bundle::IndexBundle;
+    /// # use orca_core::llm::bert::Bert;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let bundle = IndexBundle::open("./manual")?;
+    /// let bert = Bert::new().with_model_id(&bundle.manifest().embedding_model);
+    /// let bert = bert.build_model_and_tokenizer().await?;
+    /// let records = bundle.retriever(&bert)?.with_limit(5).retrieve("How do I reset the device?").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dir = dir.as_ref();
+        let manifest: Manifest = serde_json::from_slice(&std::fs::read(dir.join(MANIFEST_FILE))?)?;
+        if manifest.version != BUNDLE_VERSION {
+            return Err(anyhow!(
+                "Unsupported bundle version {}, expected {}",
+                manifest.version,
+                BUNDLE_VERSION
+            ));
+        }
+        for (file, checksum) in &manifest.checksums {
+            verify_sha256(dir.join(file), checksum)?;
+        }
+        let store = MemoryStore::load(dir.join(VECTORS_FILE))?;
+        let docs = BundleDocs(FileDocStore::new(dir.join(DOCS_DIR))?);
+        Ok(IndexBundle { manifest, store, docs })
+    }
+
+    /// Manifest of the bundle.
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// Store holding the vectors of the bundle, in the collection named in the manifest. Changes to it are
+    /// not saved.
+    pub fn store(&self) -> &MemoryStore {
+        &self.store
+    }
+
+    /// Document store holding the records of the bundle.
+    pub fn docs(&self) -> &BundleDocs {
+        &self.docs
+    }
+
+    /// Retriever searching the bundle and returning the records of the chunks found. Fails if the
+    /// embedder does not embed vectors of the dimensions of the bundle.
+    pub fn retriever<'a, E: Embedding + Send + Sync>(
+        &'a self,
+        embedder: &'a E,
+    ) -> Result<ParentDocumentRetriever<'a, E, MemoryStore, BundleDocs>> {
+        if embedder.dimensions() != self.manifest.dimensions {
+            return Err(anyhow!(
+                "The bundle holds vectors of {} dimensions, but the embedder generates {}",
+                self.manifest.dimensions,
+                embedder.dimensions()
+            ));
+        }
+        Ok(ParentDocumentRetriever::new(
+            embedder,
+            &self.store,
+            &self.docs,
+            &self.manifest.collection,
+        ))
+    }
+}
+
+/// Read-only document store of an opened bundle.
+pub struct BundleDocs(FileDocStore);
+
+#[async_trait::async_trait]
+impl DocStore for BundleDocs {
+    async fn put(&self, id: &str, _record: &Record) -> Result<()> {
+        Err(anyhow!("Cannot store record {}: bundles are read-only", id))
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Record>> {
+        self.0.get(id).await
+    }
+
+    async fn delete(&self, id: &str) -> Result<()> {
+        Err(anyhow!("Cannot delete record {}: bundles are read-only", id))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pipeline::ingest::IngestPipeline;
+    use crate::record::Content;
+    use crate::testing::FixtureEmbedder;
+
+    #[tokio::test]
+    async fn test_bundle() {
+        let dir = std::env::temp_dir().join(format!("orca-bundle-{}", uuid::Uuid::new_v4()));
+        let bundle = BundleBuilder::new(&dir, "manual").unwrap().with_embedding_model("fixtures");
+        let records = vec![
+            Record::new(Content::String("Orcas hunt seals in pods.".to_string())),
+            Record::new(Content::String("Lighthouses guide ships at night.".to_string())),
+        ];
+        IngestPipeline::new(bundle.store(), &FixtureEmbedder, bundle.collection())
+            .with_parents(bundle.docs(), 399)
+            .ingest(records)
+            .await
+            .unwrap();
+        let manifest = bundle.finish().unwrap();
+        assert_eq!((manifest.points, manifest.documents), (2, 2));
+        assert!(BundleBuilder::new(&dir, "manual").is_err());
+
+        let bundle = IndexBundle::open(&dir).unwrap();
+        assert_eq!(bundle.manifest(), &manifest);
+        let records = bundle.retriever(&FixtureEmbedder).unwrap().with_limit(1).retrieve("orcas").await.unwrap();
+        assert_eq!(records[0].content.to_string(), "Orcas hunt seals in pods.");
+        assert!(bundle.docs().put("orcas", &records[0]).await.is_err());
+
+        // Tampered files are rejected.
+        let (file, _) = manifest.checksums.iter().find(|(file, _)| file.starts_with(DOCS_DIR)).unwrap();
+        std::fs::write(dir.join(file), "{}").unwrap();
+        assert!(IndexBundle::open(&dir).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
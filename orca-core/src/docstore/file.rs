@@ -0,0 +1,86 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use super::{check_id, DocStore};
+use crate::record::Record;
+
+/// Document store keeping each record as a JSON file named after its id.
+#[derive(Debug, Clone)]
+pub struct FileDocStore {
+    dir: PathBuf,
+}
+
+impl FileDocStore {
+    /// Create a store in the given directory, creating the directory if needed.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use orca_core::docstore::{DocStore, FileDocStore};
+    /// # use orca_core::record::{Content, Record};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let docs = FileDocStore::new("./documents")?;
+    /// docs.put("resume", &Record::new(Content::String("Software engineer".into()))).await?;
+    /// let record = docs.get("resume").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        std::fs::create_dir_all(dir.as_ref())?;
+        Ok(FileDocStore {
+            dir: dir.as_ref().to_path_buf(),
+        })
+    }
+
+    fn path(&self, id: &str) -> Result<PathBuf> {
+        check_id(id)?;
+        Ok(self.dir.join(format!("{}.json", id)))
+    }
+}
+
+#[async_trait::async_trait]
+impl DocStore for FileDocStore {
+    async fn put(&self, id: &str, record: &Record) -> Result<()> {
+        tokio::fs::write(self.path(id)?, serde_json::to_vec(record)?).await?;
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Record>> {
+        match tokio::fs::read(self.path(id)?).await {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn delete(&self, id: &str) -> Result<()> {
+        match tokio::fs::remove_file(self.path(id)?).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::record::Content;
+
+    #[tokio::test]
+    async fn test_file_doc_store() {
+        let dir = std::env::temp_dir().join(format!("orca-docstore-{}", uuid::Uuid::new_v4()));
+        let docs = FileDocStore::new(&dir).unwrap();
+        let record = Record::new(Content::String("Orcas live in pods.".to_string())).with_attribute("page", 2);
+        docs.put("orcas", &record).await.unwrap();
+
+        let found = docs.get_many(&["orcas".to_string(), "whales".to_string()]).await.unwrap();
+        assert_eq!(found[0].as_ref().unwrap().content, record.content);
+        assert!(found[1].is_none());
+
+        docs.delete("orcas").await.unwrap();
+        assert!(docs.get("orcas").await.unwrap().is_none());
+        assert!(docs.put("../orcas", &record).await.is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
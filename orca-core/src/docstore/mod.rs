@@ -0,0 +1,62 @@
+//! Document stores holding full records by id.
+//!
+//! Vector stores only need the chunks of a document that are embedded. A `DocStore` keeps the whole
+//! records, keyed by id, so that chunks can carry the id of the record they were split from in the
+//! `parent_id` attribute instead of the record itself. Retrievers then join the chunks found by a
+//! search with their parent records, see `pipeline::parent::ParentDocumentRetriever`.
+//!
+//! Records are stored as files with `FileDocStore`, in SQLite with `SqliteDocStore` (`sqlite`
+//! feature) or in an S3 bucket with `S3DocStore` (`s3` feature).
+
+pub mod file;
+#[cfg(feature = "s3")]
+pub mod s3;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+pub use file::FileDocStore;
+#[cfg(feature = "s3")]
+pub use s3::S3DocStore;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteDocStore;
+
+use anyhow::{anyhow, Result};
+
+use crate::record::Record;
+
+/// Attribute of a chunk holding the id of the record it was split from.
+pub const PARENT_ID_ATTRIBUTE: &str = "parent_id";
+
+/// Store of full records keyed by id.
+#[async_trait::async_trait]
+pub trait DocStore: Send + Sync {
+    /// Store a record, replacing any record with the same id.
+    async fn put(&self, id: &str, record: &Record) -> Result<()>;
+
+    /// Get the record with the given id, if any.
+    async fn get(&self, id: &str) -> Result<Option<Record>>;
+
+    /// Delete the record with the given id, if any.
+    async fn delete(&self, id: &str) -> Result<()>;
+
+    /// Get the records with the given ids, in the same order, with `None` for missing records.
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<Option<Record>>> {
+        let mut records = Vec::with_capacity(ids.len());
+        for id in ids {
+            records.push(self.get(id).await?);
+        }
+        Ok(records)
+    }
+}
+
+/// Error out on ids that are empty or could escape the file, object key or table row they name.
+pub(crate) fn check_id(id: &str) -> Result<()> {
+    let valid = id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if id.is_empty() || !valid {
+        return Err(anyhow!(
+            "Invalid record id {:?}: only ASCII letters, digits, '-' and '_' are allowed",
+            id
+        ));
+    }
+    Ok(())
+}
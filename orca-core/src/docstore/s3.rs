@@ -0,0 +1,123 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use object_store::path::Path;
+use object_store::{ObjectStore, PutPayload};
+
+use super::{check_id, DocStore};
+use crate::record::Record;
+
+pub use object_store::aws::AmazonS3Builder;
+
+/// Document store keeping each record as a JSON object in an S3 bucket, or in any S3 compatible
+/// storage such as MinIO or Cloudflare R2.
+#[derive(Clone)]
+pub struct S3DocStore {
+    store: Arc<dyn ObjectStore>,
+
+    /// Prefix of the object keys, e.g. `documents/`.
+    prefix: String,
+}
+
+impl S3DocStore {
+    /// Create a store in an AWS S3 bucket, configured by the `AWS_*` environment variables, e.g.
+    /// `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and optional `AWS_SESSION_TOKEN` for the credentials.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use orca_core::docstore::{DocStore, S3DocStore};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let docs = S3DocStore::new("my-bucket", "eu-west-1")?.with_prefix("documents/");
+    /// let record = docs.get("resume").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new(bucket: &str, region: &str) -> Result<Self> {
+        Self::from_builder(AmazonS3Builder::from_env().with_bucket_name(bucket).with_region(region))
+    }
+
+    /// Create a store from a configured S3 client builder, e.g. for a MinIO server with its own
+    /// credentials.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use orca_core::docstore::s3::{AmazonS3Builder, S3DocStore};
+    /// let builder = AmazonS3Builder::new()
+    ///     .with_endpoint("http://localhost:9000")
+    ///     .with_allow_http(true)
+    ///     .with_bucket_name("documents")
+    ///     .with_region("us-east-1")
+    ///     .with_access_key_id("minioadmin")
+    ///     .with_secret_access_key("minioadmin");
+    /// let docs = S3DocStore::from_builder(builder).unwrap();
+    /// ```
+    pub fn from_builder(builder: AmazonS3Builder) -> Result<Self> {
+        Ok(S3DocStore {
+            store: Arc::new(builder.build()?),
+            prefix: String::new(),
+        })
+    }
+
+    /// Prefix the object keys, e.g. with `documents/`.
+    pub fn with_prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    /// Path of the object of a record.
+    fn path(&self, id: &str) -> Result<Path> {
+        check_id(id)?;
+        Ok(Path::from(format!("{}{}.json", self.prefix, id)))
+    }
+}
+
+#[async_trait::async_trait]
+impl DocStore for S3DocStore {
+    async fn put(&self, id: &str, record: &Record) -> Result<()> {
+        let payload = PutPayload::from(serde_json::to_vec(record)?);
+        self.store.put(&self.path(id)?, payload).await?;
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Record>> {
+        let bytes = match self.store.get(&self.path(id)?).await {
+            Ok(object) => object.bytes().await?,
+            Err(object_store::Error::NotFound { .. }) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Some(serde_json::from_slice(&bytes)?))
+    }
+
+    async fn delete(&self, id: &str) -> Result<()> {
+        match self.store.delete(&self.path(id)?).await {
+            Ok(()) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::record::Content;
+    use object_store::memory::InMemory;
+
+    #[tokio::test]
+    async fn test_s3_doc_store() {
+        let docs = S3DocStore {
+            store: Arc::new(InMemory::new()),
+            prefix: String::new(),
+        }
+        .with_prefix("documents/");
+        let record = Record::new(Content::String("Orcas live in pods.".to_string()));
+        docs.put("orcas", &record).await.unwrap();
+        assert_eq!(docs.get("orcas").await.unwrap().unwrap().content, record.content);
+        assert!(docs.store.head(&Path::from("documents/orcas.json")).await.is_ok());
+
+        docs.delete("orcas").await.unwrap();
+        docs.delete("orcas").await.unwrap();
+        assert!(docs.get("orcas").await.unwrap().is_none());
+        assert!(docs.get("../orcas").await.is_err());
+    }
+}
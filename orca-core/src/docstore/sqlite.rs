@@ -0,0 +1,83 @@
+use anyhow::Result;
+
+use super::DocStore;
+use crate::record::Record;
+
+/// Document store keeping records as JSON in a SQLite table.
+pub struct SqliteDocStore {
+    pool: sqlx::SqlitePool,
+    table: String,
+}
+
+impl SqliteDocStore {
+    /// Connect to a SQLite database, e.g. `sqlite://documents.db?mode=rwc`, and create the `documents`
+    /// table if it does not exist.
+    pub async fn connect(url: &str) -> Result<Self> {
+        Self::from_pool(sqlx::SqlitePool::connect(url).await?, "documents").await
+    }
+
+    /// Create a store in the given table of an existing pool, creating the table if it does not exist.
+    pub async fn from_pool(pool: sqlx::SqlitePool, table: &str) -> Result<Self> {
+        super::check_id(table)?;
+        let create = format!(
+            "CREATE TABLE IF NOT EXISTS {} (id TEXT PRIMARY KEY NOT NULL, record TEXT NOT NULL)",
+            table
+        );
+        sqlx::query(&create).execute(&pool).await?;
+        Ok(SqliteDocStore {
+            pool,
+            table: table.to_string(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl DocStore for SqliteDocStore {
+    async fn put(&self, id: &str, record: &Record) -> Result<()> {
+        sqlx::query(&format!(
+            "INSERT OR REPLACE INTO {} (id, record) VALUES (?, ?)",
+            self.table
+        ))
+        .bind(id)
+        .bind(serde_json::to_string(record)?)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Record>> {
+        let record: Option<String> = sqlx::query_scalar(&format!("SELECT record FROM {} WHERE id = ?", self.table))
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(record.map(|record| serde_json::from_str(&record)).transpose()?)
+    }
+
+    async fn delete(&self, id: &str) -> Result<()> {
+        sqlx::query(&format!("DELETE FROM {} WHERE id = ?", self.table))
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::record::Content;
+
+    #[tokio::test]
+    async fn test_sqlite_doc_store() {
+        // A single connection, since each connection to an in-memory database opens a new database.
+        let pool = sqlx::sqlite::SqlitePoolOptions::new().max_connections(1).connect("sqlite::memory:").await.unwrap();
+        let docs = SqliteDocStore::from_pool(pool, "documents").await.unwrap();
+        let record = Record::new(Content::String("Orcas live in pods.".to_string()));
+        docs.put("orcas", &record).await.unwrap();
+        docs.put("orcas", &record.clone().with_attribute("page", 2)).await.unwrap();
+        assert_eq!(docs.get("orcas").await.unwrap().unwrap().attributes["page"], 2);
+
+        docs.delete("orcas").await.unwrap();
+        assert!(docs.get("orcas").await.unwrap().is_none());
+    }
+}
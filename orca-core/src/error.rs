@@ -0,0 +1,141 @@
+//! Errors of the failures callers may want to handle.
+//!
+//! Functions of this crate return `anyhow::Error`s, which carry the errors of dependencies as they are.
+//! Failures that callers may want to tell apart, such as a provider rejecting a request or a template
+//! failing to render, are raised as an `OrcaError`, which can be matched on after downcasting instead of
+//! matching the error message. Errors with details of their own, such as an execution going over its
+//! budget, are wrapped in a variant of `OrcaError`, so that a single downcast covers every failure.
+//! `OrcaError` implements `std::error::Error`, so it converts into an `anyhow::Error` with `?` or `into()`,
+//! as before.
+//!
+//! ```
+//! use orca_core::error::OrcaError;
+//!
+//! fn is_rate_limited(error: &anyhow::Error) -> bool {
+//!     matches!(error.downcast_ref::<OrcaError>(), Some(OrcaError::LLM { status: Some(429), .. }))
+//! }
+//! ```
+
+use std::fmt::Display;
+
+use crate::pipeline::budget::BudgetExceeded;
+use crate::prompt::limits::TemplateLimitError;
+use crate::record::provenance::VerificationError;
+use crate::vectorstore::VectorStoreUnavailable;
+
+/// Failure of an operation of orca, by the component that failed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OrcaError {
+    /// A template does not exist or failed to render.
+    Template { template: String, message: String },
+
+    /// An LLM provider failed or rejected a request, with the HTTP status of the response, if any.
+    LLM {
+        provider: String,
+        status: Option<u16>,
+        message: String,
+    },
+
+    /// An embedding model failed or generated invalid embeddings.
+    Embedding { provider: String, message: String },
+
+    /// A vector store failed or rejected a request. Stores that stay unreachable after retries fail with
+    /// `OrcaError::VectorStoreUnavailable` instead.
+    VectorStore { store: String, message: String },
+
+    /// A vector store stayed unreachable after retries.
+    VectorStoreUnavailable(VectorStoreUnavailable),
+
+    /// A record could not be read from its source, e.g. a file or a URL.
+    Record { source: String, message: String },
+
+    /// A template or its rendered prompt went over the limits of the template engine.
+    TemplateLimit(TemplateLimitError),
+
+    /// An execution went over its budget.
+    Budget(BudgetExceeded),
+
+    /// A record failed the verification of its provenance.
+    Verification(VerificationError),
+}
+
+impl Display for OrcaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OrcaError::Template { template, message } => write!(f, "Template {}: {}", template, message),
+            OrcaError::LLM {
+                provider,
+                status: Some(status),
+                message,
+            } => write!(f, "{} request failed with status {}: {}", provider, status, message),
+            OrcaError::LLM { provider, message, .. } => write!(f, "{} request failed: {}", provider, message),
+            OrcaError::Embedding { provider, message } => write!(f, "{} embeddings failed: {}", provider, message),
+            OrcaError::VectorStore { store, message } => write!(f, "{} request failed: {}", store, message),
+            OrcaError::VectorStoreUnavailable(error) => error.fmt(f),
+            OrcaError::Record { source, message } => write!(f, "Failed to read record from {}: {}", source, message),
+            OrcaError::TemplateLimit(error) => error.fmt(f),
+            OrcaError::Budget(error) => error.fmt(f),
+            OrcaError::Verification(error) => error.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for OrcaError {}
+
+impl From<VectorStoreUnavailable> for OrcaError {
+    fn from(error: VectorStoreUnavailable) -> Self {
+        OrcaError::VectorStoreUnavailable(error)
+    }
+}
+
+impl From<TemplateLimitError> for OrcaError {
+    fn from(error: TemplateLimitError) -> Self {
+        OrcaError::TemplateLimit(error)
+    }
+}
+
+impl From<BudgetExceeded> for OrcaError {
+    fn from(error: BudgetExceeded) -> Self {
+        OrcaError::Budget(error)
+    }
+}
+
+impl From<VerificationError> for OrcaError {
+    fn from(error: VerificationError) -> Self {
+        OrcaError::Verification(error)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_orca_error() {
+        let error: anyhow::Error = OrcaError::LLM {
+            provider: "OpenAI".to_string(),
+            status: Some(429),
+            message: "Rate limit reached".to_string(),
+        }
+        .into();
+        assert_eq!(
+            error.to_string(),
+            "OpenAI request failed with status 429: Rate limit reached"
+        );
+        let error = error.context("Failed to answer the question");
+        assert!(matches!(
+            error.downcast_ref::<OrcaError>(),
+            Some(OrcaError::LLM { status: Some(429), .. })
+        ));
+    }
+
+    #[test]
+    fn test_from() {
+        let error: anyhow::Error = OrcaError::from(BudgetExceeded::Tokens { used: 3, limit: 2 }).into();
+        assert_eq!(error.to_string(), "Budget exceeded: 3 tokens, more than the limit of 2");
+        assert!(matches!(
+            error.downcast_ref::<OrcaError>(),
+            Some(OrcaError::Budget(BudgetExceeded::Tokens { limit: 2, .. }))
+        ));
+    }
+}
@@ -0,0 +1,237 @@
+//! Datasets of collected pipeline runs.
+//!
+//! A `PipelineRun` records the question a pipeline was executed with, the context it retrieved, the
+//! answer of its `PipelineResult`, when it ran and tags such as the deployment it ran in. Runs can be
+//! appended to a JSONL log as they happen and loaded into a `Dataset` later, which is filtered by date
+//! and tag and exported as OpenAI chat JSONL for fine-tuning, as CSV of question, context and answer,
+//! or as samples for the judge.
+
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value as JsonValue};
+
+use super::judge::JudgeSample;
+use crate::finetune::{self, TrainingExample};
+use crate::pipeline::PipelineResult;
+use crate::prompt::chat::Role;
+use crate::record::table::Table;
+
+/// Run of a pipeline, as collected for evaluation and tuning.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PipelineRun {
+    /// Name of the pipeline which generated the answer.
+    pub pipeline: String,
+
+    /// The question the pipeline was executed with.
+    pub question: String,
+
+    /// Context the answer was generated from, e.g. the retrieved records.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<String>,
+
+    /// The answer of the pipeline.
+    pub answer: String,
+
+    /// Unix timestamp of the run, in seconds.
+    pub timestamp: u64,
+
+    /// Tags of the run, e.g. the deployment or the version of the prompt.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+
+    /// Metadata of the pipeline result, e.g. the `tier` of the model router that answered.
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub metadata: Map<String, JsonValue>,
+}
+
+impl PipelineRun {
+    /// Record a run of a pipeline with the given question, happening now.
+    pub fn new(question: &str, result: &PipelineResult) -> Self {
+        PipelineRun {
+            pipeline: result.name.clone(),
+            question: question.to_string(),
+            context: None,
+            answer: result.content(),
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+            tags: Vec::new(),
+            metadata: result.metadata().clone(),
+        }
+    }
+
+    /// Set the context the answer was generated from.
+    pub fn with_context(mut self, context: &str) -> Self {
+        self.context = Some(context.to_string());
+        self
+    }
+
+    /// Add a tag to the run.
+    pub fn with_tag(mut self, tag: &str) -> Self {
+        self.tags.push(tag.to_string());
+        self
+    }
+
+    /// Set the Unix timestamp of the run, in seconds.
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// Append the run to a JSONL log, creating the file if needed.
+    pub fn append_to<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut line = serde_json::to_vec(self)?;
+        line.push(b'\n');
+        let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+        file.write_all(&line)?;
+        Ok(())
+    }
+
+    /// The run as a training example, the context being given in the system message if the run has one.
+    pub fn to_training_example(&self) -> TrainingExample {
+        let example = match &self.context {
+            Some(context) => TrainingExample::new().with_message(Role::System, &format!("Context:\n{}", context)),
+            None => TrainingExample::new(),
+        };
+        example.with_message(Role::User, &self.question).with_message(Role::Assistant, &self.answer)
+    }
+
+    /// The run as a sample for the judge, its answer being the candidate.
+    pub fn to_judge_sample(&self) -> JudgeSample {
+        JudgeSample::new(&self.question, &self.answer)
+    }
+}
+
+/// Collection of pipeline runs.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Dataset {
+    /// The runs, in the order they were collected.
+    pub runs: Vec<PipelineRun>,
+}
+
+impl Dataset {
+    /// Create an empty dataset.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a run to the dataset.
+    pub fn push(&mut self, run: PipelineRun) {
+        self.runs.push(run);
+    }
+
+    /// Load a dataset from a JSONL log of runs, skipping blank lines.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let runs = std::fs::read_to_string(path)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| Ok(serde_json::from_str(line)?))
+            .collect::<Result<_>>()?;
+        Ok(Dataset { runs })
+    }
+
+    /// Runs between the given Unix timestamps, in seconds, inclusive.
+    pub fn between(&self, since: u64, until: u64) -> Dataset {
+        self.filter(|run| (since..=until).contains(&run.timestamp))
+    }
+
+    /// Runs with the given tag.
+    pub fn tagged(&self, tag: &str) -> Dataset {
+        self.filter(|run| run.tags.iter().any(|t| t == tag))
+    }
+
+    /// Runs of the given pipeline.
+    pub fn of_pipeline(&self, pipeline: &str) -> Dataset {
+        self.filter(|run| run.pipeline == pipeline)
+    }
+
+    /// Runs matching a predicate.
+    pub fn filter<F: Fn(&PipelineRun) -> bool>(&self, predicate: F) -> Dataset {
+        Dataset {
+            runs: self.runs.iter().filter(|run| predicate(run)).cloned().collect(),
+        }
+    }
+
+    /// The runs as training examples, see `PipelineRun::to_training_example`.
+    pub fn to_training_examples(&self) -> Vec<TrainingExample> {
+        self.runs.iter().map(PipelineRun::to_training_example).collect()
+    }
+
+    /// The runs as OpenAI chat JSONL, ready to be uploaded for fine-tuning.
+    pub fn to_chat_jsonl(&self) -> Result<String> {
+        finetune::to_jsonl(&self.to_training_examples())
+    }
+
+    /// The runs as samples for the judge.
+    pub fn to_judge_samples(&self) -> Vec<JudgeSample> {
+        self.runs.iter().map(PipelineRun::to_judge_sample).collect()
+    }
+
+    /// The runs as CSV with `question`, `context` and `answer` columns.
+    pub fn to_csv(&self) -> String {
+        let header = vec!["question".to_string(), "context".to_string(), "answer".to_string()];
+        let rows = self.runs.iter().map(|run| {
+            vec![
+                run.question.clone(),
+                run.context.clone().unwrap_or_default(),
+                run.answer.clone(),
+            ]
+        });
+        Table {
+            rows: std::iter::once(header).chain(rows).collect(),
+        }
+        .to_csv()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::llm::LLMResponse;
+
+    fn run(question: &str, timestamp: u64, tag: &str) -> PipelineRun {
+        let result = PipelineResult::new("rag".to_string())
+            .with_llm_response(LLMResponse::Empty)
+            .with_metadata("tier", 0);
+        PipelineRun::new(question, &result).with_timestamp(timestamp).with_tag(tag)
+    }
+
+    #[test]
+    fn test_filter() {
+        let path = std::env::temp_dir().join(format!("orca-runs-{}.jsonl", uuid::Uuid::new_v4()));
+        run("a", 100, "prod").append_to(&path).unwrap();
+        run("b", 200, "staging").append_to(&path).unwrap();
+        run("c", 300, "prod").append_to(&path).unwrap();
+        let dataset = Dataset::load(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(dataset.runs.len(), 3);
+        assert_eq!(dataset.runs[0].metadata["tier"], 0);
+        let filtered = dataset.tagged("prod").between(150, 300);
+        assert_eq!(filtered.runs.len(), 1);
+        assert_eq!(filtered.runs[0].question, "c");
+        assert!(dataset.of_pipeline("sql").runs.is_empty());
+    }
+
+    #[test]
+    fn test_export() {
+        let mut dataset = Dataset::new();
+        dataset.push(run("What do orcas eat?", 100, "prod").with_context("Orcas eat fish, and seals."));
+        dataset.runs[0].answer = "Fish and seals.".to_string();
+        assert_eq!(
+            dataset.to_csv(),
+            "question,context,answer\nWhat do orcas eat?,\"Orcas eat fish, and seals.\",Fish and seals.\n"
+        );
+        assert_eq!(
+            dataset.to_chat_jsonl().unwrap(),
+            concat!(
+                r#"{"messages":[{"role":"system","content":"Context:\nOrcas eat fish, and seals."},"#,
+                r#"{"role":"user","content":"What do orcas eat?"},{"role":"assistant","content":"Fish and seals."}]}"#,
+                "\n"
+            )
+        );
+        assert_eq!(dataset.to_judge_samples()[0].candidate, "Fish and seals.");
+    }
+}
@@ -0,0 +1,521 @@
+//! Fine-tuning of OpenAI models on data collected with orca.
+//!
+//! A `TrainingExample` is a conversation in the chat format of OpenAI's fine-tuning API, built from a
+//! chat prompt and its completion or from an evaluation dataset, and `to_jsonl`/`write_jsonl` turn
+//! examples into a training file. `FineTuning` uploads training files, creates fine-tuning jobs and
+//! waits for them to finish; the tuned model they report can then be used with `OpenAI::with_model`.
+
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use reqwest::multipart::{Form, Part};
+use reqwest::Client;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::eval::judge::{JudgeReport, JudgeSample};
+use crate::llm::secrets::{EnvSecrets, SecretsProvider};
+use crate::prompt::chat::{ChatPrompt, Role};
+
+static OPENAI_FILES_URL: &str = "https://api.openai.com/v1/files";
+static OPENAI_FINE_TUNING_URL: &str = "https://api.openai.com/v1/fine_tuning/jobs";
+
+/// Message of a training example.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrainingMessage {
+    /// The message role (system, user, assistant).
+    pub role: Role,
+
+    /// The message text.
+    pub content: String,
+}
+
+/// Conversation the model is trained to complete, its assistant messages being the expected answers.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrainingExample {
+    /// The messages of the conversation.
+    pub messages: Vec<TrainingMessage>,
+}
+
+impl TrainingExample {
+    /// Create an empty example.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a message to the example.
+    pub fn with_message(mut self, role: Role, content: &str) -> Self {
+        self.messages.push(TrainingMessage {
+            role,
+            content: content.to_string(),
+        });
+        self
+    }
+
+    /// Create an example from a chat prompt and the completion it should be answered with. Images of
+    /// the prompt are left out.
+    pub fn from_chat(prompt: &ChatPrompt, completion: &str) -> Self {
+        let example = prompt.to_vec_ref().iter().fold(Self::new(), |example, message| {
+            example.with_message(message.role.clone(), &message.content)
+        });
+        example.with_message(Role::Assistant, completion)
+    }
+
+    /// Create an example from an evaluation sample, answering its question with the reference answer,
+    /// or with the candidate answer if the sample has none.
+    pub fn from_sample(sample: &JudgeSample) -> Self {
+        let answer = sample.reference.as_deref().unwrap_or(&sample.candidate);
+        Self::new().with_message(Role::User, &sample.question).with_message(Role::Assistant, answer)
+    }
+
+    /// Prepend a system message, e.g. the system prompt of the pipeline the example was collected from.
+    pub fn with_system(mut self, system: &str) -> Self {
+        self.messages.insert(
+            0,
+            TrainingMessage {
+                role: Role::System,
+                content: system.to_string(),
+            },
+        );
+        self
+    }
+}
+
+/// Examples answering the questions of judged samples with the candidate answers scoring at least
+/// `min_score`, to train a model on the answers of a pipeline that the judge rated well. Samples
+/// whose judgement failed are left out.
+pub fn judged_examples(samples: &[JudgeSample], report: &JudgeReport, min_score: u8) -> Vec<TrainingExample> {
+    samples
+        .iter()
+        .zip(&report.judgements)
+        .filter(|(_, judgement)| judgement.as_ref().is_some_and(|judgement| judgement.score >= min_score))
+        .map(|(sample, _)| {
+            TrainingExample::new()
+                .with_message(Role::User, &sample.question)
+                .with_message(Role::Assistant, &sample.candidate)
+        })
+        .collect()
+}
+
+/// Serialize examples as a JSONL training file, one example per line. Fails if an example has no
+/// assistant message, as OpenAI rejects such files.
+pub fn to_jsonl(examples: &[TrainingExample]) -> Result<String> {
+    let mut jsonl = String::new();
+    for (i, example) in examples.iter().enumerate() {
+        if !example.messages.iter().any(|message| message.role == Role::Assistant) {
+            return Err(anyhow!("Training example {} has no assistant message", i));
+        }
+        jsonl.push_str(&serde_json::to_string(example)?);
+        jsonl.push('\n');
+    }
+    Ok(jsonl)
+}
+
+/// Write examples to a JSONL training file.
+pub fn write_jsonl<P: AsRef<Path>>(examples: &[TrainingExample], path: P) -> Result<()> {
+    std::fs::write(path, to_jsonl(examples)?)?;
+    Ok(())
+}
+
+/// Read examples from a JSONL training file, skipping blank lines.
+pub fn read_jsonl<P: AsRef<Path>>(path: P) -> Result<Vec<TrainingExample>> {
+    std::fs::read_to_string(path)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Ok(serde_json::from_str(line)?))
+        .collect()
+}
+
+/// File uploaded to OpenAI.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileObject {
+    /// ID of the file, used to refer to it in fine-tuning jobs.
+    pub id: String,
+
+    /// Size of the file in bytes.
+    pub bytes: u64,
+
+    /// Name of the file.
+    pub filename: String,
+
+    /// Purpose of the file, `fine-tune` for training files.
+    pub purpose: String,
+}
+
+/// Parameters of a fine-tuning job.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FineTuningJobRequest {
+    /// Model to fine-tune, e.g. `gpt-3.5-turbo-1106`.
+    model: String,
+
+    /// ID of the uploaded training file.
+    training_file: String,
+
+    /// ID of the uploaded validation file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    validation_file: Option<String>,
+
+    /// Suffix of the name of the tuned model.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    suffix: Option<String>,
+
+    /// Training hyperparameters, chosen by OpenAI unless set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hyperparameters: Option<Hyperparameters>,
+}
+
+/// Training hyperparameters of a fine-tuning job.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Hyperparameters {
+    /// Number of epochs to train for.
+    pub n_epochs: usize,
+}
+
+impl FineTuningJobRequest {
+    /// Create a request to fine-tune a model on an uploaded training file.
+    pub fn new(model: &str, training_file: &str) -> Self {
+        FineTuningJobRequest {
+            model: model.to_string(),
+            training_file: training_file.to_string(),
+            validation_file: None,
+            suffix: None,
+            hyperparameters: None,
+        }
+    }
+
+    /// Set the uploaded file the job reports validation metrics on.
+    pub fn with_validation_file(mut self, validation_file: &str) -> Self {
+        self.validation_file = Some(validation_file.to_string());
+        self
+    }
+
+    /// Set the suffix of the name of the tuned model, e.g. `support-bot`.
+    pub fn with_suffix(mut self, suffix: &str) -> Self {
+        self.suffix = Some(suffix.to_string());
+        self
+    }
+
+    /// Set the number of epochs to train for.
+    pub fn with_epochs(mut self, n_epochs: usize) -> Self {
+        self.hyperparameters = Some(Hyperparameters { n_epochs });
+        self
+    }
+}
+
+/// Status of a fine-tuning job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    ValidatingFiles,
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    /// Whether the job is over, successfully or not.
+    pub fn is_finished(&self) -> bool {
+        matches!(self, JobStatus::Succeeded | JobStatus::Failed | JobStatus::Cancelled)
+    }
+}
+
+/// Error a fine-tuning job failed with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobError {
+    #[serde(default)]
+    pub code: Option<String>,
+    pub message: String,
+    #[serde(default)]
+    pub param: Option<String>,
+}
+
+/// Fine-tuning job, as reported by OpenAI.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FineTuningJob {
+    /// ID of the job.
+    pub id: String,
+
+    /// Model being fine-tuned.
+    pub model: String,
+
+    /// Status of the job.
+    pub status: JobStatus,
+
+    /// Name of the tuned model, once the job succeeded.
+    #[serde(default)]
+    pub fine_tuned_model: Option<String>,
+
+    /// ID of the training file.
+    pub training_file: String,
+
+    /// ID of the validation file.
+    #[serde(default)]
+    pub validation_file: Option<String>,
+
+    /// Number of tokens trained on, once the job succeeded.
+    #[serde(default)]
+    pub trained_tokens: Option<u64>,
+
+    /// Error the job failed with.
+    #[serde(default)]
+    pub error: Option<JobError>,
+
+    /// Unix timestamp of the creation of the job.
+    pub created_at: i64,
+
+    /// Unix timestamp of the end of the job.
+    #[serde(default)]
+    pub finished_at: Option<i64>,
+}
+
+/// Event of a fine-tuning job, e.g. the training loss at a step.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FineTuningEvent {
+    pub id: String,
+    pub created_at: i64,
+    pub level: String,
+    pub message: String,
+}
+
+/// Page of a list returned by the API.
+#[derive(Debug, Deserialize)]
+struct List<T> {
+    data: Vec<T>,
+}
+
+/// Client of the OpenAI files and fine-tuning endpoints.
+#[derive(Clone)]
+pub struct FineTuning {
+    /// HTTP client.
+    client: Client,
+
+    /// API key for the OpenAI API.
+    api_key: String,
+
+    /// Interval between the checks of the status of a job being waited for.
+    poll_interval: Duration,
+}
+
+impl FineTuning {
+    /// Create a fine-tuning client authenticated with the given API key.
+    pub fn new(api_key: &str) -> Self {
+        Self {
+            client: Client::new(),
+            api_key: api_key.to_string(),
+            poll_interval: Duration::from_secs(30),
+        }
+    }
+
+    /// Create a fine-tuning client with the API key of the OPENAI_API_KEY environment variable. Fails if
+    /// it is not set.
+    pub fn from_env() -> Result<Self> {
+        Self::with_secrets(&EnvSecrets)
+    }
+
+    /// Create a fine-tuning client with the `OPENAI_API_KEY` secret of a provider, e.g. the one used by the
+    /// `OpenAI` client of the pipeline whose examples are tuned on. Fails if the provider has no such secret.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::finetune::FineTuning;
+    /// use orca_core::llm::secrets::StaticSecrets;
+    ///
+    /// let secrets = StaticSecrets::new().with_secret("OPENAI_API_KEY", "sk-test");
+    /// let client = FineTuning::with_secrets(&secrets).unwrap();
+    /// assert!(FineTuning::with_secrets(&StaticSecrets::new()).is_err());
+    /// ```
+    pub fn with_secrets(secrets: &dyn SecretsProvider) -> Result<Self> {
+        Ok(Self::new(&secrets.secret("OPENAI_API_KEY")?))
+    }
+
+    /// Set the interval between the checks of the status of a job being waited for, 30 seconds by default.
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Generate a request uploading a training file with the given name and content.
+    pub fn generate_upload_request(&self, filename: &str, content: Vec<u8>) -> Result<reqwest::Request> {
+        let form = Form::new()
+            .text("purpose", "fine-tune")
+            .part("file", Part::bytes(content).file_name(filename.to_string()));
+        let req = self
+            .client
+            .post(OPENAI_FILES_URL)
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .multipart(form)
+            .build()?;
+        Ok(req)
+    }
+
+    /// Generate a request creating a fine-tuning job.
+    pub fn generate_job_request(&self, request: &FineTuningJobRequest) -> Result<reqwest::Request> {
+        let req = self
+            .client
+            .post(OPENAI_FINE_TUNING_URL)
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .json(request)
+            .build()?;
+        Ok(req)
+    }
+
+    /// Upload examples as a JSONL training file with the given name.
+    pub async fn upload(&self, filename: &str, examples: &[TrainingExample]) -> Result<FileObject> {
+        let content = to_jsonl(examples)?.into_bytes();
+        self.send(self.generate_upload_request(filename, content)?).await
+    }
+
+    /// Upload a JSONL training file.
+    pub async fn upload_file<P: AsRef<Path>>(&self, path: P) -> Result<FileObject> {
+        let path = path.as_ref();
+        let filename = path.file_name().and_then(|name| name.to_str()).unwrap_or("training.jsonl");
+        let content = tokio::fs::read(path).await?;
+        self.send(self.generate_upload_request(filename, content)?).await
+    }
+
+    /// Create a fine-tuning job.
+    pub async fn create_job(&self, request: &FineTuningJobRequest) -> Result<FineTuningJob> {
+        self.send(self.generate_job_request(request)?).await
+    }
+
+    /// Get the current state of a fine-tuning job.
+    pub async fn retrieve_job(&self, id: &str) -> Result<FineTuningJob> {
+        let req = self.client.get(format!("{}/{}", OPENAI_FINE_TUNING_URL, id));
+        self.send(self.authorize(req).build()?).await
+    }
+
+    /// Cancel a fine-tuning job.
+    pub async fn cancel_job(&self, id: &str) -> Result<FineTuningJob> {
+        let req = self.client.post(format!("{}/{}/cancel", OPENAI_FINE_TUNING_URL, id));
+        self.send(self.authorize(req).build()?).await
+    }
+
+    /// Get the latest events of a fine-tuning job, most recent first.
+    pub async fn events(&self, id: &str) -> Result<Vec<FineTuningEvent>> {
+        let req = self.client.get(format!("{}/{}/events", OPENAI_FINE_TUNING_URL, id));
+        let events: List<FineTuningEvent> = self.send(self.authorize(req).build()?).await?;
+        Ok(events.data)
+    }
+
+    /// Wait for a fine-tuning job to finish, checking its status at the poll interval. Fails if the job
+    /// failed or was cancelled, and otherwise returns the job with the name of the tuned model.
+    pub async fn wait(&self, id: &str) -> Result<FineTuningJob> {
+        loop {
+            let job = self.retrieve_job(id).await?;
+            match job.status {
+                JobStatus::Succeeded => return Ok(job),
+                JobStatus::Failed => {
+                    let message = job.error.map(|error| error.message).unwrap_or_default();
+                    return Err(anyhow!("Fine-tuning job {} failed: {}", id, message));
+                }
+                JobStatus::Cancelled => return Err(anyhow!("Fine-tuning job {} was cancelled", id)),
+                status => log::info!("Fine-tuning job {} is {:?}", id, status),
+            }
+            tokio::time::sleep(self.poll_interval).await;
+        }
+    }
+
+    fn authorize(&self, req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        req.header("Authorization", format!("Bearer {}", self.api_key))
+    }
+
+    async fn send<T: DeserializeOwned>(&self, req: reqwest::Request) -> Result<T> {
+        let res = self.client.execute(req).await?;
+        let status = res.status();
+        if !status.is_success() {
+            return Err(anyhow!(
+                "OpenAI request failed with status {}: {}",
+                status,
+                res.text().await?
+            ));
+        }
+        Ok(res.json().await?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::eval::judge::Judgement;
+    use crate::prompt::chat::Message;
+
+    #[test]
+    fn test_to_jsonl() {
+        let prompt = ChatPrompt(vec![
+            Message::new(Role::System, "You are a marine biologist."),
+            Message::new(Role::User, "What do orcas eat?"),
+        ]);
+        let examples = vec![
+            TrainingExample::from_chat(&prompt, "Fish, seals and whales."),
+            TrainingExample::from_sample(&JudgeSample::new("Are orcas dolphins?", "No.").with_reference("Yes.")),
+        ];
+        let jsonl = to_jsonl(&examples).unwrap();
+        let lines: Vec<&str> = jsonl.lines().collect();
+        assert_eq!(
+            lines[1],
+            r#"{"messages":[{"role":"user","content":"Are orcas dolphins?"},{"role":"assistant","content":"Yes."}]}"#
+        );
+
+        let path = std::env::temp_dir().join(format!("orca-finetune-{}.jsonl", uuid::Uuid::new_v4()));
+        write_jsonl(&examples, &path).unwrap();
+        assert_eq!(read_jsonl(&path).unwrap(), examples);
+        std::fs::remove_file(path).unwrap();
+
+        let unanswered = TrainingExample::new().with_message(Role::User, "Hello?");
+        assert!(to_jsonl(&[unanswered]).is_err());
+    }
+
+    #[test]
+    fn test_judged_examples() {
+        let samples = vec![
+            JudgeSample::new("Q1", "A1"),
+            JudgeSample::new("Q2", "A2"),
+            JudgeSample::new("Q3", "A3"),
+        ];
+        let judgement = |score| {
+            Some(Judgement {
+                score,
+                rationale: String::new(),
+            })
+        };
+        let report = JudgeReport::from_judgements(vec![judgement(5), judgement(2), None]);
+        let examples = judged_examples(&samples, &report, 4);
+        assert_eq!(examples.len(), 1);
+        assert_eq!(examples[0].messages[1].content, "A1");
+    }
+
+    #[test]
+    fn test_job_request() {
+        let request = FineTuningJobRequest::new("gpt-3.5-turbo-1106", "file-abc").with_suffix("orca").with_epochs(3);
+        let client = FineTuning::new("sk-test");
+        let req = client.generate_job_request(&request).unwrap();
+        assert_eq!(req.url().as_str(), OPENAI_FINE_TUNING_URL);
+        assert_eq!(req.headers()["Authorization"], "Bearer sk-test");
+        let body: serde_json::Value = serde_json::from_slice(req.body().unwrap().as_bytes().unwrap()).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "model": "gpt-3.5-turbo-1106",
+                "training_file": "file-abc",
+                "suffix": "orca",
+                "hyperparameters": {"n_epochs": 3}
+            })
+        );
+
+        let job: FineTuningJob = serde_json::from_str(
+            r#"{"object": "fine_tuning.job", "id": "ftjob-abc", "model": "gpt-3.5-turbo-1106", "created_at": 1692661014,
+            "finished_at": 1692661190, "fine_tuned_model": "ft:gpt-3.5-turbo-1106:org:orca:7p4lURel",
+            "organization_id": "org-123", "result_files": [], "status": "succeeded", "validation_file": null,
+            "training_file": "file-abc", "trained_tokens": 5768, "error": null}"#,
+        )
+        .unwrap();
+        assert!(job.status.is_finished());
+        assert_eq!(
+            job.fine_tuned_model.as_deref(),
+            Some("ft:gpt-3.5-turbo-1106:org:orca:7p4lURel")
+        );
+    }
+}
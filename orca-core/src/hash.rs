@@ -0,0 +1,41 @@
+//! Stable hashes.
+//!
+//! FNV-1a hashes, for hashes that are stored or shared between processes, e.g. deduplication hashes
+//! and prompt cache keys. Unlike `DefaultHasher`, whose algorithm may change between Rust versions,
+//! they are the same for every build.
+
+/// Initial state of FNV-1a.
+const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+
+/// Multiplier of FNV-1a.
+const PRIME: u64 = 0x100000001b3;
+
+/// Feed bytes to an FNV-1a hash.
+fn extend(hash: u64, bytes: impl IntoIterator<Item = u8>) -> u64 {
+    bytes.into_iter().fold(hash, |hash, byte| (hash ^ byte as u64).wrapping_mul(PRIME))
+}
+
+/// FNV-1a hash of bytes.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    extend(OFFSET_BASIS, bytes.iter().copied())
+}
+
+/// FNV-1a hash of a sequence of strings, each followed by a byte that never occurs in UTF-8, so that
+/// e.g. `["ab", "c"]` and `["a", "bc"]` hash differently.
+pub(crate) fn fnv1a_words<S: AsRef<str>>(words: impl IntoIterator<Item = S>) -> u64 {
+    words.into_iter().fold(OFFSET_BASIS, |hash, word| {
+        extend(hash, word.as_ref().bytes().chain(std::iter::once(0xff)))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fnv1a() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        assert_ne!(fnv1a_words(["ab", "c"]), fnv1a_words(["a", "bc"]));
+    }
+}
@@ -1,6 +1,19 @@
+pub mod bench;
+pub mod bundle;
+pub mod docstore;
+pub mod error;
+pub mod eval;
+pub mod finetune;
+pub(crate) mod hash;
 pub mod llm;
+pub mod math;
 pub mod memory;
 pub mod pipeline;
 pub mod prompt;
 pub mod qdrant;
 pub mod record;
+pub(crate) mod task;
+pub mod telemetry;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod vectorstore;
@@ -0,0 +1,408 @@
+use std::fmt::Display;
+use std::sync::Arc;
+
+use crate::{
+    error::OrcaError,
+    llm::{RequestMetadata, LLM},
+    prompt::{
+        chat::{CacheControl, Message, Role},
+        Prompt,
+    },
+};
+use anyhow::Result;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+
+use super::secrets::{EnvSecrets, SecretsProvider, StaticSecrets};
+use super::stop::{truncate_parts, StopConditions, StopCriteria};
+use super::{LLMResponse, TokenUsage};
+
+/// Name of the secret holding the API key.
+pub const ANTHROPIC_API_KEY: &str = "ANTHROPIC_API_KEY";
+
+static ANTHROPIC_MESSAGES_URL: &str = "https://api.anthropic.com/v1/messages";
+static ANTHROPIC_VERSION: &str = "2023-06-01";
+
+#[derive(Serialize, Debug)]
+pub struct Payload {
+    model: String,
+    max_tokens: u32,
+    temperature: f32,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    system: Vec<ContentBlock>,
+    messages: Vec<AnthropicMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<AnthropicMetadata>,
+}
+
+/// Request metadata supported by the Anthropic Messages API.
+#[derive(Serialize, Debug)]
+pub struct AnthropicMetadata {
+    user_id: String,
+}
+
+/// Message as sent to the Anthropic Messages API.
+#[derive(Serialize, Debug)]
+pub struct AnthropicMessage {
+    role: Role,
+    content: Vec<ContentBlock>,
+}
+
+/// Text content block. A block with `cache_control` marks the end of a cacheable prompt prefix.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ContentBlock {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cache_control: Option<CacheControl>,
+}
+
+/// Text block of a message. Anthropic has no tool role without tool use, so the output of a tool is
+/// labelled with the name of the tool. The names of other participants are not sent.
+impl From<&Message> for ContentBlock {
+    fn from(message: &Message) -> Self {
+        let text = match message.role {
+            Role::Tool => format!(
+                "Output of {}:\n{}",
+                message.name.as_deref().unwrap_or("tool"),
+                message.content
+            ),
+            _ => message.content.clone(),
+        };
+        ContentBlock {
+            kind: "text".to_string(),
+            text,
+            cache_control: message.cache_control,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Response {
+    id: String,
+    model: String,
+    content: Vec<ContentBlock>,
+    stop_reason: Option<String>,
+    usage: Usage,
+}
+
+impl Response {
+    /// Number of input tokens written to the prompt cache by this request.
+    pub fn cache_creation_tokens(&self) -> u32 {
+        self.usage.cache_creation_input_tokens.unwrap_or(0)
+    }
+
+    /// Number of input tokens read from the prompt cache by this request.
+    pub fn cached_tokens(&self) -> u32 {
+        self.usage.cache_read_input_tokens.unwrap_or(0)
+    }
+
+    /// Total number of input and output tokens used by this request.
+    pub fn total_tokens(&self) -> u32 {
+        self.usage.input_tokens + self.usage.output_tokens
+    }
+
+    /// Input and output tokens used by this request.
+    pub fn token_usage(&self) -> TokenUsage {
+        TokenUsage {
+            prompt_tokens: self.usage.input_tokens,
+            completion_tokens: self.usage.output_tokens,
+            model: Some(self.model.clone()),
+        }
+    }
+
+    /// Truncate the text of the response to the given length in bytes.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        let blocks = self.content.iter_mut().filter(|block| block.kind == "text");
+        truncate_parts(blocks.map(|block| &mut block.text), len);
+    }
+}
+
+impl Display for Response {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut s = String::new();
+        for block in self.content.iter().filter(|block| block.kind == "text") {
+            s.push_str(&block.text);
+        }
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Usage {
+    input_tokens: u32,
+    output_tokens: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cache_creation_input_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cache_read_input_tokens: Option<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ErrorDetails {
+    #[serde(rename = "type")]
+    kind: String,
+    message: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum AnthropicResponse {
+    Response(Response),
+    Error { error: ErrorDetails },
+}
+
+#[derive(Clone)]
+pub struct Anthropic {
+    /// Client member for the Anthropic API.
+    client: Client,
+
+    /// URL of the Anthropic API
+    /// This URL is set to https://api.anthropic.com/v1/messages by default.
+    url: String,
+
+    /// Provider of the API key for the Anthropic API, read on every request.
+    /// By default, the key is read from the ANTHROPIC_API_KEY environment variable.
+    secrets: Arc<dyn SecretsProvider>,
+
+    /// ID of the model to use, e.g. "claude-3-haiku-20240307".
+    model: String,
+
+    /// The maximum number of tokens to generate.
+    max_tokens: u32,
+
+    /// Amount of randomness injected into the response, between 0 and 1.
+    temperature: f32,
+
+    /// Criteria applied client-side to the responses, which are cut where one is met.
+    stop: StopConditions,
+}
+
+impl Default for Anthropic {
+    fn default() -> Self {
+        Self {
+            client: Client::new(),
+            url: ANTHROPIC_MESSAGES_URL.to_string(),
+            secrets: Arc::new(EnvSecrets),
+            model: "claude-3-haiku-20240307".to_string(),
+            max_tokens: 1024,
+            temperature: 1.0,
+            stop: StopConditions::new(),
+        }
+    }
+}
+
+impl Anthropic {
+    /// Create a new Anthropic client
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the API key, instead of reading it from the ANTHROPIC_API_KEY environment variable.
+    pub fn with_api_key(self, api_key: &str) -> Self {
+        self.with_secrets(StaticSecrets::new().with_secret(ANTHROPIC_API_KEY, api_key))
+    }
+
+    /// Set the provider the API key is read from on every request, e.g. to use a key per tenant or
+    /// rotate keys at runtime. The key is looked up as `ANTHROPIC_API_KEY`.
+    pub fn with_secrets<S: SecretsProvider + 'static>(mut self, secrets: S) -> Self {
+        self.secrets = Arc::new(secrets);
+        self
+    }
+
+    /// Set model to use
+    pub fn with_model(mut self, model: &str) -> Self {
+        self.model = model.to_string();
+        self
+    }
+
+    /// Set the maximum number of tokens to generate
+    pub fn with_max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    /// Set temperature
+    pub fn with_temperature(mut self, temperature: f32) -> Self {
+        self.temperature = temperature;
+        self
+    }
+
+    /// Add a stop criteria, applied client-side to the responses as if they were streamed.
+    pub fn with_stop_criteria<S: StopCriteria + 'static>(mut self, criteria: S) -> Self {
+        self.stop = self.stop.with(criteria);
+        self
+    }
+
+    /// Generate a request for the Anthropic API and set the parameters
+    pub fn generate_request(&self, messages: &[Message]) -> Result<reqwest::Request> {
+        self.generate_request_with_metadata(messages, &RequestMetadata::default())
+    }
+
+    /// Generate a request for the Anthropic API, sending the end user id in the request metadata and
+    /// the trace id, idempotency key and custom headers as HTTP headers.
+    pub fn generate_request_with_metadata(
+        &self,
+        messages: &[Message],
+        metadata: &RequestMetadata,
+    ) -> Result<reqwest::Request> {
+        let mut payload = self.payload(messages);
+        payload.metadata = metadata.user_id.clone().map(|user_id| AnthropicMetadata { user_id });
+        let req = metadata
+            .apply(self.client.post(&self.url))
+            .header("x-api-key", self.secrets.secret(ANTHROPIC_API_KEY)?)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .json(&payload)
+            .build()?;
+        Ok(req)
+    }
+
+    /// Build the request body. System messages are sent as system blocks, tool messages as user
+    /// messages, and consecutive messages with the same role are merged into a single message with
+    /// one block each, as the API requires alternating roles.
+    fn payload(&self, messages: &[Message]) -> Payload {
+        let mut system = Vec::new();
+        let mut turns: Vec<AnthropicMessage> = Vec::new();
+        for message in messages {
+            let role = match message.role {
+                Role::Tool => &Role::User,
+                ref role => role,
+            };
+            match (role, turns.last_mut()) {
+                (Role::System, _) => system.push(message.into()),
+                (role, Some(last)) if last.role == *role => last.content.push(message.into()),
+                (role, _) => turns.push(AnthropicMessage {
+                    role: role.clone(),
+                    content: vec![message.into()],
+                }),
+            }
+        }
+
+        Payload {
+            model: self.model.clone(),
+            max_tokens: self.max_tokens,
+            temperature: self.temperature,
+            system,
+            messages: turns,
+            metadata: None,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl LLM for Anthropic {
+    async fn generate(&self, prompt: Box<dyn Prompt>) -> Result<LLMResponse> {
+        self.generate_with_metadata(prompt, &RequestMetadata::default()).await
+    }
+
+    async fn generate_with_metadata(&self, prompt: Box<dyn Prompt>, metadata: &RequestMetadata) -> Result<LLMResponse> {
+        let messages = prompt.to_chat()?;
+        let req = self.generate_request_with_metadata(messages.to_vec_ref(), metadata)?;
+        let res = self.client.execute(req).await?;
+        let status = res.status();
+        match res.json::<AnthropicResponse>().await? {
+            AnthropicResponse::Response(mut response) => {
+                if let Some(len) = self.stop.apply(&response.to_string()) {
+                    response.truncate(len);
+                }
+                Ok(response.into())
+            }
+            AnthropicResponse::Error { error } => Err(OrcaError::LLM {
+                provider: "Anthropic".to_string(),
+                status: Some(status.as_u16()),
+                message: format!("{}: {}", error.kind, error.message),
+            }
+            .into()),
+        }
+    }
+
+    fn deterministic(&self) -> Option<Self> {
+        Some(self.clone().with_temperature(0.0))
+    }
+
+    fn config(&self) -> JsonValue {
+        json!({
+            "type": "Anthropic",
+            "model": self.model,
+            "temperature": self.temperature,
+            "max_tokens": self.max_tokens,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn client() -> Anthropic {
+        Anthropic {
+            client: Client::new(),
+            url: ANTHROPIC_MESSAGES_URL.to_string(),
+            secrets: Arc::new(StaticSecrets::new().with_secret(ANTHROPIC_API_KEY, "test")),
+            model: "claude-3-haiku-20240307".to_string(),
+            max_tokens: 1024,
+            temperature: 1.0,
+            stop: StopConditions::new(),
+        }
+    }
+
+    #[test]
+    fn test_payload() {
+        let messages = vec![
+            Message::new(Role::System, "You answer questions about Orca.").with_cache_control(),
+            Message::new(Role::User, "What is Orca?"),
+            Message::new(Role::User, "Answer briefly."),
+            Message::new(Role::Tool, "Orca is an LLM orchestration framework.").with_name("search"),
+        ];
+        let payload = serde_json::to_value(client().payload(&messages)).unwrap();
+        assert_eq!(
+            payload["system"],
+            json!([{"type": "text", "text": "You answer questions about Orca.", "cache_control": {"type": "ephemeral"}}])
+        );
+        assert_eq!(
+            payload["messages"],
+            json!([{"role": "user", "content": [
+                {"type": "text", "text": "What is Orca?"},
+                {"type": "text", "text": "Answer briefly."},
+                {"type": "text", "text": "Output of search:\nOrca is an LLM orchestration framework."}
+            ]}])
+        );
+    }
+
+    #[test]
+    fn test_request_metadata() {
+        let metadata = RequestMetadata::new().with_user_id("user-1").with_idempotency_key("key-1");
+        let request = client().generate_request_with_metadata(&[Message::new(Role::User, "Hi")], &metadata).unwrap();
+        assert_eq!(request.headers()["Idempotency-Key"], "key-1");
+        let body: serde_json::Value = serde_json::from_slice(request.body().unwrap().as_bytes().unwrap()).unwrap();
+        assert_eq!(body["metadata"], json!({"user_id": "user-1"}));
+    }
+
+    #[test]
+    fn test_response() {
+        let response: AnthropicResponse = serde_json::from_value(json!({
+            "id": "msg_01",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-3-haiku-20240307",
+            "content": [{"type": "text", "text": "Orca is an LLM orchestration framework."}],
+            "stop_reason": "end_turn",
+            "usage": {"input_tokens": 12, "output_tokens": 9, "cache_read_input_tokens": 2048}
+        }))
+        .unwrap();
+        match response {
+            AnthropicResponse::Response(mut response) => {
+                assert_eq!(response.to_string(), "Orca is an LLM orchestration framework.");
+                assert_eq!(response.cached_tokens(), 2048);
+                assert_eq!(response.cache_creation_tokens(), 0);
+                response.truncate(4);
+                assert_eq!(response.to_string(), "Orca");
+            }
+            AnthropicResponse::Error { .. } => panic!("expected a response"),
+        }
+    }
+}
@@ -8,7 +8,7 @@ use anyhow::{anyhow, Error as E, Result};
 use candle_core::Tensor;
 use candle_nn::VarBuilder;
 use candle_transformers::models::bert::{BertModel, Config, DTYPE};
-use hf_hub::{api::tokio::Api, Cache, Repo, RepoType};
+use hf_hub::{Repo, RepoType};
 use rayon::prelude::*;
 use std::sync::{Arc, Mutex};
 use tokenizers::{PaddingParams, Tokenizer};
@@ -16,7 +16,8 @@ use tokio::sync::RwLock;
 
 use crate::prompt::Prompt;
 
-use super::{Embedding, EmbeddingResponse};
+use super::source::ModelSource;
+use super::{Embedding, EmbeddingPreset, Embeddings, LoadMode, Precision};
 
 pub struct Bert {
     /// Run on CPU rather than on GPU.
@@ -25,7 +26,10 @@ pub struct Bert {
     /// Run offline (you must have the files already cached)
     offline: bool,
 
-    /// Enable tracing (generates a trace-timestamp.json file).
+    /// Where the model files are fetched from, the global `ModelSource` by default.
+    source: Option<ModelSource>,
+
+    /// Trace the computations of the model to their own Chrome trace file (trace-timestamp.json).
     tracing: bool,
 
     /// The model to use, check out available models: https://huggingface.co/models?library=sentence-transformers&sort=trending
@@ -41,20 +45,44 @@ pub struct Bert {
 
     /// L2 normalization for embeddings.
     normalize_embeddings: bool,
+
+    /// Size of the embeddings, read from the model config.
+    hidden_size: Option<usize>,
+
+    /// Precision of the output embeddings.
+    precision: Precision,
+
+    /// Number of threads computing the embeddings, the candle default (all cores) unless set.
+    threads: Option<usize>,
+
+    /// Thread pool of the model, built with the model when the number of threads is set.
+    thread_pool: Option<Arc<rayon::ThreadPool>>,
+
+    /// How the weights file is loaded.
+    load_mode: LoadMode,
 }
 
+/// Size of the embeddings of the default model, `sentence-transformers/all-MiniLM-L6-v2`.
+const DEFAULT_DIMENSIONS: usize = 384;
+
 impl Default for Bert {
     /// Provides default values for `Bert`.
     fn default() -> Self {
         Self {
             cpu: true,
             offline: false,
+            source: None,
             tracing: false,
             model_id: None,
             model: None,
             tokenizer: None,
             revision: None,
             normalize_embeddings: false,
+            hidden_size: None,
+            precision: Precision::F32,
+            threads: None,
+            thread_pool: None,
+            load_mode: LoadMode::Mmap,
         }
     }
 }
@@ -83,7 +111,14 @@ impl Bert {
         self
     }
 
-    /// Enables tracing for the model.
+    /// Sets where the model files are fetched from, instead of the global `ModelSource`.
+    pub fn with_source(mut self, source: ModelSource) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// Enables tracing for the model, its computations being traced to their own Chrome trace file without
+    /// touching the subscriber of the application. Whole pipeline executions are traced with `orca_core::telemetry`.
     pub fn with_tracing(mut self) -> Self {
         self.tracing = true;
         self
@@ -101,12 +136,39 @@ impl Bert {
         self
     }
 
+    /// Sets the model ID and revision from a preset, e.g. `EmbeddingPreset::ParaphraseMultilingualMiniLmL12V2`
+    /// for mixed-language corpora. Presets of models that are not BERT models fail to build.
+    pub fn with_preset(self, preset: EmbeddingPreset) -> Self {
+        self.with_model_id(preset.model_id()).with_revision(preset.revision())
+    }
+
     /// Enables L2 normalization for embeddings.
     pub fn with_normalize_embeddings(mut self) -> Self {
         self.normalize_embeddings = true;
         self
     }
 
+    /// Sets the precision of the output embeddings. `F16` and `Int8` embeddings take half and a quarter of the
+    /// memory of `F32` ones, and the scale of each `Int8` embedding is kept alongside it.
+    pub fn with_precision(mut self, precision: Precision) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// Sets the number of threads computing the embeddings, e.g. to partition the cores of a server
+    /// between the models of several pipelines. The model runs on its own thread pool of that size,
+    /// built with the model, instead of the global one using all cores.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Sets how the weights file is loaded, memory-mapped by default.
+    pub fn with_load_mode(mut self, load_mode: LoadMode) -> Self {
+        self.load_mode = load_mode;
+        self
+    }
+
     /// Builds the model and tokenizer.
     pub async fn build_model_and_tokenizer(mut self) -> Result<Self> {
         let device = super::device(self.cpu)?;
@@ -120,53 +182,68 @@ impl Bert {
         };
 
         let repo = Repo::with_revision(model_id, RepoType::Model, revision);
-        let (config_filename, tokenizer_filename, weights_filename) = if self.offline {
-            let cache = Cache::default().repo(repo);
-            (
-                cache.get("config.json").ok_or(anyhow!("Missing config file in cache"))?,
-                cache.get("tokenizer.json").ok_or(anyhow!("Missing tokenizer file in cache"))?,
-                cache.get("model.safetensors").ok_or(anyhow!("Missing weights file in cache"))?,
-            )
-        } else {
-            let api = Api::new()?;
-            let api = api.repo(repo);
-            (
-                api.get("config.json").await?,
-                api.get("tokenizer.json").await?,
-                api.get("model.safetensors").await?,
-            )
-        };
+        let source = ModelSource::resolve(self.source.as_ref(), self.offline);
+        let (config_filename, tokenizer_filename, weights_filename) = (
+            source.get(&repo, "config.json").await?,
+            source.get(&repo, "tokenizer.json").await?,
+            source.get(&repo, "model.safetensors").await?,
+        );
         let config = std::fs::read_to_string(config_filename)?;
         let config: Config = serde_json::from_str(&config)?;
+        if let Some(model_type) = config.model_type.as_deref().filter(|model_type| *model_type != "bert") {
+            return Err(anyhow!(
+                "Model type {} is not supported by Bert, try the ONNX backend",
+                model_type
+            ));
+        }
         let tokenizer = Tokenizer::from_file(tokenizer_filename).map_err(E::msg)?;
 
-        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[weights_filename], DTYPE, &device)? };
+        let vb = match self.load_mode {
+            LoadMode::Mmap => unsafe { VarBuilder::from_mmaped_safetensors(&[weights_filename], DTYPE, &device)? },
+            LoadMode::Read => VarBuilder::from_buffered_safetensors(std::fs::read(weights_filename)?, DTYPE, &device)?,
+        };
         let model = BertModel::load(vb, &config)?;
+        self.hidden_size = Some(config.hidden_size);
         self.model = Some(Arc::new(model));
         self.tokenizer = Some(RwLock::new(tokenizer));
+        self.thread_pool = self.threads.map(super::thread_pool).transpose()?;
         Ok(self)
     }
+
+    /// Runs the function on the thread pool of the model, if it has one, in a `bert.forward` span.
+    fn install<R: Send>(&self, f: impl FnOnce() -> R + Send) -> R {
+        super::run_traced(
+            self.thread_pool.as_deref(),
+            self.tracing,
+            || tracing::info_span!("bert.forward"),
+            f,
+        )
+    }
+
+    /// Averages the token embeddings of each prompt, normalizing them if configured to, and converts them
+    /// to the configured precision.
+    fn pool(&self, hidden_states: &Tensor) -> Result<Embeddings> {
+        let embeddings = Embeddings::from_hidden_states("bert", hidden_states)?;
+        let embeddings = match &self.model_id {
+            Some(model_id) => embeddings.with_model(model_id),
+            None => embeddings,
+        };
+        let embeddings = if self.normalize_embeddings {
+            embeddings.normalize()
+        } else {
+            embeddings
+        };
+        Ok(embeddings.with_precision(self.precision))
+    }
 }
 
 #[async_trait::async_trait]
 impl Embedding for Bert {
-    async fn generate_embedding(&self, prompt: Box<dyn Prompt>) -> Result<EmbeddingResponse> {
-        use tracing_chrome::ChromeLayerBuilder;
-        use tracing_subscriber::prelude::*;
-
+    async fn generate_embedding(&self, prompt: Box<dyn Prompt>) -> Result<Embeddings> {
         if self.model.is_none() || self.tokenizer.is_none() {
             return Err(anyhow!("Model or tokenizer not initialized"));
         }
 
-        let _guard = if self.tracing {
-            log::info!("tracing...");
-            let (chrome_layer, guard) = ChromeLayerBuilder::new().build();
-            tracing_subscriber::registry().with(chrome_layer).init();
-            Some(guard)
-        } else {
-            None
-        };
-
         let model = self.model.as_ref().unwrap().clone();
         let mut tokenizer = self.tokenizer.as_ref().unwrap().write().await;
         let device = &model.device;
@@ -178,29 +255,17 @@ impl Embedding for Bert {
         let token_type_ids = token_ids.zeros_like()?;
         log::info!("running inference {:?}", token_ids.shape());
         let start = std::time::Instant::now();
-        let embedding = model.forward(&token_ids, &token_type_ids)?;
+        let embedding = self.install(|| model.forward(&token_ids, &token_type_ids))?;
         log::info!("embedding shape: {:?}", embedding.shape());
         log::info!("Embedding took {:?} to generate", start.elapsed());
-        Ok(EmbeddingResponse::Bert(embedding))
+        self.pool(&embedding)
     }
 
-    async fn generate_embeddings(&self, prompts: Vec<Box<dyn Prompt>>) -> Result<EmbeddingResponse> {
-        use tracing_chrome::ChromeLayerBuilder;
-        use tracing_subscriber::prelude::*;
-
+    async fn generate_embeddings(&self, prompts: Vec<Box<dyn Prompt>>) -> Result<Embeddings> {
         if self.model.is_none() || self.tokenizer.is_none() {
             return Err(anyhow!("Model or tokenizer not initialized"));
         }
 
-        let _guard = if self.tracing {
-            log::info!("tracing...");
-            let (chrome_layer, guard) = ChromeLayerBuilder::new().build();
-            tracing_subscriber::registry().with(chrome_layer).init();
-            Some(guard)
-        } else {
-            None
-        };
-
         let model: Arc<BertModel> = self.model.as_ref().unwrap().clone();
         let mut tokenizer: tokio::sync::RwLockWriteGuard<'_, Tokenizer> =
             self.tokenizer.as_ref().unwrap().write().await;
@@ -236,15 +301,17 @@ impl Embedding for Bert {
         // Use rayon to compute embeddings in parallel
         log::info!("Computing embeddings");
         let start = std::time::Instant::now();
-        token_ids.par_iter().try_for_each_with(embeddings_arc.clone(), |embeddings_arc, (i, token_ids)| {
-            let token_type_ids = token_ids.zeros_like()?;
-            let embedding = model.forward(token_ids, &token_type_ids)?.squeeze(0)?;
+        self.install(|| {
+            token_ids.par_iter().try_for_each_with(embeddings_arc.clone(), |embeddings_arc, (i, token_ids)| {
+                let token_type_ids = token_ids.zeros_like()?;
+                let embedding = model.forward(token_ids, &token_type_ids)?.squeeze(0)?;
 
-            // Lock the mutex and write the embedding to the correct index
-            let mut embeddings = embeddings_arc.lock().map_err(|e| anyhow!("Mutex error: {}", e))?;
-            embeddings[*i] = embedding;
+                // Lock the mutex and write the embedding to the correct index
+                let mut embeddings = embeddings_arc.lock().map_err(|e| anyhow!("Mutex error: {}", e))?;
+                embeddings[*i] = embedding;
 
-            Ok::<(), anyhow::Error>(())
+                Ok::<(), anyhow::Error>(())
+            })
         })?;
         log::info!("Done computing embeddings");
         log::info!("Embeddings took {:?} to generate", start.elapsed());
@@ -257,7 +324,15 @@ impl Embedding for Bert {
 
         let stacked_embeddings = Tensor::stack(&embeddings_arc, 0)?;
 
-        Ok(EmbeddingResponse::Bert(stacked_embeddings))
+        self.pool(&stacked_embeddings)
+    }
+
+    fn dimensions(&self) -> usize {
+        self.hidden_size.unwrap_or(DEFAULT_DIMENSIONS)
+    }
+
+    fn precision(&self) -> Precision {
+        self.precision
     }
 }
 
@@ -281,7 +356,7 @@ mod test {
         let bert = Bert::new().build_model_and_tokenizer().await.unwrap();
         let response = bert.generate_embeddings(prompts!("Hello World", "Goodbye World")).await;
         let response = response.unwrap();
-        let vec = response.to_vec2().unwrap();
+        let vec = response.to_vec2();
         assert_eq!(vec.len(), 2);
         assert_eq!(vec[0].len(), 384);
         assert_eq!(vec[1].len(), 384);
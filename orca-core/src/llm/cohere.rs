@@ -0,0 +1,479 @@
+use std::fmt::Display;
+use std::sync::Arc;
+
+use crate::{
+    error::OrcaError,
+    llm::{Embedding, Embeddings, RequestMetadata, LLM},
+    prompt::{
+        chat::{Message, Role},
+        Prompt,
+    },
+};
+use anyhow::Result;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+
+use super::secrets::{EnvSecrets, SecretsProvider, StaticSecrets};
+use super::{LLMResponse, TokenUsage};
+
+/// Name of the secret holding the API key.
+pub const COHERE_API_KEY: &str = "COHERE_API_KEY";
+
+static COHERE_CHAT_URL: &str = "https://api.cohere.ai/v1/chat";
+static COHERE_EMBED_URL: &str = "https://api.cohere.ai/v1/embed";
+
+/// Maximum number of texts the embed endpoint accepts per request.
+const EMBED_BATCH_SIZE: usize = 96;
+
+/// What embeddings are used for. Cohere embeds texts differently for each use, so documents indexed with
+/// `SearchDocument` must be searched with queries embedded with `SearchQuery`.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum InputType {
+    /// Documents stored in a vector store to be searched.
+    #[default]
+    SearchDocument,
+
+    /// Queries searching the documents embedded with `SearchDocument`.
+    SearchQuery,
+
+    /// Texts given to a classifier.
+    Classification,
+
+    /// Texts grouped by a clustering algorithm.
+    Clustering,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Payload {
+    model: String,
+    message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    chat_history: Vec<ChatMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    preamble: Option<String>,
+    temperature: f32,
+    max_tokens: u32,
+}
+
+/// Message of the chat history, as sent to the Cohere Chat API.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ChatMessage {
+    role: &'static str,
+    message: String,
+}
+
+/// Message of the chat history. Cohere has no tool role without tool use, so the output of a tool is sent
+/// as a user message labelled with the name of the tool.
+impl From<&Message> for ChatMessage {
+    fn from(message: &Message) -> Self {
+        let (role, text) = match message.role {
+            Role::System => ("SYSTEM", message.content.clone()),
+            Role::Assistant => ("CHATBOT", message.content.clone()),
+            Role::User => ("USER", message.content.clone()),
+            Role::Tool => (
+                "USER",
+                format!(
+                    "Output of {}:\n{}",
+                    message.name.as_deref().unwrap_or("tool"),
+                    message.content
+                ),
+            ),
+        };
+        ChatMessage { role, message: text }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct EmbedPayload<'a> {
+    model: &'a str,
+    texts: &'a [String],
+    input_type: InputType,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Response {
+    #[serde(default)]
+    response_id: String,
+    text: String,
+    #[serde(default)]
+    finish_reason: Option<String>,
+    #[serde(default)]
+    meta: Meta,
+}
+
+impl Response {
+    /// Total number of input and output tokens billed for this request.
+    pub fn total_tokens(&self) -> u32 {
+        self.meta.billed_units.input_tokens + self.meta.billed_units.output_tokens
+    }
+
+    /// Input and output tokens billed for this request. Cohere does not report the model that answered.
+    pub fn token_usage(&self) -> TokenUsage {
+        TokenUsage {
+            prompt_tokens: self.meta.billed_units.input_tokens,
+            completion_tokens: self.meta.billed_units.output_tokens,
+            model: None,
+        }
+    }
+}
+
+impl Display for Response {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Meta {
+    #[serde(default)]
+    billed_units: BilledUnits,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct BilledUnits {
+    #[serde(default)]
+    input_tokens: u32,
+    #[serde(default)]
+    output_tokens: u32,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EmbedResponse {
+    embeddings: Vec<Vec<f32>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum CohereResponse<T> {
+    Response(T),
+    Error { message: String },
+}
+
+#[derive(Clone)]
+pub struct Cohere {
+    /// Client member for the Cohere API.
+    client: Client,
+
+    /// URL of the chat endpoint, https://api.cohere.ai/v1/chat by default.
+    chat_url: String,
+
+    /// URL of the embed endpoint, https://api.cohere.ai/v1/embed by default.
+    embed_url: String,
+
+    /// Provider of the API key for the Cohere API, read on every request.
+    /// By default, the key is read from the COHERE_API_KEY environment variable.
+    secrets: Arc<dyn SecretsProvider>,
+
+    /// ID of the chat model to use, e.g. "command-r".
+    model: String,
+
+    /// ID of the embedding model to use, e.g. "embed-english-v3.0".
+    embedding_model: String,
+
+    /// What the generated embeddings are used for.
+    input_type: InputType,
+
+    /// The maximum number of tokens to generate.
+    max_tokens: u32,
+
+    /// Amount of randomness injected into the response, between 0 and 1.
+    temperature: f32,
+}
+
+impl Default for Cohere {
+    fn default() -> Self {
+        Self {
+            client: Client::new(),
+            chat_url: COHERE_CHAT_URL.to_string(),
+            embed_url: COHERE_EMBED_URL.to_string(),
+            secrets: Arc::new(EnvSecrets),
+            model: "command-r".to_string(),
+            embedding_model: "embed-english-v3.0".to_string(),
+            input_type: InputType::SearchDocument,
+            max_tokens: 1024,
+            temperature: 0.3,
+        }
+    }
+}
+
+impl Cohere {
+    /// Create a new Cohere client
+    ///
+    /// # Example
+    /// Documents and queries are embedded with different input types.
+    /// ```no_run
+    /// use orca_core::llm::cohere::{Cohere, InputType};
+    ///
+    /// let documents = Cohere::new().with_embedding_model("embed-multilingual-v3.0");
+    /// let queries = documents.clone().with_input_type(InputType::SearchQuery);
+    /// ```
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the API key, instead of reading it from the COHERE_API_KEY environment variable.
+    pub fn with_api_key(self, api_key: &str) -> Self {
+        self.with_secrets(StaticSecrets::new().with_secret(COHERE_API_KEY, api_key))
+    }
+
+    /// Set the provider the API key is read from on every request, e.g. to use a key per tenant or
+    /// rotate keys at runtime. The key is looked up as `COHERE_API_KEY`.
+    pub fn with_secrets<S: SecretsProvider + 'static>(mut self, secrets: S) -> Self {
+        self.secrets = Arc::new(secrets);
+        self
+    }
+
+    /// Set model to use
+    pub fn with_model(mut self, model: &str) -> Self {
+        self.model = model.to_string();
+        self
+    }
+
+    /// Set the embedding model to use
+    pub fn with_embedding_model(mut self, model: &str) -> Self {
+        self.embedding_model = model.to_string();
+        self
+    }
+
+    /// Set what the generated embeddings are used for, `SearchDocument` by default.
+    pub fn with_input_type(mut self, input_type: InputType) -> Self {
+        self.input_type = input_type;
+        self
+    }
+
+    /// Set the maximum number of tokens to generate
+    pub fn with_max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    /// Set temperature
+    pub fn with_temperature(mut self, temperature: f32) -> Self {
+        self.temperature = temperature;
+        self
+    }
+
+    /// Generate a request for the Cohere Chat API and set the parameters
+    pub fn generate_request(&self, messages: &[Message]) -> Result<reqwest::Request> {
+        self.generate_request_with_metadata(messages, &RequestMetadata::default())
+    }
+
+    /// Generate a request for the Cohere Chat API, sending the trace id, idempotency key and custom
+    /// headers as HTTP headers. Cohere has no field for the end user id, so it is not sent.
+    pub fn generate_request_with_metadata(
+        &self,
+        messages: &[Message],
+        metadata: &RequestMetadata,
+    ) -> Result<reqwest::Request> {
+        let req = metadata
+            .apply(self.client.post(&self.chat_url))
+            .bearer_auth(self.secrets.secret(COHERE_API_KEY)?)
+            .json(&self.payload(messages))
+            .build()?;
+        Ok(req)
+    }
+
+    /// Generate a request for the Cohere Embed API, embedding the texts with the input type of the client.
+    pub fn generate_embedding_request(&self, texts: &[String]) -> Result<reqwest::Request> {
+        let payload = EmbedPayload {
+            model: &self.embedding_model,
+            texts,
+            input_type: self.input_type,
+        };
+        let req = self
+            .client
+            .post(&self.embed_url)
+            .bearer_auth(self.secrets.secret(COHERE_API_KEY)?)
+            .json(&payload)
+            .build()?;
+        Ok(req)
+    }
+
+    /// Build the request body. The leading system messages are sent as the preamble and the last message
+    /// as the message to answer, after the chat history of the others.
+    fn payload(&self, messages: &[Message]) -> Payload {
+        let leading = messages.iter().take_while(|message| message.role == Role::System).count();
+        let (system, messages) = match leading {
+            // A prompt of system messages only is answered as a message.
+            leading if leading == messages.len() => messages.split_at(leading.saturating_sub(1)),
+            leading => messages.split_at(leading),
+        };
+        let preamble = system.iter().map(|message| message.content.as_str()).collect::<Vec<_>>();
+        let (message, history) = match messages.split_last() {
+            Some((last, history)) => (ChatMessage::from(last).message, history),
+            None => (String::new(), messages),
+        };
+
+        Payload {
+            model: self.model.clone(),
+            message,
+            chat_history: history.iter().map(ChatMessage::from).collect(),
+            preamble: (!preamble.is_empty()).then(|| preamble.join("\n\n")),
+            temperature: self.temperature,
+            max_tokens: self.max_tokens,
+        }
+    }
+
+    /// Send a request and parse its response, failing with the error message returned by Cohere.
+    async fn send<T: serde::de::DeserializeOwned>(&self, req: reqwest::Request) -> Result<T> {
+        let res = self.client.execute(req).await?;
+        let status = res.status();
+        match res.json::<CohereResponse<T>>().await? {
+            CohereResponse::Response(response) if status.is_success() => Ok(response),
+            CohereResponse::Error { message } => Err(OrcaError::LLM {
+                provider: "Cohere".to_string(),
+                status: Some(status.as_u16()),
+                message,
+            }
+            .into()),
+            CohereResponse::Response(_) => Err(OrcaError::LLM {
+                provider: "Cohere".to_string(),
+                status: Some(status.as_u16()),
+                message: status.canonical_reason().unwrap_or("Unknown error").to_string(),
+            }
+            .into()),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl LLM for Cohere {
+    async fn generate(&self, prompt: Box<dyn Prompt>) -> Result<LLMResponse> {
+        self.generate_with_metadata(prompt, &RequestMetadata::default()).await
+    }
+
+    async fn generate_with_metadata(&self, prompt: Box<dyn Prompt>, metadata: &RequestMetadata) -> Result<LLMResponse> {
+        let messages = prompt.to_chat()?;
+        let req = self.generate_request_with_metadata(messages.to_vec_ref(), metadata)?;
+        let response: Response = self.send(req).await?;
+        Ok(response.into())
+    }
+
+    fn deterministic(&self) -> Option<Self> {
+        Some(self.clone().with_temperature(0.0))
+    }
+
+    fn config(&self) -> JsonValue {
+        json!({
+            "type": "Cohere",
+            "model": self.model,
+            "temperature": self.temperature,
+            "max_tokens": self.max_tokens,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Embedding for Cohere {
+    async fn generate_embedding(&self, prompt: Box<dyn Prompt>) -> Result<Embeddings> {
+        self.generate_embeddings(vec![prompt]).await
+    }
+
+    async fn generate_embeddings(&self, prompts: Vec<Box<dyn Prompt>>) -> Result<Embeddings> {
+        let texts = prompts.iter().map(|prompt| prompt.to_string()).collect::<Vec<_>>();
+        let mut vectors = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(EMBED_BATCH_SIZE) {
+            let req = self.generate_embedding_request(batch)?;
+            let response: EmbedResponse = self.send(req).await.map_err(|e| OrcaError::Embedding {
+                provider: "Cohere".to_string(),
+                message: e.to_string(),
+            })?;
+            if response.embeddings.len() != batch.len() {
+                return Err(OrcaError::Embedding {
+                    provider: "Cohere".to_string(),
+                    message: format!("Expected {} embeddings, got {}", batch.len(), response.embeddings.len()),
+                }
+                .into());
+            }
+            vectors.extend(response.embeddings);
+        }
+        Ok(Embeddings::new("cohere", vectors)?.with_model(&self.embedding_model))
+    }
+
+    fn dimensions(&self) -> usize {
+        match self.embedding_model.as_str() {
+            "embed-english-light-v3.0" | "embed-multilingual-light-v3.0" => 384,
+            "embed-english-v2.0" => 4096,
+            "embed-english-light-v2.0" => 1024,
+            "embed-multilingual-v2.0" => 768,
+            _ => 1024,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn client() -> Cohere {
+        Cohere::new().with_api_key("test")
+    }
+
+    #[test]
+    fn test_payload() {
+        let messages = vec![
+            Message::new(Role::System, "You answer questions about Orca."),
+            Message::new(Role::User, "What is Orca?"),
+            Message::new(Role::Assistant, "An LLM orchestration framework."),
+            Message::new(Role::Tool, "Orca is written in Rust.").with_name("search"),
+            Message::new(Role::User, "What language is it written in?"),
+        ];
+        let payload = serde_json::to_value(client().payload(&messages)).unwrap();
+        assert_eq!(payload["preamble"], "You answer questions about Orca.");
+        assert_eq!(payload["message"], "What language is it written in?");
+        assert_eq!(
+            payload["chat_history"],
+            json!([
+                {"role": "USER", "message": "What is Orca?"},
+                {"role": "CHATBOT", "message": "An LLM orchestration framework."},
+                {"role": "USER", "message": "Output of search:\nOrca is written in Rust."}
+            ])
+        );
+
+        let payload = client().payload(&[Message::new(Role::System, "Say hi.")]);
+        assert_eq!((payload.message.as_str(), payload.preamble), ("Say hi.", None));
+    }
+
+    #[test]
+    fn test_embedding_request() {
+        let client = client().with_input_type(InputType::SearchQuery);
+        let request = client.generate_embedding_request(&["Where do orcas live?".to_string()]).unwrap();
+        assert_eq!(request.headers()["Authorization"], "Bearer test");
+        let body: JsonValue = serde_json::from_slice(request.body().unwrap().as_bytes().unwrap()).unwrap();
+        assert_eq!(
+            body,
+            json!({"model": "embed-english-v3.0", "texts": ["Where do orcas live?"], "input_type": "search_query"})
+        );
+        assert_eq!(
+            client.with_embedding_model("embed-english-light-v3.0").dimensions(),
+            384
+        );
+    }
+
+    #[test]
+    fn test_response() {
+        let response: CohereResponse<Response> = serde_json::from_value(json!({
+            "response_id": "5b5d1d1c",
+            "text": "Orca is written in Rust.",
+            "generation_id": "0d0c1d5e",
+            "finish_reason": "COMPLETE",
+            "meta": {"billed_units": {"input_tokens": 42, "output_tokens": 7}}
+        }))
+        .unwrap();
+        match response {
+            CohereResponse::Response(response) => {
+                assert_eq!(
+                    LLMResponse::from(response.clone()).to_string(),
+                    "Orca is written in Rust."
+                );
+                assert_eq!(response.total_tokens(), 49);
+            }
+            CohereResponse::Error { .. } => panic!("expected a response"),
+        }
+        let error: CohereResponse<Response> = serde_json::from_value(json!({"message": "invalid api token"})).unwrap();
+        assert!(matches!(error, CohereResponse::Error { message } if message == "invalid api token"));
+    }
+}
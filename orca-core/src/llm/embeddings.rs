@@ -0,0 +1,364 @@
+//! Embedding vectors returned by the `Embedding` trait, whatever the backend.
+
+use anyhow::Result;
+use candle_core::Tensor;
+use half::f16;
+
+use crate::error::OrcaError;
+use crate::math;
+
+/// Numeric precision in which embedding vectors are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Precision {
+    /// Full precision.
+    #[default]
+    F32,
+
+    /// Half precision, half the memory of `F32`.
+    F16,
+
+    /// 8-bit integers with one scale per vector, a quarter of the memory of `F32`.
+    Int8,
+}
+
+/// Sentence embedding models with known settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmbeddingPreset {
+    /// `sentence-transformers/all-MiniLM-L6-v2`, a small English model.
+    AllMiniLmL6V2,
+
+    /// `sentence-transformers/paraphrase-multilingual-MiniLM-L12-v2`, a small model covering 50+ languages.
+    ParaphraseMultilingualMiniLmL12V2,
+
+    /// `BAAI/bge-m3`, a larger model covering 100+ languages. It is an XLM-RoBERTa model, so it can only
+    /// run with the ONNX backend.
+    BgeM3,
+}
+
+impl EmbeddingPreset {
+    /// Hugging Face model ID.
+    pub fn model_id(&self) -> &'static str {
+        match self {
+            EmbeddingPreset::AllMiniLmL6V2 => "sentence-transformers/all-MiniLM-L6-v2",
+            EmbeddingPreset::ParaphraseMultilingualMiniLmL12V2 => {
+                "sentence-transformers/paraphrase-multilingual-MiniLM-L12-v2"
+            }
+            EmbeddingPreset::BgeM3 => "BAAI/bge-m3",
+        }
+    }
+
+    /// Revision of the model repository that provides safetensors weights.
+    pub fn revision(&self) -> &'static str {
+        match self {
+            EmbeddingPreset::AllMiniLmL6V2 => "refs/pr/21",
+            EmbeddingPreset::ParaphraseMultilingualMiniLmL12V2 | EmbeddingPreset::BgeM3 => "main",
+        }
+    }
+
+    /// Size of the embeddings.
+    pub fn dimensions(&self) -> usize {
+        match self {
+            EmbeddingPreset::AllMiniLmL6V2 | EmbeddingPreset::ParaphraseMultilingualMiniLmL12V2 => 384,
+            EmbeddingPreset::BgeM3 => 1024,
+        }
+    }
+
+    /// Whether the model embeds texts of different languages in the same space.
+    pub fn is_multilingual(&self) -> bool {
+        !matches!(self, EmbeddingPreset::AllMiniLmL6V2)
+    }
+}
+
+/// Embedding vectors in the precision they are kept in.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Vectors {
+    F32(Vec<Vec<f32>>),
+    F16(Vec<Vec<f16>>),
+
+    /// Each value is `value as f32 * scale`, with the scale of its vector.
+    Int8 {
+        values: Vec<Vec<i8>>,
+        scales: Vec<f32>,
+    },
+}
+
+impl Default for Vectors {
+    fn default() -> Self {
+        Vectors::F32(Vec::new())
+    }
+}
+
+impl Vectors {
+    /// Precision of the vectors.
+    pub fn precision(&self) -> Precision {
+        match self {
+            Vectors::F32(_) => Precision::F32,
+            Vectors::F16(_) => Precision::F16,
+            Vectors::Int8 { .. } => Precision::Int8,
+        }
+    }
+
+    /// Number of vectors.
+    pub fn len(&self) -> usize {
+        match self {
+            Vectors::F32(vectors) => vectors.len(),
+            Vectors::F16(vectors) => vectors.len(),
+            Vectors::Int8 { values, .. } => values.len(),
+        }
+    }
+
+    /// Whether there are no vectors.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Convert the vectors to full precision.
+    pub fn to_f32(&self) -> Vec<Vec<f32>> {
+        match self {
+            Vectors::F32(vectors) => vectors.clone(),
+            Vectors::F16(vectors) => vectors.iter().map(|vector| vector.iter().map(|x| x.to_f32()).collect()).collect(),
+            Vectors::Int8 { values, scales } => values
+                .iter()
+                .zip(scales)
+                .map(|(vector, scale)| vector.iter().map(|&x| x as f32 * scale).collect())
+                .collect(),
+        }
+    }
+
+    /// Convert full precision vectors to the given precision. Int8 vectors are scaled so that the
+    /// largest absolute value of each vector maps to 127.
+    pub fn from_f32(vectors: Vec<Vec<f32>>, precision: Precision) -> Self {
+        match precision {
+            Precision::F32 => Vectors::F32(vectors),
+            Precision::F16 => {
+                Vectors::F16(vectors.iter().map(|vector| vector.iter().map(|&x| f16::from_f32(x)).collect()).collect())
+            }
+            Precision::Int8 => {
+                let scales: Vec<f32> = vectors
+                    .iter()
+                    .map(|vector| match vector.iter().fold(0f32, |max, x| max.max(x.abs())) {
+                        max if max > 0.0 => max / i8::MAX as f32,
+                        _ => 1.0,
+                    })
+                    .collect();
+                let values = vectors
+                    .iter()
+                    .zip(&scales)
+                    .map(|(vector, scale)| vector.iter().map(|x| (x / scale).round() as i8).collect())
+                    .collect();
+                Vectors::Int8 { values, scales }
+            }
+        }
+    }
+}
+
+/// Embeddings generated by a model, one vector per input, all of the same size.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Embeddings {
+    /// One vector per input, in the order of the inputs.
+    vectors: Vectors,
+
+    /// Size of each vector.
+    dimensions: usize,
+
+    /// Backend that generated the embeddings, e.g. "openai" or "bert".
+    provider: String,
+
+    /// Model that generated the embeddings, if known.
+    model: Option<String>,
+}
+
+impl Embeddings {
+    /// Create embeddings from vectors, checking that they all have the same size.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::llm::Embeddings;
+    ///
+    /// let embeddings = Embeddings::new("custom", vec![vec![0.1, 0.2], vec![0.3, 0.4]]).unwrap();
+    /// assert_eq!(embeddings.len(), 2);
+    /// assert_eq!(embeddings.dimensions(), 2);
+    /// assert!(Embeddings::new("custom", vec![vec![0.1, 0.2], vec![0.3]]).is_err());
+    /// ```
+    pub fn new(provider: &str, vectors: Vec<Vec<f32>>) -> Result<Self> {
+        let dimensions = vectors.first().map_or(0, |vector| vector.len());
+        if let Some((index, vector)) = vectors.iter().enumerate().find(|(_, vector)| vector.len() != dimensions) {
+            return Err(OrcaError::Embedding {
+                provider: provider.to_string(),
+                message: format!(
+                    "Embedding at index {} has {} dimensions, but the first embedding has {}",
+                    index,
+                    vector.len(),
+                    dimensions
+                ),
+            }
+            .into());
+        }
+        Ok(Self {
+            vectors: Vectors::F32(vectors),
+            dimensions,
+            provider: provider.to_string(),
+            model: None,
+        })
+    }
+
+    /// Create embeddings by averaging the token hidden states of a transformer, a tensor of shape
+    /// `(inputs, tokens, hidden_size)`.
+    pub fn from_hidden_states(provider: &str, hidden_states: &Tensor) -> Result<Self> {
+        let (_n, n_tokens, _hidden_size) = hidden_states.dims3()?;
+        let pooled = (hidden_states.sum(1)? / (n_tokens as f64))?;
+        Self::new(provider, pooled.to_vec2()?)
+    }
+
+    /// Set the model that generated the embeddings.
+    pub fn with_model(mut self, model: &str) -> Self {
+        self.model = Some(model.to_string());
+        self
+    }
+
+    /// Convert the vectors to the given precision, e.g. to reduce the memory of a large local index.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::llm::embeddings::{Embeddings, Precision, Vectors};
+    ///
+    /// let embeddings = Embeddings::new("custom", vec![vec![0.2, -1.0]]).unwrap().with_precision(Precision::Int8);
+    /// assert_eq!(embeddings.vectors(), &Vectors::Int8 { values: vec![vec![25, -127]], scales: vec![1.0 / 127.0] });
+    /// ```
+    pub fn with_precision(mut self, precision: Precision) -> Self {
+        if self.precision() != precision {
+            self.vectors = Vectors::from_f32(self.vectors.to_f32(), precision);
+        }
+        self
+    }
+
+    /// Scale each vector to unit length, so that dot product and cosine similarity are equivalent.
+    pub fn normalize(mut self) -> Self {
+        let mut vectors = self.vectors.to_f32();
+        vectors.iter_mut().for_each(|vector| math::normalize(vector));
+        self.vectors = Vectors::from_f32(vectors, self.precision());
+        self
+    }
+
+    /// Backend that generated the embeddings.
+    pub fn provider(&self) -> &str {
+        &self.provider
+    }
+
+    /// Model that generated the embeddings, if known.
+    pub fn model(&self) -> Option<&str> {
+        self.model.as_deref()
+    }
+
+    /// Size of each vector.
+    pub fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    /// Precision in which the vectors are kept.
+    pub fn precision(&self) -> Precision {
+        self.vectors.precision()
+    }
+
+    /// Scale of each int8 vector, if the vectors are kept as int8.
+    pub fn scales(&self) -> Option<&[f32]> {
+        match &self.vectors {
+            Vectors::Int8 { scales, .. } => Some(scales),
+            _ => None,
+        }
+    }
+
+    /// Number of vectors.
+    pub fn len(&self) -> usize {
+        self.vectors.len()
+    }
+
+    /// Whether there are no vectors.
+    pub fn is_empty(&self) -> bool {
+        self.vectors.is_empty()
+    }
+
+    /// The vectors, in the order of the inputs and in the precision they are kept in.
+    pub fn vectors(&self) -> &Vectors {
+        &self.vectors
+    }
+
+    /// Take the vectors at full precision, in the order of the inputs.
+    pub fn into_vectors(self) -> Vec<Vec<f32>> {
+        match self.vectors {
+            Vectors::F32(vectors) => vectors,
+            vectors => vectors.to_f32(),
+        }
+    }
+
+    /// Get the single vector of embeddings generated from one input, at full precision.
+    pub fn to_vec(&self) -> Result<Vec<f32>> {
+        match self.len() {
+            1 => Ok(self.vectors.to_f32().remove(0)),
+            len => Err(OrcaError::Embedding {
+                provider: self.provider.clone(),
+                message: format!("expected 1 embedding, got {}", len),
+            }
+            .into()),
+        }
+    }
+
+    /// Get all the vectors at full precision, in the order of the inputs.
+    pub fn to_vec2(&self) -> Vec<Vec<f32>> {
+        self.vectors.to_f32()
+    }
+}
+
+impl IntoIterator for Embeddings {
+    type Item = Vec<f32>;
+    type IntoIter = std::vec::IntoIter<Vec<f32>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.into_vectors().into_iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use candle_core::Device;
+
+    #[test]
+    fn test_from_hidden_states() {
+        // Two inputs of two tokens each, with a hidden size of 3.
+        let hidden_states = Tensor::new(
+            &[[[1f32, 2., 3.], [3., 4., 5.]], [[0., 0., 4.], [0., 0., 2.]]],
+            &Device::Cpu,
+        )
+        .unwrap();
+        let embeddings = Embeddings::from_hidden_states("bert", &hidden_states).unwrap().with_model("test");
+        assert_eq!(embeddings.to_vec2(), vec![vec![2., 3., 4.], vec![0., 0., 3.]]);
+        assert_eq!(embeddings.dimensions(), 3);
+        assert_eq!(embeddings.model(), Some("test"));
+        assert!(embeddings.to_vec().is_err());
+
+        let normalized = embeddings.normalize();
+        assert_eq!(normalized.to_vec2()[1], vec![0., 0., 1.]);
+    }
+
+    #[test]
+    fn test_precision() {
+        let embeddings = Embeddings::new("bert", vec![vec![0.25, -0.5, 1.0], vec![0.0, 0.0, 0.0]]).unwrap();
+
+        let half = embeddings.clone().with_precision(Precision::F16);
+        assert_eq!(half.precision(), Precision::F16);
+        assert_eq!(half.to_vec2(), embeddings.to_vec2());
+        assert!(half.scales().is_none());
+
+        let int8 = embeddings.clone().with_precision(Precision::Int8);
+        assert_eq!(int8.scales(), Some(&[1.0 / 127.0, 1.0][..]));
+        assert_eq!(int8.dimensions(), 3);
+        for (quantized, original) in int8.to_vec2()[0].iter().zip(&embeddings.to_vec2()[0]) {
+            assert!((quantized - original).abs() <= 0.5 / 127.0);
+        }
+        assert_eq!(int8.to_vec2()[1], vec![0.0, 0.0, 0.0]);
+
+        let full = int8.with_precision(Precision::F32);
+        assert_eq!(full.precision(), Precision::F32);
+    }
+}
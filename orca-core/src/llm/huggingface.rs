@@ -0,0 +1,420 @@
+use std::fmt::Display;
+use std::sync::Arc;
+
+use crate::{
+    error::OrcaError,
+    llm::{quantized::Quantized, RequestMetadata, LLM},
+    prompt::Prompt,
+};
+use anyhow::Result;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+use tokio::sync::mpsc;
+
+use super::openai::EventParser;
+use super::secrets::{EnvSecrets, SecretsProvider, StaticSecrets};
+use super::{task_stream, LLMResponse, TokenStream};
+
+/// Name of the secret holding the access token.
+pub const HF_TOKEN: &str = "HF_TOKEN";
+
+static HUGGINGFACE_INFERENCE_URL: &str = "https://api-inference.huggingface.co/models";
+
+/// Where the requests are sent.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Endpoint {
+    /// The hosted Inference API, serving the model of the client.
+    InferenceApi,
+
+    /// A text-generation-inference (TGI) server at the given URL, e.g. `http://localhost:8080`, serving the
+    /// model it was started with.
+    Server(String),
+}
+
+#[derive(Serialize, Debug)]
+pub struct Payload {
+    inputs: String,
+    parameters: Parameters,
+    stream: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Parameters {
+    max_new_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    do_sample: bool,
+    return_full_text: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stop: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Response {
+    generated_text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    details: Option<Details>,
+}
+
+impl Response {
+    /// Number of tokens generated, if reported by the server.
+    pub fn generated_tokens(&self) -> Option<u32> {
+        self.details.as_ref().map(|details| details.generated_tokens)
+    }
+}
+
+impl Display for Response {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.generated_text)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Details {
+    finish_reason: String,
+    generated_tokens: u32,
+}
+
+/// Response of the Inference API, a list of generations, or of a TGI server, a single generation.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum HuggingFaceResponse {
+    Generations(Vec<Response>),
+    Generation(Response),
+    Error { error: String },
+}
+
+/// Event of a streamed response.
+#[derive(Deserialize, Debug)]
+struct StreamEvent {
+    #[serde(default)]
+    token: Option<StreamToken>,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct StreamToken {
+    text: String,
+    #[serde(default)]
+    special: bool,
+}
+
+#[derive(Clone)]
+pub struct HuggingFace {
+    /// Client member for the Hugging Face APIs.
+    client: Client,
+
+    /// Where the requests are sent, the Inference API by default.
+    endpoint: Endpoint,
+
+    /// Provider of the access token, read on every request.
+    /// By default, the token is read from the HF_TOKEN environment variable. It is optional for servers.
+    secrets: Arc<dyn SecretsProvider>,
+
+    /// ID of the model to use on the Inference API, e.g. "mistralai/Mistral-7B-Instruct-v0.2".
+    model: String,
+
+    /// The maximum number of tokens to generate.
+    max_new_tokens: u32,
+
+    /// Amount of randomness injected into the response. Sampling is greedy at 0.
+    temperature: f32,
+
+    /// Nucleus sampling probability mass, if set.
+    top_p: Option<f32>,
+
+    /// Sequences where the server stops generating.
+    stop: Vec<String>,
+}
+
+impl Default for HuggingFace {
+    fn default() -> Self {
+        Self {
+            client: Client::new(),
+            endpoint: Endpoint::InferenceApi,
+            secrets: Arc::new(EnvSecrets),
+            model: "mistralai/Mistral-7B-Instruct-v0.2".to_string(),
+            max_new_tokens: 1024,
+            temperature: 0.7,
+            top_p: None,
+            stop: Vec::new(),
+        }
+    }
+}
+
+impl HuggingFace {
+    /// Create a new client of the Inference API.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use orca_core::llm::huggingface::HuggingFace;
+    ///
+    /// let hosted = HuggingFace::new().with_model("HuggingFaceH4/zephyr-7b-beta");
+    /// let local = HuggingFace::new().with_server("http://localhost:8080");
+    /// ```
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Send the requests to a text-generation-inference server instead of the Inference API. The server
+    /// generates with the model it was started with, whatever the model of the client.
+    pub fn with_server(mut self, url: &str) -> Self {
+        self.endpoint = Endpoint::Server(url.trim_end_matches('/').to_string());
+        self
+    }
+
+    /// Set the access token, instead of reading it from the HF_TOKEN environment variable.
+    pub fn with_api_key(self, api_key: &str) -> Self {
+        self.with_secrets(StaticSecrets::new().with_secret(HF_TOKEN, api_key))
+    }
+
+    /// Set the provider the access token is read from on every request. The token is looked up as
+    /// `HF_TOKEN`.
+    pub fn with_secrets<S: SecretsProvider + 'static>(mut self, secrets: S) -> Self {
+        self.secrets = Arc::new(secrets);
+        self
+    }
+
+    /// Set model to use
+    pub fn with_model(mut self, model: &str) -> Self {
+        self.model = model.to_string();
+        self
+    }
+
+    /// Set the maximum number of tokens to generate
+    pub fn with_max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_new_tokens = max_tokens;
+        self
+    }
+
+    /// Set temperature
+    pub fn with_temperature(mut self, temperature: f32) -> Self {
+        self.temperature = temperature;
+        self
+    }
+
+    /// Set top_p
+    pub fn with_top_p(mut self, top_p: f32) -> Self {
+        self.top_p = Some(top_p);
+        self
+    }
+
+    /// Add sequences where the server stops generating, e.g. the start of the next turn of a chat.
+    pub fn with_stop(mut self, stop: &[&str]) -> Self {
+        self.stop.extend(stop.iter().map(|stop| stop.to_string()));
+        self
+    }
+
+    /// Generate a request for the Inference API or the server and set the parameters. Chat prompts are
+    /// formatted as instructions, `[INST] ... [/INST]`, as for local models.
+    pub fn generate_request(&self, prompt: &dyn Prompt, stream: bool) -> Result<reqwest::Request> {
+        self.generate_request_with_metadata(prompt, &RequestMetadata::default(), stream)
+    }
+
+    /// Generate a request, sending the trace id, idempotency key and custom headers as HTTP headers.
+    pub fn generate_request_with_metadata(
+        &self,
+        prompt: &dyn Prompt,
+        metadata: &RequestMetadata,
+        stream: bool,
+    ) -> Result<reqwest::Request> {
+        let inputs = match prompt.to_chat() {
+            Ok(chat) => Quantized::format_chat_prompt(chat),
+            Err(_) => prompt.to_string(),
+        };
+        let payload = Payload {
+            inputs,
+            parameters: Parameters {
+                max_new_tokens: self.max_new_tokens,
+                temperature: (self.temperature > 0.0).then_some(self.temperature),
+                top_p: self.top_p,
+                do_sample: self.temperature > 0.0,
+                return_full_text: false,
+                stop: self.stop.clone(),
+            },
+            stream,
+        };
+        let (url, token) = match &self.endpoint {
+            Endpoint::InferenceApi => (
+                format!("{}/{}", HUGGINGFACE_INFERENCE_URL, self.model),
+                Some(self.secrets.secret(HF_TOKEN)?),
+            ),
+            Endpoint::Server(url) => {
+                let route = if stream { "generate_stream" } else { "generate" };
+                (format!("{}/{}", url, route), self.secrets.secret(HF_TOKEN).ok())
+            }
+        };
+        let mut req = metadata.apply(self.client.post(url)).json(&payload);
+        if let Some(token) = token {
+            req = req.bearer_auth(token);
+        }
+        Ok(req.build()?)
+    }
+
+    /// Error of a failed request, with the message returned by the server if any.
+    async fn error(res: reqwest::Response) -> anyhow::Error {
+        let status = res.status();
+        let message = match res.json::<JsonValue>().await {
+            Ok(body) => body["error"].as_str().map(str::to_string).unwrap_or(body.to_string()),
+            Err(_) => status.canonical_reason().unwrap_or("Unknown error").to_string(),
+        };
+        OrcaError::LLM {
+            provider: "HuggingFace".to_string(),
+            status: Some(status.as_u16()),
+            message,
+        }
+        .into()
+    }
+}
+
+/// Text of an event of a streamed response, empty for special tokens.
+fn event_text(data: &str) -> Result<String> {
+    let event = serde_json::from_str::<StreamEvent>(data)?;
+    if let Some(error) = event.error {
+        return Err(OrcaError::LLM {
+            provider: "HuggingFace".to_string(),
+            status: None,
+            message: error,
+        }
+        .into());
+    }
+    Ok(event.token.filter(|token| !token.special).map(|token| token.text).unwrap_or_default())
+}
+
+/// Send the text of a streamed response token by token, until the response ends or the stream is dropped.
+async fn forward_events(response: &mut reqwest::Response, sender: &mpsc::Sender<Result<String>>) -> Result<()> {
+    let mut parser = EventParser::default();
+    while let Some(chunk) = response.chunk().await? {
+        for data in parser.push(&chunk) {
+            let text = event_text(&data)?;
+            if !text.is_empty() && sender.send(Ok(text)).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl LLM for HuggingFace {
+    async fn generate(&self, prompt: Box<dyn Prompt>) -> Result<LLMResponse> {
+        self.generate_with_metadata(prompt, &RequestMetadata::default()).await
+    }
+
+    async fn generate_with_metadata(&self, prompt: Box<dyn Prompt>, metadata: &RequestMetadata) -> Result<LLMResponse> {
+        let req = self.generate_request_with_metadata(prompt.as_ref(), metadata, false)?;
+        let res = self.client.execute(req).await?;
+        if !res.status().is_success() {
+            return Err(Self::error(res).await);
+        }
+        match res.json::<HuggingFaceResponse>().await? {
+            HuggingFaceResponse::Generations(mut generations) if !generations.is_empty() => {
+                Ok(generations.swap_remove(0).into())
+            }
+            HuggingFaceResponse::Generation(response) => Ok(response.into()),
+            HuggingFaceResponse::Generations(_) => Ok(LLMResponse::Empty),
+            HuggingFaceResponse::Error { error } => Err(OrcaError::LLM {
+                provider: "HuggingFace".to_string(),
+                status: None,
+                message: error,
+            }
+            .into()),
+        }
+    }
+
+    async fn generate_stream(&self, prompt: Box<dyn Prompt>) -> Result<TokenStream> {
+        let req = self.generate_request(prompt.as_ref(), true)?;
+        let mut res = self.client.execute(req).await?;
+        if !res.status().is_success() {
+            return Err(Self::error(res).await);
+        }
+        Ok(task_stream(move |sender| async move {
+            if let Err(e) = forward_events(&mut res, &sender).await {
+                let _ = sender.send(Err(e)).await;
+            }
+        }))
+    }
+
+    fn deterministic(&self) -> Option<Self> {
+        Some(self.clone().with_temperature(0.0))
+    }
+
+    fn config(&self) -> JsonValue {
+        let mut config = json!({
+            "type": "HuggingFace",
+            "model": self.model,
+            "temperature": self.temperature,
+            "max_tokens": self.max_new_tokens,
+        });
+        if let Endpoint::Server(url) = &self.endpoint {
+            config["endpoint"] = json!(url);
+        }
+        config
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prompt::chat::{ChatPrompt, Message, Role};
+
+    fn body(request: &reqwest::Request) -> JsonValue {
+        serde_json::from_slice(request.body().unwrap().as_bytes().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_request() {
+        let client = HuggingFace::new().with_api_key("test").with_temperature(0.0).with_stop(&["</s>"]);
+        let request = client.generate_request(&"Where do orcas live?".to_string(), false).unwrap();
+        assert_eq!(
+            request.url().as_str(),
+            "https://api-inference.huggingface.co/models/mistralai/Mistral-7B-Instruct-v0.2"
+        );
+        assert_eq!(request.headers()["Authorization"], "Bearer test");
+        assert_eq!(
+            body(&request),
+            json!({
+                "inputs": "Where do orcas live?",
+                "parameters": {"max_new_tokens": 1024, "do_sample": false, "return_full_text": false, "stop": ["</s>"]},
+                "stream": false
+            })
+        );
+
+        // Servers stream from their own route, with an optional token.
+        let client = HuggingFace::new().with_server("http://localhost:8080/").with_secrets(StaticSecrets::new());
+        let chat = ChatPrompt(vec![Message::new(Role::User, "Where do orcas live?")]);
+        let request = client.generate_request(&chat, true).unwrap();
+        assert_eq!(request.url().as_str(), "http://localhost:8080/generate_stream");
+        assert!(!request.headers().contains_key("Authorization"));
+        assert_eq!(body(&request)["inputs"], "[INST] Where do orcas live? [/INST]");
+    }
+
+    #[test]
+    fn test_response() {
+        let parse = |value: JsonValue| serde_json::from_value::<HuggingFaceResponse>(value).unwrap();
+        let generations = parse(json!([{"generated_text": "In every ocean."}]));
+        assert!(matches!(generations, HuggingFaceResponse::Generations(g) if g[0].to_string() == "In every ocean."));
+        let generation = parse(json!({
+            "generated_text": "In every ocean.",
+            "details": {"finish_reason": "eos_token", "generated_tokens": 5, "seed": null}
+        }));
+        assert!(matches!(generation, HuggingFaceResponse::Generation(g) if g.generated_tokens() == Some(5)));
+        let error = parse(json!({"error": "Model is currently loading", "estimated_time": 20.0}));
+        assert!(matches!(error, HuggingFaceResponse::Error { error } if error == "Model is currently loading"));
+
+        let body = concat!(
+            "data:{\"token\":{\"id\":1,\"text\":\"In\",\"logprob\":-0.1,\"special\":false},\"generated_text\":null}\n\n",
+            "data:{\"token\":{\"id\":2,\"text\":\" every ocean.\",\"logprob\":-0.2,\"special\":false}}\n\n",
+            "data:{\"token\":{\"id\":3,\"text\":\"</s>\",\"logprob\":0.0,\"special\":true}}\n\n",
+        );
+        let mut parser = EventParser::default();
+        let texts = parser.push(body.as_bytes()).iter().map(|data| event_text(data).unwrap()).collect::<Vec<_>>();
+        assert_eq!(texts, vec!["In", " every ocean.", ""]);
+        let error = event_text("{\"error\":\"Input validation error\",\"error_type\":\"validation\"}").unwrap_err();
+        assert_eq!(error.to_string(), "HuggingFace request failed: Input validation error");
+    }
+}
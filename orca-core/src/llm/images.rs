@@ -0,0 +1,82 @@
+//! Images generated from a prompt by an [`ImageGenerator`](super::ImageGenerator).
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::prompt::chat::Image;
+
+/// An image generated from a prompt.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratedImage {
+    /// Encoded image, e.g. PNG data.
+    pub bytes: Vec<u8>,
+
+    /// MIME type of the encoded image, e.g. `image/png`.
+    pub mime_type: String,
+
+    /// Prompt the image was actually generated from, when the provider rewrites prompts.
+    pub revised_prompt: Option<String>,
+
+    /// Path of the file the image was saved to, if it was saved.
+    pub path: Option<PathBuf>,
+}
+
+impl GeneratedImage {
+    /// Create an image from PNG data.
+    pub fn png(bytes: Vec<u8>) -> Self {
+        GeneratedImage {
+            bytes,
+            mime_type: "image/png".to_string(),
+            revised_prompt: None,
+            path: None,
+        }
+    }
+
+    /// Set the prompt the image was actually generated from.
+    pub fn with_revised_prompt(mut self, revised_prompt: &str) -> Self {
+        self.revised_prompt = Some(revised_prompt.to_string());
+        self
+    }
+
+    /// File extension matching the MIME type of the image.
+    pub fn extension(&self) -> &str {
+        match self.mime_type.as_str() {
+            "image/jpeg" => "jpg",
+            "image/gif" => "gif",
+            "image/webp" => "webp",
+            _ => "png",
+        }
+    }
+
+    /// Write the image to a file and remember its path.
+    pub fn save<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        std::fs::write(path.as_ref(), &self.bytes)?;
+        self.path = Some(path.as_ref().to_path_buf());
+        Ok(())
+    }
+
+    /// Convert the image to a chat image, e.g. to have a vision model describe it.
+    pub fn to_image(&self) -> Image {
+        Image::from_bytes(&self.mime_type, &self.bytes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_save() {
+        let dir = std::env::temp_dir().join(format!("orca-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut image = GeneratedImage::png(vec![1, 2, 3]);
+        let path = dir.join(format!("image.{}", image.extension()));
+        image.save(&path).unwrap();
+
+        assert_eq!(image.path.as_deref(), Some(path.as_path()));
+        assert_eq!(std::fs::read(&path).unwrap(), vec![1, 2, 3]);
+        assert_eq!(image.to_image().url, "data:image/png;base64,AQID");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
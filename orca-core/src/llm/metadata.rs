@@ -0,0 +1,121 @@
+//! Per-request metadata attached to LLM calls.
+//!
+//! A `RequestMetadata` identifies the end user and the trace a call belongs to, and carries an
+//! idempotency key so that a retried call is not billed or executed twice. Providers send it along with
+//! their requests (e.g. the OpenAI `user` field and HTTP headers), and pipelines record it in their
+//! results and audit logs, which is needed to attribute abuse in multi-tenant services.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Header carrying the idempotency key.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// Header carrying the trace id.
+pub const TRACE_ID_HEADER: &str = "X-Trace-Id";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RequestMetadata {
+    /// Identifier of the end user on whose behalf the request is made.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+
+    /// Identifier of the trace the request belongs to, e.g. the id of the incoming HTTP request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<String>,
+
+    /// Key identifying the request, so that retries of the same request can be deduplicated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+
+    /// Additional HTTP headers sent with the request.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+}
+
+impl RequestMetadata {
+    /// Create empty request metadata.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the identifier of the end user.
+    pub fn with_user_id(mut self, user_id: &str) -> Self {
+        self.user_id = Some(user_id.to_string());
+        self
+    }
+
+    /// Set the identifier of the trace the request belongs to.
+    pub fn with_trace_id(mut self, trace_id: &str) -> Self {
+        self.trace_id = Some(trace_id.to_string());
+        self
+    }
+
+    /// Set the idempotency key of the request.
+    pub fn with_idempotency_key(mut self, idempotency_key: &str) -> Self {
+        self.idempotency_key = Some(idempotency_key.to_string());
+        self
+    }
+
+    /// Add an HTTP header sent with the request.
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// Metadata for a follow-up request made while handling this one, e.g. a correction. The idempotency
+    /// key is suffixed with the attempt number so that the follow-up is not deduplicated with the
+    /// original request.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::llm::RequestMetadata;
+    ///
+    /// let metadata = RequestMetadata::new().with_idempotency_key("order-42");
+    /// assert_eq!(metadata.for_attempt(1).idempotency_key.as_deref(), Some("order-42-1"));
+    /// ```
+    pub fn for_attempt(&self, attempt: usize) -> Self {
+        let mut metadata = self.clone();
+        metadata.idempotency_key = self.idempotency_key.as_ref().map(|key| format!("{}-{}", key, attempt));
+        metadata
+    }
+
+    /// Add the metadata headers to a request.
+    pub fn apply(&self, mut request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        if let Some(idempotency_key) = &self.idempotency_key {
+            request = request.header(IDEMPOTENCY_KEY_HEADER, idempotency_key);
+        }
+        if let Some(trace_id) = &self.trace_id {
+            request = request.header(TRACE_ID_HEADER, trace_id);
+        }
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        request
+    }
+
+    /// Whether no metadata is set.
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        let metadata = RequestMetadata::new()
+            .with_trace_id("trace-1")
+            .with_idempotency_key("key-1")
+            .with_header("X-Tenant", "acme");
+        let request = metadata.apply(reqwest::Client::new().post("https://example.com")).build().unwrap();
+        assert_eq!(request.headers()[IDEMPOTENCY_KEY_HEADER], "key-1");
+        assert_eq!(request.headers()[TRACE_ID_HEADER], "trace-1");
+        assert_eq!(request.headers()["X-Tenant"], "acme");
+        assert!(!metadata.is_empty());
+        assert!(RequestMetadata::new().is_empty());
+    }
+}
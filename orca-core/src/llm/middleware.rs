@@ -0,0 +1,418 @@
+//! Middleware around the requests of LLM clients.
+//!
+//! An `LLMStack` wraps an LLM in layers, each implementing one concern, such as logging, caching, rate
+//! limiting, retries or guardrails, once for every backend:
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use orca_core::llm::middleware::{Cache, LLMStack, Logging, RateLimit, Retry};
+//! use orca_core::llm::openai::OpenAI;
+//!
+//! let llm = LLMStack::new(OpenAI::new())
+//!     .layer(Retry::new(3))
+//!     .layer(RateLimit::new(60, Duration::from_secs(60)))
+//!     .layer(Cache::new(1000))
+//!     .layer(Logging);
+//! ```
+//!
+//! Each layer wraps the stack built so far, so the last layer added sees the requests first: above, a
+//! request is logged, then answered from the cache if possible, and only then waits for the rate limit
+//! and is retried on failure. The stack is itself an `LLM`, usable by any pipeline. Layers handle whole
+//! responses, so streamed responses go through the layers and are sent as a single piece.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use serde_json::{json, Value as JsonValue};
+
+use super::{LLMResponse, RequestMetadata, LLM};
+use crate::pipeline::describe::type_name;
+use crate::pipeline::validated::Validator;
+use crate::prompt::Prompt;
+
+/// Middleware handling a request to an LLM, usually by passing it on to the rest of the stack.
+#[async_trait::async_trait]
+pub trait Layer: Send + Sync {
+    /// Handle a request, calling `next` to pass it on to the rest of the stack, as many times as needed.
+    async fn call(&self, prompt: Box<dyn Prompt>, metadata: &RequestMetadata, next: Next<'_>) -> Result<LLMResponse>;
+
+    /// Name of the layer, to describe the stack.
+    fn name(&self) -> &'static str {
+        type_name::<Self>()
+    }
+}
+
+/// Rest of a stack below a layer.
+#[derive(Clone, Copy)]
+pub struct Next<'a> {
+    /// LLM at the bottom of the stack.
+    llm: &'a dyn LLM,
+
+    /// Layers below the current one, the innermost first.
+    layers: &'a [Arc<dyn Layer>],
+}
+
+impl<'a> Next<'a> {
+    /// Pass a request on to the rest of the stack.
+    pub async fn run(self, prompt: Box<dyn Prompt>, metadata: &RequestMetadata) -> Result<LLMResponse> {
+        match self.layers.split_last() {
+            Some((layer, layers)) => layer.call(prompt, metadata, Next { llm: self.llm, layers }).await,
+            None => self.llm.generate_with_metadata(prompt, metadata).await,
+        }
+    }
+}
+
+/// LLM wrapped in layers of middleware.
+pub struct LLMStack<M> {
+    /// Wrapped LLM.
+    llm: Arc<M>,
+
+    /// Layers, the innermost first.
+    layers: Vec<Arc<dyn Layer>>,
+}
+
+impl<M> Clone for LLMStack<M> {
+    fn clone(&self) -> Self {
+        LLMStack {
+            llm: self.llm.clone(),
+            layers: self.layers.clone(),
+        }
+    }
+}
+
+impl<M: LLM + 'static> LLMStack<M> {
+    /// Create a stack without layers around an LLM.
+    pub fn new(llm: M) -> Self {
+        LLMStack {
+            llm: Arc::new(llm),
+            layers: Vec::new(),
+        }
+    }
+
+    /// Wrap the stack in a layer, which sees the requests before the layers already added.
+    pub fn layer<L: Layer + 'static>(mut self, layer: L) -> Self {
+        self.layers.push(Arc::new(layer));
+        self
+    }
+
+    /// Wrap another LLM in the same layers.
+    fn rewrap<N>(&self, llm: N) -> LLMStack<N> {
+        LLMStack {
+            llm: Arc::new(llm),
+            layers: self.layers.clone(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<M: LLM + 'static> LLM for LLMStack<M> {
+    async fn generate(&self, prompt: Box<dyn Prompt>) -> Result<LLMResponse> {
+        self.generate_with_metadata(prompt, &RequestMetadata::default()).await
+    }
+
+    async fn generate_with_metadata(&self, prompt: Box<dyn Prompt>, metadata: &RequestMetadata) -> Result<LLMResponse> {
+        let next = Next {
+            llm: self.llm.as_ref(),
+            layers: &self.layers,
+        };
+        next.run(prompt, metadata).await
+    }
+
+    fn json_mode(&self) -> Option<Self> {
+        self.llm.json_mode().map(|llm| self.rewrap(llm))
+    }
+
+    fn deterministic(&self) -> Option<Self> {
+        self.llm.deterministic().map(|llm| self.rewrap(llm))
+    }
+
+    fn config(&self) -> JsonValue {
+        let layers = self.layers.iter().rev().map(|layer| layer.name()).collect::<Vec<_>>();
+        json!({ "type": "LLMStack", "layers": layers, "model": self.llm.config() })
+    }
+}
+
+/// Logs each request with its duration and, if reported, its number of tokens.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Logging;
+
+#[async_trait::async_trait]
+impl Layer for Logging {
+    async fn call(&self, prompt: Box<dyn Prompt>, metadata: &RequestMetadata, next: Next<'_>) -> Result<LLMResponse> {
+        let start = Instant::now();
+        let characters = prompt.to_string().len();
+        let result = next.run(prompt, metadata).await;
+        match &result {
+            Ok(response) => log::info!(
+                "LLM request of {} characters answered in {:?} ({} tokens)",
+                characters,
+                start.elapsed(),
+                response.total_tokens().map_or("unknown".to_string(), |tokens| tokens.to_string())
+            ),
+            Err(e) => log::warn!("LLM request failed after {:?}: {}", start.elapsed(), e),
+        }
+        result
+    }
+}
+
+/// Answers requests whose prompt was already answered from memory, keeping the responses to the given
+/// number of most recent prompts. Errors are not cached.
+#[derive(Debug, Default)]
+pub struct Cache {
+    /// Maximum number of cached responses.
+    capacity: usize,
+
+    /// Cached responses by prompt, and the prompts in order of insertion.
+    entries: Mutex<(HashMap<String, LLMResponse>, VecDeque<String>)>,
+}
+
+impl Cache {
+    /// Create a cache holding up to `capacity` responses.
+    pub fn new(capacity: usize) -> Self {
+        Cache {
+            capacity,
+            entries: Mutex::default(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Layer for Cache {
+    async fn call(&self, prompt: Box<dyn Prompt>, metadata: &RequestMetadata, next: Next<'_>) -> Result<LLMResponse> {
+        let key = prompt.to_string();
+        if let Some(response) = self.entries.lock().unwrap().0.get(&key) {
+            return Ok(response.clone());
+        }
+        let response = next.run(prompt, metadata).await?;
+        let (responses, order) = &mut *self.entries.lock().unwrap();
+        if self.capacity > 0 && responses.insert(key.clone(), response.clone()).is_none() {
+            order.push_back(key);
+            if order.len() > self.capacity {
+                order.pop_front().map(|oldest| responses.remove(&oldest));
+            }
+        }
+        Ok(response)
+    }
+}
+
+/// Spaces requests evenly to stay under a number of requests per period.
+#[derive(Debug)]
+pub struct RateLimit {
+    /// Minimum time between the starts of two requests.
+    interval: Duration,
+
+    /// Earliest start of the next request.
+    next: tokio::sync::Mutex<Instant>,
+}
+
+impl RateLimit {
+    /// Allow `requests` requests per `period`.
+    pub fn new(requests: u32, period: Duration) -> Self {
+        RateLimit {
+            interval: period / requests.max(1),
+            next: tokio::sync::Mutex::new(Instant::now()),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Layer for RateLimit {
+    async fn call(&self, prompt: Box<dyn Prompt>, metadata: &RequestMetadata, next: Next<'_>) -> Result<LLMResponse> {
+        let start = {
+            let mut slot = self.next.lock().await;
+            let start = (*slot).max(Instant::now());
+            *slot = start + self.interval;
+            start
+        };
+        tokio::time::sleep_until(start.into()).await;
+        next.run(prompt, metadata).await
+    }
+}
+
+/// Retries failed requests with exponential backoff.
+#[derive(Debug, Clone, Copy)]
+pub struct Retry {
+    /// Number of retries after the first attempt.
+    max_retries: usize,
+
+    /// Wait before the first retry, doubled on each retry.
+    initial_backoff: Duration,
+
+    /// Maximum wait between retries.
+    max_backoff: Duration,
+}
+
+impl Retry {
+    /// Retry failed requests up to `max_retries` times, waiting 500ms, then twice as long before each
+    /// retry, up to 30 seconds.
+    pub fn new(max_retries: usize) -> Self {
+        Retry {
+            max_retries,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+
+    /// Set the wait before the first retry and the maximum wait between retries.
+    pub fn with_backoff(mut self, initial_backoff: Duration, max_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self.max_backoff = max_backoff;
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl Layer for Retry {
+    async fn call(&self, prompt: Box<dyn Prompt>, metadata: &RequestMetadata, next: Next<'_>) -> Result<LLMResponse> {
+        let mut backoff = self.initial_backoff;
+        for retry in 0..self.max_retries {
+            match next.run(prompt.clone_prompt(), metadata).await {
+                Ok(response) => return Ok(response),
+                Err(e) => log::warn!("LLM request failed, retry {} in {:?}: {}", retry + 1, backoff, e),
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(self.max_backoff);
+        }
+        next.run(prompt, metadata).await
+    }
+}
+
+/// Request or response rejected by a guardrail.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GuardrailViolation {
+    /// Whether the prompt, rather than the response, was rejected.
+    pub input: bool,
+
+    /// Why the text was rejected.
+    pub reason: String,
+}
+
+impl Display for GuardrailViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = if self.input { "Prompt" } else { "Response" };
+        write!(f, "{} rejected by guardrail: {}", text, self.reason)
+    }
+}
+
+impl std::error::Error for GuardrailViolation {}
+
+/// Rejects prompts or responses failing a validator with a [`GuardrailViolation`], e.g. prompts leaking
+/// secrets before they are sent, or responses that do not cite a source.
+pub struct Guardrail {
+    /// Checks the text.
+    validator: Box<dyn Validator>,
+
+    /// Whether the prompt, rather than the response, is checked.
+    input: bool,
+}
+
+impl Guardrail {
+    /// Check the prompts before they are sent.
+    pub fn input<V: Validator + 'static>(validator: V) -> Self {
+        Guardrail {
+            validator: Box::new(validator),
+            input: true,
+        }
+    }
+
+    /// Check the responses before they are returned.
+    pub fn output<V: Validator + 'static>(validator: V) -> Self {
+        Guardrail {
+            validator: Box::new(validator),
+            input: false,
+        }
+    }
+
+    /// Check a text, failing with a violation.
+    fn check(&self, text: &str) -> Result<()> {
+        self.validator.validate(text).map_err(|e| {
+            GuardrailViolation {
+                input: self.input,
+                reason: e.to_string(),
+            }
+            .into()
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Layer for Guardrail {
+    async fn call(&self, prompt: Box<dyn Prompt>, metadata: &RequestMetadata, next: Next<'_>) -> Result<LLMResponse> {
+        if self.input {
+            self.check(&prompt.to_string())?;
+            return next.run(prompt, metadata).await;
+        }
+        let response = next.run(prompt, metadata).await?;
+        self.check(&response.to_string())?;
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// LLM echoing the prompt, failing every other call.
+    #[derive(Clone, Default)]
+    struct Flaky {
+        calls: Arc<AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl LLM for Flaky {
+        async fn generate(&self, prompt: Box<dyn Prompt>) -> Result<LLMResponse> {
+            if self.calls.fetch_add(1, Ordering::SeqCst).is_multiple_of(2) {
+                return Err(anyhow::anyhow!("Service unavailable"));
+            }
+            Ok(LLMResponse::Quantized(prompt.to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stack() {
+        let llm = Flaky::default();
+        let no_password = |text: &str| match text.contains("password") {
+            true => Err(anyhow::anyhow!("The text contains a password")),
+            false => Ok(()),
+        };
+        let stack = LLMStack::new(llm.clone())
+            .layer(Retry::new(1).with_backoff(Duration::ZERO, Duration::ZERO))
+            .layer(Cache::new(1))
+            .layer(Guardrail::input(no_password));
+        assert_eq!(stack.config()["layers"], json!(["Guardrail", "Cache", "Retry"]));
+
+        let orcas = || Box::new("Orcas".to_string());
+        assert_eq!(stack.generate(orcas()).await.unwrap().to_string(), "Orcas");
+        assert_eq!(stack.generate(orcas()).await.unwrap().to_string(), "Orcas");
+        assert_eq!(llm.calls.load(Ordering::SeqCst), 2);
+
+        // A new prompt evicts the cached one.
+        stack.generate(Box::new("Dolphins".to_string())).await.unwrap();
+        stack.generate(orcas()).await.unwrap();
+        assert_eq!(llm.calls.load(Ordering::SeqCst), 6);
+
+        let error = stack.generate(Box::new("My password is hunter2".to_string())).await.unwrap_err();
+        let violation = error.downcast_ref::<GuardrailViolation>().unwrap();
+        assert!(violation.input);
+        assert_eq!(llm.calls.load(Ordering::SeqCst), 6);
+
+        assert!(LLMStack::new(Flaky::default()).generate(orcas()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() {
+        let stack = LLMStack::new(Flaky::default())
+            .layer(Retry::new(1).with_backoff(Duration::ZERO, Duration::ZERO))
+            .layer(RateLimit::new(10, Duration::from_millis(500)));
+        let start = Instant::now();
+        for _ in 0..3 {
+            stack.generate(Box::new("Orcas".to_string())).await.unwrap();
+        }
+        // The first request starts immediately, the next two 50ms apart.
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
@@ -1,13 +1,46 @@
+pub mod anthropic;
 pub mod bert;
+pub mod cohere;
+pub mod embeddings;
+pub mod huggingface;
+pub mod images;
+pub mod metadata;
+pub mod middleware;
+pub mod ner;
+#[cfg(feature = "ort")]
+pub mod onnx;
 pub mod openai;
+pub mod pii;
 pub mod quantized;
-
-use openai::{OpenAIEmbeddingResponse, Response};
+pub mod query_cache;
+pub mod router;
+pub mod safety;
+pub mod secrets;
+pub mod sharded;
+pub mod snapshot;
+pub mod source;
+#[cfg(feature = "stable-diffusion")]
+pub mod stable_diffusion;
+pub mod stop;
+pub mod tools;
+
+pub use embeddings::{EmbeddingPreset, Embeddings, Precision};
+pub use images::GeneratedImage;
+pub use metadata::RequestMetadata;
+use openai::Response;
 use std::fmt::Display;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
 
 use anyhow::Result;
-use candle_core::{Device, Result as CandleResult, Tensor};
+use candle_core::{Device, Result as CandleResult};
+use futures::Stream;
+use serde_json::{json, Value as JsonValue};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
 
+use crate::pipeline::describe::type_name;
 use crate::prompt::Prompt;
 
 /// Generate with context trait is used to execute an LLM using a context and a prompt template.
@@ -47,6 +80,64 @@ pub trait LLM: Sync + Send {
     /// }
     /// ```
     async fn generate(&self, prompt: Box<dyn Prompt>) -> Result<LLMResponse>;
+
+    /// Generate a response, sending the request metadata (user id, trace id, idempotency key) to the
+    /// provider if it supports it. By default, the metadata is ignored.
+    async fn generate_with_metadata(&self, prompt: Box<dyn Prompt>, metadata: &RequestMetadata) -> Result<LLMResponse> {
+        let _ = metadata;
+        self.generate(prompt).await
+    }
+
+    /// Generate a response as a stream of pieces of text, sent as soon as the model generates them. By
+    /// default, the whole response is generated and sent as a single piece.
+    ///
+    /// Stop criteria end the stream when they are met, but text already sent is not taken back, e.g. the
+    /// start of a stop string spanning several pieces. Dropping the stream cancels the generation.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use futures::StreamExt;
+    /// use orca_core::llm::openai::OpenAI;
+    /// use orca_core::llm::LLM;
+    /// use orca_core::prompt;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let mut stream = OpenAI::new().generate_stream(prompt!("Tell me about orcas.")).await?;
+    /// while let Some(text) = stream.next().await {
+    ///     print!("{}", text?);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    async fn generate_stream(&self, prompt: Box<dyn Prompt>) -> Result<TokenStream> {
+        let response = self.generate(prompt).await?;
+        Ok(Box::pin(futures::stream::once(async move { Ok(response.to_string()) })))
+    }
+
+    /// Returns a copy of the LLM that is constrained to respond with a JSON object, if the provider
+    /// supports a JSON mode.
+    fn json_mode(&self) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    /// Returns a copy of the LLM that answers the same prompt the same way as far as the provider
+    /// allows, e.g. sampling greedily at temperature 0, for outputs such as titles and labels.
+    fn deterministic(&self) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    /// Returns the configuration of the LLM, e.g. its model and sampling parameters, to describe the
+    /// pipelines using it. By default, only the type of the LLM.
+    fn config(&self) -> JsonValue {
+        json!({ "type": type_name::<Self>() })
+    }
 }
 
 /// Embedding trait is used to generate an embedding from an Online Service.
@@ -57,7 +148,7 @@ pub trait Embedding {
     /// * `input` - Boxed prompt trait object.
     ///
     /// # Returns
-    /// * `EmbeddingResponse` - An embedding response.
+    /// * `Embeddings` - The embeddings, one vector per prompt.
     ///
     /// # Examples
     /// This example uses the OpenAI chat models.
@@ -69,17 +160,18 @@ pub trait Embedding {
     /// # async fn main() {
     /// let client = OpenAI::new();
     /// let input = prompt!("Hello, world");
-    /// let response = client.generate_embedding(input).await.unwrap();
+    /// let embeddings = client.generate_embedding(input).await.unwrap();
+    /// let vector = embeddings.to_vec().unwrap();
     /// # }
     /// ```
-    async fn generate_embedding(&self, prompt: Box<dyn Prompt>) -> Result<EmbeddingResponse>;
+    async fn generate_embedding(&self, prompt: Box<dyn Prompt>) -> Result<Embeddings>;
 
     /// Generate an embedding by batch
     /// # Arguments
     /// * `prompts` - A vector of boxed prompt trait objects.
     ///
     /// # Returns
-    /// * `EmbeddingResponse` - An embedding response.
+    /// * `Embeddings` - The embeddings, one vector per prompt.
     ///
     /// # Example
     /// This example uses the Bert model.
@@ -93,46 +185,148 @@ pub trait Embedding {
     /// let bert = Bert::new().build_model_and_tokenizer().await.unwrap();
     /// let response = bert.generate_embeddings(prompts!("Hello World", "Goodbye World")).await;
     /// let response = response.unwrap();
-    /// let vec = response.to_vec2().unwrap();
+    /// let vec = response.to_vec2();
     /// assert_eq!(vec.len(), 2);
     /// # }
     /// ````
-    async fn generate_embeddings(&self, prompts: Vec<Box<dyn Prompt>>) -> Result<EmbeddingResponse>;
+    async fn generate_embeddings(&self, prompts: Vec<Box<dyn Prompt>>) -> Result<Embeddings>;
+
+    /// Number of dimensions of the generated embeddings, used to size vector store collections.
+    fn dimensions(&self) -> usize;
+
+    /// Precision of the generated embeddings, used to configure quantized vector store collections.
+    fn precision(&self) -> Precision {
+        Precision::F32
+    }
 }
 
-#[derive(Debug)]
-pub enum EmbeddingResponse {
-    /// OpenAI embedding response
-    OpenAI(Vec<OpenAIEmbeddingResponse>),
+/// Image generator trait is used to generate images from a text prompt, e.g. for content generation
+/// workflows that produce images alongside text.
+#[async_trait::async_trait]
+pub trait ImageGenerator: Sync + Send {
+    /// Generate images from a prompt.
+    /// # Arguments
+    /// * `prompt` - A prompt trait object describing the image.
+    ///
+    /// # Example
+    /// This example uses the OpenAI Images API.
+    /// ```no_run
+    /// # use orca_core::prompt;
+    /// # use orca_core::llm::ImageGenerator;
+    /// # use orca_core::llm::openai::OpenAI;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let client = OpenAI::new().with_image_size("1024x1024");
+    /// let mut images = client.generate_image(prompt!("A lighthouse at dawn, watercolor")).await.unwrap();
+    /// images[0].save("lighthouse.png").unwrap();
+    /// # }
+    /// ```
+    async fn generate_image(&self, prompt: Box<dyn Prompt>) -> Result<Vec<GeneratedImage>>;
+}
 
-    /// Bert embedding response
-    Bert(Tensor),
+/// Stream of the pieces of text of a response, in order of generation.
+pub type TokenStream = Pin<Box<dyn Stream<Item = Result<String>> + Send>>;
 
-    /// Empty response; usually used to initialize a pipeline result when
-    /// no response is available.
-    Empty,
+/// Stream of the pieces of text sent on a channel, ending when every sender is dropped. Generating tasks
+/// stop when sending fails, i.e. when the stream is dropped.
+pub(crate) fn channel_stream(receiver: mpsc::Receiver<Result<String>>) -> TokenStream {
+    Box::pin(futures::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|text| (text, receiver))
+    }))
+}
+
+/// Stream of the pieces of text sent by a task on a channel, ending when the task is done. The stream owns
+/// the task, which is aborted as soon as the stream is dropped, e.g. closing the connection of a streamed
+/// response instead of reading it to the end.
+pub(crate) fn task_stream<F, T>(generate: F) -> TokenStream
+where
+    F: FnOnce(mpsc::Sender<Result<String>>) -> T,
+    T: Future<Output = ()> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel(64);
+    let mut task = JoinSet::new();
+    task.spawn(generate(sender));
+    Box::pin(futures::stream::unfold(
+        (receiver, task),
+        |(mut receiver, task)| async move { receiver.recv().await.map(|text| (text, (receiver, task))) },
+    ))
 }
 
-impl From<OpenAIEmbeddingResponse> for EmbeddingResponse {
-    /// Convert an OpenAI embedding response to an EmbeddingResponse
-    fn from(response: openai::OpenAIEmbeddingResponse) -> Self {
-        EmbeddingResponse::OpenAI(vec![response])
+/// Tokens used by a request, as reported by the provider.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TokenUsage {
+    /// Tokens of the prompt.
+    pub prompt_tokens: u32,
+
+    /// Tokens of the response.
+    pub completion_tokens: u32,
+
+    /// Model that answered, if reported.
+    pub model: Option<String>,
+}
+
+impl TokenUsage {
+    /// Total number of prompt and completion tokens.
+    pub fn total_tokens(&self) -> u32 {
+        self.prompt_tokens + self.completion_tokens
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum LLMResponse {
     /// OpenAI response
     OpenAI(openai::Response),
 
+    /// Anthropic response
+    Anthropic(anthropic::Response),
+
+    /// Cohere response
+    Cohere(cohere::Response),
+
+    /// Hugging Face Inference API or text-generation-inference response
+    HuggingFace(huggingface::Response),
+
     /// Quantized model response
     Quantized(String),
 
+    /// Output computed without a model, e.g. by the reduce function of a map-reduce pipeline.
+    Computed(String),
+
+    /// Response of a model router, with the name of the tier that answered.
+    Routed { tier: String, response: Box<LLMResponse> },
+
+    /// Response calling tools instead of, or in addition to, answering, with the parsed calls.
+    ToolCall {
+        calls: Vec<tools::ToolCall>,
+        response: Box<LLMResponse>,
+    },
+
     /// Empty response; usually used to initialize a pipeline result when
     /// no response is available.
     Empty,
 }
 
+impl From<anthropic::Response> for LLMResponse {
+    /// Convert an Anthropic response to an LLMResponse
+    fn from(response: anthropic::Response) -> Self {
+        LLMResponse::Anthropic(response)
+    }
+}
+
+impl From<cohere::Response> for LLMResponse {
+    /// Convert a Cohere response to an LLMResponse
+    fn from(response: cohere::Response) -> Self {
+        LLMResponse::Cohere(response)
+    }
+}
+
+impl From<huggingface::Response> for LLMResponse {
+    /// Convert a Hugging Face response to an LLMResponse
+    fn from(response: huggingface::Response) -> Self {
+        LLMResponse::HuggingFace(response)
+    }
+}
+
 impl From<Response> for LLMResponse {
     /// Convert an OpenAI response to an LLMResponse
     fn from(response: openai::Response) -> Self {
@@ -140,66 +334,63 @@ impl From<Response> for LLMResponse {
     }
 }
 
-impl EmbeddingResponse {
-    pub fn to_vec(&self) -> Result<Vec<f32>> {
+impl LLMResponse {
+    /// Get the role of the response from an LLMResponse, if supported by the LLM.
+    pub fn to_role(&self) -> String {
         match self {
-            EmbeddingResponse::OpenAI(response) => Ok(response[0].to_vec()),
-            EmbeddingResponse::Bert(embedding) => {
-                // perform avg-pooling to get the embedding
-                let (_n, n_tokens, _hidden_size) = embedding.dims3()?;
-                let embedding = (embedding.sum(1)? / (n_tokens as f64))?;
-                let embedding = embedding.to_vec2()?;
-
-                match embedding.len() {
-                    1 => Ok(embedding[0].clone()),
-                    _ => Err(anyhow::anyhow!(format!(
-                        "expected 1 embedding, got {}",
-                        embedding.len()
-                    ))),
-                }
-            }
-            EmbeddingResponse::Empty => Err(anyhow::anyhow!("empty response does not have an embedding")),
+            LLMResponse::OpenAI(response) => response.to_string(),
+            LLMResponse::Anthropic(_)
+            | LLMResponse::Cohere(_)
+            | LLMResponse::HuggingFace(_)
+            | LLMResponse::Computed(_) => "assistant".to_string(),
+            LLMResponse::Quantized(_) => "ai".to_string(),
+            LLMResponse::Routed { response, .. } | LLMResponse::ToolCall { response, .. } => response.to_role(),
+            LLMResponse::Empty => panic!("empty response does not have a role"),
         }
     }
 
-    /// Get the embedding from an OpenAIEmbeddingResponse
-    pub fn to_vec2(&self) -> Result<Vec<Vec<f32>>> {
+    /// Get the number of tokens used to generate the response, if reported by the LLM.
+    pub fn total_tokens(&self) -> Option<u32> {
         match self {
-            EmbeddingResponse::OpenAI(response) => {
-                let mut embeddings = Vec::new();
-                for embedding in response {
-                    embeddings.push(embedding.to_vec());
-                }
-                Ok(embeddings)
+            LLMResponse::OpenAI(response) => Some(response.total_tokens() as u32),
+            LLMResponse::Anthropic(response) => Some(response.total_tokens()),
+            LLMResponse::Cohere(response) => Some(response.total_tokens()),
+            LLMResponse::Routed { response, .. } | LLMResponse::ToolCall { response, .. } => response.total_tokens(),
+            LLMResponse::HuggingFace(_) | LLMResponse::Quantized(_) | LLMResponse::Computed(_) | LLMResponse::Empty => {
+                None
             }
-            EmbeddingResponse::Bert(embedding) => {
-                // perform avg-pooling to get the embedding
-                let (_n, n_tokens, _hidden_size) = embedding.dims3()?;
-                let embedding = (embedding.sum(1)? / (n_tokens as f64))?;
-                let embedding = embedding.to_vec2()?;
+        }
+    }
 
-                Ok(embedding.clone())
+    /// Get the prompt and completion tokens used to generate the response, if reported by the LLM. Local
+    /// models and Hugging Face servers, which do not report the prompt tokens, return `None`.
+    pub fn usage(&self) -> Option<TokenUsage> {
+        match self {
+            LLMResponse::OpenAI(response) => Some(response.token_usage()),
+            LLMResponse::Anthropic(response) => Some(response.token_usage()),
+            LLMResponse::Cohere(response) => Some(response.token_usage()),
+            LLMResponse::Routed { response, .. } | LLMResponse::ToolCall { response, .. } => response.usage(),
+            LLMResponse::HuggingFace(_) | LLMResponse::Quantized(_) | LLMResponse::Computed(_) | LLMResponse::Empty => {
+                None
             }
-            EmbeddingResponse::Empty => Err(anyhow::anyhow!("empty response does not have an embedding")),
         }
     }
 
-    pub fn to_tensor(&self) -> Option<Tensor> {
+    /// Get the name of the model router tier that generated the response, if it was routed.
+    pub fn tier(&self) -> Option<&str> {
         match self {
-            EmbeddingResponse::OpenAI(_) => None,
-            EmbeddingResponse::Bert(tensor) => Some(tensor.clone()),
-            EmbeddingResponse::Empty => None,
+            LLMResponse::Routed { tier, .. } => Some(tier),
+            LLMResponse::ToolCall { response, .. } => response.tier(),
+            _ => None,
         }
     }
-}
 
-impl LLMResponse {
-    /// Get the role of the response from an LLMResponse, if supported by the LLM.
-    pub fn to_role(&self) -> String {
+    /// Get the calls of tools made by the model, empty if it only answered.
+    pub fn tool_calls(&self) -> &[tools::ToolCall] {
         match self {
-            LLMResponse::OpenAI(response) => response.to_string(),
-            LLMResponse::Quantized(_) => "ai".to_string(),
-            LLMResponse::Empty => panic!("empty response does not have a role"),
+            LLMResponse::ToolCall { calls, .. } => calls,
+            LLMResponse::Routed { response, .. } => response.tool_calls(),
+            _ => &[],
         }
     }
 }
@@ -211,25 +402,22 @@ impl Display for LLMResponse {
             LLMResponse::OpenAI(response) => {
                 write!(f, "{}", response)
             }
-            LLMResponse::Quantized(response) => {
+            LLMResponse::Anthropic(response) => {
                 write!(f, "{}", response)
             }
-            LLMResponse::Empty => write!(f, ""),
-        }
-    }
-}
-
-impl Display for EmbeddingResponse {
-    /// Display the response content from an EmbeddingResponse
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            EmbeddingResponse::OpenAI(response) => {
-                write!(f, "{:?}", response)
+            LLMResponse::Cohere(response) => {
+                write!(f, "{}", response)
             }
-            EmbeddingResponse::Bert(response) => {
-                write!(f, "{:?}", response)
+            LLMResponse::HuggingFace(response) => {
+                write!(f, "{}", response)
             }
-            EmbeddingResponse::Empty => write!(f, ""),
+            LLMResponse::Quantized(response) | LLMResponse::Computed(response) => {
+                write!(f, "{}", response)
+            }
+            LLMResponse::Routed { response, .. } | LLMResponse::ToolCall { response, .. } => {
+                write!(f, "{}", response)
+            }
+            LLMResponse::Empty => write!(f, ""),
         }
     }
 }
@@ -241,13 +429,6 @@ impl Default for LLMResponse {
     }
 }
 
-impl Default for EmbeddingResponse {
-    /// Default EmbeddingResponse is Empty
-    fn default() -> Self {
-        EmbeddingResponse::Empty
-    }
-}
-
 /// Returns a `Device` object representing either a CPU or a CUDA device.
 ///
 /// # Arguments
@@ -275,6 +456,57 @@ pub fn device(cpu: bool) -> CandleResult<Device> {
     }
 }
 
+/// How local models load their weights file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadMode {
+    /// Map the file into memory, sharing its pages with the page cache and other processes mapping it.
+    /// Loading is faster, but the file must not be modified while the model is loaded.
+    Mmap,
+
+    /// Read the whole file into memory.
+    Read,
+}
+
+/// Build a thread pool with the given number of threads for a local model to compute on.
+pub(crate) fn thread_pool(threads: usize) -> Result<Arc<rayon::ThreadPool>> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(|i| format!("orca-{}", i))
+        .build()?;
+    Ok(Arc::new(pool))
+}
+
+/// Run a computation of a local model in the span built by `span`, on the thread pool of the model if
+/// it has one. The span is a child of the current span, so that the computation shows in the trace of
+/// the execution. With `chrome`, the computation is instead traced to its own Chrome trace file
+/// (trace-timestamp.json) by a subscriber scoped to it, which leaves the subscriber of the application
+/// untouched; work the model spreads over other threads of the pool is left out of that file.
+pub(crate) fn run_traced<R: Send>(
+    pool: Option<&rayon::ThreadPool>,
+    chrome: bool,
+    span: impl FnOnce() -> tracing::Span + Send,
+    f: impl FnOnce() -> R + Send,
+) -> R {
+    use tracing_subscriber::prelude::*;
+
+    if chrome {
+        let compute = move || {
+            let (chrome_layer, _guard) = tracing_chrome::ChromeLayerBuilder::new().build();
+            tracing::subscriber::with_default(tracing_subscriber::registry().with(chrome_layer), || span().in_scope(f))
+        };
+        return match pool {
+            Some(pool) => pool.install(compute),
+            None => compute(),
+        };
+    }
+    // Created here rather than on a thread of the pool, where it would have no parent.
+    let span = span();
+    match pool {
+        Some(pool) => pool.install(move || span.in_scope(f)),
+        None => span.in_scope(f),
+    }
+}
+
 /// This is a wrapper around a tokenizer to ensure that tokens can be returned to the user in a
 /// streaming way rather than having to wait for the full decoding.
 pub struct TokenOutputStream {
@@ -0,0 +1,293 @@
+//! Named entity recognition with a Bert token classification model.
+//! It utilizes the [candle](https://github.com/huggingface/candle) ML framework.
+//!
+//! The model predicts an IOB tag (e.g. `B-PER`, `I-PER` or `O`) for each token, and the tags are
+//! grouped into entities spanning one or more words.
+
+use anyhow::{anyhow, Error as E, Result};
+use candle_core::{Tensor, D};
+use candle_nn::{Linear, Module, VarBuilder};
+use candle_transformers::models::bert::{BertModel, Config, DTYPE};
+use hf_hub::{Repo, RepoType};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokenizers::{Tokenizer, TruncationParams};
+
+use crate::record::enrich::{Entity, EntityExtractor};
+
+use super::source::ModelSource;
+
+/// Labels of a token classification model, read from its config.
+#[derive(Deserialize)]
+struct LabelConfig {
+    hidden_size: usize,
+    id2label: HashMap<String, String>,
+}
+
+pub struct Ner {
+    /// Run on CPU rather than on GPU.
+    cpu: bool,
+
+    /// Run offline (you must have the files already cached)
+    offline: bool,
+
+    /// Where the model files are fetched from, the global `ModelSource` by default.
+    source: Option<ModelSource>,
+
+    /// The model to use, check out available models: https://huggingface.co/models?pipeline_tag=token-classification
+    model_id: Option<String>,
+
+    revision: Option<String>,
+
+    /// Model weights.
+    model: Option<Arc<BertModel>>,
+
+    /// Token classification head.
+    classifier: Option<Linear>,
+
+    /// Label of each class predicted by the classifier.
+    labels: Vec<String>,
+
+    /// Tokenizer.
+    tokenizer: Option<Tokenizer>,
+}
+
+impl Default for Ner {
+    /// Provides default values for `Ner`.
+    fn default() -> Self {
+        Self {
+            cpu: true,
+            offline: false,
+            source: None,
+            model_id: None,
+            revision: None,
+            model: None,
+            classifier: None,
+            labels: Vec::new(),
+            tokenizer: None,
+        }
+    }
+}
+
+impl Ner {
+    /// Creates a new `Ner` instance.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Configures the model to run on GPU.
+    pub fn with_gpu(mut self) -> Self {
+        self.cpu = false;
+        self
+    }
+
+    /// Configures the model to run offline.
+    pub fn offline(mut self) -> Self {
+        self.offline = true;
+        self
+    }
+
+    /// Sets where the model files are fetched from, instead of the global `ModelSource`.
+    pub fn with_source(mut self, source: ModelSource) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// Sets the model ID.
+    pub fn with_model_id(mut self, model_id: &str) -> Self {
+        self.model_id = Some(model_id.to_string());
+        self
+    }
+
+    /// Sets the revision for the model.
+    pub fn with_revision(mut self, revision: &str) -> Self {
+        self.revision = Some(revision.to_string());
+        self
+    }
+
+    /// Builds the model and tokenizer. Defaults to `dslim/bert-base-NER`, which tags persons,
+    /// organizations, locations and miscellaneous entities.
+    pub async fn build_model_and_tokenizer(mut self) -> Result<Self> {
+        let device = super::device(self.cpu)?;
+        let model_id = self.model_id.clone().unwrap_or_else(|| "dslim/bert-base-NER".to_string());
+        let revision = self.revision.clone().unwrap_or_else(|| "main".to_string());
+
+        let repo = Repo::with_revision(model_id, RepoType::Model, revision);
+        let source = ModelSource::resolve(self.source.as_ref(), self.offline);
+        let (config_filename, tokenizer_filename, weights_filename) = (
+            source.get(&repo, "config.json").await?,
+            source.get(&repo, "tokenizer.json").await?,
+            source.get(&repo, "model.safetensors").await?,
+        );
+        let config = std::fs::read_to_string(config_filename)?;
+        let label_config: LabelConfig = serde_json::from_str(&config)?;
+        let config: Config = serde_json::from_str(&config)?;
+
+        let mut labels = vec![String::new(); label_config.id2label.len()];
+        for (id, label) in label_config.id2label {
+            let id = id.parse::<usize>()?;
+            *labels.get_mut(id).ok_or(anyhow!("Invalid label id {} in config", id))? = label;
+        }
+
+        let mut tokenizer = Tokenizer::from_file(tokenizer_filename).map_err(E::msg)?;
+        tokenizer
+            .with_padding(None)
+            .with_truncation(Some(TruncationParams {
+                max_length: 512,
+                ..Default::default()
+            }))
+            .map_err(E::msg)?;
+
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[weights_filename], DTYPE, &device)? };
+        let model = BertModel::load(vb.clone(), &config)?;
+        let classifier = candle_nn::linear(label_config.hidden_size, labels.len(), vb.pp("classifier"))?;
+        self.model = Some(Arc::new(model));
+        self.classifier = Some(classifier);
+        self.labels = labels;
+        self.tokenizer = Some(tokenizer);
+        Ok(self)
+    }
+
+    /// Tag each token of the text and group the tags into entities.
+    pub fn entities(&self, text: &str) -> Result<Vec<Entity>> {
+        let (model, classifier, tokenizer) = match (&self.model, &self.classifier, &self.tokenizer) {
+            (Some(model), Some(classifier), Some(tokenizer)) => (model, classifier, tokenizer),
+            _ => return Err(anyhow!("Model or tokenizer not initialized")),
+        };
+
+        let encoding = tokenizer.encode(text, true).map_err(E::msg)?;
+        let token_ids = Tensor::new(encoding.get_ids(), &model.device)?.unsqueeze(0)?;
+        let token_type_ids = token_ids.zeros_like()?;
+        let hidden_states = model.forward(&token_ids, &token_type_ids)?;
+        let predictions = classifier.forward(&hidden_states)?.argmax(D::Minus1)?.squeeze(0)?.to_vec1::<u32>()?;
+
+        let tokens = predictions
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| encoding.get_special_tokens_mask()[*i] == 0)
+            .map(|(i, class)| TaggedToken {
+                word: encoding.get_word_ids()[i],
+                offsets: encoding.get_offsets()[i],
+                tag: self.labels.get(*class as usize).map(|label| label.as_str()).unwrap_or("O"),
+            })
+            .collect::<Vec<_>>();
+        Ok(group_entities(text, &tokens))
+    }
+}
+
+#[async_trait::async_trait]
+impl EntityExtractor for Ner {
+    async fn extract(&self, text: &str) -> Result<Vec<Entity>> {
+        self.entities(text)
+    }
+}
+
+/// A token and the IOB tag predicted for it.
+struct TaggedToken<'a> {
+    /// Index of the word the token belongs to.
+    word: Option<u32>,
+
+    /// Byte offsets of the token in the text.
+    offsets: (usize, usize),
+
+    /// Predicted tag, e.g. `B-PER`.
+    tag: &'a str,
+}
+
+/// Group IOB tags into entities. The tag of the first token of each word is used for the whole
+/// word, and consecutive words with the same entity type are merged unless a `B-` tag starts a new
+/// entity.
+fn group_entities(text: &str, tokens: &[TaggedToken]) -> Vec<Entity> {
+    let mut entities = Vec::new();
+    // Label, start and end offsets of the entity being built.
+    let mut current: Option<(&str, usize, usize)> = None;
+    let mut last_word = None;
+
+    for token in tokens {
+        let (start, end) = token.offsets;
+        if token.word.is_some() && token.word == last_word {
+            // Continuation of the previous word.
+            if let Some(entity) = current.as_mut() {
+                entity.2 = end;
+            }
+            continue;
+        }
+        last_word = token.word;
+
+        let (prefix, label) = token.tag.split_once('-').unwrap_or((token.tag, ""));
+        current = match (prefix, current) {
+            ("I", Some((current_label, current_start, _))) if current_label == label => {
+                Some((current_label, current_start, end))
+            }
+            ("B", previous) | ("I", previous) => {
+                if let Some(entity) = previous {
+                    entities.push(entity);
+                }
+                Some((label, start, end))
+            }
+            (_, previous) => {
+                if let Some(entity) = previous {
+                    entities.push(entity);
+                }
+                None
+            }
+        };
+    }
+    if let Some(entity) = current {
+        entities.push(entity);
+    }
+
+    entities
+        .into_iter()
+        .filter_map(|(label, start, end)| text.get(start..end).map(|entity| Entity::new(entity, &label_name(label))))
+        .collect()
+}
+
+/// Map the short labels used by most NER models to the labels used in record attributes.
+fn label_name(label: &str) -> String {
+    match label {
+        "PER" | "PERSON" => "person".to_string(),
+        "ORG" => "organization".to_string(),
+        "LOC" | "GPE" => "location".to_string(),
+        "DATE" => "date".to_string(),
+        "MISC" => "misc".to_string(),
+        label => label.to_lowercase(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_group_entities() {
+        let text = "Ada Lovelace visited Charles Babbage in London";
+        let tags = [
+            (Some(0), (0, 3), "B-PER"),
+            (Some(1), (4, 8), "I-PER"),
+            (Some(1), (8, 12), "I-PER"),
+            (Some(2), (13, 20), "O"),
+            (Some(3), (21, 28), "B-PER"),
+            (Some(4), (29, 36), "I-PER"),
+            (Some(5), (37, 39), "O"),
+            (Some(6), (40, 46), "B-LOC"),
+        ];
+        let tokens = tags
+            .iter()
+            .map(|(word, offsets, tag)| TaggedToken {
+                word: *word,
+                offsets: *offsets,
+                tag,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            group_entities(text, &tokens),
+            vec![
+                Entity::new("Ada Lovelace", "person"),
+                Entity::new("Charles Babbage", "person"),
+                Entity::new("London", "location"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,313 @@
+//! This module provides an embedding backend that runs sentence-transformers models exported to ONNX
+//! with [ONNX Runtime](https://onnxruntime.ai), through the [ort](https://github.com/pykeio/ort) crate.
+//!
+//! On CPU, ONNX Runtime is often several times faster than candle for small models such as MiniLM.
+//! The CUDA and DirectML execution providers can be used when ONNX Runtime was built with them; when
+//! a provider is not available, ONNX Runtime falls back to the CPU.
+
+use std::sync::Arc;
+
+use anyhow::{anyhow, Error as E, Result};
+use hf_hub::{Repo, RepoType};
+use ndarray::{Array2, Axis, CowArray};
+use ort::{Environment, GraphOptimizationLevel, Session, SessionBuilder, Value};
+use tokenizers::{PaddingParams, PaddingStrategy, Tokenizer};
+
+use crate::prompt::Prompt;
+
+use super::source::ModelSource;
+use super::{Embedding, EmbeddingPreset, Embeddings, Precision};
+
+/// Hardware on which ONNX Runtime runs the model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExecutionProvider {
+    #[default]
+    Cpu,
+
+    /// NVIDIA GPUs.
+    Cuda,
+
+    /// DirectX 12 GPUs on Windows.
+    DirectMl,
+}
+
+impl ExecutionProvider {
+    fn to_ort(self) -> ort::ExecutionProvider {
+        match self {
+            ExecutionProvider::Cpu => ort::ExecutionProvider::CPU(Default::default()),
+            ExecutionProvider::Cuda => ort::ExecutionProvider::CUDA(Default::default()),
+            ExecutionProvider::DirectMl => ort::ExecutionProvider::DirectML(Default::default()),
+        }
+    }
+}
+
+pub struct Onnx {
+    /// The model to use, it must provide an ONNX export, e.g. `sentence-transformers/all-MiniLM-L6-v2`.
+    model_id: Option<String>,
+
+    /// Revision of the model repository.
+    revision: Option<String>,
+
+    /// Path of the ONNX file in the model repository.
+    onnx_file: String,
+
+    /// Run offline (you must have the files already cached)
+    offline: bool,
+
+    /// Where the model files are fetched from, the global `ModelSource` by default.
+    source: Option<ModelSource>,
+
+    /// Hardware on which to run the model.
+    execution_provider: ExecutionProvider,
+
+    /// Number of threads used to run the model on CPU, ONNX Runtime decides if not set.
+    intra_threads: Option<i16>,
+
+    /// L2 normalization for embeddings.
+    normalize_embeddings: bool,
+
+    /// Precision of the output embeddings.
+    precision: Precision,
+
+    /// ONNX Runtime session.
+    session: Option<Arc<Session>>,
+
+    /// Tokenizer.
+    tokenizer: Option<Tokenizer>,
+
+    /// Size of the embeddings, read from the model outputs.
+    hidden_size: Option<usize>,
+}
+
+/// Size of the embeddings of the default model, `sentence-transformers/all-MiniLM-L6-v2`.
+const DEFAULT_DIMENSIONS: usize = 384;
+
+impl Default for Onnx {
+    fn default() -> Self {
+        Self {
+            model_id: None,
+            revision: None,
+            onnx_file: "onnx/model.onnx".to_string(),
+            offline: false,
+            source: None,
+            execution_provider: ExecutionProvider::default(),
+            intra_threads: None,
+            normalize_embeddings: false,
+            precision: Precision::F32,
+            session: None,
+            tokenizer: None,
+            hidden_size: None,
+        }
+    }
+}
+
+impl Onnx {
+    /// Creates a new `Onnx` instance.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the model ID.
+    pub fn with_model_id(mut self, model_id: &str) -> Self {
+        self.model_id = Some(model_id.to_string());
+        self
+    }
+
+    /// Sets the revision for the model.
+    pub fn with_revision(mut self, revision: &str) -> Self {
+        self.revision = Some(revision.to_string());
+        self
+    }
+
+    /// Sets the model ID from a preset, e.g. `EmbeddingPreset::BgeM3` for mixed-language corpora.
+    pub fn with_preset(self, preset: EmbeddingPreset) -> Self {
+        self.with_model_id(preset.model_id())
+    }
+
+    /// Sets the path of the ONNX file in the model repository, `onnx/model.onnx` by default.
+    pub fn with_onnx_file(mut self, onnx_file: &str) -> Self {
+        self.onnx_file = onnx_file.to_string();
+        self
+    }
+
+    /// Configures the model to run offline.
+    pub fn offline(mut self) -> Self {
+        self.offline = true;
+        self
+    }
+
+    /// Sets where the model files are fetched from, instead of the global `ModelSource`.
+    pub fn with_source(mut self, source: ModelSource) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// Sets the hardware on which to run the model.
+    pub fn with_execution_provider(mut self, execution_provider: ExecutionProvider) -> Self {
+        self.execution_provider = execution_provider;
+        self
+    }
+
+    /// Sets the number of threads used to run the model on CPU.
+    pub fn with_intra_threads(mut self, intra_threads: i16) -> Self {
+        self.intra_threads = Some(intra_threads);
+        self
+    }
+
+    /// Enables L2 normalization for embeddings.
+    pub fn with_normalize_embeddings(mut self) -> Self {
+        self.normalize_embeddings = true;
+        self
+    }
+
+    /// Sets the precision of the output embeddings.
+    pub fn with_precision(mut self, precision: Precision) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// Builds the ONNX Runtime session and tokenizer.
+    pub async fn build_model_and_tokenizer(mut self) -> Result<Self> {
+        let model_id = self.model_id.clone().unwrap_or("sentence-transformers/all-MiniLM-L6-v2".to_string());
+        let revision = self.revision.clone().unwrap_or("main".to_string());
+        let repo = Repo::with_revision(model_id, RepoType::Model, revision);
+        let source = ModelSource::resolve(self.source.as_ref(), self.offline);
+        let (tokenizer_filename, onnx_filename) = (
+            source.get(&repo, "tokenizer.json").await?,
+            source.get(&repo, &self.onnx_file).await?,
+        );
+
+        let mut tokenizer = Tokenizer::from_file(tokenizer_filename).map_err(E::msg)?;
+        tokenizer.with_padding(Some(PaddingParams {
+            strategy: PaddingStrategy::BatchLongest,
+            ..Default::default()
+        }));
+
+        let environment = Environment::builder()
+            .with_name("orca")
+            .with_execution_providers([self.execution_provider.to_ort()])
+            .build()?
+            .into_arc();
+        let mut builder = SessionBuilder::new(&environment)?.with_optimization_level(GraphOptimizationLevel::Level3)?;
+        if let Some(intra_threads) = self.intra_threads {
+            builder = builder.with_intra_threads(intra_threads)?;
+        }
+        let session = builder.with_model_from_file(onnx_filename)?;
+
+        self.hidden_size = session
+            .outputs
+            .first()
+            .and_then(|output| output.dimensions.last().copied().flatten())
+            .map(|size| size as usize);
+        self.session = Some(Arc::new(session));
+        self.tokenizer = Some(tokenizer);
+        Ok(self)
+    }
+
+    /// Runs the model on a batch of prompts and averages the token embeddings of each prompt, ignoring
+    /// padding tokens.
+    fn embed(&self, prompts: Vec<String>) -> Result<Embeddings> {
+        let (Some(session), Some(tokenizer)) = (self.session.as_ref(), self.tokenizer.as_ref()) else {
+            return Err(anyhow!("Model or tokenizer not initialized"));
+        };
+
+        let encodings = tokenizer.encode_batch(prompts, true).map_err(E::msg)?;
+        let n_tokens = encodings.first().map_or(0, |encoding| encoding.len());
+        let to_array = |ids: fn(&tokenizers::Encoding) -> &[u32]| {
+            let values = encodings.iter().flat_map(|encoding| ids(encoding).iter().map(|&id| id as i64)).collect();
+            Array2::from_shape_vec((encodings.len(), n_tokens), values)
+        };
+        let input_ids = to_array(|encoding| encoding.get_ids())?;
+        let attention_mask = to_array(|encoding| encoding.get_attention_mask())?;
+        let token_type_ids = to_array(|encoding| encoding.get_type_ids())?;
+
+        // Models exported without segment embeddings do not take token type ids.
+        let mut inputs = vec![
+            CowArray::from(input_ids.into_dyn()),
+            CowArray::from(attention_mask.clone().into_dyn()),
+        ];
+        if session.inputs.iter().any(|input| input.name == "token_type_ids") {
+            inputs.push(CowArray::from(token_type_ids.into_dyn()));
+        }
+        let inputs = inputs
+            .iter()
+            .map(|input| Value::from_array(session.allocator(), input))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        let start = std::time::Instant::now();
+        let outputs = session.run(inputs)?;
+        log::info!("Embeddings took {:?} to generate", start.elapsed());
+        let hidden_states = outputs.first().ok_or(anyhow!("Model returned no outputs"))?.try_extract::<f32>()?;
+        let hidden_states = hidden_states.view();
+
+        let vectors = hidden_states
+            .axis_iter(Axis(0))
+            .zip(attention_mask.axis_iter(Axis(0)))
+            .map(|(tokens, mask)| {
+                let mut sum = vec![0f32; tokens.shape().last().copied().unwrap_or(0)];
+                let mut count = 0f32;
+                for (token, &mask) in tokens.axis_iter(Axis(0)).zip(mask.iter()) {
+                    if mask == 1 {
+                        sum.iter_mut().zip(token.iter()).for_each(|(sum, x)| *sum += x);
+                        count += 1.0;
+                    }
+                }
+                sum.into_iter().map(|x| x / count.max(1.0)).collect()
+            })
+            .collect();
+
+        let embeddings = Embeddings::new("onnx", vectors)?;
+        let embeddings = match &self.model_id {
+            Some(model_id) => embeddings.with_model(model_id),
+            None => embeddings,
+        };
+        let embeddings = if self.normalize_embeddings {
+            embeddings.normalize()
+        } else {
+            embeddings
+        };
+        Ok(embeddings.with_precision(self.precision))
+    }
+}
+
+#[async_trait::async_trait]
+impl Embedding for Onnx {
+    async fn generate_embedding(&self, prompt: Box<dyn Prompt>) -> Result<Embeddings> {
+        self.embed(vec![prompt.to_string()])
+    }
+
+    async fn generate_embeddings(&self, prompts: Vec<Box<dyn Prompt>>) -> Result<Embeddings> {
+        self.embed(prompts.iter().map(|prompt| prompt.to_string()).collect())
+    }
+
+    fn dimensions(&self) -> usize {
+        self.hidden_size.unwrap_or(DEFAULT_DIMENSIONS)
+    }
+
+    fn precision(&self) -> Precision {
+        self.precision
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{prompt, prompts};
+
+    #[tokio::test]
+    async fn test_generate() {
+        let onnx = Onnx::new().build_model_and_tokenizer().await.unwrap();
+        let response = onnx.generate_embedding(prompt!("Hello World")).await.unwrap();
+        assert_eq!(response.to_vec().unwrap().len(), 384);
+    }
+
+    #[tokio::test]
+    async fn test_batch() {
+        let onnx = Onnx::new().build_model_and_tokenizer().await.unwrap();
+        let response = onnx.generate_embeddings(prompts!("Hello World", "Goodbye World")).await.unwrap();
+        let vec = response.to_vec2();
+        assert_eq!(vec.len(), 2);
+        assert_eq!(vec[0].len(), 384);
+        assert_eq!(vec[1].len(), 384);
+    }
+}
@@ -1,14 +1,27 @@
 use std::fmt::Display;
+use std::sync::Arc;
 
 use crate::{
-    llm::{Embedding as EmbeddingTrait, LLM},
-    prompt::{chat::Message, Prompt},
+    error::OrcaError,
+    hash::fnv1a,
+    llm::{Embedding as EmbeddingTrait, GeneratedImage, ImageGenerator, RequestMetadata, LLM},
+    prompt::{
+        chat::{Image, Message, Role},
+        Prompt,
+    },
 };
 use anyhow::Result;
+use base64::{engine::general_purpose, Engine as _};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+use std::time::Duration;
+use tokio::sync::mpsc;
 
-use super::{EmbeddingResponse, LLMResponse};
+use super::secrets::{EnvSecrets, SecretsProvider, StaticSecrets};
+use super::stop::{truncate_parts, StopConditions, StopCriteria};
+use super::tools::{Tool, ToolCall, ToolChoice};
+use super::{task_stream, Embeddings, LLMResponse, TokenStream, TokenUsage};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Payload {
@@ -19,15 +32,236 @@ pub struct Payload {
     temperature: f32,
     #[serde(skip_serializing_if = "Option::is_none")]
     stop: Option<Vec<String>>,
-    messages: Vec<Message>,
+    messages: Vec<OpenAIMessage>,
     stream: bool,
     response_format: ResponseFormatWrapper,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prompt_cache_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<OpenAITool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<JsonValue>,
+}
+
+/// Tool as sent to the OpenAI API, which only supports functions.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OpenAITool {
+    #[serde(rename = "type")]
+    _type: String,
+    function: Tool,
+}
+
+impl From<&Tool> for OpenAITool {
+    fn from(tool: &Tool) -> Self {
+        OpenAITool {
+            _type: "function".to_string(),
+            function: tool.clone(),
+        }
+    }
+}
+
+/// Tool choice as sent to the OpenAI API.
+fn tool_choice(choice: &ToolChoice) -> JsonValue {
+    match choice {
+        ToolChoice::Auto => json!("auto"),
+        ToolChoice::None => json!("none"),
+        ToolChoice::Required => json!("required"),
+        ToolChoice::Function(name) => json!({"type": "function", "function": {"name": name}}),
+    }
+}
+
+/// Call of a function in a response of the OpenAI API, or replayed in a request, with its arguments
+/// serialized as JSON.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OpenAIToolCall {
+    id: String,
+    #[serde(rename = "type", default = "function_type")]
+    _type: String,
+    function: FunctionCall,
+}
+
+fn function_type() -> String {
+    "function".to_string()
+}
+
+impl From<&ToolCall> for OpenAIToolCall {
+    fn from(call: &ToolCall) -> Self {
+        let arguments = match &call.arguments {
+            JsonValue::String(arguments) => arguments.clone(),
+            arguments => arguments.to_string(),
+        };
+        OpenAIToolCall {
+            id: call.id.clone(),
+            _type: function_type(),
+            function: FunctionCall {
+                name: call.name.clone(),
+                arguments,
+            },
+        }
+    }
+}
+
+impl From<&OpenAIToolCall> for ToolCall {
+    fn from(call: &OpenAIToolCall) -> Self {
+        ToolCall::new(&call.id, &call.function.name, &call.function.arguments)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunctionCall {
+    name: String,
+    arguments: String,
+}
+
+/// Message of a choice of a response. Its content is null when the model only calls tools.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ResponseMessage {
+    role: Role,
+    #[serde(default, deserialize_with = "null_as_empty")]
+    content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<OpenAIToolCall>,
+}
+
+/// Deserialize a null string as an empty one.
+fn null_as_empty<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Ok(Option::<String>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// Message as sent to the OpenAI API. OpenAI caches prompt prefixes automatically, so cache
+/// breakpoints are not sent. Tool messages reference the call they answer by its id, and assistant
+/// messages carry the calls they made, so that parallel calls can be told apart.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OpenAIMessage {
+    role: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    content: OpenAIContent,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<OpenAIToolCall>,
+}
+
+/// Message content: plain text, or text and image parts for messages with images.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum OpenAIContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: Image },
+}
+
+impl From<&Message> for OpenAIMessage {
+    fn from(message: &Message) -> Self {
+        let content = if message.images.is_empty() {
+            OpenAIContent::Text(message.content.clone())
+        } else {
+            let text = Some(message.content.clone()).filter(|text| !text.is_empty());
+            let parts = text.map(|text| ContentPart::Text { text }).into_iter();
+            let images = message.images.iter().map(|image| ContentPart::ImageUrl {
+                image_url: image.clone(),
+            });
+            OpenAIContent::Parts(parts.chain(images).collect())
+        };
+        // Tool messages are identified by their call id, OpenAI does not take their name.
+        let name = match message.role {
+            Role::Tool => None,
+            _ => message.name.clone(),
+        };
+        OpenAIMessage {
+            role: message.role.to_string(),
+            name,
+            content,
+            tool_call_id: message.tool_call_id.clone(),
+            tool_calls: message.tool_calls.iter().map(OpenAIToolCall::from).collect(),
+        }
+    }
+}
+
+impl From<OpenAIMessage> for Message {
+    /// Convert a message of an OpenAI conversation back, e.g. when importing one. Text parts are joined
+    /// and image parts become images. Tool messages are left unnamed, as OpenAI does not name them.
+    fn from(message: OpenAIMessage) -> Self {
+        let (content, images) = match message.content {
+            OpenAIContent::Text(text) => (text, Vec::new()),
+            OpenAIContent::Parts(parts) => {
+                let mut texts = Vec::new();
+                let mut images = Vec::new();
+                for part in parts {
+                    match part {
+                        ContentPart::Text { text } => texts.push(text),
+                        ContentPart::ImageUrl { image_url } => images.push(image_url),
+                    }
+                }
+                (texts.join("\n"), images)
+            }
+        };
+        Message {
+            name: message.name,
+            images,
+            tool_call_id: message.tool_call_id,
+            tool_calls: message.tool_calls.iter().map(ToolCall::from).collect(),
+            ..Message::new(Role::from(message.role.as_str()), &content)
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct EmbeddingPayload {
     input: String,
     model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dimensions: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ImagePayload {
+    prompt: String,
+    model: String,
+    n: u8,
+    size: String,
+    response_format: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OpenAIImageResponse {
+    created: i64,
+    data: Vec<ImageData>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ImageData {
+    b64_json: String,
+    #[serde(default)]
+    revised_prompt: Option<String>,
+}
+
+impl TryFrom<OpenAIImageResponse> for Vec<GeneratedImage> {
+    type Error = anyhow::Error;
+
+    /// Decode the images of an OpenAI image response, which are PNG files.
+    fn try_from(response: OpenAIImageResponse) -> Result<Self> {
+        response
+            .data
+            .into_iter()
+            .map(|data| {
+                let image = GeneratedImage::png(general_purpose::STANDARD.decode(data.b64_json)?);
+                Ok(match data.revised_prompt {
+                    Some(revised_prompt) => image.with_revised_prompt(&revised_prompt),
+                    None => image,
+                })
+            })
+            .collect()
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -36,12 +270,19 @@ pub struct ResponseFormatWrapper {
     pub format: ResponseFormat,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Chat completion response. Only the choices are required, so that the responses of OpenAI-compatible
+/// servers leaving out the other fields, e.g. the usage, are accepted.
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Response {
+    #[serde(default)]
     id: String,
+    #[serde(default)]
     object: String,
-    created: i32,
+    #[serde(default)]
+    created: i64,
+    #[serde(default)]
     model: String,
+    #[serde(default)]
     usage: Usage,
     choices: Vec<Choice>,
 }
@@ -56,6 +297,7 @@ pub struct QuotaError {
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
 pub enum OpenAIResponse {
     Response(Response),
     QuotaError(QuotaError),
@@ -63,9 +305,12 @@ pub enum OpenAIResponse {
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct OpenAIEmbeddingResponse {
+    #[serde(default)]
     object: String,
+    #[serde(default)]
     model: String,
     data: Vec<Embedding>,
+    #[serde(default)]
     usage: Usage,
 }
 
@@ -79,6 +324,23 @@ impl OpenAIEmbeddingResponse {
     }
 }
 
+impl TryFrom<Vec<OpenAIEmbeddingResponse>> for Embeddings {
+    type Error = anyhow::Error;
+
+    /// Collect the embeddings of one or more OpenAI embedding responses, in order.
+    fn try_from(responses: Vec<OpenAIEmbeddingResponse>) -> Result<Self> {
+        let model = responses.first().map(|response| response.model.clone()).unwrap_or_default();
+        let vectors = responses
+            .into_iter()
+            .flat_map(|mut response| {
+                response.data.sort_by_key(|embedding| embedding.index);
+                response.data.into_iter().map(|embedding| embedding.embedding)
+            })
+            .collect();
+        Ok(Embeddings::new("openai", vectors)?.with_model(&model))
+    }
+}
+
 impl Display for OpenAIEmbeddingResponse {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut s = String::new();
@@ -91,7 +353,9 @@ impl Display for OpenAIEmbeddingResponse {
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
 pub struct Embedding {
+    #[serde(default)]
     pub index: u32,
+    #[serde(default)]
     pub object: String,
     pub embedding: Vec<f32>,
 }
@@ -102,6 +366,43 @@ pub struct EmbeddingUsage {
     pub total_tokens: u32,
 }
 
+impl Response {
+    /// Number of prompt tokens that were served from the provider's prompt cache.
+    pub fn cached_tokens(&self) -> i32 {
+        self.usage.prompt_tokens_details.as_ref().map_or(0, |details| details.cached_tokens)
+    }
+
+    /// Total number of prompt and completion tokens used by this request.
+    pub fn total_tokens(&self) -> i32 {
+        self.usage.total_tokens
+    }
+
+    /// Prompt and completion tokens used by this request. Servers that only report the total are assumed to
+    /// count the rest as completion tokens.
+    pub fn token_usage(&self) -> TokenUsage {
+        let prompt_tokens = self.usage.prompt_tokens.max(0) as u32;
+        let completion_tokens = match self.usage.completion_tokens {
+            Some(completion_tokens) => completion_tokens.max(0) as u32,
+            None => (self.usage.total_tokens.max(0) as u32).saturating_sub(prompt_tokens),
+        };
+        TokenUsage {
+            prompt_tokens,
+            completion_tokens,
+            model: Some(self.model.clone()).filter(|model| !model.is_empty()),
+        }
+    }
+
+    /// Calls of tools made by the model, with their parsed arguments.
+    pub fn tool_calls(&self) -> Vec<ToolCall> {
+        self.choices.iter().flat_map(|choice| &choice.message.tool_calls).map(ToolCall::from).collect()
+    }
+
+    /// Truncate the content of the response to the given length in bytes.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        truncate_parts(self.choices.iter_mut().map(|choice| &mut choice.message.content), len);
+    }
+}
+
 impl Display for Response {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut s = String::new();
@@ -114,9 +415,20 @@ impl Display for Response {
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Usage {
+    #[serde(default)]
     prompt_tokens: i32,
+    #[serde(default)]
     completion_tokens: Option<i32>,
+    #[serde(default)]
     total_tokens: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    prompt_tokens_details: Option<PromptTokensDetails>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct PromptTokensDetails {
+    #[serde(default)]
+    cached_tokens: i32,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
@@ -132,15 +444,243 @@ impl From<ResponseFormat> for ResponseFormatWrapper {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Choice {
+    #[serde(default)]
     index: i32,
-    message: Message,
-    finish_reason: String,
+    message: ResponseMessage,
+    #[serde(default)]
+    finish_reason: Option<String>,
+}
+
+/// Chunk of a streamed response.
+#[derive(Deserialize, Debug)]
+struct StreamChunk {
+    choices: Vec<StreamChoice>,
+}
+
+#[derive(Deserialize, Debug)]
+struct StreamChoice {
+    delta: Delta,
+}
+
+/// Text added to a message by a chunk of a streamed response.
+#[derive(Deserialize, Debug)]
+struct Delta {
+    #[serde(default)]
+    content: Option<String>,
+}
+
+/// Parser of the server-sent events of a streamed response, fed the body as it arrives.
+#[derive(Debug, Default)]
+pub(crate) struct EventParser {
+    /// Bytes received after the last complete line.
+    buffer: Vec<u8>,
+}
+
+impl EventParser {
+    /// Add a chunk of the body, returning the data of the events completed by it.
+    pub(crate) fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|&byte| byte == b'\n') {
+            let line = self.buffer.drain(..=end).collect::<Vec<_>>();
+            if let Some(data) = String::from_utf8_lossy(&line).trim_end().strip_prefix("data:") {
+                events.push(data.trim_start().to_string());
+            }
+        }
+        events
+    }
+}
+
+/// Text of an event of a streamed response, or `None` for the event ending the stream.
+fn event_text(data: &str) -> Result<Option<String>> {
+    if data == "[DONE]" {
+        return Ok(None);
+    }
+    let event = serde_json::from_str::<JsonValue>(data)?;
+    if let Some(error) = event.get("error") {
+        let message = error["message"].as_str().map(str::to_string).unwrap_or(error.to_string());
+        return Err(OrcaError::LLM {
+            provider: "OpenAI".to_string(),
+            status: None,
+            message,
+        }
+        .into());
+    }
+    let chunk = serde_json::from_value::<StreamChunk>(event)?;
+    Ok(Some(
+        chunk.choices.into_iter().filter_map(|choice| choice.delta.content).collect(),
+    ))
+}
+
+/// Send the text of a streamed response piece by piece, until the response ends, a stop criteria is met or
+/// the stream is dropped. Each piece counts as a token for the stop criteria.
+async fn forward_events(
+    response: &mut reqwest::Response,
+    stop: &StopConditions,
+    sender: &mpsc::Sender<Result<String>>,
+) -> Result<()> {
+    let mut parser = EventParser::default();
+    let mut text = String::new();
+    let mut pieces = 0;
+    while let Some(chunk) = response.chunk().await? {
+        for data in parser.push(&chunk) {
+            let Some(piece) = event_text(&data)? else {
+                return Ok(());
+            };
+            if piece.is_empty() {
+                continue;
+            }
+            let sent = text.len();
+            text.push_str(&piece);
+            pieces += 1;
+            let stopped = stop.check(pieces, &text);
+            let end = stopped.unwrap_or(text.len()).max(sent);
+            if end > sent && sender.send(Ok(text[sent..end].to_string())).await.is_err() {
+                return Ok(());
+            }
+            if stopped.is_some() {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Name of the secret holding the API key.
+pub const OPENAI_API_KEY: &str = "OPENAI_API_KEY";
+
+/// Name of the secret holding the API key of Azure OpenAI clients.
+pub const AZURE_OPENAI_API_KEY: &str = "AZURE_OPENAI_API_KEY";
+
+static OPENAI_API_URL: &str = "https://api.openai.com/v1";
+
+/// Endpoint of the API a request is sent to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Endpoint {
+    Chat,
+    Embeddings,
+    Images,
+}
+
+/// Azure OpenAI resource and deployments the requests of a client are sent to.
+#[derive(Debug, Clone, PartialEq)]
+struct Azure {
+    /// Endpoint of the resource, e.g. `https://my-resource.openai.azure.com`.
+    endpoint: String,
+
+    /// Deployment of the chat model.
+    deployment: String,
+
+    /// Deployment of the embedding model, named after the model if not set.
+    embedding_deployment: Option<String>,
+
+    /// Version of the API, e.g. `2024-02-01`.
+    api_version: String,
+}
+
+/// Retries of the requests failing with a rate limit or a server error.
+#[derive(Debug, Clone, Copy)]
+struct RetryPolicy {
+    /// Number of attempts of a request, including the first one.
+    max_attempts: usize,
+
+    /// Wait before the first retry, doubled on each retry.
+    initial_backoff: Duration,
+
+    /// Maximum wait between retries, unless the server asks for a longer one.
+    max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Whether a request failing with the status may succeed if retried.
+fn is_transient(status: reqwest::StatusCode) -> bool {
+    status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// Wait requested by the `retry-after-ms` or `retry-after` header of a response. Retry dates are not
+/// supported.
+fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let header = |name: &str| headers.get(name)?.to_str().ok()?.trim().parse::<f64>().ok();
+    let seconds = header("retry-after-ms").map(|millis| millis / 1000.0).or_else(|| header("retry-after"))?;
+    Duration::try_from_secs_f64(seconds).ok()
 }
 
-static OPENAI_COMPLETIONS_URL: &str = "https://api.openai.com/v1/chat/completions";
-static OPENAI_EMBEDDING_URL: &str = " https://api.openai.com/v1/embeddings";
+/// Send a request, retrying it according to the policy on rate limits, server errors, timeouts and
+/// connection failures. Requests hitting the quota of the account are not retried. Returns the last
+/// response, which may have failed.
+async fn execute(client: &Client, policy: RetryPolicy, mut request: reqwest::Request) -> Result<reqwest::Response> {
+    let mut backoff = policy.initial_backoff;
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let retry = match request.try_clone() {
+            Some(retry) if attempt < policy.max_attempts => retry,
+            _ => return Ok(client.execute(request).await?),
+        };
+        let wait = match client.execute(request).await {
+            Ok(response) if !is_transient(response.status()) => return Ok(response),
+            Ok(response) => {
+                let status = response.status();
+                // The wait asked for by the server is a lower bound: retrying sooner would be rate limited again.
+                let wait = backoff.min(policy.max_backoff).max(retry_after(response.headers()).unwrap_or_default());
+                let body = response.text().await.unwrap_or_default();
+                if body.contains("insufficient_quota") {
+                    return Err(OrcaError::LLM {
+                        provider: "OpenAI".to_string(),
+                        status: Some(status.as_u16()),
+                        message: body,
+                    }
+                    .into());
+                }
+                log::warn!(
+                    "OpenAI request failed with status {}, attempt {} retried in {:?}",
+                    status,
+                    attempt,
+                    wait
+                );
+                wait
+            }
+            Err(e) if e.is_timeout() || e.is_connect() => {
+                log::warn!(
+                    "OpenAI request failed, attempt {} retried in {:?}: {}",
+                    attempt,
+                    backoff,
+                    e
+                );
+                backoff
+            }
+            Err(e) => return Err(e.into()),
+        };
+        tokio::time::sleep(wait).await;
+        backoff = (backoff * 2).min(policy.max_backoff);
+        request = retry;
+    }
+}
+
+/// Fail on a response with an error status with an `OrcaError`, holding the body of the response.
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if !status.is_success() {
+        return Err(OrcaError::LLM {
+            provider: "OpenAI".to_string(),
+            status: Some(status.as_u16()),
+            message: response.text().await?,
+        }
+        .into());
+    }
+    Ok(response)
+}
 
 #[derive(Clone)]
 pub struct OpenAI {
@@ -148,13 +688,16 @@ pub struct OpenAI {
     /// support LLM orchestration.
     client: Client,
 
-    /// URL of the OpenAI API
-    /// This URL is set to https://api.openai.com/v1/chat/completions by default.
+    /// Base URL of the OpenAI API, or of an OpenAI-compatible API, the endpoints are appended to.
+    /// This URL is set to https://api.openai.com/v1 by default.
     url: String,
 
-    /// API key for the OpenAI API
-    /// This key is stored in the OPENAI_API_KEY environment variable.
-    api_key: String,
+    /// Provider of the API key for the OpenAI API, read on every request.
+    /// By default, the key is read from the OPENAI_API_KEY environment variable.
+    secrets: Arc<dyn SecretsProvider>,
+
+    /// Whether requests are sent without an `Authorization` header when there is no API key.
+    api_key_optional: bool,
 
     /// ID of the model to use.
     /// See the [model endpoint compatibility](https://platform.openai.com/docs/models/model-endpoint-compatibility) table for details on which models work with the Chat API.
@@ -164,6 +707,17 @@ pub struct OpenAI {
     /// See the [model endpoint compatibility](https://platform.openai.com/docs/models/model-endpoint-compatibility) table for details on which models work with the Chat API.
     emedding_model: String,
 
+    /// Number of dimensions of the embeddings. Only supported by `text-embedding-3` and later models.
+    /// If not set, the model's default size is used.
+    embedding_dimensions: Option<usize>,
+
+    /// ID of the image generation model to use, e.g. `dall-e-3`.
+    image_model: String,
+
+    /// Size of the generated images, e.g. `1024x1024`. See the
+    /// [images API reference](https://platform.openai.com/docs/api-reference/images/create) for the sizes supported by each model.
+    image_size: String,
+
     /// What sampling temperature to use, between 0 and 2. Higher values like 0.8 will make the output more random,
     /// while lower values like 0.2 will make it more focused and deterministic.
     ///
@@ -190,21 +744,50 @@ pub struct OpenAI {
     /// The format of the returned data. With the new update, the response can be set to a JSON object.
     /// https://platform.openai.com/docs/guides/text-generation/json-mode
     response_format: ResponseFormat,
+
+    /// Key used by OpenAI to route requests sharing a prompt prefix to the same cache.
+    /// If not set, a key is derived from the cacheable prefix of the prompt, if it has one.
+    prompt_cache_key: Option<String>,
+
+    /// Criteria applied client-side to the responses, which are cut where one is met.
+    stop: StopConditions,
+
+    /// Azure OpenAI deployments the requests are sent to instead of the OpenAI API, if set.
+    azure: Option<Azure>,
+
+    /// Functions the model can call.
+    tools: Vec<Tool>,
+
+    /// Whether and which tools the model must call, left to OpenAI if not set.
+    tool_choice: Option<ToolChoice>,
+
+    /// Retries of the requests failing with a rate limit or a server error, none by default.
+    retry: RetryPolicy,
 }
 
 impl Default for OpenAI {
     fn default() -> Self {
         Self {
             client: Client::new(),
-            url: OPENAI_COMPLETIONS_URL.to_string(),
-            api_key: std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY not set"),
+            url: OPENAI_API_URL.to_string(),
+            secrets: Arc::new(EnvSecrets),
+            api_key_optional: false,
             model: "gpt-3.5-turbo-1106".to_string(),
             emedding_model: "text-embedding-ada-002".to_string(),
+            embedding_dimensions: None,
+            image_model: "dall-e-3".to_string(),
+            image_size: "1024x1024".to_string(),
             temperature: 1.0,
             top_p: 1.0,
             stream: false,
             max_tokens: 1024u16,
             response_format: ResponseFormat::Text,
+            prompt_cache_key: None,
+            stop: StopConditions::new(),
+            azure: None,
+            tools: Vec::new(),
+            tool_choice: None,
+            retry: RetryPolicy::default(),
         }
     }
 }
@@ -215,6 +798,90 @@ impl OpenAI {
         Self::default()
     }
 
+    /// Create a client of an Azure OpenAI deployment, authenticated with the `api-key` header. The key is
+    /// read from the AZURE_OPENAI_API_KEY environment variable by default. The model of the requests is
+    /// the one of the deployment; embeddings are sent to the deployment named after the embedding model,
+    /// unless set with `with_embedding_deployment`, and images to the one named after the image model.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::llm::openai::OpenAI;
+    ///
+    /// let client = OpenAI::azure("https://my-resource.openai.azure.com", "gpt-4", "2024-02-01")
+    ///     .with_embedding_deployment("ada")
+    ///     .with_api_key("azure-key");
+    /// let request = client.generate_embedding_request("Orcas").unwrap();
+    /// assert_eq!(
+    ///     request.url().as_str(),
+    ///     "https://my-resource.openai.azure.com/openai/deployments/ada/embeddings?api-version=2024-02-01"
+    /// );
+    /// assert_eq!(request.headers()["api-key"], "azure-key");
+    /// ```
+    pub fn azure(endpoint: &str, deployment: &str, api_version: &str) -> Self {
+        OpenAI {
+            azure: Some(Azure {
+                endpoint: endpoint.trim_end_matches('/').to_string(),
+                deployment: deployment.to_string(),
+                embedding_deployment: None,
+                api_version: api_version.to_string(),
+            }),
+            ..Self::default()
+        }
+    }
+
+    /// Send the requests to an OpenAI-compatible API instead of the OpenAI API, such as the servers of vLLM,
+    /// LocalAI, LM Studio or llama.cpp. The URL is the base URL of the API, the one the `chat/completions`,
+    /// `embeddings` and `images/generations` endpoints are relative to, e.g. `http://localhost:8000/v1`.
+    /// Servers running without authentication also need `with_api_key_optional`, and the model should be
+    /// set to the one they serve.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::llm::openai::OpenAI;
+    /// use orca_core::prompt::chat::{Message, Role};
+    ///
+    /// let client = OpenAI::new()
+    ///     .with_url("http://localhost:8000/v1/")
+    ///     .with_model("mistralai/Mistral-7B-Instruct-v0.2")
+    ///     .with_api_key_optional();
+    /// let request = client.generate_request(&[Message::new(Role::User, "Hi")]).unwrap();
+    /// assert_eq!(request.url().as_str(), "http://localhost:8000/v1/chat/completions");
+    /// ```
+    pub fn with_url(mut self, url: &str) -> Self {
+        self.url = url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Send the requests without an `Authorization` header when no API key is set, rather than failing,
+    /// for OpenAI-compatible servers that do not require one. A key that is set is still sent.
+    pub fn with_api_key_optional(mut self) -> Self {
+        self.api_key_optional = true;
+        self
+    }
+
+    /// Set the Azure OpenAI deployment of the embedding model. Ignored by clients of the OpenAI API.
+    pub fn with_embedding_deployment(mut self, deployment: &str) -> Self {
+        if let Some(azure) = &mut self.azure {
+            azure.embedding_deployment = Some(deployment.to_string());
+        }
+        self
+    }
+
+    /// Set the API key, instead of reading it from the OPENAI_API_KEY environment variable, or
+    /// AZURE_OPENAI_API_KEY for Azure clients.
+    pub fn with_api_key(self, api_key: &str) -> Self {
+        let name = self.api_key_name();
+        self.with_secrets(StaticSecrets::new().with_secret(name, api_key))
+    }
+
+    /// Set the provider the API key is read from on every request, e.g. to use a key per tenant or
+    /// rotate keys at runtime. The key is looked up as `OPENAI_API_KEY`, or `AZURE_OPENAI_API_KEY` for
+    /// Azure clients.
+    pub fn with_secrets<S: SecretsProvider + 'static>(mut self, secrets: S) -> Self {
+        self.secrets = Arc::new(secrets);
+        self
+    }
+
     /// Set model to use
     /// e.g. "davinci", "gpt-3.5-turbo"
     pub fn with_model(mut self, model: &str) -> Self {
@@ -229,6 +896,25 @@ impl OpenAI {
         self
     }
 
+    /// Set the number of dimensions of the embeddings, e.g. to shorten `text-embedding-3` embeddings.
+    pub fn with_embedding_dimensions(mut self, dimensions: usize) -> Self {
+        self.embedding_dimensions = Some(dimensions);
+        self
+    }
+
+    /// Set image generation model to use
+    /// e.g. "dall-e-2", "dall-e-3"
+    pub fn with_image_model(mut self, image_model: &str) -> Self {
+        self.image_model = image_model.to_string();
+        self
+    }
+
+    /// Set the size of the generated images, e.g. "1024x1024" or "1792x1024".
+    pub fn with_image_size(mut self, image_size: &str) -> Self {
+        self.image_size = image_size.to_string();
+        self
+    }
+
     /// What sampling temperature to use, between 0 and 2. Higher values like 0.8 will make the output more random,
     /// while lower values like 0.2 will make it more focused and deterministic.
     pub fn with_temperature(mut self, temperature: f32) -> Self {
@@ -260,146 +946,308 @@ impl OpenAI {
         self
     }
 
+    /// Set the key used by OpenAI to route requests sharing a prompt prefix to the same cache.
+    pub fn with_prompt_cache_key(mut self, prompt_cache_key: &str) -> Self {
+        self.prompt_cache_key = Some(prompt_cache_key.to_string());
+        self
+    }
+
+    /// Retry the requests failing with a rate limit (429) or a server error (5xx), as well as those timing out
+    /// or failing to connect, up to `max_attempts` attempts in total. Retries wait for `initial_backoff`,
+    /// doubled on each retry and capped at `max_backoff`, or for the time requested by the `Retry-After` header
+    /// of the response if longer. Requests over the quota of the account are not retried.
+    ///
+    /// # Example
+    /// ```
+    /// use std::time::Duration;
+    /// use orca_core::llm::openai::OpenAI;
+    ///
+    /// let client = OpenAI::new().with_retry(5, Duration::from_millis(500), Duration::from_secs(20));
+    /// ```
+    pub fn with_retry(mut self, max_attempts: usize, initial_backoff: Duration, max_backoff: Duration) -> Self {
+        self.retry = RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            initial_backoff,
+            max_backoff,
+        };
+        self
+    }
+
+    /// Add a function the model can call. Responses calling tools are returned as `LLMResponse::ToolCall`;
+    /// streamed responses only carry text.
+    pub fn with_tool(mut self, tool: Tool) -> Self {
+        self.tools.push(tool);
+        self
+    }
+
+    /// Set whether and which tools the model must call.
+    pub fn with_tool_choice(mut self, tool_choice: ToolChoice) -> Self {
+        self.tool_choice = Some(tool_choice);
+        self
+    }
+
+    /// Add a stop criteria, applied client-side to the responses as if they were streamed.
+    pub fn with_stop_criteria<S: StopCriteria + 'static>(mut self, criteria: S) -> Self {
+        self.stop = self.stop.with(criteria);
+        self
+    }
+
     /// Generate a request for the OpenAI API and set the parameters
     pub fn generate_request(&self, messages: &[Message]) -> Result<reqwest::Request> {
+        self.generate_request_with_metadata(messages, &RequestMetadata::default())
+    }
+
+    /// Generate a request for the OpenAI API, sending the end user id in the `user` field and the
+    /// trace id, idempotency key and custom headers as HTTP headers.
+    pub fn generate_request_with_metadata(
+        &self,
+        messages: &[Message],
+        metadata: &RequestMetadata,
+    ) -> Result<reqwest::Request> {
+        self.chat_request(messages, metadata, self.stream)
+    }
+
+    /// Generate a chat completion request, streamed or not.
+    fn chat_request(&self, messages: &[Message], metadata: &RequestMetadata, stream: bool) -> Result<reqwest::Request> {
         let payload = Payload {
             model: self.model.clone(),
             prompt: None,
             max_tokens: self.max_tokens as i32,
             temperature: self.temperature,
             stop: None,
-            messages: messages.to_vec(),
-            stream: self.stream,
+            messages: messages.iter().map(OpenAIMessage::from).collect(),
+            stream,
             response_format: self.response_format.clone().into(),
+            prompt_cache_key: self.prompt_cache_key.clone().or_else(|| prefix_cache_key(messages)),
+            user: metadata.user_id.clone(),
+            tools: self.tools.iter().map(OpenAITool::from).collect(),
+            tool_choice: self.tool_choice.as_ref().map(tool_choice),
         };
-        let req = self
-            .client
-            .post(&self.url)
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&payload)
-            .build()?;
+        let req = metadata.apply(self.post(Endpoint::Chat)?).json(&payload).build()?;
         Ok(req)
     }
 
+    /// Name of the secret holding the API key.
+    fn api_key_name(&self) -> &'static str {
+        match self.azure {
+            Some(_) => AZURE_OPENAI_API_KEY,
+            None => OPENAI_API_KEY,
+        }
+    }
+
+    /// Start a request to an endpoint of the OpenAI API or of the Azure deployment, authenticated with the
+    /// current API key.
+    fn post(&self, endpoint: Endpoint) -> Result<reqwest::RequestBuilder> {
+        let api_key = match self.secrets.secret(self.api_key_name()) {
+            Ok(api_key) => Some(api_key),
+            Err(_) if self.api_key_optional => None,
+            Err(e) => return Err(e),
+        };
+        let Some(azure) = &self.azure else {
+            let path = match endpoint {
+                Endpoint::Chat => "chat/completions",
+                Endpoint::Embeddings => "embeddings",
+                Endpoint::Images => "images/generations",
+            };
+            let request = self.client.post(format!("{}/{}", self.url, path));
+            return Ok(match api_key {
+                Some(api_key) => request.header("Authorization", format!("Bearer {}", api_key)),
+                None => request,
+            });
+        };
+        let (deployment, path) = match endpoint {
+            Endpoint::Chat => (&azure.deployment, "chat/completions"),
+            Endpoint::Embeddings => (
+                azure.embedding_deployment.as_ref().unwrap_or(&self.emedding_model),
+                "embeddings",
+            ),
+            Endpoint::Images => (&self.image_model, "images/generations"),
+        };
+        let url = format!(
+            "{}/openai/deployments/{}/{}?api-version={}",
+            azure.endpoint, deployment, path, azure.api_version
+        );
+        let request = self.client.post(url);
+        Ok(match api_key {
+            Some(api_key) => request.header("api-key", api_key),
+            None => request,
+        })
+    }
+
     /// Generate a request for the OpenAI API to create embeddings
 
     pub fn generate_embedding_request(&self, prompt: &str) -> Result<reqwest::Request> {
         let payload = EmbeddingPayload {
             model: self.emedding_model.clone(),
             input: prompt.to_string(),
+            dimensions: self.embedding_dimensions,
+        };
+
+        let req = self.post(Endpoint::Embeddings)?.header("Content-Type", "application/json").json(&payload).build()?;
+
+        Ok(req)
+    }
+
+    /// Generate a request for the OpenAI Images API, returning the image as base64 encoded PNG data.
+    pub fn generate_image_request(&self, prompt: &str) -> Result<reqwest::Request> {
+        let payload = ImagePayload {
+            prompt: prompt.to_string(),
+            model: self.image_model.clone(),
+            n: 1,
+            size: self.image_size.clone(),
+            response_format: "b64_json".to_string(),
         };
 
-        let req = self
-            .client
-            .post(OPENAI_EMBEDDING_URL)
-            .header("Content-Type", "application/json")
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&payload)
-            .build()?;
+        let req = self.post(Endpoint::Images)?.header("Content-Type", "application/json").json(&payload).build()?;
 
         Ok(req)
     }
 }
 
+/// Derive a cache key from the messages up to the last cache breakpoint, as sent to the API, so that
+/// calls sharing the same static prefix are routed to the same cache. The key is a stable hash, so that
+/// builds with different Rust versions share the cache.
+fn prefix_cache_key(messages: &[Message]) -> Option<String> {
+    let prefix_len = messages.iter().rposition(|message| message.cache_control.is_some())? + 1;
+    let prefix = messages[..prefix_len].iter().map(OpenAIMessage::from).collect::<Vec<_>>();
+    let json = serde_json::to_vec(&prefix).ok()?;
+    Some(format!("orca-{:016x}", fnv1a(&json)))
+}
+
 #[async_trait::async_trait]
 impl LLM for OpenAI {
     async fn generate(&self, prompt: Box<dyn Prompt>) -> Result<LLMResponse> {
+        self.generate_with_metadata(prompt, &RequestMetadata::default()).await
+    }
+
+    async fn generate_with_metadata(&self, prompt: Box<dyn Prompt>, metadata: &RequestMetadata) -> Result<LLMResponse> {
         let messages = prompt.to_chat()?;
-        let req = self.generate_request(messages.to_vec_ref())?;
-        let res = self.client.execute(req).await?;
+        let req = self.generate_request_with_metadata(messages.to_vec_ref(), metadata)?;
+        let res = check_status(execute(&self.client, self.retry, req).await?).await?;
         match res.json::<OpenAIResponse>().await? {
-            OpenAIResponse::Response(response) => Ok(response.into()),
-            OpenAIResponse::QuotaError(e) => Err(anyhow::anyhow!("Quota error: {}", e.message)),
+            OpenAIResponse::Response(mut response) => {
+                if let Some(len) = self.stop.apply(&response.to_string()) {
+                    response.truncate(len);
+                }
+                let calls = response.tool_calls();
+                if calls.is_empty() {
+                    return Ok(response.into());
+                }
+                Ok(LLMResponse::ToolCall {
+                    calls,
+                    response: Box::new(response.into()),
+                })
+            }
+            OpenAIResponse::QuotaError(e) => Err(OrcaError::LLM {
+                provider: "OpenAI".to_string(),
+                status: None,
+                message: format!("Quota error: {}", e.message),
+            }
+            .into()),
         }
     }
-}
 
-const MAX_RETRIES: u32 = 5;
-const INITIAL_BACKOFF: tokio::time::Duration = tokio::time::Duration::from_millis(100);
-const MAX_BACKOFF: tokio::time::Duration = tokio::time::Duration::from_secs(10);
+    async fn generate_stream(&self, prompt: Box<dyn Prompt>) -> Result<TokenStream> {
+        let messages = prompt.to_chat()?;
+        let req = self.chat_request(messages.to_vec_ref(), &RequestMetadata::default(), true)?;
+        let mut res = check_status(execute(&self.client, self.retry, req).await?).await?;
+        let stop = self.stop.clone();
+        Ok(task_stream(move |sender| async move {
+            if let Err(e) = forward_events(&mut res, &stop, &sender).await {
+                let _ = sender.send(Err(e)).await;
+            }
+        }))
+    }
 
-async fn send_with_exponential_backoff<T>(sender: &tokio::sync::mpsc::Sender<T>, message: T) -> Result<(), String>
-where
-    T: Clone + Send + 'static,
-{
-    let mut attempts = 0;
-    let mut backoff = INITIAL_BACKOFF;
+    fn json_mode(&self) -> Option<Self> {
+        Some(self.clone().with_response_format(ResponseFormat::JsonObject))
+    }
 
-    loop {
-        match sender.send(message.clone()).await {
-            Ok(_) => return Ok(()),
-            Err(_) if attempts < MAX_RETRIES => {
-                attempts += 1;
-                tokio::time::sleep(backoff).await;
-                backoff = std::cmp::min(backoff * 2, MAX_BACKOFF);
-            }
-            Err(e) => return Err(format!("Failed to send message: {}", e)),
+    fn deterministic(&self) -> Option<Self> {
+        Some(self.clone().with_temperature(0.0))
+    }
+
+    fn config(&self) -> JsonValue {
+        let model = self.azure.as_ref().map_or(&self.model, |azure| &azure.deployment);
+        let mut config = json!({
+            "type": "OpenAI",
+            "model": model,
+            "temperature": self.temperature,
+            "top_p": self.top_p,
+            "max_tokens": self.max_tokens,
+        });
+        if let Some(azure) = &self.azure {
+            config["endpoint"] = json!(azure.endpoint);
+        } else if self.url != OPENAI_API_URL {
+            config["url"] = json!(self.url);
         }
+        config
     }
 }
 
 #[async_trait::async_trait]
 impl EmbeddingTrait for OpenAI {
-    async fn generate_embedding(&self, prompt: Box<dyn Prompt>) -> Result<EmbeddingResponse> {
+    async fn generate_embedding(&self, prompt: Box<dyn Prompt>) -> Result<Embeddings> {
         let req = self.generate_embedding_request(&prompt.to_string())?;
-        let res = self.client.execute(req).await?;
+        let res = check_status(execute(&self.client, self.retry, req).await?).await?;
         let res = res.json::<OpenAIEmbeddingResponse>().await?;
 
-        Ok(res.into())
+        Embeddings::try_from(vec![res])
     }
 
-    async fn generate_embeddings(&self, prompts: Vec<Box<dyn Prompt>>) -> Result<EmbeddingResponse> {
-        let num_prompts = prompts.len();
-        let mut embeddings = vec![OpenAIEmbeddingResponse::default(); num_prompts];
-
-        let (sender, mut receiver) = tokio::sync::mpsc::channel(num_prompts);
+    async fn generate_embeddings(&self, prompts: Vec<Box<dyn Prompt>>) -> Result<Embeddings> {
+        let mut embeddings = vec![OpenAIEmbeddingResponse::default(); prompts.len()];
 
+        // Dropping the set on an error aborts the requests still running.
+        let mut requests = tokio::task::JoinSet::new();
         for (i, prompt) in prompts.into_iter().enumerate() {
-            let sender = sender.clone();
-            let client = self.client.clone();
+            let (client, retry) = (self.client.clone(), self.retry);
             let req = self.generate_embedding_request(&prompt.to_string())?;
-
-            tokio::spawn(async move {
+            requests.spawn(async move {
                 let result = async {
-                    let res = client.execute(req).await.map_err(|e| format!("Failed to execute request: {}", e))?;
-                    let response = match res.json::<OpenAIEmbeddingResponse>().await {
-                        Ok(response) => response,
-                        Err(e) => {
-                            return Err(format!("Failed to parse response: {}", e));
-                        }
-                    };
-                    Ok(response)
+                    let res =
+                        execute(&client, retry, req).await.map_err(|e| format!("Failed to execute request: {}", e))?;
+                    let res = check_status(res).await.map_err(|e| e.to_string())?;
+                    res.json::<OpenAIEmbeddingResponse>().await.map_err(|e| format!("Failed to parse response: {}", e))
                 }
                 .await;
-
-                // Send back the result (success or error) via the channel.
-                if let Err(e) = send_with_exponential_backoff(&sender, (i, result)).await {
-                    // Log the error or take appropriate action
-                    log::error!("Error sending message: {}", e);
-                }
+                (i, result)
             });
         }
 
-        drop(sender);
+        while let Some(joined) = requests.join_next().await {
+            let (i, result) = crate::task::joined(joined)?;
+            embeddings[i] = result.map_err(|e| OrcaError::Embedding {
+                provider: "OpenAI".to_string(),
+                message: format!("Failed to generate embedding index {}: {}", i, e),
+            })?;
+        }
 
-        while let Some((i, result)) = receiver.recv().await {
-            match result {
-                Ok(response) => {
-                    embeddings[i] = response;
-                }
-                Err(e) => {
-                    return Err(anyhow::anyhow!("Failed to generate embedding index {}: {}", i, e));
-                }
-            }
+        Embeddings::try_from(embeddings)
+    }
+
+    fn dimensions(&self) -> usize {
+        match (self.embedding_dimensions, self.emedding_model.as_str()) {
+            (Some(dimensions), _) => dimensions,
+            (None, "text-embedding-3-large") => 3072,
+            (None, _) => 1536,
         }
+    }
+}
 
-        Ok(EmbeddingResponse::OpenAI(embeddings))
+#[async_trait::async_trait]
+impl ImageGenerator for OpenAI {
+    async fn generate_image(&self, prompt: Box<dyn Prompt>) -> Result<Vec<GeneratedImage>> {
+        let req = self.generate_image_request(&prompt.to_string())?;
+        let res = check_status(execute(&self.client, self.retry, req).await?).await?;
+        Vec::<GeneratedImage>::try_from(res.json::<OpenAIImageResponse>().await?)
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::llm::secrets::ScopedSecrets;
     use crate::prompt::TemplateEngine;
     use crate::template;
     use crate::{prompt, prompts};
@@ -461,12 +1309,350 @@ mod test {
         assert!(response.to_string().starts_with("{"));
     }
 
+    #[test]
+    fn test_stream_events() {
+        let body = concat!(
+            "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"Orcas \"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"are dolphins.\"}}]}\n\n",
+            "data: [DONE]\n\n",
+        );
+        // Chunks end anywhere, in the middle of events or of characters.
+        let mut parser = EventParser::default();
+        let events = body.as_bytes().chunks(7).flat_map(|chunk| parser.push(chunk)).collect::<Vec<_>>();
+        let texts = events.iter().map(|data| event_text(data).unwrap()).collect::<Vec<_>>();
+        assert_eq!(
+            texts,
+            vec![
+                Some(String::new()),
+                Some("Orcas ".to_string()),
+                Some("are dolphins.".to_string()),
+                None
+            ]
+        );
+        let event = "data: {\"choices\":[{\"delta\":{\"content\":\"Épaulard\"}}]}\n".as_bytes();
+        let (start, end) = event.split_at(event.len() - 14);
+        assert!(parser.push(start).is_empty());
+        let events = parser.push(end);
+        assert_eq!(event_text(&events[0]).unwrap().as_deref(), Some("Épaulard"));
+
+        let error = event_text("{\"error\":{\"message\":\"Rate limit reached\"}}").unwrap_err();
+        assert_eq!(error.to_string(), "OpenAI request failed: Rate limit reached");
+    }
+
+    #[test]
+    fn test_prefix_cache_key() {
+        let question = |q: &str| {
+            vec![
+                Message::new(Role::System, "You answer questions about Orca.").with_cache_control(),
+                Message::new(Role::User, q),
+            ]
+        };
+        let key = prefix_cache_key(&question("What is Orca?"));
+        // The key is the same for every build.
+        assert_eq!(key.as_deref(), Some("orca-d46d2af0a2c16627"));
+        assert_eq!(key, prefix_cache_key(&question("Who wrote Orca?")));
+        assert_eq!(prefix_cache_key(&[Message::new(Role::User, "Hi")]), None);
+
+        // Names and tool calls are part of the prefix.
+        let named = vec![Message::new(Role::System, "You answer questions about Orca.")
+            .with_name("guide")
+            .with_cache_control()];
+        assert_ne!(prefix_cache_key(&named), key);
+        let call = |arguments: &str| {
+            let call = ToolCall::new("call_1", "search", arguments);
+            vec![Message::new(Role::Assistant, "").with_tool_calls(vec![call]).with_cache_control()]
+        };
+        assert_ne!(
+            prefix_cache_key(&call(r#"{"pod": "J"}"#)),
+            prefix_cache_key(&call(r#"{"pod": "K"}"#))
+        );
+    }
+
+    #[test]
+    fn test_secrets() {
+        let secrets = StaticSecrets::new().with_secret("acme/OPENAI_API_KEY", "sk-acme");
+        let client = OpenAI::new().with_secrets(ScopedSecrets::new(secrets.clone(), "acme"));
+        let messages = [Message::new(Role::User, "Hi")];
+        assert_eq!(
+            client.generate_request(&messages).unwrap().headers()["Authorization"],
+            "Bearer sk-acme"
+        );
+        secrets.rotate("acme/OPENAI_API_KEY", "sk-rotated");
+        assert_eq!(
+            client.generate_request(&messages).unwrap().headers()["Authorization"],
+            "Bearer sk-rotated"
+        );
+
+        let client = OpenAI::new().with_secrets(ScopedSecrets::new(secrets, "globex"));
+        assert!(client.generate_request(&messages).is_err());
+        let client = OpenAI::new().with_api_key("sk-globex");
+        assert_eq!(
+            client.generate_embedding_request("Hi").unwrap().headers()["Authorization"],
+            "Bearer sk-globex"
+        );
+    }
+
+    #[test]
+    fn test_azure() {
+        let client = OpenAI::azure("https://orcas.openai.azure.com/", "gpt-4", "2024-02-01")
+            .with_secrets(StaticSecrets::new().with_secret(AZURE_OPENAI_API_KEY, "azure-key"));
+        let request = client.generate_request(&[Message::new(Role::User, "Hi")]).unwrap();
+        assert_eq!(
+            request.url().as_str(),
+            "https://orcas.openai.azure.com/openai/deployments/gpt-4/chat/completions?api-version=2024-02-01"
+        );
+        assert_eq!(request.headers()["api-key"], "azure-key");
+        assert!(!request.headers().contains_key("Authorization"));
+        let request = client.generate_embedding_request("Hi").unwrap();
+        assert_eq!(
+            request.url().path(),
+            "/openai/deployments/text-embedding-ada-002/embeddings"
+        );
+        assert_eq!(client.config()["model"], "gpt-4");
+
+        // The OpenAI key is not sent to Azure.
+        let client = OpenAI::azure("https://orcas.openai.azure.com", "gpt-4", "2024-02-01")
+            .with_secrets(StaticSecrets::new().with_secret(OPENAI_API_KEY, "sk-openai"));
+        assert!(client.generate_image_request("Orcas").is_err());
+    }
+
+    #[test]
+    fn test_compatible_server() {
+        let client = OpenAI::new().with_url("http://localhost:8080/v1/").with_secrets(StaticSecrets::new());
+        assert!(client.generate_request(&[Message::new(Role::User, "Hi")]).is_err());
+        let client = client.with_api_key_optional();
+        let request = client.generate_request(&[Message::new(Role::User, "Hi")]).unwrap();
+        assert_eq!(request.url().as_str(), "http://localhost:8080/v1/chat/completions");
+        assert!(!request.headers().contains_key("Authorization"));
+        let request = client.generate_embedding_request("Hi").unwrap();
+        assert_eq!(request.url().as_str(), "http://localhost:8080/v1/embeddings");
+        assert_eq!(client.config()["url"], "http://localhost:8080/v1");
+        let request = OpenAI::new().with_api_key("sk-openai").generate_embedding_request("Hi").unwrap();
+        assert_eq!(request.url().as_str(), "https://api.openai.com/v1/embeddings");
+
+        // Responses without usage, system fingerprint or finish reason, as sent by llama.cpp.
+        let response: OpenAIResponse = serde_json::from_value(json!({
+            "model": "llama-2-7b-chat",
+            "choices": [{"message": {"role": "assistant", "content": "Orcas are dolphins."}, "finish_reason": null}]
+        }))
+        .unwrap();
+        let OpenAIResponse::Response(response) = response else {
+            panic!("Expected a response");
+        };
+        assert_eq!(response.to_string(), "Orcas are dolphins.");
+        assert_eq!(response.total_tokens(), 0);
+        let response: OpenAIEmbeddingResponse =
+            serde_json::from_value(json!({"data": [{"embedding": [0.1, 0.2]}]})).unwrap();
+        assert_eq!(response.to_vec(), vec![0.1, 0.2]);
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Server rate limiting the first request, failing the second and answering the third.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/v1/embeddings", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let responses = [
+                "HTTP/1.1 429 Too Many Requests\r\nretry-after-ms: 100\r\ncontent-length: 0\r\n\r\n",
+                "HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\n\r\n",
+                "HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\n{}",
+            ];
+            for response in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buffer = [0; 4096];
+                let _ = socket.read(&mut buffer).await.unwrap();
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        let client = Client::new();
+        let request = || client.post(&url).body("{}").build().unwrap();
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(50),
+        };
+        let start = std::time::Instant::now();
+        let response = execute(&client, policy, request()).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        // The wait asked for by the rate limit is longer than the maximum backoff, and still honored.
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        server.await.unwrap();
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+        headers.insert("retry-after", "2".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(2)));
+        headers.insert("retry-after", "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap());
+        assert_eq!(retry_after(&headers), None);
+        assert!(is_transient(reqwest::StatusCode::BAD_GATEWAY));
+        assert!(!is_transient(reqwest::StatusCode::BAD_REQUEST));
+    }
+
+    #[tokio::test]
+    async fn test_stream_dropped() {
+        use futures::StreamExt;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Server sending a single piece, then keeping the response open until the client disconnects.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/v1", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buffer = [0; 4096];
+            let _ = socket.read(&mut buffer).await.unwrap();
+            let event = "data: {\"choices\":[{\"delta\":{\"content\":\"Orcas\"}}]}\n\n";
+            let head = "HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\ntransfer-encoding: chunked\r\n\r\n";
+            let response = format!("{}{:x}\r\n{}\r\n", head, event.len(), event);
+            socket.write_all(response.as_bytes()).await.unwrap();
+            while socket.read(&mut buffer).await.map_or(false, |read| read > 0) {}
+        });
+
+        let client = OpenAI::new().with_url(&url).with_api_key("sk-test");
+        let mut stream = client.generate_stream(prompt!("Tell me about orcas")).await.unwrap();
+        assert_eq!(stream.next().await.unwrap().unwrap(), "Orcas");
+        // Dropping the stream closes the connection rather than leaving a task reading it.
+        drop(stream);
+        tokio::time::timeout(Duration::from_secs(5), server).await.unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_tool_call() {
+        let search = Tool::new(
+            "search",
+            "Search the sightings of orcas",
+            json!({"type": "object", "properties": {"pod": {"type": "string"}}}),
+        );
+        let client = OpenAI::new()
+            .with_secrets(StaticSecrets::new().with_secret(OPENAI_API_KEY, "sk-openai"))
+            .with_tool(search)
+            .with_tool_choice(ToolChoice::Function("search".to_string()));
+        let request = client.generate_request(&[Message::new(Role::User, "Where is J-pod?")]).unwrap();
+        let body: JsonValue = serde_json::from_slice(request.body().unwrap().as_bytes().unwrap()).unwrap();
+        assert_eq!(body["tools"][0]["type"], "function");
+        assert_eq!(body["tools"][0]["function"]["name"], "search");
+        assert_eq!(
+            body["tool_choice"],
+            json!({"type": "function", "function": {"name": "search"}})
+        );
+        let request = OpenAI::new()
+            .with_secrets(StaticSecrets::new().with_secret(OPENAI_API_KEY, "sk-openai"))
+            .generate_request(&[Message::new(Role::User, "Hi")])
+            .unwrap();
+        let body: JsonValue = serde_json::from_slice(request.body().unwrap().as_bytes().unwrap()).unwrap();
+        assert!(body.get("tools").is_none() && body.get("tool_choice").is_none());
+
+        let response: Response = serde_json::from_value(json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 1700000000,
+            "model": "gpt-3.5-turbo",
+            "usage": {"prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15},
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": {"name": "search", "arguments": "{\"pod\": \"J\"}"}
+                    }]
+                },
+                "finish_reason": "tool_calls"
+            }]
+        }))
+        .unwrap();
+        assert_eq!(response.to_string(), "");
+        let calls = response.tool_calls();
+        assert_eq!(calls, vec![ToolCall::new("call_1", "search", r#"{"pod": "J"}"#)]);
+        assert_eq!(calls[0].arguments["pod"], "J");
+        let response = LLMResponse::ToolCall {
+            calls,
+            response: Box::new(response.into()),
+        };
+        assert_eq!(response.tool_calls()[0].name, "search");
+    }
+
+    #[test]
+    fn test_image_message() {
+        let message = Message::new(Role::User, "What is in this image?")
+            .with_image(Image::new("https://example.com/cat.png").with_detail("low"));
+        let json = serde_json::to_value(OpenAIMessage::from(&message)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "role": "user",
+                "content": [
+                    {"type": "text", "text": "What is in this image?"},
+                    {"type": "image_url", "image_url": {"url": "https://example.com/cat.png", "detail": "low"}}
+                ]
+            })
+        );
+
+        let json = serde_json::to_value(OpenAIMessage::from(&Message::new(Role::User, "Hi"))).unwrap();
+        assert_eq!(json, serde_json::json!({"role": "user", "content": "Hi"}));
+    }
+
+    #[test]
+    fn test_named_message() {
+        let message = Message::new(Role::User, "Hi").with_name("alice").with_id("msg-1").with_timestamp(1000);
+        let json = serde_json::to_value(OpenAIMessage::from(&message)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"role": "user", "name": "alice", "content": "Hi"})
+        );
+    }
+
+    #[test]
+    fn test_tool_messages() {
+        let call = ToolCall::new("call_1", "search", r#"{"pod": "J"}"#);
+        let message = Message::new(Role::Assistant, "").with_tool_calls(vec![call.clone()]);
+        let json = serde_json::to_value(OpenAIMessage::from(&message)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "role": "assistant",
+                "content": "",
+                "tool_calls": [{
+                    "id": "call_1",
+                    "type": "function",
+                    "function": {"name": "search", "arguments": "{\"pod\":\"J\"}"}
+                }]
+            })
+        );
+
+        let message = Message::tool_output(&call, "Orcas were seen near Seattle.");
+        let json = serde_json::to_value(OpenAIMessage::from(&message)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"role": "tool", "content": "Orcas were seen near Seattle.", "tool_call_id": "call_1"})
+        );
+    }
+
+    #[test]
+    fn test_image_response() {
+        let response: OpenAIImageResponse = serde_json::from_value(serde_json::json!({
+            "created": 1700000000,
+            "data": [{"b64_json": "iVBORw==", "revised_prompt": "A red lighthouse at dawn"}]
+        }))
+        .unwrap();
+        let images = Vec::<GeneratedImage>::try_from(response).unwrap();
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].bytes, vec![0x89, b'P', b'N', b'G']);
+        assert_eq!(images[0].mime_type, "image/png");
+        assert_eq!(images[0].revised_prompt.as_deref(), Some("A red lighthouse at dawn"));
+    }
+
     #[tokio::test]
     async fn test_embedding() {
         let client = OpenAI::new();
         let content = prompt!("This is a test");
         let res = client.generate_embedding(content).await.unwrap();
-        assert!(res.to_vec2().unwrap().len() > 0);
+        assert!(!res.is_empty());
     }
 
     #[tokio::test]
@@ -474,6 +1660,6 @@ mod test {
         let client = OpenAI::new();
         let content = prompts!("This is a test", "This is another test", "This is a third test");
         let res = client.generate_embeddings(content).await.unwrap();
-        assert!(res.to_vec2().unwrap().len() > 0);
+        assert!(!res.is_empty());
     }
 }
@@ -0,0 +1,279 @@
+//! Detection of personally identifiable information in prompts.
+//!
+//! A `PiiGuard` is a middleware layer that scans the prompts sent to an LLM for PII, such as email
+//! addresses, phone numbers or card numbers, and redacts it or blocks the request before it reaches the
+//! provider. PII is found by regular expressions, the defaults of which can be replaced or extended, and
+//! optionally by an entity extractor such as the `Ner` model, for names and places that no pattern can
+//! catch. Put the guard outermost in an `LLMStack`, so that caches and logs below it only see redacted
+//! prompts:
+//!
+//! ```no_run
+//! use orca_core::llm::middleware::{LLMStack, Logging};
+//! use orca_core::llm::openai::OpenAI;
+//! use orca_core::llm::pii::PiiGuard;
+//!
+//! let llm = LLMStack::new(OpenAI::new()).layer(Logging).layer(PiiGuard::new());
+//! ```
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use regex::Regex;
+
+use super::middleware::{GuardrailViolation, Layer, Next};
+use super::{LLMResponse, RequestMetadata};
+use crate::prompt::Prompt;
+use crate::record::enrich::EntityExtractor;
+
+/// Patterns of the PII detected by default, by label.
+const DEFAULT_PATTERNS: &[(&str, &str)] = &[
+    ("EMAIL", r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}"),
+    ("CREDIT_CARD", r"\b\d(?:[ -]?\d){12,15}\b"),
+    ("SSN", r"\b\d{3}-\d{2}-\d{4}\b"),
+    ("PHONE", r"(?:\+\d{1,3}[ .-]?)?\(?\b\d{3}\)?[ .-]?\d{3}[ .-]?\d{4}\b"),
+    ("IP_ADDRESS", r"\b(?:\d{1,3}\.){3}\d{1,3}\b"),
+];
+
+/// What a guard does with prompts containing PII.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PiiAction {
+    /// Replace the PII with its label, e.g. `[EMAIL]`, and send the prompt.
+    #[default]
+    Redact,
+
+    /// Fail the request with a `GuardrailViolation`.
+    Block,
+}
+
+/// PII found in a text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PiiMatch {
+    /// Label of the PII, e.g. `EMAIL`, or the label of the entity.
+    pub label: String,
+
+    /// Byte offsets of the PII in the text.
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Layer redacting or blocking the prompts containing PII.
+#[derive(Clone)]
+pub struct PiiGuard {
+    /// Patterns of the PII, by label.
+    patterns: Vec<(String, Regex)>,
+
+    /// Extractor of the entities that are PII, with their labels, all entities if empty.
+    extractor: Option<(Arc<dyn EntityExtractor>, Vec<String>)>,
+
+    /// What to do with prompts containing PII.
+    action: PiiAction,
+}
+
+impl Default for PiiGuard {
+    fn default() -> Self {
+        let patterns = DEFAULT_PATTERNS
+            .iter()
+            .map(|(label, pattern)| {
+                (
+                    label.to_string(),
+                    Regex::new(pattern).expect("default PII patterns are valid"),
+                )
+            })
+            .collect();
+        PiiGuard {
+            patterns,
+            extractor: None,
+            action: PiiAction::Redact,
+        }
+    }
+}
+
+impl PiiGuard {
+    /// Create a guard redacting email addresses, card numbers, US social security numbers, phone numbers
+    /// and IP addresses.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::llm::pii::PiiGuard;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let guard = PiiGuard::new().with_pattern("EMPLOYEE_ID", r"\bE-\d{6}\b").unwrap();
+    /// let redacted = guard.redact("Mail jane@example.com about E-123456").await.unwrap();
+    /// assert_eq!(redacted, "Mail [EMAIL] about [EMPLOYEE_ID]");
+    /// # }
+    /// ```
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a guard without the default patterns, detecting only the PII configured with `with_pattern`
+    /// and `with_extractor`.
+    pub fn empty() -> Self {
+        PiiGuard {
+            patterns: Vec::new(),
+            ..Self::default()
+        }
+    }
+
+    /// Detect the text matching a regular expression as PII with the given label.
+    pub fn with_pattern(mut self, label: &str, pattern: &str) -> Result<Self> {
+        self.patterns.push((label.to_string(), Regex::new(pattern)?));
+        Ok(self)
+    }
+
+    /// Stop detecting the PII with the given label, e.g. `IP_ADDRESS`.
+    pub fn without_pattern(mut self, label: &str) -> Self {
+        self.patterns.retain(|(pattern, _)| pattern != label);
+        self
+    }
+
+    /// Also detect the entities found by an extractor, e.g. a `Ner` model, with one of the given labels,
+    /// e.g. `person` and `location`, or with any label if none are given. Every occurrence of an entity is redacted.
+    pub fn with_extractor<X: EntityExtractor + 'static>(mut self, extractor: X, labels: &[&str]) -> Self {
+        let labels = labels.iter().map(|label| label.to_string()).collect();
+        self.extractor = Some((Arc::new(extractor), labels));
+        self
+    }
+
+    /// Fail the requests whose prompt contains PII instead of redacting it.
+    pub fn blocking(mut self) -> Self {
+        self.action = PiiAction::Block;
+        self
+    }
+
+    /// Find the PII in a text, ordered by position, without overlaps: of overlapping matches, the one
+    /// starting first, then the longest, is kept.
+    pub async fn scan(&self, text: &str) -> Result<Vec<PiiMatch>> {
+        let mut matches = Vec::new();
+        for (label, pattern) in &self.patterns {
+            matches.extend(pattern.find_iter(text).map(|found| PiiMatch {
+                label: label.clone(),
+                start: found.start(),
+                end: found.end(),
+            }));
+        }
+        if let Some((extractor, labels)) = &self.extractor {
+            let entities = extractor.extract(text).await?;
+            let entities = entities.iter().filter(|entity| labels.is_empty() || labels.contains(&entity.label));
+            for entity in entities.filter(|entity| !entity.text.trim().is_empty()) {
+                matches.extend(text.match_indices(&entity.text).map(|(start, found)| PiiMatch {
+                    label: entity.label.clone(),
+                    start,
+                    end: start + found.len(),
+                }));
+            }
+        }
+        matches.sort_by(|a, b| a.start.cmp(&b.start).then(b.end.cmp(&a.end)));
+        let mut kept: Vec<PiiMatch> = Vec::with_capacity(matches.len());
+        for found in matches {
+            if kept.last().map_or(true, |last| found.start >= last.end) {
+                kept.push(found);
+            }
+        }
+        Ok(kept)
+    }
+
+    /// Replace the PII in a text with its label in brackets.
+    pub async fn redact(&self, text: &str) -> Result<String> {
+        let mut redacted = String::with_capacity(text.len());
+        let mut end = 0;
+        for found in self.scan(text).await? {
+            redacted.push_str(&text[end..found.start]);
+            redacted.push_str(&format!("[{}]", found.label));
+            end = found.end;
+        }
+        redacted.push_str(&text[end..]);
+        Ok(redacted)
+    }
+
+    /// Fail if a text contains PII, naming the labels found.
+    async fn check(&self, text: &str) -> Result<()> {
+        let mut labels = self.scan(text).await?.into_iter().map(|found| found.label).collect::<Vec<_>>();
+        if labels.is_empty() {
+            return Ok(());
+        }
+        labels.sort();
+        labels.dedup();
+        Err(GuardrailViolation {
+            input: true,
+            reason: format!("The prompt contains PII: {}", labels.join(", ")),
+        }
+        .into())
+    }
+}
+
+#[async_trait::async_trait]
+impl Layer for PiiGuard {
+    async fn call(&self, prompt: Box<dyn Prompt>, metadata: &RequestMetadata, next: Next<'_>) -> Result<LLMResponse> {
+        // Chat prompts are handled message by message, to keep their roles.
+        let Ok(mut chat) = prompt.to_chat() else {
+            let text = prompt.to_string();
+            return match self.action {
+                PiiAction::Redact => next.run(Box::new(self.redact(&text).await?), metadata).await,
+                PiiAction::Block => {
+                    self.check(&text).await?;
+                    next.run(prompt, metadata).await
+                }
+            };
+        };
+        for message in chat.0.iter_mut() {
+            match self.action {
+                PiiAction::Redact => message.content = self.redact(&message.content).await?,
+                PiiAction::Block => self.check(&message.content).await?,
+            }
+        }
+        next.run(Box::new(chat), metadata).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::llm::middleware::LLMStack;
+    use crate::llm::LLM;
+    use crate::prompt::chat::{ChatPrompt, Message, Role};
+    use crate::record::enrich::Entity;
+    use crate::testing::EchoLLM;
+
+    /// Extractor finding a fixed person.
+    struct Names;
+
+    #[async_trait::async_trait]
+    impl EntityExtractor for Names {
+        async fn extract(&self, _text: &str) -> Result<Vec<Entity>> {
+            Ok(vec![
+                Entity::new("Jane Doe", "person"),
+                Entity::new("Seattle", "location"),
+            ])
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pii_guard() {
+        let guard = PiiGuard::new().with_extractor(Names, &["person"]);
+        let text = "Jane Doe (jane@example.com, 555-123-4567) paid with 4111 1111 1111 1111 in Seattle.";
+        assert_eq!(
+            guard.redact(text).await.unwrap(),
+            "[person] ([EMAIL], [PHONE]) paid with [CREDIT_CARD] in Seattle."
+        );
+        assert!(guard.scan("Orcas live in pods.").await.unwrap().is_empty());
+        let guard = PiiGuard::new().without_pattern("PHONE");
+        assert_eq!(guard.redact("Call 555-123-4567").await.unwrap(), "Call 555-123-4567");
+
+        // Chat messages are redacted one by one.
+        let stack = LLMStack::new(EchoLLM::new()).layer(PiiGuard::new());
+        let chat = ChatPrompt(vec![
+            Message::new(Role::System, "Never repeat emails."),
+            Message::new(Role::User, "My email is jane@example.com"),
+        ]);
+        let response = stack.generate(Box::new(chat)).await.unwrap();
+        assert!(response.to_string().contains("My email is [EMAIL]"));
+
+        let stack = LLMStack::new(EchoLLM::new()).layer(PiiGuard::new().blocking());
+        let error = stack.generate(Box::new("My SSN is 123-45-6789".to_string())).await.unwrap_err();
+        let violation = error.downcast_ref::<GuardrailViolation>().unwrap();
+        assert_eq!(violation.reason, "The prompt contains PII: SSN");
+        assert!(stack.generate(Box::new("Orcas live in pods.".to_string())).await.is_ok());
+    }
+}
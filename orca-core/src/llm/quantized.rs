@@ -9,16 +9,23 @@ use tokenizers::Tokenizer;
 use candle_core::quantized::{ggml_file, gguf_file};
 use candle_core::{Device, Tensor};
 use candle_transformers::generation::LogitsProcessor;
+use hf_hub::Repo;
 
 use anyhow::Result;
 use candle_transformers::models::quantized_llama as model;
 use model::ModelWeights;
+use serde_json::{json, Value as JsonValue};
+use std::sync::Arc;
+use tokio::sync::mpsc;
 
 use crate::prompt::chat::{ChatPrompt, Role};
 
 use crate::prompt::Prompt;
 
-use super::{LLMResponse, LLM};
+use super::sharded::{ModelDevice, ShardedWeights};
+use super::source::{verify_sha256, ModelSource};
+use super::stop::{StopConditions, StopCriteria};
+use super::{channel_stream, LLMResponse, LoadMode, TokenStream, LLM};
 
 #[derive(Clone, Debug, Copy)]
 pub enum Model {
@@ -52,10 +59,38 @@ impl Model {
     }
 }
 
+/// Loaded model weights, on the CPU or split across devices.
+#[derive(Clone)]
+enum Weights {
+    Cpu(ModelWeights),
+    Sharded(ShardedWeights),
+}
+
+impl Weights {
+    /// Device the input tokens must be on.
+    fn input_device(&self) -> Device {
+        match self {
+            Weights::Cpu(_) => Device::Cpu,
+            Weights::Sharded(weights) => weights.input_device().clone(),
+        }
+    }
+
+    /// Logits of the next token, on the CPU.
+    fn forward(&mut self, x: &Tensor, index_pos: usize) -> Result<Tensor> {
+        match self {
+            Weights::Cpu(weights) => Ok(weights.forward(x, index_pos)?),
+            Weights::Sharded(weights) => Ok(weights.forward(x, index_pos)?.to_device(&Device::Cpu)?),
+        }
+    }
+}
+
+/// Seed used by the test profile of the models.
+pub const TEST_SEED: u64 = 42;
+
 #[derive(Clone)]
 pub struct Quantized {
     /// The loaded model weights
-    model: Option<ModelWeights>,
+    model: Option<Weights>,
 
     /// The path to read the model from.
     model_path: Option<std::path::PathBuf>,
@@ -75,7 +110,7 @@ pub struct Quantized {
     /// The seed to use when generating random samples.
     seed: u64,
 
-    /// Enable tracing (generates a trace-timestamp.json file).
+    /// Trace the computations of the model to their own Chrome trace file (trace-timestamp.json).
     tracing: bool,
 
     /// Penalty to be applied for repeating tokens, 1. means no penalty.
@@ -89,6 +124,30 @@ pub struct Quantized {
 
     /// Group-Query Attention, use 8 for the 70B version of LLaMAv2.
     gqa: Option<usize>,
+
+    /// Where the model and tokenizer files are fetched from, the global `ModelSource` by default.
+    source: Option<ModelSource>,
+
+    /// Expected SHA-256 checksum of the model file.
+    sha256: Option<String>,
+
+    /// Devices the layers of the model are split across, the CPU only by default.
+    device_map: Vec<ModelDevice>,
+
+    /// Number of layers placed on each device of the device map, evenly split by default.
+    device_layers: Vec<usize>,
+
+    /// Number of threads computing the model, the candle default (all cores) unless set.
+    threads: Option<usize>,
+
+    /// Thread pool of the model, built with the model when the number of threads is set.
+    pool: Option<Arc<rayon::ThreadPool>>,
+
+    /// How the model file is loaded.
+    load_mode: LoadMode,
+
+    /// Criteria checked after each sampled token to stop generation early.
+    stop: StopConditions,
     //// Use to give context to the prompt for a chat interaction.
     // chat_context: Option<String>,
 }
@@ -108,6 +167,14 @@ impl Default for Quantized {
             repeat_last_n: 1,
             which: Model::L7b,
             gqa: None,
+            source: None,
+            sha256: None,
+            device_map: Vec::new(),
+            device_layers: Vec::new(),
+            threads: None,
+            pool: None,
+            load_mode: LoadMode::Read,
+            stop: StopConditions::new(),
             // chat_context: None,
         }
     }
@@ -128,23 +195,124 @@ impl Quantized {
         self
     }
 
+    /// Samples the most likely token at each step, so that the same prompt always gets the same
+    /// completion on a given machine.
+    pub fn greedy(mut self) -> Self {
+        self.temperature = 0.;
+        self.top_p = None;
+        self
+    }
+
+    /// Configures the model for tests pinning its generated text, e.g. with
+    /// [`Snapshots`](super::snapshot::Snapshots): greedy sampling with the seed `TEST_SEED`, no repeat
+    /// penalty, and a single thread so that floating point sums are computed in the same order on every
+    /// machine.
+    pub fn test_profile(self) -> Self {
+        self.greedy().with_seed(TEST_SEED).with_repeat_penalty(1., 1).with_threads(1)
+    }
+
     pub fn with_seed(mut self, seed: u64) -> Self {
         self.seed = seed;
         self
     }
 
-    fn tokenizer(&self) -> anyhow::Result<Tokenizer> {
+    /// Sets where the model and tokenizer files are fetched from, instead of the global `ModelSource`.
+    pub fn with_source(mut self, source: ModelSource) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// Pins the model file, downloaded or loaded from a path, to its SHA-256 checksum in hexadecimal:
+    /// building the model fails if the file differs.
+    pub fn with_sha256(mut self, sha256: &str) -> Self {
+        self.sha256 = Some(sha256.to_string());
+        self
+    }
+
+    /// Splits the layers of the model across devices, in order, e.g. `&[Cuda(0), Cuda(1), Cpu]` to run a
+    /// 70B model on two GPUs with the remaining layers in CPU RAM. The layers are split evenly unless
+    /// set with `with_device_layers`. Only GGUF models can be split.
+    pub fn with_device_map(mut self, devices: &[ModelDevice]) -> Self {
+        self.device_map = devices.to_vec();
+        self
+    }
+
+    /// Sets the number of layers placed on each device of the device map, the remaining layers going to
+    /// the last device, e.g. `&[40, 30]` for the 80 layers of a 70B model on `&[Cuda(0), Cuda(1), Cpu]`.
+    pub fn with_device_layers(mut self, layers: &[usize]) -> Self {
+        self.device_layers = layers.to_vec();
+        self
+    }
+
+    /// Sets the number of threads computing the model, e.g. to partition the cores of a server between
+    /// the models of several pipelines. The model runs on its own thread pool of that size, built with
+    /// the model, instead of the global one using all cores.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Sets how the model file is loaded, read into memory by default.
+    pub fn with_load_mode(mut self, load_mode: LoadMode) -> Self {
+        self.load_mode = load_mode;
+        self
+    }
+
+    /// Sets the sampling temperature, use 0 for greedy sampling.
+    pub fn with_temperature(mut self, temperature: f64) -> Self {
+        self.temperature = temperature;
+        self
+    }
+
+    /// Sets the nucleus sampling probability cutoff.
+    pub fn with_top_p(mut self, top_p: f64) -> Self {
+        self.top_p = Some(top_p);
+        self
+    }
+
+    /// Sets the penalty applied to tokens repeated in the last `last_n` tokens, 1. means no penalty.
+    pub fn with_repeat_penalty(mut self, penalty: f32, last_n: usize) -> Self {
+        self.repeat_penalty = penalty;
+        self.repeat_last_n = last_n;
+        self
+    }
+
+    /// Adds a criteria checked after each sampled token, stopping generation before `sample_len`
+    /// tokens when it is met.
+    pub fn with_stop_criteria<S: StopCriteria + 'static>(mut self, criteria: S) -> Self {
+        self.stop = self.stop.with(criteria);
+        self
+    }
+
+    /// Counts the tokens of a text with the tokenizer of the model.
+    pub async fn count_tokens(&self, text: &str) -> Result<usize> {
+        let tokens = self.tokenizer().await?.encode(text, false).map_err(anyhow::Error::msg)?;
+        Ok(tokens.get_ids().len())
+    }
+
+    /// The model and sampling parameters, as reported by benchmarks.
+    pub(crate) fn sample_params(&self) -> serde_json::Map<String, serde_json::Value> {
+        let mut params = serde_json::Map::new();
+        params.insert("model".to_string(), format!("{:?}", self.which).into());
+        params.insert("sample_len".to_string(), self.sample_len.into());
+        params.insert("temperature".to_string(), self.temperature.into());
+        params.insert("top_p".to_string(), self.top_p.into());
+        params.insert("repeat_penalty".to_string(), self.repeat_penalty.into());
+        params.insert("repeat_last_n".to_string(), self.repeat_last_n.into());
+        params
+    }
+
+    async fn tokenizer(&self) -> anyhow::Result<Tokenizer> {
         let tokenizer_path = match &self.tokenizer {
             Some(config) => std::path::PathBuf::from(config),
             None => {
-                let api = hf_hub::api::sync::Api::new()?;
                 let repo = if self.which.is_mistral() {
                     "mistralai/Mistral-7B-v0.1"
                 } else {
                     "hf-internal-testing/llama-tokenizer"
                 };
-                let api = api.model(repo.to_string());
-                api.get("tokenizer.json")?
+                let source = ModelSource::resolve(self.source.as_ref(), false);
+                source.get(&Repo::model(repo.to_string()), "tokenizer.json").await?
             }
         };
         Tokenizer::from_file(tokenizer_path).map_err(anyhow::Error::msg)
@@ -167,8 +335,8 @@ impl Quantized {
                 "mistral-7b-instruct-v0.1.Q4_K_S.gguf",
             ),
         };
-        let api = hf_hub::api::tokio::Api::new()?;
-        self.model_path = Some(api.model(repo.to_string()).get(filename).await?);
+        let source = ModelSource::resolve(self.source.as_ref(), false);
+        self.model_path = Some(source.get(&Repo::model(repo.to_string()), filename).await?);
         Ok(self)
     }
 
@@ -189,12 +357,30 @@ impl Quantized {
             return Err(anyhow::Error::msg("model path not set"));
         }
         let model_path = self.model_path.as_ref().unwrap();
-        let mut file = std::fs::File::open(model_path)?;
-        let start = std::time::Instant::now();
+        if let Some(sha256) = &self.sha256 {
+            verify_sha256(model_path, sha256)?;
+        }
+        let file = std::fs::File::open(model_path)?;
+        let weights = match self.load_mode {
+            // Safety: the file must not be modified while the model is loaded from the map.
+            LoadMode::Mmap => {
+                self.read_weights(&mut std::io::Cursor::new(&unsafe { memmap2::Mmap::map(&file)? }[..]))?
+            }
+            LoadMode::Read => self.read_weights(&mut std::io::BufReader::new(file))?,
+        };
+        self.model = Some(weights);
+        self.pool = self.threads.map(super::thread_pool).transpose()?;
+        log::info!("model built");
+        Ok(self)
+    }
 
-        self.model = match model_path.extension().and_then(|v| v.to_str()) {
+    /// Reads the model weights in the format given by the extension of the model file.
+    fn read_weights<R: std::io::Read + std::io::Seek>(&self, reader: &mut R) -> Result<Weights> {
+        let model_path = self.model_path.as_ref().ok_or(anyhow::Error::msg("model path not set"))?;
+        let start = std::time::Instant::now();
+        let weights = match model_path.extension().and_then(|v| v.to_str()) {
             Some("gguf") => {
-                let model = gguf_file::Content::read(&mut file)?;
+                let model = gguf_file::Content::read(reader)?;
                 let mut total_size_in_bytes = 0;
                 for (_, tensor) in model.tensor_infos.iter() {
                     let elem_count = tensor.shape.elem_count();
@@ -206,10 +392,22 @@ impl Quantized {
                     &format_size(total_size_in_bytes),
                     start.elapsed().as_secs_f32(),
                 );
-                Some(ModelWeights::from_gguf(model, &mut file, &Device::Cpu)?)
+                if self.device_map.is_empty() {
+                    Weights::Cpu(ModelWeights::from_gguf(model, reader, &Device::Cpu)?)
+                } else {
+                    Weights::Sharded(ShardedWeights::from_gguf(
+                        model,
+                        reader,
+                        &self.device_map,
+                        &self.device_layers,
+                    )?)
+                }
+            }
+            Some("ggml" | "bin") | Some(_) | None if !self.device_map.is_empty() => {
+                return Err(anyhow::Error::msg("only GGUF models can be split across devices"));
             }
             Some("ggml" | "bin") | Some(_) | None => {
-                let model = ggml_file::Content::read(&mut file, &Device::Cpu)?;
+                let model = ggml_file::Content::read(reader, &Device::Cpu)?;
                 let mut total_size_in_bytes = 0;
                 for (_, tensor) in model.tensors.iter() {
                     let elem_count = tensor.shape().elem_count();
@@ -232,17 +430,107 @@ impl Quantized {
                     | Model::L34bCode => 1,
                     Model::Mistral7b | Model::Mistral7bInstruct | Model::L70b | Model::L70bChat => 8,
                 };
-                Some(ModelWeights::from_ggml(model, self.gqa.unwrap_or(default_gqa))?)
+                Weights::Cpu(ModelWeights::from_ggml(model, self.gqa.unwrap_or(default_gqa))?)
             }
         };
-        log::info!("model built");
-        Ok(self)
+        Ok(weights)
+    }
+
+    /// Runs the function on the thread pool of the model, if it has one, in a `quantized.sample` span.
+    fn install<R: Send>(&self, f: impl FnOnce() -> R + Send) -> R {
+        super::run_traced(
+            self.pool.as_deref(),
+            self.tracing,
+            || tracing::info_span!("quantized.sample"),
+            f,
+        )
+    }
+
+    /// Generates a completion of the prompt tokens, passing the text added by each token to `emit` as it
+    /// is generated; generation stops early if `emit` returns false.
+    fn sample(
+        &self,
+        tokenizer: &Tokenizer,
+        prompt_tokens: &[u32],
+        temperature: Option<f64>,
+        mut emit: impl FnMut(&str) -> bool,
+    ) -> Result<String> {
+        let mut result = String::new();
+        let mut sent = 0;
+        let to_sample = self.sample_len.saturating_sub(1);
+        let mut all_tokens = vec![];
+        let mut logits_processor = LogitsProcessor::new(self.seed, temperature, self.top_p);
+
+        let mut model = self.model.clone().unwrap();
+        let device = model.input_device();
+
+        let start_prompt_processing = std::time::Instant::now();
+        let mut next_token = {
+            let input = Tensor::new(prompt_tokens, &device)?.unsqueeze(0)?;
+            let logits = model.forward(&input, 0)?;
+            let logits = logits.squeeze(0)?;
+            logits_processor.sample(&logits)?
+        };
+        let prompt_dt = start_prompt_processing.elapsed();
+        all_tokens.push(next_token);
+        get_token(next_token, tokenizer, &mut result);
+        if let Some(len) = self.stop.check(all_tokens.len(), &result) {
+            result.truncate(len);
+            emit_new(&result, &mut sent, &mut emit);
+            return Ok(result);
+        }
+        if !emit_new(&result, &mut sent, &mut emit) {
+            return Ok(result);
+        }
+
+        let eos_token = *tokenizer.get_vocab(true).get("</s>").unwrap();
+
+        let start_post_prompt = std::time::Instant::now();
+        for index in 0..to_sample {
+            let input = Tensor::new(&[next_token], &device)?.unsqueeze(0)?;
+            let logits = model.forward(&input, prompt_tokens.len() + index)?;
+            let logits = logits.squeeze(0)?;
+            let logits = if self.repeat_penalty == 1. {
+                logits
+            } else {
+                let start_at = all_tokens.len().saturating_sub(self.repeat_last_n);
+                candle_transformers::utils::apply_repeat_penalty(&logits, self.repeat_penalty, &all_tokens[start_at..])?
+            };
+            next_token = logits_processor.sample(&logits)?;
+            all_tokens.push(next_token);
+            get_token(next_token, tokenizer, &mut result);
+            if next_token == eos_token {
+                emit_new(&result, &mut sent, &mut emit);
+                break;
+            };
+            if let Some(len) = self.stop.check(all_tokens.len(), &result) {
+                result.truncate(len);
+                emit_new(&result, &mut sent, &mut emit);
+                break;
+            }
+            if !emit_new(&result, &mut sent, &mut emit) {
+                break;
+            }
+        }
+        let dt = start_post_prompt.elapsed();
+        log::info!(
+            "\n\n{:4} prompt tokens processed: {:.2} token/s",
+            prompt_tokens.len(),
+            prompt_tokens.len() as f64 / prompt_dt.as_secs_f64(),
+        );
+        log::info!(
+            "{:4} tokens generated: {:.2} token/s",
+            to_sample,
+            to_sample as f64 / dt.as_secs_f64(),
+        );
+
+        Ok(result)
     }
 
-    fn format_chat_prompt(chat_prompt: ChatPrompt) -> String {
+    pub(crate) fn format_chat_prompt(chat_prompt: ChatPrompt) -> String {
         let mut prompt = String::new();
         for message in chat_prompt.to_vec_ref() {
-            if message.role == Role::System || message.role == Role::User {
+            if message.role != Role::Assistant {
                 prompt.push_str(&format!("[INST] {} [/INST]", message.content));
             } else {
                 prompt.push_str(&message.content);
@@ -250,6 +538,56 @@ impl Quantized {
         }
         prompt
     }
+
+    /// Formats and tokenizes a prompt, returning the tokenizer, the prompt tokens that fit in the context
+    /// with the tokens to sample, and the sampling temperature.
+    async fn prepare(&self, prompt: Box<dyn Prompt>) -> Result<(Tokenizer, Vec<u32>, Option<f64>)> {
+        let temperature = if self.temperature == 0. {
+            None
+        } else {
+            Some(self.temperature)
+        };
+        let tokenizer = self.tokenizer().await?;
+        let prompt = if prompt.to_chat().is_err() {
+            let prompt = prompt.to_string();
+            if self.which.is_mistral() {
+                format!("[INST] {prompt} [/INST]")
+            } else {
+                prompt
+            }
+        } else {
+            Quantized::format_chat_prompt(prompt.to_chat()?)
+        };
+
+        log::debug!("prompt:\n{}", &prompt);
+        let tokens = tokenizer.encode(prompt, true).map_err(anyhow::Error::msg)?;
+        if log::log_enabled!(log::Level::Debug) {
+            for (token, id) in tokens.get_tokens().iter().zip(tokens.get_ids().iter()) {
+                let token = token.replace('▁', " ").replace("<0x0A>", "\n");
+                log::debug!("{id:7} -> '{token}'");
+            }
+        }
+
+        let prompt_tokens = tokens.get_ids().to_vec();
+        let to_sample = self.sample_len.saturating_sub(1);
+        let prompt_tokens = if prompt_tokens.len() + to_sample > model::MAX_SEQ_LEN - 10 {
+            let to_remove = prompt_tokens.len() + to_sample + 10 - model::MAX_SEQ_LEN;
+            prompt_tokens[prompt_tokens.len().saturating_sub(to_remove)..].to_vec()
+        } else {
+            prompt_tokens
+        };
+        Ok((tokenizer, prompt_tokens, temperature))
+    }
+}
+
+/// Pass the text added to the result since the last call to `emit`, returning whether to go on.
+fn emit_new(result: &str, sent: &mut usize, emit: &mut impl FnMut(&str) -> bool) -> bool {
+    if result.len() <= *sent {
+        return true;
+    }
+    let text = &result[*sent..];
+    *sent = result.len();
+    emit(text)
 }
 
 fn get_token(next_token: u32, tokenizer: &Tokenizer, result: &mut String) {
@@ -292,101 +630,38 @@ fn format_size(size_in_bytes: usize) -> String {
 #[async_trait::async_trait]
 impl LLM for Quantized {
     async fn generate(&self, prompt: Box<dyn Prompt>) -> Result<LLMResponse> {
-        use tracing_chrome::ChromeLayerBuilder;
-        use tracing_subscriber::prelude::*;
+        let (tokenizer, prompt_tokens, temperature) = self.prepare(prompt).await?;
+        let result = self.install(|| self.sample(&tokenizer, &prompt_tokens, temperature, |_| true))?;
 
-        let temperature = if self.temperature == 0. {
-            None
-        } else {
-            Some(self.temperature)
-        };
-        let _guard = if self.tracing {
-            let (chrome_layer, guard) = ChromeLayerBuilder::new().build();
-            tracing_subscriber::registry().with(chrome_layer).init();
-            Some(guard)
-        } else {
-            None
-        };
+        Ok(LLMResponse::Quantized(result))
+    }
 
-        let tokenizer = self.tokenizer()?;
-        let prompt = if prompt.to_chat().is_err() {
-            let prompt = prompt.to_string();
-            if self.which.is_mistral() {
-                format!("[INST] {prompt} [/INST]")
-            } else {
-                prompt
+    async fn generate_stream(&self, prompt: Box<dyn Prompt>) -> Result<TokenStream> {
+        let (tokenizer, prompt_tokens, temperature) = self.prepare(prompt).await?;
+        let llm = self.clone();
+        let (sender, receiver) = mpsc::channel(64);
+        tokio::task::spawn_blocking(move || {
+            let emit = |text: &str| sender.blocking_send(Ok(text.to_string())).is_ok();
+            if let Err(e) = llm.install(|| llm.sample(&tokenizer, &prompt_tokens, temperature, emit)) {
+                let _ = sender.blocking_send(Err(e));
             }
-        } else {
-            Quantized::format_chat_prompt(prompt.to_chat()?)
-        };
-
-        log::debug!("prompt:\n{}", &prompt);
-        let mut result = String::new();
-        let tokens = tokenizer.encode(prompt, true).map_err(anyhow::Error::msg)?;
-        if log::log_enabled!(log::Level::Debug) {
-            for (token, id) in tokens.get_tokens().iter().zip(tokens.get_ids().iter()) {
-                let token = token.replace('▁', " ").replace("<0x0A>", "\n");
-                log::debug!("{id:7} -> '{token}'");
-            }
-        }
-
-        let prompt_tokens = tokens.get_ids().to_vec();
-        let to_sample = self.sample_len.saturating_sub(1);
-        let prompt_tokens = if prompt_tokens.len() + to_sample > model::MAX_SEQ_LEN - 10 {
-            let to_remove = prompt_tokens.len() + to_sample + 10 - model::MAX_SEQ_LEN;
-            prompt_tokens[prompt_tokens.len().saturating_sub(to_remove)..].to_vec()
-        } else {
-            prompt_tokens
-        };
-        let mut all_tokens = vec![];
-        let mut logits_processor = LogitsProcessor::new(self.seed, temperature, self.top_p);
-
-        let mut model = self.model.clone().unwrap();
-
-        let start_prompt_processing = std::time::Instant::now();
-        let mut next_token = {
-            let input = Tensor::new(prompt_tokens.as_slice(), &Device::Cpu)?.unsqueeze(0)?;
-            let logits = model.forward(&input, 0)?;
-            let logits = logits.squeeze(0)?;
-            logits_processor.sample(&logits)?
-        };
-        let prompt_dt = start_prompt_processing.elapsed();
-        all_tokens.push(next_token);
-        get_token(next_token, &tokenizer, &mut result);
-
-        let eos_token = *tokenizer.get_vocab(true).get("</s>").unwrap();
+        });
+        Ok(channel_stream(receiver))
+    }
 
-        let start_post_prompt = std::time::Instant::now();
-        for index in 0..to_sample {
-            let input = Tensor::new(&[next_token], &Device::Cpu)?.unsqueeze(0)?;
-            let logits = model.forward(&input, prompt_tokens.len() + index)?;
-            let logits = logits.squeeze(0)?;
-            let logits = if self.repeat_penalty == 1. {
-                logits
-            } else {
-                let start_at = all_tokens.len().saturating_sub(self.repeat_last_n);
-                candle_transformers::utils::apply_repeat_penalty(&logits, self.repeat_penalty, &all_tokens[start_at..])?
-            };
-            next_token = logits_processor.sample(&logits)?;
-            all_tokens.push(next_token);
-            get_token(next_token, &tokenizer, &mut result);
-            if next_token == eos_token {
-                break;
-            };
-        }
-        let dt = start_post_prompt.elapsed();
-        log::info!(
-            "\n\n{:4} prompt tokens processed: {:.2} token/s",
-            prompt_tokens.len(),
-            prompt_tokens.len() as f64 / prompt_dt.as_secs_f64(),
-        );
-        log::info!(
-            "{:4} tokens generated: {:.2} token/s",
-            to_sample,
-            to_sample as f64 / dt.as_secs_f64(),
-        );
+    fn deterministic(&self) -> Option<Self> {
+        Some(self.clone().greedy())
+    }
 
-        Ok(LLMResponse::Quantized(result))
+    fn config(&self) -> JsonValue {
+        json!({
+            "type": "Quantized",
+            "model": format!("{:?}", self.which),
+            "temperature": self.temperature,
+            "top_p": self.top_p,
+            "seed": self.seed,
+            "sample_len": self.sample_len,
+        })
     }
 }
 
@@ -408,4 +683,20 @@ mod test {
         println!("{:?}", response.to_string());
         assert!(response.to_string().len() > 0);
     }
+
+    #[test]
+    fn test_profile() {
+        let model = Quantized::new().with_temperature(0.8).with_top_p(0.9).with_seed(7).test_profile();
+        assert_eq!((model.temperature, model.top_p, model.seed), (0., None, TEST_SEED));
+        assert_eq!((model.repeat_penalty, model.threads), (1., Some(1)));
+    }
+
+    #[test]
+    fn test_install() {
+        let mut model = Quantized::new().with_threads(2);
+        assert_eq!(model.install(rayon::current_num_threads), rayon::current_num_threads());
+        model.pool = model.threads.map(super::super::thread_pool).transpose().unwrap();
+        assert_eq!(model.install(rayon::current_num_threads), 2);
+        assert!(model.install(|| std::thread::current().name().unwrap().starts_with("orca-")));
+    }
 }
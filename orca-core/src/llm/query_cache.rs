@@ -0,0 +1,209 @@
+//! Cache of query embeddings.
+//!
+//! Chat applications retrieve context for every message, and follow-up messages often repeat the query of
+//! a previous one, word for word or up to case and spacing. A `QueryEmbeddingCache` wraps the embedder of
+//! a retriever so that such queries are embedded once: it is itself an `Embedding`, usable wherever the
+//! wrapped embedder is.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use orca_core::llm::openai::OpenAI;
+//! use orca_core::llm::query_cache::QueryEmbeddingCache;
+//! use orca_core::pipeline::federated::FederatedRetriever;
+//! use orca_core::vectorstore::memory::MemoryStore;
+//!
+//! let embedder = QueryEmbeddingCache::new(OpenAI::new(), 256).with_ttl(Duration::from_secs(300));
+//! let store = MemoryStore::new();
+//! let retriever = FederatedRetriever::new(&embedder).with_collection(&store, "docs", 1.0);
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use super::{Embedding, Embeddings, Precision};
+use crate::prompt::Prompt;
+
+/// Cached embeddings by normalized query, with the time they were embedded, and the queries from the least
+/// to the most recently used.
+type Entries = (HashMap<String, (Instant, Embeddings)>, VecDeque<String>);
+
+/// Embedder answering single prompts already embedded from memory, keeping the embeddings of the given
+/// number of most recently used queries for a limited time, 10 minutes by default.
+///
+/// Queries are compared once normalized: trimmed, lowercased and with runs of whitespace replaced by a
+/// single space. Batches, used to embed documents rather than queries, are passed on as is. Errors are not
+/// cached.
+pub struct QueryEmbeddingCache<E> {
+    /// The wrapped embedder.
+    embedder: E,
+
+    /// Maximum number of cached embeddings.
+    capacity: usize,
+
+    /// Time after which a cached embedding is embedded again.
+    ttl: Duration,
+
+    /// Cached embeddings and their order of use.
+    entries: Mutex<Entries>,
+}
+
+impl<E> QueryEmbeddingCache<E> {
+    /// Wrap an embedder, caching up to `capacity` query embeddings.
+    pub fn new(embedder: E, capacity: usize) -> Self {
+        QueryEmbeddingCache {
+            embedder,
+            capacity,
+            ttl: Duration::from_secs(600),
+            entries: Mutex::default(),
+        }
+    }
+
+    /// Set the time after which a cached embedding expires.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// The wrapped embedder.
+    pub fn embedder(&self) -> &E {
+        &self.embedder
+    }
+
+    /// Number of cached embeddings, including expired ones not yet evicted.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().0.len()
+    }
+
+    /// Whether no embedding is cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove all the cached embeddings.
+    pub fn clear(&self) {
+        let (embeddings, order) = &mut *self.entries.lock().unwrap();
+        embeddings.clear();
+        order.clear();
+    }
+
+    /// Cached embedding of a normalized query, if it has not expired, marking it as the most recently used.
+    fn get(&self, key: &str) -> Option<Embeddings> {
+        let (embeddings, order) = &mut *self.entries.lock().unwrap();
+        let (time, embedding) = embeddings.get(key)?;
+        if time.elapsed() >= self.ttl {
+            embeddings.remove(key);
+            order.retain(|query| query != key);
+            return None;
+        }
+        let embedding = embedding.clone();
+        order.retain(|query| query != key);
+        order.push_back(key.to_string());
+        Some(embedding)
+    }
+
+    /// Cache the embedding of a normalized query, evicting the least recently used one if the cache is full.
+    fn insert(&self, key: String, embedding: Embeddings) {
+        if self.capacity == 0 {
+            return;
+        }
+        let (embeddings, order) = &mut *self.entries.lock().unwrap();
+        if embeddings.insert(key.clone(), (Instant::now(), embedding)).is_some() {
+            order.retain(|query| *query != key);
+        }
+        order.push_back(key);
+        while order.len() > self.capacity {
+            order.pop_front().map(|oldest| embeddings.remove(&oldest));
+        }
+    }
+}
+
+/// Normalized text of a query, so that queries differing only by case or spacing share an embedding.
+fn normalize(query: &str) -> String {
+    query.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+#[async_trait::async_trait]
+impl<E: Embedding + Send + Sync> Embedding for QueryEmbeddingCache<E> {
+    async fn generate_embedding(&self, prompt: Box<dyn Prompt>) -> Result<Embeddings> {
+        let key = normalize(&prompt.to_string());
+        if let Some(embedding) = self.get(&key) {
+            return Ok(embedding);
+        }
+        let embedding = self.embedder.generate_embedding(prompt).await?;
+        self.insert(key, embedding.clone());
+        Ok(embedding)
+    }
+
+    async fn generate_embeddings(&self, prompts: Vec<Box<dyn Prompt>>) -> Result<Embeddings> {
+        self.embedder.generate_embeddings(prompts).await
+    }
+
+    fn dimensions(&self) -> usize {
+        self.embedder.dimensions()
+    }
+
+    fn precision(&self) -> Precision {
+        self.embedder.precision()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prompt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Embedder embedding prompts as their length, counting its calls.
+    #[derive(Default)]
+    struct Counting(AtomicUsize);
+
+    #[async_trait::async_trait]
+    impl Embedding for Counting {
+        async fn generate_embedding(&self, prompt: Box<dyn Prompt>) -> Result<Embeddings> {
+            self.generate_embeddings(vec![prompt]).await
+        }
+
+        async fn generate_embeddings(&self, prompts: Vec<Box<dyn Prompt>>) -> Result<Embeddings> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Embeddings::new(
+                "counting",
+                prompts.iter().map(|prompt| vec![prompt.to_string().len() as f32]).collect(),
+            )
+        }
+
+        fn dimensions(&self) -> usize {
+            1
+        }
+    }
+
+    #[tokio::test]
+    async fn test_query_embedding_cache() {
+        let cache = QueryEmbeddingCache::new(Counting::default(), 2);
+        let calls = |cache: &QueryEmbeddingCache<Counting>| cache.embedder().0.load(Ordering::SeqCst);
+
+        let first = cache.generate_embedding(prompt!("Where do orcas live?")).await.unwrap();
+        let again = cache.generate_embedding(prompt!("  where do  Orcas live? ")).await.unwrap();
+        assert_eq!((first, calls(&cache)), (again, 1));
+
+        // The least recently used query is evicted.
+        cache.generate_embedding(prompt!("What do orcas eat?")).await.unwrap();
+        cache.generate_embedding(prompt!("Where do orcas live?")).await.unwrap();
+        cache.generate_embedding(prompt!("How long do orcas live?")).await.unwrap();
+        assert_eq!((cache.len(), calls(&cache)), (2, 3));
+        cache.generate_embedding(prompt!("Where do orcas live?")).await.unwrap();
+        assert_eq!(calls(&cache), 3);
+        cache.generate_embedding(prompt!("What do orcas eat?")).await.unwrap();
+        assert_eq!(calls(&cache), 4);
+
+        let cache = cache.with_ttl(Duration::from_millis(20));
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        cache.generate_embedding(prompt!("What do orcas eat?")).await.unwrap();
+        assert_eq!(calls(&cache), 5);
+
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,232 @@
+//! This module provides a model router that answers with a cheap model first and escalates to
+//! stronger models when the answer does not look confident.
+//!
+//! Tiers are tried in the order they are added, e.g. a local quantized model, then `gpt-3.5-turbo`,
+//! then `gpt-4`. After each answer, the confidence checks are run; the first answer that passes all of
+//! them, or the answer of the last tier, is returned as an `LLMResponse::Routed` naming the tier that
+//! answered. A tier that fails with an error is escalated as well.
+
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value as JsonValue};
+
+use crate::eval::judge::{JudgePipeline, JudgeSample};
+use crate::prompt::Prompt;
+
+use super::{LLMResponse, RequestMetadata, LLM};
+
+/// Heuristic deciding whether an answer is good enough to stop escalating.
+#[async_trait::async_trait]
+pub trait ConfidenceCheck: Send + Sync {
+    /// Whether the response to the prompt looks confident.
+    async fn is_confident(&self, prompt: &str, response: &str) -> Result<bool>;
+}
+
+/// Answers shorter than the given number of characters, once trimmed, are not confident.
+pub struct MinLength(pub usize);
+
+#[async_trait::async_trait]
+impl ConfidenceCheck for MinLength {
+    async fn is_confident(&self, _prompt: &str, response: &str) -> Result<bool> {
+        Ok(response.trim().chars().count() >= self.0)
+    }
+}
+
+/// Answers containing a refusal phrase, such as "I'm not sure" or "As an AI", are not confident.
+pub struct NoRefusal {
+    phrases: Vec<String>,
+}
+
+impl Default for NoRefusal {
+    fn default() -> Self {
+        Self::new(&[
+            "i'm not sure",
+            "i am not sure",
+            "i don't know",
+            "i do not know",
+            "i cannot",
+            "i can't",
+            "i'm unable",
+            "i am unable",
+            "as an ai",
+        ])
+    }
+}
+
+impl NoRefusal {
+    /// Create a check with the given refusal phrases, matched ignoring case.
+    pub fn new(phrases: &[&str]) -> Self {
+        Self {
+            phrases: phrases.iter().map(|phrase| phrase.to_lowercase()).collect(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl ConfidenceCheck for NoRefusal {
+    async fn is_confident(&self, _prompt: &str, response: &str) -> Result<bool> {
+        let response = response.to_lowercase().replace('’', "'");
+        Ok(!self.phrases.iter().any(|phrase| response.contains(phrase)))
+    }
+}
+
+/// Answers scored below a minimum by an LLM judge are not confident.
+pub struct JudgeScore<M> {
+    judge: JudgePipeline<M>,
+    min_score: u8,
+}
+
+impl<M> JudgeScore<M> {
+    /// Create a check requiring a score of at least `min_score`, from 1 to 5.
+    pub fn new(judge: JudgePipeline<M>, min_score: u8) -> Self {
+        Self { judge, min_score }
+    }
+}
+
+#[async_trait::async_trait]
+impl<M: LLM + Clone + 'static> ConfidenceCheck for JudgeScore<M> {
+    async fn is_confident(&self, prompt: &str, response: &str) -> Result<bool> {
+        let judgement = self.judge.judge(&JudgeSample::new(prompt, response)).await?;
+        Ok(judgement.score >= self.min_score)
+    }
+}
+
+/// A model the router can answer with.
+#[derive(Clone)]
+struct Tier {
+    name: String,
+    llm: Arc<dyn LLM>,
+}
+
+/// Routes requests to the cheapest model whose answer is confident.
+#[derive(Clone)]
+pub struct ModelRouter {
+    /// Tiers, from the cheapest to the strongest.
+    tiers: Vec<Tier>,
+
+    /// Checks an answer must pass to stop escalating.
+    checks: Vec<Arc<dyn ConfidenceCheck>>,
+}
+
+impl ModelRouter {
+    /// Create a router whose first tier is the given model.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use orca_core::llm::openai::OpenAI;
+    /// use orca_core::llm::router::{MinLength, ModelRouter, NoRefusal};
+    ///
+    /// let router = ModelRouter::new("cheap", OpenAI::new().with_model("gpt-3.5-turbo"))
+    ///     .with_tier("strong", OpenAI::new().with_model("gpt-4"))
+    ///     .with_check(MinLength(20))
+    ///     .with_check(NoRefusal::default());
+    /// ```
+    pub fn new<L: LLM + 'static>(name: &str, llm: L) -> Self {
+        Self {
+            tiers: Vec::new(),
+            checks: Vec::new(),
+        }
+        .with_tier(name, llm)
+    }
+
+    /// Add a tier that answers when the previous tiers are not confident.
+    pub fn with_tier<L: LLM + 'static>(mut self, name: &str, llm: L) -> Self {
+        self.tiers.push(Tier {
+            name: name.to_string(),
+            llm: Arc::new(llm),
+        });
+        self
+    }
+
+    /// Add a check an answer must pass to stop escalating.
+    pub fn with_check<C: ConfidenceCheck + 'static>(mut self, check: C) -> Self {
+        self.checks.push(Arc::new(check));
+        self
+    }
+
+    /// Whether the response passes every check.
+    async fn is_confident(&self, prompt: &str, response: &str) -> Result<bool> {
+        for check in &self.checks {
+            if !check.is_confident(prompt, response).await? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[async_trait::async_trait]
+impl LLM for ModelRouter {
+    async fn generate(&self, prompt: Box<dyn Prompt>) -> Result<LLMResponse> {
+        self.generate_with_metadata(prompt, &RequestMetadata::default()).await
+    }
+
+    async fn generate_with_metadata(&self, prompt: Box<dyn Prompt>, metadata: &RequestMetadata) -> Result<LLMResponse> {
+        let text = prompt.to_string();
+        let mut error = None;
+        for (index, tier) in self.tiers.iter().enumerate() {
+            let last = index == self.tiers.len() - 1;
+            let response = match tier.llm.generate_with_metadata(prompt.clone_prompt(), metadata).await {
+                Ok(response) => response,
+                Err(e) if !last => {
+                    log::warn!("Tier {} failed, escalating: {}", tier.name, e);
+                    continue;
+                }
+                Err(e) => {
+                    error = Some(e);
+                    break;
+                }
+            };
+            if last || self.is_confident(&text, &response.to_string()).await? {
+                return Ok(LLMResponse::Routed {
+                    tier: tier.name.clone(),
+                    response: Box::new(response),
+                });
+            }
+            log::debug!("Tier {} is not confident, escalating", tier.name);
+        }
+        Err(error.unwrap_or_else(|| anyhow!("Router has no tiers")))
+    }
+
+    fn config(&self) -> JsonValue {
+        let tiers = self.tiers.iter().map(|tier| json!({ "name": tier.name, "model": tier.llm.config() }));
+        json!({ "type": "ModelRouter", "tiers": tiers.collect::<Vec<_>>() })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pipeline::PipelineResult;
+    use crate::prompt;
+    use crate::testing::FixedLLM;
+
+    #[tokio::test]
+    async fn test_escalation() {
+        let router = ModelRouter::new("local", FixedLLM::new("I'm not sure."))
+            .with_tier("cheap", FixedLLM::new("Paris"))
+            .with_tier("strong", FixedLLM::new("The capital of France is Paris."))
+            .with_check(NoRefusal::default())
+            .with_check(MinLength(10));
+        let response = router.generate(prompt!("What is the capital of France?")).await.unwrap();
+        assert_eq!(response.tier(), Some("strong"));
+        assert_eq!(response.to_string(), "The capital of France is Paris.");
+
+        let router = ModelRouter::new("local", FixedLLM::new("The capital of France is Paris."))
+            .with_tier("strong", FixedLLM::new("Paris"))
+            .with_check(MinLength(10));
+        let response = router.generate(prompt!("What is the capital of France?")).await.unwrap();
+        assert_eq!(response.tier(), Some("local"));
+    }
+
+    #[tokio::test]
+    async fn test_last_tier_answers() {
+        let router = ModelRouter::new("local", FixedLLM::new(""))
+            .with_tier("strong", FixedLLM::new(""))
+            .with_check(MinLength(1));
+        let response = router.generate(prompt!("Hello")).await.unwrap();
+        let result = PipelineResult::new("router".to_string()).with_llm_response(response);
+        assert_eq!(result.metadata()["tier"], "strong");
+    }
+}
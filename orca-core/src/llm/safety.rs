@@ -0,0 +1,309 @@
+//! Content-safety classification with a Bert sequence classification model.
+//! It utilizes the [candle](https://github.com/huggingface/candle) ML framework.
+//!
+//! A `SafetyClassifier` scores a text for each category of harmful content of its model, e.g. `toxic`,
+//! `threat` or `insult`, and flags the categories whose score reaches their threshold. It runs locally,
+//! for deployments without a remote moderation API, and is a `Validator`, so it guards the prompts and
+//! responses of an `LLMStack` as a `Guardrail`:
+//!
+//! ```no_run
+//! use orca_core::llm::middleware::{Guardrail, LLMStack};
+//! use orca_core::llm::openai::OpenAI;
+//! use orca_core::llm::safety::SafetyClassifier;
+//!
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! let classifier = SafetyClassifier::new().with_threshold("insult", 0.8).build_model_and_tokenizer().await?;
+//! let llm = LLMStack::new(OpenAI::new())
+//!     .layer(Guardrail::output(classifier.clone()))
+//!     .layer(Guardrail::input(classifier));
+//! # Ok(())
+//! # }
+//! ```
+
+use anyhow::{anyhow, Error as E, Result};
+use candle_core::{IndexOp, Tensor};
+use candle_nn::{Linear, Module, VarBuilder};
+use candle_transformers::models::bert::{BertModel, Config, DTYPE};
+use hf_hub::{Repo, RepoType};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokenizers::{Tokenizer, TruncationParams};
+
+use crate::pipeline::validated::Validator;
+
+use super::source::ModelSource;
+
+/// Labels and problem type of a sequence classification model, read from its config.
+#[derive(Deserialize)]
+struct ClassifierConfig {
+    hidden_size: usize,
+    id2label: HashMap<String, String>,
+    #[serde(default)]
+    problem_type: Option<String>,
+}
+
+/// Score of a text for a category of harmful content, between 0 and 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CategoryScore {
+    /// Category, e.g. `toxic`.
+    pub category: String,
+
+    /// Probability that the text belongs to the category.
+    pub score: f32,
+}
+
+#[derive(Clone)]
+pub struct SafetyClassifier {
+    /// Run on CPU rather than on GPU.
+    cpu: bool,
+
+    /// Run offline (you must have the files already cached)
+    offline: bool,
+
+    /// Where the model files are fetched from, the global `ModelSource` by default.
+    source: Option<ModelSource>,
+
+    /// The model to use, check out available models: https://huggingface.co/models?pipeline_tag=text-classification
+    model_id: Option<String>,
+
+    revision: Option<String>,
+
+    /// Score from which a category is flagged, by category.
+    thresholds: HashMap<String, f32>,
+
+    /// Score from which the categories without a threshold are flagged.
+    default_threshold: f32,
+
+    /// Model weights.
+    model: Option<Arc<BertModel>>,
+
+    /// Pooler applied to the hidden state of the first token.
+    pooler: Option<Linear>,
+
+    /// Sequence classification head.
+    classifier: Option<Linear>,
+
+    /// Category of each class predicted by the classifier.
+    categories: Vec<String>,
+
+    /// Whether the categories are scored independently, rather than as exclusive classes.
+    multi_label: bool,
+
+    /// Tokenizer.
+    tokenizer: Option<Tokenizer>,
+}
+
+impl Default for SafetyClassifier {
+    /// Provides default values for `SafetyClassifier`.
+    fn default() -> Self {
+        Self {
+            cpu: true,
+            offline: false,
+            source: None,
+            model_id: None,
+            revision: None,
+            thresholds: HashMap::new(),
+            default_threshold: 0.5,
+            model: None,
+            pooler: None,
+            classifier: None,
+            categories: Vec::new(),
+            multi_label: true,
+            tokenizer: None,
+        }
+    }
+}
+
+impl SafetyClassifier {
+    /// Creates a new `SafetyClassifier` instance.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Configures the model to run on GPU.
+    pub fn with_gpu(mut self) -> Self {
+        self.cpu = false;
+        self
+    }
+
+    /// Configures the model to run offline.
+    pub fn offline(mut self) -> Self {
+        self.offline = true;
+        self
+    }
+
+    /// Sets where the model files are fetched from, instead of the global `ModelSource`.
+    pub fn with_source(mut self, source: ModelSource) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// Sets the model ID.
+    pub fn with_model_id(mut self, model_id: &str) -> Self {
+        self.model_id = Some(model_id.to_string());
+        self
+    }
+
+    /// Sets the revision for the model.
+    pub fn with_revision(mut self, revision: &str) -> Self {
+        self.revision = Some(revision.to_string());
+        self
+    }
+
+    /// Sets the score from which a category is flagged. A threshold above 1 never flags the category.
+    pub fn with_threshold(mut self, category: &str, threshold: f32) -> Self {
+        self.thresholds.insert(category.to_string(), threshold);
+        self
+    }
+
+    /// Sets the score from which the categories without a threshold are flagged, 0.5 by default.
+    pub fn with_default_threshold(mut self, threshold: f32) -> Self {
+        self.default_threshold = threshold;
+        self
+    }
+
+    /// Builds the model and tokenizer. Defaults to `unitary/toxic-bert`, which scores toxic, severe
+    /// toxic, obscene, threatening, insulting and identity hating content.
+    pub async fn build_model_and_tokenizer(mut self) -> Result<Self> {
+        let device = super::device(self.cpu)?;
+        let model_id = self.model_id.clone().unwrap_or_else(|| "unitary/toxic-bert".to_string());
+        let revision = self.revision.clone().unwrap_or_else(|| "main".to_string());
+
+        let repo = Repo::with_revision(model_id, RepoType::Model, revision);
+        let source = ModelSource::resolve(self.source.as_ref(), self.offline);
+        let (config_filename, tokenizer_filename, weights_filename) = (
+            source.get(&repo, "config.json").await?,
+            source.get(&repo, "tokenizer.json").await?,
+            source.get(&repo, "model.safetensors").await?,
+        );
+        let config = std::fs::read_to_string(config_filename)?;
+        let classifier_config: ClassifierConfig = serde_json::from_str(&config)?;
+        let config: Config = serde_json::from_str(&config)?;
+
+        let mut categories = vec![String::new(); classifier_config.id2label.len()];
+        for (id, label) in classifier_config.id2label {
+            let id = id.parse::<usize>()?;
+            *categories.get_mut(id).ok_or(anyhow!("Invalid label id {} in config", id))? = label;
+        }
+
+        let mut tokenizer = Tokenizer::from_file(tokenizer_filename).map_err(E::msg)?;
+        tokenizer
+            .with_padding(None)
+            .with_truncation(Some(TruncationParams {
+                max_length: 512,
+                ..Default::default()
+            }))
+            .map_err(E::msg)?;
+
+        let hidden_size = classifier_config.hidden_size;
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[weights_filename], DTYPE, &device)? };
+        let model = BertModel::load(vb.clone(), &config)?;
+        let pooler = candle_nn::linear(hidden_size, hidden_size, vb.pp("bert.pooler.dense"))?;
+        let classifier = candle_nn::linear(hidden_size, categories.len(), vb.pp("classifier"))?;
+        self.model = Some(Arc::new(model));
+        self.pooler = Some(pooler);
+        self.classifier = Some(classifier);
+        self.categories = categories;
+        self.multi_label = classifier_config.problem_type.as_deref() != Some("single_label_classification");
+        self.tokenizer = Some(tokenizer);
+        Ok(self)
+    }
+
+    /// Score the text for each category of the model.
+    pub fn scores(&self, text: &str) -> Result<Vec<CategoryScore>> {
+        let (model, pooler, classifier, tokenizer) =
+            match (&self.model, &self.pooler, &self.classifier, &self.tokenizer) {
+                (Some(model), Some(pooler), Some(classifier), Some(tokenizer)) => {
+                    (model, pooler, classifier, tokenizer)
+                }
+                _ => return Err(anyhow!("Model or tokenizer not initialized")),
+            };
+
+        let encoding = tokenizer.encode(text, true).map_err(E::msg)?;
+        let token_ids = Tensor::new(encoding.get_ids(), &model.device)?.unsqueeze(0)?;
+        let token_type_ids = token_ids.zeros_like()?;
+        let hidden_states = model.forward(&token_ids, &token_type_ids)?;
+        let pooled = pooler.forward(&hidden_states.i((.., 0))?)?.tanh()?;
+        let logits = classifier.forward(&pooled)?.squeeze(0)?.to_vec1::<f32>()?;
+
+        let scores = activate(&logits, self.multi_label);
+        Ok(self
+            .categories
+            .iter()
+            .zip(scores)
+            .map(|(category, score)| CategoryScore {
+                category: category.clone(),
+                score,
+            })
+            .collect())
+    }
+
+    /// Score the text and keep the categories whose score reaches their threshold, highest first.
+    pub fn flagged(&self, text: &str) -> Result<Vec<CategoryScore>> {
+        Ok(self.flag(self.scores(text)?))
+    }
+
+    /// Keep the scores reaching the threshold of their category, highest first.
+    fn flag(&self, scores: Vec<CategoryScore>) -> Vec<CategoryScore> {
+        let mut flagged = scores
+            .into_iter()
+            .filter(|score| {
+                let threshold = self.thresholds.get(&score.category).copied().unwrap_or(self.default_threshold);
+                score.score >= threshold
+            })
+            .collect::<Vec<_>>();
+        flagged.sort_by(|a, b| b.score.total_cmp(&a.score));
+        flagged
+    }
+}
+
+/// Fails for texts with a flagged category, naming the categories and their scores.
+impl Validator for SafetyClassifier {
+    fn validate(&self, output: &str) -> Result<()> {
+        let flagged = self.flagged(output)?;
+        if flagged.is_empty() {
+            return Ok(());
+        }
+        let categories =
+            flagged.iter().map(|score| format!("{} ({:.2})", score.category, score.score)).collect::<Vec<_>>();
+        Err(anyhow!("The text was flagged as {}", categories.join(", ")))
+    }
+}
+
+/// Turn the logits of the classifier into probabilities: a sigmoid per category for multi-label models,
+/// a softmax over the categories otherwise.
+fn activate(logits: &[f32], multi_label: bool) -> Vec<f32> {
+    if multi_label {
+        return logits.iter().map(|logit| 1.0 / (1.0 + (-logit).exp())).collect();
+    }
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let exps = logits.iter().map(|logit| (logit - max).exp()).collect::<Vec<_>>();
+    let sum: f32 = exps.iter().sum();
+    exps.into_iter().map(|exp| exp / sum).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_flag() {
+        let scores = activate(&[2.0, 0.0, -2.0], true);
+        assert!((scores[0] - 0.881).abs() < 1e-3 && scores[1] == 0.5 && (scores[2] - 0.119).abs() < 1e-3);
+        let scores = activate(&[1.0, 1.0], false);
+        assert_eq!(scores, vec![0.5, 0.5]);
+
+        let classifier = SafetyClassifier::new().with_threshold("insult", 0.9).with_threshold("threat", 0.2);
+        let score = |category: &str, score: f32| CategoryScore {
+            category: category.to_string(),
+            score,
+        };
+        let flagged = classifier.flag(vec![score("toxic", 0.6), score("insult", 0.8), score("threat", 0.3)]);
+        assert_eq!(flagged, vec![score("toxic", 0.6), score("threat", 0.3)]);
+
+        // The model must be built to validate texts.
+        assert!(classifier.validate("Orcas live in pods.").is_err());
+    }
+}
@@ -0,0 +1,185 @@
+//! Providers of the API keys of LLM clients.
+//!
+//! Clients such as `OpenAI` and `Anthropic` read their API key from a [`SecretsProvider`] on every
+//! request, the environment by default. Giving each pipeline or tenant its own client with its own
+//! provider lets them use different keys, e.g. with [`ScopedSecrets`], and keys rotated in the provider,
+//! such as a file rewritten by a secrets manager, are used from the next request without restarting.
+
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use anyhow::{anyhow, Context, Result};
+
+/// Source of secrets, looked up by name, e.g. `OPENAI_API_KEY`.
+pub trait SecretsProvider: Send + Sync {
+    /// Get the current value of a secret.
+    fn secret(&self, name: &str) -> Result<String>;
+}
+
+impl<F> SecretsProvider for F
+where
+    F: Fn(&str) -> Result<String> + Send + Sync,
+{
+    fn secret(&self, name: &str) -> Result<String> {
+        self(name)
+    }
+}
+
+impl<S: SecretsProvider + ?Sized> SecretsProvider for Arc<S> {
+    fn secret(&self, name: &str) -> Result<String> {
+        (**self).secret(name)
+    }
+}
+
+/// Reads secrets from environment variables. Characters of the names that are not valid in variable
+/// names, such as the `/` of scoped names, are replaced by `_` and letters are uppercased, so that
+/// `acme/OPENAI_API_KEY` is read from `ACME_OPENAI_API_KEY`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EnvSecrets;
+
+impl SecretsProvider for EnvSecrets {
+    fn secret(&self, name: &str) -> Result<String> {
+        let variable = name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+        std::env::var(&variable).map_err(|_| anyhow!("{} not set", variable))
+    }
+}
+
+/// Reads each secret from the file of the same name in a directory, e.g. `/run/secrets/OPENAI_API_KEY`,
+/// as mounted by Docker and Kubernetes. The files are read on every lookup, so rotated secrets are picked
+/// up, and surrounding whitespace is trimmed. The `/` of scoped names separate subdirectories; names that
+/// could point outside of the directory, with `..`, `.` or empty parts, a leading `/` or a backslash, are
+/// rejected.
+#[derive(Debug, Clone)]
+pub struct FileSecrets {
+    /// Directory holding the secrets.
+    dir: PathBuf,
+}
+
+impl FileSecrets {
+    /// Create a provider reading the secrets from the given directory.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        FileSecrets { dir: dir.into() }
+    }
+}
+
+impl SecretsProvider for FileSecrets {
+    fn secret(&self, name: &str) -> Result<String> {
+        let mut path = self.dir.clone();
+        for part in name.split('/') {
+            let mut components = Path::new(part).components();
+            match (components.next(), components.next()) {
+                (Some(Component::Normal(_)), None) if !part.contains('\\') => path.push(part),
+                _ => return Err(anyhow!("Invalid secret name {:?}", name)),
+            }
+        }
+        let secret = std::fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        Ok(secret.trim().to_string())
+    }
+}
+
+/// Secrets held in memory, which can be rotated at runtime through any clone of the provider.
+#[derive(Debug, Clone, Default)]
+pub struct StaticSecrets(Arc<RwLock<HashMap<String, String>>>);
+
+impl StaticSecrets {
+    /// Create an empty provider.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a secret.
+    pub fn with_secret(self, name: &str, value: &str) -> Self {
+        self.rotate(name, value);
+        self
+    }
+
+    /// Replace the value of a secret, used by the clients from their next request.
+    pub fn rotate(&self, name: &str, value: &str) {
+        self.0.write().unwrap().insert(name.to_string(), value.to_string());
+    }
+}
+
+impl SecretsProvider for StaticSecrets {
+    fn secret(&self, name: &str) -> Result<String> {
+        self.0.read().unwrap().get(name).cloned().ok_or_else(|| anyhow!("Secret {} not set", name))
+    }
+}
+
+/// Looks up secrets under a scope, e.g. a tenant or pipeline, as `<scope>/<name>` in another provider.
+///
+/// # Example
+/// ```
+/// use orca_core::llm::secrets::{ScopedSecrets, SecretsProvider, StaticSecrets};
+///
+/// let secrets = StaticSecrets::new().with_secret("acme/OPENAI_API_KEY", "sk-acme");
+/// let acme = ScopedSecrets::new(secrets.clone(), "acme");
+/// assert_eq!(acme.secret("OPENAI_API_KEY").unwrap(), "sk-acme");
+/// secrets.rotate("acme/OPENAI_API_KEY", "sk-acme-2");
+/// assert_eq!(acme.secret("OPENAI_API_KEY").unwrap(), "sk-acme-2");
+/// assert!(ScopedSecrets::new(secrets, "globex").secret("OPENAI_API_KEY").is_err());
+/// ```
+#[derive(Debug, Clone)]
+pub struct ScopedSecrets<S> {
+    /// Provider the scoped secrets are looked up in.
+    provider: S,
+
+    /// Scope of the secrets.
+    scope: String,
+}
+
+impl<S: SecretsProvider> ScopedSecrets<S> {
+    /// Create a provider looking up the secrets of the given scope.
+    pub fn new(provider: S, scope: &str) -> Self {
+        ScopedSecrets {
+            provider,
+            scope: scope.to_string(),
+        }
+    }
+}
+
+impl<S: SecretsProvider> SecretsProvider for ScopedSecrets<S> {
+    fn secret(&self, name: &str) -> Result<String> {
+        self.provider.secret(&format!("{}/{}", self.scope, name))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_secrets() {
+        std::env::set_var("ORCA_TEST_ACME_API_KEY", "sk-env");
+        assert_eq!(EnvSecrets.secret("orca_test/acme-api-key").unwrap(), "sk-env");
+        assert_eq!(
+            EnvSecrets.secret("ORCA_TEST_MISSING").unwrap_err().to_string(),
+            "ORCA_TEST_MISSING not set"
+        );
+
+        let dir = std::env::temp_dir().join(format!("orca-secrets-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("acme")).unwrap();
+        std::fs::write(dir.join("acme").join("API_KEY"), "sk-file\n").unwrap();
+        let secrets = ScopedSecrets::new(FileSecrets::new(&dir), "acme");
+        assert_eq!(secrets.secret("API_KEY").unwrap(), "sk-file");
+        std::fs::write(dir.join("acme").join("API_KEY"), "sk-rotated").unwrap();
+        assert_eq!(secrets.secret("API_KEY").unwrap(), "sk-rotated");
+        for scope in ["..", "../acme", "/etc", "acme/", ".", "acme\\.."] {
+            let error = ScopedSecrets::new(FileSecrets::new(dir.join("acme")), scope).secret("API_KEY").unwrap_err();
+            assert!(error.to_string().starts_with("Invalid secret name"), "{}", scope);
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+
+        let custom = |name: &str| Ok(name.to_lowercase());
+        assert_eq!(custom.secret("API_KEY").unwrap(), "api_key");
+    }
+}
@@ -0,0 +1,290 @@
+//! Quantized llama weights split across devices.
+//!
+//! candle's `quantized_llama::ModelWeights` keeps the whole model on one device, so 70B-class models do
+//! not fit a single GPU. `ShardedWeights` loads the layers of a GGUF model onto several devices instead,
+//! e.g. the first layers on two GPUs and the rest in CPU RAM. Each forward pass runs the layers in order
+//! and moves the activations when crossing to the next device, so the devices hold their share of the
+//! weights and of the key-value cache but compute one after the other.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use candle_core::quantized::{gguf_file, QMatMul};
+use candle_core::{DType, Device, IndexOp, Tensor};
+use candle_nn::{Embedding, Module};
+use candle_transformers::models::quantized_llama::MAX_SEQ_LEN;
+use candle_transformers::quantized_nn::RmsNorm;
+
+/// Device a share of the layers of a model is placed on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModelDevice {
+    /// CPU RAM.
+    Cpu,
+
+    /// CUDA GPU with the given ordinal.
+    Cuda(usize),
+}
+
+impl ModelDevice {
+    fn to_device(self) -> Result<Device> {
+        Ok(match self {
+            ModelDevice::Cpu => Device::Cpu,
+            ModelDevice::Cuda(ordinal) => Device::new_cuda(ordinal)?,
+        })
+    }
+}
+
+/// Index of the device of each layer, placing `layers[i]` layers on the i-th device and the remaining
+/// layers on the last one. Without counts, the layers are spread evenly, the first devices taking one
+/// more layer when they do not divide evenly.
+pub(crate) fn assign_layers(n_layers: usize, n_devices: usize, layers: &[usize]) -> Result<Vec<usize>> {
+    if n_devices == 0 {
+        return Err(anyhow!("The device map is empty"));
+    }
+    if layers.len() > n_devices {
+        return Err(anyhow!("{} layer counts given for {} devices", layers.len(), n_devices));
+    }
+    let counts: Vec<usize> = if layers.is_empty() {
+        (0..n_devices).map(|i| n_layers / n_devices + usize::from(i < n_layers % n_devices)).collect()
+    } else {
+        let placed: usize = layers.iter().sum();
+        if placed > n_layers {
+            return Err(anyhow!("{} layers placed but the model has {}", placed, n_layers));
+        }
+        let mut counts = layers.to_vec();
+        counts.resize(n_devices, 0);
+        counts[n_devices - 1] += n_layers - placed;
+        counts
+    };
+    Ok(counts.iter().enumerate().flat_map(|(device, &count)| vec![device; count]).collect())
+}
+
+#[derive(Debug, Clone)]
+struct Layer {
+    attention_wq: QMatMul,
+    attention_wk: QMatMul,
+    attention_wv: QMatMul,
+    attention_wo: QMatMul,
+    attention_norm: RmsNorm,
+    feed_forward_w1: QMatMul,
+    feed_forward_w2: QMatMul,
+    feed_forward_w3: QMatMul,
+    ffn_norm: RmsNorm,
+    n_head: usize,
+    n_kv_head: usize,
+    head_dim: usize,
+    cos: Tensor,
+    sin: Tensor,
+    neg_inf: Tensor,
+    kv_cache: Option<(Tensor, Tensor)>,
+    device_idx: usize,
+}
+
+impl Layer {
+    fn forward_attn(&mut self, x: &Tensor, mask: Option<&Tensor>, index_pos: usize) -> Result<Tensor> {
+        let (b_sz, seq_len, n_embd) = x.dims3()?;
+        let q = self
+            .attention_wq
+            .forward(x)?
+            .reshape((b_sz, seq_len, self.n_head, self.head_dim))?
+            .transpose(1, 2)?;
+        let k = self
+            .attention_wk
+            .forward(x)?
+            .reshape((b_sz, seq_len, self.n_kv_head, self.head_dim))?
+            .transpose(1, 2)?;
+        let v = self
+            .attention_wv
+            .forward(x)?
+            .reshape((b_sz, seq_len, self.n_kv_head, self.head_dim))?
+            .transpose(1, 2)?
+            .contiguous()?;
+
+        let cos = self.cos.narrow(0, index_pos, seq_len)?;
+        let sin = self.sin.narrow(0, index_pos, seq_len)?;
+        let q = candle_nn::rotary_emb::rope_i(&q.contiguous()?, &cos, &sin)?;
+        let k = candle_nn::rotary_emb::rope_i(&k.contiguous()?, &cos, &sin)?;
+
+        let (k, v) = match &self.kv_cache {
+            Some((k_cache, v_cache)) if index_pos > 0 => {
+                (Tensor::cat(&[k_cache, &k], 2)?, Tensor::cat(&[v_cache, &v], 2)?)
+            }
+            _ => (k, v),
+        };
+        self.kv_cache = Some((k.clone(), v.clone()));
+
+        let k = candle_transformers::utils::repeat_kv(k, self.n_head / self.n_kv_head)?;
+        let v = candle_transformers::utils::repeat_kv(v, self.n_head / self.n_kv_head)?;
+        let att = (q.matmul(&k.t()?)? / (self.head_dim as f64).sqrt())?;
+        let att = match mask {
+            None => att,
+            Some(mask) => {
+                let mask = mask.broadcast_as(att.shape())?;
+                mask.where_cond(&self.neg_inf.broadcast_as(mask.shape().dims())?, &att)?
+            }
+        };
+        let att = candle_nn::ops::softmax_last_dim(&att)?;
+        let y = att.matmul(&v.contiguous()?)?;
+        let y = y.transpose(1, 2)?.reshape(&[b_sz, seq_len, n_embd])?;
+        Ok(self.attention_wo.forward(&y)?)
+    }
+
+    fn forward(&mut self, x: &Tensor, mask: Option<&Tensor>, index_pos: usize) -> Result<Tensor> {
+        let residual = x;
+        let attn = self.forward_attn(&self.attention_norm.forward(x)?, mask, index_pos)?;
+        let x = (attn + residual)?;
+        let residual = &x;
+        let h = self.ffn_norm.forward(&x)?;
+        let w1 = self.feed_forward_w1.forward(&h)?;
+        let w3 = self.feed_forward_w3.forward(&h)?;
+        let mlp = self.feed_forward_w2.forward(&(candle_nn::ops::silu(&w1)? * w3)?)?;
+        Ok((mlp + residual)?)
+    }
+}
+
+/// Weights of a quantized llama or mistral model, with their layers split across devices.
+#[derive(Debug, Clone)]
+pub struct ShardedWeights {
+    tok_embeddings: Embedding,
+    layers: Vec<Layer>,
+    norm: RmsNorm,
+    output: QMatMul,
+    devices: Vec<Device>,
+    masks: HashMap<(usize, usize), Tensor>,
+}
+
+impl ShardedWeights {
+    /// Load a GGUF model, placing its layers on the devices as given by `assign_layers`. The token
+    /// embeddings go to the device of the first layer, the output head to the device of the last one.
+    pub fn from_gguf<R: std::io::Seek + std::io::Read>(
+        ct: gguf_file::Content,
+        reader: &mut R,
+        devices: &[ModelDevice],
+        layers: &[usize],
+    ) -> Result<Self> {
+        let md_get = |s: &str| ct.metadata.get(s).ok_or_else(|| anyhow!("cannot find {} in metadata", s));
+        let head_count = md_get("llama.attention.head_count")?.to_u32()? as usize;
+        let head_count_kv = md_get("llama.attention.head_count_kv")?.to_u32()? as usize;
+        let block_count = md_get("llama.block_count")?.to_u32()? as usize;
+        let embedding_length = md_get("llama.embedding_length")?.to_u32()? as usize;
+        let rope_dim = md_get("llama.rope.dimension_count")?.to_u32()? as usize;
+        let rms_norm_eps = md_get("llama.attention.layer_norm_rms_epsilon")?.to_f32()? as f64;
+        let rope_freq_base = md_get("llama.rope.freq_base").ok().and_then(|m| m.to_f32().ok()).unwrap_or(10000f32);
+
+        let devices = devices.iter().map(|device| device.to_device()).collect::<Result<Vec<_>>>()?;
+        let assignment = assign_layers(block_count, devices.len(), layers)?;
+        let first = &devices[assignment.first().copied().unwrap_or(0)];
+        let last = &devices[assignment.last().copied().unwrap_or(0)];
+
+        let tok_embeddings_q = ct.tensor(reader, "token_embd.weight", first)?;
+        let tok_embeddings = tok_embeddings_q.dequantize(first)?;
+        let norm = RmsNorm::from_qtensor(ct.tensor(reader, "output_norm.weight", last)?, rms_norm_eps)?;
+        let output = match ct.tensor(reader, "output.weight", last) {
+            Ok(tensor) => tensor,
+            Err(_) => ct.tensor(reader, "token_embd.weight", last)?,
+        };
+
+        // Rotary embeddings are small, each device keeps its own copy.
+        let rotary = devices
+            .iter()
+            .map(|device| {
+                let (cos, sin) = precompute_freqs_cis(rope_dim, rope_freq_base, device)?;
+                Ok((cos, sin, Tensor::new(f32::NEG_INFINITY, device)?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut model_layers = Vec::with_capacity(block_count);
+        for (layer_idx, &device_idx) in assignment.iter().enumerate() {
+            let device = &devices[device_idx];
+            let (cos, sin, neg_inf) = rotary[device_idx].clone();
+            let prefix = format!("blk.{layer_idx}");
+            let mut tensor = |name: &str| ct.tensor(reader, &format!("{prefix}.{name}.weight"), device);
+            model_layers.push(Layer {
+                attention_wq: QMatMul::from_qtensor(tensor("attn_q")?)?,
+                attention_wk: QMatMul::from_qtensor(tensor("attn_k")?)?,
+                attention_wv: QMatMul::from_qtensor(tensor("attn_v")?)?,
+                attention_wo: QMatMul::from_qtensor(tensor("attn_output")?)?,
+                attention_norm: RmsNorm::from_qtensor(tensor("attn_norm")?, rms_norm_eps)?,
+                feed_forward_w1: QMatMul::from_qtensor(tensor("ffn_gate")?)?,
+                feed_forward_w2: QMatMul::from_qtensor(tensor("ffn_down")?)?,
+                feed_forward_w3: QMatMul::from_qtensor(tensor("ffn_up")?)?,
+                ffn_norm: RmsNorm::from_qtensor(tensor("ffn_norm")?, rms_norm_eps)?,
+                n_head: head_count,
+                n_kv_head: head_count_kv,
+                head_dim: embedding_length / head_count,
+                cos,
+                sin,
+                neg_inf,
+                kv_cache: None,
+                device_idx,
+            });
+        }
+        Ok(ShardedWeights {
+            tok_embeddings: Embedding::new(tok_embeddings, embedding_length),
+            layers: model_layers,
+            norm,
+            output: QMatMul::from_qtensor(output)?,
+            devices,
+            masks: HashMap::new(),
+        })
+    }
+
+    /// Device the input tokens must be on.
+    pub fn input_device(&self) -> &Device {
+        self.tok_embeddings.embeddings().device()
+    }
+
+    /// Causal mask of a sequence, cached for each device.
+    fn mask(&mut self, t: usize, device_idx: usize) -> Result<Tensor> {
+        if let Some(mask) = self.masks.get(&(t, device_idx)) {
+            return Ok(mask.clone());
+        }
+        let mask: Vec<u8> = (0..t).flat_map(|i| (0..t).map(move |j| u8::from(j > i))).collect();
+        let mask = Tensor::from_slice(&mask, (t, t), &self.devices[device_idx])?;
+        self.masks.insert((t, device_idx), mask.clone());
+        Ok(mask)
+    }
+
+    /// Logits of the next token, on the device of the output head.
+    pub fn forward(&mut self, x: &Tensor, index_pos: usize) -> Result<Tensor> {
+        let (_b_sz, seq_len) = x.dims2()?;
+        let mut hidden = self.tok_embeddings.forward(x)?;
+        for i in 0..self.layers.len() {
+            let device_idx = self.layers[i].device_idx;
+            hidden = hidden.to_device(&self.devices[device_idx])?;
+            let mask = if seq_len == 1 {
+                None
+            } else {
+                Some(self.mask(seq_len, device_idx)?)
+            };
+            hidden = self.layers[i].forward(&hidden, mask.as_ref(), index_pos)?;
+        }
+        let x = self.norm.forward(&hidden)?;
+        let x = x.i((.., seq_len - 1, ..))?;
+        Ok(self.output.forward(&x)?)
+    }
+}
+
+fn precompute_freqs_cis(head_dim: usize, freq_base: f32, device: &Device) -> Result<(Tensor, Tensor)> {
+    let theta: Vec<_> = (0..head_dim).step_by(2).map(|i| 1f32 / freq_base.powf(i as f32 / head_dim as f32)).collect();
+    let theta = Tensor::new(theta.as_slice(), device)?;
+    let idx_theta = Tensor::arange(0, MAX_SEQ_LEN as u32, device)?
+        .to_dtype(DType::F32)?
+        .reshape((MAX_SEQ_LEN, 1))?
+        .matmul(&theta.reshape((1, theta.elem_count()))?)?;
+    Ok((idx_theta.cos()?, idx_theta.sin()?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_assign_layers() {
+        assert_eq!(assign_layers(5, 2, &[]).unwrap(), vec![0, 0, 0, 1, 1]);
+        assert_eq!(assign_layers(6, 3, &[1, 2]).unwrap(), vec![0, 1, 1, 2, 2, 2]);
+        assert_eq!(assign_layers(3, 2, &[3]).unwrap(), vec![0, 0, 0]);
+        assert!(assign_layers(3, 2, &[2, 2]).is_err());
+        assert!(assign_layers(3, 1, &[1, 2]).is_err());
+        assert!(assign_layers(3, 0, &[]).is_err());
+    }
+}
@@ -0,0 +1,161 @@
+//! Snapshot tests of generated text.
+//!
+//! Local models sampled greedily, e.g. with `Quantized::test_profile`, generate the same text for the
+//! same prompt, so their output can be pinned in CI and compared across candle versions. `Snapshots`
+//! stores the expected text of each test in a directory, `<name>.txt`, and fails when the generated
+//! text differs. Missing snapshots are recorded on the first run, and setting `ORCA_UPDATE_SNAPSHOTS=1`
+//! records every snapshot again after an intended change.
+
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use super::LLM;
+use crate::prompt::Prompt;
+
+/// Environment variable that, when set to `1`, makes snapshot checks record the text they are given.
+pub const UPDATE_SNAPSHOTS_VAR: &str = "ORCA_UPDATE_SNAPSHOTS";
+
+/// Generated text that differs from its snapshot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotMismatch {
+    /// Path of the snapshot.
+    pub path: PathBuf,
+
+    /// Text of the snapshot.
+    pub expected: String,
+
+    /// Generated text.
+    pub actual: String,
+}
+
+impl Display for SnapshotMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Generated text differs from snapshot {} (set {}=1 to update it)\n--- expected\n{}\n+++ actual\n{}",
+            self.path.display(),
+            UPDATE_SNAPSHOTS_VAR,
+            self.expected,
+            self.actual
+        )
+    }
+}
+
+impl std::error::Error for SnapshotMismatch {}
+
+/// Directory of snapshots of generated text.
+#[derive(Debug, Clone)]
+pub struct Snapshots {
+    /// Directory holding the snapshots.
+    dir: PathBuf,
+
+    /// Whether snapshots are recorded rather than checked.
+    update: bool,
+}
+
+impl Snapshots {
+    /// Open a directory of snapshots, updated if `ORCA_UPDATE_SNAPSHOTS` is `1`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use orca_core::llm::quantized::{Model, Quantized};
+    /// use orca_core::llm::snapshot::Snapshots;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let model = Quantized::new()
+    ///     .with_model(Model::Mistral7bInstruct)
+    ///     .with_sample_len(20)
+    ///     .test_profile()
+    ///     .load_model(Model::Mistral7bInstruct)
+    ///     .await?
+    ///     .build_model()?;
+    /// let snapshots = Snapshots::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/snapshots"));
+    /// let prompt = Box::new("What is the capital of France?".to_string());
+    /// snapshots.check_generation(&model, "mistral_capital", prompt).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        Snapshots {
+            dir: dir.as_ref().to_path_buf(),
+            update: std::env::var(UPDATE_SNAPSHOTS_VAR).is_ok_and(|value| value == "1"),
+        }
+    }
+
+    /// Set whether snapshots are recorded rather than checked, regardless of the environment.
+    pub fn with_update(mut self, update: bool) -> Self {
+        self.update = update;
+        self
+    }
+
+    /// Path of the snapshot with the given name.
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.txt", name))
+    }
+
+    /// Check a text against the snapshot with the given name, recording it if the snapshot is missing
+    /// or snapshots are updated. Fails with a [`SnapshotMismatch`] if the text differs.
+    pub fn check(&self, name: &str, text: &str) -> Result<()> {
+        let path = self.path(name);
+        if self.update || !path.exists() {
+            std::fs::create_dir_all(&self.dir)?;
+            std::fs::write(&path, text)?;
+            log::info!("Recorded snapshot {}", path.display());
+            return Ok(());
+        }
+        let expected = std::fs::read_to_string(&path)?;
+        if expected != text {
+            return Err(SnapshotMismatch {
+                path,
+                expected,
+                actual: text.to_string(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Generate a response to the prompt and check it against the snapshot with the given name.
+    pub async fn check_generation<M: LLM + ?Sized>(&self, llm: &M, name: &str, prompt: Box<dyn Prompt>) -> Result<()> {
+        let response = llm.generate(prompt).await?;
+        self.check(name, &response.to_string())
+    }
+
+    /// Check a text against the snapshot with the given name, panicking with the differing texts if it
+    /// differs. Meant for tests.
+    #[track_caller]
+    pub fn assert(&self, name: &str, text: &str) {
+        if let Err(error) = self.check(name, text) {
+            panic!("{}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::EchoLLM;
+
+    #[tokio::test]
+    async fn test_snapshots() {
+        let dir = std::env::temp_dir().join(format!("orca-snapshots-{}", uuid::Uuid::new_v4()));
+        let snapshots = Snapshots::new(&dir).with_update(false);
+        snapshots.check_generation(&EchoLLM::new(), "echo", Box::new("Orcas".to_string())).await.unwrap();
+        assert_eq!(std::fs::read_to_string(snapshots.path("echo")).unwrap(), "Orcas");
+        snapshots.assert("echo", "Orcas");
+
+        let error = snapshots.check("echo", "Dolphins").unwrap_err();
+        let mismatch = error.downcast_ref::<SnapshotMismatch>().unwrap();
+        assert_eq!(
+            (mismatch.expected.as_str(), mismatch.actual.as_str()),
+            ("Orcas", "Dolphins")
+        );
+
+        snapshots.clone().with_update(true).check("echo", "Dolphins").unwrap();
+        snapshots.assert("echo", "Dolphins");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,296 @@
+//! Where the model loaders fetch model files from.
+//!
+//! Every loader downloading files from the Hugging Face Hub (`Bert`, `NER`, `Onnx`, `Quantized` and
+//! `StableDiffusion`, and the `from_api` loaders of `orca-models`) resolves them through a `ModelSource`:
+//! the global one set with `ModelSource::set_global`, unless the loader was given its own with
+//! `with_source`. A source sets the cache directory, a mirror of the Hub, the access token, and whether
+//! files may be downloaded at all, so that air-gapped deployments can load models from a pre-populated
+//! cache only. Files can also be pinned to their SHA-256 checksum, in which case loading fails unless the
+//! file matches it.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use anyhow::{anyhow, Result};
+use hf_hub::api::tokio::ApiBuilder;
+use hf_hub::{Cache, Repo, RepoType};
+use sha2::{Digest, Sha256};
+
+/// Address of the Hugging Face Hub.
+const HUB_ENDPOINT: &str = "https://huggingface.co";
+
+/// Source set with `ModelSource::set_global`.
+static GLOBAL: RwLock<Option<ModelSource>> = RwLock::new(None);
+
+/// Configuration of where model files are fetched from.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModelSource {
+    /// Cache directory, `$HF_HOME/hub` or `~/.cache/huggingface/hub` by default.
+    cache_dir: Option<PathBuf>,
+
+    /// Only use files already in the cache.
+    offline: bool,
+
+    /// Address of a mirror of the Hub, e.g. `https://hf-mirror.internal`.
+    mirror: Option<String>,
+
+    /// Access token, the one saved by `huggingface-cli login` by default.
+    token: Option<String>,
+
+    /// Expected SHA-256 checksums of files, by file key.
+    pins: BTreeMap<String, String>,
+}
+
+impl ModelSource {
+    /// Create a source downloading files from the Hub into the default cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a source configured by the `HF_HUB_CACHE`, `HF_HUB_OFFLINE`, `HF_ENDPOINT` and `HF_TOKEN`
+    /// environment variables, as the Hugging Face tools are.
+    pub fn from_env() -> Self {
+        let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+        ModelSource {
+            cache_dir: var("HF_HUB_CACHE").map(PathBuf::from),
+            offline: var("HF_HUB_OFFLINE").is_some_and(|value| value != "0" && value.to_lowercase() != "false"),
+            mirror: var("HF_ENDPOINT").filter(|endpoint| endpoint.trim_end_matches('/') != HUB_ENDPOINT),
+            token: var("HF_TOKEN"),
+            pins: BTreeMap::new(),
+        }
+        .normalized()
+    }
+
+    /// Set the cache directory.
+    pub fn with_cache_dir<P: Into<PathBuf>>(mut self, cache_dir: P) -> Self {
+        self.cache_dir = Some(cache_dir.into());
+        self
+    }
+
+    /// Only use files already in the cache, failing to load models with missing files.
+    pub fn offline(mut self) -> Self {
+        self.offline = true;
+        self
+    }
+
+    /// Download files from a mirror of the Hub at the given address instead of the Hub.
+    pub fn with_mirror(mut self, mirror: &str) -> Self {
+        self.mirror = Some(mirror.to_string());
+        self.normalized()
+    }
+
+    /// Set the access token, e.g. for gated models.
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.token = Some(token.to_string());
+        self
+    }
+
+    /// Pin a file of a model repository, e.g. `model.safetensors` of
+    /// `sentence-transformers/all-MiniLM-L6-v2`, to its SHA-256 checksum in hexadecimal. Loading the file
+    /// then fails if its checksum differs, whether it was cached or downloaded. Pinned files are hashed
+    /// each time they are loaded, which takes a few seconds for large weights.
+    pub fn with_sha256(mut self, repo_id: &str, filename: &str, sha256: &str) -> Self {
+        let repo = Repo::new(repo_id.to_string(), RepoType::Model);
+        self.pins.insert(pin_key(&repo, filename), sha256.to_lowercase());
+        self
+    }
+
+    /// Whether the source only uses files already in the cache.
+    pub fn is_offline(&self) -> bool {
+        self.offline
+    }
+
+    /// Set the source of the loaders not given their own, replacing the one read from the environment.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use orca_core::llm::bert::Bert;
+    /// # use orca_core::llm::source::ModelSource;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// ModelSource::new().with_cache_dir("/models/huggingface").offline().set_global();
+    /// let bert = Bert::new().build_model_and_tokenizer().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_global(self) {
+        *GLOBAL.write().unwrap_or_else(|e| e.into_inner()) = Some(self);
+    }
+
+    /// The source set with `set_global`, or else the one configured by the environment.
+    pub fn global() -> Self {
+        GLOBAL.read().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_else(Self::from_env)
+    }
+
+    /// The source of a loader, the global one unless it was given its own, offline if the loader is.
+    pub(crate) fn resolve(source: Option<&ModelSource>, offline: bool) -> Self {
+        let source = source.cloned().unwrap_or_else(Self::global);
+        if offline {
+            source.offline()
+        } else {
+            source
+        }
+    }
+
+    fn normalized(mut self) -> Self {
+        self.mirror = self.mirror.map(|mirror| mirror.trim_end_matches('/').to_string());
+        self
+    }
+
+    fn cache(&self) -> Cache {
+        match &self.cache_dir {
+            Some(dir) => Cache::new(dir.clone()),
+            None => Cache::default(),
+        }
+    }
+
+    /// Path of a file of a repository, downloaded into the cache unless it is already there, and
+    /// verified if pinned.
+    pub async fn get(&self, repo: &Repo, filename: &str) -> Result<PathBuf> {
+        let path = self.fetch(repo, filename).await?;
+        if let Some(sha256) = self.pins.get(&pin_key(repo, filename)) {
+            let (file, sha256) = (path.clone(), sha256.clone());
+            tokio::task::spawn_blocking(move || verify_sha256(&file, &sha256)).await??;
+        }
+        Ok(path)
+    }
+
+    async fn fetch(&self, repo: &Repo, filename: &str) -> Result<PathBuf> {
+        let cache = self.cache();
+        if let Some(path) = cache.repo(repo.clone()).get(filename) {
+            return Ok(path);
+        }
+        if self.offline {
+            return Err(anyhow!(
+                "Missing {} of {} in cache {}, and the model source is offline",
+                filename,
+                repo.folder_name(),
+                cache.path().display()
+            ));
+        }
+
+        let mut api = ApiBuilder::from_cache(cache.clone());
+        if let Some(token) = &self.token {
+            api = api.with_token(Some(token.clone()));
+        }
+        let api = api.build()?.repo(repo.clone());
+        match &self.mirror {
+            None => Ok(api.get(filename).await?),
+            Some(mirror) => {
+                let url = api.url(filename).replacen(HUB_ENDPOINT, mirror, 1);
+                let token = self.token.clone().or_else(|| cache.token());
+                self.download(&url, token.as_deref(), &cache, repo, filename).await
+            }
+        }
+    }
+
+    /// Download a file from a mirror into the cache, under the revision of the repository, so that the
+    /// cache finds it offline.
+    async fn download(
+        &self,
+        url: &str,
+        token: Option<&str>,
+        cache: &Cache,
+        repo: &Repo,
+        filename: &str,
+    ) -> Result<PathBuf> {
+        let mut request = reqwest::Client::new().get(url);
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        let bytes = request.send().await?.error_for_status()?.bytes().await?;
+
+        let path = snapshot_path(cache, repo, filename);
+        let temporary = path.with_extension("part");
+        std::fs::create_dir_all(path.parent().ok_or(anyhow!("Invalid file name {}", filename))?)?;
+        std::fs::write(&temporary, &bytes)?;
+        std::fs::rename(&temporary, &path)?;
+        cache.repo(repo.clone()).create_ref(repo.revision())?;
+        Ok(path)
+    }
+}
+
+/// Fail unless the SHA-256 checksum of a file is the expected one, in hexadecimal.
+pub fn verify_sha256<P: AsRef<Path>>(path: P, sha256: &str) -> Result<()> {
+    let path = path.as_ref();
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+    let actual = hex::encode(hasher.finalize());
+    if !actual.eq_ignore_ascii_case(sha256) {
+        return Err(anyhow!(
+            "Checksum mismatch for {}: expected sha256 {}, found {}",
+            path.display(),
+            sha256,
+            actual
+        ));
+    }
+    Ok(())
+}
+
+/// Key of a file of a repository in the pins.
+fn pin_key(repo: &Repo, filename: &str) -> String {
+    format!("{}/{}", repo.folder_name(), filename)
+}
+
+/// Path of a file of a repository in the cache, in the snapshot named after the revision.
+fn snapshot_path(cache: &Cache, repo: &Repo, filename: &str) -> PathBuf {
+    let mut path = cache.path().join(repo.folder_name()).join("snapshots");
+    path.extend(repo.revision().split('/'));
+    path.extend(filename.split('/'));
+    path
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_offline() {
+        let dir = std::env::temp_dir().join(format!("orca-source-{}", uuid::Uuid::new_v4()));
+        let source = ModelSource::new().with_cache_dir(&dir).offline();
+        let repo = Repo::with_revision("orca/bert".to_string(), RepoType::Model, "refs/pr/1".to_string());
+        let error = source.get(&repo, "config.json").await.unwrap_err();
+        assert!(error.to_string().contains("Missing config.json of models--orca--bert"));
+
+        // Files downloaded from a mirror are laid out as the cache expects them.
+        let cache = Cache::new(dir.clone());
+        let path = snapshot_path(&cache, &repo, "config.json");
+        assert_eq!(path, dir.join("models--orca--bert/snapshots/refs/pr/1/config.json"));
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "{}").unwrap();
+        cache.repo(repo.clone()).create_ref("refs/pr/1").unwrap();
+        assert_eq!(source.get(&repo, "config.json").await.unwrap(), path);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sha256() {
+        let dir = std::env::temp_dir().join(format!("orca-source-{}", uuid::Uuid::new_v4()));
+        let cache = Cache::new(dir.clone());
+        let repo = Repo::new("orca/bert".to_string(), RepoType::Model);
+        let path = snapshot_path(&cache, &repo, "model.safetensors");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "weights").unwrap();
+        cache.repo(repo.clone()).create_ref("main").unwrap();
+
+        // echo -n weights | sha256sum
+        let sha256 = "9a129038d9a00aed0cf6a7ea059ca50a813449061ab87848cf1a13eafdf33b2c";
+        let source = ModelSource::new().with_cache_dir(&dir).offline();
+        let pinned = source.clone().with_sha256("orca/bert", "model.safetensors", &sha256.to_uppercase());
+        assert_eq!(pinned.get(&repo, "model.safetensors").await.unwrap(), path);
+
+        let pinned = source.with_sha256("orca/bert", "model.safetensors", &"0".repeat(64));
+        let error = pinned.get(&repo, "model.safetensors").await.unwrap_err();
+        assert!(error.to_string().contains("Checksum mismatch"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_resolve() {
+        let source = ModelSource::new().with_mirror("https://hf-mirror.internal/");
+        assert_eq!(source.mirror.as_deref(), Some("https://hf-mirror.internal"));
+        assert!(ModelSource::resolve(Some(&source), true).is_offline());
+        assert!(!ModelSource::resolve(Some(&source), false).is_offline());
+    }
+}
@@ -0,0 +1,360 @@
+//! This module provides an image generation backend for Stable Diffusion.
+//! It utilizes the [candle](https://github.com/huggingface/candle) ML framework.
+//!
+//! The weights of the text encoder, UNet and autoencoder are downloaded from the Hugging Face Hub the
+//! first time the model is built. Generating an image takes a few seconds on a GPU and a few minutes
+//! on a CPU.
+
+use std::io::Cursor;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Error as E, Result};
+use candle_core::{DType, Device, IndexOp, Module, Tensor};
+use candle_transformers::models::stable_diffusion::{
+    build_clip_transformer, clip::ClipTextTransformer, unet_2d::UNet2DConditionModel, vae::AutoEncoderKL,
+    StableDiffusionConfig,
+};
+use hf_hub::{Repo, RepoType};
+use tokenizers::Tokenizer;
+
+use crate::prompt::Prompt;
+
+use super::source::ModelSource;
+use super::{GeneratedImage, ImageGenerator};
+
+/// Factor by which the autoencoder latents are scaled.
+const VAE_SCALE: f64 = 0.18215;
+
+/// Stable Diffusion release to use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StableDiffusionVersion {
+    /// Stable Diffusion 1.5, generating 512x512 images.
+    #[default]
+    V1_5,
+
+    /// Stable Diffusion 2.1, generating 768x768 images.
+    V2_1,
+}
+
+impl StableDiffusionVersion {
+    /// Repository of the model on the Hugging Face Hub.
+    fn repo(&self) -> &'static str {
+        match self {
+            StableDiffusionVersion::V1_5 => "runwayml/stable-diffusion-v1-5",
+            StableDiffusionVersion::V2_1 => "stabilityai/stable-diffusion-2-1",
+        }
+    }
+
+    fn config(&self, width: Option<usize>, height: Option<usize>) -> StableDiffusionConfig {
+        match self {
+            StableDiffusionVersion::V1_5 => StableDiffusionConfig::v1_5(None, height, width),
+            StableDiffusionVersion::V2_1 => StableDiffusionConfig::v2_1(None, height, width),
+        }
+    }
+}
+
+/// Models making up the Stable Diffusion pipeline.
+struct Models {
+    tokenizer: Tokenizer,
+    text_model: ClipTextTransformer,
+    unet: UNet2DConditionModel,
+    vae: AutoEncoderKL,
+}
+
+#[derive(Clone)]
+pub struct StableDiffusion {
+    /// Run on CPU rather than on GPU.
+    cpu: bool,
+
+    /// Run offline (you must have the files already cached)
+    offline: bool,
+
+    /// Where the model files are fetched from, the global `ModelSource` by default.
+    source: Option<ModelSource>,
+
+    /// Stable Diffusion release to use.
+    version: StableDiffusionVersion,
+
+    /// Width of the generated images, the default of the version if not set. Must be a multiple of 8.
+    width: Option<usize>,
+
+    /// Height of the generated images, the default of the version if not set. Must be a multiple of 8.
+    height: Option<usize>,
+
+    /// Number of denoising steps.
+    steps: usize,
+
+    /// Classifier-free guidance scale. Higher values follow the prompt more closely, 1.0 disables guidance.
+    guidance_scale: f64,
+
+    /// What the generated images should not look like.
+    negative_prompt: String,
+
+    /// Run the UNet and autoencoder in half precision, which requires a GPU.
+    use_f16: bool,
+
+    /// Pipeline configuration.
+    config: Option<StableDiffusionConfig>,
+
+    /// Device the models run on.
+    device: Option<Device>,
+
+    /// Tokenizer and model weights.
+    models: Option<Arc<Models>>,
+}
+
+impl Default for StableDiffusion {
+    fn default() -> Self {
+        Self {
+            cpu: false,
+            offline: false,
+            source: None,
+            version: StableDiffusionVersion::default(),
+            width: None,
+            height: None,
+            steps: 30,
+            guidance_scale: 7.5,
+            negative_prompt: String::new(),
+            use_f16: false,
+            config: None,
+            device: None,
+            models: None,
+        }
+    }
+}
+
+impl StableDiffusion {
+    /// Creates a new `StableDiffusion` instance.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Configures the model to run on CPU.
+    pub fn with_cpu(mut self) -> Self {
+        self.cpu = true;
+        self
+    }
+
+    /// Configures the model to run offline.
+    pub fn offline(mut self) -> Self {
+        self.offline = true;
+        self
+    }
+
+    /// Sets where the model files are fetched from, instead of the global `ModelSource`.
+    pub fn with_source(mut self, source: ModelSource) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// Sets the Stable Diffusion release to use.
+    pub fn with_version(mut self, version: StableDiffusionVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Sets the size of the generated images. Both dimensions must be multiples of 8.
+    pub fn with_size(mut self, width: usize, height: usize) -> Self {
+        self.width = Some(width);
+        self.height = Some(height);
+        self
+    }
+
+    /// Sets the number of denoising steps, 30 by default.
+    pub fn with_steps(mut self, steps: usize) -> Self {
+        self.steps = steps;
+        self
+    }
+
+    /// Sets the classifier-free guidance scale, 7.5 by default.
+    pub fn with_guidance_scale(mut self, guidance_scale: f64) -> Self {
+        self.guidance_scale = guidance_scale;
+        self
+    }
+
+    /// Sets what the generated images should not look like, e.g. "blurry, low quality".
+    pub fn with_negative_prompt(mut self, negative_prompt: &str) -> Self {
+        self.negative_prompt = negative_prompt.to_string();
+        self
+    }
+
+    /// Runs the UNet and autoencoder in half precision.
+    pub fn with_f16(mut self) -> Self {
+        self.use_f16 = true;
+        self
+    }
+
+    /// Builds the models and tokenizer.
+    pub async fn build_model_and_tokenizer(mut self) -> Result<Self> {
+        if [self.width, self.height].into_iter().flatten().any(|size| !size.is_multiple_of(8)) {
+            return Err(anyhow!("Image width and height must be multiples of 8"));
+        }
+        let device = super::device(self.cpu)?;
+        let config = self.version.config(self.width, self.height);
+        let dtype = self.dtype();
+
+        let suffix = if self.use_f16 { ".fp16" } else { "" };
+        let weights = [
+            format!("text_encoder/model{}.safetensors", suffix),
+            format!("unet/diffusion_pytorch_model{}.safetensors", suffix),
+            format!("vae/diffusion_pytorch_model{}.safetensors", suffix),
+        ];
+        let model_repo = Repo::model(self.version.repo().to_string());
+        let tokenizer_repo = Repo::new("openai/clip-vit-base-patch32".to_string(), RepoType::Model);
+        let source = ModelSource::resolve(self.source.as_ref(), self.offline);
+        let (tokenizer_filename, [clip_filename, unet_filename, vae_filename]) = (
+            source.get(&tokenizer_repo, "tokenizer.json").await?,
+            [
+                source.get(&model_repo, &weights[0]).await?,
+                source.get(&model_repo, &weights[1]).await?,
+                source.get(&model_repo, &weights[2]).await?,
+            ],
+        );
+
+        let tokenizer = Tokenizer::from_file(tokenizer_filename).map_err(E::msg)?;
+        // The text encoder always runs in full precision, its output is converted to `dtype`.
+        let text_model = build_clip_transformer(&config.clip, clip_filename, &device, DType::F32)?;
+        let unet = config.build_unet(unet_filename, &device, 4, false, dtype)?;
+        let vae = config.build_vae(vae_filename, &device, dtype)?;
+
+        self.models = Some(Arc::new(Models {
+            tokenizer,
+            text_model,
+            unet,
+            vae,
+        }));
+        self.config = Some(config);
+        self.device = Some(device);
+        Ok(self)
+    }
+
+    fn dtype(&self) -> DType {
+        if self.use_f16 {
+            DType::F16
+        } else {
+            DType::F32
+        }
+    }
+
+    /// Whether classifier-free guidance is used, which runs the UNet on the prompt and on the negative prompt.
+    fn use_guidance(&self) -> bool {
+        self.guidance_scale > 1.0
+    }
+
+    /// Embeds a prompt with the text encoder, padding it to the maximum prompt length.
+    fn embed_prompt(
+        &self,
+        models: &Models,
+        config: &StableDiffusionConfig,
+        device: &Device,
+        prompt: &str,
+    ) -> Result<Tensor> {
+        let vocab = models.tokenizer.get_vocab(true);
+        let pad_with = config.clip.pad_with.as_deref().unwrap_or("<|endoftext|>");
+        let pad_id = *vocab.get(pad_with).ok_or(anyhow!("Missing padding token {} in vocabulary", pad_with))?;
+        let mut tokens = models.tokenizer.encode(prompt, true).map_err(E::msg)?.get_ids().to_vec();
+        let max_length = config.clip.max_position_embeddings;
+        if tokens.len() > max_length {
+            return Err(anyhow!(
+                "Prompt is too long, {} tokens for a maximum of {}",
+                tokens.len(),
+                max_length
+            ));
+        }
+        tokens.resize(max_length, pad_id);
+        let tokens = Tensor::new(tokens.as_slice(), device)?.unsqueeze(0)?;
+        Ok(models.text_model.forward(&tokens)?)
+    }
+
+    /// Runs the diffusion process and encodes the image as PNG.
+    fn generate(&self, prompt: &str) -> Result<GeneratedImage> {
+        let (Some(models), Some(config), Some(device)) =
+            (self.models.as_ref(), self.config.as_ref(), self.device.as_ref())
+        else {
+            return Err(anyhow!("Model or tokenizer not initialized"));
+        };
+        let dtype = self.dtype();
+        let start = std::time::Instant::now();
+
+        let text_embeddings = self.embed_prompt(models, config, device, prompt)?;
+        let text_embeddings = if self.use_guidance() {
+            let uncond_embeddings = self.embed_prompt(models, config, device, &self.negative_prompt)?;
+            Tensor::cat(&[uncond_embeddings, text_embeddings], 0)?
+        } else {
+            text_embeddings
+        }
+        .to_dtype(dtype)?;
+
+        let mut scheduler = config.build_scheduler(self.steps)?;
+        let timesteps = scheduler.timesteps().to_vec();
+        let latents = Tensor::randn(0f32, 1f32, (1, 4, config.height / 8, config.width / 8), device)?;
+        let mut latents = (latents * scheduler.init_noise_sigma())?.to_dtype(dtype)?;
+        for timestep in timesteps {
+            let input = if self.use_guidance() {
+                Tensor::cat(&[&latents, &latents], 0)?
+            } else {
+                latents.clone()
+            };
+            let input = scheduler.scale_model_input(input, timestep)?;
+            let noise_pred = models.unet.forward(&input, timestep as f64, &text_embeddings)?;
+            let noise_pred = if self.use_guidance() {
+                let noise_pred = noise_pred.chunk(2, 0)?;
+                let (uncond, text) = (&noise_pred[0], &noise_pred[1]);
+                (uncond + ((text - uncond)? * self.guidance_scale)?)?
+            } else {
+                noise_pred
+            };
+            latents = scheduler.step(&noise_pred, timestep, &latents)?;
+        }
+
+        let image = models.vae.decode(&(latents / VAE_SCALE)?)?;
+        let image = ((image / 2.)? + 0.5)?.to_device(&Device::Cpu)?.to_dtype(DType::F32)?;
+        let image = (image.clamp(0f32, 1.)? * 255.)?.to_dtype(DType::U8)?.i(0)?;
+        log::info!("Image took {:?} to generate", start.elapsed());
+        encode_png(&image)
+    }
+}
+
+/// Encodes a `(channels, height, width)` RGB tensor as PNG.
+fn encode_png(image: &Tensor) -> Result<GeneratedImage> {
+    let (_, height, width) = image.dims3()?;
+    let pixels = image.permute((1, 2, 0))?.flatten_all()?.to_vec1::<u8>()?;
+    let image = image::RgbImage::from_raw(width as u32, height as u32, pixels)
+        .ok_or(anyhow!("Image buffer does not match its size"))?;
+    let mut bytes = Cursor::new(Vec::new());
+    image.write_to(&mut bytes, image::ImageOutputFormat::Png)?;
+    Ok(GeneratedImage::png(bytes.into_inner()))
+}
+
+#[async_trait::async_trait]
+impl ImageGenerator for StableDiffusion {
+    async fn generate_image(&self, prompt: Box<dyn Prompt>) -> Result<Vec<GeneratedImage>> {
+        // Diffusion takes seconds to minutes of CPU or GPU time, so it runs off the async runtime.
+        let generator = self.clone();
+        let prompt = prompt.to_string();
+        let image = tokio::task::spawn_blocking(move || generator.generate(&prompt)).await??;
+        Ok(vec![image])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prompt;
+
+    #[test]
+    fn test_encode_png() {
+        let image = Tensor::zeros((3, 8, 16), DType::U8, &Device::Cpu).unwrap();
+        let image = encode_png(&image).unwrap();
+        assert_eq!(image.mime_type, "image/png");
+        assert_eq!(&image.bytes[1..4], b"PNG");
+    }
+
+    #[tokio::test]
+    #[ignore = "downloads the model weights from the Hugging Face Hub"]
+    async fn test_generate() {
+        let sd = StableDiffusion::new().with_steps(2).build_model_and_tokenizer().await.unwrap();
+        let images = sd.generate_image(prompt!("A lighthouse at dawn")).await.unwrap();
+        assert_eq!(images.len(), 1);
+    }
+}
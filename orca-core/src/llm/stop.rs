@@ -0,0 +1,167 @@
+//! Criteria for stopping text generation.
+//!
+//! A [`StopCriteria`] decides, after each generated token, whether generation should stop and how
+//! much of the text to keep, e.g. to cut a stop string off the response. Local models evaluate the
+//! criteria of their [`StopConditions`] as they sample, which saves the remaining tokens. Remote
+//! providers return whole responses, so their criteria are applied client-side to the received text,
+//! replayed word by word as if it were streamed; token counts are then approximated by words.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use regex::Regex;
+
+/// Decides when to stop generating text.
+pub trait StopCriteria: Send + Sync {
+    /// Check the text decoded so far, `tokens` being the number of tokens generated. Returns the
+    /// length in bytes of the text to keep to stop generation, or `None` to go on.
+    fn check(&self, tokens: usize, text: &str) -> Option<usize>;
+}
+
+impl<F> StopCriteria for F
+where
+    F: Fn(usize, &str) -> Option<usize> + Send + Sync,
+{
+    fn check(&self, tokens: usize, text: &str) -> Option<usize> {
+        self(tokens, text)
+    }
+}
+
+/// Stops after the given number of tokens.
+#[derive(Debug, Clone, Copy)]
+pub struct MaxTokens(pub usize);
+
+impl StopCriteria for MaxTokens {
+    fn check(&self, tokens: usize, text: &str) -> Option<usize> {
+        (tokens >= self.0).then_some(text.len())
+    }
+}
+
+/// Stops at the first of the given strings, which is cut off the text.
+#[derive(Debug, Clone)]
+pub struct StopStrings(Vec<String>);
+
+impl StopStrings {
+    /// Create a criteria stopping at any of the given strings.
+    pub fn new(strings: &[&str]) -> Self {
+        StopStrings(strings.iter().map(|string| string.to_string()).collect())
+    }
+}
+
+impl StopCriteria for StopStrings {
+    fn check(&self, _tokens: usize, text: &str) -> Option<usize> {
+        self.0
+            .iter()
+            .filter(|string| !string.is_empty())
+            .filter_map(|string| text.find(string.as_str()))
+            .min()
+    }
+}
+
+/// Stops at the first match of a regular expression, which is cut off the text.
+#[derive(Debug, Clone)]
+pub struct StopRegex(Regex);
+
+impl StopRegex {
+    /// Create a criteria stopping at the first match of the given regular expression.
+    pub fn new(pattern: &str) -> Result<Self> {
+        Ok(StopRegex(Regex::new(pattern)?))
+    }
+}
+
+impl StopCriteria for StopRegex {
+    fn check(&self, _tokens: usize, text: &str) -> Option<usize> {
+        self.0.find(text).map(|found| found.start())
+    }
+}
+
+/// Set of stop criteria; generation stops at the first criteria met.
+#[derive(Clone, Default)]
+pub struct StopConditions(Vec<Arc<dyn StopCriteria>>);
+
+impl StopConditions {
+    /// Create an empty set, which never stops generation.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a criteria to the set.
+    pub fn with<S: StopCriteria + 'static>(mut self, criteria: S) -> Self {
+        self.0.push(Arc::new(criteria));
+        self
+    }
+
+    /// Whether the set has no criteria.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Check every criteria after a token, returning the shortest length of text to keep if any of
+    /// them is met.
+    pub fn check(&self, tokens: usize, text: &str) -> Option<usize> {
+        self.0.iter().filter_map(|criteria| criteria.check(tokens, text)).min()
+    }
+
+    /// Replay a complete response word by word, returning the length of text to keep if a criteria
+    /// is met. Each word, with the whitespace before it, counts as one token.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::llm::stop::{MaxTokens, StopConditions, StopStrings};
+    ///
+    /// let stop = StopConditions::new().with(StopStrings::new(&["\nUser:"])).with(MaxTokens(50));
+    /// let text = "Orcas are dolphins.\nUser: and sharks?";
+    /// assert_eq!(stop.apply(text).map(|len| &text[..len]), Some("Orcas are dolphins."));
+    /// assert_eq!(stop.apply("Orcas are dolphins."), None);
+    /// ```
+    pub fn apply(&self, text: &str) -> Option<usize> {
+        if self.is_empty() {
+            return None;
+        }
+        let mut end = 0;
+        let mut tokens = 0;
+        while end < text.len() {
+            let word = text[end..].trim_start().len();
+            end = text.len() - word + text[text.len() - word..].find(char::is_whitespace).unwrap_or(word);
+            tokens += 1;
+            if let Some(len) = self.check(tokens, &text[..end]) {
+                return Some(len.min(end));
+            }
+        }
+        None
+    }
+}
+
+/// Truncate text split in consecutive parts, e.g. the choices or content blocks of a response, to the
+/// given total length in bytes.
+pub(crate) fn truncate_parts<'a>(parts: impl Iterator<Item = &'a mut String>, len: usize) {
+    let mut remaining = len;
+    for part in parts {
+        let keep = remaining.min(part.len());
+        part.truncate(keep);
+        remaining -= keep;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_stop_conditions() {
+        let stop = StopConditions::new()
+            .with(StopRegex::new(r"\[\d+\]").unwrap())
+            .with(|_tokens: usize, text: &str| text.ends_with("!!").then(|| text.len() - 2));
+        assert_eq!(stop.check(3, "Orcas hunt seals [1] and"), Some(17));
+        assert_eq!(stop.check(3, "Orcas!!"), Some(5));
+        assert_eq!(stop.check(3, "Orcas"), None);
+        assert_eq!(StopConditions::new().apply("Orcas"), None);
+
+        let stop = StopConditions::new().with(MaxTokens(2));
+        assert_eq!(stop.apply("  Orcas hunt seals"), Some(12));
+
+        let mut parts = vec!["Orcas ".to_string(), "hunt".to_string(), " seals".to_string()];
+        truncate_parts(parts.iter_mut(), 8);
+        assert_eq!(parts, ["Orcas ", "hu", ""]);
+    }
+}
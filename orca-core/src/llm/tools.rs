@@ -0,0 +1,91 @@
+//! Functions that LLMs can call.
+//!
+//! A [`Tool`] describes a function and the JSON schema of its arguments. Given tools, a model may answer
+//! with calls to them instead of text, returned as an `LLMResponse::ToolCall` with the name and parsed
+//! arguments of each call, so that pipelines can branch on them, run the functions and send their output
+//! back as `tool` messages, created with `Message::tool_output` after the assistant message holding the
+//! calls.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+/// Function the model can call.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tool {
+    /// Name of the function, e.g. `search`.
+    pub name: String,
+
+    /// What the function does, to decide when to call it.
+    pub description: String,
+
+    /// JSON schema of the arguments of the function.
+    pub parameters: JsonValue,
+}
+
+impl Tool {
+    /// Create a tool from the JSON schema of its arguments.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::llm::tools::Tool;
+    /// use serde_json::json;
+    ///
+    /// let search = Tool::new(
+    ///     "search",
+    ///     "Search the sightings of orcas",
+    ///     json!({
+    ///         "type": "object",
+    ///         "properties": {"pod": {"type": "string"}},
+    ///         "required": ["pod"]
+    ///     }),
+    /// );
+    /// ```
+    pub fn new(name: &str, description: &str, parameters: JsonValue) -> Self {
+        Tool {
+            name: name.to_string(),
+            description: description.to_string(),
+            parameters,
+        }
+    }
+}
+
+/// Whether and which tools the model must call.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum ToolChoice {
+    /// The model decides whether to answer or call tools.
+    #[default]
+    Auto,
+
+    /// The model answers without calling tools.
+    None,
+
+    /// The model calls one or more tools.
+    Required,
+
+    /// The model calls the named tool.
+    Function(String),
+}
+
+/// Call of a tool by a model.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    /// Identifier of the call, to reference it when sending the output of the tool back.
+    pub id: String,
+
+    /// Name of the called tool.
+    pub name: String,
+
+    /// Arguments of the call. Arguments that are not valid JSON are kept as a string.
+    pub arguments: JsonValue,
+}
+
+impl ToolCall {
+    /// Create a call from arguments serialized as JSON, as sent by most providers.
+    pub fn new(id: &str, name: &str, arguments: &str) -> Self {
+        ToolCall {
+            id: id.to_string(),
+            name: name.to_string(),
+            arguments: serde_json::from_str(arguments).unwrap_or_else(|_| JsonValue::String(arguments.to_string())),
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! Vector math on embeddings.
+//!
+//! Similarities, normalization, pooling and maximal marginal relevance, shared by the vector stores,
+//! rerankers and caches rather than reimplemented in each. The sums are accumulated in `LANES`
+//! independent lanes, which lets the compiler vectorize them with the SIMD instructions of the target
+//! without relying on floating point reassociation, and without unstable or platform specific code.
+
+/// Number of lanes the sums are accumulated in, enough to fill a 256-bit register of `f32`.
+const LANES: usize = 8;
+
+/// Sum of `f(a[i], b[i])` over the elements of two slices, truncated to the shorter one.
+#[inline]
+fn sum_zip(a: &[f32], b: &[f32], f: impl Fn(f32, f32) -> f32) -> f32 {
+    let len = a.len().min(b.len());
+    let (a, b) = (&a[..len], &b[..len]);
+    let mut lanes = [0.0f32; LANES];
+    let chunks = a.chunks_exact(LANES).zip(b.chunks_exact(LANES));
+    for (a, b) in chunks {
+        for lane in 0..LANES {
+            lanes[lane] += f(a[lane], b[lane]);
+        }
+    }
+    let tail = len - len % LANES;
+    let rest = a[tail..].iter().zip(&b[tail..]).map(|(&a, &b)| f(a, b)).sum::<f32>();
+    lanes.iter().sum::<f32>() + rest
+}
+
+/// Dot product of two vectors. Extra elements of the longer vector are ignored.
+pub fn dot(a: &[f32], b: &[f32]) -> f32 {
+    sum_zip(a, b, |a, b| a * b)
+}
+
+/// Euclidean norm of a vector.
+pub fn norm(v: &[f32]) -> f32 {
+    dot(v, v).sqrt()
+}
+
+/// Squared euclidean distance between two vectors.
+pub fn squared_distance(a: &[f32], b: &[f32]) -> f32 {
+    sum_zip(a, b, |a, b| (a - b) * (a - b))
+}
+
+/// Cosine similarity of two vectors, 0 if either is zero.
+///
+/// # Example
+/// ```
+/// use orca_core::math::cosine;
+///
+/// assert_eq!(cosine(&[1.0, 0.0], &[2.0, 0.0]), 1.0);
+/// assert_eq!(cosine(&[1.0, 0.0], &[0.0, 3.0]), 0.0);
+/// assert_eq!(cosine(&[1.0, 0.0], &[0.0, 0.0]), 0.0);
+/// ```
+pub fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot(a, b) / norms
+    }
+}
+
+/// Scale a vector to unit length, so that dot product and cosine similarity are equivalent. Zero vectors
+/// are left unchanged.
+pub fn normalize(v: &mut [f32]) {
+    let norm = norm(v);
+    if norm > 0.0 {
+        v.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+/// Element-wise mean of vectors of the same size, e.g. to embed a document as the mean of the vectors of
+/// its chunks. `None` if there are no vectors.
+pub fn mean_pool<V: AsRef<[f32]>>(vectors: &[V]) -> Option<Vec<f32>> {
+    let weights = vec![1.0; vectors.len()];
+    weighted_mean_pool(vectors, &weights)
+}
+
+/// Element-wise mean of vectors of the same size weighted by `weights`, e.g. the lengths of the chunks the
+/// vectors embed. `None` if there are no vectors or the weights sum to zero.
+pub fn weighted_mean_pool<V: AsRef<[f32]>>(vectors: &[V], weights: &[f32]) -> Option<Vec<f32>> {
+    let total = weights.iter().take(vectors.len()).sum::<f32>();
+    let first = vectors.first()?.as_ref();
+    if total == 0.0 {
+        return None;
+    }
+    let mut mean = vec![0.0; first.len()];
+    for (vector, weight) in vectors.iter().zip(weights) {
+        let scale = weight / total;
+        mean.iter_mut().zip(vector.as_ref()).for_each(|(mean, x)| *mean += x * scale);
+    }
+    Some(mean)
+}
+
+/// Select `k` candidates by maximal marginal relevance: each pick maximizes
+/// `lambda * sim(query, candidate) - (1 - lambda) * max sim(candidate, picked)`, trading relevance for
+/// diversity, by cosine similarity. A `lambda` of 1 ranks by relevance only. Returns the indices of the
+/// picked candidates, in order of selection.
+///
+/// # Example
+/// ```
+/// use orca_core::math::mmr;
+///
+/// let candidates = [vec![1.0, 0.0], vec![0.99, 0.1], vec![0.7, 0.7]];
+/// assert_eq!(mmr(&[1.0, 0.0], &candidates, 2, 1.0), vec![0, 1]);
+/// assert_eq!(mmr(&[1.0, 0.0], &candidates, 2, 0.3), vec![0, 2]);
+/// ```
+pub fn mmr<V: AsRef<[f32]>>(query: &[f32], candidates: &[V], k: usize, lambda: f32) -> Vec<usize> {
+    let relevance = candidates.iter().map(|candidate| cosine(query, candidate.as_ref())).collect::<Vec<_>>();
+    // Highest similarity of each candidate to the candidates picked so far.
+    let mut redundancy = vec![f32::NEG_INFINITY; candidates.len()];
+    let mut picked = Vec::with_capacity(k.min(candidates.len()));
+    let mut available = vec![true; candidates.len()];
+    while picked.len() < k {
+        let score = |index: usize| match picked.is_empty() {
+            true => relevance[index],
+            false => lambda * relevance[index] - (1.0 - lambda) * redundancy[index],
+        };
+        let Some(best) = (0..candidates.len()).filter(|&index| available[index]).max_by(|&a, &b| {
+            // Ties go to the earlier candidate.
+            score(a).total_cmp(&score(b)).then(b.cmp(&a))
+        }) else {
+            break;
+        };
+        available[best] = false;
+        picked.push(best);
+        for (index, candidate) in candidates.iter().enumerate().filter(|(index, _)| available[*index]) {
+            let similarity = cosine(candidate.as_ref(), candidates[best].as_ref());
+            redundancy[index] = redundancy[index].max(similarity);
+        }
+    }
+    picked
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_math() {
+        let a = (0..19).map(|i| i as f32).collect::<Vec<_>>();
+        let b = (0..19).map(|i| (i % 3) as f32 - 1.0).collect::<Vec<_>>();
+        let naive = a.iter().zip(&b).map(|(a, b)| a * b).sum::<f32>();
+        assert_eq!(dot(&a, &b), naive);
+        assert_eq!(squared_distance(&[1.0, 2.0], &[4.0, 6.0]), 25.0);
+        assert_eq!(norm(&[3.0, 4.0]), 5.0);
+
+        let mut v = vec![3.0, 4.0];
+        normalize(&mut v);
+        assert_eq!(v, vec![0.6, 0.8]);
+        let mut zero = vec![0.0, 0.0];
+        normalize(&mut zero);
+        assert_eq!(zero, vec![0.0, 0.0]);
+
+        assert_eq!(mean_pool(&[vec![1.0, 2.0], vec![3.0, 4.0]]), Some(vec![2.0, 3.0]));
+        assert_eq!(mean_pool::<Vec<f32>>(&[]), None);
+        assert_eq!(weighted_mean_pool(&[[0.0], [4.0]], &[1.0, 3.0]), Some(vec![3.0]));
+        assert_eq!(weighted_mean_pool(&[[1.0]], &[0.0]), None);
+
+        assert_eq!(mmr(&[1.0, 0.0], &[[0.0, 1.0], [1.0, 0.0]], 5, 0.5), vec![1, 0]);
+        assert!(mmr::<[f32; 2]>(&[1.0, 0.0], &[], 3, 0.5).is_empty());
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use serde_json::json;
+
+use super::Memory;
+use crate::llm::LLM;
+use crate::pipeline::parse_json;
+use crate::prompt::chat::{ChatPrompt, Message, Role};
+use crate::prompt::{Prompt, TemplateEngine};
+use crate::template;
+
+/// Name of the template used to extract facts about entities.
+pub const ENTITY_TEMPLATE: &str = "entities";
+
+static DEFAULT_ENTITY: &str = r#"{{#chat}}
+{{#system}}You extract facts about the entities, such as people, organizations, places or products, referenced in the new messages of a conversation. Only extract facts the new messages state. Use the name of a known entity when the messages refer to it. Respond only with a JSON object mapping each entity name to a list of short facts about it, e.g. {"Alice": ["works at Acme"]}, or {} if there are none.{{/system}}
+{{#user}}{{#if entities}}Known entities: {{#each entities}}{{this}}; {{/each}}
+{{/if}}New messages:
+{{#each messages}}{{role}}: {{content}}
+{{/each}}{{/user}}
+{{/chat}}"#;
+
+/// Chat memory keeping facts about the entities referenced in the conversation.
+///
+/// After each turn, i.e. when a pipeline flushes the memory, an LLM extracts facts about the entities of
+/// the new messages into a map of entities to facts. The facts about the entities mentioned in the recent
+/// messages are then summarized in a system message at the start of the conversation, so that the model
+/// still knows them once the messages stating them are trimmed or far behind. A failed extraction is
+/// logged, and retried with the next turn.
+#[derive(Clone)]
+pub struct EntityMemory {
+    /// The LLM extracting the facts.
+    llm: Arc<dyn LLM>,
+
+    /// Template engine holding the extraction template.
+    template_engine: TemplateEngine,
+
+    /// The conversation, starting with the summary of the relevant entities if any.
+    conversation: ChatPrompt,
+
+    /// Whether the first message of the conversation is the summary.
+    summarized: bool,
+
+    /// Number of messages of the conversation, not counting the summary, facts were extracted from.
+    extracted: usize,
+
+    /// Facts by entity.
+    entities: BTreeMap<String, Vec<String>>,
+
+    /// Number of recent messages mentioning the entities whose facts are summarized.
+    window: usize,
+}
+
+impl EntityMemory {
+    /// Create an empty memory extracting facts with the given LLM, summarizing the entities mentioned in
+    /// the last 4 messages.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use orca_core::llm::openai::OpenAI;
+    /// # use orca_core::memory::entity::EntityMemory;
+    /// # use orca_core::pipeline::simple::LLMPipeline;
+    /// let extractor = OpenAI::new().with_model("gpt-3.5-turbo-1106").with_temperature(0.0);
+    /// let pipeline = LLMPipeline::new(&OpenAI::new())
+    ///     .load_template("chat", "{{#chat}}{{#user}}{{message}}{{/user}}{{/chat}}")
+    ///     .unwrap()
+    ///     .load_memory(EntityMemory::new(&extractor).with_window(6));
+    /// ```
+    pub fn new<M: LLM + Clone + 'static>(llm: &M) -> Self {
+        EntityMemory {
+            llm: Arc::new(llm.clone()),
+            template_engine: template!(ENTITY_TEMPLATE, DEFAULT_ENTITY),
+            conversation: ChatPrompt::default(),
+            summarized: false,
+            extracted: 0,
+            entities: BTreeMap::new(),
+            window: 4,
+        }
+    }
+
+    /// Set the number of recent messages the summarized entities must be mentioned in.
+    pub fn with_window(mut self, window: usize) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Override the extraction template, which receives `entities`, the names of the known entities, and
+    /// `messages` (with `role` and `content`). The model must respond with a JSON object mapping entity
+    /// names to lists of facts.
+    pub fn load_template(self, template: &str) -> Result<Self> {
+        Ok(Self {
+            template_engine: self.template_engine.register_template(ENTITY_TEMPLATE, template)?,
+            ..self
+        })
+    }
+
+    /// Facts by entity.
+    pub fn entities(&self) -> &BTreeMap<String, Vec<String>> {
+        &self.entities
+    }
+
+    /// Messages of the conversation, without the summary.
+    fn messages(&self) -> &[Message] {
+        &self.conversation.0[self.summarized as usize..]
+    }
+
+    /// Extract the facts stated in the messages not extracted yet.
+    async fn extract(&mut self) -> Result<()> {
+        let messages = self.messages()[self.extracted.min(self.messages().len())..]
+            .iter()
+            .filter(|message| matches!(message.role, Role::User | Role::Assistant))
+            .map(|message| json!({ "role": message.role.title(), "content": message.content }))
+            .collect::<Vec<_>>();
+        if messages.is_empty() {
+            return Ok(());
+        }
+        let context = json!({ "entities": self.entities.keys().collect::<Vec<_>>(), "messages": messages });
+        let prompt = self.template_engine.render_context(ENTITY_TEMPLATE, &context)?;
+        let response = self.llm.generate(prompt).await?.to_string();
+        let extracted: BTreeMap<String, Vec<String>> =
+            parse_json(&response).map_err(|e| anyhow!("Invalid entity extraction: {}", e))?;
+        for (entity, facts) in extracted {
+            self.add_facts(&entity, facts);
+        }
+        Ok(())
+    }
+
+    /// Add facts about an entity, under the name it is known by if its name only differs by case.
+    fn add_facts(&mut self, entity: &str, facts: Vec<String>) {
+        let entity = entity.trim();
+        if entity.is_empty() {
+            return;
+        }
+        let name = self.entities.keys().find(|name| name.to_lowercase() == entity.to_lowercase()).cloned();
+        let known = self.entities.entry(name.unwrap_or_else(|| entity.to_string())).or_default();
+        for fact in facts {
+            let fact = fact.trim().to_string();
+            if !fact.is_empty() && !known.iter().any(|known| known.to_lowercase() == fact.to_lowercase()) {
+                known.push(fact);
+            }
+        }
+    }
+
+    /// Summary of the facts about the entities mentioned in the recent messages, if any.
+    fn summary(&self) -> Option<String> {
+        let messages = self.messages();
+        let recent = messages[messages.len().saturating_sub(self.window)..]
+            .iter()
+            .map(|message| message.content.to_lowercase())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let lines = self
+            .entities
+            .iter()
+            .filter(|(entity, facts)| !facts.is_empty() && recent.contains(&entity.to_lowercase()))
+            .map(|(entity, facts)| format!("- {}: {}", entity, facts.join("; ")))
+            .collect::<Vec<_>>();
+        match lines.is_empty() {
+            true => None,
+            false => Some(format!(
+                "Known facts about the entities in the conversation:\n{}",
+                lines.join("\n")
+            )),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Memory for EntityMemory {
+    fn memory(&mut self) -> &mut dyn Prompt {
+        &mut self.conversation
+    }
+
+    /// Replace the conversation, whose facts are extracted on the next flush. Known facts are kept.
+    fn save_memory(&mut self, msgs: &dyn Prompt) -> Result<()> {
+        self.conversation = msgs.to_chat()?;
+        self.summarized = false;
+        self.extracted = 0;
+        Ok(())
+    }
+
+    /// Extract the facts of the new messages and summarize the relevant entities.
+    async fn flush(&mut self) -> Result<()> {
+        match self.extract().await {
+            Ok(()) => self.extracted = self.messages().len(),
+            Err(e) => log::warn!("Failed to extract entities from the conversation: {:#}", e),
+        }
+        if self.summarized {
+            self.conversation.0.remove(0);
+        }
+        let summary = self.summary();
+        self.summarized = summary.is_some();
+        if let Some(summary) = summary {
+            self.conversation.0.insert(0, Message::new(Role::System, &summary));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pipeline::simple::LLMPipeline;
+    use crate::pipeline::Pipeline;
+    use crate::testing::FixedLLM;
+
+    fn user(content: &str) -> Box<dyn Prompt> {
+        Box::new(ChatPrompt(vec![Message::new(Role::User, content)]))
+    }
+
+    #[tokio::test]
+    async fn test_entity_memory() {
+        let mut memory =
+            EntityMemory::new(&FixedLLM::new(r#"{"Alice": ["works at Acme", "likes tea"]}"#)).with_window(1);
+        memory.memory().save(user("Alice works at Acme and likes tea."));
+        memory.flush().await.unwrap();
+        let chat = memory.memory().to_chat().unwrap().to_vec();
+        assert_eq!(
+            chat[0],
+            Message::new(
+                Role::System,
+                "Known facts about the entities in the conversation:\n- Alice: works at Acme; likes tea"
+            )
+        );
+        assert_eq!(chat.len(), 2);
+
+        // Facts already known are not repeated, and entities that are not mentioned are not summarized.
+        memory.memory().save(user("What is the weather like?"));
+        memory.flush().await.unwrap();
+        let chat = memory.memory().to_chat().unwrap().to_vec();
+        assert_eq!(chat.len(), 2);
+        assert_eq!(chat[0].content, "Alice works at Acme and likes tea.");
+        assert_eq!(memory.entities()["Alice"].len(), 2);
+
+        memory.memory().save(user("Where does alice work?"));
+        memory.flush().await.unwrap();
+        assert_eq!(memory.memory().to_chat().unwrap().to_vec().len(), 4);
+
+        // Failed extractions are retried with the next turn.
+        let mut memory = EntityMemory::new(&FixedLLM::new("Alice works at Acme."));
+        memory.memory().save(user("Alice works at Acme."));
+        memory.flush().await.unwrap();
+        assert!(memory.entities().is_empty());
+        assert_eq!(memory.extracted, 0);
+    }
+
+    #[tokio::test]
+    async fn test_load_memory() {
+        let llm = FixedLLM::new(r#"{"Alice": ["works at Acme"]}"#);
+        let mut pipeline = LLMPipeline::new(&llm)
+            .load_template("chat", "{{#chat}}{{#user}}{{message}}{{/user}}{{/chat}}")
+            .unwrap()
+            .load_memory(EntityMemory::new(&llm));
+        pipeline.context().unwrap().set("message", "Alice works at Acme.").unwrap();
+        pipeline.execute("chat").await.unwrap();
+
+        // The facts of the turn are extracted before the model is called, and summarized in its prompt.
+        let prompts = llm.prompts();
+        assert!(prompts[0].contains("User: Alice works at Acme."));
+        assert!(prompts[1].contains("- Alice: works at Acme"));
+    }
+}
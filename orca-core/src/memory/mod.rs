@@ -0,0 +1,207 @@
+//! Conversation memories of pipelines.
+//!
+//! `Buffer` and `ChatBuffer` keep the conversation in memory, for the lifetime of the pipeline.
+//! `PersistentMemory` keeps the conversation of a session in a `ConversationStore`, a JSON file with
+//! `JsonFileStore` or SQLite with `SqliteConversationStore` (`sqlite` feature), so that it survives
+//! restarts. `RedisMemory` (`redis` feature) keeps it in Redis, shared by the instances of a deployment.
+//! `SessionManager` hands out the memories of many sessions and compacts the idle ones. `EntityMemory` keeps
+//! facts about the entities of the conversation and reminds the model of them.
+
+pub mod entity;
+pub mod persistent;
+#[cfg(feature = "redis")]
+pub mod redis;
+pub mod sessions;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+#[cfg(feature = "redis")]
+pub use self::redis::{RedisMemory, RedisStore};
+pub use entity::EntityMemory;
+pub use persistent::{ConversationStore, JsonFileStore, PersistentMemory};
+pub use sessions::SessionManager;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteConversationStore;
+
+use crate::pipeline::describe::type_name;
+use crate::prompt::chat::ChatPrompt;
+use crate::prompt::Prompt;
+
+use anyhow::Result;
+use std::fmt::{Display, Formatter};
+
+#[async_trait::async_trait]
+pub trait Memory: MemoryClone + Send + Sync {
+    /// Get the memory of the Memory Buffer.
+    fn memory(&mut self) -> &mut dyn Prompt;
+
+    /// Load a message into the Memory Buffer.
+    fn save_memory(&mut self, msgs: &dyn Prompt) -> Result<()>;
+
+    /// Export the memory as an OpenAI-style JSON conversation.
+    fn export(&mut self) -> Result<String> {
+        self.memory().to_chat()?.to_openai_json()
+    }
+
+    /// Replace the memory with a conversation in OpenAI-style JSON.
+    fn import(&mut self, json: &str) -> Result<()> {
+        self.save_memory(&ChatPrompt::from_openai_json(json)?)
+    }
+
+    /// Render the memory as a markdown transcript.
+    fn to_markdown(&mut self) -> Result<String> {
+        Ok(self.memory().to_chat()?.to_markdown())
+    }
+
+    /// Persist the changes made to the memory, for memories backed by a store. Pipelines call it after
+    /// saving messages in the memory. Does nothing by default.
+    async fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Kind of the memory, e.g. `ChatBuffer`, to describe the pipelines using it.
+    fn kind(&self) -> &'static str {
+        type_name::<Self>()
+    }
+}
+
+/// We do this to allow for cloning of Box<dyn Memory>.
+pub trait MemoryClone {
+    fn clone_box(&self) -> Box<dyn Memory>;
+}
+
+impl<T> MemoryClone for T
+where
+    T: Memory + Clone + 'static,
+{
+    fn clone_box(&self) -> Box<dyn Memory> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Memory> {
+    fn clone(&self) -> Box<dyn Memory> {
+        self.clone_box()
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct Buffer {
+    memory: String,
+}
+
+impl Buffer {
+    /// Initialize a new Memory Buffer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Memory for Buffer {
+    /// Get the memory of the Memory Buffer.
+    fn memory(&mut self) -> &mut dyn Prompt {
+        &mut self.memory
+    }
+
+    /// Load a message into the Memory Buffer.
+    fn save_memory(&mut self, msgs: &dyn Prompt) -> Result<()> {
+        self.memory = msgs.to_string();
+        Ok(())
+    }
+}
+
+impl Display for Buffer {
+    /// Display the memory of the Memory Buffer.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.memory)
+    }
+}
+
+impl Clone for Buffer {
+    fn clone(&self) -> Self {
+        Self {
+            memory: self.memory.clone(),
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct ChatBuffer {
+    memory: ChatPrompt,
+}
+
+impl ChatBuffer {
+    /// Initialize a new Memory Buffer.
+    pub fn new() -> Self {
+        Self {
+            memory: ChatPrompt(Vec::new()),
+        }
+    }
+
+    pub fn from_chat(chat: &ChatPrompt) -> Self {
+        Self { memory: chat.clone() }
+    }
+}
+
+impl Memory for ChatBuffer {
+    /// Get the memory of the Memory Buffer.
+    fn memory(&mut self) -> &mut dyn Prompt {
+        &mut self.memory
+    }
+
+    /// Load a message into the Memory Buffer.
+    fn save_memory(&mut self, msgs: &dyn Prompt) -> Result<()> {
+        self.memory = msgs.to_chat()?;
+        Ok(())
+    }
+}
+
+impl Clone for ChatBuffer {
+    fn clone(&self) -> Self {
+        Self {
+            memory: self.memory.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::llm::tools::ToolCall;
+    use crate::prompt::chat::{Image, Message, Role};
+
+    #[test]
+    fn test_export_import() {
+        let mut memory = ChatBuffer::from_chat(&ChatPrompt(vec![
+            Message::new(Role::User, "My name is Orca"),
+            Message::new(Role::Assistant, "Hello, Orca!"),
+        ]));
+        let json = memory.export().unwrap();
+
+        let mut imported = ChatBuffer::new();
+        imported.import(&json).unwrap();
+        assert_eq!(imported.memory().to_chat().unwrap(), memory.memory().to_chat().unwrap());
+    }
+
+    #[test]
+    fn test_export_import_tools_and_images() {
+        let call = ToolCall::new("call_1", "search", r#"{"pod": "J"}"#);
+        let mut memory = ChatBuffer::from_chat(&ChatPrompt(vec![
+            Message::new(Role::User, "Where was this orca seen?")
+                .with_image(Image::new("https://example.com/orca.png").with_detail("low")),
+            Message::new(Role::Assistant, "").with_tool_calls(vec![call.clone()]),
+            Message::tool_output(&call, "Orcas were seen near Seattle."),
+            Message::new(Role::Assistant, "Near Seattle."),
+        ]));
+        let json = memory.export().unwrap();
+
+        let value = serde_json::from_str::<serde_json::Value>(&json).unwrap();
+        assert_eq!(value["messages"][0]["content"][1]["type"], "image_url");
+        assert_eq!(value["messages"][1]["tool_calls"][0]["function"]["name"], "search");
+        assert_eq!(value["messages"][2]["tool_call_id"], "call_1");
+
+        let mut imported = ChatBuffer::new();
+        imported.import(&json).unwrap();
+        assert_eq!(imported.memory().to_chat().unwrap(), memory.memory().to_chat().unwrap());
+    }
+}
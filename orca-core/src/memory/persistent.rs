@@ -0,0 +1,197 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Result;
+use tokio::sync::Mutex;
+
+use super::Memory;
+use crate::prompt::chat::ChatPrompt;
+use crate::prompt::Prompt;
+
+/// Store of conversations keyed by session id.
+#[async_trait::async_trait]
+pub trait ConversationStore: Send + Sync {
+    /// Get the conversation of a session, if any.
+    async fn load(&self, session: &str) -> Result<Option<ChatPrompt>>;
+
+    /// Store the conversation of a session, replacing any conversation it had.
+    async fn save(&self, session: &str, conversation: &ChatPrompt) -> Result<()>;
+
+    /// Delete the conversation of a session, if any.
+    async fn delete(&self, session: &str) -> Result<()>;
+
+    /// Ids of the sessions with a conversation, in order.
+    async fn sessions(&self) -> Result<Vec<String>>;
+}
+
+/// Conversation store keeping the conversations of all sessions in a single JSON file, as an object
+/// mapping session ids to their messages.
+///
+/// Sessions sharing the store are written one at a time, each write replacing the file atomically. Writes
+/// are only coordinated within the store, so the file must not be shared by several processes.
+#[derive(Debug, Clone)]
+pub struct JsonFileStore {
+    path: PathBuf,
+    lock: Arc<Mutex<()>>,
+}
+
+impl JsonFileStore {
+    /// Create a store in the given file, which is created on the first save.
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        JsonFileStore {
+            path: path.as_ref().to_path_buf(),
+            lock: Arc::default(),
+        }
+    }
+
+    async fn read(&self) -> Result<BTreeMap<String, ChatPrompt>> {
+        match tokio::fs::read(&self.path).await {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn write(&self, conversations: &BTreeMap<String, ChatPrompt>) -> Result<()> {
+        let temporary = self.path.with_extension("tmp");
+        tokio::fs::write(&temporary, serde_json::to_vec_pretty(conversations)?).await?;
+        tokio::fs::rename(temporary, &self.path).await?;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl ConversationStore for JsonFileStore {
+    async fn load(&self, session: &str) -> Result<Option<ChatPrompt>> {
+        let _guard = self.lock.lock().await;
+        Ok(self.read().await?.remove(session))
+    }
+
+    async fn save(&self, session: &str, conversation: &ChatPrompt) -> Result<()> {
+        let _guard = self.lock.lock().await;
+        let mut conversations = self.read().await?;
+        conversations.insert(session.to_string(), conversation.clone());
+        self.write(&conversations).await
+    }
+
+    async fn delete(&self, session: &str) -> Result<()> {
+        let _guard = self.lock.lock().await;
+        let mut conversations = self.read().await?;
+        if conversations.remove(session).is_some() {
+            self.write(&conversations).await?;
+        }
+        Ok(())
+    }
+
+    async fn sessions(&self) -> Result<Vec<String>> {
+        let _guard = self.lock.lock().await;
+        Ok(self.read().await?.into_keys().collect())
+    }
+}
+
+/// Chat memory of a session, loaded from and saved to a conversation store.
+///
+/// Each user or conversation gets its own session id, and thus its own memory, while the memories share
+/// the store, so that a chatbot serves several users at once and resumes their conversations after a
+/// restart. Changes are kept in memory until `flush` is called, which pipelines do after saving messages.
+#[derive(Clone)]
+pub struct PersistentMemory {
+    store: Arc<dyn ConversationStore>,
+    session: String,
+    memory: ChatPrompt,
+    saved: ChatPrompt,
+}
+
+impl PersistentMemory {
+    /// Load the memory of a session from a store, starting an empty conversation for new sessions.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use orca_core::llm::openai::OpenAI;
+    /// # use orca_core::memory::{JsonFileStore, PersistentMemory};
+    /// # use orca_core::pipeline::simple::LLMPipeline;
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let store = Arc::new(JsonFileStore::new("conversations.json"));
+    /// let memory = PersistentMemory::load(store.clone(), "user-42").await?;
+    /// let pipeline = LLMPipeline::new(&OpenAI::new()).load_memory(memory);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn load(store: Arc<dyn ConversationStore>, session: &str) -> Result<Self> {
+        let memory = store.load(session).await?.unwrap_or_default();
+        Ok(PersistentMemory {
+            store,
+            session: session.to_string(),
+            saved: memory.clone(),
+            memory,
+        })
+    }
+
+    /// Id of the session.
+    pub fn session(&self) -> &str {
+        &self.session
+    }
+
+    /// Forget the conversation, deleting it from the store.
+    pub async fn clear(&mut self) -> Result<()> {
+        self.store.delete(&self.session).await?;
+        self.memory = ChatPrompt::default();
+        self.saved = ChatPrompt::default();
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Memory for PersistentMemory {
+    fn memory(&mut self) -> &mut dyn Prompt {
+        &mut self.memory
+    }
+
+    fn save_memory(&mut self, msgs: &dyn Prompt) -> Result<()> {
+        self.memory = msgs.to_chat()?;
+        Ok(())
+    }
+
+    /// Save the conversation in the store if it changed since it was loaded or last saved.
+    async fn flush(&mut self) -> Result<()> {
+        if self.memory != self.saved {
+            self.store.save(&self.session, &self.memory).await?;
+            self.saved = self.memory.clone();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prompt::chat::{Message, Role};
+
+    #[tokio::test]
+    async fn test_persistent_memory() {
+        let path = std::env::temp_dir().join(format!("orca-conversations-{}.json", uuid::Uuid::new_v4()));
+        let store: Arc<dyn ConversationStore> = Arc::new(JsonFileStore::new(&path));
+        let mut alice = PersistentMemory::load(store.clone(), "alice").await.unwrap();
+        let mut bob = PersistentMemory::load(store.clone(), "bob").await.unwrap();
+        alice.memory().save(Box::new(ChatPrompt(vec![Message::new(Role::User, "My name is Alice")])));
+        bob.memory().save(Box::new(ChatPrompt(vec![Message::new(Role::User, "My name is Bob")])));
+        alice.flush().await.unwrap();
+        bob.flush().await.unwrap();
+
+        // A new store on the same file, as after a restart, resumes each session.
+        let store: Arc<dyn ConversationStore> = Arc::new(JsonFileStore::new(&path));
+        let mut alice = PersistentMemory::load(store.clone(), "alice").await.unwrap();
+        assert_eq!(
+            alice.memory().to_chat().unwrap().to_vec(),
+            vec![Message::new(Role::User, "My name is Alice")]
+        );
+        assert_eq!(store.sessions().await.unwrap(), vec!["alice", "bob"]);
+
+        alice.clear().await.unwrap();
+        assert_eq!(store.sessions().await.unwrap(), vec!["bob"]);
+        std::fs::remove_file(path).unwrap();
+    }
+}
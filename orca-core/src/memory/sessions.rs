@@ -0,0 +1,262 @@
+//! Sessions of a chat server.
+//!
+//! `SessionManager` hands out the `PersistentMemory` of each session, keeping the memories of recent
+//! sessions in RAM. Long-running servers accumulate many sessions nobody comes back to, so the manager can
+//! compact idle sessions: their older messages are summarized into a single system message, the compacted
+//! conversation is saved in the store and the memory is dropped, to be loaded again on the next request.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+use super::{ConversationStore, Memory, PersistentMemory};
+use crate::pipeline::summarize::Summarize;
+use crate::prompt::chat::{ChatPrompt, Message, Role};
+use crate::record::{Content, Record};
+
+/// Metadata key set on the system message holding the summary of a compacted conversation.
+pub const SUMMARY_METADATA: &str = "summary";
+
+/// Compaction of idle sessions.
+struct Compaction {
+    summarizer: Arc<dyn Summarize>,
+    idle: Duration,
+    keep: usize,
+}
+
+/// Memory of a session held by the manager.
+struct Session {
+    memory: Arc<Mutex<PersistentMemory>>,
+    last_used: Instant,
+}
+
+/// Hands out the memories of sessions sharing a conversation store, and optionally compacts idle ones.
+pub struct SessionManager {
+    store: Arc<dyn ConversationStore>,
+    sessions: Mutex<HashMap<String, Session>>,
+    compaction: Option<Compaction>,
+}
+
+impl SessionManager {
+    /// Create a manager of the sessions of a store. Memories are kept in RAM until `evict` is called, or
+    /// until they are compacted if `with_compaction` is set.
+    pub fn new(store: Arc<dyn ConversationStore>) -> Self {
+        SessionManager {
+            store,
+            sessions: Mutex::default(),
+            compaction: None,
+        }
+    }
+
+    /// Compact the sessions left unused for `idle`, summarizing all but their last 4 messages with the
+    /// summarizer. Compaction runs when `compact` is called, periodically when run by `spawn_compactor`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use std::time::Duration;
+    /// # use orca_core::llm::openai::OpenAI;
+    /// # use orca_core::memory::{JsonFileStore, SessionManager};
+    /// # use orca_core::pipeline::simple::LLMPipeline;
+    /// # use orca_core::pipeline::summarize::Summarizer;
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let client = OpenAI::new();
+    /// let sessions = Arc::new(
+    ///     SessionManager::new(Arc::new(JsonFileStore::new("conversations.json")))
+    ///         .with_compaction(Arc::new(Summarizer::new(&client)), Duration::from_secs(30 * 60)),
+    /// );
+    /// let _compactor = sessions.spawn_compactor(Duration::from_secs(60));
+    ///
+    /// let memory = sessions.session("user-42").await?;
+    /// let pipeline = LLMPipeline::new(&client).load_shared_memory(memory);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_compaction(mut self, summarizer: Arc<dyn Summarize>, idle: Duration) -> Self {
+        self.compaction = Some(Compaction {
+            summarizer,
+            idle,
+            keep: 4,
+        });
+        self
+    }
+
+    /// Set the number of most recent messages kept verbatim when a session is compacted, 4 by default.
+    /// Does nothing unless `with_compaction` is set.
+    pub fn with_keep(mut self, keep: usize) -> Self {
+        if let Some(compaction) = self.compaction.as_mut() {
+            compaction.keep = keep;
+        }
+        self
+    }
+
+    /// Memory of a session, loaded from the store if it is not in RAM.
+    pub async fn session(&self, session: &str) -> Result<Arc<Mutex<PersistentMemory>>> {
+        let mut sessions = self.sessions.lock().await;
+        if let Some(held) = sessions.get_mut(session) {
+            held.last_used = Instant::now();
+            return Ok(held.memory.clone());
+        }
+        let memory = Arc::new(Mutex::new(PersistentMemory::load(self.store.clone(), session).await?));
+        sessions.insert(
+            session.to_string(),
+            Session {
+                memory: memory.clone(),
+                last_used: Instant::now(),
+            },
+        );
+        Ok(memory)
+    }
+
+    /// Ids of the sessions held in RAM.
+    pub async fn loaded(&self) -> Vec<String> {
+        self.sessions.lock().await.keys().cloned().collect()
+    }
+
+    /// Save the memory of a session and drop it from RAM. Does nothing if the session is not loaded.
+    pub async fn evict(&self, session: &str) -> Result<()> {
+        let held = self.sessions.lock().await.remove(session);
+        if let Some(held) = held {
+            held.memory.lock().await.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Compact the sessions idle for longer than the compaction threshold and drop them from RAM, returning
+    /// the number of sessions compacted. Sessions whose memory is still held elsewhere, e.g. by a running
+    /// pipeline, are left alone. Does nothing unless `with_compaction` is set.
+    pub async fn compact(&self) -> Result<usize> {
+        let Some(compaction) = &self.compaction else {
+            return Ok(0);
+        };
+
+        let idle = self
+            .sessions
+            .lock()
+            .await
+            .iter()
+            .filter(|(_, held)| held.last_used.elapsed() >= compaction.idle)
+            .filter(|(_, held)| Arc::strong_count(&held.memory) == 1)
+            .map(|(id, held)| (id.clone(), held.memory.clone()))
+            .collect::<Vec<_>>();
+
+        // The memories stay in the manager while they are compacted, so that requests for them meanwhile
+        // wait for the compaction and keep them in RAM instead of loading a stale copy from the store.
+        let mut compacted = 0;
+        for (id, memory) in idle {
+            if let Err(e) = compact_memory(&mut *memory.lock().await, compaction).await {
+                log::warn!("Failed to compact session {}: {}", id, e);
+                continue;
+            }
+            let mut sessions = self.sessions.lock().await;
+            // Two references: the manager's and ours.
+            if sessions.get(&id).is_some_and(|held| Arc::strong_count(&held.memory) == 2) {
+                sessions.remove(&id);
+            }
+            compacted += 1;
+        }
+        Ok(compacted)
+    }
+
+    /// Compact idle sessions every `interval` in a background task, which stops when the returned handle is
+    /// dropped.
+    pub fn spawn_compactor(self: &Arc<Self>, interval: Duration) -> Compactor {
+        let manager = self.clone();
+        let task = tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            loop {
+                ticks.tick().await;
+                if let Err(e) = manager.compact().await {
+                    log::warn!("Failed to compact idle sessions: {}", e);
+                }
+            }
+        });
+        Compactor { task }
+    }
+}
+
+/// Summarize all but the most recent messages of a memory into a system message and save it.
+async fn compact_memory(memory: &mut PersistentMemory, compaction: &Compaction) -> Result<()> {
+    let messages = memory.memory().to_chat()?.to_vec();
+    if messages.len() > compaction.keep + 1 {
+        let (older, recent) = messages.split_at(messages.len() - compaction.keep);
+        let transcript = ChatPrompt(older.to_vec()).to_markdown();
+        let summary = compaction.summarizer.summarize(&[Record::new(Content::String(transcript))]).await?;
+
+        let mut compacted = vec![Message::new(
+            Role::System,
+            &format!("Summary of the earlier conversation:\n{}", summary.content()),
+        )
+        .with_metadata(SUMMARY_METADATA, true)];
+        compacted.extend_from_slice(recent);
+        memory.save_memory(&ChatPrompt(compacted))?;
+    }
+    memory.flush().await
+}
+
+/// Handle on a task compacting idle sessions, stopped when the handle is dropped.
+pub struct Compactor {
+    task: JoinHandle<()>,
+}
+
+impl Drop for Compactor {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory::JsonFileStore;
+    use crate::pipeline::PipelineResult;
+
+    /// Summarizer returning the number of lines it was given.
+    struct Lines;
+
+    #[async_trait::async_trait]
+    impl Summarize for Lines {
+        async fn summarize(&self, records: &[Record]) -> Result<PipelineResult> {
+            let lines = records[0].content.to_string().lines().filter(|l| !l.is_empty()).count();
+            Ok(PipelineResult::new("summary".to_string())
+                .with_llm_response(crate::llm::LLMResponse::Quantized(format!("{} lines", lines))))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_compact() {
+        let path = std::env::temp_dir().join(format!("orca-sessions-{}.json", uuid::Uuid::new_v4()));
+        let store: Arc<dyn ConversationStore> = Arc::new(JsonFileStore::new(&path));
+        let sessions = SessionManager::new(store.clone()).with_compaction(Arc::new(Lines), Duration::ZERO).with_keep(2);
+
+        let memory = sessions.session("alice").await.unwrap();
+        {
+            let mut memory = memory.lock().await;
+            let messages = (0..6).map(|i| Message::new(Role::User, &format!("message {}", i))).collect();
+            memory.save_memory(&ChatPrompt(messages)).unwrap();
+        }
+
+        // Sessions in use are not compacted.
+        assert_eq!(sessions.compact().await.unwrap(), 0);
+        drop(memory);
+        assert_eq!(sessions.compact().await.unwrap(), 1);
+        assert!(sessions.loaded().await.is_empty());
+
+        let mut memory = PersistentMemory::load(store, "alice").await.unwrap();
+        let messages = memory.memory().to_chat().unwrap().to_vec();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0].role, Role::System);
+        assert_eq!(messages[0].metadata[SUMMARY_METADATA], true);
+        assert_eq!(messages[2].content, "message 5");
+
+        // The compacted session is loaded again on the next request.
+        let memory = sessions.session("alice").await.unwrap();
+        assert_eq!(memory.lock().await.memory().to_chat().unwrap().to_vec(), messages);
+        std::fs::remove_file(path).unwrap();
+    }
+}
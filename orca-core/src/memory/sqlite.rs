@@ -0,0 +1,99 @@
+use anyhow::Result;
+
+use super::ConversationStore;
+use crate::docstore::check_id;
+use crate::prompt::chat::ChatPrompt;
+
+/// Conversation store keeping the messages of each session as JSON in a SQLite table.
+pub struct SqliteConversationStore {
+    pool: sqlx::SqlitePool,
+    table: String,
+}
+
+impl SqliteConversationStore {
+    /// Connect to a SQLite database, e.g. `sqlite://conversations.db?mode=rwc`, and create the
+    /// `conversations` table if it does not exist.
+    pub async fn connect(url: &str) -> Result<Self> {
+        Self::from_pool(sqlx::SqlitePool::connect(url).await?, "conversations").await
+    }
+
+    /// Create a store in the given table of an existing pool, creating the table if it does not exist.
+    pub async fn from_pool(pool: sqlx::SqlitePool, table: &str) -> Result<Self> {
+        check_id(table)?;
+        let create = format!(
+            "CREATE TABLE IF NOT EXISTS {} (session TEXT PRIMARY KEY NOT NULL, messages TEXT NOT NULL)",
+            table
+        );
+        sqlx::query(&create).execute(&pool).await?;
+        Ok(SqliteConversationStore {
+            pool,
+            table: table.to_string(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl ConversationStore for SqliteConversationStore {
+    async fn load(&self, session: &str) -> Result<Option<ChatPrompt>> {
+        let messages: Option<String> =
+            sqlx::query_scalar(&format!("SELECT messages FROM {} WHERE session = ?", self.table))
+                .bind(session)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(messages.map(|messages| serde_json::from_str(&messages)).transpose()?)
+    }
+
+    async fn save(&self, session: &str, conversation: &ChatPrompt) -> Result<()> {
+        sqlx::query(&format!(
+            "INSERT OR REPLACE INTO {} (session, messages) VALUES (?, ?)",
+            self.table
+        ))
+        .bind(session)
+        .bind(serde_json::to_string(conversation)?)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, session: &str) -> Result<()> {
+        sqlx::query(&format!("DELETE FROM {} WHERE session = ?", self.table))
+            .bind(session)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn sessions(&self) -> Result<Vec<String>> {
+        let sessions = sqlx::query_scalar(&format!("SELECT session FROM {} ORDER BY session", self.table))
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(sessions)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory::{Memory, PersistentMemory};
+    use crate::prompt::chat::{Message, Role};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_sqlite_conversation_store() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new().max_connections(1).connect("sqlite::memory:").await.unwrap();
+        let store: Arc<dyn ConversationStore> =
+            Arc::new(SqliteConversationStore::from_pool(pool, "conversations").await.unwrap());
+        let mut memory = PersistentMemory::load(store.clone(), "alice").await.unwrap();
+        memory.memory().save(Box::new(ChatPrompt(vec![Message::new(Role::User, "Hello")])));
+        memory.flush().await.unwrap();
+
+        let mut memory = PersistentMemory::load(store.clone(), "alice").await.unwrap();
+        assert_eq!(
+            memory.memory().to_chat().unwrap().to_vec(),
+            vec![Message::new(Role::User, "Hello")]
+        );
+        assert_eq!(store.sessions().await.unwrap(), vec!["alice"]);
+        store.delete("alice").await.unwrap();
+        assert!(store.load("alice").await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,201 @@
+//! Two-stage retrieval through document abstracts.
+//!
+//! On very large corpora, the chunks nearest to a query are often scattered over many loosely related
+//! documents. An `IngestPipeline` with abstracts stores a summary of each document in a separate
+//! collection; a `TwoStageRetriever` first searches the abstracts for the most relevant documents, then
+//! searches only the chunks of those documents, matched by their `document_id` attribute.
+
+use crate::llm::Embedding;
+use crate::pipeline::ingest::attribute_field;
+use crate::prompt;
+use crate::record::Record;
+use crate::vectorstore::{Filter, SearchQuery, VectorStore};
+
+use anyhow::Result;
+use serde_json::Value as JsonValue;
+use tracing::field::Empty;
+use tracing::Span;
+
+/// Attribute holding the id of the document a chunk or an abstract belongs to.
+pub const DOCUMENT_ID_ATTRIBUTE: &str = "document_id";
+
+/// Retriever searching the abstracts of the documents, then the chunks of the best documents.
+pub struct TwoStageRetriever<'a, E, S: ?Sized> {
+    /// Model embedding the queries.
+    embedder: &'a E,
+
+    /// Vector store holding the abstracts and the chunks.
+    store: &'a S,
+
+    /// Name of the collection of abstracts.
+    abstracts: String,
+
+    /// Name of the collection of chunks.
+    chunks: String,
+
+    /// Maximum number of documents whose chunks are searched.
+    documents: usize,
+
+    /// Maximum number of chunks returned.
+    limit: usize,
+
+    /// Conditions the abstracts and chunks must satisfy.
+    filters: Vec<Filter>,
+}
+
+impl<'a, E, S> TwoStageRetriever<'a, E, S>
+where
+    E: Embedding + Send + Sync,
+    S: VectorStore + ?Sized,
+{
+    /// Create a retriever returning the 10 chunks nearest to each query among the chunks of the 3
+    /// documents whose abstracts are nearest to it.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use orca_core::llm::bert::Bert;
+    /// # use orca_core::pipeline::abstracts::TwoStageRetriever;
+    /// # use orca_core::qdrant::Qdrant;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let qdrant = Qdrant::new("http://localhost:6334").unwrap();
+    /// let bert = Bert::new().build_model_and_tokenizer().await?;
+    /// let retriever = TwoStageRetriever::new(&bert, &qdrant, "abstracts", "chunks").with_documents(5);
+    /// let records = retriever.retrieve("Where do orcas live?").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new(embedder: &'a E, store: &'a S, abstracts: &str, chunks: &str) -> Self {
+        TwoStageRetriever {
+            embedder,
+            store,
+            abstracts: abstracts.to_string(),
+            chunks: chunks.to_string(),
+            documents: 3,
+            limit: 10,
+            filters: Vec::new(),
+        }
+    }
+
+    /// Set the maximum number of documents whose chunks are searched.
+    pub fn with_documents(mut self, documents: usize) -> Self {
+        self.documents = documents;
+        self
+    }
+
+    /// Set the maximum number of chunks returned.
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Add a condition the abstracts and chunks must satisfy. Abstracts have the attributes of their
+    /// document.
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    /// Retrieve the chunks most similar to the query among those of the documents whose abstracts are
+    /// most similar to it.
+    #[tracing::instrument(name = "retrieval", skip_all, fields(collection = %self.chunks, limit = self.limit, hits = Empty))]
+    pub async fn retrieve(&self, query: &str) -> Result<Vec<Record>> {
+        let vector = self.embedder.generate_embedding(prompt!(query)).await?.to_vec()?;
+        let mut search = SearchQuery::new(vector.clone()).with_limit(self.documents);
+        search.filters = self.filters.clone();
+        let abstracts = self.store.search(&self.abstracts, search).await?;
+        let ids: Vec<JsonValue> = abstracts
+            .iter()
+            .filter_map(|hit| hit.payload.get("attributes")?.get(DOCUMENT_ID_ATTRIBUTE).cloned())
+            .collect();
+        if ids.is_empty() {
+            Span::current().record("hits", 0);
+            return Ok(Vec::new());
+        }
+
+        let mut search = SearchQuery::new(vector).with_limit(self.limit);
+        search.filters = self.filters.clone();
+        search.filters.push(Filter::MatchesAny(attribute_field(DOCUMENT_ID_ATTRIBUTE), ids));
+        let hits = self.store.search(&self.chunks, search).await?;
+        Span::current().record("hits", hits.len());
+        Ok(hits.iter().filter_map(|hit| hit.to_record()).collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::llm::{Embeddings, LLMResponse, LLM};
+    use crate::pipeline::ingest::IngestPipeline;
+    use crate::pipeline::summarize::Summarizer;
+    use crate::prompt::Prompt;
+    use crate::record::Content;
+    use crate::vectorstore::memory::MemoryStore;
+
+    /// Embedder counting the mentions of orcas and salmon.
+    struct Keywords;
+
+    #[async_trait::async_trait]
+    impl Embedding for Keywords {
+        async fn generate_embedding(&self, prompt: Box<dyn Prompt>) -> Result<Embeddings> {
+            self.generate_embeddings(vec![prompt]).await
+        }
+
+        async fn generate_embeddings(&self, prompts: Vec<Box<dyn Prompt>>) -> Result<Embeddings> {
+            let vectors = prompts
+                .iter()
+                .map(|prompt| {
+                    let text = prompt.to_string().to_lowercase();
+                    vec![
+                        text.matches("orca").count() as f32 + 0.1,
+                        text.matches("salmon").count() as f32 + 0.1,
+                    ]
+                })
+                .collect();
+            Embeddings::new("keywords", vectors)
+        }
+
+        fn dimensions(&self) -> usize {
+            2
+        }
+    }
+
+    /// LLM summarizing a text as its first sentence.
+    #[derive(Clone)]
+    struct FirstSentence;
+
+    #[async_trait::async_trait]
+    impl LLM for FirstSentence {
+        async fn generate(&self, prompt: Box<dyn Prompt>) -> Result<LLMResponse> {
+            let text = prompt.to_chat()?.to_vec().pop().map(|m| m.content).unwrap_or_default();
+            Ok(LLMResponse::Quantized(
+                text.split('.').next().unwrap_or_default().to_string(),
+            ))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retrieve() {
+        let store = MemoryStore::new();
+        let summarizer = Summarizer::new(&FirstSentence);
+        let documents = vec![
+            Record::new(Content::String("All about orcas. Salmon is their food.".to_string())),
+            Record::new(Content::String("All about salmon. Salmon swim upstream.".to_string())),
+        ];
+        IngestPipeline::new(&store, &Keywords, "chunks")
+            .with_abstracts(&summarizer, "abstracts")
+            .ingest(documents)
+            .await
+            .unwrap();
+        assert_eq!(store.len("abstracts"), Some(2));
+
+        let retriever = TwoStageRetriever::new(&Keywords, &store, "abstracts", "chunks").with_documents(1);
+        let records = retriever.retrieve("orca").await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].content.to_string(), "All about orcas. Salmon is their food.");
+        assert!(records[0].attributes.contains_key(DOCUMENT_ID_ATTRIBUTE));
+
+        let retriever = TwoStageRetriever::new(&Keywords, &store, "abstracts", "chunks").with_documents(2);
+        assert_eq!(retriever.retrieve("salmon").await.unwrap().len(), 2);
+    }
+}
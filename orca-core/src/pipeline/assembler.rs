@@ -0,0 +1,233 @@
+//! Assembly of retrieved records into the documents block of a RAG prompt.
+//!
+//! A `ContextAssembler` merges overlapping chunks of the same record, removes duplicate records, numbers
+//! the rest in order of relevance, tags each with its source and page, and keeps as many as fit in a token
+//! budget. The resulting block is set
+//! under the `documents` key of a pipeline context, so templates render it with `{{documents}}` and
+//! the model can cite documents by number.
+
+use super::context::PipelineContext;
+use super::simple::DOCUMENTS_KEY;
+use crate::prompt::budget::truncate_tokens;
+use crate::prompt::estimate_tokens;
+use crate::record::{dedup, Record};
+
+use anyhow::Result;
+
+/// Attribute holding the source of a record, used when the record has no header.
+pub const SOURCE_ATTRIBUTE: &str = "source";
+
+/// Attribute holding the page a record was extracted from.
+pub const PAGE_ATTRIBUTE: &str = "page";
+
+/// Attribute holding the similarity score of a retrieved record.
+pub const SCORE_ATTRIBUTE: &str = "score";
+
+/// Maximum number of characters between two chunks merged into a passage, i.e. the whitespace trimmed
+/// between the chunks of a paragraph or of consecutive paragraphs.
+const MERGE_MAX_GAP: usize = 2;
+
+/// Formats retrieved records into a numbered documents block.
+#[derive(Debug, Clone)]
+pub struct ContextAssembler {
+    /// Context key the block is set under.
+    key: String,
+
+    /// Maximum number of tokens of the block.
+    max_tokens: Option<usize>,
+
+    /// Whether records with the same content are removed.
+    dedup: bool,
+
+    /// Whether overlapping or adjacent chunks of the same record are merged.
+    merge: bool,
+
+    /// Minimum similarity score of the records kept.
+    min_score: Option<f32>,
+}
+
+impl Default for ContextAssembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ContextAssembler {
+    /// Create an assembler setting the `documents` key, merging overlapping chunks, removing duplicates and
+    /// without a token limit.
+    pub fn new() -> Self {
+        ContextAssembler {
+            key: DOCUMENTS_KEY.to_string(),
+            max_tokens: None,
+            dedup: true,
+            merge: true,
+            min_score: None,
+        }
+    }
+
+    /// Set the context key the block is set under.
+    pub fn with_key(mut self, key: &str) -> Self {
+        self.key = key.to_string();
+        self
+    }
+
+    /// Limit the block to the given number of tokens. The least relevant documents are dropped first,
+    /// and the last document kept is truncated to fill the limit.
+    pub fn with_max_tokens(mut self, max_tokens: usize) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    /// Set whether records with the same content are removed.
+    pub fn with_dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    /// Set whether chunks of the same record that overlap or follow each other are merged into a single
+    /// passage, see `dedup::overlapping`. Chunks are located by the offsets `Record::split` sets, so chunks
+    /// without offsets are never merged.
+    pub fn with_merge(mut self, merge: bool) -> Self {
+        self.merge = merge;
+        self
+    }
+
+    /// Drop records whose `score` attribute is below the given floor, so that irrelevant hits are not
+    /// added to the prompt when nothing in the corpus matches well. Records without a score are kept.
+    pub fn with_min_score(mut self, min_score: f32) -> Self {
+        self.min_score = Some(min_score);
+        self
+    }
+
+    /// Format records, most relevant first, into a documents block.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::pipeline::assembler::ContextAssembler;
+    /// use orca_core::record::{Content, Record};
+    ///
+    /// let records = vec![
+    ///     Record::new(Content::String("Orcas are toothed whales.".to_string()))
+    ///         .with_header("orcas.pdf".to_string())
+    ///         .with_attribute("page", 3),
+    ///     Record::new(Content::String("They live in pods.".to_string())),
+    /// ];
+    /// assert_eq!(
+    ///     ContextAssembler::new().format(records),
+    ///     "[1] (orcas.pdf, page 3)\nOrcas are toothed whales.\n\n[2]\nThey live in pods."
+    /// );
+    /// ```
+    pub fn format(&self, records: Vec<Record>) -> String {
+        let records = match self.merge {
+            true => dedup::overlapping(records, MERGE_MAX_GAP),
+            false => records,
+        };
+        let records = match self.dedup {
+            true => dedup::exact(records),
+            false => records,
+        };
+        let records = records.into_iter().filter(|record| match (self.min_score, score(record)) {
+            (Some(min_score), Some(score)) => score >= min_score,
+            _ => true,
+        });
+        let mut block = String::new();
+        for (index, record) in records.enumerate() {
+            let separator = if block.is_empty() { "" } else { "\n\n" };
+            let document = match source(&record) {
+                Some(source) => format!("{}[{}] ({})\n{}", separator, index + 1, source, record.content),
+                None => format!("{}[{}]\n{}", separator, index + 1, record.content),
+            };
+            if let Some(max_tokens) = self.max_tokens {
+                let remaining = max_tokens.saturating_sub(estimate_tokens(&block));
+                if estimate_tokens(&document) > remaining {
+                    block.push_str(&truncate_tokens(&document, remaining));
+                    break;
+                }
+            }
+            block.push_str(&document);
+        }
+        block
+    }
+
+    /// Format records, most relevant first, and set the block in a pipeline context.
+    pub fn assemble(&self, records: Vec<Record>, context: &mut PipelineContext) -> Result<()> {
+        context.set(&self.key, self.format(records))
+    }
+}
+
+/// Similarity score of a retrieved record.
+fn score(record: &Record) -> Option<f32> {
+    record.attributes.get(SCORE_ATTRIBUTE).and_then(|score| score.as_f64()).map(|score| score as f32)
+}
+
+/// Source tag of a record, from its header or source attribute and its page.
+fn source(record: &Record) -> Option<String> {
+    let name = record
+        .header
+        .clone()
+        .or_else(|| record.attributes.get(SOURCE_ATTRIBUTE).and_then(|source| source.as_str()).map(String::from));
+    let page = record.attributes.get(PAGE_ATTRIBUTE).map(|page| match page {
+        serde_json::Value::String(page) => format!("page {}", page),
+        page => format!("page {}", page),
+    });
+    match (name, page) {
+        (Some(name), Some(page)) => Some(format!("{}, {}", name, page)),
+        (name, page) => name.or(page),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::record::Content;
+
+    fn record(content: &str) -> Record {
+        Record::new(Content::String(content.to_string()))
+    }
+
+    #[test]
+    fn test_format() {
+        let records = vec![
+            record("Orcas are toothed whales.").with_attribute("source", "wiki").with_attribute("page", 1),
+            record("orcas are  toothed whales."),
+            record("They live in pods.").with_attribute("page", "iv"),
+            record(&"a".repeat(400)),
+        ];
+
+        let assembler = ContextAssembler::new().with_max_tokens(30);
+        let block = assembler.format(records.clone());
+        assert!(block.starts_with(
+            "[1] (wiki, page 1)\nOrcas are toothed whales.\n\n[2] (page iv)\nThey live in pods.\n\n[3]\naaa"
+        ));
+        assert!(estimate_tokens(&block) <= 30);
+
+        let mut context = PipelineContext::new();
+        ContextAssembler::new().with_dedup(false).assemble(records, &mut context).unwrap();
+        let documents = context.get(DOCUMENTS_KEY).unwrap().as_str().unwrap();
+        assert!(documents.contains("[2]\norcas are  toothed whales."));
+        assert!(documents.contains("[4]\naaaa"));
+    }
+
+    #[test]
+    fn test_merge() {
+        let document = record("Orcas are toothed whales. They live in pods.").with_header("orcas.txt".to_string());
+        let mut chunks = document.split(30);
+        chunks.reverse();
+        assert_eq!(chunks.len(), 2);
+        let block = ContextAssembler::new().format(chunks.clone());
+        assert_eq!(block, "[1] (orcas.txt)\nOrcas are toothed whales. They live in pods.");
+        let block = ContextAssembler::new().with_merge(false).format(chunks);
+        assert!(block.contains("[2] (orcas.txt)"));
+    }
+
+    #[test]
+    fn test_min_score() {
+        let records = vec![
+            record("Orcas are toothed whales.").with_attribute(SCORE_ATTRIBUTE, 0.9),
+            record("Bananas are berries.").with_attribute(SCORE_ATTRIBUTE, 0.2),
+            record("They live in pods."),
+        ];
+        let block = ContextAssembler::new().with_min_score(0.5).format(records);
+        assert_eq!(block, "[1]\nOrcas are toothed whales.\n\n[2]\nThey live in pods.");
+    }
+}
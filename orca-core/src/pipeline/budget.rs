@@ -0,0 +1,235 @@
+//! Budgets bounding the tokens, cost and wall time of a pipeline execution.
+//!
+//! A pipeline given a `Budget` tracks the tokens its LLM calls use and the time it has been running.
+//! Before each call, the tokens of the prompt are estimated; a call that would go over the token or
+//! cost limit is made with the cheaper fallback model of the pipeline if it has one, and otherwise the
+//! execution stops with an `OrcaError::Budget` error. Calls are cut short once the wall time runs out.
+//! The consumption of the budget is recorded in the `budget` metadata of the result.
+
+use std::fmt::Display;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value as JsonValue};
+
+/// Limits of a pipeline execution. Unset limits are not enforced.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Budget {
+    /// Maximum number of prompt and completion tokens over all LLM calls.
+    pub max_tokens: Option<u32>,
+
+    /// Maximum cost over all LLM calls, in the currency of `cost_per_1k_tokens`.
+    pub max_cost: Option<f64>,
+
+    /// Maximum time the execution may take.
+    pub max_wall_time: Option<Duration>,
+
+    /// Cost of a thousand tokens, used to compute the cost of the calls.
+    pub cost_per_1k_tokens: f64,
+}
+
+impl Budget {
+    /// Create a budget without limits.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the maximum number of tokens.
+    pub fn with_max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    /// Set the maximum cost, given the cost of a thousand tokens, e.g. `with_max_cost(0.05, 0.002)` for
+    /// 5 cents of a model billed $0.002 per thousand tokens.
+    pub fn with_max_cost(mut self, max_cost: f64, cost_per_1k_tokens: f64) -> Self {
+        self.max_cost = Some(max_cost);
+        self.cost_per_1k_tokens = cost_per_1k_tokens;
+        self
+    }
+
+    /// Set the maximum wall time.
+    pub fn with_max_wall_time(mut self, max_wall_time: Duration) -> Self {
+        self.max_wall_time = Some(max_wall_time);
+        self
+    }
+
+    fn cost(&self, tokens: u32) -> f64 {
+        tokens as f64 / 1000.0 * self.cost_per_1k_tokens
+    }
+}
+
+/// Consumption of a budget.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct BudgetUsage {
+    /// Tokens used by the LLM calls.
+    pub tokens: u32,
+
+    /// Cost of the LLM calls.
+    pub cost: f64,
+
+    /// Time the execution took.
+    pub wall_time: Duration,
+
+    /// Number of LLM calls.
+    pub calls: usize,
+
+    /// Whether calls were made with the fallback model because the budget was exceeded.
+    pub degraded: bool,
+}
+
+impl BudgetUsage {
+    /// The usage as JSON, as recorded in the metadata of pipeline results.
+    pub fn to_json(&self) -> JsonValue {
+        json!({
+            "tokens": self.tokens,
+            "cost": self.cost,
+            "wall_time_ms": self.wall_time.as_millis() as u64,
+            "calls": self.calls,
+            "degraded": self.degraded,
+        })
+    }
+}
+
+/// Limit of a budget that an execution went over.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BudgetExceeded {
+    /// The tokens used, including those estimated for the next call, are over the limit.
+    Tokens { used: u32, limit: u32 },
+
+    /// The cost, including the one estimated for the next call, is over the limit.
+    Cost { used: f64, limit: f64 },
+
+    /// The execution ran out of time.
+    WallTime { elapsed: Duration, limit: Duration },
+}
+
+impl Display for BudgetExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BudgetExceeded::Tokens { used, limit } => {
+                write!(f, "Budget exceeded: {} tokens, more than the limit of {}", used, limit)
+            }
+            BudgetExceeded::Cost { used, limit } => {
+                write!(
+                    f,
+                    "Budget exceeded: cost of {:.4}, more than the limit of {:.4}",
+                    used, limit
+                )
+            }
+            BudgetExceeded::WallTime { elapsed, limit } => write!(
+                f,
+                "Budget exceeded: ran for {:?}, more than the limit of {:?}",
+                elapsed, limit
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BudgetExceeded {}
+
+/// Tracks the consumption of a budget over an execution, started when the tracker is created.
+#[derive(Debug, Clone)]
+pub struct BudgetTracker {
+    budget: Budget,
+    start: Instant,
+    usage: BudgetUsage,
+}
+
+impl BudgetTracker {
+    /// Start tracking an execution.
+    pub fn new(budget: Budget) -> Self {
+        BudgetTracker {
+            budget,
+            start: Instant::now(),
+            usage: BudgetUsage::default(),
+        }
+    }
+
+    /// Check that a call expected to use the given number of tokens fits in the budget.
+    pub fn check(&self, tokens: u32) -> Result<(), BudgetExceeded> {
+        let used = self.usage.tokens.saturating_add(tokens);
+        if let Some(limit) = self.budget.max_tokens.filter(|limit| used > *limit) {
+            return Err(BudgetExceeded::Tokens { used, limit });
+        }
+        let cost = self.budget.cost(used);
+        if let Some(limit) = self.budget.max_cost.filter(|limit| cost > *limit) {
+            return Err(BudgetExceeded::Cost { used: cost, limit });
+        }
+        let elapsed = self.start.elapsed();
+        if let Some(limit) = self.budget.max_wall_time.filter(|limit| elapsed >= *limit) {
+            return Err(BudgetExceeded::WallTime { elapsed, limit });
+        }
+        Ok(())
+    }
+
+    /// Record a call that used the given number of tokens.
+    pub fn record(&mut self, tokens: u32) {
+        self.usage.tokens = self.usage.tokens.saturating_add(tokens);
+        self.usage.cost = self.budget.cost(self.usage.tokens);
+        self.usage.calls += 1;
+    }
+
+    /// Record that the budget was exceeded and calls are made with the fallback model.
+    pub fn degrade(&mut self) {
+        self.usage.degraded = true;
+    }
+
+    /// Run a future, failing if it does not complete in the remaining wall time.
+    pub async fn run<F: Future>(&self, future: F) -> Result<F::Output, BudgetExceeded> {
+        match self.budget.max_wall_time {
+            Some(limit) => {
+                let remaining = limit.saturating_sub(self.start.elapsed());
+                tokio::time::timeout(remaining, future).await.map_err(|_| BudgetExceeded::WallTime {
+                    elapsed: self.start.elapsed(),
+                    limit,
+                })
+            }
+            None => Ok(future.await),
+        }
+    }
+
+    /// Consumption of the budget so far.
+    pub fn usage(&self) -> BudgetUsage {
+        BudgetUsage {
+            wall_time: self.start.elapsed(),
+            ..self.usage
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let mut tracker = BudgetTracker::new(Budget::new().with_max_tokens(1000).with_max_cost(0.01, 0.02));
+        assert_eq!(tracker.check(400), Ok(()));
+        tracker.record(400);
+        assert!(matches!(tracker.check(200), Err(BudgetExceeded::Cost { used, .. }) if (used - 0.012).abs() < 1e-9));
+
+        let mut tracker = BudgetTracker::new(Budget::new().with_max_tokens(1000));
+        tracker.record(900);
+        tracker.record(50);
+        assert_eq!(
+            tracker.check(100),
+            Err(BudgetExceeded::Tokens {
+                used: 1050,
+                limit: 1000
+            })
+        );
+        let usage = tracker.usage();
+        assert_eq!((usage.tokens, usage.calls, usage.degraded), (950, 2, false));
+        assert_eq!(usage.to_json()["tokens"], 950);
+    }
+
+    #[tokio::test]
+    async fn test_wall_time() {
+        let tracker = BudgetTracker::new(Budget::new().with_max_wall_time(Duration::from_millis(20)));
+        assert_eq!(tracker.run(async { 1 }).await, Ok(1));
+        let error = tracker.run(tokio::time::sleep(Duration::from_secs(5))).await.unwrap_err();
+        assert!(matches!(error, BudgetExceeded::WallTime { .. }));
+        assert!(tracker.check(0).is_err());
+    }
+}
@@ -0,0 +1,130 @@
+//! Progress of ingestion runs, persisted so that interrupted runs can resume.
+//!
+//! A `Checkpoint` keeps, for each ingested source, a cursor past the records already processed and
+//! the records that failed, with their errors. `IngestPipeline::with_checkpoint` saves it after every
+//! batch; a later run over the same sources skips the processed records and retries the failed ones.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// Record of a source that failed to be ingested.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FailedItem {
+    /// Index of the record in the source.
+    pub index: usize,
+
+    /// Error the record failed with.
+    pub error: String,
+}
+
+/// Progress of the ingestion of a source.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SourceProgress {
+    /// Number of records of the source processed, successfully or not.
+    pub cursor: usize,
+
+    /// Processed records that failed.
+    #[serde(default)]
+    pub failed: Vec<FailedItem>,
+}
+
+/// Progress of an ingestion run over several sources.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Progress of each source, by source name.
+    pub sources: BTreeMap<String, SourceProgress>,
+}
+
+impl Checkpoint {
+    /// Load a checkpoint from a JSON file, or start a new one if the file does not exist.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        match std::fs::read(path.as_ref()) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Checkpoint::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Save the checkpoint to a JSON file. The file is replaced atomically, so that a run interrupted
+    /// while saving leaves the previous checkpoint intact.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let temporary = path.with_extension("tmp");
+        std::fs::write(&temporary, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(temporary, path)?;
+        Ok(())
+    }
+
+    /// Number of records of a source already processed.
+    pub fn cursor(&self, source: &str) -> usize {
+        self.sources.get(source).map_or(0, |progress| progress.cursor)
+    }
+
+    /// Failed records of a source.
+    pub fn failed(&self, source: &str) -> &[FailedItem] {
+        self.sources.get(source).map_or(&[], |progress| &progress.failed)
+    }
+
+    /// Move the cursor of a source forward to the given number of processed records.
+    pub fn advance(&mut self, source: &str, cursor: usize) {
+        let progress = self.sources.entry(source.to_string()).or_default();
+        progress.cursor = progress.cursor.max(cursor);
+    }
+
+    /// Record that a record of a source failed, replacing any earlier failure of the same record.
+    pub fn fail(&mut self, source: &str, index: usize, error: &str) {
+        let progress = self.sources.entry(source.to_string()).or_default();
+        progress.failed.retain(|item| item.index != index);
+        progress.failed.push(FailedItem {
+            index,
+            error: error.to_string(),
+        });
+    }
+
+    /// Remove a failed record of a source, once it has been ingested.
+    pub fn clear_failed(&mut self, source: &str, index: usize) {
+        if let Some(progress) = self.sources.get_mut(source) {
+            progress.failed.retain(|item| item.index != index);
+        }
+    }
+
+    /// Indexes of the failed records of a source, in order, so that they can be retried. The records
+    /// stay in the checkpoint until `clear_failed` is called, so that a run interrupted while retrying
+    /// them does not lose them.
+    pub fn failed_indexes(&self, source: &str) -> Vec<usize> {
+        let mut indexes: Vec<usize> = self.failed(source).iter().map(|item| item.index).collect();
+        indexes.sort_unstable();
+        indexes.dedup();
+        indexes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint() {
+        let path = std::env::temp_dir().join(format!("orca-checkpoint-{}.json", uuid::Uuid::new_v4()));
+        let mut checkpoint = Checkpoint::load(&path).unwrap();
+        assert_eq!(checkpoint.cursor("paper.pdf"), 0);
+
+        checkpoint.advance("paper.pdf", 64);
+        checkpoint.advance("paper.pdf", 32);
+        checkpoint.fail("paper.pdf", 40, "timeout");
+        checkpoint.fail("paper.pdf", 3, "timeout");
+        checkpoint.fail("paper.pdf", 40, "connection reset");
+        checkpoint.save(&path).unwrap();
+
+        let mut checkpoint = Checkpoint::load(&path).unwrap();
+        assert_eq!(checkpoint.cursor("paper.pdf"), 64);
+        assert_eq!(checkpoint.failed("paper.pdf")[1].error, "connection reset");
+        assert_eq!(checkpoint.failed_indexes("paper.pdf"), vec![3, 40]);
+        checkpoint.clear_failed("paper.pdf", 3);
+        assert_eq!(checkpoint.failed_indexes("paper.pdf"), vec![40]);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,299 @@
+//! Classification of texts into a fixed set of labels.
+//!
+//! A `Classifier` asks an LLM which of the declared labels a text belongs to, e.g. whether a user
+//! message is small talk or needs retrieval. Few-shot examples are shown to the LLM as previous turns
+//! of the conversation, all of them or those picked by an [`ExampleSelector`]. The response is
+//! constrained to the declared labels: a label mentioned among other words is accepted, and any other
+//! response is reported as an [`UnknownLabel`] error. Labels map to an enum through serde, so
+//! `classify` can return a variant directly.
+
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::sync::Arc;
+
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value as JsonValue};
+
+use crate::llm::LLM;
+use crate::prompt::TemplateEngine;
+use crate::template;
+
+/// Name of the template used to classify a text.
+pub const CLASSIFY_TEMPLATE: &str = "classify";
+
+static DEFAULT_CLASSIFY: &str = r#"{{#chat}}
+{{#system}}Classify the text given by the user into exactly one of the following labels:
+{{#each labels}}- {{name}}: {{description}}
+{{/each}}Respond only with the label.{{/system}}
+{{#each examples}}{{#user}}{{text}}{{/user}}{{#assistant}}{{label}}{{/assistant}}{{/each}}
+{{#user}}{{text}}{{/user}}
+{{/chat}}"#;
+
+/// A label a text can be classified into.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Label {
+    /// Name of the label, as the LLM responds with it.
+    pub name: String,
+
+    /// Description of the texts with this label, shown to the LLM.
+    pub description: String,
+}
+
+/// A text and its label, shown to the LLM as an example.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Example {
+    pub text: String,
+    pub label: String,
+}
+
+impl Example {
+    /// Create an example.
+    pub fn new(text: &str, label: &str) -> Self {
+        Example {
+            text: text.to_string(),
+            label: label.to_string(),
+        }
+    }
+}
+
+/// Picks the few-shot examples shown to the LLM for a text.
+pub trait ExampleSelector: Send + Sync {
+    /// Select examples for the text, in the order they are shown.
+    fn select(&self, text: &str, examples: &[Example]) -> Vec<Example>;
+}
+
+/// Selects the examples sharing the most words with the text.
+#[derive(Debug, Clone, Copy)]
+pub struct OverlapSelector(pub usize);
+
+impl ExampleSelector for OverlapSelector {
+    fn select(&self, text: &str, examples: &[Example]) -> Vec<Example> {
+        let words = |text: &str| -> HashSet<String> { normalize(text).split(' ').map(str::to_string).collect() };
+        let query = words(text);
+        let mut scored: Vec<(usize, &Example)> = examples
+            .iter()
+            .map(|example| (words(&example.text).intersection(&query).count(), example))
+            .collect();
+        // Stable sort, so that ties keep the order the examples were added in.
+        scored.sort_by_key(|(overlap, _)| std::cmp::Reverse(*overlap));
+        scored.into_iter().take(self.0).map(|(_, example)| example.clone()).collect()
+    }
+}
+
+/// Response of the LLM that is not one of the labels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnknownLabel {
+    /// The response of the LLM.
+    pub response: String,
+
+    /// Names of the declared labels.
+    pub labels: Vec<String>,
+}
+
+impl Display for UnknownLabel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Response {:?} is not one of the labels {}",
+            self.response,
+            self.labels.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnknownLabel {}
+
+/// Classifies texts into one of the declared labels with an LLM.
+pub struct Classifier<M> {
+    /// The LLM classifying the texts.
+    llm: Arc<M>,
+
+    /// Labels the texts are classified into.
+    labels: Vec<Label>,
+
+    /// Examples of classified texts.
+    examples: Vec<Example>,
+
+    /// Picks the examples shown for a text, all of them if unset.
+    selector: Option<Arc<dyn ExampleSelector>>,
+
+    /// Template engine holding the classification template.
+    template_engine: TemplateEngine,
+}
+
+impl<M: LLM + Clone + 'static> Classifier<M> {
+    /// Create a classifier without labels.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use orca_core::llm::openai::OpenAI;
+    /// use orca_core::pipeline::classify::{Classifier, OverlapSelector};
+    /// use serde::Deserialize;
+    ///
+    /// #[derive(Deserialize)]
+    /// #[serde(rename_all = "snake_case")]
+    /// enum Intent {
+    ///     SmallTalk,
+    ///     NeedsRetrieval,
+    /// }
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let classifier = Classifier::new(&OpenAI::new().with_temperature(0.0))
+    ///     .with_label("small_talk", "Greetings and chit-chat")
+    ///     .with_label("needs_retrieval", "Questions about the documentation")
+    ///     .with_example("Hi there!", "small_talk")
+    ///     .with_example("How do I configure Qdrant?", "needs_retrieval")
+    ///     .with_selector(OverlapSelector(4));
+    /// let intent: Intent = classifier.classify("What does the ingest pipeline do?").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new(llm: &M) -> Self {
+        Classifier {
+            llm: Arc::new(llm.clone()),
+            labels: Vec::new(),
+            examples: Vec::new(),
+            selector: None,
+            template_engine: template!(CLASSIFY_TEMPLATE, DEFAULT_CLASSIFY),
+        }
+    }
+
+    /// Declare a label.
+    pub fn with_label(mut self, name: &str, description: &str) -> Self {
+        self.labels.push(Label {
+            name: name.to_string(),
+            description: description.to_string(),
+        });
+        self
+    }
+
+    /// Add an example of a classified text.
+    pub fn with_example(mut self, text: &str, label: &str) -> Self {
+        self.examples.push(Example::new(text, label));
+        self
+    }
+
+    /// Set the selector picking the examples shown for each text.
+    pub fn with_selector<S: ExampleSelector + 'static>(mut self, selector: S) -> Self {
+        self.selector = Some(Arc::new(selector));
+        self
+    }
+
+    /// Override the classification template, which receives `labels` (with `name` and `description`),
+    /// `examples` (with `text` and `label`) and `text`.
+    pub fn load_template(self, template: &str) -> Result<Self> {
+        Ok(Self {
+            template_engine: self.template_engine.register_template(CLASSIFY_TEMPLATE, template)?,
+            ..self
+        })
+    }
+
+    /// The LLM classifying the texts.
+    pub(crate) fn llm(&self) -> &M {
+        &self.llm
+    }
+
+    /// Names of the declared labels.
+    pub fn labels(&self) -> Vec<String> {
+        self.labels.iter().map(|label| label.name.clone()).collect()
+    }
+
+    /// Classify a text, returning the name of its label.
+    pub async fn classify_label(&self, text: &str) -> Result<String> {
+        let examples = match &self.selector {
+            Some(selector) => selector.select(text, &self.examples),
+            None => self.examples.clone(),
+        };
+        let context = json!({ "labels": self.labels, "examples": examples, "text": text });
+        let prompt = self.template_engine.render_context(CLASSIFY_TEMPLATE, &context)?;
+        let response = self.llm.generate(prompt).await?.to_string();
+        Ok(self.parse_label(&response)?)
+    }
+
+    /// Classify a text into the variant of an enum whose serde name is the label.
+    pub async fn classify<T: DeserializeOwned>(&self, text: &str) -> Result<T> {
+        let label = self.classify_label(text).await?;
+        Ok(serde_json::from_value(JsonValue::String(label))?)
+    }
+
+    /// The label a response names, either on its own or as the only label mentioned in it, ignoring
+    /// case and punctuation.
+    pub fn parse_label(&self, response: &str) -> Result<String, UnknownLabel> {
+        let normalized = normalize(response);
+        if let Some(label) = self.labels.iter().find(|label| normalize(&label.name) == normalized) {
+            return Ok(label.name.clone());
+        }
+        let padded = format!(" {} ", normalized);
+        let mentioned: Vec<&Label> = self
+            .labels
+            .iter()
+            .filter(|label| padded.contains(&format!(" {} ", normalize(&label.name))))
+            .collect();
+        match mentioned.as_slice() {
+            [label] => Ok(label.name.clone()),
+            _ => Err(UnknownLabel {
+                response: response.to_string(),
+                labels: self.labels(),
+            }),
+        }
+    }
+}
+
+/// Lowercase a text and replace runs of punctuation and whitespace by a single space.
+fn normalize(text: &str) -> String {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::FixedLLM;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    enum Intent {
+        SmallTalk,
+        NeedsRetrieval,
+    }
+
+    fn classifier(response: &'static str) -> Classifier<FixedLLM> {
+        Classifier::new(&FixedLLM::new(response))
+            .with_label("small_talk", "Greetings and chit-chat")
+            .with_label("needs_retrieval", "Questions about orcas")
+    }
+
+    #[tokio::test]
+    async fn test_classify() {
+        let intent: Intent = classifier("Label: needs_retrieval.").classify("Where do orcas live?").await.unwrap();
+        assert_eq!(intent, Intent::NeedsRetrieval);
+
+        let classifier = classifier("Small talk").with_example("Hello!", "small_talk");
+        assert_eq!(classifier.classify::<Intent>("Hi!").await.unwrap(), Intent::SmallTalk);
+        let prompt = classifier.llm.prompts()[0].clone();
+        assert!(prompt.contains("needs_retrieval: Questions about orcas"));
+        assert!(prompt.contains(r#"{"role":"assistant","content":"small_talk"}"#));
+
+        let error = self::classifier("small_talk or needs_retrieval").classify_label("Hi").await.unwrap_err();
+        assert!(error.downcast_ref::<UnknownLabel>().is_some());
+    }
+
+    #[test]
+    fn test_overlap_selector() {
+        let examples = vec![
+            Example::new("Hello there", "small_talk"),
+            Example::new("Where do orcas hunt?", "needs_retrieval"),
+            Example::new("What do orcas eat?", "needs_retrieval"),
+        ];
+        let selected = OverlapSelector(1).select("What do orcas weigh?", &examples);
+        assert_eq!(selected, vec![examples[2].clone()]);
+        assert_eq!(OverlapSelector(5).select("Hi", &examples).len(), 3);
+    }
+}
@@ -0,0 +1,351 @@
+//! Collection of streamed responses.
+//!
+//! A streamed response is only known in full once its last piece is sent, and may never be if the
+//! consumer stops reading, e.g. when the user of a chat interface cancels the generation. A
+//! `StreamCollector` forwards the pieces of a stream while assembling them, and hands the assembled
+//! response to its sinks once the stream ends, whether it completed, failed or was dropped by the
+//! consumer: the conversation memory, the audit log and the usage tracker thus see the same message, once.
+//!
+//! ```no_run
+//! use futures::StreamExt;
+//! use orca_core::llm::openai::OpenAI;
+//! use orca_core::llm::LLM;
+//! use orca_core::pipeline::collector::{StreamCollector, UsageSink};
+//! use orca_core::pipeline::usage::UsageTracker;
+//! use orca_core::prompt;
+//!
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! let usage = UsageTracker::new();
+//! let stream = OpenAI::new().generate_stream(prompt!("Tell me about orcas.")).await?;
+//! let mut stream = StreamCollector::new().with_sink(UsageSink::new(usage.clone(), "orcas")).collect(stream);
+//! while let Some(text) = stream.next().await {
+//!     print!("{}", text?);
+//! }
+//! println!("{} tokens", usage.total().total_tokens());
+//! # Ok(())
+//! # }
+//! ```
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use futures::StreamExt;
+use tokio::sync::{mpsc, Mutex};
+
+use super::usage::{UsageRecord, UsageTracker};
+use crate::llm::{channel_stream, RequestMetadata, TokenStream};
+use crate::memory::Memory;
+use crate::prompt::chat::{ChatPrompt, Message, Role};
+use crate::prompt::estimate_tokens;
+
+/// How a collected stream ended.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamEnd {
+    /// The stream sent its last piece.
+    Completed,
+
+    /// The consumer dropped the stream before its end.
+    Cancelled,
+
+    /// The stream failed with the given error, which was sent to the consumer.
+    Failed(String),
+}
+
+/// Response assembled from the pieces of a stream.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CollectedStream {
+    /// Pieces of text sent to the consumer, concatenated.
+    pub text: String,
+
+    /// Number of pieces sent to the consumer.
+    pub pieces: usize,
+
+    /// How the stream ended.
+    pub end: StreamEnd,
+
+    /// Time from the start of the collection to the end of the stream.
+    pub elapsed: Duration,
+}
+
+impl CollectedStream {
+    /// Whether the text is only the start of the response, the stream having failed or been cancelled.
+    pub fn is_partial(&self) -> bool {
+        self.end != StreamEnd::Completed
+    }
+}
+
+/// Destination of collected responses.
+#[async_trait::async_trait]
+pub trait StreamSink: Send + Sync {
+    /// Handle the response assembled from a stream that ended.
+    async fn collect(&self, stream: &CollectedStream) -> Result<()>;
+}
+
+#[async_trait::async_trait]
+impl<F> StreamSink for F
+where
+    F: Fn(&CollectedStream) -> Result<()> + Send + Sync,
+{
+    async fn collect(&self, stream: &CollectedStream) -> Result<()> {
+        self(stream)
+    }
+}
+
+/// Forwards streams to their consumer while collecting them for its sinks.
+#[derive(Clone, Default)]
+pub struct StreamCollector {
+    sinks: Vec<Arc<dyn StreamSink>>,
+}
+
+impl StreamCollector {
+    /// Create a collector without sinks.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a sink, called after the sinks added before it.
+    pub fn with_sink<S: StreamSink + 'static>(mut self, sink: S) -> Self {
+        self.sinks.push(Arc::new(sink));
+        self
+    }
+
+    /// Forward a stream, handing the collected response to the sinks when it ends.
+    ///
+    /// The sinks are called before the returned stream ends, so that a consumer reading it to the end sees
+    /// their effects, e.g. the response in memory. A sink failing does not keep the others from being called,
+    /// and its error is logged: the response was sent to the consumer already. The stream is read by a task,
+    /// which drops it, cancelling the generation, as soon as the returned stream is dropped.
+    pub fn collect(self, mut stream: TokenStream) -> TokenStream {
+        // A single piece is buffered, so that the collected text is the text read by the consumer, unless
+        // the consumer drops the stream with the last piece unread.
+        let (sender, receiver) = mpsc::channel(1);
+        tokio::spawn(async move {
+            let start = Instant::now();
+            let mut text = String::new();
+            let mut pieces = 0;
+            let end = loop {
+                let next = tokio::select! {
+                    next = stream.next() => next,
+                    _ = sender.closed() => break StreamEnd::Cancelled,
+                };
+                match next {
+                    None => break StreamEnd::Completed,
+                    Some(Ok(piece)) => {
+                        if sender.send(Ok(piece.clone())).await.is_err() {
+                            break StreamEnd::Cancelled;
+                        }
+                        text.push_str(&piece);
+                        pieces += 1;
+                    }
+                    Some(Err(e)) => {
+                        let message = e.to_string();
+                        let _ = sender.send(Err(e)).await;
+                        break StreamEnd::Failed(message);
+                    }
+                }
+            };
+            drop(stream);
+            let collected = CollectedStream {
+                text,
+                pieces,
+                end,
+                elapsed: start.elapsed(),
+            };
+            for sink in &self.sinks {
+                if let Err(e) = sink.collect(&collected).await {
+                    log::warn!("Failed to collect a streamed response: {:#}", e);
+                }
+            }
+        });
+        channel_stream(receiver)
+    }
+}
+
+/// Saves collected responses as assistant messages in a conversation memory. Partial responses are saved
+/// too, as the consumer showed them, unless they are discarded.
+pub struct MemorySink {
+    memory: Arc<Mutex<dyn Memory>>,
+    discard_partial: bool,
+}
+
+impl MemorySink {
+    /// Save the responses in the given memory.
+    pub fn new(memory: Arc<Mutex<dyn Memory>>) -> Self {
+        MemorySink {
+            memory,
+            discard_partial: false,
+        }
+    }
+
+    /// Only save the responses of completed streams.
+    pub fn discard_partial(mut self) -> Self {
+        self.discard_partial = true;
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl StreamSink for MemorySink {
+    async fn collect(&self, stream: &CollectedStream) -> Result<()> {
+        if stream.text.is_empty() || (stream.is_partial() && self.discard_partial) {
+            return Ok(());
+        }
+        let message = ChatPrompt(vec![Message::new(Role::Assistant, &stream.text)]);
+        let mut memory = self.memory.lock().await;
+        memory.memory().save(Box::new(message));
+        memory.flush().await
+    }
+}
+
+/// Records the tokens of collected responses in a usage tracker. Streams do not report their usage, so the
+/// tokens are estimated; responses of failed or cancelled streams are recorded too, as they are billed.
+pub struct UsageSink {
+    usage: UsageTracker,
+    step: String,
+    prompt_tokens: u32,
+    model: Option<String>,
+}
+
+impl UsageSink {
+    /// Record the responses under the given step.
+    pub fn new(usage: UsageTracker, step: &str) -> Self {
+        UsageSink {
+            usage,
+            step: step.to_string(),
+            prompt_tokens: 0,
+            model: None,
+        }
+    }
+
+    /// Set the estimated tokens of the prompt the responses answer.
+    pub fn with_prompt_tokens(mut self, prompt_tokens: u32) -> Self {
+        self.prompt_tokens = prompt_tokens;
+        self
+    }
+
+    /// Set the model generating the responses, to price them.
+    pub fn with_model(mut self, model: &str) -> Self {
+        self.model = Some(model.to_string());
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl StreamSink for UsageSink {
+    async fn collect(&self, stream: &CollectedStream) -> Result<()> {
+        let completion_tokens = estimate_tokens(&stream.text) as u32;
+        let record = UsageRecord::estimated(&self.step, self.prompt_tokens, completion_tokens, self.model.as_deref());
+        self.usage.record([record]);
+        Ok(())
+    }
+}
+
+/// Logs collected responses to the `orca::audit` target, with the metadata of their request.
+pub struct AuditSink {
+    pipeline: String,
+    template: String,
+    metadata: RequestMetadata,
+}
+
+impl AuditSink {
+    /// Log the responses of a template of a pipeline, requested with the given metadata.
+    pub fn new(pipeline: &str, template: &str, metadata: RequestMetadata) -> Self {
+        AuditSink {
+            pipeline: pipeline.to_string(),
+            template: template.to_string(),
+            metadata,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl StreamSink for AuditSink {
+    async fn collect(&self, stream: &CollectedStream) -> Result<()> {
+        let end = match &stream.end {
+            StreamEnd::Completed => "completed",
+            StreamEnd::Cancelled => "cancelled",
+            StreamEnd::Failed(_) => "failed",
+        };
+        log::info!(
+            target: "orca::audit",
+            "pipeline={} template={} user_id={} trace_id={} idempotency_key={} stream={} chars={}",
+            self.pipeline,
+            self.template,
+            self.metadata.user_id.as_deref().unwrap_or("-"),
+            self.metadata.trace_id.as_deref().unwrap_or("-"),
+            self.metadata.idempotency_key.as_deref().unwrap_or("-"),
+            end,
+            stream.text.chars().count(),
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory::ChatBuffer;
+    use anyhow::anyhow;
+    use futures::TryStreamExt;
+
+    /// Sink sending the collected responses on a channel.
+    fn channel_sink() -> (impl StreamSink, mpsc::UnboundedReceiver<CollectedStream>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let sink = move |stream: &CollectedStream| {
+            sender.send(stream.clone())?;
+            Ok(())
+        };
+        (sink, receiver)
+    }
+
+    fn words(text: &str) -> Vec<Result<String>> {
+        text.split_inclusive(' ').map(|word| Ok(word.to_string())).collect()
+    }
+
+    #[tokio::test]
+    async fn test_collect() {
+        let memory: Arc<Mutex<dyn Memory>> = Arc::new(Mutex::new(ChatBuffer::new()));
+        let usage = UsageTracker::new();
+        let (sink, mut collected) = channel_sink();
+        let collector = StreamCollector::new()
+            .with_sink(MemorySink::new(memory.clone()))
+            .with_sink(UsageSink::new(usage.clone(), "orcas").with_prompt_tokens(10))
+            .with_sink(sink);
+
+        let stream = collector.clone().collect(Box::pin(futures::stream::iter(words("Orcas are dolphins."))));
+        let pieces = stream.try_collect::<Vec<_>>().await.unwrap();
+        assert_eq!(pieces.concat(), "Orcas are dolphins.");
+        // The sinks are called before the stream ends.
+        let chat = memory.lock().await.memory().to_chat().unwrap();
+        assert_eq!(
+            chat.to_vec(),
+            vec![Message::new(Role::Assistant, "Orcas are dolphins.")]
+        );
+        assert_eq!(usage.records()[0].prompt_tokens, 10);
+        let stream = collected.recv().await.unwrap();
+        assert_eq!((stream.pieces, stream.end), (3, StreamEnd::Completed));
+
+        // Cancelled streams are collected as far as the consumer read them.
+        let pending = futures::stream::iter(words("Orcas live in pods")).chain(futures::stream::pending());
+        let mut stream = collector.clone().collect(Box::pin(pending));
+        assert_eq!(stream.next().await.unwrap().unwrap(), "Orcas ");
+        drop(stream);
+        let stream = collected.recv().await.unwrap();
+        assert_eq!(stream.end, StreamEnd::Cancelled);
+        assert!(stream.is_partial() && "Orcas live ".starts_with(&stream.text));
+
+        let failing =
+            futures::stream::iter(words("Orcas ")).chain(futures::stream::once(async { Err(anyhow!("Lost")) }));
+        let error = collector.collect(Box::pin(failing)).try_collect::<Vec<_>>().await.unwrap_err();
+        assert_eq!(error.to_string(), "Lost");
+        let stream = collected.recv().await.unwrap();
+        assert_eq!(
+            (stream.text.as_str(), stream.end),
+            ("Orcas ", StreamEnd::Failed("Lost".to_string()))
+        );
+        assert_eq!(usage.records().len(), 3);
+        assert_eq!(memory.lock().await.memory().to_chat().unwrap().to_vec().len(), 3);
+    }
+}
@@ -0,0 +1,280 @@
+//! Context shared by the pipelines and rendered into their templates.
+//!
+//! Keys are dotted paths into nested objects, e.g. `user.name`, and array elements can be
+//! addressed by index, e.g. `results.0.title`.
+
+use crate::prompt::context::Context;
+
+use anyhow::{anyhow, Result};
+use serde::{de::DeserializeOwned, Serialize, Serializer};
+use serde_json::{Map, Value as JsonValue};
+use std::collections::BTreeSet;
+
+/// What to do when a merged key already exists in the context.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MergeStrategy {
+    /// Fail on the first key that already exists.
+    #[default]
+    Error,
+
+    /// Replace existing values.
+    Overwrite,
+
+    /// Keep existing values.
+    Keep,
+
+    /// Merge nested objects key by key, replacing other existing values.
+    Deep,
+}
+
+/// Key-value context of a pipeline.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PipelineContext {
+    values: Map<String, JsonValue>,
+
+    /// Paths set since the context was created or the changes were last taken.
+    changes: BTreeSet<String>,
+}
+
+impl PipelineContext {
+    /// Create an empty context.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the value at a dotted path.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::pipeline::context::PipelineContext;
+    ///
+    /// let mut context = PipelineContext::new();
+    /// context.set("user.name", "Ada").unwrap();
+    /// assert_eq!(context.get("user.name").unwrap(), "Ada");
+    /// assert!(context.get("user.age").is_none());
+    /// ```
+    pub fn get(&self, path: &str) -> Option<&JsonValue> {
+        let mut segments = path.split('.');
+        let mut value = self.values.get(segments.next()?)?;
+        for segment in segments {
+            value = match value {
+                JsonValue::Object(map) => map.get(segment)?,
+                JsonValue::Array(values) => values.get(segment.parse::<usize>().ok()?)?,
+                _ => return None,
+            };
+        }
+        Some(value)
+    }
+
+    /// Get the value at a dotted path, deserialized into `T`.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::pipeline::context::PipelineContext;
+    ///
+    /// let mut context = PipelineContext::new();
+    /// context.set("retries", 3).unwrap();
+    /// assert_eq!(context.get_as::<u32>("retries").unwrap(), 3);
+    /// ```
+    pub fn get_as<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let value = self.get(path).ok_or(anyhow!("Context does not contain a key with name {}", path))?;
+        Ok(serde_json::from_value(value.clone())?)
+    }
+
+    /// Whether a value exists at a dotted path.
+    pub fn contains(&self, path: &str) -> bool {
+        self.get(path).is_some()
+    }
+
+    /// Set the value at a dotted path, where value is any serializable type. Missing intermediate
+    /// objects are created, and existing intermediate values that are not objects are replaced.
+    pub fn set<T: Serialize>(&mut self, path: &str, value: T) -> Result<()> {
+        let value = serde_json::to_value(value)?;
+        let segments = path.split('.').collect::<Vec<_>>();
+        if segments.iter().any(|segment| segment.is_empty()) {
+            return Err(anyhow!("Invalid context key {}", path));
+        }
+
+        let (last, parents) = segments.split_last().unwrap();
+        let mut map = &mut self.values;
+        for segment in parents {
+            let entry = map.entry(segment.to_string()).or_insert_with(|| JsonValue::Object(Map::new()));
+            if !entry.is_object() {
+                *entry = JsonValue::Object(Map::new());
+            }
+            map = entry.as_object_mut().unwrap();
+        }
+        map.insert(last.to_string(), value);
+        self.changes.insert(path.to_string());
+        Ok(())
+    }
+
+    /// Remove the value at a dotted path, returning it if it existed.
+    pub fn remove(&mut self, path: &str) -> Option<JsonValue> {
+        let (parent, last) = match path.rsplit_once('.') {
+            Some((parent, last)) => (self.get_mut(parent)?.as_object_mut()?, last),
+            None => (&mut self.values, path),
+        };
+        let removed = parent.remove(last);
+        if removed.is_some() {
+            self.changes.insert(path.to_string());
+        }
+        removed
+    }
+
+    fn get_mut(&mut self, path: &str) -> Option<&mut JsonValue> {
+        let mut segments = path.split('.');
+        let mut value = self.values.get_mut(segments.next()?)?;
+        for segment in segments {
+            value = value.as_object_mut()?.get_mut(segment)?;
+        }
+        Some(value)
+    }
+
+    /// Merge the top-level keys of another context into this one.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::pipeline::context::{MergeStrategy, PipelineContext};
+    /// use serde_json::json;
+    ///
+    /// let mut context = PipelineContext::try_from(json!({"user": {"name": "Ada"}})).unwrap();
+    /// let other = PipelineContext::try_from(json!({"user": {"age": 36}})).unwrap();
+    /// context.merge(&other, MergeStrategy::Deep).unwrap();
+    /// assert_eq!(context.get("user.name").unwrap(), "Ada");
+    /// assert_eq!(context.get("user.age").unwrap(), 36);
+    /// ```
+    pub fn merge(&mut self, other: &PipelineContext, strategy: MergeStrategy) -> Result<()> {
+        if strategy == MergeStrategy::Error {
+            if let Some(key) = other.values.keys().find(|key| self.values.contains_key(*key)) {
+                return Err(anyhow!("Context already contains a key with name {}", key));
+            }
+        }
+
+        for (key, value) in &other.values {
+            match (self.values.get_mut(key), strategy) {
+                (Some(_), MergeStrategy::Keep) => continue,
+                (Some(existing), MergeStrategy::Deep) => deep_merge(existing, value),
+                _ => {
+                    self.values.insert(key.clone(), value.clone());
+                }
+            }
+            self.changes.insert(key.clone());
+        }
+        Ok(())
+    }
+
+    /// Paths set or removed since the context was created or the changes were last taken.
+    pub fn changes(&self) -> impl Iterator<Item = &str> {
+        self.changes.iter().map(|path| path.as_str())
+    }
+
+    /// Return the changed paths and start tracking changes afresh.
+    pub fn take_changes(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.changes).into_iter().collect()
+    }
+
+    /// Get a reference to the top-level values.
+    pub fn as_object(&self) -> &Map<String, JsonValue> {
+        &self.values
+    }
+}
+
+fn deep_merge(existing: &mut JsonValue, value: &JsonValue) {
+    match (existing, value) {
+        (JsonValue::Object(existing), JsonValue::Object(values)) => {
+            for (key, value) in values {
+                match existing.get_mut(key) {
+                    Some(existing) => deep_merge(existing, value),
+                    None => {
+                        existing.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        (existing, value) => *existing = value.clone(),
+    }
+}
+
+impl Serialize for PipelineContext {
+    /// Serialize as the underlying object, so that the context can be rendered into templates.
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        self.values.serialize(serializer)
+    }
+}
+
+impl TryFrom<JsonValue> for PipelineContext {
+    type Error = anyhow::Error;
+
+    fn try_from(value: JsonValue) -> Result<Self> {
+        match value {
+            JsonValue::Object(values) => Ok(Self {
+                changes: values.keys().cloned().collect(),
+                values,
+            }),
+            value => Err(anyhow!("Context must be a JSON object, got {}", value)),
+        }
+    }
+}
+
+impl From<&Context> for PipelineContext {
+    fn from(context: &Context) -> Self {
+        let values = context.as_object().iter().map(|(key, value)| (key.clone(), value.clone())).collect::<Map<_, _>>();
+        Self {
+            changes: values.keys().cloned().collect(),
+            values,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_paths() {
+        let mut context = PipelineContext::try_from(json!({"results": [{"title": "Orca"}]})).unwrap();
+        assert_eq!(context.get("results.0.title").unwrap(), "Orca");
+        assert!(context.get("results.1.title").is_none());
+
+        context.set("user.name", "Ada").unwrap();
+        context.set("user.age", 36).unwrap();
+        assert_eq!(context.get_as::<u8>("user.age").unwrap(), 36);
+        assert!(context.get_as::<u8>("user.name").is_err());
+        assert!(context.set("user..name", "Ada").is_err());
+
+        assert_eq!(context.remove("user.age"), Some(json!(36)));
+        assert!(!context.contains("user.age"));
+        assert_eq!(
+            serde_json::to_value(&context).unwrap(),
+            json!({"results": [{"title": "Orca"}], "user": {"name": "Ada"}})
+        );
+    }
+
+    #[test]
+    fn test_merge_and_changes() {
+        let mut context = PipelineContext::try_from(json!({"a": 1, "b": {"c": 2}})).unwrap();
+        context.take_changes();
+
+        let other = PipelineContext::try_from(json!({"b": {"d": 3}, "e": 4})).unwrap();
+        assert!(context.merge(&other, MergeStrategy::Error).is_err());
+        assert!(!context.contains("e"));
+
+        let mut keep = context.clone();
+        keep.merge(
+            &PipelineContext::try_from(json!({"a": 5})).unwrap(),
+            MergeStrategy::Keep,
+        )
+        .unwrap();
+        assert_eq!(keep.get("a").unwrap(), 1);
+
+        context.merge(&other, MergeStrategy::Deep).unwrap();
+        assert_eq!(
+            serde_json::to_value(&context).unwrap(),
+            json!({"a": 1, "b": {"c": 2, "d": 3}, "e": 4})
+        );
+        assert_eq!(context.take_changes(), vec!["b", "e"]);
+        assert_eq!(context.changes().count(), 0);
+    }
+}
@@ -0,0 +1,113 @@
+//! Records that failed to be ingested, persisted so that they can be retried.
+//!
+//! `IngestPipeline::with_dead_letters` collects the records whose embedding or insertion still fails
+//! after the retries into a `DeadLetters` file, with the error they failed with, instead of stopping the
+//! run. The records are kept as they were to be stored, i.e. transformed, chunked and signed, so that
+//! `IngestPipeline::retry_dead_letters` stores them as is once the cause of the failure is fixed.
+
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::record::Record;
+
+/// Record that failed to be embedded or stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    /// Collection the record was to be stored in.
+    pub collection: String,
+
+    /// The record.
+    pub record: Record,
+
+    /// Error of the last attempt.
+    pub error: String,
+
+    /// Number of runs that failed to store the record.
+    pub attempts: usize,
+}
+
+/// Records that failed to be ingested.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeadLetters {
+    /// The records, in the order they failed.
+    pub letters: Vec<DeadLetter>,
+}
+
+impl DeadLetters {
+    /// Load dead letters from a JSON file, or start without any if the file does not exist.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        match std::fs::read(path.as_ref()) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(DeadLetters::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Save the dead letters to a JSON file. The file is replaced atomically, so that a run interrupted
+    /// while saving leaves the previous dead letters intact.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let temporary = path.with_extension("tmp");
+        std::fs::write(&temporary, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(temporary, path)?;
+        Ok(())
+    }
+
+    /// Add records that failed.
+    pub fn extend<I: IntoIterator<Item = DeadLetter>>(&mut self, letters: I) {
+        self.letters.extend(letters);
+    }
+
+    /// Remove the records to be stored in one of the given collections, returning them in order so that
+    /// they can be retried.
+    pub fn take(&mut self, collections: &[&str]) -> Vec<DeadLetter> {
+        let (taken, kept) = std::mem::take(&mut self.letters)
+            .into_iter()
+            .partition(|letter| collections.contains(&letter.collection.as_str()));
+        self.letters = kept;
+        taken
+    }
+
+    /// Number of records.
+    pub fn len(&self) -> usize {
+        self.letters.len()
+    }
+
+    /// Whether no record failed.
+    pub fn is_empty(&self) -> bool {
+        self.letters.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::record::Content;
+
+    #[test]
+    fn test_dead_letters() {
+        let path = std::env::temp_dir().join(format!("orca-dead-letters-{}.json", uuid::Uuid::new_v4()));
+        let mut letters = DeadLetters::load(&path).unwrap();
+        assert!(letters.is_empty());
+
+        let letter = |collection: &str, content: &str| DeadLetter {
+            collection: collection.to_string(),
+            record: Record::new(Content::String(content.to_string())),
+            error: "connection reset".to_string(),
+            attempts: 1,
+        };
+        letters.extend([letter("chunks", "a"), letter("abstracts", "b"), letter("other", "c")]);
+        letters.save(&path).unwrap();
+
+        let mut letters = DeadLetters::load(&path).unwrap();
+        let taken = letters.take(&["chunks", "abstracts"]);
+        assert_eq!(
+            taken.iter().map(|letter| letter.record.content.to_string()).collect::<Vec<_>>(),
+            vec!["a", "b"]
+        );
+        assert_eq!(letters.letters[0].collection, "other");
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,269 @@
+//! Structured descriptions of pipelines.
+//!
+//! `Pipeline::describe` returns a `PipelineDescription` of how a pipeline is built: its templates and
+//! the variables they read, the configuration of its model, its memory, the collections it reads and,
+//! for composed pipelines, the description of each of its steps. Descriptions serialize to JSON, to be
+//! diffed between versions of a pipeline, and render as a mermaid flowchart.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use tokio::sync::RwLock;
+
+use super::Pipeline;
+use crate::prompt::TemplateEngine;
+
+/// Template of a pipeline.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TemplateDescription {
+    /// Name of the template.
+    pub name: String,
+
+    /// Variables of the context that the template reads, in order of appearance.
+    pub variables: Vec<String>,
+}
+
+/// Description of a pipeline and of the pipelines it is composed of.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PipelineDescription {
+    /// Kind of the pipeline, e.g. `LLMPipeline`.
+    pub kind: String,
+
+    /// Name of the pipeline, empty if it has none.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
+
+    /// Label of the pipeline in the pipeline it is a step of, e.g. the route of a router.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+
+    /// Templates of the pipeline, sorted by name.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub templates: Vec<TemplateDescription>,
+
+    /// Configuration of the model of the pipeline, see `LLM::config`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<JsonValue>,
+
+    /// Kind of the memory of the pipeline.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<String>,
+
+    /// Vector store collections the pipeline reads or writes.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub collections: Vec<String>,
+
+    /// Pipelines the pipeline is composed of, in order of execution.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub steps: Vec<PipelineDescription>,
+}
+
+impl PipelineDescription {
+    /// Create the description of a pipeline of the given kind and name.
+    pub fn new(kind: &str, name: &str) -> Self {
+        PipelineDescription {
+            kind: kind.to_string(),
+            name: name.to_string(),
+            label: None,
+            templates: Vec::new(),
+            model: None,
+            memory: None,
+            collections: Vec::new(),
+            steps: Vec::new(),
+        }
+    }
+
+    /// Set the label of the pipeline in the pipeline it is a step of.
+    pub fn with_label(mut self, label: &str) -> Self {
+        self.label = Some(label.to_string());
+        self
+    }
+
+    /// Describe the templates registered in a template engine.
+    pub fn with_templates(mut self, engine: &TemplateEngine) -> Self {
+        let mut names = engine.templates.keys().collect::<Vec<_>>();
+        names.sort();
+        self.templates = names
+            .into_iter()
+            .map(|name| TemplateDescription {
+                name: name.clone(),
+                variables: engine.variables(name).unwrap_or_default(),
+            })
+            .collect();
+        self
+    }
+
+    /// Set the configuration of the model.
+    pub fn with_model(mut self, model: JsonValue) -> Self {
+        self.model = Some(model);
+        self
+    }
+
+    /// Set the kind of the memory.
+    pub fn with_memory(mut self, memory: &str) -> Self {
+        self.memory = Some(memory.to_string());
+        self
+    }
+
+    /// Add a collection the pipeline reads or writes.
+    pub fn with_collection(mut self, collection: &str) -> Self {
+        self.collections.push(collection.to_string());
+        self
+    }
+
+    /// Add a step of the pipeline.
+    pub fn with_step(mut self, step: PipelineDescription) -> Self {
+        self.steps.push(step);
+        self
+    }
+
+    /// Serialize the description to pretty-printed JSON.
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Render the description as a mermaid flowchart, with a node per pipeline linked to its steps.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::pipeline::describe::PipelineDescription;
+    /// use serde_json::json;
+    ///
+    /// let description = PipelineDescription::new("SequentialPipeline", "qa")
+    ///     .with_step(PipelineDescription::new("LLMPipeline", "answer").with_model(json!({"model": "gpt-4"})));
+    /// assert_eq!(
+    ///     description.to_mermaid(),
+    ///     "flowchart TD\n    p0[\"SequentialPipeline qa\"]\n    p1[\"LLMPipeline answer<br/>model: gpt-4\"]\n    p0 -->|1| p1\n"
+    /// );
+    /// ```
+    pub fn to_mermaid(&self) -> String {
+        let mut nodes = Vec::new();
+        let mut edges = Vec::new();
+        self.mermaid(&mut nodes, &mut edges);
+        let mut chart = "flowchart TD\n".to_string();
+        for line in nodes.into_iter().chain(edges) {
+            chart.push_str("    ");
+            chart.push_str(&line);
+            chart.push('\n');
+        }
+        chart
+    }
+
+    /// Add the node of the pipeline and of its steps to a flowchart, returning the identifier of the node.
+    fn mermaid(&self, nodes: &mut Vec<String>, edges: &mut Vec<String>) -> String {
+        let id = format!("p{}", nodes.len());
+        let mut lines = vec![format!("{} {}", self.kind, self.name).trim().to_string()];
+        lines.extend(
+            self.templates
+                .iter()
+                .map(|template| format!("template: {}({})", template.name, template.variables.join(", "))),
+        );
+        if let Some(model) = &self.model {
+            let name = model.get("model").or_else(|| model.get("type")).unwrap_or(model);
+            lines.push(format!(
+                "model: {}",
+                name.as_str().map(str::to_string).unwrap_or(name.to_string())
+            ));
+        }
+        if let Some(memory) = &self.memory {
+            lines.push(format!("memory: {}", memory));
+        }
+        if !self.collections.is_empty() {
+            lines.push(format!("collections: {}", self.collections.join(", ")));
+        }
+        let label = lines.join("<br/>").replace('"', "#quot;");
+        nodes.push(format!("{}[\"{}\"]", id, label));
+        for (index, step) in self.steps.iter().enumerate() {
+            let step_id = step.mermaid(nodes, edges);
+            let label = step.label.clone().unwrap_or_else(|| (index + 1).to_string());
+            edges.push(format!("{} -->|{}| {}", id, label.replace('"', "#quot;"), step_id));
+        }
+        id
+    }
+}
+
+/// Describe a pipeline shared by a composed pipeline. Pipelines being executed are locked, in which case
+/// only their kind is known.
+pub(crate) fn describe_shared<P: Pipeline>(pipeline: &RwLock<P>) -> PipelineDescription {
+    match pipeline.try_read() {
+        Ok(pipeline) => pipeline.describe(),
+        Err(_) => PipelineDescription::new(type_name::<P>(), ""),
+    }
+}
+
+/// Name of a type without its path and generic parameters, e.g. `LLMPipeline` for
+/// `orca_core::pipeline::simple::LLMPipeline<orca_core::llm::openai::OpenAI>`.
+pub(crate) fn type_name<T: ?Sized>() -> &'static str {
+    let name = std::any::type_name::<T>();
+    let name = &name[..name.find('<').unwrap_or(name.len())];
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory::ChatBuffer;
+    use crate::pipeline::sequential::SequentialPipeline;
+    use crate::pipeline::simple::LLMPipeline;
+    use crate::testing::FixedLLM;
+    use serde_json::json;
+
+    #[test]
+    fn test_description() {
+        assert_eq!(type_name::<Vec<String>>(), "Vec");
+        let engine = TemplateEngine::new()
+            .register_template(
+                "qa",
+                "{{#user}}{{#if context}}{{context}}{{/if}} {{lowercase question}}{{/user}}",
+            )
+            .unwrap();
+        let description = PipelineDescription::new("RouterPipeline", "support")
+            .with_step(
+                PipelineDescription::new("LLMPipeline", "billing")
+                    .with_label("billing \"invoices\"")
+                    .with_templates(&engine)
+                    .with_memory("ChatBuffer")
+                    .with_collection("invoices"),
+            )
+            .with_step(PipelineDescription::new("LLMPipeline", "").with_model(json!({"type": "Quantized"})));
+        assert_eq!(
+            description.steps[0].templates,
+            vec![TemplateDescription {
+                name: "qa".to_string(),
+                variables: vec!["context".to_string(), "question".to_string()],
+            }]
+        );
+
+        let json = description.to_json().unwrap();
+        assert!(!json.contains("\"label\": null"));
+        assert_eq!(serde_json::from_str::<PipelineDescription>(&json).unwrap(), description);
+
+        let chart = description.to_mermaid();
+        assert!(chart.contains(
+            "p1[\"LLMPipeline billing<br/>template: qa(context, question)<br/>memory: ChatBuffer<br/>collections: invoices\"]"
+        ));
+        assert!(chart.contains("p0 -->|billing #quot;invoices#quot;| p1\n"));
+        assert!(chart.contains("p2[\"LLMPipeline<br/>model: Quantized\"]"));
+        assert!(chart.contains("p0 -->|2| p2\n"));
+    }
+
+    #[test]
+    fn test_describe_pipeline() {
+        let mut answer = LLMPipeline::new(&FixedLLM::new("Orcas"))
+            .load_template("qa", "{{#each documents}}{{this}}{{/each}} {{question}}")
+            .unwrap()
+            .load_memory(ChatBuffer::new());
+        answer.name = "answer".to_string();
+        let pipeline = SequentialPipeline::new().link(answer).link(LLMPipeline::new(&FixedLLM::new("Orcas")));
+        let description = pipeline.describe();
+        assert_eq!(description.kind, "SequentialPipeline");
+        assert_eq!(description.steps.len(), 2);
+        assert_eq!(description.steps[0].name, "answer");
+        assert_eq!(
+            description.steps[0].templates[0].variables,
+            vec!["documents", "question"]
+        );
+        assert_eq!(description.steps[0].model, Some(json!({"type": "FixedLLM"})));
+        assert_eq!(description.steps[0].memory.as_deref(), Some("ChatBuffer"));
+    }
+}
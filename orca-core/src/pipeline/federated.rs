@@ -0,0 +1,266 @@
+//! Federated search over several collections.
+//!
+//! Users keeping a collection per data source, possibly in different vector stores, still want a single
+//! list of results. A `FederatedRetriever` embeds the query once, searches every collection concurrently
+//! and merges the hits. Scores of different collections are not comparable, e.g. cosine similarities of
+//! one store and dot products of another, so the scores of each collection are normalized before being
+//! weighted by the weight of their collection. Records keep the merged score as their `score` attribute
+//! and the name of their collection as their `collection` attribute.
+
+use crate::llm::Embedding;
+use crate::pipeline::assembler::SCORE_ATTRIBUTE;
+use crate::prompt;
+use crate::record::Record;
+use crate::vectorstore::{Filter, SearchHit, SearchQuery, VectorStore};
+
+use anyhow::{Context as _, Result};
+use tracing::field::Empty;
+use tracing::Span;
+
+/// Attribute holding the name of the collection a record was found in.
+pub const COLLECTION_ATTRIBUTE: &str = "collection";
+
+/// How the scores of each collection are made comparable before they are weighted.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ScoreNormalization {
+    /// Scale the scores of each collection between 0 and 1, the best hit scoring 1.
+    #[default]
+    MinMax,
+
+    /// Replace the scores by their reciprocal rank fusion score `1 / (k + rank)`, with rank starting at 1.
+    /// Ignores the score distributions, which suits collections whose scores vary widely.
+    Rank { k: f32 },
+
+    /// Keep the scores as they are, for collections of stores with the same similarity metric.
+    None,
+}
+
+/// Collection searched by a federated retriever.
+struct Source<'a> {
+    store: &'a dyn VectorStore,
+    collection: String,
+    weight: f32,
+}
+
+/// Retriever searching several collections and merging their results.
+pub struct FederatedRetriever<'a, E> {
+    /// Model embedding the queries, which must have embedded the records of every collection.
+    embedder: &'a E,
+
+    /// Collections searched.
+    sources: Vec<Source<'a>>,
+
+    /// Maximum number of records searched in each collection and returned.
+    limit: usize,
+
+    /// Conditions the records must satisfy in every collection.
+    filters: Vec<Filter>,
+
+    /// How the scores of each collection are normalized.
+    normalization: ScoreNormalization,
+}
+
+impl<'a, E: Embedding + Send + Sync> FederatedRetriever<'a, E> {
+    /// Create a retriever without collections, returning the 10 best records.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use orca_core::llm::bert::Bert;
+    /// # use orca_core::pipeline::federated::FederatedRetriever;
+    /// # use orca_core::qdrant::Qdrant;
+    /// # use orca_core::vectorstore::memory::MemoryStore;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let qdrant = Qdrant::new("http://localhost:6334").unwrap();
+    /// let notes = MemoryStore::new();
+    /// let bert = Bert::new().build_model_and_tokenizer().await?;
+    /// let retriever = FederatedRetriever::new(&bert)
+    ///     .with_collection(&qdrant, "wiki", 1.0)
+    ///     .with_collection(&qdrant, "tickets", 0.5)
+    ///     .with_collection(&notes, "notes", 0.8);
+    /// let records = retriever.retrieve("Where do orcas live?").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new(embedder: &'a E) -> Self {
+        FederatedRetriever {
+            embedder,
+            sources: Vec::new(),
+            limit: 10,
+            filters: Vec::new(),
+            normalization: ScoreNormalization::default(),
+        }
+    }
+
+    /// Add a collection of a store to search, whose normalized scores are multiplied by the weight.
+    pub fn with_collection(mut self, store: &'a dyn VectorStore, collection: &str, weight: f32) -> Self {
+        self.sources.push(Source {
+            store,
+            collection: collection.to_string(),
+            weight,
+        });
+        self
+    }
+
+    /// Set the maximum number of records searched in each collection and returned.
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Add a condition the records must satisfy, in every collection.
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    /// Set how the scores of each collection are normalized, min-max by default.
+    pub fn with_normalization(mut self, normalization: ScoreNormalization) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    /// Retrieve the records most similar to the query over all the collections, best first. Fails if the
+    /// search of any collection fails.
+    #[tracing::instrument(name = "retrieval", skip_all, fields(collections = self.sources.len(), limit = self.limit, hits = Empty))]
+    pub async fn retrieve(&self, query: &str) -> Result<Vec<Record>> {
+        let vector = self.embedder.generate_embedding(prompt!(query)).await?.to_vec()?;
+        let searches = self.sources.iter().map(|source| {
+            let mut search = SearchQuery::new(vector.clone()).with_limit(self.limit);
+            search.filters = self.filters.clone();
+            async move {
+                let hits = source.store.search(&source.collection, search).await;
+                hits.with_context(|| format!("Failed to search collection {}", source.collection))
+            }
+        });
+        let results = futures::future::try_join_all(searches).await?;
+        Span::current().record("hits", results.iter().map(Vec::len).sum::<usize>());
+
+        let mut records = Vec::new();
+        for (source, hits) in self.sources.iter().zip(results) {
+            let scores = normalize(&hits, self.normalization);
+            for (hit, score) in hits.iter().zip(scores) {
+                if let Some(record) = hit.to_record() {
+                    records.push((
+                        score * source.weight,
+                        record.with_attribute(COLLECTION_ATTRIBUTE, source.collection.as_str()),
+                    ));
+                }
+            }
+        }
+        records.sort_by(|a, b| b.0.total_cmp(&a.0));
+        records.truncate(self.limit);
+        Ok(records.into_iter().map(|(score, record)| record.with_attribute(SCORE_ATTRIBUTE, score)).collect())
+    }
+}
+
+/// Normalized scores of the hits of a collection, which are sorted by score.
+fn normalize(hits: &[SearchHit], normalization: ScoreNormalization) -> Vec<f32> {
+    match normalization {
+        ScoreNormalization::MinMax => {
+            let max = hits.iter().map(|hit| hit.score).fold(f32::NEG_INFINITY, f32::max);
+            let min = hits.iter().map(|hit| hit.score).fold(f32::INFINITY, f32::min);
+            hits.iter()
+                .map(|hit| match max > min {
+                    true => (hit.score - min) / (max - min),
+                    false => 1.0,
+                })
+                .collect()
+        }
+        ScoreNormalization::Rank { k } => (1..=hits.len()).map(|rank| 1.0 / (k + rank as f32)).collect(),
+        ScoreNormalization::None => hits.iter().map(|hit| hit.score).collect(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::record::Content;
+    use crate::testing::ConstantEmbedder;
+    use crate::vectorstore::Point;
+    use anyhow::anyhow;
+    use serde_json::json;
+
+    /// Store returning the same texts and scores for any search of a collection, failing for the others.
+    struct Scored(&'static str, Vec<(&'static str, f32)>);
+
+    #[async_trait::async_trait]
+    impl VectorStore for Scored {
+        async fn ensure_collection(&self, _collection: &str, _dimensions: usize) -> Result<()> {
+            Ok(())
+        }
+
+        async fn insert(&self, _collection: &str, _points: Vec<Point>) -> Result<()> {
+            Ok(())
+        }
+
+        async fn search(&self, collection: &str, _query: SearchQuery) -> Result<Vec<SearchHit>> {
+            if collection != self.0 {
+                return Err(anyhow!("Collection {} does not exist", collection));
+            }
+            Ok(self
+                .1
+                .iter()
+                .enumerate()
+                .map(|(i, (text, score))| {
+                    let record = Record::new(Content::String(text.to_string()));
+                    let point = Point::new(i as u64, vec![], record).unwrap();
+                    SearchHit {
+                        id: point.id,
+                        score: *score,
+                        payload: point.payload,
+                    }
+                })
+                .collect())
+        }
+
+        async fn delete_collection(&self, _collection: &str) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retrieve() {
+        let wiki = Scored("wiki", vec![("Orcas live in pods.", 0.9), ("Orcas are dolphins.", 0.7)]);
+        let tickets = Scored(
+            "tickets",
+            vec![("Orca sighting report.", 25.0), ("Whale sighting report.", 5.0)],
+        );
+        let retriever = FederatedRetriever::new(&ConstantEmbedder(1.0))
+            .with_collection(&wiki, "wiki", 1.0)
+            .with_collection(&tickets, "tickets", 0.5)
+            .with_limit(3);
+        let records = retriever.retrieve("orcas").await.unwrap();
+        let texts = records.iter().map(|record| record.content.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            texts,
+            vec!["Orcas live in pods.", "Orca sighting report.", "Orcas are dolphins."]
+        );
+        assert_eq!(records[1].attributes[COLLECTION_ATTRIBUTE], json!("tickets"));
+        assert_eq!(records[1].attributes[SCORE_ATTRIBUTE], json!(0.5));
+
+        let retriever = retriever.with_normalization(ScoreNormalization::None);
+        let records = retriever.retrieve("orcas").await.unwrap();
+        assert_eq!(records[0].content.to_string(), "Orca sighting report.");
+
+        let error = retriever.with_collection(&wiki, "notes", 1.0).retrieve("orcas").await.unwrap_err();
+        assert_eq!(error.to_string(), "Failed to search collection notes");
+    }
+
+    #[test]
+    fn test_normalize() {
+        let hit = |score| SearchHit {
+            id: 0,
+            score,
+            payload: Default::default(),
+        };
+        let hits = vec![hit(0.8), hit(0.6), hit(0.4)];
+        let scores = normalize(&hits, ScoreNormalization::MinMax);
+        assert!((scores[1] - 0.5).abs() < 1e-6 && scores[0] == 1.0 && scores[2] == 0.0);
+        assert_eq!(normalize(&hits[..1], ScoreNormalization::MinMax), vec![1.0]);
+        assert_eq!(
+            normalize(&hits[..2], ScoreNormalization::Rank { k: 1.0 }),
+            vec![0.5, 1.0 / 3.0]
+        );
+    }
+}
@@ -0,0 +1,239 @@
+//! Verification that answers are grounded in their context.
+//!
+//! Retrieval-augmented answers can still state things the retrieved documents do not say. A
+//! `GroundingVerifier` splits an answer into sentences and asks a model, typically a cheaper one than the
+//! model answering, which of them the documents do not support. The resulting `GroundingReport` flags the
+//! unsupported sentences and scores the answer with the share of supported ones. `LLMPipeline::with_grounding`
+//! verifies every answer against the `documents` of the context and records the report in the `grounding`
+//! metadata of the result.
+
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+
+use super::simple::usage_record;
+use super::usage::UsageRecord;
+use super::{parse_json, PipelineResult};
+use crate::llm::LLM;
+use crate::prompt::{estimate_tokens, TemplateEngine};
+use crate::template;
+
+/// Name of the template used to verify an answer.
+pub const GROUNDING_TEMPLATE: &str = "grounding";
+
+/// Metadata key of pipeline results holding their grounding report.
+pub const GROUNDING_METADATA: &str = "grounding";
+
+static DEFAULT_GROUNDING: &str = r#"{{#chat}}
+{{#system}}You check whether the sentences of an answer are supported by the context the answer was given. A sentence is supported if the context states it or directly implies it; general knowledge does not count. Respond only with a JSON object of the form {"unsupported": [<numbers of the sentences that are not supported>]}.{{/system}}
+{{#user}}Context:
+{{#each documents}}{{this}}
+{{/each}}
+Sentences:
+{{#each sentences}}{{number}}. {{text}}
+{{/each}}{{/user}}
+{{/chat}}"#;
+
+/// Sentence of an answer and whether the context supports it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroundedSentence {
+    pub text: String,
+    pub supported: bool,
+}
+
+/// Verdict on the sentences of an answer.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroundingReport {
+    /// Share of the sentences supported by the context, from 0 to 1. Answers without sentences score 1.
+    pub score: f32,
+
+    /// The sentences of the answer, in order.
+    pub sentences: Vec<GroundedSentence>,
+}
+
+impl GroundingReport {
+    /// Score sentences verified against the context.
+    pub fn new(sentences: Vec<GroundedSentence>) -> Self {
+        let supported = sentences.iter().filter(|sentence| sentence.supported).count();
+        GroundingReport {
+            score: match sentences.is_empty() {
+                true => 1.0,
+                false => supported as f32 / sentences.len() as f32,
+            },
+            sentences,
+        }
+    }
+
+    /// Sentences the context does not support.
+    pub fn unsupported(&self) -> Vec<&str> {
+        self.sentences
+            .iter()
+            .filter(|sentence| !sentence.supported)
+            .map(|sentence| sentence.text.as_str())
+            .collect()
+    }
+
+    /// Report recorded in the metadata of a pipeline result, if its answer was verified.
+    pub fn from_result(result: &PipelineResult) -> Option<Self> {
+        serde_json::from_value(result.metadata().get(GROUNDING_METADATA)?.clone()).ok()
+    }
+}
+
+/// Verifies that the sentences of answers are supported by their context with an LLM.
+pub struct GroundingVerifier {
+    /// The LLM verifying the answers.
+    llm: Arc<dyn LLM>,
+
+    /// Template engine holding the verification template.
+    template_engine: TemplateEngine,
+}
+
+impl GroundingVerifier {
+    /// Create a verifier asking the given LLM.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use orca_core::llm::openai::OpenAI;
+    /// use orca_core::pipeline::grounding::GroundingVerifier;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let verifier = GroundingVerifier::new(&OpenAI::new().with_model("gpt-3.5-turbo-1106").with_temperature(0.0));
+    /// let documents = vec!["Orcas live in pods of up to 40 individuals.".to_string()];
+    /// let report = verifier.verify("Orcas live in pods. They can live for 200 years.", &documents).await?;
+    /// println!("{} {:?}", report.score, report.unsupported());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new<M: LLM + Clone + 'static>(llm: &M) -> Self {
+        GroundingVerifier {
+            llm: Arc::new(llm.clone()),
+            template_engine: template!(GROUNDING_TEMPLATE, DEFAULT_GROUNDING),
+        }
+    }
+
+    /// Override the verification template, which receives `documents` and `sentences` (with `number`,
+    /// starting at 1, and `text`). The model must respond with the numbers of the unsupported sentences in
+    /// an `unsupported` JSON array.
+    pub fn load_template(self, template: &str) -> Result<Self> {
+        Ok(Self {
+            template_engine: self.template_engine.register_template(GROUNDING_TEMPLATE, template)?,
+            ..self
+        })
+    }
+
+    /// Verify the sentences of an answer against the documents it was given. Without documents, no sentence
+    /// is supported and the model is not asked.
+    pub async fn verify(&self, answer: &str, documents: &[String]) -> Result<GroundingReport> {
+        self.verify_with_usage(answer, documents, &mut Vec::new()).await
+    }
+
+    /// Verify an answer, recording the tokens used by the model.
+    pub(crate) async fn verify_with_usage(
+        &self,
+        answer: &str,
+        documents: &[String],
+        usage: &mut Vec<UsageRecord>,
+    ) -> Result<GroundingReport> {
+        let sentences = split_sentences(answer);
+        if sentences.is_empty() || documents.is_empty() {
+            let sentences = sentences.into_iter().map(|text| GroundedSentence { text, supported: false }).collect();
+            return Ok(GroundingReport::new(sentences));
+        }
+
+        let numbered = sentences
+            .iter()
+            .enumerate()
+            .map(|(i, text)| json!({ "number": i + 1, "text": text }))
+            .collect::<Vec<_>>();
+        let context = json!({ "documents": documents, "sentences": numbered });
+        let prompt = self.template_engine.render_context(GROUNDING_TEMPLATE, &context)?;
+        let estimate = estimate_tokens(&prompt.to_string()) as u32;
+        let response = self.llm.generate(prompt).await?;
+        usage.push(usage_record(self.llm.as_ref(), &response, estimate));
+
+        let unsupported = parse_unsupported(&response.to_string())?;
+        let sentences = sentences
+            .into_iter()
+            .enumerate()
+            .map(|(i, text)| GroundedSentence {
+                text,
+                supported: !unsupported.contains(&(i + 1)),
+            })
+            .collect();
+        Ok(GroundingReport::new(sentences))
+    }
+}
+
+/// Numbers of the unsupported sentences in a response, given as numbers or as strings of numbers.
+fn parse_unsupported(response: &str) -> Result<Vec<usize>> {
+    let value: JsonValue = parse_json(response)?;
+    let numbers = value["unsupported"]
+        .as_array()
+        .ok_or_else(|| anyhow!("Grounding response has no unsupported sentences array: {}", response))?;
+    Ok(numbers
+        .iter()
+        .filter_map(|number| match number {
+            JsonValue::Number(number) => number.as_u64().map(|number| number as usize),
+            JsonValue::String(number) => number.trim().parse().ok(),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Split a text into sentences, ending at line breaks and at `.`, `!` or `?` followed by whitespace.
+pub fn split_sentences(text: &str) -> Vec<String> {
+    let mut sentences = Vec::new();
+    for line in text.lines() {
+        let mut start = 0;
+        let mut chars = line.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            let followed_by_space = matches!(chars.peek(), Some((_, next)) if next.is_whitespace());
+            if matches!(c, '.' | '!' | '?') && followed_by_space {
+                sentences.push(&line[start..i + 1]);
+                start = i + 1;
+            }
+        }
+        sentences.push(&line[start..]);
+    }
+    sentences
+        .into_iter()
+        .map(str::trim)
+        .filter(|sentence| !sentence.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::FixedLLM;
+
+    #[tokio::test]
+    async fn test_verify() {
+        let documents = vec!["Orcas live in pods of up to 40 individuals.".to_string()];
+        let answer = "Orcas live in pods. They can live for 200 years.";
+        let verifier = GroundingVerifier::new(&FixedLLM::new("```json\n{\"unsupported\": [2]}\n```"));
+        let report = verifier.verify(answer, &documents).await.unwrap();
+        assert_eq!(report.score, 0.5);
+        assert_eq!(report.unsupported(), vec!["They can live for 200 years."]);
+
+        let report = verifier.verify(answer, &[]).await.unwrap();
+        assert_eq!(report.score, 0.0);
+        let error = GroundingVerifier::new(&FixedLLM::new("All supported."))
+            .verify(answer, &documents)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().starts_with("Unable to find JSON"));
+    }
+
+    #[test]
+    fn test_split_sentences() {
+        assert_eq!(
+            split_sentences("Orcas weigh 3.5 tons. Really?\n- They hunt seals!\n\nThe end"),
+            vec!["Orcas weigh 3.5 tons.", "Really?", "- They hunt seals!", "The end"]
+        );
+    }
+}
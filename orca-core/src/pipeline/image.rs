@@ -0,0 +1,138 @@
+use super::context::{MergeStrategy, PipelineContext};
+use super::{Pipeline, PipelineResult};
+use crate::llm::ImageGenerator;
+use crate::prompt::context::Context;
+use crate::prompt::TemplateEngine;
+
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Pipeline that renders a prompt template and generates images from it, e.g. to illustrate text
+/// generated by an `LLMPipeline` in a content generation workflow.
+///
+/// The images are returned in the `PipelineResult`, and saved to files if an output directory is set.
+pub struct ImagePipeline<G> {
+    /// The unique identifier for this ImagePipeline, used as the prefix of the saved files.
+    pub name: String,
+
+    /// The prompt template engine instance used to render the image prompts.
+    pub template_engine: TemplateEngine,
+
+    /// The image generator used by the pipeline.
+    generator: Arc<G>,
+
+    /// The context containing key-value pairs which the template engine uses to render the prompt.
+    context: PipelineContext,
+
+    /// Directory where the generated images are saved, if any.
+    output_dir: Option<PathBuf>,
+}
+
+impl<G: ImageGenerator + Clone + 'static> ImagePipeline<G> {
+    /// Creates a new ImagePipeline given an image generator.
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// use orca_core::llm::openai::OpenAI;
+    /// use orca_core::pipeline::image::ImagePipeline;
+    ///
+    /// let client = OpenAI::new();
+    /// let pipeline = ImagePipeline::new(&client)
+    ///     .load_template("cover", "A book cover for a story about {{topic}}, flat illustration")
+    ///     .unwrap()
+    ///     .with_output_dir("covers");
+    /// ```
+    pub fn new(generator: &G) -> ImagePipeline<G> {
+        ImagePipeline {
+            name: uuid::Uuid::new_v4().to_string(),
+            template_engine: TemplateEngine::new(),
+            generator: Arc::new(generator.clone()),
+            context: PipelineContext::new(),
+            output_dir: None,
+        }
+    }
+
+    /// Registers a prompt template with the given name.
+    pub fn load_template(self, name: &str, prompt: &str) -> Result<Self> {
+        Ok(Self {
+            template_engine: self.template_engine.register_template(name, prompt)?,
+            ..self
+        })
+    }
+
+    /// Loads a context into the pipeline, failing if one of its keys is already set.
+    pub fn load_context(mut self, context: &Context) -> Result<Self> {
+        self.context.merge(&context.into(), MergeStrategy::Error)?;
+        Ok(self)
+    }
+
+    /// Saves the generated images to the given directory, which is created if it does not exist.
+    pub fn with_output_dir<P: AsRef<Path>>(mut self, output_dir: P) -> Self {
+        self.output_dir = Some(output_dir.as_ref().to_path_buf());
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl<G: ImageGenerator + Clone + 'static> Pipeline for ImagePipeline<G> {
+    async fn execute(&self, target: &str) -> Result<PipelineResult> {
+        let prompt = self.template_engine.render_context(target, &self.context)?;
+        let mut images = self.generator.generate_image(prompt).await?;
+
+        if let Some(output_dir) = &self.output_dir {
+            std::fs::create_dir_all(output_dir)?;
+            for image in images.iter_mut() {
+                let file_name = format!("{}-{}.{}", self.name, uuid::Uuid::new_v4(), image.extension());
+                image.save(output_dir.join(file_name))?;
+            }
+        }
+
+        Ok(PipelineResult::new(self.name.clone()).with_images(images))
+    }
+
+    fn template_engine(&mut self) -> &mut TemplateEngine {
+        &mut self.template_engine
+    }
+
+    fn context(&mut self) -> Option<&mut PipelineContext> {
+        Some(&mut self.context)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::llm::GeneratedImage;
+    use crate::prompt::Prompt;
+
+    /// Generator that returns the prompt as the image bytes.
+    #[derive(Clone)]
+    struct Echo;
+
+    #[async_trait::async_trait]
+    impl ImageGenerator for Echo {
+        async fn generate_image(&self, prompt: Box<dyn Prompt>) -> Result<Vec<GeneratedImage>> {
+            Ok(vec![GeneratedImage::png(prompt.to_string().into_bytes())])
+        }
+    }
+
+    #[tokio::test]
+    async fn test_execute() {
+        let output_dir = std::env::temp_dir().join(format!("orca-{}", uuid::Uuid::new_v4()));
+        let mut pipeline = ImagePipeline::new(&Echo)
+            .load_template("cover", "A cover about {{topic}}")
+            .unwrap()
+            .with_output_dir(&output_dir);
+        pipeline.context().unwrap().set("topic", "whales").unwrap();
+
+        let result = pipeline.execute("cover").await.unwrap();
+        let images = result.images();
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].bytes, b"A cover about whales");
+        let path = images[0].path.as_ref().unwrap();
+        assert_eq!(path.extension().unwrap(), "png");
+        assert_eq!(std::fs::read(path).unwrap(), b"A cover about whales");
+        std::fs::remove_dir_all(output_dir).unwrap();
+    }
+}
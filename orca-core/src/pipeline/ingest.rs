@@ -0,0 +1,822 @@
+//! Ingestion of records into a vector store collection.
+//!
+//! An `IngestPipeline` applies the ingestion transforms to records, embeds them in batches and stores
+//! them with their embeddings in any `VectorStore`, Qdrant by default. It creates the collection if
+//! needed, along with payload indexes on the record attributes that searches filter on, since filtered
+//! searches over large collections are slow without them. With a checkpoint, long runs save their
+//! progress after every batch and resume where they left off. With abstracts, each record is also
+//! summarized into a separate collection for two-stage retrieval. With a signer, every stored record
+//! carries a provenance signature checked at retrieval. With dead letters, records that still fail to be
+//! embedded or stored after the retries are set aside with their error instead of stopping the run.
+//! `stream::StreamingIngest` instead overlaps loading, embedding and storing for large corpora.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::docstore::{DocStore, PARENT_ID_ATTRIBUTE};
+use crate::llm::Embedding;
+use crate::pipeline::abstracts::DOCUMENT_ID_ATTRIBUTE;
+use crate::pipeline::checkpoint::Checkpoint;
+use crate::pipeline::dead_letter::{DeadLetter, DeadLetters};
+use crate::pipeline::summarize::Summarize;
+use crate::prompts;
+use crate::qdrant::{FieldIndex, Qdrant};
+use crate::record::provenance::Signer;
+use crate::record::{transform_all, Content, Record, Transform};
+use crate::vectorstore::{Point, VectorStore};
+
+use anyhow::{anyhow, Result};
+
+/// Path of a record attribute in the payload of the stored points.
+pub fn attribute_field(attribute: &str) -> String {
+    format!("attributes.{}", attribute)
+}
+
+/// Pipeline storing records and their embeddings in a vector store collection.
+pub struct IngestPipeline<'a, E, S: ?Sized = Qdrant> {
+    /// The vector store.
+    store: &'a S,
+
+    /// Model embedding the records.
+    embedder: &'a E,
+
+    /// Name of the collection.
+    collection: String,
+
+    /// Transforms applied to the records before they are embedded.
+    transforms: Vec<Box<dyn Transform>>,
+
+    /// Record attributes to index, with the kind of index.
+    indexes: Vec<(String, FieldIndex)>,
+
+    /// Number of records embedded and stored at once.
+    batch_size: usize,
+
+    /// Document store keeping the full records, with the number of tokens of their chunks.
+    parents: Option<(&'a dyn DocStore, usize)>,
+
+    /// File holding the progress of the ingestion.
+    checkpoint: Option<PathBuf>,
+
+    /// Summarizer writing the abstracts of the records, with the name of their collection.
+    abstracts: Option<(&'a dyn Summarize, String)>,
+
+    /// Signer adding the provenance of the stored records.
+    signer: Option<&'a Signer>,
+
+    /// Number of times a batch failing to be embedded or stored is retried.
+    max_retries: usize,
+
+    /// Wait before the first retry, doubled on each retry.
+    initial_backoff: Duration,
+
+    /// Maximum wait between retries.
+    max_backoff: Duration,
+
+    /// File holding the records that failed to be embedded or stored.
+    dead_letters: Option<PathBuf>,
+}
+
+/// Outcome of the ingestion of a source.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct IngestReport {
+    /// Records stored by this run.
+    pub stored: usize,
+
+    /// Records skipped because an earlier run stored them.
+    pub skipped: usize,
+
+    /// Records that failed, recorded in the checkpoint to be retried by the next run.
+    pub failed: usize,
+
+    /// Records, or chunks of records when keeping the records as parents, that failed to be embedded or
+    /// stored, added to the dead letters. Records with dead-lettered chunks count as stored.
+    pub dead_lettered: usize,
+}
+
+impl<'a, E: Embedding + Send + Sync, S: VectorStore + ?Sized> IngestPipeline<'a, E, S> {
+    /// Create a pipeline storing records in the given collection of a vector store, in batches of 32.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use orca_core::llm::bert::Bert;
+    /// # use orca_core::pipeline::ingest::IngestPipeline;
+    /// # use orca_core::qdrant::{FieldIndex, Qdrant};
+    /// # use orca_core::record::{pdf::Pdf, Spin};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let qdrant = Qdrant::new("http://localhost:6334").unwrap();
+    /// let bert = Bert::new().build_model_and_tokenizer().await?;
+    /// let records = Pdf::from_file("paper.pdf", false).spin()?.split(399);
+    /// IngestPipeline::new(&qdrant, &bert, "papers")
+    ///     .with_index("page", FieldIndex::Integer)
+    ///     .with_index("language", FieldIndex::Keyword)
+    ///     .ingest(records)
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new(store: &'a S, embedder: &'a E, collection: &str) -> Self {
+        IngestPipeline {
+            store,
+            embedder,
+            collection: collection.to_string(),
+            transforms: Vec::new(),
+            indexes: Vec::new(),
+            batch_size: 32,
+            parents: None,
+            checkpoint: None,
+            abstracts: None,
+            signer: None,
+            max_retries: 0,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            dead_letters: None,
+        }
+    }
+
+    /// Add a transform applied to the records before they are embedded.
+    pub fn with_transform<T: Transform + 'static>(mut self, transform: T) -> Self {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+
+    /// Index a record attribute, e.g. `page`, so that searches filtering on it stay fast.
+    pub fn with_index(mut self, attribute: &str, index: FieldIndex) -> Self {
+        self.indexes.push((attribute.to_string(), index));
+        self
+    }
+
+    /// Set the number of records embedded and stored at once.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Keep the full records in a document store and only embed their chunks of up to `max_tokens`
+    /// tokens, each chunk holding the attributes of its record and the record id in the `parent_id`
+    /// attribute. See `ParentDocumentRetriever` for retrieving the records.
+    pub fn with_parents(mut self, docs: &'a dyn DocStore, max_tokens: usize) -> Self {
+        self.parents = Some((docs, max_tokens));
+        self
+    }
+
+    /// Save the progress of the ingestion to a JSON file after every batch, and resume from it. Delete
+    /// the file to start over.
+    pub fn with_checkpoint<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.checkpoint = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Summarize each record into an abstract stored in a separate collection, e.g. with a cheap LLM.
+    /// Records are given an id in their `document_id` attribute, which their abstract and their
+    /// stored points share, so that `TwoStageRetriever` can search the abstracts first and then the
+    /// chunks of the best documents only. Records are usually whole documents, split into chunks with
+    /// `with_parents`. The collection name of the abstracts is used as an alias by `reindex`, which
+    /// rebuilds them along with the chunks.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use orca_core::docstore::FileDocStore;
+    /// # use orca_core::llm::bert::Bert;
+    /// # use orca_core::llm::openai::OpenAI;
+    /// # use orca_core::pipeline::ingest::IngestPipeline;
+    /// # use orca_core::pipeline::summarize::Summarizer;
+    /// # use orca_core::qdrant::Qdrant;
+    /// # use orca_core::record::{pdf::Pdf, Spin};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let qdrant = Qdrant::new("http://localhost:6334").unwrap();
+    /// let docs = FileDocStore::new("./documents")?;
+    /// let bert = Bert::new().build_model_and_tokenizer().await?;
+    /// let summarizer = Summarizer::new(&OpenAI::new().with_model("gpt-3.5-turbo-1106"));
+    /// IngestPipeline::new(&qdrant, &bert, "chunks")
+    ///     .with_parents(&docs, 399)
+    ///     .with_abstracts(&summarizer, "abstracts")
+    ///     .ingest(vec![Pdf::from_file("paper.pdf", false).spin()?])
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_abstracts(mut self, summarizer: &'a dyn Summarize, collection: &str) -> Self {
+        self.abstracts = Some((summarizer, collection.to_string()));
+        self
+    }
+
+    /// Sign every stored record, chunk, parent and abstract, so that retrievers can verify that they were not
+    /// modified since, see `ParentDocumentRetriever::with_verifier`.
+    pub fn with_signer(mut self, signer: &'a Signer) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    /// Retry the batches failing to be embedded or stored up to `max_retries` times, waiting 500ms, then
+    /// twice as long before each retry, up to 30 seconds.
+    pub fn with_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Set the wait before the first retry and the maximum wait between retries.
+    pub fn with_backoff(mut self, initial_backoff: Duration, max_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Add the records that still fail to be embedded or stored after the retries to a JSON file of dead
+    /// letters, with their error, instead of failing the run. The records of a failing batch are then
+    /// tried one by one, so that only the records that fail on their own, e.g. because they are too long
+    /// for the embedder, are set aside. See `retry_dead_letters`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use orca_core::llm::openai::OpenAI;
+    /// # use orca_core::pipeline::ingest::IngestPipeline;
+    /// # use orca_core::qdrant::Qdrant;
+    /// # use orca_core::record::{pdf::Pdf, Spin};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let qdrant = Qdrant::new("http://localhost:6334").unwrap();
+    /// let client = OpenAI::new();
+    /// let pipeline = IngestPipeline::new(&qdrant, &client, "papers")
+    ///     .with_retries(3)
+    ///     .with_dead_letters("papers.dead.json");
+    /// let records = Pdf::from_file("paper.pdf", false).spin()?.split(399);
+    /// let report = pipeline.ingest_source("paper.pdf", records).await?;
+    /// if report.dead_lettered > 0 {
+    ///     // Later, e.g. once the rate limit of the embedder is raised.
+    ///     pipeline.retry_dead_letters().await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_dead_letters<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.dead_letters = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Create the collection, if it does not exist, and the payload indexes.
+    pub async fn prepare(&self) -> Result<()> {
+        self.prepare_collection(&self.collection, self.abstracts_collection()).await
+    }
+
+    /// Name of the collection of abstracts, if the records are summarized.
+    fn abstracts_collection(&self) -> Option<&str> {
+        self.abstracts.as_ref().map(|(_, abstracts)| abstracts.as_str())
+    }
+
+    async fn prepare_collection(&self, collection: &str, abstracts: Option<&str>) -> Result<()> {
+        self.store
+            .ensure_collection_with_precision(collection, self.embedder.dimensions(), self.embedder.precision())
+            .await?;
+        for (attribute, index) in &self.indexes {
+            self.store.create_field_index(collection, &attribute_field(attribute), *index).await?;
+        }
+        if let Some(abstracts) = abstracts {
+            let field = attribute_field(DOCUMENT_ID_ATTRIBUTE);
+            self.store.create_field_index(collection, &field, FieldIndex::Keyword).await?;
+            self.store
+                .ensure_collection_with_precision(abstracts, self.embedder.dimensions(), self.embedder.precision())
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Transform, embed and store records, returning the number of records stored.
+    pub async fn ingest(&self, records: Vec<Record>) -> Result<usize> {
+        Ok(self.ingest_source(&self.collection, records).await?.stored)
+    }
+
+    /// Transform, embed and store the records of a source, e.g. a file. With a checkpoint, records
+    /// the source already stored are skipped, previously failed records are retried, and batches
+    /// that fail are recorded in the checkpoint instead of stopping the run. Transforms must then be
+    /// deterministic, so that records keep their position in the source across runs.
+    pub async fn ingest_source(&self, source: &str, records: Vec<Record>) -> Result<IngestReport> {
+        self.prepare().await?;
+        let records = transform_all(records, &self.transforms).await?;
+        let mut checkpoint = match &self.checkpoint {
+            Some(path) => Checkpoint::load(path)?,
+            None => Checkpoint::default(),
+        };
+
+        let cursor = checkpoint.cursor(source).min(records.len());
+        let retries: Vec<usize> = checkpoint.failed_indexes(source).into_iter().filter(|&i| i < cursor).collect();
+        let mut report = IngestReport {
+            skipped: cursor - retries.len(),
+            ..Default::default()
+        };
+        let pending: Vec<usize> = retries.into_iter().chain(cursor..records.len()).collect();
+        for batch in pending.chunks(self.batch_size) {
+            let batch_records = batch.iter().map(|&i| records[i].clone()).collect();
+            let stored = self.store_batch(&self.collection, self.abstracts_collection(), batch_records).await;
+            // Earlier failures are only cleared once their batch went through, so that they are retried
+            // again if the run is interrupted before.
+            if stored.is_ok() {
+                batch.iter().for_each(|&index| checkpoint.clear_failed(source, index));
+            }
+            match stored {
+                Ok(letters) if letters.is_empty() => report.stored += batch.len(),
+                Ok(letters) => {
+                    log::warn!(
+                        "Failed to ingest {} records of {}, added to the dead letters",
+                        letters.len(),
+                        source
+                    );
+                    // Without parents, the dead letters of the collection are records of the batch, not chunks.
+                    let records = letters.iter().filter(|letter| letter.collection == self.collection).count();
+                    report.stored += batch.len() - if self.parents.is_none() { records } else { 0 };
+                    report.dead_lettered += letters.len();
+                    self.add_dead_letters(letters)?;
+                }
+                Err(e) if self.checkpoint.is_some() => {
+                    log::warn!("Failed to ingest {} records of {}: {}", batch.len(), source, e);
+                    for &index in batch {
+                        checkpoint.fail(source, index, &e.to_string());
+                    }
+                    report.failed += batch.len();
+                }
+                Err(e) => return Err(e),
+            }
+            if let Some(path) = &self.checkpoint {
+                checkpoint.advance(source, batch[batch.len() - 1] + 1);
+                checkpoint.save(path)?;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Rebuild the collection without downtime. The collection name is used as an alias: records are
+    /// ingested into a fresh collection named after it, the current time and a random suffix, the alias is then
+    /// atomically switched to the fresh collection, and the collection it pointed to before is deleted.
+    /// Searches through the alias see the previous records until the switch. With abstracts, they are
+    /// rebuilt the same way into a fresh collection behind their own alias, switched right after the
+    /// chunks. If the ingestion fails, the fresh collections are deleted and the aliases left untouched.
+    /// A collection already named like an alias, e.g. one ingested before the first reindex, is replaced
+    /// by the alias. Returns the name of the fresh collection of chunks; checkpoints are not used.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use orca_core::llm::bert::Bert;
+    /// # use orca_core::pipeline::ingest::IngestPipeline;
+    /// # use orca_core::qdrant::Qdrant;
+    /// # use orca_core::record::{pdf::Pdf, Spin};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let qdrant = Qdrant::new("http://localhost:6334").unwrap();
+    /// let bert = Bert::new().build_model_and_tokenizer().await?;
+    /// let records = Pdf::from_file("paper.pdf", false).spin()?.split(399);
+    /// // Searches keep using the "papers" alias while it is rebuilt.
+    /// IngestPipeline::new(&qdrant, &bert, "papers").reindex(records).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn reindex(&self, records: Vec<Record>) -> Result<String> {
+        let millis = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        let suffix = uuid::Uuid::new_v4().simple().to_string();
+        let fresh = |alias: &str| format!("{}_{}_{}", alias, millis, &suffix[..8]);
+        // Aliases to switch, chunks first, with the fresh collections they are to point to.
+        let mut switches = vec![(self.collection.as_str(), fresh(&self.collection))];
+        switches.extend(self.abstracts_collection().map(|abstracts| (abstracts, fresh(abstracts))));
+        let fresh_abstracts = switches.get(1).map(|(_, collection)| collection.as_str());
+
+        let mut result = async {
+            self.prepare_collection(&switches[0].1, fresh_abstracts).await?;
+            let records = transform_all(records, &self.transforms).await?;
+            for batch in records.chunks(self.batch_size) {
+                // Fresh collections are retried through their alias, which points to them after the switch.
+                let letters = self.store_batch(&switches[0].1, fresh_abstracts, batch.to_vec()).await?;
+                let letters = letters.into_iter().map(|mut letter| {
+                    if let Some((alias, _)) = switches.iter().find(|(_, collection)| *collection == letter.collection) {
+                        letter.collection = alias.to_string();
+                    }
+                    letter
+                });
+                self.add_dead_letters(letters.collect())?;
+            }
+            Ok::<(), anyhow::Error>(())
+        }
+        .await;
+
+        // A fresh collection is deleted unless its alias was switched to it.
+        let mut previous = Vec::new();
+        for (alias, collection) in &switches {
+            if result.is_ok() {
+                match self.store.switch_alias(alias, collection).await {
+                    Ok(before) => {
+                        previous.extend(before);
+                        continue;
+                    }
+                    Err(e) => result = Err(e),
+                }
+            }
+            if let Err(cleanup) = self.store.delete_collection(collection).await {
+                log::warn!(
+                    "Failed to delete collection {} after a failed reindex: {}",
+                    collection,
+                    cleanup
+                );
+            }
+        }
+        for collection in previous {
+            self.store.delete_collection(&collection).await?;
+        }
+        result?;
+        Ok(switches.swap_remove(0).1)
+    }
+
+    /// Retry storing the dead letters of the collections of the pipeline, as they were to be stored. The
+    /// records that fail again are kept in the dead letters with their new error. Returns the number of
+    /// records stored and still failing, in `dead_lettered`.
+    pub async fn retry_dead_letters(&self) -> Result<IngestReport> {
+        let path = self.dead_letters.as_ref().ok_or_else(|| anyhow!("The pipeline has no dead letters"))?;
+        let mut dead_letters = DeadLetters::load(path)?;
+        let mut collections = vec![self.collection.as_str()];
+        if let Some((_, abstracts)) = &self.abstracts {
+            collections.push(abstracts);
+        }
+        let letters = dead_letters.take(&collections);
+
+        let mut report = IngestReport::default();
+        for collection in collections {
+            let letters = letters.iter().filter(|letter| letter.collection == collection).collect::<Vec<_>>();
+            if letters.is_empty() {
+                continue;
+            }
+            let records = letters.iter().map(|letter| letter.record.clone()).collect::<Vec<_>>();
+            let failed = self.insert_signed(collection, &records).await?;
+            report.stored += letters.len() - failed.len();
+            report.dead_lettered += failed.len();
+            dead_letters.extend(failed.into_iter().map(|(index, error)| DeadLetter {
+                error,
+                attempts: letters[index].attempts + 1,
+                ..letters[index].clone()
+            }));
+        }
+        dead_letters.save(path)?;
+        Ok(report)
+    }
+
+    /// Embed and store a batch of records, or of their chunks when keeping the records as parents,
+    /// along with their abstracts. Returns the records set aside as dead letters.
+    async fn store_batch(
+        &self,
+        collection: &str,
+        abstracts: Option<&str>,
+        records: Vec<Record>,
+    ) -> Result<Vec<DeadLetter>> {
+        let mut letters = Vec::new();
+        let records = match (&self.abstracts, abstracts) {
+            (Some((summarizer, _)), Some(abstracts)) => {
+                let (records, summaries) = summarize_records(*summarizer, records).await?;
+                letters.extend(self.insert(abstracts, summaries).await?);
+                records
+            }
+            _ => records,
+        };
+        let records = match self.parents {
+            Some((docs, max_tokens)) => store_parents(docs, records, max_tokens, self.signer).await?,
+            None => records,
+        };
+        letters.extend(self.insert(collection, records).await?);
+        Ok(letters)
+    }
+
+    /// Sign, embed and insert records in batches, returning the records set aside as dead letters.
+    async fn insert(&self, collection: &str, records: Vec<Record>) -> Result<Vec<DeadLetter>> {
+        let records = match self.signer {
+            Some(signer) => records.into_iter().map(|record| signer.sign(record)).collect::<Result<Vec<_>>>()?,
+            None => records,
+        };
+        let failed = self.insert_signed(collection, &records).await?;
+        Ok(failed
+            .into_iter()
+            .map(|(index, error)| DeadLetter {
+                collection: collection.to_string(),
+                record: records[index].clone(),
+                error,
+                attempts: 1,
+            })
+            .collect())
+    }
+
+    /// Embed and insert records in batches, retrying the batches that fail. Without dead letters, fails with
+    /// the error of the first batch failing every retry; with dead letters, the records of the batch are
+    /// tried one by one, and the indexes of those failing are returned with their error.
+    async fn insert_signed(&self, collection: &str, records: &[Record]) -> Result<Vec<(usize, String)>> {
+        let mut failed = Vec::new();
+        for (start, batch) in (0..).step_by(self.batch_size).zip(records.chunks(self.batch_size)) {
+            let error = match self.insert_with_retries(collection, batch).await {
+                Ok(()) => continue,
+                Err(e) if self.dead_letters.is_none() => return Err(e),
+                Err(e) => e,
+            };
+            if batch.len() == 1 {
+                failed.push((start, format!("{:#}", error)));
+                continue;
+            }
+            log::warn!(
+                "Failed to ingest a batch into {}, ingesting its records one by one: {}",
+                collection,
+                error
+            );
+            for (index, record) in (start..).zip(batch) {
+                if let Err(e) = self.embed_and_insert(collection, std::slice::from_ref(record)).await {
+                    failed.push((index, format!("{:#}", e)));
+                }
+            }
+        }
+        Ok(failed)
+    }
+
+    /// Embed and insert a batch of records, retrying with exponential backoff.
+    async fn insert_with_retries(&self, collection: &str, batch: &[Record]) -> Result<()> {
+        let mut backoff = self.initial_backoff;
+        for retry in 0..self.max_retries {
+            match self.embed_and_insert(collection, batch).await {
+                Ok(()) => return Ok(()),
+                Err(e) => log::warn!(
+                    "Failed to ingest {} records into {}, retry {} in {:?}: {}",
+                    batch.len(),
+                    collection,
+                    retry + 1,
+                    backoff,
+                    e
+                ),
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(self.max_backoff);
+        }
+        self.embed_and_insert(collection, batch).await
+    }
+
+    /// Embed and insert a batch of records.
+    async fn embed_and_insert(&self, collection: &str, batch: &[Record]) -> Result<()> {
+        let embeddings = self.embedder.generate_embeddings(prompts!(batch)).await?;
+        // Random ids, so that batches and later ingestions do not replace stored points.
+        let points = batch
+            .iter()
+            .zip(embeddings.into_vectors())
+            .map(|(record, vector)| Point::new(uuid::Uuid::new_v4().as_u64_pair().0, vector, record))
+            .collect::<Result<Vec<_>>>()?;
+        self.store.insert(collection, points).await
+    }
+
+    /// Add records to the dead letters file.
+    fn add_dead_letters(&self, letters: Vec<DeadLetter>) -> Result<()> {
+        let Some(path) = &self.dead_letters else {
+            return Ok(());
+        };
+        if letters.is_empty() {
+            return Ok(());
+        }
+        let mut dead_letters = DeadLetters::load(path)?;
+        dead_letters.extend(letters);
+        dead_letters.save(path)
+    }
+}
+
+/// Give records a document id and summarize them into abstracts sharing their id and attributes.
+async fn summarize_records(summarizer: &dyn Summarize, records: Vec<Record>) -> Result<(Vec<Record>, Vec<Record>)> {
+    let mut documents = Vec::with_capacity(records.len());
+    let mut abstracts = Vec::with_capacity(records.len());
+    for record in records {
+        let id = uuid::Uuid::new_v4().to_string();
+        let record = record.with_attribute(DOCUMENT_ID_ATTRIBUTE, id.as_str());
+        let summary = summarizer.summarize(std::slice::from_ref(&record)).await?;
+        let mut summary = Record::new(Content::String(summary.content()));
+        summary.attributes = record.attributes.clone();
+        abstracts.push(summary);
+        documents.push(record);
+    }
+    Ok((documents, abstracts))
+}
+
+/// Store records in a document store, signed if there is a signer, and split them into chunks pointing to them.
+async fn store_parents(
+    docs: &dyn DocStore,
+    records: Vec<Record>,
+    max_tokens: usize,
+    signer: Option<&Signer>,
+) -> Result<Vec<Record>> {
+    let mut chunks = Vec::new();
+    for record in records {
+        let id = uuid::Uuid::new_v4().to_string();
+        match signer {
+            Some(signer) => docs.put(&id, &signer.sign(record.clone())?).await?,
+            None => docs.put(&id, &record).await?,
+        }
+        chunks.extend(
+            record
+                .split(max_tokens)
+                .into_iter()
+                .map(|chunk| chunk.with_attribute(PARENT_ID_ATTRIBUTE, id.as_str())),
+        );
+    }
+    Ok(chunks)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pipeline::summarize::Summarizer;
+    use crate::record::Content;
+    use crate::testing::{ConstantEmbedder, FixedLLM};
+    use crate::vectorstore::memory::MemoryStore;
+    use crate::vectorstore::{SearchHit, SearchQuery};
+    use std::sync::Mutex;
+
+    /// Store keeping the content of the inserted points, failing the given insert calls and never
+    /// answering the `hang_on` call.
+    struct Flaky {
+        inserted: Mutex<Vec<String>>,
+        calls: Mutex<usize>,
+        fail_on: Vec<usize>,
+        hang_on: usize,
+    }
+
+    #[async_trait::async_trait]
+    impl VectorStore for Flaky {
+        async fn ensure_collection(&self, _collection: &str, _dimensions: usize) -> Result<()> {
+            Ok(())
+        }
+
+        async fn insert(&self, _collection: &str, points: Vec<Point>) -> Result<()> {
+            let call = {
+                let mut calls = self.calls.lock().unwrap();
+                *calls += 1;
+                *calls
+            };
+            if call == self.hang_on {
+                std::future::pending::<()>().await;
+            }
+            if self.fail_on.contains(&call) {
+                return Err(anyhow::anyhow!("connection reset"));
+            }
+            let contents = points.iter().map(|point| point.payload["content"].as_str().unwrap().to_string());
+            self.inserted.lock().unwrap().extend(contents);
+            Ok(())
+        }
+
+        async fn search(&self, _collection: &str, _query: SearchQuery) -> Result<Vec<SearchHit>> {
+            Ok(Vec::new())
+        }
+
+        async fn delete_collection(&self, _collection: &str) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resume() {
+        let path = std::env::temp_dir().join(format!("orca-ingest-{}.json", uuid::Uuid::new_v4()));
+        let store = Flaky {
+            inserted: Mutex::new(Vec::new()),
+            calls: Mutex::new(0),
+            fail_on: vec![2],
+            hang_on: 0,
+        };
+        let records = || (0..5).map(|i| Record::new(Content::String(i.to_string()))).collect::<Vec<_>>();
+        let pipeline = IngestPipeline::new(&store, &ConstantEmbedder(1.0), "numbers")
+            .with_batch_size(2)
+            .with_checkpoint(&path);
+
+        let report = pipeline.ingest_source("numbers.txt", records()).await.unwrap();
+        assert_eq!((report.stored, report.skipped, report.failed), (3, 0, 2));
+        assert_eq!(*store.inserted.lock().unwrap(), vec!["0", "1", "4"]);
+
+        let report = pipeline.ingest_source("numbers.txt", records()).await.unwrap();
+        assert_eq!((report.stored, report.skipped, report.failed), (2, 3, 0));
+        assert_eq!(*store.inserted.lock().unwrap(), vec!["0", "1", "4", "2", "3"]);
+        assert_eq!(Checkpoint::load(&path).unwrap().cursor("numbers.txt"), 5);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_retry() {
+        let path = std::env::temp_dir().join(format!("orca-ingest-{}.json", uuid::Uuid::new_v4()));
+        let store = Flaky {
+            inserted: Mutex::new(Vec::new()),
+            calls: Mutex::new(0),
+            fail_on: vec![2, 3],
+            hang_on: 6,
+        };
+        let records = || (0..4).map(|i| Record::new(Content::String(i.to_string()))).collect::<Vec<_>>();
+        let pipeline = IngestPipeline::new(&store, &ConstantEmbedder(1.0), "numbers")
+            .with_batch_size(1)
+            .with_checkpoint(&path);
+
+        let report = pipeline.ingest_source("numbers.txt", records()).await.unwrap();
+        assert_eq!((report.stored, report.failed), (2, 2));
+
+        // The run is interrupted while retrying the second failed record, after the first went through.
+        let run = pipeline.ingest_source("numbers.txt", records());
+        assert!(tokio::time::timeout(Duration::from_millis(100), run).await.is_err());
+        assert_eq!(Checkpoint::load(&path).unwrap().failed_indexes("numbers.txt"), vec![2]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// Store rejecting the points of the given content while it is rejected.
+    struct Picky {
+        inserted: Mutex<Vec<String>>,
+        rejected: Mutex<Option<&'static str>>,
+    }
+
+    #[async_trait::async_trait]
+    impl VectorStore for Picky {
+        async fn ensure_collection(&self, _collection: &str, _dimensions: usize) -> Result<()> {
+            Ok(())
+        }
+
+        async fn insert(&self, _collection: &str, points: Vec<Point>) -> Result<()> {
+            let contents = points.iter().map(|point| point.payload["content"].as_str().unwrap().to_string());
+            let contents = contents.collect::<Vec<_>>();
+            if let Some(rejected) = *self.rejected.lock().unwrap() {
+                if contents.iter().any(|content| content == rejected) {
+                    return Err(anyhow!("Invalid point {}", rejected));
+                }
+            }
+            self.inserted.lock().unwrap().extend(contents);
+            Ok(())
+        }
+
+        async fn search(&self, _collection: &str, _query: SearchQuery) -> Result<Vec<SearchHit>> {
+            Ok(Vec::new())
+        }
+
+        async fn delete_collection(&self, _collection: &str) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dead_letters() {
+        let path = std::env::temp_dir().join(format!("orca-dead-letters-{}.json", uuid::Uuid::new_v4()));
+        let store = Picky {
+            inserted: Mutex::new(Vec::new()),
+            rejected: Mutex::new(Some("2")),
+        };
+        let records = (0..5).map(|i| Record::new(Content::String(i.to_string()))).collect::<Vec<_>>();
+        let pipeline = IngestPipeline::new(&store, &ConstantEmbedder(1.0), "numbers")
+            .with_batch_size(2)
+            .with_retries(1)
+            .with_backoff(Duration::from_millis(1), Duration::from_millis(1))
+            .with_dead_letters(&path);
+
+        // Only the rejected record of the failing batch is set aside.
+        let report = pipeline.ingest_source("numbers.txt", records).await.unwrap();
+        assert_eq!((report.stored, report.dead_lettered), (4, 1));
+        assert_eq!(*store.inserted.lock().unwrap(), vec!["0", "1", "3", "4"]);
+        let letters = DeadLetters::load(&path).unwrap();
+        assert_eq!(
+            (letters.letters[0].error.as_str(), letters.letters[0].attempts),
+            ("Invalid point 2", 1)
+        );
+
+        let report = pipeline.retry_dead_letters().await.unwrap();
+        assert_eq!((report.stored, report.dead_lettered), (0, 1));
+        assert_eq!(DeadLetters::load(&path).unwrap().letters[0].attempts, 2);
+
+        *store.rejected.lock().unwrap() = None;
+        let report = pipeline.retry_dead_letters().await.unwrap();
+        assert_eq!((report.stored, report.dead_lettered), (1, 0));
+        assert!(DeadLetters::load(&path).unwrap().is_empty());
+        assert_eq!(store.inserted.lock().unwrap().last().unwrap(), "2");
+        std::fs::remove_file(path).unwrap();
+
+        // Without dead letters, the run fails.
+        *store.rejected.lock().unwrap() = Some("0");
+        let pipeline = IngestPipeline::new(&store, &ConstantEmbedder(1.0), "numbers");
+        let error = pipeline.ingest(vec![Record::new(Content::String("0".to_string()))]).await.unwrap_err();
+        assert_eq!(error.to_string(), "Invalid point 0");
+    }
+
+    #[tokio::test]
+    async fn test_reindex() {
+        let store = MemoryStore::new();
+        let records = |n| (0..n).map(|i| Record::new(Content::String(i.to_string()))).collect::<Vec<_>>();
+        let pipeline = IngestPipeline::new(&store, &ConstantEmbedder(1.0), "numbers");
+
+        let first = pipeline.reindex(records(3)).await.unwrap();
+        assert_eq!(store.len("numbers"), Some(3));
+        let second = pipeline.reindex(records(5)).await.unwrap();
+        assert_ne!(first, second);
+        assert_eq!(store.len("numbers"), Some(5));
+        assert_eq!(store.len(&first), None);
+    }
+
+    #[tokio::test]
+    async fn test_reindex_abstracts() {
+        let store = MemoryStore::new();
+        let records = |n| (0..n).map(|i| Record::new(Content::String(i.to_string()))).collect::<Vec<_>>();
+        let summarizer = Summarizer::new(&FixedLLM::new("A number."));
+        let pipeline =
+            IngestPipeline::new(&store, &ConstantEmbedder(1.0), "numbers").with_abstracts(&summarizer, "abstracts");
+
+        pipeline.reindex(records(3)).await.unwrap();
+        assert_eq!(store.len("abstracts"), Some(3));
+        pipeline.reindex(records(5)).await.unwrap();
+        assert_eq!(store.len("numbers"), Some(5));
+        assert_eq!(store.len("abstracts"), Some(5));
+    }
+}
@@ -0,0 +1,413 @@
+//! Knowledge graph extraction and retrieval.
+//!
+//! A `GraphExtractor` asks an LLM to extract (subject, relation, object) triples from records and
+//! stores them in a `KnowledgeGraph`. A `GraphRetriever` then finds the entities mentioned in a
+//! question and returns their neighborhood in the graph, which can be used as context to answer
+//! questions that need several hops, e.g. "Where was the founder of Orca born?".
+
+use crate::llm::LLM;
+use crate::prompt::TemplateEngine;
+use crate::record::Record;
+use crate::template;
+
+use anyhow::Result;
+use petgraph::graph::{DiGraph, NodeIndex};
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+
+/// Name of the template used to extract triples.
+pub const EXTRACTION_TEMPLATE: &str = "extraction";
+
+static DEFAULT_EXTRACTION: &str = r#"{{#chat}}
+{{#system}}You are an expert at building knowledge graphs. Extract the facts stated in the text given by the user as (subject, relation, object) triples. Subjects and objects are entities such as people, organizations, places, dates or concepts, and should be written the same way every time they appear. Relations are short lowercase verb phrases such as "founded" or "is located in".
+Respond only with a JSON array of the form [{"subject": "<entity>", "relation": "<relation>", "object": "<entity>"}]. If the text states no facts, respond with an empty array.{{/system}}
+{{#user}}{{text}}{{/user}}
+{{/chat}}"#;
+
+/// A fact extracted from a record.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Triple {
+    pub subject: String,
+    pub relation: String,
+    pub object: String,
+}
+
+impl Triple {
+    /// Create a new triple.
+    pub fn new(subject: &str, relation: &str, object: &str) -> Self {
+        Self {
+            subject: subject.to_string(),
+            relation: relation.to_string(),
+            object: object.to_string(),
+        }
+    }
+
+    /// Parse a list of triples from an LLM response, possibly surrounded by other text such as a
+    /// markdown code fence. Triples with an empty field are dropped.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::pipeline::knowledge_graph::Triple;
+    ///
+    /// let triples = Triple::parse_all(r#"[{"subject": "Orca", "relation": "written in", "object": "Rust"}]"#).unwrap();
+    /// assert_eq!(triples, vec![Triple::new("Orca", "written in", "Rust")]);
+    /// ```
+    pub fn parse_all(response: &str) -> Result<Vec<Triple>> {
+        let start = response.find('[');
+        let end = response.rfind(']');
+        let triples: Vec<Triple> = match (start, end) {
+            (Some(start), Some(end)) if start < end => serde_json::from_str(&response[start..=end])?,
+            _ => return Err(anyhow::anyhow!("Unable to find triples in response: {}", response)),
+        };
+        Ok(triples
+            .into_iter()
+            .map(|t| Triple::new(&normalize(&t.subject), &normalize(&t.relation), &normalize(&t.object)))
+            .filter(|t| !t.subject.is_empty() && !t.relation.is_empty() && !t.object.is_empty())
+            .collect())
+    }
+}
+
+impl std::fmt::Display for Triple {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.subject, self.relation, self.object)
+    }
+}
+
+/// Collapse whitespace so that the same entity is always written the same way.
+fn normalize(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// In-memory knowledge graph. Entities are nodes and relations are directed edges. Entities and
+/// relations are matched case-insensitively, so adding the same fact twice has no effect.
+#[derive(Debug, Clone, Default)]
+pub struct KnowledgeGraph {
+    /// The underlying graph.
+    graph: DiGraph<String, String>,
+
+    /// Node of each entity, keyed by its lowercase name.
+    nodes: HashMap<String, NodeIndex>,
+
+    /// Edges already in the graph, used to deduplicate triples.
+    edges: HashSet<(NodeIndex, String, NodeIndex)>,
+}
+
+impl KnowledgeGraph {
+    /// Create an empty knowledge graph.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a triple to the graph. Returns `false` if the triple was already in the graph.
+    pub fn add(&mut self, triple: Triple) -> bool {
+        let subject = self.node(&triple.subject);
+        let object = self.node(&triple.object);
+        if !self.edges.insert((subject, triple.relation.to_lowercase(), object)) {
+            return false;
+        }
+        self.graph.add_edge(subject, object, triple.relation);
+        true
+    }
+
+    /// Add several triples to the graph and return how many were new.
+    pub fn extend(&mut self, triples: impl IntoIterator<Item = Triple>) -> usize {
+        triples.into_iter().filter(|triple| self.add(triple.clone())).count()
+    }
+
+    fn node(&mut self, entity: &str) -> NodeIndex {
+        match self.nodes.get(&entity.to_lowercase()) {
+            Some(index) => *index,
+            None => {
+                let index = self.graph.add_node(entity.to_string());
+                self.nodes.insert(entity.to_lowercase(), index);
+                index
+            }
+        }
+    }
+
+    /// Number of triples in the graph.
+    pub fn len(&self) -> usize {
+        self.graph.edge_count()
+    }
+
+    /// Whether the graph has no triples.
+    pub fn is_empty(&self) -> bool {
+        self.graph.edge_count() == 0
+    }
+
+    /// The entities in the graph.
+    pub fn entities(&self) -> impl Iterator<Item = &str> {
+        self.graph.node_weights().map(|entity| entity.as_str())
+    }
+
+    /// All the triples in the graph, in insertion order.
+    pub fn triples(&self) -> Vec<Triple> {
+        self.graph
+            .edge_references()
+            .map(|edge| self.triple(edge.source(), edge.weight(), edge.target()))
+            .collect()
+    }
+
+    fn triple(&self, subject: NodeIndex, relation: &str, object: NodeIndex) -> Triple {
+        Triple::new(&self.graph[subject], relation, &self.graph[object])
+    }
+
+    /// The triples within `hops` edges of an entity, following edges in both directions. Triples
+    /// closer to the entity come first.
+    pub fn neighborhood(&self, entity: &str, hops: usize) -> Vec<Triple> {
+        let start = match self.nodes.get(&entity.to_lowercase()) {
+            Some(index) => *index,
+            None => return Vec::new(),
+        };
+
+        let mut triples = Vec::new();
+        let mut seen_edges = HashSet::new();
+        let mut visited = HashSet::from([start]);
+        let mut queue = VecDeque::from([(start, 0)]);
+        while let Some((node, depth)) = queue.pop_front() {
+            if depth == hops {
+                continue;
+            }
+            let edges = self
+                .graph
+                .edges_directed(node, Direction::Outgoing)
+                .chain(self.graph.edges_directed(node, Direction::Incoming));
+            for edge in edges {
+                if seen_edges.insert(edge.id()) {
+                    triples.push(self.triple(edge.source(), edge.weight(), edge.target()));
+                }
+                let next = if edge.source() == node {
+                    edge.target()
+                } else {
+                    edge.source()
+                };
+                if visited.insert(next) {
+                    queue.push_back((next, depth + 1));
+                }
+            }
+        }
+        triples
+    }
+
+    /// Export the triples as JSON.
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(&self.triples())?)
+    }
+
+    /// Build a graph from triples exported with `to_json`.
+    pub fn from_json(json: &str) -> Result<Self> {
+        let mut graph = Self::new();
+        graph.extend(serde_json::from_str::<Vec<Triple>>(json)?);
+        Ok(graph)
+    }
+
+    /// Export the graph in Graphviz DOT format.
+    pub fn to_dot(&self) -> String {
+        format!("{}", petgraph::dot::Dot::new(&self.graph))
+    }
+}
+
+/// Pipeline that extracts triples from records with an LLM.
+pub struct GraphExtractor<M> {
+    /// The LLM used to extract triples.
+    llm: Arc<M>,
+
+    /// Template engine holding the extraction template.
+    template_engine: TemplateEngine,
+}
+
+impl<M: LLM + Clone + 'static> GraphExtractor<M> {
+    /// Creates a new extractor with the default extraction template.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use orca_core::llm::openai::OpenAI;
+    /// use orca_core::pipeline::knowledge_graph::GraphExtractor;
+    ///
+    /// let client = OpenAI::new();
+    /// let extractor = GraphExtractor::new(&client);
+    /// ```
+    pub fn new(llm: &M) -> GraphExtractor<M> {
+        GraphExtractor {
+            llm: Arc::new(llm.clone()),
+            template_engine: template!(EXTRACTION_TEMPLATE, DEFAULT_EXTRACTION),
+        }
+    }
+
+    /// Override the extraction template. The template receives `text`.
+    pub fn load_template(self, template: &str) -> Result<Self> {
+        Ok(Self {
+            template_engine: self.template_engine.register_template(EXTRACTION_TEMPLATE, template)?,
+            ..self
+        })
+    }
+
+    /// Extract the triples stated in a record.
+    pub async fn extract(&self, record: &Record) -> Result<Vec<Triple>> {
+        let context = json!({ "text": record.content.to_string() });
+        let prompt = self.template_engine.render_context(EXTRACTION_TEMPLATE, &context)?;
+        let response = self.llm.generate(prompt).await?;
+        Triple::parse_all(&response.to_string())
+    }
+
+    /// Extract the triples of every record into a graph and return how many new triples were added.
+    pub async fn extract_into(&self, records: &[Record], graph: &mut KnowledgeGraph) -> Result<usize> {
+        let mut added = 0;
+        for record in records {
+            added += graph.extend(self.extract(record).await?);
+        }
+        log::debug!("Added {} triples to the knowledge graph", added);
+        Ok(added)
+    }
+}
+
+/// Retriever that returns the graph neighborhood of the entities mentioned in a question.
+pub struct GraphRetriever {
+    /// The graph to retrieve from.
+    graph: KnowledgeGraph,
+
+    /// Number of hops to follow from each entity.
+    hops: usize,
+
+    /// Maximum number of triples returned.
+    limit: usize,
+}
+
+impl GraphRetriever {
+    /// Creates a new retriever over a graph, following 2 hops and returning at most 50 triples.
+    pub fn new(graph: KnowledgeGraph) -> Self {
+        Self {
+            graph,
+            hops: 2,
+            limit: 50,
+        }
+    }
+
+    /// Set the number of hops to follow from each entity.
+    pub fn with_hops(mut self, hops: usize) -> Self {
+        self.hops = hops;
+        self
+    }
+
+    /// Set the maximum number of triples returned.
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// The graph to retrieve from.
+    pub fn graph(&self) -> &KnowledgeGraph {
+        &self.graph
+    }
+
+    /// The entities of the graph mentioned in the text, as whole words.
+    pub fn entities_in(&self, text: &str) -> Vec<&str> {
+        let text = text.to_lowercase();
+        self.graph
+            .entities()
+            .filter(|entity| {
+                let entity = entity.to_lowercase();
+                text.match_indices(&entity).any(|(i, _)| {
+                    let before = text[..i].chars().last();
+                    let after = text[i + entity.len()..].chars().next();
+                    !before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+                })
+            })
+            .collect()
+    }
+
+    /// Retrieve the triples around the entities mentioned in the question.
+    pub fn retrieve(&self, question: &str) -> Vec<Triple> {
+        let mut seen = HashSet::new();
+        self.entities_in(question)
+            .into_iter()
+            .flat_map(|entity| self.graph.neighborhood(entity, self.hops))
+            .filter(|triple| seen.insert(triple.clone()))
+            .take(self.limit)
+            .collect()
+    }
+
+    /// Retrieve the triples around the entities mentioned in the question, one fact per line, to
+    /// be used as context in a prompt.
+    pub fn context(&self, question: &str) -> String {
+        self.retrieve(question).iter().map(|triple| triple.to_string()).collect::<Vec<_>>().join("\n")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::record::Content;
+    use crate::testing::FixedLLM;
+
+    /// Triples answered by the LLM of the extraction tests.
+    const TRIPLES: &str = r#"```json
+[{"subject": "Ada", "relation": "founded", "object": "Orca Labs"},
+ {"subject": "Orca  Labs", "relation": "is located in", "object": "Lisbon"},
+ {"subject": "ada", "relation": "Founded", "object": "orca labs"}]
+```"#;
+
+    fn graph() -> KnowledgeGraph {
+        let mut graph = KnowledgeGraph::new();
+        graph.extend(vec![
+            Triple::new("Ada", "founded", "Orca Labs"),
+            Triple::new("Orca Labs", "is located in", "Lisbon"),
+            Triple::new("Lisbon", "is the capital of", "Portugal"),
+        ]);
+        graph
+    }
+
+    #[test]
+    fn test_dedup() {
+        let mut graph = graph();
+        assert!(!graph.add(Triple::new("ada", "Founded", "ORCA LABS")));
+        assert!(graph.add(Triple::new("Ada", "works at", "Orca Labs")));
+        assert_eq!(graph.len(), 4);
+        assert_eq!(graph.entities().count(), 4);
+    }
+
+    #[test]
+    fn test_neighborhood() {
+        let graph = graph();
+        assert_eq!(
+            graph.neighborhood("ada", 1),
+            vec![Triple::new("Ada", "founded", "Orca Labs")]
+        );
+        assert_eq!(graph.neighborhood("Ada", 2).len(), 2);
+        assert_eq!(graph.neighborhood("Lisbon", 1).len(), 2);
+        assert!(graph.neighborhood("Rust", 2).is_empty());
+    }
+
+    #[test]
+    fn test_json_roundtrip() {
+        let graph = graph();
+        let restored = KnowledgeGraph::from_json(&graph.to_json().unwrap()).unwrap();
+        assert_eq!(restored.triples(), graph.triples());
+        assert!(graph.to_dot().contains("Orca Labs"));
+    }
+
+    #[test]
+    fn test_retriever() {
+        let retriever = GraphRetriever::new(graph()).with_hops(2);
+        assert_eq!(retriever.entities_in("Where is the company Ada founded?"), vec!["Ada"]);
+        assert!(retriever.entities_in("Adam who?").is_empty());
+
+        let context = retriever.context("In which city is the company founded by Ada?");
+        assert_eq!(context, "Ada founded Orca Labs\nOrca Labs is located in Lisbon");
+    }
+
+    #[tokio::test]
+    async fn test_extract_into() {
+        let extractor = GraphExtractor::new(&FixedLLM::new(TRIPLES));
+        let mut graph = KnowledgeGraph::new();
+        let records = vec![Record::new(Content::String(
+            "Ada founded Orca Labs in Lisbon.".to_string(),
+        ))];
+        assert_eq!(extractor.extract_into(&records, &mut graph).await.unwrap(), 2);
+        assert_eq!(extractor.extract_into(&records, &mut graph).await.unwrap(), 0);
+    }
+}
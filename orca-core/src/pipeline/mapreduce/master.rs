@@ -1,100 +1,316 @@
 use super::task::{Task, TaskType, WorkerMsg, WorkerTask};
 use super::worker::Worker;
-use crate::pipelines::pipeline::LLMPipeline;
-use crate::pipelines::PipelineResult;
+use super::StageUsage;
+use crate::llm::{LLMResponse, LLM};
+use crate::pipeline::simple::LLMPipeline;
+use crate::pipeline::PipelineResult;
 use crate::record::{self, Record};
-use std::sync::Arc;
+use crate::task;
+use anyhow::Result;
+use serde_json::{Map, Value as JsonValue};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex as StdMutex};
 use tokio::sync::{
     mpsc::{channel, Receiver, Sender},
-    Mutex, RwLock,
+    Mutex,
 };
+use tokio::task::JoinSet;
+
+/// Async function reducing the map outputs, in the order of the input records, into a single output.
+pub type ReduceFn = Arc<dyn Fn(Vec<String>) -> Pin<Box<dyn Future<Output = Result<String>> + Send>> + Send + Sync>;
+
+/// How the map outputs are reduced.
+#[derive(Clone)]
+pub enum Reducer {
+    /// Run the reduce pipeline with the given template over the map outputs.
+    Template(String),
+
+    /// Call a function over the map outputs. Useful when the reduction is not another LLM call.
+    Function(ReduceFn),
+}
+
+impl Reducer {
+    /// Create a reducer from an async function over the ordered map outputs.
+    pub fn function<F, Fut>(f: F) -> Self
+    where
+        F: Fn(Vec<String>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<String>> + Send + 'static,
+    {
+        Reducer::Function(Arc::new(move |outputs| Box::pin(f(outputs))))
+    }
+
+    /// Join the map outputs with a separator.
+    pub fn concatenate(separator: &str) -> Self {
+        let separator = separator.to_string();
+        Reducer::function(move |outputs| {
+            let separator = separator.clone();
+            async move { Ok(outputs.join(&separator)) }
+        })
+    }
+
+    /// Pick the most frequent map output, ignoring surrounding whitespace. Ties go to the output
+    /// that appeared first.
+    pub fn majority_vote() -> Self {
+        Reducer::function(|outputs| async move { Ok(majority_vote(&outputs)) })
+    }
+
+    /// Merge map outputs that are JSON objects into a single object. Arrays under the same key are
+    /// concatenated, and other values are overwritten by later outputs.
+    pub fn json_merge() -> Self {
+        Reducer::function(|outputs| async move { Ok(json_merge(&outputs)?.to_string()) })
+    }
+}
+
+impl From<&str> for Reducer {
+    fn from(template_name: &str) -> Self {
+        Reducer::Template(template_name.to_string())
+    }
+}
+
+impl From<String> for Reducer {
+    fn from(template_name: String) -> Self {
+        Reducer::Template(template_name)
+    }
+}
+
+fn majority_vote(outputs: &[String]) -> String {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for output in outputs {
+        *counts.entry(output.trim()).or_default() += 1;
+    }
+    let mut winner = "";
+    let mut best = 0;
+    for output in outputs {
+        let count = counts[output.trim()];
+        if count > best {
+            winner = output.trim();
+            best = count;
+        }
+    }
+    winner.to_string()
+}
+
+fn json_merge(outputs: &[String]) -> Result<JsonValue> {
+    let mut merged = Map::new();
+    for output in outputs {
+        let object = match (output.find('{'), output.rfind('}')) {
+            (Some(start), Some(end)) if start < end => {
+                serde_json::from_str::<Map<String, JsonValue>>(&output[start..=end])?
+            }
+            _ => return Err(anyhow::anyhow!("Map output is not a JSON object: {}", output)),
+        };
+        for (key, value) in object {
+            match (merged.get_mut(&key), value) {
+                (Some(JsonValue::Array(existing)), JsonValue::Array(values)) => existing.extend(values),
+                (_, value) => {
+                    merged.insert(key, value);
+                }
+            }
+        }
+    }
+    Ok(JsonValue::Object(merged))
+}
 
 pub(crate) struct Master {
     worker_channels: Vec<Sender<WorkerTask>>,
-    receiver: Arc<Mutex<Receiver<WorkerMsg>>>,
-    group: Option<Record>,
+    receiver: Receiver<WorkerMsg>,
+    outputs: Vec<String>,
+
+    /// The workers, aborted when the master is dropped, e.g. when the execution fails or is cancelled.
+    workers: JoinSet<()>,
+
+    /// Name under which the map outputs are loaded into the reduce pipeline. It is the name of the
+    /// mapped records, so that both stages can share a template.
+    record_name: String,
 }
 
 impl Master {
-    pub fn new(
+    pub fn new<M: LLM + Clone + 'static, R: LLM + Clone + 'static>(
         num_workers: usize,
-        map_pipeline: Arc<RwLock<LLMPipeline>>,
-        reduce_pipeline: Arc<RwLock<LLMPipeline>>,
+        map_pipeline: &LLMPipeline<M>,
+        reduce_pipeline: &LLMPipeline<R>,
+        map_usage: Arc<StdMutex<StageUsage>>,
+        reduce_usage: Arc<StdMutex<StageUsage>>,
     ) -> Self {
+        let num_workers = num_workers.max(1);
         let mut worker_channels = Vec::new();
         let (sender, receiver) = channel::<WorkerMsg>(std::mem::size_of::<WorkerMsg>() * num_workers);
         let sender = Arc::new(Mutex::new(sender));
 
+        let mut workers = JoinSet::new();
         for _ in 0..num_workers {
             let (tx, rx) = channel::<WorkerTask>(std::mem::size_of::<Task>() * num_workers);
             worker_channels.push(tx);
-            let worker = Worker::new(rx, map_pipeline.clone(), reduce_pipeline.clone(), sender.clone());
-            worker.spawn().unwrap();
+            let worker = Worker::new(
+                rx,
+                map_pipeline.clone(),
+                reduce_pipeline.clone(),
+                map_usage.clone(),
+                reduce_usage.clone(),
+                sender.clone(),
+            );
+            worker.spawn(&mut workers);
         }
 
         Master {
             worker_channels,
-            receiver: Arc::new(Mutex::new(receiver)),
-            group: None,
+            receiver,
+            outputs: Vec::new(),
+            workers,
+            record_name: String::new(),
         }
     }
 
-    pub async fn map(mut self, task: Task) -> Self {
-        let receiver_clone = self.receiver.clone();
-        let record = tokio::spawn(async move {
-            let mut res = Vec::<String>::new();
-            while let Some(msg) = receiver_clone.lock().await.recv().await {
-                if msg.task_completed == TaskType::Map {
-                    res.push(msg.pipeline_result.content());
-                } else {
-                    panic!("Reduce task completed before map task.")
+    pub async fn map(mut self, task: Task) -> Result<Self> {
+        let num_records = task.records.len();
+        if let Some((record_name, _)) = task.records.first() {
+            self.record_name = record_name.clone();
+        }
+
+        // Records are dealt to the workers in turn, so there may be more records than workers.
+        let worker_channels = self.worker_channels.clone();
+        let send = async move {
+            for (index, (record_name, record)) in task.records.into_iter().enumerate() {
+                worker_channels[index % worker_channels.len()]
+                    .send(WorkerTask {
+                        task_type: TaskType::Map,
+                        index,
+                        template_name: task.template_name.clone(),
+                        record_name,
+                        record,
+                    })
+                    .await
+                    .map_err(|_| anyhow::anyhow!("Worker channel closed."))?;
+            }
+            Ok(())
+        };
+        let receiver = &mut self.receiver;
+        let collect = async move {
+            let mut res = vec![String::new(); num_records];
+            for _ in 0..num_records {
+                let msg = receiver
+                    .recv()
+                    .await
+                    .ok_or(anyhow::anyhow!("Worker channel closed before all map tasks completed."))?;
+                if msg.task_completed != TaskType::Map {
+                    return Err(anyhow::anyhow!("Reduce task completed before map task."));
                 }
+                res[msg.index] = msg.pipeline_result?.content();
             }
-            Record::new(record::Content::Vec(res))
-        });
-
-        let mut worker_channels = self.worker_channels.clone();
-        for (record_name, record) in task.records {
-            let channel = worker_channels.pop().unwrap();
-            channel
-                .send(WorkerTask {
-                    task_type: TaskType::Map,
-                    template_name: task.template_name.clone(),
-                    record_name,
-                    record,
-                })
-                .await
-                .unwrap();
-        }
+            Ok(res)
+        };
 
-        self.group = Some(record.await.unwrap());
-        self
+        let ((), outputs) = supervise(&mut self.workers, async { tokio::try_join!(send, collect) }).await?;
+        self.outputs = outputs;
+        Ok(self)
     }
 
-    pub async fn reduce(&self, template_name: String) -> PipelineResult {
-        let receiver_clone = self.receiver.clone();
-        let result = tokio::spawn(async move {
-            while let Some(msg) = receiver_clone.lock().await.recv().await {
-                if msg.task_completed == TaskType::Reduce {
-                    return msg.pipeline_result;
-                } else {
-                    panic!("Map task completed before reduce task.")
-                }
+    pub async fn reduce(&mut self, reducer: impl Into<Reducer>) -> Result<PipelineResult> {
+        let template_name = match reducer.into() {
+            Reducer::Template(template_name) => template_name,
+            Reducer::Function(f) => {
+                let output = f(self.outputs.clone()).await?;
+                return Ok(PipelineResult::new("reduce".to_string()).with_llm_response(LLMResponse::Computed(output)));
             }
-            panic!("No reduce task completed.")
+        };
+
+        let channel = self.worker_channels.first().ok_or_else(|| anyhow::anyhow!("Master has no workers."))?;
+        let send = channel.send(WorkerTask {
+            task_type: TaskType::Reduce,
+            index: 0,
+            template_name,
+            record_name: self.record_name.clone(),
+            record: Record::new(record::Content::Vec(self.outputs.clone())),
         });
+        let receiver = &mut self.receiver;
+        let reduce = async move {
+            send.await.map_err(|_| anyhow::anyhow!("Worker channel closed."))?;
+            let msg = receiver.recv().await.ok_or(anyhow::anyhow!("No reduce task completed."))?;
+            if msg.task_completed != TaskType::Reduce {
+                return Err(anyhow::anyhow!("Map task completed before reduce task."));
+            }
+            msg.pipeline_result
+        };
+        supervise(&mut self.workers, reduce).await
+    }
+}
+
+/// Run work of the master while watching the workers, so that the panic of a worker is resumed on the
+/// caller instead of leaving the master waiting for its result.
+async fn supervise<T>(workers: &mut JoinSet<()>, work: impl Future<Output = Result<T>>) -> Result<T> {
+    tokio::pin!(work);
+    loop {
+        tokio::select! {
+            result = &mut work => return result,
+            Some(joined) = workers.join_next() => task::joined(joined)?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prompt::Prompt;
 
-        let channel = self.worker_channels.first().unwrap();
-        channel
-            .send(WorkerTask {
-                task_type: TaskType::Reduce,
-                template_name,
-                record_name: "".into(),
-                record: self.group.as_ref().unwrap().clone(),
+    /// Model answering with the last word of the prompt.
+    #[derive(Clone)]
+    struct LastWord;
+
+    #[async_trait::async_trait]
+    impl LLM for LastWord {
+        async fn generate(&self, prompt: Box<dyn Prompt>) -> Result<LLMResponse> {
+            let prompt = prompt.to_string();
+            Ok(LLMResponse::Quantized(
+                prompt.split_whitespace().last().unwrap_or_default().to_string(),
+            ))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_more_records_than_workers() {
+        let pipeline = LLMPipeline::new(&LastWord).load_template("vote", "{{rec}}").unwrap();
+        let usage = Arc::new(StdMutex::new(StageUsage::default()));
+        let records = ["yes", "no", "no"]
+            .iter()
+            .map(|answer| {
+                (
+                    "rec".to_string(),
+                    Record::new(record::Content::String(answer.to_string())),
+                )
             })
+            .collect();
+        let mut master = Master::new(1, &pipeline, &pipeline, usage.clone(), usage.clone())
+            .map(Task::new("vote".to_string(), records))
             .await
             .unwrap();
+        assert_eq!(master.outputs, vec!["yes", "no", "no"]);
+        let result = master.reduce(Reducer::majority_vote()).await.unwrap();
+        assert_eq!((result.content().as_str(), result.role().as_str()), ("no", "assistant"));
+    }
+
+    #[tokio::test]
+    async fn test_reducers() {
+        let outputs = vec!["positive".to_string(), " negative".to_string(), "negative ".to_string()];
+        assert_eq!(majority_vote(&outputs), "negative");
+
+        let outputs = vec![
+            r#"{"people": ["Ada"], "topic": "math"}"#.to_string(),
+            r#"Here you go: {"people": ["Charles"], "topic": "engines"}"#.to_string(),
+        ];
+        assert_eq!(
+            json_merge(&outputs).unwrap(),
+            serde_json::json!({"people": ["Ada", "Charles"], "topic": "engines"})
+        );
 
-        result.await.unwrap()
+        let concatenate = match Reducer::concatenate("\n") {
+            Reducer::Function(f) => f,
+            Reducer::Template(_) => unreachable!(),
+        };
+        assert_eq!(
+            concatenate(vec!["a".to_string(), "b".to_string()]).await.unwrap(),
+            "a\nb"
+        );
     }
 }
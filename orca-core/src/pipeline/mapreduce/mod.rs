@@ -1,34 +1,75 @@
-use std::{collections::HashMap, sync::Arc};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use master::Master;
-use tokio::sync::RwLock;
+use master::{Master, Reducer};
 
+use crate::llm::LLM;
+use crate::prompt::context::Context;
 use crate::record::Record;
 
 use self::task::Task;
 
-use super::{pipeline::LLMPipeline, Pipeline, PipelineResult};
+use super::context::{MergeStrategy, PipelineContext};
+use super::describe::PipelineDescription;
+use super::{context_of, simple::LLMPipeline, Pipeline, PipelineResult};
 use anyhow::Result;
-use serde_json::Value as JsonValue;
 
 pub mod master;
 pub mod task;
 pub mod worker;
 
-pub struct MapReducePipeline {
-    context: HashMap<String, JsonValue>,
-    map_pipeline: Arc<RwLock<LLMPipeline>>,
-    reduce_pipeline: Arc<RwLock<LLMPipeline>>,
+/// Default number of map calls a `MapReducePipeline` runs at once.
+pub const DEFAULT_WORKERS: usize = 8;
+
+/// Usage of the LLM by one stage of a map-reduce pipeline.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StageUsage {
+    /// Number of successful LLM calls.
+    pub calls: usize,
+
+    /// Number of tokens used, for LLMs that report it.
+    pub tokens: u64,
+
+    /// Total time spent in LLM calls.
+    pub elapsed: Duration,
+}
+
+impl StageUsage {
+    fn record(&mut self, result: &PipelineResult, elapsed: Duration) {
+        self.calls += 1;
+        self.tokens += result.llm_response.as_ref().and_then(|response| response.total_tokens()).unwrap_or(0) as u64;
+        self.elapsed += elapsed;
+    }
+}
+
+/// Runs the map pipeline over each record and reduces the outputs with the reduce pipeline.
+///
+/// The two stages are independent: each has its own model, context and memory, so the map stage
+/// can use a cheap or local model and the reduce stage a stronger remote one.
+pub struct MapReducePipeline<M, R> {
+    context: PipelineContext,
+    map_pipeline: LLMPipeline<M>,
+    reduce_pipeline: LLMPipeline<R>,
     records: Vec<(String, Record)>,
+    reducer: Option<Reducer>,
+    map_usage: Arc<Mutex<StageUsage>>,
+    reduce_usage: Arc<Mutex<StageUsage>>,
+
+    /// Maximum number of map calls running at once.
+    workers: usize,
 }
 
-impl MapReducePipeline {
-    pub fn new(map_pipeline: Arc<RwLock<LLMPipeline>>, reduce_pipeline: Arc<RwLock<LLMPipeline>>) -> Self {
+impl<M: LLM + Clone + 'static, R: LLM + Clone + 'static> MapReducePipeline<M, R> {
+    pub fn new(map_pipeline: LLMPipeline<M>, reduce_pipeline: LLMPipeline<R>) -> Self {
         Self {
-            context: HashMap::new(),
+            context: PipelineContext::new(),
             map_pipeline,
             reduce_pipeline,
             records: Vec::new(),
+            reducer: None,
+            map_usage: Arc::new(Mutex::new(StageUsage::default())),
+            reduce_usage: Arc::new(Mutex::new(StageUsage::default())),
+            workers: DEFAULT_WORKERS,
         }
     }
 
@@ -36,46 +77,194 @@ impl MapReducePipeline {
         self.records.push((record_name, record));
         self
     }
+
+    /// Set how the map outputs are reduced. By default, the reduce pipeline is run with the same
+    /// template name as the map pipeline.
+    pub fn with_reducer(mut self, reducer: impl Into<Reducer>) -> Self {
+        self.reducer = Some(reducer.into());
+        self
+    }
+
+    /// Set the maximum number of map calls running at once, `DEFAULT_WORKERS` by default. Records are
+    /// dealt to the workers in turn, so that large inputs do not flood the model with requests.
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// Load context shared by both stages. Keys already set in a stage take precedence.
+    pub fn load_context(mut self, context: &Context) -> Result<Self> {
+        self.context.merge(&context.into(), MergeStrategy::Error)?;
+        Ok(self)
+    }
+
+    /// Load context into the map stage only.
+    pub fn load_map_context(mut self, context: &Context) -> Result<Self> {
+        self.map_pipeline = self.map_pipeline.load_context(context)?;
+        Ok(self)
+    }
+
+    /// Load context into the reduce stage only.
+    pub fn load_reduce_context(mut self, context: &Context) -> Result<Self> {
+        self.reduce_pipeline = self.reduce_pipeline.load_context(context)?;
+        Ok(self)
+    }
+
+    /// Usage of the map stage across executions.
+    pub fn map_usage(&self) -> StageUsage {
+        self.map_usage.lock().unwrap().clone()
+    }
+
+    /// Usage of the reduce stage across executions.
+    pub fn reduce_usage(&self) -> StageUsage {
+        self.reduce_usage.lock().unwrap().clone()
+    }
 }
 
 #[async_trait::async_trait]
-impl Pipeline for MapReducePipeline {
+impl<M: LLM + Clone + 'static, R: LLM + Clone + 'static> Pipeline for MapReducePipeline<M, R> {
     async fn execute(&self, target: &str) -> Result<PipelineResult> {
         let task = Task::new(target.to_string(), self.records.clone());
-        Ok(Master::new(
-            self.records.len(),
-            self.map_pipeline.clone(),
-            self.reduce_pipeline.clone(),
+        let mut map_pipeline = self.map_pipeline.clone();
+        context_of(&mut map_pipeline)?.merge(&self.context, MergeStrategy::Keep)?;
+        let mut reduce_pipeline = self.reduce_pipeline.clone();
+        context_of(&mut reduce_pipeline)?.merge(&self.context, MergeStrategy::Keep)?;
+        Master::new(
+            self.records.len().min(self.workers),
+            &map_pipeline,
+            &reduce_pipeline,
+            self.map_usage.clone(),
+            self.reduce_usage.clone(),
         )
         .map(task)
+        .await?
+        .reduce(self.reducer.clone().unwrap_or_else(|| target.into()))
         .await
-        .reduce(target.to_string())
-        .await)
     }
 
-    fn context(&mut self) -> &mut HashMap<String, JsonValue> {
-        &mut self.context
+    fn context(&mut self) -> Option<&mut PipelineContext> {
+        Some(&mut self.context)
     }
 
-    async fn load_context<T>(&mut self, context: &T)
-    where
-        T: serde::Serialize + Sync,
-    {
-        self.map_pipeline.write().await.load_context(context).await;
-        self.reduce_pipeline.write().await.load_context(context).await;
+    fn describe(&self) -> PipelineDescription {
+        PipelineDescription::new("MapReducePipeline", "")
+            .with_step(self.map_pipeline.describe().with_label("map"))
+            .with_step(self.reduce_pipeline.describe().with_label("reduce"))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{
-        llm::openai::OpenAI,
-        pipelines::pipeline::LLMPipeline,
-        record::{pdf::Pdf, Spin},
+        llm::{openai::OpenAI, LLMResponse},
+        prompt::Prompt,
+        record::{pdf::Pdf, Content, Spin},
+        testing::SlowLLM,
     };
 
     use super::*;
 
+    /// Local model that echoes the prompt in upper case.
+    #[derive(Clone)]
+    struct Shout;
+
+    #[async_trait::async_trait]
+    impl LLM for Shout {
+        async fn generate(&self, prompt: Box<dyn Prompt>) -> Result<LLMResponse> {
+            Ok(LLMResponse::Quantized(prompt.to_string().to_uppercase()))
+        }
+    }
+
+    /// Model that counts the lines of the prompt.
+    #[derive(Clone)]
+    struct CountLines;
+
+    #[async_trait::async_trait]
+    impl LLM for CountLines {
+        async fn generate(&self, prompt: Box<dyn Prompt>) -> Result<LLMResponse> {
+            Ok(LLMResponse::Quantized(prompt.to_string().lines().count().to_string()))
+        }
+    }
+
+    /// Model whose calls all fail.
+    #[derive(Clone)]
+    struct Failing;
+
+    #[async_trait::async_trait]
+    impl LLM for Failing {
+        async fn generate(&self, _prompt: Box<dyn Prompt>) -> Result<LLMResponse> {
+            Err(anyhow::anyhow!("model unavailable"))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_heterogeneous_stages() {
+        let map_pipeline = LLMPipeline::new(&Shout).load_template("mapreduce", "{{tone}} {{rec}}").unwrap();
+        let reduce_pipeline = LLMPipeline::new(&CountLines).load_template("mapreduce", "{{rec}}").unwrap();
+        let pipeline = MapReducePipeline::new(map_pipeline, reduce_pipeline)
+            .load_map_context(&Context::new(serde_json::json!({"tone": "loud"})).unwrap())
+            .unwrap()
+            .load_context(&Context::new(serde_json::json!({"tone": "quiet"})).unwrap())
+            .unwrap()
+            .with_record("rec".to_string(), Record::new(Content::String("first".to_string())))
+            .with_record("rec".to_string(), Record::new(Content::String("second".to_string())));
+
+        let result = pipeline.execute("mapreduce").await.unwrap();
+        // Two outputs joined with a separator line.
+        assert_eq!(result.content(), "3");
+        assert_eq!(pipeline.map_usage().calls, 2);
+        assert_eq!(pipeline.reduce_usage().calls, 1);
+
+        let pipeline = pipeline.with_reducer(master::Reducer::concatenate(", "));
+        assert_eq!(
+            pipeline.execute("mapreduce").await.unwrap().content(),
+            "LOUD FIRST, LOUD SECOND"
+        );
+        assert_eq!(pipeline.reduce_usage().calls, 1);
+    }
+
+    #[tokio::test]
+    async fn test_json_merge_reducer() {
+        let map_pipeline = LLMPipeline::new(&Shout).load_template("mapreduce", r#"{"words": ["{{rec}}"]}"#).unwrap();
+        let reduce_pipeline = LLMPipeline::new(&CountLines).load_template("mapreduce", "{{rec}}").unwrap();
+        let pipeline = MapReducePipeline::new(map_pipeline, reduce_pipeline)
+            .with_record("rec".to_string(), Record::new(Content::String("first".to_string())))
+            .with_record("rec".to_string(), Record::new(Content::String("second".to_string())))
+            .with_reducer(master::Reducer::json_merge());
+        let result = pipeline.execute("mapreduce").await.unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&result.content()).unwrap(),
+            serde_json::json!({"WORDS": ["FIRST", "SECOND"]})
+        );
+        assert_eq!(pipeline.reduce_usage().calls, 0);
+    }
+
+    #[tokio::test]
+    async fn test_workers() {
+        let llm = SlowLLM::new(Duration::from_millis(10));
+        let map_pipeline = LLMPipeline::new(&llm).load_template("mapreduce", "{{rec}}").unwrap();
+        let reduce_pipeline = LLMPipeline::new(&CountLines).load_template("mapreduce", "{{rec}}").unwrap();
+        let pipeline = (0..5)
+            .fold(MapReducePipeline::new(map_pipeline, reduce_pipeline), |pipeline, i| {
+                pipeline.with_record("rec".to_string(), Record::new(Content::String(i.to_string())))
+            })
+            .with_workers(2)
+            .with_reducer(master::Reducer::concatenate(","));
+        assert_eq!(pipeline.execute("mapreduce").await.unwrap().content(), "0,1,2,3,4");
+        assert!(llm.max_running() <= 2);
+    }
+
+    #[tokio::test]
+    async fn test_map_error() {
+        let map_pipeline = LLMPipeline::new(&Failing).load_template("mapreduce", "{{rec}}").unwrap();
+        let reduce_pipeline = LLMPipeline::new(&CountLines).load_template("mapreduce", "{{rec}}").unwrap();
+        let pipeline = MapReducePipeline::new(map_pipeline, reduce_pipeline)
+            .with_record("rec".to_string(), Record::new(Content::String("first".to_string())));
+        let error = pipeline.execute("mapreduce").await.unwrap_err();
+        assert_eq!(error.to_string(), "model unavailable");
+        assert_eq!(pipeline.reduce_usage().calls, 0);
+    }
+
     #[tokio::test]
     #[ignore = "wip"]
     async fn test_mapreduce() {
@@ -84,26 +273,30 @@ mod tests {
         let split_rec = rec.split(5);
 
         let client = OpenAI::new();
-        let map_pipeline = Arc::new(RwLock::new(LLMPipeline::new(&client).with_template(
-            "mapreduce",
-            r#"{{#chat}}
+        let map_pipeline = LLMPipeline::new(&client)
+            .load_template(
+                "mapreduce",
+                r#"{{#chat}}
                 {{#user}}
                 Get me a summary of the following:
                 {{rec}}
                 {{/user}}
                 {{/chat}}
                 "#,
-        )));
-        let reduce_pipeline = Arc::new(RwLock::new(LLMPipeline::new(&client).with_template(
-            "mapreduce",
-            r#"{{#chat}}
+            )
+            .unwrap();
+        let reduce_pipeline = LLMPipeline::new(&client.with_model("gpt-4"))
+            .load_template(
+                "mapreduce",
+                r#"{{#chat}}
             {{#user}}
             Get me a summary of the following:
             {{rec}}
             {{/user}}
             {{/chat}}
             "#,
-        )));
+            )
+            .unwrap();
         let mp_pipeline = MapReducePipeline::new(map_pipeline, reduce_pipeline)
             .with_record("rec".to_string(), split_rec[0].clone())
             .with_record("rec".to_string(), split_rec[1].clone())
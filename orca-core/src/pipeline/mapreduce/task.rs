@@ -1,7 +1,8 @@
 use std::fmt::Display;
 
-use crate::pipelines::PipelineResult;
+use crate::pipeline::PipelineResult;
 use crate::record::Record;
+use anyhow::Result;
 
 #[derive(PartialEq)]
 pub(crate) enum TaskType {
@@ -22,6 +23,8 @@ impl Task {
 
 pub(crate) struct WorkerTask {
     pub task_type: TaskType,
+    /// Position of the record in the task, so that map outputs can be put back in order.
+    pub index: usize,
     pub template_name: String,
     pub record_name: String,
     pub record: Record,
@@ -29,11 +32,15 @@ pub(crate) struct WorkerTask {
 
 pub(crate) struct WorkerMsg {
     pub task_completed: TaskType,
-    pub pipeline_result: PipelineResult,
+    pub index: usize,
+    pub pipeline_result: Result<PipelineResult>,
 }
 
 impl Display for WorkerMsg {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.pipeline_result.content())
+        match &self.pipeline_result {
+            Ok(result) => write!(f, "{}", result.content()),
+            Err(e) => write!(f, "{}", e),
+        }
     }
 }
@@ -1,81 +1,80 @@
 use super::task::{TaskType, WorkerMsg, WorkerTask};
-use crate::pipelines::pipeline::LLMPipeline;
-use crate::pipelines::Pipeline;
-use anyhow::Result;
-use std::sync::Arc;
+use super::StageUsage;
+use crate::llm::LLM;
+use crate::pipeline::simple::LLMPipeline;
+use crate::pipeline::Pipeline;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Instant;
 use tokio::sync::mpsc::{Receiver, Sender};
-use tokio::sync::{Mutex, RwLock};
+use tokio::sync::Mutex;
+use tokio::task::JoinSet;
 
-pub(crate) struct Worker {
+pub(crate) struct Worker<M, R> {
     receiver: Receiver<WorkerTask>,
-    map_pipeline: Arc<RwLock<LLMPipeline>>,
-    reduce_pipeline: Arc<RwLock<LLMPipeline>>,
+    map_pipeline: LLMPipeline<M>,
+    reduce_pipeline: LLMPipeline<R>,
+    map_usage: Arc<StdMutex<StageUsage>>,
+    reduce_usage: Arc<StdMutex<StageUsage>>,
     sender: Arc<Mutex<Sender<WorkerMsg>>>,
 }
 
-impl Worker {
+impl<M: LLM + Clone + 'static, R: LLM + Clone + 'static> Worker<M, R> {
     pub fn new(
         receiver: Receiver<WorkerTask>,
-        map_pipeline: Arc<RwLock<LLMPipeline>>,
-        reduce_pipeline: Arc<RwLock<LLMPipeline>>,
+        map_pipeline: LLMPipeline<M>,
+        reduce_pipeline: LLMPipeline<R>,
+        map_usage: Arc<StdMutex<StageUsage>>,
+        reduce_usage: Arc<StdMutex<StageUsage>>,
         sender: Arc<Mutex<Sender<WorkerMsg>>>,
     ) -> Self {
         Worker {
             receiver,
             map_pipeline,
             reduce_pipeline,
+            map_usage,
+            reduce_usage,
             sender,
         }
     }
 
-    pub fn spawn(self) -> Result<()> {
-        let map_pipeline = self.map_pipeline.clone();
-        let reduce_pipeline = self.reduce_pipeline.clone();
-        let sender = self.sender.clone();
-        tokio::spawn(async move {
+    /// Spawn the worker in the given set, which aborts it when dropped.
+    pub fn spawn(self, workers: &mut JoinSet<()>) {
+        workers.spawn(async move {
             let mut receiver = self.receiver;
             while let Some(task) = receiver.recv().await {
-                let mut template_name = task.template_name;
-                {
-                    let mut locked_pipeline = match task.task_type {
-                        TaskType::Map => map_pipeline.write().await,
-                        TaskType::Reduce => reduce_pipeline.write().await,
-                    };
-                    template_name = locked_pipeline.duplicate_template(&template_name).unwrap_or_else(|| {
-                        log::error!("{}", format!("Template [{}] does not exist", template_name));
-                        panic!("{}", format!("Template [{}] does not exist", template_name));
-                    });
-                    locked_pipeline.load_record(&task.record_name, task.record);
+                // Each task runs on its own copy of the stage pipeline, so records loaded into the
+                // context of one task are not seen by the others.
+                let start = Instant::now();
+                let (pipeline_result, usage) = match task.task_type {
+                    TaskType::Map => {
+                        let result = match self.map_pipeline.clone().load_record(&task.record_name, task.record) {
+                            Ok(pipeline) => pipeline.execute(&task.template_name).await,
+                            Err(e) => Err(e),
+                        };
+                        (result, &self.map_usage)
+                    }
+                    TaskType::Reduce => {
+                        let result = match self.reduce_pipeline.clone().load_record(&task.record_name, task.record) {
+                            Ok(pipeline) => pipeline.execute(&task.template_name).await,
+                            Err(e) => Err(e),
+                        };
+                        (result, &self.reduce_usage)
+                    }
+                };
+                match &pipeline_result {
+                    Ok(result) => usage.lock().unwrap().record(result, start.elapsed()),
+                    Err(e) => log::error!("Error while executing pipeline [{}]: {}", task.template_name, e),
                 }
-                let locked_pipeline = match task.task_type {
-                    TaskType::Map => map_pipeline.read().await,
-                    TaskType::Reduce => reduce_pipeline.read().await,
+
+                let msg = WorkerMsg {
+                    task_completed: task.task_type,
+                    index: task.index,
+                    pipeline_result,
                 };
-                let pipeline_result = locked_pipeline.execute(&template_name).await.unwrap_or_else(|e| {
-                    log::error!(
-                        "{}",
-                        format!("Error while executing pipeline [{}]: {}", locked_pipeline.name, e)
-                    );
-                    panic!(
-                        "{}",
-                        format!("Error while executing pipeline [{}]: {}", locked_pipeline.name, e)
-                    );
-                });
-                println!("{}", pipeline_result.content());
-                sender
-                    .lock()
-                    .await
-                    .send(WorkerMsg {
-                        task_completed: task.task_type,
-                        pipeline_result,
-                    })
-                    .await
-                    .unwrap_or_else(|e| {
-                        log::error!("{}", format!("Error while sending message: {}", e));
-                        panic!();
-                    })
+                if let Err(e) = self.sender.lock().await.send(msg).await {
+                    log::error!("Error while sending message: {}", e);
+                }
             }
         });
-        Ok(())
     }
 }
@@ -1,11 +1,46 @@
+pub mod abstracts;
+pub mod assembler;
+pub mod budget;
+pub mod checkpoint;
+pub mod classify;
+pub mod collector;
+pub mod context;
+pub mod dead_letter;
+pub mod describe;
+pub mod federated;
+pub mod grounding;
+pub mod image;
+pub mod ingest;
+pub mod knowledge_graph;
 #[cfg(feature = "unstable")]
 pub mod mapreduce;
+pub mod parent;
+pub mod partial;
+pub mod registry;
+pub mod reload;
+pub mod router;
+pub mod self_query;
 pub mod simple;
+pub mod stream;
 // #[cfg(feature = "unstable")]
 pub mod sequential;
-use crate::{llm::LLMResponse, prompt::TemplateEngine};
+pub mod sql;
+pub mod summarize;
+pub mod title;
+pub mod typed;
+pub mod usage;
+pub mod validated;
+use crate::{
+    llm::{GeneratedImage, LLMResponse},
+    prompt::TemplateEngine,
+};
+use context::PipelineContext;
+use describe::PipelineDescription;
+use usage::UsageRecord;
 
 use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value as JsonValue};
 
 #[async_trait::async_trait]
 pub trait Pipeline: Sync + Send {
@@ -22,6 +57,29 @@ pub trait Pipeline: Sync + Send {
     fn template_engine(&mut self) -> &mut TemplateEngine {
         unimplemented!()
     }
+
+    /// Retrieves the context rendered into the templates of the current pipeline.
+    ///
+    /// # Returns
+    /// - A mutable reference to the context, or `None` if the pipeline has no context of its own. By default,
+    ///   `None`.
+    fn context(&mut self) -> Option<&mut PipelineContext> {
+        None
+    }
+
+    /// Describes how the pipeline is built: its templates, model, memory and steps.
+    ///
+    /// # Returns
+    /// - A description serializable to JSON and mermaid. By default, only the kind of the pipeline.
+    fn describe(&self) -> PipelineDescription {
+        PipelineDescription::new(describe::type_name::<Self>(), "")
+    }
+}
+
+/// Context of a pipeline that is passed a context by another one, failing if it has none.
+pub(crate) fn context_of<P: Pipeline + ?Sized>(pipeline: &mut P) -> Result<&mut PipelineContext> {
+    let kind = pipeline.describe().kind;
+    pipeline.context().ok_or_else(|| anyhow::anyhow!("Pipeline {} has no context to set", kind))
 }
 
 #[derive(Debug)]
@@ -31,6 +89,15 @@ pub struct PipelineResult {
 
     /// LLM response generated by the pipeline.
     llm_response: Option<LLMResponse>,
+
+    /// Images generated by the pipeline, if any.
+    images: Vec<GeneratedImage>,
+
+    /// Metadata about how the result was generated, e.g. the `tier` of the model router that answered.
+    metadata: Map<String, JsonValue>,
+
+    /// Tokens used by the LLM calls made to generate the result.
+    usage: Vec<UsageRecord>,
 }
 
 impl PipelineResult {
@@ -45,6 +112,9 @@ impl PipelineResult {
         PipelineResult {
             name,
             llm_response: None,
+            images: Vec::new(),
+            metadata: Map::new(),
+            usage: Vec::new(),
         }
     }
 
@@ -56,6 +126,14 @@ impl PipelineResult {
         self.llm_response.as_ref().unwrap_or(&LLMResponse::Empty).to_string()
     }
 
+    /// Retrieves the number of tokens used to generate the LLM response, if reported by the LLM.
+    ///
+    /// # Returns
+    /// - The number of prompt and completion tokens, or `None` if unknown.
+    pub fn total_tokens(&self) -> Option<u32> {
+        self.llm_response.as_ref().and_then(LLMResponse::total_tokens)
+    }
+
     /// Determines the role associated with the LLM response.
     ///
     /// # Returns
@@ -64,7 +142,8 @@ impl PipelineResult {
         self.llm_response.as_ref().unwrap_or(&LLMResponse::Empty).to_role()
     }
 
-    /// Sets the LLM response for the current `PipelineResult`.
+    /// Sets the LLM response for the current `PipelineResult`. If the response was routed, the tier
+    /// that answered is recorded in the `tier` metadata.
     ///
     /// # Parameters
     /// - `llm_response`: The actual LLM response to set.
@@ -72,7 +151,102 @@ impl PipelineResult {
     /// # Returns
     /// - The modified `PipelineResult` instance.
     pub fn with_llm_response(mut self, llm_response: LLMResponse) -> Self {
+        if let Some(tier) = llm_response.tier() {
+            self.metadata.insert("tier".to_string(), tier.into());
+        }
         self.llm_response = Some(llm_response);
         self
     }
+
+    /// Sets a metadata value.
+    ///
+    /// # Parameters
+    /// - `key`: The metadata key.
+    /// - `value`: The metadata value.
+    ///
+    /// # Returns
+    /// - The modified `PipelineResult` instance.
+    pub fn with_metadata<V: Into<JsonValue>>(mut self, key: &str, value: V) -> Self {
+        self.metadata.insert(key.to_string(), value.into());
+        self
+    }
+
+    /// Retrieves the metadata about how the result was generated.
+    ///
+    /// # Returns
+    /// - The metadata, keyed by name.
+    pub fn metadata(&self) -> &Map<String, JsonValue> {
+        &self.metadata
+    }
+
+    /// Sets the tokens used by the LLM calls made to generate the result.
+    ///
+    /// # Parameters
+    /// - `usage`: The records of the calls.
+    ///
+    /// # Returns
+    /// - The modified `PipelineResult` instance.
+    pub fn with_usage(mut self, usage: Vec<UsageRecord>) -> Self {
+        self.usage = usage;
+        self
+    }
+
+    /// Retrieves the tokens used by the LLM calls made to generate the result.
+    ///
+    /// # Returns
+    /// - The records of the calls, empty if the pipeline does not track usage.
+    pub fn usage(&self) -> &[UsageRecord] {
+        &self.usage
+    }
+
+    /// Sets the images generated by the pipeline.
+    ///
+    /// # Parameters
+    /// - `images`: The generated images.
+    ///
+    /// # Returns
+    /// - The modified `PipelineResult` instance.
+    pub fn with_images(mut self, images: Vec<GeneratedImage>) -> Self {
+        self.images = images;
+        self
+    }
+
+    /// Retrieves the images generated by the pipeline, with their bytes and, if they were saved, their paths.
+    ///
+    /// # Returns
+    /// - The generated images, empty if the pipeline generated none.
+    pub fn images(&self) -> &[GeneratedImage] {
+        &self.images
+    }
+
+    /// Parses the content of the LLM response as JSON. See [`parse_json`].
+    ///
+    /// # Returns
+    /// - The deserialized content, or an error if it is not valid JSON of the expected shape.
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T> {
+        parse_json(&self.content())
+    }
+}
+
+/// Parses JSON out of an LLM response. Models often wrap JSON in a Markdown code block or surround it
+/// with prose, so if the whole text is not valid JSON, the outermost object or array is parsed instead.
+///
+/// # Example
+/// ```
+/// use orca_core::pipeline::parse_json;
+/// use serde_json::{json, Value};
+///
+/// let value: Value = parse_json("Sure! ```json\n{\"city\": \"Paris\"}\n```").unwrap();
+/// assert_eq!(value, json!({"city": "Paris"}));
+/// ```
+pub fn parse_json<T: DeserializeOwned>(text: &str) -> Result<T> {
+    if let Ok(value) = serde_json::from_str(text.trim()) {
+        return Ok(value);
+    }
+    let start = text.find(['{', '[']);
+    let end = text.rfind(['}', ']']);
+    match (start, end) {
+        (Some(start), Some(end)) if start < end => Ok(serde_json::from_str(&text[start..=end])?),
+        _ => Err(anyhow::anyhow!("Unable to find JSON in response: {}", text)),
+    }
 }
@@ -0,0 +1,249 @@
+//! Parent document retrieval.
+//!
+//! Small chunks embed more precisely than whole documents, but make poor context on their own. A
+//! `ParentDocumentRetriever` searches the chunks in a vector store and returns the records they were
+//! split from, looked up in a `DocStore` by the `parent_id` attribute of the chunks. Records are
+//! ordered by their best matching chunk, whose score they keep as their `score` attribute. With a
+//! verifier, retrieval fails on records whose provenance signature does not hold.
+
+use crate::docstore::{DocStore, PARENT_ID_ATTRIBUTE};
+use crate::llm::Embedding;
+use crate::pipeline::assembler::SCORE_ATTRIBUTE;
+use crate::prompt;
+use crate::record::provenance::Signer;
+use crate::record::Record;
+use crate::vectorstore::{Filter, SearchQuery, VectorStore};
+
+use anyhow::Result;
+use tracing::field::Empty;
+use tracing::Span;
+
+/// Retriever searching chunks and returning their parent records.
+pub struct ParentDocumentRetriever<'a, E, S: ?Sized, D: ?Sized> {
+    /// Model embedding the queries.
+    embedder: &'a E,
+
+    /// Vector store holding the chunks.
+    store: &'a S,
+
+    /// Document store holding the parent records.
+    docs: &'a D,
+
+    /// Name of the collection of chunks.
+    collection: String,
+
+    /// Maximum number of chunks searched.
+    limit: usize,
+
+    /// Conditions the chunks must satisfy.
+    filters: Vec<Filter>,
+
+    /// Signer checking the provenance of the returned records.
+    verifier: Option<&'a Signer>,
+}
+
+impl<'a, E, S, D> ParentDocumentRetriever<'a, E, S, D>
+where
+    E: Embedding + Send + Sync,
+    S: VectorStore + ?Sized,
+    D: DocStore + ?Sized,
+{
+    /// Create a retriever searching the 10 chunks nearest to each query.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use orca_core::docstore::FileDocStore;
+    /// # use orca_core::llm::bert::Bert;
+    /// # use orca_core::pipeline::parent::ParentDocumentRetriever;
+    /// # use orca_core::qdrant::Qdrant;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let qdrant = Qdrant::new("http://localhost:6334").unwrap();
+    /// let docs = FileDocStore::new("./documents")?;
+    /// let bert = Bert::new().build_model_and_tokenizer().await?;
+    /// let retriever = ParentDocumentRetriever::new(&bert, &qdrant, &docs, "chunks").with_limit(20);
+    /// let records = retriever.retrieve("Where do orcas live?").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new(embedder: &'a E, store: &'a S, docs: &'a D, collection: &str) -> Self {
+        ParentDocumentRetriever {
+            embedder,
+            store,
+            docs,
+            collection: collection.to_string(),
+            limit: 10,
+            filters: Vec::new(),
+            verifier: None,
+        }
+    }
+
+    /// Set the maximum number of chunks searched. Several chunks may share a parent, so fewer records
+    /// may be returned.
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Add a condition the chunks must satisfy.
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    /// Verify the provenance of the returned records, failing the retrieval with an
+    /// `OrcaError::Verification` on records that were not signed at ingestion, see
+    /// `IngestPipeline::with_signer`, or were modified since.
+    pub fn with_verifier(mut self, verifier: &'a Signer) -> Self {
+        self.verifier = Some(verifier);
+        self
+    }
+
+    /// Retrieve the parent records of the chunks most similar to the query. Chunks without a parent
+    /// are returned as they are.
+    #[tracing::instrument(name = "retrieval", skip_all, fields(collection = %self.collection, limit = self.limit, hits = Empty))]
+    pub async fn retrieve(&self, query: &str) -> Result<Vec<Record>> {
+        let vector = self.embedder.generate_embedding(prompt!(query)).await?.to_vec()?;
+        let mut search = SearchQuery::new(vector).with_limit(self.limit);
+        search.filters = self.filters.clone();
+        let hits = self.store.search(&self.collection, search).await?;
+        Span::current().record("hits", hits.len());
+
+        // Hits are sorted by score, so the first chunk of each parent is its best one.
+        let mut results: Vec<(Option<String>, Record)> = Vec::new();
+        for chunk in hits.iter().filter_map(|hit| hit.to_record()) {
+            match chunk.attributes.get(PARENT_ID_ATTRIBUTE).and_then(|id| id.as_str()) {
+                Some(id) if results.iter().any(|(parent, _)| parent.as_deref() == Some(id)) => {}
+                Some(id) => results.push((Some(id.to_string()), chunk.clone())),
+                None => results.push((None, chunk)),
+            }
+        }
+
+        let ids: Vec<String> = results.iter().filter_map(|(id, _)| id.clone()).collect();
+        let mut parents = self.docs.get_many(&ids).await?.into_iter();
+        let mut records = Vec::with_capacity(results.len());
+        for (id, chunk) in results {
+            if id.is_none() {
+                records.push(chunk);
+                continue;
+            }
+            match parents.next().flatten() {
+                Some(parent) => {
+                    records.push(parent.with_attribute(SCORE_ATTRIBUTE, chunk.attributes[SCORE_ATTRIBUTE].clone()))
+                }
+                None => log::warn!(
+                    "Parent record {} of a chunk is missing from the document store",
+                    id.unwrap_or_default()
+                ),
+            }
+        }
+        if let Some(verifier) = self.verifier {
+            for record in &records {
+                verifier.verify(record)?;
+            }
+        }
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::docstore::FileDocStore;
+    use crate::error::OrcaError;
+    use crate::pipeline::ingest::IngestPipeline;
+    use crate::record::provenance::VerificationError;
+    use crate::record::Content;
+    use crate::testing::ConstantEmbedder;
+    use crate::vectorstore::memory::MemoryStore;
+    use crate::vectorstore::{Point, SearchHit};
+
+    /// Store returning its chunks in order, with decreasing scores.
+    struct Chunks(Vec<Record>);
+
+    #[async_trait::async_trait]
+    impl VectorStore for Chunks {
+        async fn ensure_collection(&self, _collection: &str, _dimensions: usize) -> Result<()> {
+            Ok(())
+        }
+
+        async fn insert(&self, _collection: &str, _points: Vec<Point>) -> Result<()> {
+            Ok(())
+        }
+
+        async fn search(&self, _collection: &str, _query: SearchQuery) -> Result<Vec<SearchHit>> {
+            Ok(self
+                .0
+                .iter()
+                .enumerate()
+                .map(|(i, chunk)| {
+                    let point = Point::new(i as u64, vec![], chunk).unwrap();
+                    SearchHit {
+                        id: point.id,
+                        score: 1.0 - i as f32 / 10.0,
+                        payload: point.payload,
+                    }
+                })
+                .collect())
+        }
+
+        async fn delete_collection(&self, _collection: &str) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retrieve() {
+        let dir = std::env::temp_dir().join(format!("orca-parent-{}", uuid::Uuid::new_v4()));
+        let docs = FileDocStore::new(&dir).unwrap();
+        let parent = Record::new(Content::String("Orcas live in pods. Pods are families.".to_string()));
+        docs.put("orcas", &parent).await.unwrap();
+
+        let chunk = |text: &str| Record::new(Content::String(text.to_string()));
+        let store = Chunks(vec![
+            chunk("Pods are families.").with_attribute(PARENT_ID_ATTRIBUTE, "orcas"),
+            chunk("Dolphins are fast."),
+            chunk("Orcas live in pods.").with_attribute(PARENT_ID_ATTRIBUTE, "orcas"),
+            chunk("Whales sing.").with_attribute(PARENT_ID_ATTRIBUTE, "whales"),
+        ]);
+        let records = ParentDocumentRetriever::new(&ConstantEmbedder(1.0), &store, &docs, "chunks")
+            .retrieve("orcas")
+            .await
+            .unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].content, parent.content);
+        assert_eq!(records[0].attributes[SCORE_ATTRIBUTE], serde_json::json!(1.0));
+        assert_eq!(records[1].content, Content::String("Dolphins are fast.".to_string()));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_verify() {
+        let dir = std::env::temp_dir().join(format!("orca-parent-{}", uuid::Uuid::new_v4()));
+        let docs = FileDocStore::new(&dir).unwrap();
+        let store = MemoryStore::new();
+        let signer = Signer::new("2024-01", b"secret");
+        let record = Record::new(Content::String("Orcas live in pods.".to_string())).with_attribute("page", 1);
+        IngestPipeline::new(&store, &ConstantEmbedder(1.0), "chunks")
+            .with_parents(&docs, 399)
+            .with_signer(&signer)
+            .ingest(vec![record])
+            .await
+            .unwrap();
+        let retriever =
+            ParentDocumentRetriever::new(&ConstantEmbedder(1.0), &store, &docs, "chunks").with_verifier(&signer);
+        let records = retriever.retrieve("orcas").await.unwrap();
+        assert_eq!(records[0].content, Content::String("Orcas live in pods.".to_string()));
+
+        // Parents edited in the document store fail the retrieval.
+        let entry = std::fs::read_dir(&dir).unwrap().next().unwrap().unwrap();
+        let id = entry.path().file_stem().unwrap().to_string_lossy().to_string();
+        docs.put(&id, &records[0].clone().with_attribute("page", 2)).await.unwrap();
+        let error = retriever.retrieve("orcas").await.unwrap_err();
+        assert_eq!(
+            error.downcast::<OrcaError>().unwrap(),
+            OrcaError::Verification(VerificationError::Tampered)
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
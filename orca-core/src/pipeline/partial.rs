@@ -0,0 +1,301 @@
+//! Incremental parsing of JSON responses as they are generated.
+//!
+//! A `PartialJson` parser is fed the chunks of a response, e.g. the tokens decoded by a
+//! `TokenOutputStream`, and reports each field of the top-level object (or item of the top-level
+//! array) as soon as its value is complete, so that a UI can render structured results progressively.
+//! At any point, the text received so far can also be read as a value, with the open strings,
+//! arrays and objects closed and the incomplete trailing entry dropped, and deserialized into a type
+//! whose fields are optional. Text around the JSON, such as a code fence, is ignored.
+
+use anyhow::{anyhow, Result};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value as JsonValue};
+
+/// An entry of the top-level JSON value whose value is complete.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonUpdate {
+    /// A field of the top-level object.
+    Field { key: String, value: JsonValue },
+
+    /// An item of the top-level array, with its index.
+    Item { index: usize, value: JsonValue },
+}
+
+/// Incremental parser of a JSON value received in chunks.
+#[derive(Debug, Clone, Default)]
+pub struct PartialJson {
+    /// Text received from the start of the JSON value.
+    buffer: String,
+
+    /// Containers open at the end of the buffer, as their opening bracket.
+    stack: Vec<char>,
+
+    /// Whether the buffer ends inside a string.
+    in_string: bool,
+
+    /// Whether the buffer ends right after a backslash in a string.
+    escaped: bool,
+
+    /// Whether the next or current string in an object is a key.
+    key: bool,
+
+    /// Last offset where the buffer can be cut and closed into a valid value, with the containers
+    /// open there.
+    safe: (usize, Vec<char>),
+
+    /// Offset of the top-level entry being received.
+    entry_start: usize,
+
+    /// Number of top-level entries already reported.
+    entries: usize,
+
+    /// Whether the top-level value is complete.
+    done: bool,
+}
+
+impl PartialJson {
+    /// Create a parser expecting a JSON object or array.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::pipeline::partial::{JsonUpdate, PartialJson};
+    /// use serde_json::json;
+    ///
+    /// let mut parser = PartialJson::new();
+    /// assert!(parser.push("```json\n{\"title\": \"Orc").unwrap().is_empty());
+    /// assert_eq!(parser.value(), Some(json!({"title": "Orc"})));
+    /// let updates = parser.push(r#"as", "summary": "Orcas are dolph"#).unwrap();
+    /// assert_eq!(updates, vec![JsonUpdate::Field { key: "title".to_string(), value: json!("Orcas") }]);
+    /// assert_eq!(parser.value(), Some(json!({"title": "Orcas", "summary": "Orcas are dolph"})));
+    /// ```
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed the next chunk of the response, returning the top-level entries it completes. Fails if
+    /// a completed entry is not valid JSON.
+    pub fn push(&mut self, chunk: &str) -> Result<Vec<JsonUpdate>> {
+        let mut updates = Vec::new();
+        for c in chunk.chars() {
+            if self.done {
+                break;
+            }
+            if self.stack.is_empty() {
+                // Skip the text before the JSON value.
+                if c == '{' || c == '[' {
+                    self.buffer.push(c);
+                    self.open(c);
+                    self.entry_start = self.buffer.len();
+                }
+                continue;
+            }
+            let offset = self.buffer.len();
+            self.buffer.push(c);
+            if self.in_string {
+                match (self.escaped, c) {
+                    (true, _) => self.escaped = false,
+                    (false, '\\') => self.escaped = true,
+                    (false, '"') => {
+                        self.in_string = false;
+                        if self.key {
+                            self.key = false;
+                        } else {
+                            self.safe = (self.buffer.len(), self.stack.clone());
+                        }
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            match c {
+                '"' => self.in_string = true,
+                '{' | '[' => self.open(c),
+                ',' | '}' | ']' => {
+                    if self.stack.len() == 1 {
+                        updates.extend(self.complete_entry(offset)?);
+                        self.entry_start = offset + 1;
+                    }
+                    if c == ',' {
+                        self.safe = (offset, self.stack.clone());
+                        self.key = self.stack.last() == Some(&'{');
+                    } else {
+                        self.stack.pop();
+                        self.safe = (self.buffer.len(), self.stack.clone());
+                        self.key = false;
+                        self.done = self.stack.is_empty();
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(updates)
+    }
+
+    /// Open a container at the end of the buffer.
+    fn open(&mut self, bracket: char) {
+        self.stack.push(bracket);
+        self.key = bracket == '{';
+        self.safe = (self.buffer.len(), self.stack.clone());
+    }
+
+    /// Parse the top-level entry ending at the given offset, if it is not empty.
+    fn complete_entry(&mut self, end: usize) -> Result<Option<JsonUpdate>> {
+        let entry = self.buffer[self.entry_start..end].trim();
+        if entry.is_empty() {
+            return Ok(None);
+        }
+        let update = match self.stack[0] {
+            '{' => {
+                let object: Map<String, JsonValue> = serde_json::from_str(&format!("{{{}}}", entry))
+                    .map_err(|e| anyhow!("Invalid JSON field {}: {}", entry, e))?;
+                let (key, value) = object.into_iter().next().ok_or_else(|| anyhow!("Empty JSON field"))?;
+                JsonUpdate::Field { key, value }
+            }
+            _ => JsonUpdate::Item {
+                index: self.entries,
+                value: serde_json::from_str(entry).map_err(|e| anyhow!("Invalid JSON item {}: {}", entry, e))?,
+            },
+        };
+        self.entries += 1;
+        Ok(Some(update))
+    }
+
+    /// The value received so far, with the open strings and containers closed and the incomplete
+    /// trailing entry dropped, or `None` before the start of the value. Numbers and strings being
+    /// received are included as they are.
+    pub fn value(&self) -> Option<JsonValue> {
+        if self.buffer.is_empty() {
+            return None;
+        }
+        if self.done {
+            return serde_json::from_str(&self.buffer).ok();
+        }
+        let mut text = self.buffer.clone();
+        if self.in_string && !self.key {
+            if self.escaped {
+                text.pop();
+            }
+            text.push('"');
+        }
+        if let Ok(value) = serde_json::from_str(&close(text, &self.stack)) {
+            return Some(value);
+        }
+        let (offset, stack) = &self.safe;
+        serde_json::from_str(&close(self.buffer[..*offset].to_string(), stack)).ok()
+    }
+
+    /// Deserialize the value received so far, see [`PartialJson::value`]. Fields of the type that may
+    /// not have been received yet should be optional.
+    pub fn partial<T: DeserializeOwned>(&self) -> Result<T> {
+        let value = self.value().ok_or_else(|| anyhow!("No JSON value received yet"))?;
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// Whether the top-level value is complete.
+    pub fn is_complete(&self) -> bool {
+        self.done
+    }
+
+    /// Deserialize the complete value, failing if it was not fully received.
+    pub fn finish<T: DeserializeOwned>(self) -> Result<T> {
+        if !self.done {
+            return Err(anyhow!("Incomplete JSON value: {}", self.buffer));
+        }
+        Ok(serde_json::from_str(&self.buffer)?)
+    }
+}
+
+/// Append the closing brackets of the given open containers to a text.
+fn close(mut text: String, stack: &[char]) -> String {
+    for bracket in stack.iter().rev() {
+        text.push(if *bracket == '{' { '}' } else { ']' });
+    }
+    text
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Answer {
+        title: Option<String>,
+        #[serde(default)]
+        sources: Vec<String>,
+        score: Option<f64>,
+    }
+
+    #[test]
+    fn test_partial_object() {
+        let text =
+            r#"Here you go: {"title": "Orcas \"killer\" whales", "sources": ["a.pdf", "b.pdf"], "score": 0.9} Done."#;
+        let mut parser = PartialJson::new();
+        let mut updates = Vec::new();
+        let mut partials = Vec::new();
+        // Feed the response a few characters at a time, as tokens.
+        for chunk in text.chars().collect::<Vec<_>>().chunks(3) {
+            updates.extend(parser.push(&chunk.iter().collect::<String>()).unwrap());
+            if let Ok(answer) = parser.partial::<Answer>() {
+                partials.push(answer);
+            }
+        }
+        assert_eq!(
+            updates,
+            vec![
+                JsonUpdate::Field {
+                    key: "title".to_string(),
+                    value: json!("Orcas \"killer\" whales")
+                },
+                JsonUpdate::Field {
+                    key: "sources".to_string(),
+                    value: json!(["a.pdf", "b.pdf"])
+                },
+                JsonUpdate::Field {
+                    key: "score".to_string(),
+                    value: json!(0.9)
+                },
+            ]
+        );
+        assert!(partials.iter().any(|answer| answer.title.is_some() && answer.sources == ["a.pdf"]));
+        assert!(parser.is_complete());
+        let answer: Answer = parser.finish().unwrap();
+        assert_eq!(answer.score, Some(0.9));
+    }
+
+    #[test]
+    fn test_partial_array() {
+        let mut parser = PartialJson::new();
+        assert_eq!(parser.value(), None);
+        let updates = parser.push(r#"[{"name": "orca"}, {"name": "sal"#).unwrap();
+        assert_eq!(
+            updates,
+            vec![JsonUpdate::Item {
+                index: 0,
+                value: json!({"name": "orca"})
+            }]
+        );
+        assert_eq!(parser.value(), Some(json!([{"name": "orca"}, {"name": "sal"}])));
+        let updates = parser.push(r#"mon"}, tr"#).unwrap();
+        assert_eq!(
+            updates,
+            vec![JsonUpdate::Item {
+                index: 1,
+                value: json!({"name": "salmon"})
+            }]
+        );
+        assert_eq!(parser.value(), Some(json!([{"name": "orca"}, {"name": "salmon"}])));
+        assert!(parser.clone().finish::<JsonValue>().is_err());
+        let updates = parser.push("ue]").unwrap();
+        assert_eq!(
+            updates,
+            vec![JsonUpdate::Item {
+                index: 2,
+                value: json!(true)
+            }]
+        );
+        assert_eq!(parser.finish::<Vec<JsonValue>>().unwrap().len(), 3);
+        assert!(PartialJson::new().push(r#"{"a": tru, "b": 1}"#).is_err());
+    }
+}
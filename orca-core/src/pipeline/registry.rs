@@ -0,0 +1,247 @@
+//! Pipelines registered by name.
+//!
+//! A `PipelineRegistry` holds pipelines of any type by name, so that flows can reuse them by reference
+//! instead of nesting their types: a `CallPipeline` executes the pipeline registered under a name when it
+//! is executed itself, and links into composed pipelines like any other step. Every step of a sequence of
+//! calls has the same type, whatever the pipelines they call:
+//!
+//! ```no_run
+//! use orca_core::llm::openai::OpenAI;
+//! use orca_core::pipeline::registry::PipelineRegistry;
+//! use orca_core::pipeline::sequential::SequentialPipeline;
+//! use orca_core::pipeline::simple::LLMPipeline;
+//! use orca_core::pipeline::Pipeline;
+//!
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! let client = OpenAI::new();
+//! let registry = PipelineRegistry::new();
+//! registry.register(
+//!     "summarize",
+//!     LLMPipeline::new(&client).load_template("run", "{{#chat}}{{#user}}Summarize Hamlet.{{/user}}{{/chat}}")?,
+//! );
+//! registry.register(
+//!     "review",
+//!     LLMPipeline::new(&client).load_template("run", "{{#chat}}{{#system}}Review this summary.{{/system}}{{/chat}}")?,
+//! );
+//! let flow = SequentialPipeline::new()
+//!     .link(registry.call("summarize"))
+//!     .link(registry.call("review").with_template("run")?);
+//! let review = flow.execute("run").await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use tokio::sync::RwLock;
+
+use super::context::{MergeStrategy, PipelineContext};
+use super::describe::PipelineDescription;
+use super::{context_of, Pipeline, PipelineResult};
+use crate::prompt::segment::Segment;
+use crate::prompt::TemplateEngine;
+
+/// Pipeline shared by the registry and the calls to it.
+pub type SharedPipeline = Arc<RwLock<Box<dyn Pipeline>>>;
+
+/// Pipelines by name. Clones share the same pipelines, so pipelines registered after a call is created
+/// are found when it is executed.
+#[derive(Clone, Default)]
+pub struct PipelineRegistry {
+    pipelines: Arc<std::sync::RwLock<HashMap<String, SharedPipeline>>>,
+}
+
+impl PipelineRegistry {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a pipeline under a name, replacing the pipeline registered under it, if any.
+    pub fn register<P: Pipeline + 'static>(&self, name: &str, pipeline: P) {
+        let pipeline: Box<dyn Pipeline> = Box::new(pipeline);
+        self.pipelines.write().unwrap().insert(name.to_string(), Arc::new(RwLock::new(pipeline)));
+    }
+
+    /// Remove the pipeline registered under a name, returning whether there was one.
+    pub fn unregister(&self, name: &str) -> bool {
+        self.pipelines.write().unwrap().remove(name).is_some()
+    }
+
+    /// Get the pipeline registered under a name.
+    pub fn get(&self, name: &str) -> Option<SharedPipeline> {
+        self.pipelines.read().unwrap().get(name).cloned()
+    }
+
+    /// Names of the registered pipelines, sorted.
+    pub fn names(&self) -> Vec<String> {
+        let mut names = self.pipelines.read().unwrap().keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// Execute the target of the pipeline registered under a name.
+    pub async fn execute(&self, name: &str, target: &str) -> Result<PipelineResult> {
+        self.call(name).execute(target).await
+    }
+
+    /// Create a step executing the pipeline registered under a name.
+    pub fn call(&self, name: &str) -> CallPipeline {
+        CallPipeline {
+            registry: self.clone(),
+            name: name.to_string(),
+            context: PipelineContext::new(),
+            templates: TemplateEngine::new(),
+        }
+    }
+
+    /// Find the pipeline registered under a name, failing if there is none.
+    fn resolve(&self, name: &str) -> Result<SharedPipeline> {
+        self.get(name).ok_or_else(|| anyhow!("No pipeline is registered under the name {}", name))
+    }
+}
+
+/// Pipeline executing the pipeline registered under a name in a registry, looked up on each execution.
+///
+/// The context of the call and the messages appended to its templates, e.g. the previous response passed
+/// by a `SequentialPipeline`, are added to the called pipeline for the execution only, so that flows
+/// sharing a pipeline do not see each other's inputs. The called pipeline is locked while it executes, so
+/// a pipeline must not call itself, directly or through other calls.
+pub struct CallPipeline {
+    /// Registry the pipeline is looked up in.
+    registry: PipelineRegistry,
+
+    /// Name of the called pipeline.
+    name: String,
+
+    /// Context added to the context of the called pipeline, replacing its values.
+    context: PipelineContext,
+
+    /// Messages added to the templates of the same name of the called pipeline.
+    templates: TemplateEngine,
+}
+
+impl CallPipeline {
+    /// Accept messages appended to the template of the given name, which are added to the end of the
+    /// template of the called pipeline. Needed to link the call after other steps of a `SequentialPipeline`,
+    /// which pass their response as a message.
+    pub fn with_template(mut self, name: &str) -> Result<Self> {
+        self.templates = self.templates.register_template(name, "")?;
+        Ok(self)
+    }
+
+    /// Name of the called pipeline.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Messages added to a template of the called pipeline.
+    fn messages(&self, target: &str) -> Vec<Segment> {
+        let segments = self.templates.get_segments(target).unwrap_or_default();
+        segments.iter().filter(|segment| matches!(segment, Segment::Message(..))).cloned().collect()
+    }
+}
+
+#[async_trait::async_trait]
+impl Pipeline for CallPipeline {
+    async fn execute(&self, target: &str) -> Result<PipelineResult> {
+        let pipeline = self.registry.resolve(&self.name)?;
+        let messages = self.messages(target);
+        if self.context.as_object().is_empty() && messages.is_empty() {
+            return pipeline.read().await.execute(target).await;
+        }
+
+        let mut pipeline = pipeline.write().await;
+        let saved_context = match self.context.as_object().is_empty() {
+            true => None,
+            false => Some(context_of(&mut **pipeline)?.clone()),
+        };
+        let saved_template = match messages.is_empty() {
+            true => None,
+            false => Some(
+                pipeline
+                    .template_engine()
+                    .get_template(target)
+                    .ok_or_else(|| anyhow!("Pipeline {} has no template {}", self.name, target))?,
+            ),
+        };
+        let result = async {
+            if saved_context.is_some() {
+                context_of(&mut **pipeline)?.merge(&self.context, MergeStrategy::Overwrite)?;
+            }
+            for message in &messages {
+                pipeline.template_engine().append_segment(target, message.clone())?;
+            }
+            pipeline.execute(target).await
+        }
+        .await;
+
+        if let Some(context) = saved_context {
+            *context_of(&mut **pipeline)? = context;
+        }
+        if let Some(template) = saved_template {
+            pipeline.template_engine().set_template(target, &template)?;
+        }
+        result
+    }
+
+    fn template_engine(&mut self) -> &mut TemplateEngine {
+        &mut self.templates
+    }
+
+    fn context(&mut self) -> Option<&mut PipelineContext> {
+        Some(&mut self.context)
+    }
+
+    fn describe(&self) -> PipelineDescription {
+        let description = PipelineDescription::new("CallPipeline", &self.name);
+        match self.registry.get(&self.name) {
+            Some(pipeline) => match pipeline.try_read() {
+                Ok(pipeline) => description.with_step(pipeline.describe()),
+                Err(_) => description,
+            },
+            None => description,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pipeline::sequential::SequentialPipeline;
+    use crate::pipeline::simple::LLMPipeline;
+    use crate::testing::EchoLLM;
+
+    #[tokio::test]
+    async fn test_registry() {
+        let registry = PipelineRegistry::new();
+        let first = LLMPipeline::new(&EchoLLM::new())
+            .load_template("run", "{{#chat}}{{#user}}Orcas{{/user}}{{/chat}}")
+            .unwrap();
+        let second = LLMPipeline::new(&EchoLLM::new())
+            .load_template("run", "{{#chat}}{{#user}}About {{topic}}{{/user}}{{/chat}}");
+        registry.register("first", first);
+        registry.register("second", second.unwrap());
+        assert_eq!(registry.names(), vec!["first", "second"]);
+
+        // The previous response and the context of the call are added for the execution only.
+        let mut call = registry.call("second").with_template("run").unwrap();
+        call.context().unwrap().set("topic", "Dolphins").unwrap();
+        let flow = SequentialPipeline::new().link(registry.call("first")).link(call);
+        let result = flow.execute("run").await.unwrap();
+        assert!(result.content().contains("Dolphins") && result.content().contains("Orcas"));
+        let result = registry.execute("second", "run").await.unwrap();
+        assert!(!result.content().contains("Dolphins") && !result.content().contains("Orcas"));
+
+        let describe = flow.describe();
+        assert_eq!(describe.steps[1].kind, "CallPipeline");
+        assert_eq!(describe.steps[1].steps[0].kind, "LLMPipeline");
+
+        assert!(registry.unregister("first"));
+        let error = flow.execute("run").await.unwrap_err();
+        assert_eq!(error.to_string(), "No pipeline is registered under the name first");
+    }
+}
@@ -0,0 +1,355 @@
+//! Pipelines rebuilt when their configuration changes.
+//!
+//! A `ReloadablePipeline` builds its pipeline from the files of a configuration directory, e.g. the
+//! templates loaded by [`load_templates`], and rebuilds it when the files change, so that prompt edits go
+//! live without restarting the service. The new pipeline replaces the current one atomically: executions
+//! already running finish with the pipeline they started with. If the files do not build a valid pipeline,
+//! the current one is kept and the error is reported, so a broken edit never takes a pipeline down.
+//!
+//! Pipelines are reloaded on demand with `ReloadablePipeline::reload`, or in the background with
+//! `ReloadablePipeline::watch` and [`watch_all`], which watch the directories with `notify` and rebuild the
+//! pipelines whose directory changed once the changes settle. Where the file system does not report
+//! changes, the directories are polled instead. Either way, a pipeline is only rebuilt if the hash of its
+//! files changed.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use notify_debouncer_mini::notify::RecursiveMode;
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult};
+use tokio::task::JoinHandle;
+
+use super::describe::PipelineDescription;
+use super::{Pipeline, PipelineResult};
+use crate::prompt::TemplateEngine;
+
+/// Extension of the template files loaded by [`load_templates`].
+pub const TEMPLATE_EXTENSION: &str = "hbs";
+
+/// Builds a pipeline from the files of its configuration directory.
+type Builder<P> = dyn Fn(&Path) -> Result<P> + Send + Sync;
+
+/// Register every `.hbs` file of a directory as a template named after the file, e.g. `qa.hbs` as `qa`.
+/// Fails if a template does not compile.
+pub fn load_templates(dir: &Path) -> Result<TemplateEngine> {
+    let mut engine = TemplateEngine::new();
+    for path in files(dir)? {
+        if path.extension().and_then(|extension| extension.to_str()) != Some(TEMPLATE_EXTENSION) {
+            continue;
+        }
+        let name = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or_else(|| anyhow!("Invalid template file name {}", path.display()))?;
+        let template = std::fs::read_to_string(&path)?;
+        engine = engine
+            .register_template(name, &template)
+            .with_context(|| format!("Invalid template {}", path.display()))?;
+    }
+    Ok(engine)
+}
+
+/// Files of a directory, sorted by path.
+fn files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let path = entry?.path();
+        if path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Hash of the names and contents of the files of a directory.
+fn fingerprint(dir: &Path) -> Result<u64> {
+    let mut hasher = DefaultHasher::new();
+    for path in files(dir)? {
+        path.hash(&mut hasher);
+        std::fs::read(&path)?.hash(&mut hasher);
+    }
+    Ok(hasher.finish())
+}
+
+/// Pipeline rebuilt from its configuration directory when the files change. The context of the pipeline
+/// is set by its builder.
+pub struct ReloadablePipeline<P> {
+    /// Configuration directory.
+    dir: PathBuf,
+
+    /// Builds the pipeline from the directory.
+    builder: Arc<Builder<P>>,
+
+    /// Pipeline built from the last valid configuration.
+    current: RwLock<Arc<P>>,
+
+    /// Fingerprint of the last configuration built, valid or not.
+    fingerprint: Mutex<u64>,
+}
+
+impl<P: Pipeline + 'static> ReloadablePipeline<P> {
+    /// Build a pipeline from a configuration directory, failing if it is not valid.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use std::path::Path;
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    /// use orca_core::llm::openai::OpenAI;
+    /// use orca_core::pipeline::reload::{load_templates, ReloadablePipeline};
+    /// use orca_core::pipeline::simple::LLMPipeline;
+    /// use orca_core::pipeline::Pipeline;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let pipeline = ReloadablePipeline::new("prompts/support", |dir: &Path| {
+    ///     let mut pipeline = LLMPipeline::new(&OpenAI::new());
+    ///     pipeline.template_engine = load_templates(dir)?;
+    ///     Ok(pipeline)
+    /// })?;
+    /// let pipeline = Arc::new(pipeline);
+    /// let _watcher = pipeline.clone().watch(Duration::from_secs(2));
+    /// let result = pipeline.execute("qa").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new<F>(dir: impl AsRef<Path>, builder: F) -> Result<Self>
+    where
+        F: Fn(&Path) -> Result<P> + Send + Sync + 'static,
+    {
+        let dir = dir.as_ref().to_path_buf();
+        let fingerprint = fingerprint(&dir)?;
+        let pipeline = builder(&dir).with_context(|| format!("Invalid pipeline configuration {}", dir.display()))?;
+        Ok(ReloadablePipeline {
+            dir,
+            builder: Arc::new(builder),
+            current: RwLock::new(Arc::new(pipeline)),
+            fingerprint: Mutex::new(fingerprint),
+        })
+    }
+
+    /// The pipeline built from the last valid configuration.
+    pub fn current(&self) -> Arc<P> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Rebuild the pipeline if the configuration changed since it was last built. Returns whether the
+    /// pipeline was replaced. If the new configuration is not valid, the current pipeline is kept and the
+    /// error is returned; the same configuration is not built again until it changes.
+    pub fn reload(&self) -> Result<bool> {
+        let fingerprint = fingerprint(&self.dir)?;
+        {
+            let mut last = self.fingerprint.lock().unwrap();
+            if *last == fingerprint {
+                return Ok(false);
+            }
+            *last = fingerprint;
+        }
+        let pipeline = (self.builder)(&self.dir).with_context(|| {
+            format!(
+                "Invalid pipeline configuration {}, keeping the current one",
+                self.dir.display()
+            )
+        })?;
+        *self.current.write().unwrap() = Arc::new(pipeline);
+        log::info!("Reloaded pipeline from {}", self.dir.display());
+        Ok(true)
+    }
+
+    /// Watch the configuration directory, reloading the pipeline in the background once changes have
+    /// settled for the given time, until the returned task is aborted. See [`watch_all`].
+    pub fn watch(self: Arc<Self>, debounce: Duration) -> JoinHandle<()> {
+        watch_all(vec![self], debounce)
+    }
+}
+
+/// Pipeline that can be reloaded from a configuration directory, so that pipelines of different types
+/// can be watched together.
+pub trait Reloadable: Send + Sync {
+    /// Configuration directory.
+    fn dir(&self) -> &Path;
+
+    /// Rebuild the pipeline if the configuration changed, returning whether it was replaced.
+    fn reload(&self) -> Result<bool>;
+}
+
+impl<P: Pipeline + 'static> Reloadable for ReloadablePipeline<P> {
+    fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn reload(&self) -> Result<bool> {
+        ReloadablePipeline::reload(self)
+    }
+}
+
+/// Reload a pipeline, logging invalid configurations.
+fn reload_logged(pipeline: &dyn Reloadable) {
+    if let Err(e) = pipeline.reload() {
+        log::error!("{:#}", e);
+    }
+}
+
+/// Watch the configuration directories of pipelines, reloading in the background the pipelines whose
+/// directory changed once the changes have settled for the given time, until the returned task is
+/// aborted. Invalid configurations are logged. If the directories cannot be watched, e.g. on network file
+/// systems or once the limit of watches is reached, they are polled at the debounce interval instead.
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use orca_core::llm::openai::OpenAI;
+/// use orca_core::pipeline::reload::{load_templates, watch_all, ReloadablePipeline};
+/// use orca_core::pipeline::simple::LLMPipeline;
+///
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// let build = |dir: &Path| {
+///     let mut pipeline = LLMPipeline::new(&OpenAI::new());
+///     pipeline.template_engine = load_templates(dir)?;
+///     Ok(pipeline)
+/// };
+/// let support = Arc::new(ReloadablePipeline::new("prompts/support", build)?);
+/// let sales = Arc::new(ReloadablePipeline::new("prompts/sales", build)?);
+/// let _watcher = watch_all(vec![support.clone(), sales.clone()], Duration::from_millis(500));
+/// # Ok(())
+/// # }
+/// ```
+pub fn watch_all(pipelines: Vec<Arc<dyn Reloadable>>, debounce: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let (sender, mut changes) = tokio::sync::mpsc::unbounded_channel();
+        let watched = new_debouncer(debounce, move |events: DebounceEventResult| {
+            let _ = sender.send(events);
+        })
+        .and_then(|mut debouncer| {
+            for pipeline in &pipelines {
+                debouncer.watcher().watch(pipeline.dir(), RecursiveMode::NonRecursive)?;
+            }
+            Ok(debouncer)
+        });
+        // The debouncer stops watching when dropped, so it lives as long as the task.
+        let _debouncer = match watched {
+            Ok(debouncer) => debouncer,
+            Err(e) => {
+                log::warn!("Failed to watch pipeline configurations, polling them instead: {}", e);
+                loop {
+                    tokio::time::sleep(debounce).await;
+                    pipelines.iter().for_each(|pipeline| reload_logged(pipeline.as_ref()));
+                }
+            }
+        };
+        // Event paths are relative to the watched path as given, or canonical on some platforms.
+        let dirs = pipelines
+            .iter()
+            .map(|pipeline| {
+                let dir = pipeline.dir().to_path_buf();
+                (dir.canonicalize().unwrap_or_else(|_| dir.clone()), dir)
+            })
+            .collect::<Vec<_>>();
+        while let Some(events) = changes.recv().await {
+            let events = match events {
+                Ok(events) => events,
+                Err(e) => {
+                    log::warn!("Failed to watch pipeline configurations: {}", e);
+                    continue;
+                }
+            };
+            for (pipeline, (canonical, dir)) in pipelines.iter().zip(&dirs) {
+                if events.iter().any(|event| event.path.starts_with(canonical) || event.path.starts_with(dir)) {
+                    reload_logged(pipeline.as_ref());
+                }
+            }
+        }
+    })
+}
+
+#[async_trait::async_trait]
+impl<P: Pipeline + 'static> Pipeline for ReloadablePipeline<P> {
+    async fn execute(&self, target: &str) -> Result<PipelineResult> {
+        self.current().execute(target).await
+    }
+
+    fn describe(&self) -> PipelineDescription {
+        PipelineDescription::new("ReloadablePipeline", &self.dir.display().to_string())
+            .with_step(self.current().describe())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pipeline::simple::LLMPipeline;
+    use crate::testing::EchoLLM;
+
+    #[tokio::test]
+    async fn test_reload() {
+        let dir = std::env::temp_dir().join(format!("orca-reload-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("qa.hbs"), "Where do orcas live?").unwrap();
+        std::fs::write(dir.join("notes.txt"), "Not a template").unwrap();
+        let pipeline = ReloadablePipeline::new(&dir, |dir: &Path| {
+            let mut pipeline = LLMPipeline::new(&EchoLLM::new());
+            pipeline.template_engine = load_templates(dir)?;
+            Ok(pipeline)
+        })
+        .unwrap();
+        assert_eq!(pipeline.execute("qa").await.unwrap().content(), "Where do orcas live?");
+        assert!(!pipeline.reload().unwrap());
+
+        std::fs::write(dir.join("qa.hbs"), "What do orcas eat?").unwrap();
+        let before = pipeline.current();
+        assert!(pipeline.reload().unwrap());
+        assert_eq!(pipeline.execute("qa").await.unwrap().content(), "What do orcas eat?");
+        assert_eq!(before.execute("qa").await.unwrap().content(), "Where do orcas live?");
+
+        // An invalid template is rolled back to the last valid pipeline.
+        std::fs::write(dir.join("qa.hbs"), "{{#if question}}What do orcas eat?").unwrap();
+        assert!(pipeline.reload().is_err());
+        assert!(!pipeline.reload().unwrap());
+        assert_eq!(pipeline.execute("qa").await.unwrap().content(), "What do orcas eat?");
+        assert_eq!(pipeline.describe().steps[0].templates[0].name, "qa");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_watch_all() {
+        let root = std::env::temp_dir().join(format!("orca-watch-{}", uuid::Uuid::new_v4()));
+        let builds = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let pipeline = |name: &str| {
+            let dir = root.join(name);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("qa.hbs"), "Where do orcas live?").unwrap();
+            let builds = builds.clone();
+            let pipeline = ReloadablePipeline::new(&dir, move |dir: &Path| {
+                builds.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                let mut pipeline = LLMPipeline::new(&EchoLLM::new());
+                pipeline.template_engine = load_templates(dir)?;
+                Ok(pipeline)
+            });
+            Arc::new(pipeline.unwrap())
+        };
+        let (support, sales) = (pipeline("support"), pipeline("sales"));
+        let watcher = watch_all(vec![support.clone(), sales.clone()], Duration::from_millis(50));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        std::fs::write(root.join("support").join("qa.hbs"), "What do orcas eat?").unwrap();
+        for _ in 0..100 {
+            if support.execute("qa").await.unwrap().content() == "What do orcas eat?" {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(support.execute("qa").await.unwrap().content(), "What do orcas eat?");
+        assert_eq!(sales.execute("qa").await.unwrap().content(), "Where do orcas live?");
+        assert_eq!(builds.load(std::sync::atomic::Ordering::SeqCst), 3);
+        watcher.abort();
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,173 @@
+//! Routing of requests to pipelines by intent.
+//!
+//! A `RouterPipeline` classifies the question of each execution with a `Classifier` and executes the
+//! pipeline linked to its label, e.g. a plain chat pipeline for small talk and a retrieval pipeline for
+//! questions about the documents. The label is recorded in the `route` metadata of the result.
+
+use super::classify::Classifier;
+use super::context::{MergeStrategy, PipelineContext};
+use super::describe::{describe_shared, PipelineDescription};
+use super::simple::QUESTION_KEY;
+use super::{context_of, Pipeline, PipelineResult};
+use crate::llm::LLM;
+use crate::prompt::context::Context;
+
+use anyhow::{anyhow, Result};
+use serde_json::Value as JsonValue;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Pipeline executing the pipeline linked to the label of the question.
+pub struct RouterPipeline<M, P> {
+    /// Classifier labelling the questions.
+    classifier: Classifier<M>,
+
+    /// Pipelines, by label.
+    routes: Vec<(String, Arc<RwLock<P>>)>,
+
+    /// Pipeline executed for labels without a route.
+    fallback: Option<Arc<RwLock<P>>>,
+
+    /// Context shared by the linked pipelines, holding the question. Keys already set in a linked
+    /// pipeline take precedence.
+    context: PipelineContext,
+}
+
+impl<M: LLM + Clone + 'static, P: Pipeline> RouterPipeline<M, P> {
+    /// Create a router classifying the `question` key of its context.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use orca_core::llm::openai::OpenAI;
+    /// use orca_core::pipeline::classify::Classifier;
+    /// use orca_core::pipeline::router::RouterPipeline;
+    /// use orca_core::pipeline::simple::{LLMPipeline, QUESTION_KEY};
+    /// use orca_core::pipeline::Pipeline;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let client = OpenAI::new();
+    /// let classifier = Classifier::new(&client.clone().with_temperature(0.0))
+    ///     .with_label("small_talk", "Greetings and chit-chat")
+    ///     .with_label("needs_retrieval", "Questions about the documentation");
+    /// let chat = LLMPipeline::new(&client)
+    ///     .load_template("answer", "{{#chat}}{{#user}}{{question}}{{/user}}{{/chat}}")?;
+    /// let rag = LLMPipeline::new(&client).load_template(
+    ///     "answer",
+    ///     "{{#chat}}{{#user}}{{#each documents}}{{this}}\n{{/each}}{{question}}{{/user}}{{/chat}}",
+    /// )?;
+    /// let mut router = RouterPipeline::new(classifier)
+    ///     .with_route("small_talk", chat)
+    ///     .with_route("needs_retrieval", rag);
+    /// router.context().unwrap().set(QUESTION_KEY, "How do I configure Qdrant?")?;
+    /// let result = router.execute("answer").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new(classifier: Classifier<M>) -> Self {
+        RouterPipeline {
+            classifier,
+            routes: Vec::new(),
+            fallback: None,
+            context: PipelineContext::new(),
+        }
+    }
+
+    /// Link the pipeline executed for questions with the given label.
+    pub fn with_route(mut self, label: &str, pipeline: P) -> Self {
+        self.routes.push((label.to_string(), Arc::new(RwLock::new(pipeline))));
+        self
+    }
+
+    /// Link the pipeline executed for labels without a route.
+    pub fn with_fallback(mut self, pipeline: P) -> Self {
+        self.fallback = Some(Arc::new(RwLock::new(pipeline)));
+        self
+    }
+
+    /// Load context shared by all the linked pipelines.
+    pub fn load_context(mut self, context: &Context) -> Result<Self> {
+        self.context.merge(&context.into(), MergeStrategy::Error)?;
+        Ok(self)
+    }
+
+    /// Classify a question, returning its label and the pipeline linked to it.
+    pub async fn route(&self, question: &str) -> Result<(String, Arc<RwLock<P>>)> {
+        let label = self.classifier.classify_label(question).await?;
+        let pipeline = self
+            .routes
+            .iter()
+            .find(|(route, _)| *route == label)
+            .map(|(_, pipeline)| pipeline)
+            .or(self.fallback.as_ref())
+            .ok_or_else(|| anyhow!("No pipeline is linked to label {}", label))?;
+        Ok((label, pipeline.clone()))
+    }
+}
+
+#[async_trait::async_trait]
+impl<M: LLM + Clone + 'static, P: Pipeline> Pipeline for RouterPipeline<M, P> {
+    async fn execute(&self, target: &str) -> Result<PipelineResult> {
+        let question = self
+            .context
+            .get(QUESTION_KEY)
+            .and_then(JsonValue::as_str)
+            .ok_or_else(|| anyhow!("Router context has no {} to classify", QUESTION_KEY))?;
+        let (label, pipeline) = self.route(question).await?;
+        log::debug!("Routing question to {}", label);
+        context_of(&mut *pipeline.write().await)?.merge(&self.context, MergeStrategy::Keep)?;
+        let result = pipeline.read().await.execute(target).await?;
+        Ok(result.with_metadata("route", label))
+    }
+
+    fn context(&mut self) -> Option<&mut PipelineContext> {
+        Some(&mut self.context)
+    }
+
+    fn describe(&self) -> PipelineDescription {
+        let mut description = PipelineDescription::new("RouterPipeline", "").with_model(self.classifier.llm().config());
+        for (label, pipeline) in &self.routes {
+            description = description.with_step(describe_shared(pipeline).with_label(label));
+        }
+        if let Some(fallback) = &self.fallback {
+            description = description.with_step(describe_shared(fallback).with_label("fallback"));
+        }
+        description
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pipeline::simple::LLMPipeline;
+    use crate::testing::FixedLLM;
+
+    fn pipeline(answer: &'static str) -> LLMPipeline<FixedLLM> {
+        LLMPipeline::new(&FixedLLM::new(answer)).load_template("answer", "{{question}}").unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_route() {
+        let classifier = Classifier::new(&FixedLLM::new("needs_retrieval"))
+            .with_label("small_talk", "Greetings and chit-chat")
+            .with_label("needs_retrieval", "Questions about orcas");
+        let mut router = RouterPipeline::new(classifier)
+            .with_route("small_talk", pipeline("Hello!"))
+            .with_route("needs_retrieval", pipeline("Orcas live in every ocean."));
+        assert!(router.execute("answer").await.is_err());
+
+        router.context().unwrap().set(QUESTION_KEY, "Where do orcas live?").unwrap();
+        let result = router.execute("answer").await.unwrap();
+        assert_eq!(result.content(), "Orcas live in every ocean.");
+        assert_eq!(result.metadata()["route"], "needs_retrieval");
+
+        let classifier = Classifier::new(&FixedLLM::new("needs_tools")).with_label("needs_tools", "Requests to act");
+        let router = RouterPipeline::new(classifier).with_route("small_talk", pipeline("Hello!"));
+        assert!(router.route("Book a whale watching tour").await.is_err());
+        let router = router.with_fallback(pipeline("I can't help with that."));
+        assert_eq!(
+            router.route("Book a whale watching tour").await.unwrap().0,
+            "needs_tools"
+        );
+    }
+}
@@ -0,0 +1,412 @@
+//! Self-query retrieval.
+//!
+//! A `SelfQueryRetriever` asks an LLM to split a natural language query into a semantic query and a
+//! structured filter over a declared metadata schema. The semantic query is embedded and both are
+//! executed against a Qdrant collection, so that a query like "papers about transformers published
+//! after 2020" only searches the points whose `year` is greater than 2020.
+
+use crate::llm::{Embedding, LLM};
+use crate::prompt::TemplateEngine;
+use crate::qdrant::{Condition, FoundPoint, Qdrant, Range, Value};
+use crate::{prompt, template};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+use std::sync::Arc;
+use tracing::field::Empty;
+use tracing::Span;
+
+/// Name of the template used to structure the query.
+pub const SELF_QUERY_TEMPLATE: &str = "self_query";
+
+static DEFAULT_SELF_QUERY: &str = r#"{{#chat}}
+{{#system}}Your goal is to structure the user's query for a search over documents with the following metadata fields: {{#each fields}}`{{name}}` ({{kind}}): {{description}}. {{/each}}
+Extract the text to search for and the conditions on the metadata fields that the documents must satisfy. The operators you can use are eq, ne, gt, gte, lt, lte (for integer and float fields) and in (with a list of strings). Only use the fields listed above, and do not repeat the conditions in the search text.
+Respond only with a JSON object of the form {"query": "<text to search for>", "filter": [{"field": "<field name>", "op": "<operator>", "value": <value>}]}. If the query has no conditions, use an empty filter list.{{/system}}
+{{#user}}{{query}}{{/user}}
+{{/chat}}"#;
+
+/// Type of a metadata field.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldKind {
+    String,
+    Integer,
+    Float,
+    Boolean,
+}
+
+/// A metadata field that the LLM is allowed to filter on.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MetadataField {
+    /// Name of the field in the point payload.
+    pub name: String,
+
+    /// Type of the field.
+    pub kind: FieldKind,
+
+    /// Description of the field, shown to the LLM.
+    pub description: String,
+}
+
+impl MetadataField {
+    /// Create a new metadata field.
+    pub fn new(name: &str, kind: FieldKind, description: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            kind,
+            description: description.to_string(),
+        }
+    }
+}
+
+/// Comparison operator of a filter condition.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operator {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    In,
+}
+
+/// A single filter condition extracted from the query.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Comparison {
+    /// Name of the metadata field.
+    pub field: String,
+
+    /// Comparison operator.
+    pub op: Operator,
+
+    /// Value to compare the field with.
+    pub value: JsonValue,
+}
+
+/// A query split into a semantic part and a metadata filter.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct StructuredQuery {
+    /// Text to embed and search for.
+    pub query: String,
+
+    /// Conditions the results must satisfy.
+    #[serde(default)]
+    pub filter: Vec<Comparison>,
+}
+
+impl StructuredQuery {
+    /// Parse a structured query from an LLM response, possibly surrounded by other text such as a
+    /// markdown code fence.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::pipeline::self_query::{Operator, StructuredQuery};
+    ///
+    /// let query = StructuredQuery::parse(r#"{"query": "rust", "filter": [{"field": "year", "op": "gt", "value": 2020}]}"#).unwrap();
+    /// assert_eq!(query.query, "rust");
+    /// assert_eq!(query.filter[0].op, Operator::Gt);
+    /// ```
+    pub fn parse(response: &str) -> Result<StructuredQuery> {
+        let start = response.find('{');
+        let end = response.rfind('}');
+        match (start, end) {
+            (Some(start), Some(end)) if start < end => Ok(serde_json::from_str(&response[start..=end])?),
+            _ => Err(anyhow::anyhow!(
+                "Unable to find a structured query in response: {}",
+                response
+            )),
+        }
+    }
+
+    /// Convert the filter into Qdrant conditions, checking each comparison against the schema.
+    pub fn to_conditions(&self, fields: &[MetadataField]) -> Result<Vec<Condition>> {
+        self.filter
+            .iter()
+            .map(|comparison| {
+                let field = fields
+                    .iter()
+                    .find(|f| f.name == comparison.field)
+                    .ok_or_else(|| anyhow::anyhow!("Unknown metadata field: {}", comparison.field))?;
+                comparison.to_condition(field)
+            })
+            .collect()
+    }
+}
+
+impl Comparison {
+    fn to_condition(&self, field: &MetadataField) -> Result<Condition> {
+        let name = field.name.clone();
+        let invalid = || {
+            anyhow::anyhow!(
+                "Invalid comparison on {} field {}: {:?} {}",
+                serde_json::to_string(&field.kind).unwrap_or_default(),
+                field.name,
+                self.op,
+                self.value
+            )
+        };
+
+        match self.op {
+            Operator::Eq | Operator::Ne => {
+                let condition = match field.kind {
+                    FieldKind::String if self.value.is_string() => {
+                        Condition::Matches(name, Value::from(self.value.clone()))
+                    }
+                    FieldKind::Integer if self.value.is_i64() => {
+                        Condition::Matches(name, Value::from(self.value.clone()))
+                    }
+                    FieldKind::Boolean if self.value.is_boolean() => {
+                        Condition::Matches(name, Value::from(self.value.clone()))
+                    }
+                    // Qdrant cannot match floats exactly, so use a closed range instead.
+                    FieldKind::Float => {
+                        let value = self.value.as_f64().ok_or_else(invalid)?;
+                        Condition::Range(
+                            name,
+                            Range {
+                                gte: Some(value),
+                                lte: Some(value),
+                                ..Default::default()
+                            },
+                        )
+                    }
+                    _ => return Err(invalid()),
+                };
+                match self.op {
+                    Operator::Ne => Ok(Condition::Not(Box::new(condition))),
+                    _ => Ok(condition),
+                }
+            }
+            Operator::Gt | Operator::Gte | Operator::Lt | Operator::Lte => {
+                if !matches!(field.kind, FieldKind::Integer | FieldKind::Float) {
+                    return Err(invalid());
+                }
+                let value = Some(self.value.as_f64().ok_or_else(invalid)?);
+                let range = match self.op {
+                    Operator::Gt => Range {
+                        gt: value,
+                        ..Default::default()
+                    },
+                    Operator::Gte => Range {
+                        gte: value,
+                        ..Default::default()
+                    },
+                    Operator::Lt => Range {
+                        lt: value,
+                        ..Default::default()
+                    },
+                    _ => Range {
+                        lte: value,
+                        ..Default::default()
+                    },
+                };
+                Ok(Condition::Range(name, range))
+            }
+            Operator::In => {
+                let values = match (field.kind, &self.value) {
+                    (FieldKind::String, JsonValue::Array(values)) => values
+                        .iter()
+                        .map(|v| v.as_str().map(|s| s.to_string()))
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or_else(invalid)?,
+                    _ => return Err(invalid()),
+                };
+                Ok(Condition::MatchesAny(name, values))
+            }
+        }
+    }
+}
+
+/// Retriever that turns a natural language query into a filtered semantic search.
+pub struct SelfQueryRetriever<M, E> {
+    /// The LLM used to structure the query.
+    llm: Arc<M>,
+
+    /// The model used to embed the semantic query.
+    embedder: E,
+
+    /// The vector store to search.
+    qdrant: Qdrant,
+
+    /// Name of the collection to search.
+    collection: String,
+
+    /// Metadata fields the LLM may filter on.
+    fields: Vec<MetadataField>,
+
+    /// Maximum number of results.
+    limit: usize,
+
+    /// Minimum similarity score of the results.
+    score_threshold: Option<f32>,
+
+    /// Template engine holding the self-query template.
+    template_engine: TemplateEngine,
+}
+
+impl<M, E> SelfQueryRetriever<M, E>
+where
+    M: LLM + Clone + 'static,
+    E: Embedding + Send + Sync,
+{
+    /// Creates a new self-query retriever over a Qdrant collection.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use orca_core::llm::openai::OpenAI;
+    /// use orca_core::pipeline::self_query::{FieldKind, MetadataField, SelfQueryRetriever};
+    /// use orca_core::qdrant::Qdrant;
+    ///
+    /// let client = OpenAI::new();
+    /// let qdrant = Qdrant::new("http://localhost:6334").unwrap();
+    /// let retriever = SelfQueryRetriever::new(&client, OpenAI::new(), qdrant, "papers")
+    ///     .with_field(MetadataField::new("year", FieldKind::Integer, "The year the paper was published"))
+    ///     .with_field(MetadataField::new("venue", FieldKind::String, "The conference or journal"));
+    /// ```
+    pub fn new(llm: &M, embedder: E, qdrant: Qdrant, collection: &str) -> SelfQueryRetriever<M, E> {
+        SelfQueryRetriever {
+            llm: Arc::new(llm.clone()),
+            embedder,
+            qdrant,
+            collection: collection.to_string(),
+            fields: Vec::new(),
+            limit: 10,
+            score_threshold: None,
+            template_engine: template!(SELF_QUERY_TEMPLATE, DEFAULT_SELF_QUERY),
+        }
+    }
+
+    /// Declare a metadata field that the LLM may filter on.
+    pub fn with_field(mut self, field: MetadataField) -> Self {
+        self.fields.push(field);
+        self
+    }
+
+    /// Set the maximum number of results.
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Drop results with a similarity score below the threshold.
+    pub fn with_score_threshold(mut self, score_threshold: f32) -> Self {
+        self.score_threshold = Some(score_threshold);
+        self
+    }
+
+    /// Override the self-query template. The template receives `fields` and `query`.
+    pub fn load_template(self, template: &str) -> Result<Self> {
+        Ok(Self {
+            template_engine: self.template_engine.register_template(SELF_QUERY_TEMPLATE, template)?,
+            ..self
+        })
+    }
+
+    /// The declared metadata fields.
+    pub fn fields(&self) -> &[MetadataField] {
+        &self.fields
+    }
+
+    /// Ask the LLM to split the query into a semantic query and a filter.
+    pub async fn structure(&self, query: &str) -> Result<StructuredQuery> {
+        let context = json!({ "fields": self.fields, "query": query });
+        let prompt = self.template_engine.render_context(SELF_QUERY_TEMPLATE, &context)?;
+        let response = self.llm.generate(prompt).await?;
+        let mut structured = StructuredQuery::parse(&response.to_string())?;
+        if structured.query.trim().is_empty() {
+            structured.query = query.to_string();
+        }
+        log::debug!("Structured query: {:?}", structured);
+        Ok(structured)
+    }
+
+    /// Retrieve the points matching a natural language query.
+    #[tracing::instrument(name = "retrieval", skip_all, fields(collection = %self.collection, limit = self.limit, hits = Empty))]
+    pub async fn retrieve(&self, query: &str) -> Result<Vec<FoundPoint>> {
+        let structured = self.structure(query).await?;
+        let conditions = structured.to_conditions(&self.fields)?;
+        let embedding = self.embedder.generate_embedding(prompt!(structured.query)).await?.to_vec()?;
+        let conditions = if conditions.is_empty() { None } else { Some(conditions) };
+        let points = self
+            .qdrant
+            .search(
+                &self.collection,
+                embedding,
+                self.limit,
+                conditions,
+                self.score_threshold,
+            )
+            .await?;
+        Span::current().record("hits", points.len());
+        Ok(points)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fields() -> Vec<MetadataField> {
+        vec![
+            MetadataField::new("year", FieldKind::Integer, "Publication year"),
+            MetadataField::new("venue", FieldKind::String, "Conference"),
+            MetadataField::new("rating", FieldKind::Float, "Average rating"),
+        ]
+    }
+
+    #[test]
+    fn test_parse() {
+        let response = "```json\n{\"query\": \"transformers\", \"filter\": [{\"field\": \"venue\", \"op\": \"in\", \"value\": [\"ICML\", \"NeurIPS\"]}]}\n```";
+        let query = StructuredQuery::parse(response).unwrap();
+        assert_eq!(query.query, "transformers");
+        assert_eq!(
+            query.filter,
+            vec![Comparison {
+                field: "venue".to_string(),
+                op: Operator::In,
+                value: json!(["ICML", "NeurIPS"]),
+            }]
+        );
+
+        let query = StructuredQuery::parse(r#"{"query": "transformers"}"#).unwrap();
+        assert!(query.filter.is_empty());
+        assert!(StructuredQuery::parse("transformers").is_err());
+    }
+
+    #[test]
+    fn test_to_conditions() {
+        let query = StructuredQuery::parse(
+            r#"{"query": "transformers", "filter": [
+                {"field": "year", "op": "gte", "value": 2020},
+                {"field": "venue", "op": "ne", "value": "ICML"},
+                {"field": "rating", "op": "eq", "value": 4.5}
+            ]}"#,
+        )
+        .unwrap();
+        let conditions = query.to_conditions(&fields()).unwrap();
+        assert_eq!(conditions.len(), 3);
+        assert!(matches!(&conditions[0], Condition::Range(name, range) if name == "year" && range.gte == Some(2020.0)));
+        assert!(matches!(&conditions[1], Condition::Not(inner) if matches!(**inner, Condition::Matches(..))));
+        assert!(
+            matches!(&conditions[2], Condition::Range(_, range) if range.gte == Some(4.5) && range.lte == Some(4.5))
+        );
+    }
+
+    #[test]
+    fn test_invalid_conditions() {
+        let invalid = [
+            r#"{"field": "author", "op": "eq", "value": "Vaswani"}"#,
+            r#"{"field": "venue", "op": "gt", "value": 3}"#,
+            r#"{"field": "year", "op": "eq", "value": "2020"}"#,
+            r#"{"field": "year", "op": "in", "value": [2020, 2021]}"#,
+        ];
+        for comparison in invalid {
+            let query = StructuredQuery::parse(&format!(r#"{{"query": "", "filter": [{}]}}"#, comparison)).unwrap();
+            assert!(query.to_conditions(&fields()).is_err(), "{}", comparison);
+        }
+    }
+}
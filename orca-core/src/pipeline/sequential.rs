@@ -1,7 +1,15 @@
-use super::{Pipeline, PipelineResult};
+use super::budget::{Budget, BudgetTracker};
+use super::context::{MergeStrategy, PipelineContext};
+use super::describe::{describe_shared, PipelineDescription};
+use super::usage::UsageTracker;
+use super::{context_of, Pipeline, PipelineResult};
+use crate::error::OrcaError;
+use crate::prompt::context::Context;
+use crate::prompt::estimate_tokens;
 use anyhow::Result;
 use std::sync::Arc;
 use tokio::sync::RwLock;
+use tracing::Instrument;
 
 pub struct SequentialPipeline<P> {
     /// The name of the LLMPipeline.
@@ -9,6 +17,15 @@ pub struct SequentialPipeline<P> {
 
     /// Vector of LLM pipelines used by the SequentialPipeline.
     pipelines: Vec<Arc<RwLock<P>>>,
+
+    /// Context shared by the linked pipelines. Keys already set in a linked pipeline take precedence.
+    context: PipelineContext,
+
+    /// Limits of the tokens, cost and wall time of each execution, over all the linked pipelines.
+    budget: Option<Budget>,
+
+    /// Tokens used by the LLM calls of the linked pipelines, by step.
+    usage: UsageTracker,
 }
 
 impl<P> Default for SequentialPipeline<P> {
@@ -16,6 +33,9 @@ impl<P> Default for SequentialPipeline<P> {
         Self {
             name: uuid::Uuid::new_v4().to_string(),
             pipelines: Vec::new(),
+            context: PipelineContext::new(),
+            budget: None,
+            usage: UsageTracker::new(),
         }
     }
 }
@@ -31,23 +51,93 @@ impl<P: Pipeline> SequentialPipeline<P> {
         self.pipelines.push(Arc::new(RwLock::new(pipeline)));
         self
     }
+
+    /// Bound the tokens, cost and wall time of each execution over all the linked pipelines. The
+    /// execution stops with an `OrcaError::Budget` error before a pipeline once the budget is used up, or
+    /// when it runs out of time, and the consumption is recorded in the `budget` metadata of the result.
+    pub fn with_budget(mut self, budget: Budget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Set the tracker the tokens used by the LLM calls of the linked pipelines are recorded in, under the
+    /// index of the step and the step of the linked pipeline, e.g. `1.run` for the calls of the `run`
+    /// template of the second pipeline. Only the calls reported in the results of the linked pipelines,
+    /// e.g. by an `LLMPipeline`, are recorded.
+    pub fn with_usage_tracker(mut self, usage: UsageTracker) -> Self {
+        self.usage = usage;
+        self
+    }
+
+    /// Tokens used by the LLM calls of the linked pipelines so far.
+    pub fn usage(&self) -> &UsageTracker {
+        &self.usage
+    }
+
+    /// Load context shared by all the linked pipelines.
+    pub fn load_context(mut self, context: &Context) -> Result<Self> {
+        self.context.merge(&context.into(), MergeStrategy::Error)?;
+        Ok(self)
+    }
 }
 
 #[async_trait::async_trait]
 impl<P: Pipeline> Pipeline for SequentialPipeline<P> {
     async fn execute(&self, target: &str) -> Result<PipelineResult> {
+        self.run(target).await
+    }
+
+    fn context(&mut self) -> Option<&mut PipelineContext> {
+        Some(&mut self.context)
+    }
+
+    fn describe(&self) -> PipelineDescription {
+        let description = PipelineDescription::new("SequentialPipeline", &self.name);
+        self.pipelines.iter().fold(description, |description, pipeline| {
+            description.with_step(describe_shared(pipeline))
+        })
+    }
+}
+
+impl<P: Pipeline> SequentialPipeline<P> {
+    /// Executes the linked pipelines in a `pipeline.execute` span, each of them in a `pipeline.step` span.
+    #[tracing::instrument(name = "pipeline.execute", skip(self), fields(pipeline = %self.name))]
+    async fn run(&self, target: &str) -> Result<PipelineResult> {
         let mut response = String::new();
         let mut result: PipelineResult = PipelineResult::new(self.name.to_string()); // initialize result to a default value
-        for pipeline in &self.pipelines {
+        let mut tracker = self.budget.map(BudgetTracker::new);
+        let mut usage = Vec::new();
+        for (step, pipeline) in self.pipelines.iter().enumerate() {
+            if !self.context.as_object().is_empty() {
+                context_of(&mut *pipeline.write().await)?.merge(&self.context, MergeStrategy::Keep)?;
+            }
             if !response.is_empty() {
-                pipeline
-                    .write()
-                    .await
-                    .template_engine()
-                    .add_to_template(target, &format!("{{{{#user}}}}{}{{{{/user}}}}", response));
+                pipeline.write().await.template_engine().append_user(target, &response)?;
             }
-            result = pipeline.read().await.execute(target).await?;
+            let execution = async { pipeline.read().await.execute(target).await };
+            let execution = execution.instrument(tracing::info_span!("pipeline.step", step));
+            result = match &mut tracker {
+                Some(tracker) => {
+                    tracker.check(0).map_err(OrcaError::from)?;
+                    let result = tracker.run(execution).await.map_err(OrcaError::from)??;
+                    tracker.record(result.total_tokens().unwrap_or_else(|| estimate_tokens(&result.content()) as u32));
+                    result
+                }
+                None => execution.await?,
+            };
             response = result.content();
+            let records = result.usage().iter().cloned();
+            let records = records.map(|record| {
+                let label = format!("{}.{}", step, record.step);
+                record.with_step(&label)
+            });
+            let records = records.collect::<Vec<_>>();
+            self.usage.record(records.clone());
+            usage.extend(records);
+        }
+        result = result.with_usage(usage);
+        if let Some(tracker) = &tracker {
+            result = result.with_metadata("budget", tracker.usage().to_json());
         }
         Ok(result)
     }
@@ -57,6 +147,9 @@ impl<P: Pipeline> Pipeline for SequentialPipeline<P> {
 mod test {
 
     use super::*;
+    use crate::pipeline::budget::BudgetExceeded;
+    use crate::pipeline::usage::PricingTable;
+    use crate::prompt::TemplateEngine;
     use crate::{llm::openai::OpenAI, pipeline::simple::LLMPipeline, prompt::context::Context};
     use serde::Serialize;
 
@@ -83,4 +176,96 @@ mod test {
         let res = pipeline.execute("review").await;
         assert!(res.is_ok());
     }
+
+    /// Pipeline answering with a fixed text.
+    struct Fixed(&'static str, TemplateEngine);
+
+    impl Fixed {
+        fn new(text: &'static str) -> Self {
+            Fixed(
+                text,
+                TemplateEngine::new().register_template("review", "{{#chat}}{{/chat}}").unwrap(),
+            )
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Pipeline for Fixed {
+        async fn execute(&self, _target: &str) -> Result<PipelineResult> {
+            let response = crate::llm::LLMResponse::Quantized(self.0.to_string());
+            Ok(PipelineResult::new("fixed".to_string()).with_llm_response(response))
+        }
+
+        fn template_engine(&mut self) -> &mut TemplateEngine {
+            &mut self.1
+        }
+    }
+
+    #[tokio::test]
+    async fn test_budget() {
+        let pipeline = SequentialPipeline::new().link(Fixed::new("abcdefgh")).link(Fixed::new("ijkl"));
+        let result = pipeline.with_budget(Budget::new().with_max_tokens(3)).execute("review").await.unwrap();
+        assert_eq!(result.metadata()["budget"]["tokens"], 3);
+
+        let pipeline = SequentialPipeline::new()
+            .link(Fixed::new("abcdefgh"))
+            .link(Fixed::new("ijkl"))
+            .link(Fixed::new("mnop"));
+        let error = pipeline.with_budget(Budget::new().with_max_tokens(2)).execute("review").await.unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<OrcaError>(),
+            Some(&OrcaError::Budget(BudgetExceeded::Tokens { used: 3, limit: 2 }))
+        );
+    }
+
+    /// LLM answering like OpenAI, reporting the tokens used.
+    #[derive(Clone)]
+    struct Reporting;
+
+    #[async_trait::async_trait]
+    impl crate::llm::LLM for Reporting {
+        async fn generate(&self, _prompt: Box<dyn crate::prompt::Prompt>) -> Result<crate::llm::LLMResponse> {
+            let response: crate::llm::openai::Response = serde_json::from_value(serde_json::json!({
+                "model": "gpt-4-0613",
+                "usage": {"prompt_tokens": 1000, "completion_tokens": 500, "total_tokens": 1500},
+                "choices": [{"message": {"role": "assistant", "content": "Hamlet avenges his father."}}]
+            }))?;
+            Ok(response.into())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_step_without_context() {
+        let mut pipeline = SequentialPipeline::new().link(Fixed::new("abcd"));
+        pipeline.context().unwrap().set("topic", "orcas").unwrap();
+        let error = pipeline.execute("review").await.unwrap_err();
+        assert!(error.to_string().contains("has no context"));
+    }
+
+    #[tokio::test]
+    async fn test_usage() {
+        let summary = LLMPipeline::new(&Reporting)
+            .load_template("review", "{{#chat}}{{#user}}Summarize Hamlet.{{/user}}{{/chat}}")
+            .unwrap();
+        let summaries = summary.usage().clone();
+        let review = LLMPipeline::new(&Reporting)
+            .load_template("review", "{{#chat}}{{#system}}Review this summary.{{/system}}{{/chat}}")
+            .unwrap();
+        let pricing = PricingTable::new().with_price("gpt-4", 0.03, 0.06);
+        let pipeline = SequentialPipeline::new()
+            .link(summary)
+            .link(review)
+            .with_usage_tracker(UsageTracker::new().with_pricing(pricing));
+        let result = pipeline.execute("review").await.unwrap();
+        assert_eq!(result.usage().len(), 2);
+        assert_eq!(summaries.records()[0].step, "review");
+
+        let steps = pipeline.usage().by_step();
+        assert_eq!(
+            steps.iter().map(|(step, _)| step.as_str()).collect::<Vec<_>>(),
+            vec!["0.review", "1.review"]
+        );
+        assert_eq!(steps[1].1.total_tokens(), 1500);
+        assert!((pipeline.usage().total().cost - 0.12).abs() < 1e-9);
+    }
 }
@@ -1,16 +1,30 @@
+use super::budget::{Budget, BudgetExceeded, BudgetTracker};
+use super::collector::{AuditSink, MemorySink, StreamCollector, UsageSink};
+use super::context::{MergeStrategy, PipelineContext};
+use super::describe::PipelineDescription;
+use super::grounding::{GroundingVerifier, GROUNDING_METADATA};
+use super::usage::{UsageRecord, UsageTracker};
+use super::validated::{in_language, Validator};
 use super::Pipeline;
-use super::PipelineResult;
-use crate::llm::LLM;
+use super::{parse_json, PipelineResult};
+use crate::error::OrcaError;
+use crate::llm::{LLMResponse, RequestMetadata, TokenStream, LLM};
 use crate::memory::Memory;
+use crate::prompt::budget::{PromptParts, TokenBudget};
+use crate::prompt::chat::{ChatPrompt, Message, Role};
+use crate::prompt::compress::Compressor;
 use crate::prompt::context::Context;
-use crate::prompt::TemplateEngine;
+use crate::prompt::{estimate_tokens, Prompt, TemplateEngine};
+use crate::record::language::{detect_language, language_name};
 use crate::record::Record;
 
-use anyhow::Result;
+use anyhow::{Context as _, Result};
 use serde_json::Value as JsonValue;
-use std::collections::HashMap;
+use std::borrow::Cow;
 use std::sync::Arc;
 use tokio::sync::Mutex;
+use tracing::field::Empty;
+use tracing::Span;
 
 /// Represents the simples pipeline for a Large Language Model (LLM).
 ///
@@ -33,9 +47,63 @@ pub struct LLMPipeline<M> {
 
     /// The context containing key-value pairs which the `prompt`
     /// template engine might use to render the final prompt.
-    context: HashMap<String, JsonValue>,
+    context: PipelineContext,
+
+    /// System message injected ahead of the rendered template and memory at execution time.
+    system_prompt: Option<String>,
+
+    /// Messages placed after the system prompt and before the rendered template, e.g. few-shot examples.
+    prefix_messages: Vec<Message>,
+
+    /// Whether the system prompt and prefix messages are marked as a cacheable prompt prefix.
+    cache_prefix: bool,
+
+    /// Whether the response must be valid JSON.
+    expect_json: bool,
+
+    /// Whether the response must be written in the language of the question.
+    reply_in_language: bool,
+
+    /// Budget that the system prompt, memory, documents and question are trimmed to before rendering.
+    token_budget: Option<TokenBudget>,
+
+    /// Compressor that the retrieved documents are compressed with before rendering.
+    compressor: Option<Compressor>,
+
+    /// Metadata sent with every LLM request made by the pipeline and recorded in its results.
+    request_metadata: RequestMetadata,
+
+    /// Limits of the tokens, cost and wall time of each execution.
+    budget: Option<Budget>,
+
+    /// Cheaper model the calls going over the budget are made with, instead of failing.
+    budget_fallback: Option<Arc<dyn LLM>>,
+
+    /// Whether every variable read by the template must have a non-empty value in the context.
+    strict_variables: bool,
+
+    /// Tokens used by the LLM calls of the executions, by template.
+    usage: UsageTracker,
+
+    /// Verifier checking that the answers are supported by the documents of the context.
+    grounding: Option<Arc<GroundingVerifier>>,
 }
 
+/// Instruction added to the system prompt when the pipeline expects JSON.
+static JSON_INSTRUCTION: &str = "Respond only with valid JSON.";
+
+/// Number of times the LLM is asked to correct a response that is not valid JSON.
+const JSON_RETRIES: usize = 2;
+
+/// Number of times the LLM is asked to correct a response that is not in the language of the question.
+const LANGUAGE_RETRIES: usize = 1;
+
+/// Context key holding the retrieved documents, trimmed when the pipeline has a token budget.
+pub const DOCUMENTS_KEY: &str = "documents";
+
+/// Context key holding the user question, trimmed when the pipeline has a token budget.
+pub const QUESTION_KEY: &str = "question";
+
 impl<M: LLM + Clone + 'static> LLMPipeline<M> {
     /// Creates a new LLMPipeline given an LLM and a prompt template.
     ///
@@ -56,7 +124,20 @@ impl<M: LLM + Clone + 'static> LLMPipeline<M> {
             llm: Arc::new(llm.clone()),
             template_engine: TemplateEngine::new(),
             memory: None,
-            context: HashMap::new(),
+            context: PipelineContext::new(),
+            system_prompt: None,
+            prefix_messages: Vec::new(),
+            cache_prefix: false,
+            expect_json: false,
+            reply_in_language: false,
+            token_budget: None,
+            compressor: None,
+            request_metadata: RequestMetadata::default(),
+            budget: None,
+            budget_fallback: None,
+            strict_variables: false,
+            usage: UsageTracker::new(),
+            grounding: None,
         }
     }
 
@@ -143,6 +224,517 @@ impl<M: LLM + Clone + 'static> LLMPipeline<M> {
         self
     }
 
+    /// Change the memory used by the LLMPipeline to one shared with its owner, e.g. the memory of a
+    /// session handed out by a `SessionManager`.
+    pub fn load_shared_memory(mut self, memory: Arc<Mutex<dyn Memory>>) -> Self {
+        self.memory = Some(memory);
+        self
+    }
+
+    /// Sets a system prompt that is injected ahead of any template or chat memory when the pipeline
+    /// is executed, so individual templates do not need to declare it.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use orca_core::llm::openai::OpenAI;
+    /// use orca_core::pipeline::simple::LLMPipeline;
+    ///
+    /// let client = OpenAI::new();
+    /// let pipeline = LLMPipeline::new(&client)
+    ///     .load_template("my prompt", "{{#chat}}{{#user}}Hello, LLM!{{/user}}{{/chat}}")
+    ///     .unwrap()
+    ///     .with_system_prompt("You are a helpful assistant.");
+    /// ```
+    pub fn with_system_prompt(mut self, system_prompt: &str) -> Self {
+        self.system_prompt = Some(system_prompt.to_string());
+        self
+    }
+
+    /// Sets messages that are placed after the system prompt and before the rendered template when
+    /// the pipeline is executed. This is useful for seeding few-shot examples.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use orca_core::llm::openai::OpenAI;
+    /// use orca_core::pipeline::simple::LLMPipeline;
+    /// use orca_core::prompt::chat::{Message, Role};
+    ///
+    /// let client = OpenAI::new();
+    /// let pipeline = LLMPipeline::new(&client)
+    ///     .load_template("my prompt", "{{#chat}}{{#user}}What is the capital of Germany?{{/user}}{{/chat}}")
+    ///     .unwrap()
+    ///     .with_prefix_messages(vec![
+    ///         Message::new(Role::User, "What is the capital of France?"),
+    ///         Message::new(Role::Assistant, "Paris"),
+    ///     ]);
+    /// ```
+    pub fn with_prefix_messages(mut self, messages: Vec<Message>) -> Self {
+        self.prefix_messages = messages;
+        self
+    }
+
+    /// Marks the system prompt and prefix messages as a cacheable prompt prefix, so that providers
+    /// supporting prompt caching can reuse it across executions.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use orca_core::llm::openai::OpenAI;
+    /// use orca_core::pipeline::simple::LLMPipeline;
+    ///
+    /// let client = OpenAI::new();
+    /// let pipeline = LLMPipeline::new(&client)
+    ///     .load_template("my prompt", "{{#chat}}{{#user}}{{question}}{{/user}}{{/chat}}")
+    ///     .unwrap()
+    ///     .with_system_prompt("You answer questions about a long reference manual: ...")
+    ///     .with_prefix_cache();
+    /// ```
+    pub fn with_prefix_cache(mut self) -> Self {
+        self.cache_prefix = true;
+        self
+    }
+
+    /// Requires the response to be valid JSON. The provider's JSON mode is used when the LLM supports
+    /// it. In any case, the system prompt instructs the model to respond with JSON, and responses that
+    /// are not valid JSON are sent back to the model for correction, up to two times.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use orca_core::llm::openai::OpenAI;
+    /// use orca_core::pipeline::simple::LLMPipeline;
+    ///
+    /// let client = OpenAI::new();
+    /// let pipeline = LLMPipeline::new(&client)
+    ///     .load_template("my prompt", "{{#chat}}{{#user}}List three European capitals.{{/user}}{{/chat}}")
+    ///     .unwrap()
+    ///     .expect_json();
+    /// ```
+    pub fn expect_json(mut self) -> Self {
+        if let Some(llm) = self.llm.json_mode() {
+            self.llm = Arc::new(llm);
+        }
+        self.expect_json = true;
+        self
+    }
+
+    /// Requires the response to be written in the language of the question, e.g. for a multilingual
+    /// assistant over an English corpus. The language is detected from the `question` context key, or
+    /// else from the last user message, and the system prompt instructs the model to respond in it.
+    /// Responses detected in another language are sent back to the model for correction once, and the
+    /// detected language is recorded in the `language` metadata of the result. Questions whose language
+    /// cannot be detected reliably are answered as usual.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use orca_core::llm::openai::OpenAI;
+    /// use orca_core::pipeline::simple::LLMPipeline;
+    ///
+    /// let client = OpenAI::new();
+    /// let pipeline = LLMPipeline::new(&client)
+    ///     .load_template("answer", "{{#chat}}{{#user}}{{question}}{{/user}}{{/chat}}")
+    ///     .unwrap()
+    ///     .with_reply_language();
+    /// ```
+    pub fn with_reply_language(mut self) -> Self {
+        self.reply_in_language = true;
+        self
+    }
+
+    /// Fits the prompt into the context window of the model. Before rendering, the system prompt, the
+    /// chat memory, the documents in the `documents` context key and the question in the `question`
+    /// context key are trimmed to their share of the budget, so that long documents or conversations
+    /// do not overflow the context window. The full conversation is still saved in memory.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use orca_core::llm::openai::OpenAI;
+    /// use orca_core::pipeline::simple::LLMPipeline;
+    /// use orca_core::prompt::budget::TokenBudget;
+    ///
+    /// let client = OpenAI::new().with_max_tokens(1024);
+    /// let template = "{{#chat}}{{#user}}{{#each documents}}{{this}}\n{{/each}}\nQuestion: {{question}}{{/user}}{{/chat}}";
+    /// let pipeline = LLMPipeline::new(&client)
+    ///     .load_template("qa", template)
+    ///     .unwrap()
+    ///     .with_token_budget(TokenBudget::new(16385).with_reserved_tokens(1024 + 100));
+    /// ```
+    pub fn with_token_budget(mut self, token_budget: TokenBudget) -> Self {
+        self.token_budget = Some(token_budget);
+        self
+    }
+
+    /// Compresses the retrieved documents, i.e. the `documents` key of the context, before the prompt is
+    /// rendered, dropping their least informative words to cut the cost of long-context calls. With a
+    /// token budget, the compressed documents are then trimmed to their share.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use orca_core::llm::openai::OpenAI;
+    /// use orca_core::pipeline::simple::LLMPipeline;
+    /// use orca_core::prompt::compress::Compressor;
+    ///
+    /// let client = OpenAI::new();
+    /// let template = "{{#chat}}{{#user}}{{#each documents}}{{this}}\n{{/each}}\nQuestion: {{question}}{{/user}}{{/chat}}";
+    /// let pipeline = LLMPipeline::new(&client)
+    ///     .load_template("qa", template)
+    ///     .unwrap()
+    ///     .with_compression(Compressor::new(0.6).unwrap());
+    /// ```
+    pub fn with_compression(mut self, compressor: Compressor) -> Self {
+        self.compressor = Some(compressor);
+        self
+    }
+
+    /// Sets the metadata sent with the LLM requests of the pipeline, e.g. the end user id for abuse
+    /// attribution. The metadata is also logged and recorded in the metadata of the pipeline results.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use orca_core::llm::openai::OpenAI;
+    /// use orca_core::llm::RequestMetadata;
+    /// use orca_core::pipeline::simple::LLMPipeline;
+    ///
+    /// let client = OpenAI::new();
+    /// let pipeline = LLMPipeline::new(&client)
+    ///     .load_template("my prompt", "{{#chat}}{{#user}}Hello, LLM!{{/user}}{{/chat}}")
+    ///     .unwrap()
+    ///     .with_request_metadata(RequestMetadata::new().with_user_id("user-42").with_trace_id("req-7f3a"));
+    /// ```
+    pub fn with_request_metadata(mut self, request_metadata: RequestMetadata) -> Self {
+        self.request_metadata = request_metadata;
+        self
+    }
+
+    /// Bounds the tokens, cost and wall time of each execution. An execution stops with a
+    /// `OrcaError::Budget` error before a call that would go over the token or cost limit, unless the
+    /// pipeline has a fallback model, and when it runs out of time. The consumption of the budget is
+    /// recorded in the `budget` metadata of the result.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use orca_core::llm::openai::OpenAI;
+    /// use orca_core::pipeline::budget::Budget;
+    /// use orca_core::pipeline::simple::LLMPipeline;
+    /// use std::time::Duration;
+    ///
+    /// let client = OpenAI::new().with_model("gpt-4");
+    /// let pipeline = LLMPipeline::new(&client)
+    ///     .load_template("my prompt", "{{#chat}}{{#user}}Hello, LLM!{{/user}}{{/chat}}")
+    ///     .unwrap()
+    ///     .with_budget(Budget {
+    ///         max_tokens: Some(4000),
+    ///         max_cost: Some(0.10),
+    ///         max_wall_time: Some(Duration::from_secs(30)),
+    ///         cost_per_1k_tokens: 0.03,
+    ///     })
+    ///     .with_budget_fallback(&OpenAI::new().with_model("gpt-3.5-turbo-1106"));
+    /// ```
+    pub fn with_budget(mut self, budget: Budget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Sets a cheaper model that calls going over the token or cost limit of the budget are made
+    /// with, instead of stopping the execution.
+    pub fn with_budget_fallback<F: LLM + Clone + 'static>(mut self, llm: &F) -> Self {
+        self.budget_fallback = Some(Arc::new(llm.clone()));
+        self
+    }
+
+    /// Fails the execution before calling the model when a variable read by the template is missing from
+    /// the context or empty, i.e. null, blank or an empty list or object, naming the variables. Without it,
+    /// only templates rendering to a prompt without any content fail.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use orca_core::llm::openai::OpenAI;
+    /// use orca_core::pipeline::simple::LLMPipeline;
+    ///
+    /// let client = OpenAI::new();
+    /// let pipeline = LLMPipeline::new(&client)
+    ///     .load_template("answer", "{{#chat}}{{#user}}{{question}}{{/user}}{{/chat}}")
+    ///     .unwrap()
+    ///     .with_strict_variables();
+    /// ```
+    pub fn with_strict_variables(mut self) -> Self {
+        self.strict_variables = true;
+        self
+    }
+
+    /// Sets the tracker the tokens used by the LLM calls of the executions are recorded in, under the name
+    /// of the template executed, e.g. to price them or to account for several pipelines in one tracker.
+    /// Clones of the pipeline record their calls in the same tracker.
+    pub fn with_usage_tracker(mut self, usage: UsageTracker) -> Self {
+        self.usage = usage;
+        self
+    }
+
+    /// Verifies every answer against the documents in the `documents` key of the context, recording the
+    /// [`GroundingReport`](super::grounding::GroundingReport) in the `grounding` metadata of the result.
+    /// The tokens of the verification are recorded in the usage of the result, but are not counted in the
+    /// budget. A failed verification is logged and leaves the result without report.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use orca_core::llm::openai::OpenAI;
+    /// use orca_core::pipeline::grounding::GroundingVerifier;
+    /// use orca_core::pipeline::simple::LLMPipeline;
+    ///
+    /// let client = OpenAI::new().with_model("gpt-4");
+    /// let template = "{{#chat}}{{#user}}{{#each documents}}{{this}}\n{{/each}}\nQuestion: {{question}}{{/user}}{{/chat}}";
+    /// let pipeline = LLMPipeline::new(&client)
+    ///     .load_template("qa", template)
+    ///     .unwrap()
+    ///     .with_grounding(GroundingVerifier::new(&OpenAI::new().with_model("gpt-3.5-turbo-1106")));
+    /// ```
+    pub fn with_grounding(mut self, verifier: GroundingVerifier) -> Self {
+        self.grounding = Some(Arc::new(verifier));
+        self
+    }
+
+    /// Tokens used by the LLM calls of the executions so far.
+    pub fn usage(&self) -> &UsageTracker {
+        &self.usage
+    }
+
+    /// Generates a response within the budget of the execution, if the pipeline has one.
+    #[tracing::instrument(name = "llm.generate", skip_all, fields(pipeline = %self.name, tokens = Empty))]
+    async fn generate(
+        &self,
+        prompt: Box<dyn Prompt>,
+        metadata: &RequestMetadata,
+        tracker: &mut Option<BudgetTracker>,
+        usage: &mut Vec<UsageRecord>,
+    ) -> Result<LLMResponse> {
+        let estimate = estimate_tokens(&prompt.to_string()) as u32;
+        let Some(tracker) = tracker else {
+            let response = self.llm.generate_with_metadata(prompt, metadata).await?;
+            if let Some(tokens) = response.total_tokens() {
+                Span::current().record("tokens", tokens);
+            }
+            usage.push(usage_record(self.llm.as_ref(), &response, estimate));
+            return Ok(response);
+        };
+        let llm: &dyn LLM = match (tracker.check(estimate), &self.budget_fallback) {
+            (Ok(()), _) => self.llm.as_ref(),
+            (Err(exceeded @ BudgetExceeded::WallTime { .. }), _) => return Err(OrcaError::Budget(exceeded).into()),
+            (Err(exceeded), Some(fallback)) => {
+                log::warn!("pipeline={} {}, degrading to the fallback model", self.name, exceeded);
+                tracker.degrade();
+                fallback.as_ref()
+            }
+            (Err(exceeded), None) => return Err(OrcaError::Budget(exceeded).into()),
+        };
+        let response = tracker.run(llm.generate_with_metadata(prompt, metadata)).await.map_err(OrcaError::from)??;
+        usage.push(usage_record(llm, &response, estimate));
+        let tokens =
+            response.total_tokens().unwrap_or_else(|| estimate + estimate_tokens(&response.to_string()) as u32);
+        tracker.record(tokens);
+        Span::current().record("tokens", tokens);
+        Ok(response)
+    }
+
+    /// Prepends the system prompt and prefix messages to the given prompt. A prompt that is not a chat
+    /// is treated as a single user message.
+    fn with_prefix(&self, prompt: Box<dyn Prompt>) -> Box<dyn Prompt> {
+        self.with_system_prefix(self.system_prompt.as_deref(), prompt)
+    }
+
+    /// Prepends the given system prompt and the prefix messages to the given prompt.
+    fn with_system_prefix(&self, system_prompt: Option<&str>, prompt: Box<dyn Prompt>) -> Box<dyn Prompt> {
+        let language_instruction = self
+            .reply_language(prompt.as_ref())
+            .map(|language| format!("Respond in {}.", language_name(&language).unwrap_or(&language)));
+        let system: Vec<&str> = [
+            system_prompt,
+            language_instruction.as_deref(),
+            self.expect_json.then_some(JSON_INSTRUCTION),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if system.is_empty() && self.prefix_messages.is_empty() {
+            return prompt;
+        }
+
+        let mut messages = Vec::new();
+        if !system.is_empty() {
+            messages.push(Message::new(Role::System, &system.join("\n")));
+        }
+        messages.extend(self.prefix_messages.iter().cloned());
+        if self.cache_prefix {
+            if let Some(last) = messages.pop() {
+                messages.push(last.with_cache_control());
+            }
+        }
+        match prompt.to_chat() {
+            Ok(chat) => messages.extend(chat.to_vec()),
+            Err(_) => messages.push(Message::new(Role::User, &prompt.to_string())),
+        }
+        Box::new(ChatPrompt(messages))
+    }
+
+    /// Language the response must be written in, as an ISO 639-3 code, if the pipeline replies in the
+    /// language of the question and it can be detected. The question is the `question` context key, or
+    /// else the last user message of the prompt.
+    fn reply_language(&self, prompt: &dyn Prompt) -> Option<String> {
+        if !self.reply_in_language {
+            return None;
+        }
+        let question = match self.context.get(QUESTION_KEY).and_then(JsonValue::as_str) {
+            Some(question) => question.to_string(),
+            None => match prompt.to_chat() {
+                Ok(chat) => chat.to_vec().into_iter().rev().find(|message| message.role == Role::User)?.content,
+                Err(_) => prompt.to_string(),
+            },
+        };
+        detect_language(&question)
+    }
+
+    /// The context to render, with the retrieved documents compressed if the pipeline has a compressor.
+    fn rendered_context(&self) -> Result<Cow<'_, PipelineContext>> {
+        let Some(compressor) = &self.compressor else {
+            return Ok(Cow::Borrowed(&self.context));
+        };
+        let compressed = match self.context.get(DOCUMENTS_KEY) {
+            Some(JsonValue::Array(documents)) => JsonValue::Array(
+                documents
+                    .iter()
+                    .map(|document| match document {
+                        JsonValue::String(document) => Ok(JsonValue::String(compressor.compress(document)?)),
+                        document => Ok(document.clone()),
+                    })
+                    .collect::<Result<_>>()?,
+            ),
+            Some(JsonValue::String(document)) => JsonValue::String(compressor.compress(document)?),
+            _ => return Ok(Cow::Borrowed(&self.context)),
+        };
+        let mut context = self.context.clone();
+        context.set(DOCUMENTS_KEY, compressed)?;
+        Ok(Cow::Owned(context))
+    }
+
+    /// Renders the prompt with the system prompt, memory, documents and question trimmed to the budget.
+    async fn render_within_budget(
+        &self,
+        context: &PipelineContext,
+        budget: &TokenBudget,
+        target: &str,
+    ) -> Result<Box<dyn Prompt>> {
+        let mut context = context.clone();
+        let documents = documents(&context);
+        let question = context.get(QUESTION_KEY).and_then(JsonValue::as_str).unwrap_or_default().to_string();
+        let mut memory = match &self.memory {
+            Some(memory) => Some(memory.lock().await),
+            None => None,
+        };
+        let history = match memory.as_mut() {
+            Some(memory) => memory.memory().to_chat().map(|chat| chat.to_vec()).unwrap_or_default(),
+            None => Vec::new(),
+        };
+
+        let parts = budget.fit(PromptParts {
+            system: self.system_prompt.clone().unwrap_or_default(),
+            memory: history,
+            documents,
+            question,
+        });
+        match context.get(DOCUMENTS_KEY) {
+            Some(JsonValue::String(_)) => context.set(DOCUMENTS_KEY, parts.documents.concat())?,
+            Some(_) => context.set(DOCUMENTS_KEY, &parts.documents)?,
+            None => {}
+        }
+        if context.contains(QUESTION_KEY) {
+            context.set(QUESTION_KEY, &parts.question)?;
+        }
+        let prompt = self.render(target, &context)?;
+
+        let prompt: Box<dyn Prompt> = match memory.as_mut() {
+            Some(memory) => {
+                memory.memory().save(prompt.clone_prompt());
+                memory.flush().await?;
+                let mut messages = parts.memory;
+                match prompt.to_chat() {
+                    Ok(chat) => messages.extend(chat.to_vec()),
+                    Err(_) => messages.push(Message::new(Role::User, &prompt.to_string())),
+                }
+                Box::new(ChatPrompt(messages))
+            }
+            None => prompt,
+        };
+        let system_prompt = Some(parts.system.as_str()).filter(|system| !system.is_empty());
+        Ok(self.with_system_prefix(system_prompt, prompt))
+    }
+
+    /// Asks the LLM to correct responses that are not valid JSON.
+    async fn correct_json(
+        &self,
+        prompt: Box<dyn Prompt>,
+        mut response: LLMResponse,
+        tracker: &mut Option<BudgetTracker>,
+        usage: &mut Vec<UsageRecord>,
+    ) -> Result<LLMResponse> {
+        let mut chat = match prompt.to_chat() {
+            Ok(chat) => chat,
+            Err(_) => ChatPrompt(vec![Message::new(Role::User, &prompt.to_string())]),
+        };
+        for attempt in 1..=JSON_RETRIES {
+            let content = response.to_string();
+            let error = match parse_json::<JsonValue>(&content) {
+                Ok(_) => return Ok(response),
+                Err(e) => e,
+            };
+            log::debug!("Response is not valid JSON, retrying: {}", error);
+            chat.0.push(Message::new(Role::Assistant, &content));
+            chat.0.push(Message::new(
+                Role::User,
+                &format!("Your response was not valid JSON ({}). {}", error, JSON_INSTRUCTION),
+            ));
+            let metadata = self.request_metadata.for_attempt(attempt);
+            response = self.generate(Box::new(chat.clone()), &metadata, tracker, usage).await?;
+        }
+        parse_json::<JsonValue>(&response.to_string())
+            .with_context(|| format!("Response is not valid JSON after {} retries", JSON_RETRIES))?;
+        Ok(response)
+    }
+
+    /// Asks the LLM to correct responses that are not written in the given language.
+    async fn correct_language(
+        &self,
+        prompt: Box<dyn Prompt>,
+        mut response: LLMResponse,
+        language: &str,
+        tracker: &mut Option<BudgetTracker>,
+        usage: &mut Vec<UsageRecord>,
+    ) -> Result<LLMResponse> {
+        let guard = in_language(language);
+        let mut chat = match prompt.to_chat() {
+            Ok(chat) => chat,
+            Err(_) => ChatPrompt(vec![Message::new(Role::User, &prompt.to_string())]),
+        };
+        for attempt in 1..=LANGUAGE_RETRIES {
+            let content = response.to_string();
+            let feedback = match guard.validate(&content) {
+                Ok(()) => return Ok(response),
+                Err(feedback) => feedback,
+            };
+            log::debug!(
+                "Response is not in the language of the question, retrying: {}",
+                feedback
+            );
+            chat.0.push(Message::new(Role::Assistant, &content));
+            chat.0.push(Message::new(Role::User, &feedback.to_string()));
+            let metadata = self.request_metadata.for_attempt(attempt);
+            response = self.generate(Box::new(chat.clone()), &metadata, tracker, usage).await?;
+        }
+        guard.validate(&response.to_string()).with_context(|| {
+            format!(
+                "Response is not in the language of the question after {} retries",
+                LANGUAGE_RETRIES
+            )
+        })?;
+        Ok(response)
+    }
+
     /// Sets the context for the current pipeline execution using a given data structure.
     ///
     /// # Parameters
@@ -167,13 +759,7 @@ impl<M: LLM + Clone + 'static> LLMPipeline<M> {
     /// # }
     /// ```
     pub fn load_context(mut self, context: &Context) -> Result<Self> {
-        for (key, value) in context.as_object().iter() {
-            if !self.context.contains_key(key) {
-                self.context.insert(key.to_string(), value.clone());
-            } else {
-                return Err(anyhow::anyhow!("Context already contains a key with name {}", key));
-            }
-        }
+        self.context.merge(&context.into(), MergeStrategy::Error)?;
         Ok(self)
     }
 
@@ -183,36 +769,254 @@ impl<M: LLM + Clone + 'static> LLMPipeline<M> {
     /// - `name`: The key/name for the record content in the context.
     /// - `record`: The actual record to load.
     pub fn load_record(mut self, name: &str, record: Record) -> Result<Self> {
-        if !self.context.contains_key(name) {
-            self.context.insert(name.to_string(), JsonValue::String(record.content.to_string()));
+        if !self.context.contains(name) {
+            self.context.set(name, record.content.to_string())?;
         } else {
             return Err(anyhow::anyhow!("Context already contains a key with name {}", name));
         }
         Ok(self)
     }
-}
 
-#[async_trait::async_trait]
-impl<M: LLM + Clone + 'static> Pipeline for LLMPipeline<M> {
-    async fn execute(&self, target: &str) -> Result<PipelineResult> {
-        let prompt = self.template_engine.render_context(target, &self.context)?;
+    /// Executes the pipeline, streaming the response of the LLM as it is generated, see
+    /// `LLM::generate_stream`. The prompt is rendered as by `execute`, but the response is not checked:
+    /// the budget and the JSON and language corrections only apply to `execute`.
+    ///
+    /// Once the stream ends, or is dropped, the response is saved in the memory of the pipeline, its
+    /// estimated tokens recorded in the usage tracker and, if the pipeline has request metadata, logged
+    /// to the audit log, see `StreamCollector`. The response of a dropped stream is saved as far as it was
+    /// read.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use futures::StreamExt;
+    /// use orca_core::llm::openai::OpenAI;
+    /// use orca_core::pipeline::simple::LLMPipeline;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let pipeline = LLMPipeline::new(&OpenAI::new()).load_template("orcas", "Tell me about orcas.")?;
+    /// let mut stream = pipeline.execute_stream("orcas").await?;
+    /// while let Some(text) = stream.next().await {
+    ///     print!("{}", text?);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn execute_stream(&self, target: &str) -> Result<TokenStream> {
+        let prompt = self.prompt(target).await?;
+        let mut usage =
+            UsageSink::new(self.usage.clone(), target).with_prompt_tokens(estimate_tokens(&prompt.to_string()) as u32);
+        if let Some(model) = self.llm.config()["model"].as_str() {
+            usage = usage.with_model(model);
+        }
+        let mut collector = StreamCollector::new().with_sink(usage);
+        if let Some(memory) = &self.memory {
+            collector = collector.with_sink(MemorySink::new(memory.clone()));
+        }
+        if !self.request_metadata.is_empty() {
+            collector = collector.with_sink(AuditSink::new(&self.name, target, self.request_metadata.clone()));
+        }
+        let stream = self.llm.generate_stream(prompt).await?;
+        Ok(collector.collect(stream))
+    }
 
-        let response = if let Some(memory) = &self.memory {
+    /// Renders the prompt of a template with the context, memory and prefix of the pipeline.
+    async fn prompt(&self, target: &str) -> Result<Box<dyn Prompt>> {
+        let context = self.rendered_context()?;
+        if self.strict_variables {
+            self.check_variables(target, &context)?;
+        }
+        if let Some(budget) = &self.token_budget {
+            return self.render_within_budget(&context, budget, target).await;
+        }
+        let prompt = self.render(target, &context)?;
+        if let Some(memory) = &self.memory {
             let mut locked_memory = memory.lock().await; // Lock the memory
             let mem = locked_memory.memory();
             mem.save(prompt);
+            locked_memory.flush().await?;
+            let mem = locked_memory.memory();
             log::debug!("Memory: {}", mem);
-            self.llm.generate(mem.clone_prompt()).await?
+            Ok(self.with_prefix(mem.clone_prompt()))
         } else {
-            self.llm.generate(prompt.clone_prompt()).await?
+            Ok(self.with_prefix(prompt))
+        }
+    }
+
+    /// Renders a template with the given context, failing if the prompt has no content, e.g. because the
+    /// context sets none of the variables of the template.
+    fn render(&self, target: &str, context: &PipelineContext) -> Result<Box<dyn Prompt>> {
+        let prompt = self.template_engine.render_context(target, context)?;
+        let empty = match prompt.to_chat() {
+            Ok(chat) => chat.0.iter().all(|message| message.content.trim().is_empty() && message.images.is_empty()),
+            Err(_) => prompt.to_string().trim().is_empty(),
+        };
+        if !empty {
+            return Ok(prompt);
+        }
+        let missing = self.missing_variables(target, context);
+        let message = match missing.is_empty() {
+            true => "The rendered prompt is empty".to_string(),
+            false => format!(
+                "The rendered prompt is empty, the context has no value for: {}",
+                missing.join(", ")
+            ),
         };
+        Err(OrcaError::Template {
+            template: target.to_string(),
+            message,
+        }
+        .into())
+    }
+
+    /// Fails if a variable read by the template is missing from the context or empty.
+    fn check_variables(&self, target: &str, context: &PipelineContext) -> Result<()> {
+        let missing = self.missing_variables(target, context);
+        if missing.is_empty() {
+            return Ok(());
+        }
+        Err(OrcaError::Template {
+            template: target.to_string(),
+            message: format!("The context has no value for: {}", missing.join(", ")),
+        }
+        .into())
+    }
+
+    /// Variables read by the template that are missing from the context or empty.
+    fn missing_variables(&self, target: &str, context: &PipelineContext) -> Vec<String> {
+        let variables = self.template_engine.variables(target).unwrap_or_default();
+        variables
+            .into_iter()
+            .filter(|variable| match context.get(variable) {
+                None | Some(JsonValue::Null) => true,
+                Some(JsonValue::String(value)) => value.trim().is_empty(),
+                Some(JsonValue::Array(values)) => values.is_empty(),
+                Some(JsonValue::Object(values)) => values.is_empty(),
+                Some(_) => false,
+            })
+            .collect()
+    }
+
+    /// Executes the pipeline in a `pipeline.execute` span, the root of the trace of the execution.
+    #[tracing::instrument(name = "pipeline.execute", skip(self), fields(pipeline = %self.name))]
+    async fn run(&self, target: &str) -> Result<PipelineResult> {
+        let mut tracker = self.budget.map(BudgetTracker::new);
+        let prompt = self.prompt(target).await?;
+
+        let metadata = &self.request_metadata;
+        if !metadata.is_empty() {
+            log::info!(
+                target: "orca::audit",
+                "pipeline={} template={} user_id={} trace_id={} idempotency_key={}",
+                self.name,
+                target,
+                metadata.user_id.as_deref().unwrap_or("-"),
+                metadata.trace_id.as_deref().unwrap_or("-"),
+                metadata.idempotency_key.as_deref().unwrap_or("-"),
+            );
+        }
+        let language = self.reply_language(prompt.as_ref());
+        let mut usage = Vec::new();
+        let response = async {
+            let mut response = self.generate(prompt.clone_prompt(), metadata, &mut tracker, &mut usage).await?;
+            if self.expect_json {
+                response = self.correct_json(prompt.clone_prompt(), response, &mut tracker, &mut usage).await?;
+            }
+            if let Some(language) = &language {
+                response = self.correct_language(prompt, response, language, &mut tracker, &mut usage).await?;
+            }
+            Ok::<_, anyhow::Error>(response)
+        }
+        .await;
+        let mut grounding = None;
+        if let (Ok(response), Some(verifier)) = (&response, &self.grounding) {
+            let documents = documents(self.rendered_context()?.as_ref());
+            match verifier.verify_with_usage(&response.to_string(), &documents, &mut usage).await {
+                Ok(report) => grounding = Some(report),
+                Err(e) => log::warn!(
+                    "pipeline={} Failed to verify the grounding of the answer: {:#}",
+                    self.name,
+                    e
+                ),
+            }
+        }
+        // The calls of failed executions are tracked too, as they are billed all the same.
+        let usage = usage.into_iter().map(|record| record.with_step(target)).collect::<Vec<_>>();
+        self.usage.record(usage.clone());
+
+        let mut result = PipelineResult::new(self.name.clone()).with_llm_response(response?).with_usage(usage);
+        if let Some(language) = language {
+            result = result.with_metadata("language", language);
+        }
+        for (key, value) in [
+            ("user_id", &metadata.user_id),
+            ("trace_id", &metadata.trace_id),
+            ("idempotency_key", &metadata.idempotency_key),
+        ] {
+            if let Some(value) = value {
+                result = result.with_metadata(key, value.as_str());
+            }
+        }
+        if let Some(tracker) = &tracker {
+            result = result.with_metadata("budget", tracker.usage().to_json());
+        }
+        if let Some(report) = grounding {
+            result = result.with_metadata(GROUNDING_METADATA, serde_json::to_value(report)?);
+        }
+        Ok(result)
+    }
+}
 
-        Ok(PipelineResult::new(self.name.clone()).with_llm_response(response))
+/// Documents in the `documents` key of a context, as text.
+fn documents(context: &PipelineContext) -> Vec<String> {
+    match context.get(DOCUMENTS_KEY) {
+        Some(JsonValue::Array(documents)) => documents
+            .iter()
+            .map(|document| match document {
+                JsonValue::String(document) => document.clone(),
+                document => document.to_string(),
+            })
+            .collect(),
+        Some(JsonValue::String(document)) => vec![document.clone()],
+        _ => Vec::new(),
+    }
+}
+
+/// Record of the tokens used by a call, estimated if the LLM does not report them. The step is set by the
+/// execution.
+pub(crate) fn usage_record(llm: &dyn LLM, response: &LLMResponse, prompt_tokens: u32) -> UsageRecord {
+    let config = llm.config();
+    let model = config["model"].as_str();
+    match response.usage() {
+        Some(usage) => UsageRecord::new("", usage, model),
+        None => UsageRecord::estimated("", prompt_tokens, estimate_tokens(&response.to_string()) as u32, model),
+    }
+}
+
+#[async_trait::async_trait]
+impl<M: LLM + Clone + 'static> Pipeline for LLMPipeline<M> {
+    async fn execute(&self, target: &str) -> Result<PipelineResult> {
+        self.run(target).await
     }
 
     fn template_engine(&mut self) -> &mut TemplateEngine {
         &mut self.template_engine
     }
+
+    fn context(&mut self) -> Option<&mut PipelineContext> {
+        Some(&mut self.context)
+    }
+
+    fn describe(&self) -> PipelineDescription {
+        let mut description = PipelineDescription::new("LLMPipeline", &self.name)
+            .with_templates(&self.template_engine)
+            .with_model(self.llm.config());
+        // The memory is locked while the pipeline executes, in which case its kind is unknown.
+        if let Some(memory) = self.memory.as_ref().and_then(|memory| memory.try_lock().ok()) {
+            description = description.with_memory(memory.kind());
+        }
+        description
+    }
 }
 
 impl<M: LLM + Clone + 'static> Clone for LLMPipeline<M> {
@@ -223,6 +1027,19 @@ impl<M: LLM + Clone + 'static> Clone for LLMPipeline<M> {
             template_engine: self.template_engine.clone(),
             memory: self.memory.clone(),
             context: self.context.clone(),
+            system_prompt: self.system_prompt.clone(),
+            prefix_messages: self.prefix_messages.clone(),
+            cache_prefix: self.cache_prefix,
+            expect_json: self.expect_json,
+            reply_in_language: self.reply_in_language,
+            token_budget: self.token_budget.clone(),
+            compressor: self.compressor.clone(),
+            request_metadata: self.request_metadata.clone(),
+            budget: self.budget,
+            budget_fallback: self.budget_fallback.clone(),
+            strict_variables: self.strict_variables,
+            usage: self.usage.clone(),
+            grounding: self.grounding.clone(),
         }
     }
 }
@@ -231,6 +1048,7 @@ impl<M: LLM + Clone + 'static> Clone for LLMPipeline<M> {
 mod test {
 
     use super::*;
+    use crate::pipeline::grounding::GroundingReport;
     use crate::{
         llm::openai::OpenAI,
         memory,
@@ -323,4 +1141,316 @@ mod test {
         let res = pipeline.execute("name").await.unwrap().content();
         assert!(res.to_lowercase().contains("orca"));
     }
+
+    #[test]
+    fn test_with_prefix() {
+        let client = OpenAI::new();
+        let pipeline = LLMPipeline::new(&client)
+            .with_system_prompt("You are a geography expert.")
+            .with_prefix_messages(vec![
+                Message::new(Role::User, "What is the capital of France?"),
+                Message::new(Role::Assistant, "Paris"),
+            ]);
+
+        let prompt = pipeline.with_prefix(Box::new("What is the capital of Germany?".to_string()));
+        assert_eq!(
+            prompt.to_chat().unwrap(),
+            ChatPrompt(vec![
+                Message::new(Role::System, "You are a geography expert."),
+                Message::new(Role::User, "What is the capital of France?"),
+                Message::new(Role::Assistant, "Paris"),
+                Message::new(Role::User, "What is the capital of Germany?"),
+            ])
+        );
+    }
+
+    /// LLM that answers with prose the first time and with JSON afterwards.
+    #[derive(Clone, Default)]
+    struct EventuallyJson {
+        calls: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl LLM for EventuallyJson {
+        async fn generate(&self, prompt: Box<dyn Prompt>) -> Result<LLMResponse> {
+            let chat = prompt.to_chat().unwrap();
+            assert_eq!(chat.to_vec()[0], Message::new(Role::System, JSON_INSTRUCTION));
+            let response = match self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+                0 => "The capital of France is Paris.".to_string(),
+                _ => r#"```json
+                {"capital": "Paris"}
+                ```"#
+                    .to_string(),
+            };
+            Ok(LLMResponse::Quantized(response))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_expect_json() {
+        let llm = EventuallyJson::default();
+        let pipeline = LLMPipeline::new(&llm)
+            .load_template("capital", "What is the capital of France?")
+            .unwrap()
+            .expect_json();
+        let result = pipeline.execute("capital").await.unwrap();
+        assert_eq!(
+            result.json::<JsonValue>().unwrap(),
+            serde_json::json!({"capital": "Paris"})
+        );
+        assert_eq!(llm.calls.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    /// LLM that records the last prompt it was given.
+    #[derive(Clone, Default)]
+    struct Recorder {
+        prompt: Arc<std::sync::Mutex<Option<ChatPrompt>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl LLM for Recorder {
+        async fn generate(&self, prompt: Box<dyn Prompt>) -> Result<LLMResponse> {
+            *self.prompt.lock().unwrap() = Some(prompt.to_chat()?);
+            Ok(LLMResponse::Quantized("ok".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_token_budget() {
+        let llm = Recorder::default();
+        let history = (0..10).map(|i| Message::new(Role::User, &format!("{:0>40}", i))).collect();
+        let mut pipeline = LLMPipeline::new(&llm)
+            .load_template(
+                "qa",
+                "{{#chat}}{{#user}}{{#each documents}}{{this}};{{/each}}{{question}}{{/user}}{{/chat}}",
+            )
+            .unwrap()
+            .with_system_prompt("Answer from the documents.")
+            .load_memory(memory::ChatBuffer::from_chat(&ChatPrompt(history)))
+            .with_token_budget(TokenBudget::new(100).with_ratios(0.1, 0.4, 0.4, 0.1));
+        pipeline.context().unwrap().set(DOCUMENTS_KEY, vec!["a".repeat(100), "b".repeat(400)]).unwrap();
+        pipeline.context().unwrap().set(QUESTION_KEY, "What?").unwrap();
+        pipeline.execute("qa").await.unwrap();
+
+        let messages = llm.prompt.lock().unwrap().take().unwrap().to_vec();
+        assert_eq!(messages[0], Message::new(Role::System, "Answer from the documents."));
+        assert_eq!(messages.len(), 1 + 4 + 1);
+        assert_eq!(messages[1].content, format!("{:0>40}", 6));
+        let user = &messages.last().unwrap().content;
+        assert!(user.starts_with(&format!("{};{};", "a".repeat(100), "b".repeat(80))));
+        assert!(user.ends_with("What?"));
+    }
+
+    #[tokio::test]
+    async fn test_compression() {
+        let llm = Recorder::default();
+        let mut pipeline = LLMPipeline::new(&llm)
+            .load_template(
+                "qa",
+                "{{#chat}}{{#user}}{{#each documents}}{{this}};{{/each}}{{/user}}{{/chat}}",
+            )
+            .unwrap()
+            .with_compression(Compressor::new(0.5).unwrap().with_min_words(0));
+        let documents = vec!["the orcas of the Salish Sea", "they hunt the Chinook salmon"];
+        pipeline.context().unwrap().set(DOCUMENTS_KEY, &documents).unwrap();
+        pipeline.execute("qa").await.unwrap();
+
+        let messages = llm.prompt.lock().unwrap().take().unwrap().to_vec();
+        assert_eq!(messages[0].content, "orcas Salish Sea;hunt Chinook salmon;");
+        assert_eq!(
+            pipeline.context().unwrap().get(DOCUMENTS_KEY).unwrap(),
+            &serde_json::json!(documents)
+        );
+    }
+
+    /// LLM that answers in English the first time and in the requested language afterwards.
+    #[derive(Clone, Default)]
+    struct EventuallySpanish {
+        calls: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl LLM for EventuallySpanish {
+        async fn generate(&self, prompt: Box<dyn Prompt>) -> Result<LLMResponse> {
+            let chat = prompt.to_chat().unwrap();
+            assert_eq!(chat.to_vec()[0], Message::new(Role::System, "Respond in Spanish."));
+            let response = match self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+                0 => "The orca is the largest member of the dolphin family.",
+                _ => "La orca es el miembro más grande de la familia de los delfines.",
+            };
+            Ok(LLMResponse::Quantized(response.to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reply_language() {
+        let llm = EventuallySpanish::default();
+        let mut pipeline = LLMPipeline::new(&llm)
+            .load_template("answer", "{{#chat}}{{#user}}{{question}}{{/user}}{{/chat}}")
+            .unwrap()
+            .with_reply_language();
+        pipeline
+            .context()
+            .unwrap()
+            .set(
+                QUESTION_KEY,
+                "¿Cuál es el miembro más grande de la familia de los delfines?",
+            )
+            .unwrap();
+        let result = pipeline.execute("answer").await.unwrap();
+        assert_eq!(
+            result.content(),
+            "La orca es el miembro más grande de la familia de los delfines."
+        );
+        assert_eq!(result.metadata()["language"], "spa");
+        assert_eq!(llm.calls.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_request_metadata() {
+        let llm = Recorder::default();
+        let pipeline = LLMPipeline::new(&llm)
+            .load_template("hello", "{{#chat}}{{#user}}Hello{{/user}}{{/chat}}")
+            .unwrap()
+            .with_request_metadata(RequestMetadata::new().with_user_id("user-42").with_trace_id("trace-1"));
+        let result = pipeline.execute("hello").await.unwrap();
+        assert_eq!(result.metadata()["user_id"], "user-42");
+        assert_eq!(result.metadata()["trace_id"], "trace-1");
+        assert!(!result.metadata().contains_key("idempotency_key"));
+    }
+
+    #[tokio::test]
+    async fn test_usage() {
+        let llm = EventuallyJson::default();
+        let pipeline = LLMPipeline::new(&llm).load_template("capital", "What is the capital of France?").unwrap();
+        let result = pipeline.expect_json().execute("capital").await.unwrap();
+
+        // Both the answer and its correction are recorded, with estimated tokens.
+        assert_eq!(result.usage().len(), 2);
+        assert!(result.usage().iter().all(|record| record.estimated && record.step == "capital"));
+        assert_eq!(result.usage()[0].model, "unknown");
+    }
+
+    /// LLM streaming the words of the prompt.
+    #[derive(Clone)]
+    struct Words;
+
+    #[async_trait::async_trait]
+    impl LLM for Words {
+        async fn generate(&self, prompt: Box<dyn Prompt>) -> Result<LLMResponse> {
+            Ok(LLMResponse::Quantized(prompt.to_string()))
+        }
+
+        async fn generate_stream(&self, prompt: Box<dyn Prompt>) -> Result<TokenStream> {
+            let words = prompt.to_string().split_inclusive(' ').map(|word| Ok(word.to_string())).collect::<Vec<_>>();
+            Ok(Box::pin(futures::stream::iter(words)))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_execute_stream() {
+        use futures::TryStreamExt;
+
+        let mut pipeline = LLMPipeline::new(&Words).load_template("orcas", "Orcas are {{kind}}.").unwrap();
+        pipeline.context().unwrap().set("kind", "dolphins").unwrap();
+        let pieces = pipeline.execute_stream("orcas").await.unwrap().try_collect::<Vec<_>>().await.unwrap();
+        assert_eq!(pieces, vec!["Orcas ", "are ", "dolphins."]);
+        assert_eq!(pipeline.usage().records()[0].step, "orcas");
+
+        // The streamed response is saved in memory after the prompt.
+        let pipeline = LLMPipeline::new(&Words)
+            .load_template("orcas", "{{#chat}}{{#user}}Orcas are dolphins.{{/user}}{{/chat}}")
+            .unwrap()
+            .load_memory(memory::ChatBuffer::new());
+        pipeline.execute_stream("orcas").await.unwrap().try_collect::<Vec<_>>().await.unwrap();
+        let chat = pipeline.memory.as_ref().unwrap().lock().await.memory().to_chat().unwrap();
+        assert_eq!(chat.to_vec().len(), 2);
+        assert_eq!(chat.to_vec()[1].role, Role::Assistant);
+
+        // LLMs that do not stream send the whole response at once.
+        let llm = Recorder::default();
+        let pipeline = LLMPipeline::new(&llm).load_template("orcas", "Orcas").unwrap().with_system_prompt("Be brief.");
+        let pieces = pipeline.execute_stream("orcas").await.unwrap().try_collect::<Vec<_>>().await.unwrap();
+        assert_eq!(pieces, vec!["ok"]);
+        assert_eq!(llm.prompt.lock().unwrap().take().unwrap().to_vec().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_budget() {
+        let pipeline = |llm: &EventuallyJson| {
+            LLMPipeline::new(llm)
+                .load_template("capital", "What is the capital of France?")
+                .unwrap()
+                .expect_json()
+                .with_budget(Budget::new().with_max_tokens(60))
+        };
+        let error = pipeline(&EventuallyJson::default()).execute("capital").await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<OrcaError>(),
+            Some(OrcaError::Budget(BudgetExceeded::Tokens { limit: 60, .. }))
+        ));
+
+        // The fallback answers the correction, going over the budget.
+        let llm = EventuallyJson::default();
+        let pipeline = pipeline(&llm).with_budget_fallback(&llm);
+        let result = pipeline.execute("capital").await.unwrap();
+        assert_eq!(result.metadata()["budget"]["calls"], 2);
+        assert_eq!(result.metadata()["budget"]["degraded"], true);
+    }
+
+    #[tokio::test]
+    async fn test_empty_prompt() {
+        let llm = Recorder::default();
+        let pipeline = LLMPipeline::new(&llm)
+            .load_template("answer", "{{#chat}}{{#user}}{{question}}{{/user}}{{/chat}}")
+            .unwrap()
+            .with_system_prompt("Be brief.");
+        let error = pipeline.execute("answer").await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Template answer: The rendered prompt is empty, the context has no value for: question"
+        );
+        assert!(llm.prompt.lock().unwrap().is_none());
+
+        // With strict variables, any empty variable fails, even if the prompt has other content.
+        let template = "{{#chat}}{{#user}}{{question}} {{#each documents}}{{this}}{{/each}}{{/user}}{{/chat}}";
+        let mut pipeline = pipeline.load_template("documents", template).unwrap();
+        pipeline.context().unwrap().set("question", "Where do orcas live?").unwrap();
+        pipeline.context().unwrap().set("documents", Vec::<String>::new()).unwrap();
+        assert!(pipeline.execute("documents").await.is_ok());
+        let error = pipeline.with_strict_variables().execute("documents").await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<OrcaError>(),
+            Some(OrcaError::Template { message, .. }) if message == "The context has no value for: documents"
+        ));
+    }
+
+    /// LLM finding no sentence supported by the context.
+    #[derive(Clone)]
+    struct Unsupported;
+
+    #[async_trait::async_trait]
+    impl LLM for Unsupported {
+        async fn generate(&self, _prompt: Box<dyn Prompt>) -> Result<LLMResponse> {
+            Ok(LLMResponse::Quantized(r#"{"unsupported": [1]}"#.to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_grounding() {
+        let llm = Recorder::default();
+        let mut pipeline = LLMPipeline::new(&llm)
+            .load_template(
+                "qa",
+                "{{#chat}}{{#user}}{{#each documents}}{{this}};{{/each}}{{/user}}{{/chat}}",
+            )
+            .unwrap()
+            .with_grounding(GroundingVerifier::new(&Unsupported));
+        pipeline.context().unwrap().set(DOCUMENTS_KEY, vec!["Orcas live in pods."]).unwrap();
+        let result = pipeline.execute("qa").await.unwrap();
+
+        let report = GroundingReport::from_result(&result).unwrap();
+        assert_eq!((report.score, report.unsupported()), (0.0, vec!["ok"]));
+        assert_eq!(result.usage().len(), 2);
+    }
 }
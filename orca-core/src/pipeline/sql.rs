@@ -0,0 +1,370 @@
+//! Natural language to SQL.
+//!
+//! The `SqlPipeline` injects a database schema into a prompt, asks the LLM to write a query that
+//! answers the user's question and, if a `Database` is attached, checks that the query is read-only
+//! and executes it.
+
+use crate::llm::LLM;
+use crate::prompt::TemplateEngine;
+use crate::template;
+
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::{json, Map, Value as JsonValue};
+use std::sync::Arc;
+
+/// Name of the template used to generate SQL.
+pub const SQL_TEMPLATE: &str = "sql";
+
+static DEFAULT_SQL: &str = r#"{{#chat}}
+{{#system}}You are an expert {{dialect}} developer. Given the database schema below, write a single read-only SQL query that answers the user's question. Only use tables and columns that exist in the schema. Respond with the SQL query only, without any explanation.
+
+Schema:
+{{schema}}{{/system}}
+{{#user}}{{question}}{{/user}}
+{{/chat}}"#;
+
+/// Keywords that are not allowed in a read-only query.
+/// `REPLACE` is also a string function, so it is only rejected as the `REPLACE INTO` statement.
+const WRITE_KEYWORDS: [&str; 13] = [
+    "INSERT", "UPDATE", "DELETE", "DROP", "ALTER", "CREATE", "TRUNCATE", "ATTACH", "DETACH", "PRAGMA", "GRANT",
+    "REVOKE", "VACUUM",
+];
+
+/// A row returned by a query, keyed by column name.
+pub type Row = Map<String, JsonValue>;
+
+/// A database that a `SqlPipeline` can introspect and query.
+#[async_trait::async_trait]
+pub trait Database: Send + Sync {
+    /// SQL dialect of the database, e.g. "SQLite" or "PostgreSQL".
+    fn dialect(&self) -> &str;
+
+    /// Describe the database schema, usually as `CREATE TABLE` statements.
+    async fn schema(&self) -> Result<String>;
+
+    /// Execute a query and return the resulting rows.
+    async fn query(&self, sql: &str) -> Result<Vec<Row>>;
+}
+
+/// Result of a `SqlPipeline` run.
+#[derive(Debug, Clone, Serialize)]
+pub struct SqlResult {
+    /// The generated SQL query.
+    pub query: String,
+
+    /// Rows returned by the query, if it was executed.
+    pub rows: Option<Vec<Row>>,
+}
+
+/// Pipeline that turns a question into SQL and optionally executes it.
+pub struct SqlPipeline<M> {
+    /// The LLM used to write queries.
+    llm: Arc<M>,
+
+    /// The database schema injected into the prompt.
+    schema: String,
+
+    /// SQL dialect mentioned in the prompt, if set explicitly.
+    dialect: Option<String>,
+
+    /// Database used to execute the generated queries.
+    database: Option<Arc<dyn Database>>,
+
+    /// Whether generated queries are executed against the database.
+    execute: bool,
+
+    /// Template engine holding the SQL template.
+    template_engine: TemplateEngine,
+}
+
+impl<M: LLM + Clone + 'static> SqlPipeline<M> {
+    /// Creates a new SQL pipeline with a schema provided by the caller. Queries are only generated,
+    /// not executed.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use orca_core::llm::openai::OpenAI;
+    /// use orca_core::pipeline::sql::SqlPipeline;
+    ///
+    /// let client = OpenAI::new();
+    /// let pipeline = SqlPipeline::new(&client, "CREATE TABLE users (id INTEGER, name TEXT, age INTEGER);");
+    /// ```
+    pub fn new(llm: &M, schema: &str) -> SqlPipeline<M> {
+        SqlPipeline {
+            llm: Arc::new(llm.clone()),
+            schema: schema.to_string(),
+            dialect: None,
+            database: None,
+            execute: false,
+            template_engine: template!(SQL_TEMPLATE, DEFAULT_SQL),
+        }
+    }
+
+    /// Creates a new SQL pipeline by introspecting the schema of a database. Generated queries are
+    /// validated and executed against it.
+    pub async fn from_database<D: Database + 'static>(llm: &M, database: D) -> Result<SqlPipeline<M>> {
+        let schema = database.schema().await?;
+        Ok(SqlPipeline::new(llm, &schema).with_database(database))
+    }
+
+    /// Attach a database to execute the generated queries against. Its dialect is mentioned in the prompt,
+    /// unless one is set with `with_dialect`.
+    pub fn with_database<D: Database + 'static>(mut self, database: D) -> Self {
+        self.database = Some(Arc::new(database));
+        self.execute = true;
+        self
+    }
+
+    /// Set whether generated queries are executed. Only has an effect if a database is attached.
+    pub fn with_execute(mut self, execute: bool) -> Self {
+        self.execute = execute;
+        self
+    }
+
+    /// Set the SQL dialect mentioned in the prompt, taking precedence over the dialect of the database.
+    pub fn with_dialect(mut self, dialect: &str) -> Self {
+        self.dialect = Some(dialect.to_string());
+        self
+    }
+
+    /// The SQL dialect mentioned in the prompt: the one set with `with_dialect`, otherwise the dialect of
+    /// the database, otherwise "SQL".
+    pub fn dialect(&self) -> &str {
+        match (&self.dialect, &self.database) {
+            (Some(dialect), _) => dialect,
+            (None, Some(database)) => database.dialect(),
+            (None, None) => "SQL",
+        }
+    }
+
+    /// Override the SQL template. The template receives `schema`, `dialect` and `question`.
+    pub fn load_template(self, template: &str) -> Result<Self> {
+        Ok(Self {
+            template_engine: self.template_engine.register_template(SQL_TEMPLATE, template)?,
+            ..self
+        })
+    }
+
+    /// The schema injected into the prompt.
+    pub fn schema(&self) -> &str {
+        &self.schema
+    }
+
+    /// Generate a query for the question and, if enabled, execute it.
+    pub async fn run(&self, question: &str) -> Result<SqlResult> {
+        let context = json!({
+            "schema": self.schema,
+            "dialect": self.dialect(),
+            "question": question,
+        });
+        let prompt = self.template_engine.render_context(SQL_TEMPLATE, &context)?;
+        let response = self.llm.generate(prompt).await?;
+        let query = extract_sql(&response.to_string());
+        log::debug!("Generated SQL: {}", query);
+
+        let rows = match (&self.database, self.execute) {
+            (Some(database), true) => {
+                validate_read_only(&query)?;
+                Some(database.query(&query).await?)
+            }
+            _ => None,
+        };
+        Ok(SqlResult { query, rows })
+    }
+}
+
+/// Extract the SQL query from an LLM response, removing markdown code fences if present.
+///
+/// # Example
+/// ```
+/// use orca_core::pipeline::sql::extract_sql;
+///
+/// assert_eq!(extract_sql("```sql\nSELECT 1;\n```"), "SELECT 1;");
+/// ```
+pub fn extract_sql(response: &str) -> String {
+    let response = response.trim();
+    match response.find("```") {
+        Some(start) => {
+            let rest = &response[start + 3..];
+            // Skip the language tag of the fence, if any.
+            let rest = match rest.find('\n') {
+                Some(newline) => &rest[newline + 1..],
+                None => rest,
+            };
+            match rest.find("```") {
+                Some(end) => rest[..end].trim().to_string(),
+                None => rest.trim().to_string(),
+            }
+        }
+        None => response.to_string(),
+    }
+}
+
+/// Check that a query is a single read-only statement.
+///
+/// This is a conservative lexical check meant to catch mistakes from the LLM; it is not a
+/// substitute for connecting with a read-only database user.
+///
+/// # Example
+/// ```
+/// use orca_core::pipeline::sql::validate_read_only;
+///
+/// assert!(validate_read_only("SELECT name FROM users WHERE age > 30").is_ok());
+/// assert!(validate_read_only("DELETE FROM users").is_err());
+/// ```
+pub fn validate_read_only(sql: &str) -> Result<()> {
+    let statement = sql.trim().trim_end_matches(';').trim();
+    if statement.contains(';') {
+        return Err(anyhow::anyhow!("Query must be a single statement: {}", sql));
+    }
+
+    let upper = statement.to_uppercase();
+    if !(upper.starts_with("SELECT") || upper.starts_with("WITH")) {
+        return Err(anyhow::anyhow!("Query must start with SELECT or WITH: {}", sql));
+    }
+
+    let words = upper
+        .split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>();
+    if let Some(keyword) = WRITE_KEYWORDS.iter().find(|keyword| words.contains(*keyword)) {
+        return Err(anyhow::anyhow!("Query must be read-only, found {}: {}", keyword, sql));
+    }
+    if words.windows(2).any(|pair| pair == ["REPLACE", "INTO"]) {
+        return Err(anyhow::anyhow!("Query must be read-only, found REPLACE INTO: {}", sql));
+    }
+    Ok(())
+}
+
+/// SQLite database backed by a sqlx connection pool.
+#[cfg(feature = "sqlite")]
+pub struct Sqlite {
+    pool: sqlx::SqlitePool,
+}
+
+#[cfg(feature = "sqlite")]
+impl Sqlite {
+    /// Connect to a SQLite database, e.g. `sqlite://data.db?mode=ro`.
+    pub async fn connect(url: &str) -> Result<Sqlite> {
+        Ok(Sqlite {
+            pool: sqlx::SqlitePool::connect(url).await?,
+        })
+    }
+
+    /// Create a database from an existing pool.
+    pub fn from_pool(pool: sqlx::SqlitePool) -> Sqlite {
+        Sqlite { pool }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[async_trait::async_trait]
+impl Database for Sqlite {
+    fn dialect(&self) -> &str {
+        "SQLite"
+    }
+
+    async fn schema(&self) -> Result<String> {
+        let statements: Vec<String> =
+            sqlx::query_scalar("SELECT sql FROM sqlite_master WHERE type = 'table' AND sql IS NOT NULL")
+                .fetch_all(&self.pool)
+                .await?;
+        Ok(statements.join(";\n"))
+    }
+
+    async fn query(&self, sql: &str) -> Result<Vec<Row>> {
+        use sqlx::{Column, Row as SqlxRow};
+
+        let rows = sqlx::query(sql).fetch_all(&self.pool).await?;
+        Ok(rows
+            .iter()
+            .map(|row| {
+                row.columns()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, column)| {
+                        let value = if let Ok(v) = row.try_get::<Option<i64>, _>(i) {
+                            json!(v)
+                        } else if let Ok(v) = row.try_get::<Option<f64>, _>(i) {
+                            json!(v)
+                        } else if let Ok(v) = row.try_get::<Option<String>, _>(i) {
+                            json!(v)
+                        } else {
+                            JsonValue::Null
+                        };
+                        (column.name().to_string(), value)
+                    })
+                    .collect::<Row>()
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::FixedLLM;
+
+    struct Users;
+
+    #[async_trait::async_trait]
+    impl Database for Users {
+        fn dialect(&self) -> &str {
+            "SQLite"
+        }
+
+        async fn schema(&self) -> Result<String> {
+            Ok("CREATE TABLE users (name TEXT)".to_string())
+        }
+
+        async fn query(&self, _sql: &str) -> Result<Vec<Row>> {
+            let mut row = Row::new();
+            row.insert("name".to_string(), json!("Orca"));
+            Ok(vec![row])
+        }
+    }
+
+    #[test]
+    fn test_validate_read_only() {
+        assert!(validate_read_only("select * from users;").is_ok());
+        assert!(validate_read_only("WITH t AS (SELECT 1) SELECT * FROM t").is_ok());
+        assert!(validate_read_only("SELECT created_at FROM users").is_ok());
+        assert!(validate_read_only("SELECT 1; DROP TABLE users").is_err());
+        assert!(validate_read_only("UPDATE users SET name = 'x'").is_err());
+        assert!(validate_read_only("WITH t AS (DELETE FROM users RETURNING *) SELECT * FROM t").is_err());
+        assert!(validate_read_only("SELECT replace(name, 'a', 'b') FROM users").is_ok());
+        assert!(validate_read_only("WITH t AS (SELECT 1) REPLACE INTO users SELECT * FROM t").is_err());
+    }
+
+    #[test]
+    fn test_dialect() {
+        let pipeline = SqlPipeline::new(&FixedLLM::new(""), "");
+        assert_eq!(pipeline.dialect(), "SQL");
+        assert_eq!(pipeline.with_database(Users).dialect(), "SQLite");
+
+        let pipeline = SqlPipeline::new(&FixedLLM::new(""), "").with_dialect("PostgreSQL").with_database(Users);
+        assert_eq!(pipeline.dialect(), "PostgreSQL");
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let llm = FixedLLM::new("```sql\nSELECT name FROM users;\n```");
+        let pipeline = SqlPipeline::from_database(&llm, Users).await.unwrap();
+        assert_eq!(pipeline.schema(), "CREATE TABLE users (name TEXT)");
+
+        let result = pipeline.run("What are the names of the users?").await.unwrap();
+        assert!(llm.prompts()[0].contains("CREATE TABLE users (name TEXT)"));
+        assert_eq!(result.query, "SELECT name FROM users;");
+        assert_eq!(result.rows.unwrap()[0]["name"], json!("Orca"));
+    }
+
+    #[tokio::test]
+    async fn test_run_rejects_writes() {
+        let pipeline = SqlPipeline::new(&FixedLLM::new("DELETE FROM users"), "").with_database(Users);
+        assert!(pipeline.run("Delete everyone").await.is_err());
+
+        let pipeline = pipeline.with_execute(false);
+        assert!(pipeline.run("Delete everyone").await.unwrap().rows.is_none());
+    }
+}
@@ -0,0 +1,294 @@
+//! Streaming ingestion with backpressure.
+//!
+//! `StreamingIngest` runs the ingestion stages as separate tasks connected by bounded channels:
+//! records sent by the loader are batched and transformed, embedded, then stored. Loading (e.g. PDF
+//! parsing), embedding and upserts thus overlap, while the bounded channels keep memory flat on large
+//! corpora: when the embedder falls behind, the channels fill up and the loader waits on `send`.
+//!
+//! Embedding models that compute on the calling thread, like `Bert`, occupy a runtime worker while
+//! embedding, so the stages only run in parallel on the multi-threaded runtime.
+
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::llm::Embedding;
+use crate::prompts;
+use crate::record::{transform_all, Record, Transform};
+use crate::task;
+use crate::vectorstore::{Point, VectorStore};
+
+/// Ingestion of a stream of records into a vector store collection.
+pub struct StreamingIngest<E, S: ?Sized> {
+    /// The vector store.
+    store: Arc<S>,
+
+    /// Model embedding the records.
+    embedder: Arc<E>,
+
+    /// Name of the collection.
+    collection: String,
+
+    /// Transforms applied to each batch of records before it is embedded.
+    transforms: Vec<Box<dyn Transform>>,
+
+    /// Number of records embedded and stored at once.
+    batch_size: usize,
+
+    /// Number of records the loader can send ahead of the batching stage.
+    record_buffer: usize,
+
+    /// Number of batches each stage can hand ahead of the next one.
+    batch_buffer: usize,
+}
+
+/// Handle on a running `StreamingIngest`. Dropping it aborts the ingestion, after which the loader
+/// fails to send further records.
+pub struct IngestHandle {
+    batcher: JoinHandle<Result<()>>,
+    embedder: JoinHandle<Result<()>>,
+    storer: JoinHandle<Result<usize>>,
+}
+
+impl IngestHandle {
+    /// Wait for the stages to process the records sent before the sender was dropped, returning the
+    /// number of records stored or the error that stopped the ingestion. A panic of a stage is resumed.
+    pub async fn finish(mut self) -> Result<usize> {
+        // Stages stop without error when a later stage is gone, so the first error is the cause.
+        task::joined((&mut self.batcher).await)??;
+        task::joined((&mut self.embedder).await)??;
+        task::joined((&mut self.storer).await)?
+    }
+}
+
+impl Drop for IngestHandle {
+    fn drop(&mut self) {
+        self.batcher.abort();
+        self.embedder.abort();
+        self.storer.abort();
+    }
+}
+
+impl<E, S> StreamingIngest<E, S>
+where
+    E: Embedding + Send + Sync + 'static,
+    S: VectorStore + ?Sized + 'static,
+{
+    /// Create an ingestion into the given collection, in batches of 32, with room for 256 records
+    /// and 4 batches between stages.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use orca_core::llm::bert::Bert;
+    /// # use orca_core::pipeline::stream::StreamingIngest;
+    /// # use orca_core::qdrant::Qdrant;
+    /// # use orca_core::record::{pdf::Pdf, Spin};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let qdrant = Arc::new(Qdrant::new("http://localhost:6334").unwrap());
+    /// let bert = Arc::new(Bert::new().build_model_and_tokenizer().await?);
+    /// let (sender, handle) = StreamingIngest::new(qdrant, bert, "papers").with_buffers(512, 8).start().await?;
+    /// for path in ["a.pdf", "b.pdf"] {
+    ///     let records = tokio::task::spawn_blocking(move || Pdf::from_file(path, false).spin()).await??;
+    ///     for record in records.split(399) {
+    ///         sender.send(record).await?;
+    ///     }
+    /// }
+    /// drop(sender);
+    /// let stored = handle.finish().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new(store: Arc<S>, embedder: Arc<E>, collection: &str) -> Self {
+        StreamingIngest {
+            store,
+            embedder,
+            collection: collection.to_string(),
+            transforms: Vec::new(),
+            batch_size: 32,
+            record_buffer: 256,
+            batch_buffer: 4,
+        }
+    }
+
+    /// Add a transform applied to each batch of records before it is embedded.
+    pub fn with_transform<T: Transform + 'static>(mut self, transform: T) -> Self {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+
+    /// Set the number of records embedded and stored at once.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Set the number of records the loader can send ahead of the batching stage, and the number
+    /// of batches each stage can hand ahead of the next one. Larger buffers absorb bursts at the cost
+    /// of memory.
+    pub fn with_buffers(mut self, records: usize, batches: usize) -> Self {
+        self.record_buffer = records.max(1);
+        self.batch_buffer = batches.max(1);
+        self
+    }
+
+    /// Create the collection if needed and start the stages, returning the sender the loader sends
+    /// records to and a handle to wait for the ingestion. Drop the sender once all records are sent.
+    pub async fn start(self) -> Result<(mpsc::Sender<Record>, IngestHandle)> {
+        self.store
+            .ensure_collection_with_precision(&self.collection, self.embedder.dimensions(), self.embedder.precision())
+            .await?;
+        let (sender, mut records) = mpsc::channel::<Record>(self.record_buffer);
+        let (batch_sender, mut batches) = mpsc::channel::<Vec<Record>>(self.batch_buffer);
+        let (embedded_sender, mut embedded) = mpsc::channel::<Vec<Point>>(self.batch_buffer);
+
+        let (batch_size, transforms) = (self.batch_size, self.transforms);
+        let batcher = tokio::spawn(async move {
+            let mut batch = Vec::with_capacity(batch_size);
+            loop {
+                let record = records.recv().await;
+                let done = record.is_none();
+                batch.extend(record);
+                if batch.len() == batch_size || (done && !batch.is_empty()) {
+                    let transformed = transform_all(std::mem::take(&mut batch), &transforms).await?;
+                    if !transformed.is_empty() && batch_sender.send(transformed).await.is_err() {
+                        return Ok(());
+                    }
+                }
+                if done {
+                    return Ok(());
+                }
+            }
+        });
+
+        let model = self.embedder;
+        let embedder = tokio::spawn(async move {
+            while let Some(batch) = batches.recv().await {
+                let embeddings = model.generate_embeddings(prompts!(&batch)).await?;
+                // Random ids, so that batches and later ingestions do not replace stored points.
+                let points = batch
+                    .iter()
+                    .zip(embeddings.into_vectors())
+                    .map(|(record, vector)| Point::new(uuid::Uuid::new_v4().as_u64_pair().0, vector, record))
+                    .collect::<Result<Vec<_>>>()?;
+                if embedded_sender.send(points).await.is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        });
+
+        let (store, collection) = (self.store, self.collection);
+        let storer = tokio::spawn(async move {
+            let mut stored = 0;
+            while let Some(points) = embedded.recv().await {
+                let count = points.len();
+                store
+                    .insert(&collection, points)
+                    .await
+                    .map_err(|e| anyhow!("Failed to store a batch of {} records: {}", count, e))?;
+                stored += count;
+            }
+            Ok(stored)
+        });
+
+        Ok((
+            sender,
+            IngestHandle {
+                batcher,
+                embedder,
+                storer,
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::record::Content;
+    use crate::testing::ConstantEmbedder;
+    use crate::vectorstore::{SearchHit, SearchQuery};
+    use std::sync::Mutex;
+
+    /// Store keeping the size of each inserted batch, failing once it holds `capacity` points.
+    struct Bounded {
+        batches: Mutex<Vec<usize>>,
+        capacity: usize,
+    }
+
+    #[async_trait::async_trait]
+    impl VectorStore for Bounded {
+        async fn ensure_collection(&self, _collection: &str, _dimensions: usize) -> Result<()> {
+            Ok(())
+        }
+
+        async fn insert(&self, _collection: &str, points: Vec<Point>) -> Result<()> {
+            let mut batches = self.batches.lock().unwrap();
+            if batches.iter().sum::<usize>() + points.len() > self.capacity {
+                return Err(anyhow!("collection is full"));
+            }
+            batches.push(points.len());
+            Ok(())
+        }
+
+        async fn search(&self, _collection: &str, _query: SearchQuery) -> Result<Vec<SearchHit>> {
+            Ok(Vec::new())
+        }
+
+        async fn delete_collection(&self, _collection: &str) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn store(capacity: usize) -> Arc<Bounded> {
+        Arc::new(Bounded {
+            batches: Mutex::new(Vec::new()),
+            capacity,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        let store = store(100);
+        let ingest = StreamingIngest::new(store.clone(), Arc::new(ConstantEmbedder(1.0)), "numbers")
+            .with_batch_size(2)
+            .with_buffers(1, 1);
+        let (sender, handle) = ingest.start().await.unwrap();
+        for i in 0..5 {
+            sender.send(Record::new(Content::String(i.to_string()))).await.unwrap();
+        }
+        drop(sender);
+        assert_eq!(handle.finish().await.unwrap(), 5);
+        assert_eq!(*store.batches.lock().unwrap(), vec![2, 2, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_stream_error() {
+        let ingest = StreamingIngest::new(store(3), Arc::new(ConstantEmbedder(1.0)), "numbers")
+            .with_batch_size(2)
+            .with_buffers(1, 1);
+        let (sender, handle) = ingest.start().await.unwrap();
+        for i in 0..100 {
+            // The stages stop after the failed insert, so the loader stops too.
+            if sender.send(Record::new(Content::String(i.to_string()))).await.is_err() {
+                break;
+            }
+        }
+        drop(sender);
+        let error = handle.finish().await.unwrap_err();
+        assert!(error.to_string().contains("collection is full"));
+    }
+
+    #[tokio::test]
+    async fn test_stream_abort() {
+        let ingest = StreamingIngest::new(store(100), Arc::new(ConstantEmbedder(1.0)), "numbers");
+        let (sender, handle) = ingest.start().await.unwrap();
+        drop(handle);
+        // The aborted batcher drops its receiver instead of waiting on the loader forever.
+        tokio::time::timeout(std::time::Duration::from_secs(1), sender.closed()).await.unwrap();
+    }
+}
@@ -0,0 +1,363 @@
+use super::PipelineResult;
+use crate::llm::{LLMResponse, LLM};
+use crate::prompt::{estimate_tokens, TemplateEngine};
+use crate::record::Record;
+use crate::template;
+
+use anyhow::{anyhow, Result};
+use serde_json::json;
+use std::sync::Arc;
+
+/// Template used to summarize a text in a single call.
+pub const STUFF_TEMPLATE: &str = "stuff";
+
+/// Template used to summarize each chunk in the map step.
+pub const MAP_TEMPLATE: &str = "map";
+
+/// Template used to combine the chunk summaries in the reduce step.
+pub const COMBINE_TEMPLATE: &str = "combine";
+
+/// Template used to refine an existing summary with a new chunk.
+pub const REFINE_TEMPLATE: &str = "refine";
+
+static DEFAULT_STUFF: &str = r#"{{#chat}}
+{{#system}}You are an expert at writing concise, accurate summaries. Summarize the text given by the user, keeping the key facts and conclusions.{{/system}}
+{{#user}}{{text}}{{/user}}
+{{/chat}}"#;
+
+static DEFAULT_MAP: &str = r#"{{#chat}}
+{{#system}}You are an expert at writing concise, accurate summaries. The text given by the user is a part of a larger document. Summarize it, keeping the key facts and conclusions.{{/system}}
+{{#user}}{{text}}{{/user}}
+{{/chat}}"#;
+
+static DEFAULT_COMBINE: &str = r#"{{#chat}}
+{{#system}}You are an expert at writing concise, accurate summaries. The user gives you summaries of consecutive parts of a document. Combine them into a single coherent summary of the whole document.{{/system}}
+{{#user}}{{#each summaries}}{{this}}
+
+{{/each}}{{/user}}
+{{/chat}}"#;
+
+static DEFAULT_REFINE: &str = r#"{{#chat}}
+{{#system}}You are an expert at writing concise, accurate summaries. You are given an existing summary of the beginning of a document and the next part of the document. Refine the summary so that it also covers the new part.{{/system}}
+{{#user}}Existing summary:
+{{summary}}
+
+Next part:
+{{text}}{{/user}}
+{{/chat}}"#;
+
+/// Default maximum number of calls made at once by the map-reduce strategy.
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
+/// Strategy used by the `Summarizer`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Strategy {
+    /// Put all the records into a single prompt.
+    Stuff,
+
+    /// Summarize each chunk concurrently, then combine the summaries, in groups that fit in the token
+    /// limit until a single combine call is left.
+    MapReduce,
+
+    /// Summarize the first chunk, then refine the summary with each following chunk in order.
+    Refine,
+
+    /// Use `Stuff` if the records fit in the token limit, otherwise `MapReduce`.
+    Auto,
+}
+
+/// Summarizes records with an LLM using one of several strategies.
+pub struct Summarizer<M> {
+    /// The LLM used to generate summaries.
+    llm: Arc<M>,
+
+    /// The summarization strategy.
+    strategy: Strategy,
+
+    /// Maximum number of input tokens sent in a single prompt. Larger inputs are chunked.
+    max_tokens: usize,
+
+    /// Maximum number of calls made at once by the map-reduce strategy.
+    concurrency: usize,
+
+    /// Template engine holding the stuff, map, combine and refine templates.
+    template_engine: TemplateEngine,
+}
+
+impl<M: LLM + Clone + 'static> Summarizer<M> {
+    /// Creates a new summarizer with the default prompts and automatic strategy selection.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use orca_core::llm::openai::OpenAI;
+    /// use orca_core::pipeline::summarize::{Strategy, Summarizer};
+    ///
+    /// let client = OpenAI::new();
+    /// let summarizer = Summarizer::new(&client).with_strategy(Strategy::Refine).with_max_tokens(2000);
+    /// ```
+    pub fn new(llm: &M) -> Summarizer<M> {
+        Summarizer {
+            llm: Arc::new(llm.clone()),
+            strategy: Strategy::Auto,
+            max_tokens: 3000,
+            concurrency: DEFAULT_CONCURRENCY,
+            template_engine: template!(
+                STUFF_TEMPLATE,
+                DEFAULT_STUFF,
+                MAP_TEMPLATE,
+                DEFAULT_MAP,
+                COMBINE_TEMPLATE,
+                DEFAULT_COMBINE,
+                REFINE_TEMPLATE,
+                DEFAULT_REFINE
+            ),
+        }
+    }
+
+    /// Set the summarization strategy.
+    pub fn with_strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Set the maximum number of input tokens sent in a single prompt.
+    pub fn with_max_tokens(mut self, max_tokens: usize) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    /// Set the maximum number of calls made at once by the map-reduce strategy, `DEFAULT_CONCURRENCY` by
+    /// default, so that long documents do not flood the model with requests.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Override one of the default templates (`STUFF_TEMPLATE`, `MAP_TEMPLATE`, `COMBINE_TEMPLATE` or
+    /// `REFINE_TEMPLATE`). The stuff and map templates receive `text`, the combine template receives
+    /// `summaries` and the refine template receives `summary` and `text`.
+    pub fn load_template(self, name: &str, template: &str) -> Result<Self> {
+        Ok(Self {
+            template_engine: self.template_engine.register_template(name, template)?,
+            ..self
+        })
+    }
+
+    /// Resolve the strategy that will be used for the given records.
+    pub fn select_strategy(&self, records: &[Record]) -> Strategy {
+        match self.strategy {
+            Strategy::Auto => {
+                let tokens = records.iter().map(|r| estimate_tokens(&r.content.to_string())).sum::<usize>();
+                if tokens <= self.max_tokens {
+                    Strategy::Stuff
+                } else {
+                    Strategy::MapReduce
+                }
+            }
+            strategy => strategy,
+        }
+    }
+
+    /// Summarize the given records. Fails if they hold no text.
+    pub async fn summarize(&self, records: &[Record]) -> Result<PipelineResult> {
+        if records.iter().all(|r| r.content.to_string().trim().is_empty()) {
+            return Err(anyhow!("There is no text to summarize"));
+        }
+        let response = match self.select_strategy(records) {
+            Strategy::Stuff => {
+                let text = records.iter().map(|r| r.content.to_string()).collect::<Vec<_>>().join("\n\n");
+                self.generate(STUFF_TEMPLATE, json!({ "text": text })).await?
+            }
+            Strategy::MapReduce => self.map_reduce(self.chunks(records)).await?,
+            Strategy::Refine => self.refine(self.chunks(records)).await?,
+            Strategy::Auto => unreachable!("auto strategy is always resolved"),
+        };
+        Ok(PipelineResult::new(uuid::Uuid::new_v4().to_string()).with_llm_response(response))
+    }
+
+    /// Split the records into chunks that fit in the token limit.
+    fn chunks(&self, records: &[Record]) -> Vec<String> {
+        // `Record::split` works on characters, so convert the token limit using the same ratio as
+        // `estimate_tokens`.
+        let max_chars = self.max_tokens * 4;
+        records.iter().flat_map(|r| r.split(max_chars)).map(|r| r.content.to_string()).collect()
+    }
+
+    async fn map_reduce(&self, chunks: Vec<String>) -> Result<LLMResponse> {
+        let contexts = chunks.iter().map(|chunk| json!({ "text": chunk })).collect();
+        let mut summaries = self.generate_all(MAP_TEMPLATE, contexts).await?;
+        loop {
+            let mut groups = self.groups(summaries);
+            if groups.len() <= 1 {
+                let summaries = groups.pop().unwrap_or_default();
+                log::debug!("Combining {} chunk summaries", summaries.len());
+                return self.generate(COMBINE_TEMPLATE, json!({ "summaries": summaries })).await;
+            }
+            log::debug!("Collapsing chunk summaries into {}", groups.len());
+            let contexts = groups.iter().map(|group| json!({ "summaries": group })).collect();
+            summaries = self.generate_all(COMBINE_TEMPLATE, contexts).await?;
+        }
+    }
+
+    /// Split consecutive summaries into groups that fit in the token limit. Groups have at least two
+    /// summaries, unless there is only one, so that each round of combine calls shrinks the summaries.
+    fn groups(&self, summaries: Vec<String>) -> Vec<Vec<String>> {
+        let mut groups: Vec<Vec<String>> = Vec::new();
+        let mut tokens = 0;
+        for summary in summaries {
+            let summary_tokens = estimate_tokens(&summary);
+            match groups.last_mut() {
+                Some(group) if group.len() < 2 || tokens + summary_tokens <= self.max_tokens => {
+                    tokens += summary_tokens;
+                    group.push(summary);
+                }
+                _ => {
+                    tokens = summary_tokens;
+                    groups.push(vec![summary]);
+                }
+            }
+        }
+        if let [.., previous, last] = groups.as_mut_slice() {
+            if last.len() == 1 {
+                previous.append(last);
+                groups.pop();
+            }
+        }
+        groups
+    }
+
+    /// Render a template with each context and generate the responses in order, making at most
+    /// `concurrency` calls at once.
+    async fn generate_all(&self, template: &str, contexts: Vec<serde_json::Value>) -> Result<Vec<String>> {
+        let mut responses = vec![String::new(); contexts.len()];
+        let mut contexts = contexts.into_iter().enumerate();
+        // Dropping the set on an error aborts the calls still running.
+        let mut calls = tokio::task::JoinSet::new();
+        loop {
+            while calls.len() < self.concurrency {
+                let Some((i, context)) = contexts.next() else {
+                    break;
+                };
+                let llm = self.llm.clone();
+                let prompt = self.template_engine.render_context(template, &context)?;
+                calls.spawn(async move { (i, llm.generate(prompt).await) });
+            }
+            let Some(joined) = calls.join_next().await else {
+                return Ok(responses);
+            };
+            let (i, response) = crate::task::joined(joined)?;
+            responses[i] = response?.to_string();
+        }
+    }
+
+    async fn refine(&self, chunks: Vec<String>) -> Result<LLMResponse> {
+        let mut chunks = chunks.into_iter();
+        let first = chunks.next().unwrap_or_default();
+        let mut response = self.generate(MAP_TEMPLATE, json!({ "text": first })).await?;
+        for chunk in chunks {
+            let summary = response.to_string();
+            response = self.generate(REFINE_TEMPLATE, json!({ "summary": summary, "text": chunk })).await?;
+        }
+        Ok(response)
+    }
+
+    async fn generate(&self, template: &str, context: serde_json::Value) -> Result<LLMResponse> {
+        let prompt = self.template_engine.render_context(template, &context)?;
+        self.llm.generate(prompt).await
+    }
+}
+
+/// Summarizes records. Implemented by `Summarizer` for any LLM, so that summarizers can be held as
+/// trait objects, e.g. by `IngestPipeline::with_abstracts`.
+#[async_trait::async_trait]
+pub trait Summarize: Send + Sync {
+    /// Summarize the given records.
+    async fn summarize(&self, records: &[Record]) -> Result<PipelineResult>;
+}
+
+#[async_trait::async_trait]
+impl<M: LLM + Clone + 'static> Summarize for Summarizer<M> {
+    async fn summarize(&self, records: &[Record]) -> Result<PipelineResult> {
+        Summarizer::summarize(self, records).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::record::Content;
+    use crate::testing::{EchoLLM, FixedLLM, SlowLLM};
+    use std::time::Duration;
+
+    #[test]
+    fn test_select_strategy() {
+        let records = vec![Record::new(Content::String("word ".repeat(100)))];
+        let summarizer = Summarizer::new(&EchoLLM::last_message());
+        assert_eq!(summarizer.select_strategy(&records), Strategy::Stuff);
+
+        let summarizer = summarizer.with_max_tokens(10);
+        assert_eq!(summarizer.select_strategy(&records), Strategy::MapReduce);
+
+        let summarizer = summarizer.with_strategy(Strategy::Refine);
+        assert_eq!(summarizer.select_strategy(&records), Strategy::Refine);
+    }
+
+    #[tokio::test]
+    async fn test_refine() {
+        let records = vec![Record::new(Content::Vec(vec![
+            "first".to_string(),
+            "second".to_string(),
+        ]))];
+        let summarizer = Summarizer::new(&EchoLLM::last_message()).with_strategy(Strategy::Refine);
+        let result = summarizer.summarize(&records).await.unwrap();
+        assert!(result.content().contains("first"));
+        assert!(result.content().contains("second"));
+    }
+
+    #[tokio::test]
+    async fn test_map_reduce() {
+        let records = vec![Record::new(Content::Vec(vec![
+            "first".to_string(),
+            "second".to_string(),
+        ]))];
+        let summarizer = Summarizer::new(&EchoLLM::last_message()).with_strategy(Strategy::MapReduce);
+        let result = summarizer.summarize(&records).await.unwrap();
+        assert!(result.content().contains("first"));
+        assert!(result.content().contains("second"));
+    }
+
+    #[tokio::test]
+    async fn test_map_reduce_concurrency() {
+        let parts = (0..8).map(|i| format!("part {}", i)).collect();
+        let records = vec![Record::new(Content::Vec(parts))];
+        let llm = SlowLLM::new(Duration::from_millis(10));
+        let summarizer = Summarizer::new(&llm).with_strategy(Strategy::MapReduce).with_concurrency(2);
+        let result = summarizer.summarize(&records).await.unwrap();
+        assert!(result.content().contains("part 0"));
+        assert!(result.content().contains("part 7"));
+        assert_eq!(llm.max_running(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_map_reduce_collapse() {
+        let parts = (0..8).map(|i| format!("part {}", i)).collect();
+        let records = vec![Record::new(Content::Vec(parts))];
+        // Two summaries do not fit in the token limit together, so they are combined in pairs.
+        let summary = "s".repeat(30);
+        let llm = FixedLLM::new(&summary);
+        let summarizer = Summarizer::new(&llm).with_strategy(Strategy::MapReduce).with_max_tokens(10);
+        summarizer.summarize(&records).await.unwrap();
+        let prompts = llm.prompts();
+        assert_eq!(prompts.len(), 8 + 4 + 2 + 1);
+        assert!(prompts.iter().all(|prompt| prompt.matches(&summary).count() <= 2));
+    }
+
+    #[tokio::test]
+    async fn test_empty() {
+        let llm = FixedLLM::new("Nothing.");
+        let summarizer = Summarizer::new(&llm).with_strategy(Strategy::MapReduce);
+        assert!(summarizer.summarize(&[]).await.is_err());
+        assert!(summarizer.summarize(&[Record::new(Content::String(" ".to_string()))]).await.is_err());
+        assert!(llm.prompts().is_empty());
+    }
+}
@@ -0,0 +1,190 @@
+//! Titles and topic tags of conversations and documents.
+//!
+//! A `TitleGenerator` asks an LLM for a short title or a few topic tags, e.g. to list the conversations
+//! of a chat UI. The LLM is made deterministic when it supports it, so that the same conversation gets
+//! the same title, and the responses are cleaned up: quotes and trailing periods are removed from
+//! titles, and tags are lowercased and deduplicated. Conversations are given to the LLM as a markdown
+//! transcript, truncated to a maximum number of tokens like any long document.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use serde_json::json;
+
+use super::parse_json;
+use crate::llm::LLM;
+use crate::prompt::budget::truncate_tokens;
+use crate::prompt::{Prompt, TemplateEngine};
+use crate::template;
+
+/// Name of the template used to generate titles.
+pub const TITLE_TEMPLATE: &str = "title";
+
+/// Name of the template used to generate tags.
+pub const TAGS_TEMPLATE: &str = "tags";
+
+static DEFAULT_TITLE: &str = r#"{{#chat}}
+{{#system}}Write a short title, of at most {{max_words}} words, for the conversation or document given by the user. Respond only with the title.{{/system}}
+{{#user}}{{text}}{{/user}}
+{{/chat}}"#;
+
+static DEFAULT_TAGS: &str = r#"{{#chat}}
+{{#system}}List at most {{max_tags}} topic tags for the conversation or document given by the user, each of one or two lowercase words. Respond only with a JSON array of strings.{{/system}}
+{{#user}}{{text}}{{/user}}
+{{/chat}}"#;
+
+/// Generates titles and topic tags with an LLM.
+pub struct TitleGenerator<M> {
+    /// The LLM generating the titles and tags.
+    llm: Arc<M>,
+
+    /// Maximum number of words of a title.
+    max_words: usize,
+
+    /// Maximum number of tags.
+    max_tags: usize,
+
+    /// Maximum number of tokens of the text given to the LLM. Longer texts are truncated.
+    max_tokens: usize,
+
+    /// Template engine holding the title and tags templates.
+    template_engine: TemplateEngine,
+}
+
+impl<M: LLM + Clone + 'static> TitleGenerator<M> {
+    /// Create a generator of titles of at most 8 words and of at most 5 tags, using the deterministic
+    /// version of the LLM if it has one.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use orca_core::llm::openai::OpenAI;
+    /// use orca_core::pipeline::title::TitleGenerator;
+    /// use orca_core::prompt::chat::ChatPrompt;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let chat = ChatPrompt::from_openai_json(r#"[{"role": "user", "content": "Where do orcas live?"}]"#)?;
+    /// let generator = TitleGenerator::new(&OpenAI::new().with_model("gpt-3.5-turbo-1106"));
+    /// let title = generator.title(&chat).await?;
+    /// let tags = generator.tags(&chat).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new(llm: &M) -> Self {
+        TitleGenerator {
+            llm: Arc::new(llm.deterministic().unwrap_or_else(|| llm.clone())),
+            max_words: 8,
+            max_tags: 5,
+            max_tokens: 3000,
+            template_engine: template!(TITLE_TEMPLATE, DEFAULT_TITLE, TAGS_TEMPLATE, DEFAULT_TAGS),
+        }
+    }
+
+    /// Set the maximum number of words of a title. Longer titles are cut.
+    pub fn with_max_words(mut self, max_words: usize) -> Self {
+        self.max_words = max_words.max(1);
+        self
+    }
+
+    /// Set the maximum number of tags.
+    pub fn with_max_tags(mut self, max_tags: usize) -> Self {
+        self.max_tags = max_tags;
+        self
+    }
+
+    /// Set the maximum number of tokens of the text given to the LLM.
+    pub fn with_max_tokens(mut self, max_tokens: usize) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    /// Override one of the default templates (`TITLE_TEMPLATE` or `TAGS_TEMPLATE`). They receive
+    /// `text`, and `max_words` or `max_tags` respectively.
+    pub fn load_template(self, name: &str, template: &str) -> Result<Self> {
+        Ok(Self {
+            template_engine: self.template_engine.register_template(name, template)?,
+            ..self
+        })
+    }
+
+    /// Generate a title for a conversation or a document.
+    pub async fn title(&self, input: &dyn Prompt) -> Result<String> {
+        let context = json!({ "text": self.text(input), "max_words": self.max_words });
+        let prompt = self.template_engine.render_context(TITLE_TEMPLATE, &context)?;
+        let response = self.llm.generate(prompt).await?.to_string();
+        Ok(clean_title(&response, self.max_words))
+    }
+
+    /// Generate topic tags for a conversation or a document.
+    pub async fn tags(&self, input: &dyn Prompt) -> Result<Vec<String>> {
+        let context = json!({ "text": self.text(input), "max_tags": self.max_tags });
+        let prompt = self.template_engine.render_context(TAGS_TEMPLATE, &context)?;
+        let response = self.llm.generate(prompt).await?.to_string();
+        Ok(clean_tags(&response, self.max_tags))
+    }
+
+    /// The text of a conversation or document, truncated to the maximum number of tokens.
+    fn text(&self, input: &dyn Prompt) -> String {
+        let text = match input.to_chat() {
+            Ok(chat) => chat.to_markdown(),
+            Err(_) => input.to_string(),
+        };
+        truncate_tokens(&text, self.max_tokens)
+    }
+}
+
+/// The first line of a response, without a `Title:` prefix, quotes or trailing period, cut to the
+/// maximum number of words.
+fn clean_title(response: &str, max_words: usize) -> String {
+    let line = response.trim().lines().next().unwrap_or_default();
+    let line = line.strip_prefix("Title:").unwrap_or(line);
+    let line = line.trim().trim_matches(|c: char| matches!(c, '"' | '\'' | '*' | '#')).trim();
+    let line = line.strip_suffix('.').unwrap_or(line);
+    line.split_whitespace().take(max_words).collect::<Vec<_>>().join(" ")
+}
+
+/// Tags listed in a response, as a JSON array or separated by commas or new lines, lowercased,
+/// deduplicated and cut to the maximum number of tags.
+fn clean_tags(response: &str, max_tags: usize) -> Vec<String> {
+    let tags = parse_json::<Vec<String>>(response)
+        .unwrap_or_else(|_| response.split([',', '\n']).map(str::to_string).collect());
+    let mut cleaned: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag
+            .trim_start_matches(|c: char| c.is_whitespace() || matches!(c, '-' | '*' | '#'))
+            .trim()
+            .trim_matches('"')
+            .to_lowercase();
+        if !tag.is_empty() && !cleaned.contains(&tag) {
+            cleaned.push(tag);
+        }
+    }
+    cleaned.truncate(max_tags);
+    cleaned
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prompt::chat::ChatPrompt;
+    use crate::testing::FixedLLM;
+
+    #[tokio::test]
+    async fn test_title() {
+        let chat = ChatPrompt::from_openai_json(r#"[{"role": "user", "content": "Where do orcas live?"}]"#).unwrap();
+        let llm = FixedLLM::new("Orcas").with_deterministic("\"Where Orcas Live.\"\nThis conversation is about orcas.");
+        let generator = TitleGenerator::new(&llm);
+        assert_eq!(generator.title(&chat).await.unwrap(), "Where Orcas Live");
+        assert_eq!(generator.with_max_words(2).title(&chat).await.unwrap(), "Where Orcas");
+    }
+
+    #[test]
+    fn test_clean_tags() {
+        assert_eq!(
+            clean_tags(r#"Tags: ["Orcas", "marine biology", "orcas"]"#, 5),
+            ["orcas", "marine biology"]
+        );
+        assert_eq!(clean_tags("- Orcas\n- #Salmon\n- Pacific", 2), ["orcas", "salmon"]);
+        assert_eq!(clean_title("Title: **Orca diets**", 8), "Orca diets");
+    }
+}
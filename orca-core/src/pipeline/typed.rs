@@ -0,0 +1,201 @@
+//! Pipelines with typed inputs and outputs.
+//!
+//! A `TypedPipeline` wraps a pipeline so that application code passes a struct instead of setting context
+//! keys by name, and gets a struct back instead of parsing the response: the fields of the input are set
+//! in the context rendered into the templates, and the response is parsed by an `OutputParser`, as JSON by
+//! default. Renaming a field of the input or output is then checked by the compiler in the code calling
+//! the pipeline, while the templates keep referring to the fields by name.
+//!
+//! ```no_run
+//! use orca_core::llm::openai::OpenAI;
+//! use orca_core::pipeline::simple::LLMPipeline;
+//! use orca_core::pipeline::typed::TypedPipeline;
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Serialize)]
+//! struct Question {
+//!     country: String,
+//! }
+//!
+//! #[derive(Deserialize)]
+//! struct Answer {
+//!     capital: String,
+//! }
+//!
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! let client = OpenAI::new();
+//! let pipeline = LLMPipeline::new(&client).load_template(
+//!     "capital",
+//!     r#"{{#chat}}{{#user}}What is the capital of {{country}}? Answer as {"capital": "..."}.{{/user}}{{/chat}}"#,
+//! )?;
+//! let capitals = TypedPipeline::<_, Question, Answer>::new(pipeline);
+//! let answer = capitals.run("capital", &Question { country: "France".to_string() }).await?;
+//! println!("{}", answer.capital);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use anyhow::{Context as _, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::context::{MergeStrategy, PipelineContext};
+use super::{context_of, Pipeline, PipelineResult};
+
+/// Parses the result of a pipeline into a value of type `O`.
+pub trait OutputParser<O>: Send + Sync {
+    fn parse(&self, result: &PipelineResult) -> Result<O>;
+}
+
+impl<O, F> OutputParser<O> for F
+where
+    F: Fn(&PipelineResult) -> Result<O> + Send + Sync,
+{
+    fn parse(&self, result: &PipelineResult) -> Result<O> {
+        self(result)
+    }
+}
+
+/// Parser of the JSON content of a result, found as by [`super::parse_json`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonOutput;
+
+impl<O: DeserializeOwned> OutputParser<O> for JsonOutput {
+    fn parse(&self, result: &PipelineResult) -> Result<O> {
+        result.json()
+    }
+}
+
+/// Pipeline wrapper taking an input of type `I` and returning an output of type `O`.
+///
+/// The input must serialize to an object, whose fields are set in the context of a copy of the wrapped
+/// pipeline, replacing the values of the same keys, so that concurrent runs do not see each other's inputs.
+pub struct TypedPipeline<P, I, O> {
+    /// The wrapped pipeline.
+    pipeline: P,
+
+    /// Parser of the results of the pipeline.
+    parser: Arc<dyn OutputParser<O>>,
+
+    input: PhantomData<fn(&I)>,
+}
+
+impl<P, I, O> TypedPipeline<P, I, O>
+where
+    P: Pipeline + Clone,
+    I: Serialize,
+{
+    /// Wraps a pipeline whose responses are parsed as JSON.
+    pub fn new(pipeline: P) -> Self
+    where
+        O: DeserializeOwned + 'static,
+    {
+        Self::with_parser(pipeline, JsonOutput)
+    }
+
+    /// Wraps a pipeline whose responses are parsed by the given parser.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::llm::openai::OpenAI;
+    /// use orca_core::pipeline::simple::LLMPipeline;
+    /// use orca_core::pipeline::typed::TypedPipeline;
+    /// use orca_core::pipeline::PipelineResult;
+    /// use std::collections::HashMap;
+    ///
+    /// let client = OpenAI::new();
+    /// let pipeline = LLMPipeline::new(&client)
+    ///     .load_template("count", "{{#chat}}{{#user}}Count to {{n}}.{{/user}}{{/chat}}")
+    ///     .unwrap();
+    /// let words = |result: &PipelineResult| Ok(result.content().split_whitespace().count());
+    /// let counter = TypedPipeline::<_, HashMap<String, u32>, usize>::with_parser(pipeline, words);
+    /// ```
+    pub fn with_parser<R: OutputParser<O> + 'static>(pipeline: P, parser: R) -> Self {
+        TypedPipeline {
+            pipeline,
+            parser: Arc::new(parser),
+            input: PhantomData,
+        }
+    }
+
+    /// Executes the target of the pipeline with the fields of the input in its context, and parses its result.
+    pub async fn run(&self, target: &str, input: &I) -> Result<O> {
+        let result = self.execute(target, input).await?;
+        self.parser.parse(&result).context("Failed to parse the output of the pipeline")
+    }
+
+    /// Executes the target of the pipeline with the fields of the input in its context, returning the result
+    /// as is.
+    pub async fn execute(&self, target: &str, input: &I) -> Result<PipelineResult> {
+        let input = PipelineContext::try_from(serde_json::to_value(input)?)
+            .context("The input of a typed pipeline must serialize to an object")?;
+        let mut pipeline = self.pipeline.clone();
+        context_of(&mut pipeline)?.merge(&input, MergeStrategy::Overwrite)?;
+        pipeline.execute(target).await
+    }
+
+    /// The wrapped pipeline.
+    pub fn pipeline(&self) -> &P {
+        &self.pipeline
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pipeline::simple::LLMPipeline;
+    use crate::testing::EchoLLM;
+    use serde::Deserialize;
+
+    #[derive(Serialize)]
+    struct Sighting {
+        pod: String,
+        count: u32,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Report {
+        pod: String,
+        count: u32,
+    }
+
+    #[tokio::test]
+    async fn test_typed_pipeline() {
+        let pipeline = LLMPipeline::new(&EchoLLM::last_message())
+            .load_template(
+                "report",
+                r#"{{#chat}}{{#user}}Sure! {"pod": "{{pod}}", "count": {{count}}}{{/user}}{{/chat}}"#,
+            )
+            .unwrap();
+        let typed = TypedPipeline::<_, Sighting, Report>::new(pipeline.clone());
+        let sighting = Sighting {
+            pod: "J".to_string(),
+            count: 24,
+        };
+        let report = typed.run("report", &sighting).await.unwrap();
+        assert_eq!(
+            report,
+            Report {
+                pod: "J".to_string(),
+                count: 24
+            }
+        );
+        assert!(typed.pipeline().clone().context().unwrap().as_object().is_empty());
+
+        let typed = TypedPipeline::<_, Sighting, bool>::with_parser(pipeline.clone(), |result: &PipelineResult| {
+            Ok(result.content().starts_with("Sure!"))
+        });
+        assert!(typed.run("report", &sighting).await.unwrap());
+
+        let typed = TypedPipeline::<_, u32, Report>::new(pipeline);
+        let error = typed.run("report", &24).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The input of a typed pipeline must serialize to an object"
+        );
+    }
+}
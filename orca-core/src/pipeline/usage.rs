@@ -0,0 +1,276 @@
+//! Token usage and cost accounting across pipeline executions.
+//!
+//! Pipelines record the prompt and completion tokens of each of their LLM calls, with the model that
+//! answered, in a `UsageTracker`. Providers that do not report usage, e.g. local models, are recorded with
+//! estimated tokens. The tracker keeps the records of every execution until it is reset, and prices them
+//! with a `PricingTable` giving the cost of a thousand prompt and completion tokens of each model:
+//!
+//! ```no_run
+//! use orca_core::llm::openai::OpenAI;
+//! use orca_core::pipeline::simple::LLMPipeline;
+//! use orca_core::pipeline::usage::{PricingTable, UsageTracker};
+//! use orca_core::pipeline::Pipeline;
+//!
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! let pricing = PricingTable::new().with_price("gpt-3.5-turbo", 0.0005, 0.0015).with_price("gpt-4", 0.03, 0.06);
+//! let pipeline = LLMPipeline::new(&OpenAI::new())
+//!     .load_template("hello", "{{#chat}}{{#user}}Hello!{{/user}}{{/chat}}")?
+//!     .with_usage_tracker(UsageTracker::new().with_pricing(pricing));
+//! pipeline.execute("hello").await?;
+//! let total = pipeline.usage().total();
+//! println!("{} tokens, ${:.4}", total.total_tokens(), total.cost);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::sync::{Arc, Mutex};
+
+use crate::llm::TokenUsage;
+
+/// Cost of a thousand prompt and completion tokens of a model.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelPricing {
+    /// Cost of a thousand prompt tokens.
+    pub prompt_per_1k: f64,
+
+    /// Cost of a thousand completion tokens.
+    pub completion_per_1k: f64,
+}
+
+impl ModelPricing {
+    /// Cost of a call using the given tokens.
+    pub fn cost(&self, prompt_tokens: u32, completion_tokens: u32) -> f64 {
+        (prompt_tokens as f64 * self.prompt_per_1k + completion_tokens as f64 * self.completion_per_1k) / 1000.0
+    }
+}
+
+/// Prices of the models, in any currency. A model is priced by the longest model name it starts with, so
+/// that `gpt-4` prices the dated versions of the model, e.g. `gpt-4-0613`, unless they have their own price.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PricingTable {
+    prices: Vec<(String, ModelPricing)>,
+}
+
+impl PricingTable {
+    /// Create a table without prices.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the cost of a thousand prompt and completion tokens of a model.
+    pub fn with_price(mut self, model: &str, prompt_per_1k: f64, completion_per_1k: f64) -> Self {
+        self.prices.retain(|(name, _)| name != model);
+        self.prices.push((
+            model.to_string(),
+            ModelPricing {
+                prompt_per_1k,
+                completion_per_1k,
+            },
+        ));
+        self
+    }
+
+    /// Price of a model, if the table has one.
+    pub fn price(&self, model: &str) -> Option<ModelPricing> {
+        self.prices
+            .iter()
+            .filter(|(name, _)| model.starts_with(name.as_str()))
+            .max_by_key(|(name, _)| name.len())
+            .map(|(_, pricing)| *pricing)
+    }
+}
+
+/// Tokens used by an LLM call of a pipeline execution.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UsageRecord {
+    /// Step of the execution that made the call, e.g. the template of an `LLMPipeline`.
+    pub step: String,
+
+    /// Model that answered, or `unknown` if neither the response nor the LLM name it.
+    pub model: String,
+
+    /// Tokens of the prompt.
+    pub prompt_tokens: u32,
+
+    /// Tokens of the response.
+    pub completion_tokens: u32,
+
+    /// Whether the tokens were estimated because the provider did not report them.
+    pub estimated: bool,
+}
+
+impl UsageRecord {
+    /// Record the usage reported by a provider, with the model of the LLM if the response does not name it.
+    pub fn new(step: &str, usage: TokenUsage, model: Option<&str>) -> Self {
+        UsageRecord {
+            step: step.to_string(),
+            model: usage.model.as_deref().or(model).unwrap_or("unknown").to_string(),
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+            estimated: false,
+        }
+    }
+
+    /// Record estimated tokens.
+    pub fn estimated(step: &str, prompt_tokens: u32, completion_tokens: u32, model: Option<&str>) -> Self {
+        UsageRecord {
+            step: step.to_string(),
+            model: model.unwrap_or("unknown").to_string(),
+            prompt_tokens,
+            completion_tokens,
+            estimated: true,
+        }
+    }
+
+    /// The same record under another step.
+    pub fn with_step(mut self, step: &str) -> Self {
+        self.step = step.to_string();
+        self
+    }
+}
+
+/// Totals of usage records.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct UsageTotals {
+    /// Number of LLM calls.
+    pub calls: usize,
+
+    /// Tokens of the prompts.
+    pub prompt_tokens: u32,
+
+    /// Tokens of the responses.
+    pub completion_tokens: u32,
+
+    /// Cost of the calls to priced models.
+    pub cost: f64,
+
+    /// Number of calls to models without a price, which are not counted in the cost.
+    pub unpriced_calls: usize,
+}
+
+impl UsageTotals {
+    /// Total number of prompt and completion tokens.
+    pub fn total_tokens(&self) -> u32 {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    fn add(&mut self, record: &UsageRecord, pricing: &PricingTable) {
+        self.calls += 1;
+        self.prompt_tokens += record.prompt_tokens;
+        self.completion_tokens += record.completion_tokens;
+        match pricing.price(&record.model) {
+            Some(price) => self.cost += price.cost(record.prompt_tokens, record.completion_tokens),
+            None => self.unpriced_calls += 1,
+        }
+    }
+}
+
+/// Records of the LLM calls of pipeline executions. Clones share the same records, so that a tracker can
+/// be given to several pipelines to account for all of them, while each record keeps its step.
+#[derive(Debug, Clone, Default)]
+pub struct UsageTracker {
+    records: Arc<Mutex<Vec<UsageRecord>>>,
+    pricing: Arc<PricingTable>,
+}
+
+impl UsageTracker {
+    /// Create a tracker without records or prices.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the prices the records are priced with. Clones made before keep their prices.
+    pub fn with_pricing(mut self, pricing: PricingTable) -> Self {
+        self.pricing = Arc::new(pricing);
+        self
+    }
+
+    /// Add records.
+    pub fn record<I: IntoIterator<Item = UsageRecord>>(&self, records: I) {
+        self.records.lock().unwrap().extend(records);
+    }
+
+    /// Records, in the order they were added.
+    pub fn records(&self) -> Vec<UsageRecord> {
+        self.records.lock().unwrap().clone()
+    }
+
+    /// Totals of all the records.
+    pub fn total(&self) -> UsageTotals {
+        let mut total = UsageTotals::default();
+        for record in self.records.lock().unwrap().iter() {
+            total.add(record, &self.pricing);
+        }
+        total
+    }
+
+    /// Totals of the records of each step, in the order the steps were first recorded.
+    pub fn by_step(&self) -> Vec<(String, UsageTotals)> {
+        self.group_by(|record| &record.step)
+    }
+
+    /// Totals of the records of each model, in the order the models were first recorded.
+    pub fn by_model(&self) -> Vec<(String, UsageTotals)> {
+        self.group_by(|record| &record.model)
+    }
+
+    /// Remove all the records.
+    pub fn reset(&self) {
+        self.records.lock().unwrap().clear();
+    }
+
+    fn group_by<F: Fn(&UsageRecord) -> &String>(&self, key: F) -> Vec<(String, UsageTotals)> {
+        let mut groups: Vec<(String, UsageTotals)> = Vec::new();
+        for record in self.records.lock().unwrap().iter() {
+            let index = match groups.iter().position(|(name, _)| name == key(record)) {
+                Some(index) => index,
+                None => {
+                    groups.push((key(record).clone(), UsageTotals::default()));
+                    groups.len() - 1
+                }
+            };
+            groups[index].1.add(record, &self.pricing);
+        }
+        groups
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_usage_tracker() {
+        let pricing = PricingTable::new().with_price("gpt-4", 0.03, 0.06).with_price("gpt-4-turbo", 0.01, 0.03);
+        assert_eq!(pricing.price("gpt-4-0613").unwrap().prompt_per_1k, 0.03);
+        assert_eq!(pricing.price("gpt-4-turbo-preview").unwrap().prompt_per_1k, 0.01);
+        assert_eq!(pricing.price("claude-2"), None);
+
+        let tracker = UsageTracker::new().with_pricing(pricing);
+        let usage = |prompt_tokens, completion_tokens, model: &str| TokenUsage {
+            prompt_tokens,
+            completion_tokens,
+            model: Some(model.to_string()),
+        };
+        tracker.clone().record([
+            UsageRecord::new("summarize", usage(1000, 500, "gpt-4-0613"), None),
+            UsageRecord::new("review", usage(2000, 1000, "gpt-4-turbo"), None),
+            UsageRecord::estimated("review", 100, 50, Some("mistral-7b")),
+        ]);
+
+        let total = tracker.total();
+        assert_eq!((total.calls, total.total_tokens(), total.unpriced_calls), (3, 4650, 1));
+        assert!((total.cost - (0.03 + 0.03 + 0.02 + 0.03)).abs() < 1e-9);
+        let steps = tracker.by_step();
+        assert_eq!(
+            steps.iter().map(|(step, _)| step.as_str()).collect::<Vec<_>>(),
+            vec!["summarize", "review"]
+        );
+        assert_eq!(steps[1].1.prompt_tokens, 2100);
+        assert_eq!(tracker.by_model()[2].0, "mistral-7b");
+
+        tracker.reset();
+        assert_eq!(tracker.total(), UsageTotals::default());
+    }
+}
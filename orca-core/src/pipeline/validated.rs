@@ -0,0 +1,263 @@
+use super::context::PipelineContext;
+use super::describe::PipelineDescription;
+use super::{Pipeline, PipelineResult};
+use crate::prompt::TemplateEngine;
+use crate::record::language::{detect_language, language_name};
+
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
+
+/// Checks the output of a pipeline against a semantic constraint, e.g. that the answer cites a source.
+/// The error message of a failed check is sent back to the model as corrective feedback.
+pub trait Validator: Send + Sync {
+    fn validate(&self, output: &str) -> Result<()>;
+}
+
+impl<F> Validator for F
+where
+    F: Fn(&str) -> Result<()> + Send + Sync,
+{
+    fn validate(&self, output: &str) -> Result<()> {
+        self(output)
+    }
+}
+
+/// Validator requiring the output to contain at least one of the given texts, ignoring case.
+///
+/// # Example
+/// ```
+/// use orca_core::pipeline::validated::{contains_any, Validator};
+///
+/// let validator = contains_any(&["source:", "according to"]);
+/// assert!(validator.validate("Paris, according to the atlas.").is_ok());
+/// assert!(validator.validate("Paris.").is_err());
+/// ```
+pub fn contains_any(needles: &[&str]) -> impl Validator {
+    let needles: Vec<String> = needles.iter().map(|needle| needle.to_lowercase()).collect();
+    move |output: &str| {
+        let output = output.to_lowercase();
+        match needles.iter().any(|needle| output.contains(needle)) {
+            true => Ok(()),
+            false => Err(anyhow!("The answer must mention one of: {}.", needles.join(", "))),
+        }
+    }
+}
+
+/// Validator requiring the output to be at most the given number of characters long.
+pub fn max_length(max_chars: usize) -> impl Validator {
+    move |output: &str| match output.chars().count() <= max_chars {
+        true => Ok(()),
+        false => Err(anyhow!("The answer must be at most {} characters long.", max_chars)),
+    }
+}
+
+/// Validator requiring the output to be written in the given language, as an ISO 639-3 code. Outputs
+/// whose language cannot be detected reliably, e.g. very short ones, pass.
+///
+/// # Example
+/// ```
+/// use orca_core::pipeline::validated::{in_language, Validator};
+///
+/// let validator = in_language("spa");
+/// assert!(validator.validate("La orca es el miembro más grande de la familia de los delfines.").is_ok());
+/// assert!(validator.validate("The orca is the largest member of the dolphin family.").is_err());
+/// ```
+pub fn in_language(language: &str) -> impl Validator {
+    let language = language.to_string();
+    move |output: &str| match detect_language(output) {
+        Some(detected) if detected != language => Err(anyhow!(
+            "The answer must be written in {}.",
+            language_name(&language).unwrap_or(&language)
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Pipeline wrapper that validates the output of a pipeline and re-executes it with corrective feedback
+/// until the output passes every validator, up to a maximum number of retries.
+///
+/// On a retry, the invalid output and the feedback are appended to the target template as an assistant
+/// and a user message, so the wrapped pipeline should render chat templates.
+pub struct ValidatedPipeline<P> {
+    /// The wrapped pipeline.
+    pipeline: P,
+
+    /// Validators the output must pass.
+    validators: Vec<Arc<dyn Validator>>,
+
+    /// Number of times the pipeline is re-executed with feedback.
+    max_retries: usize,
+}
+
+impl<P: Pipeline + Clone> ValidatedPipeline<P> {
+    /// Wraps a pipeline, retrying up to two times by default.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use orca_core::llm::openai::OpenAI;
+    /// use orca_core::pipeline::simple::LLMPipeline;
+    /// use orca_core::pipeline::validated::{contains_any, ValidatedPipeline};
+    ///
+    /// let client = OpenAI::new();
+    /// let pipeline = LLMPipeline::new(&client)
+    ///     .load_template("answer", "{{#chat}}{{#user}}Who wrote Hamlet? Cite a source.{{/user}}{{/chat}}")
+    ///     .unwrap();
+    /// let pipeline = ValidatedPipeline::new(pipeline)
+    ///     .with_validator(contains_any(&["source:"]))
+    ///     .with_max_retries(3);
+    /// ```
+    pub fn new(pipeline: P) -> ValidatedPipeline<P> {
+        ValidatedPipeline {
+            pipeline,
+            validators: Vec::new(),
+            max_retries: 2,
+        }
+    }
+
+    /// Adds a validator the output must pass.
+    pub fn with_validator<V: Validator + 'static>(mut self, validator: V) -> Self {
+        self.validators.push(Arc::new(validator));
+        self
+    }
+
+    /// Adds a predicate the output must satisfy, with the feedback sent to the model when it does not.
+    pub fn with_predicate<F>(self, predicate: F, feedback: &str) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        let feedback = feedback.to_string();
+        self.with_validator(move |output: &str| match predicate(output) {
+            true => Ok(()),
+            false => Err(anyhow!("{}", feedback)),
+        })
+    }
+
+    /// Sets the number of times the pipeline is re-executed with feedback.
+    pub fn with_max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Runs every validator, returning the feedback of those that failed.
+    fn feedback(&self, output: &str) -> Option<String> {
+        let errors: Vec<String> = self
+            .validators
+            .iter()
+            .filter_map(|validator| validator.validate(output).err())
+            .map(|error| error.to_string())
+            .collect();
+        match errors.is_empty() {
+            true => None,
+            false => Some(errors.join(" ")),
+        }
+    }
+}
+
+/// Escapes handlebars expressions so that model output appended to a template is rendered verbatim.
+fn escape_template(text: &str) -> String {
+    text.replace("{{", "\\{{")
+}
+
+#[async_trait::async_trait]
+impl<P: Pipeline + Clone> Pipeline for ValidatedPipeline<P> {
+    async fn execute(&self, target: &str) -> Result<PipelineResult> {
+        let mut pipeline = self.pipeline.clone();
+        let mut result = pipeline.execute(target).await?;
+        for _ in 0..self.max_retries {
+            let content = result.content();
+            let feedback = match self.feedback(&content) {
+                Some(feedback) => feedback,
+                None => return Ok(result),
+            };
+            log::debug!("Output failed validation, retrying: {}", feedback);
+            let template_engine = pipeline.template_engine();
+            template_engine.append_assistant(target, &escape_template(&content))?;
+            template_engine.append_user(
+                target,
+                &escape_template(&format!(
+                    "Your answer does not meet the requirements: {} Answer again, fixing these issues.",
+                    feedback
+                )),
+            )?;
+            result = pipeline.execute(target).await?;
+        }
+        match self.feedback(&result.content()) {
+            Some(feedback) => Err(anyhow!(
+                "Output is not valid after {} retries: {}",
+                self.max_retries,
+                feedback
+            )),
+            None => Ok(result),
+        }
+    }
+
+    fn template_engine(&mut self) -> &mut TemplateEngine {
+        self.pipeline.template_engine()
+    }
+
+    fn context(&mut self) -> Option<&mut PipelineContext> {
+        self.pipeline.context()
+    }
+
+    fn describe(&self) -> PipelineDescription {
+        PipelineDescription::new("ValidatedPipeline", "").with_step(self.pipeline.describe())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::llm::{LLMResponse, LLM};
+    use crate::pipeline::simple::LLMPipeline;
+    use crate::prompt::Prompt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// LLM that cites a source only once it has been asked to.
+    #[derive(Clone, Default)]
+    struct Forgetful {
+        calls: Arc<AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl LLM for Forgetful {
+        async fn generate(&self, prompt: Box<dyn Prompt>) -> Result<LLMResponse> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let chat = prompt.to_chat()?;
+            let response = match chat.to_vec().last().unwrap().content.contains("must mention") {
+                true => "Shakespeare (source: Folger Library).",
+                false => "Shakespeare.",
+            };
+            Ok(LLMResponse::Quantized(response.to_string()))
+        }
+    }
+
+    fn pipeline(llm: &Forgetful) -> LLMPipeline<Forgetful> {
+        LLMPipeline::new(llm)
+            .load_template("answer", "{{#chat}}{{#user}}Who wrote Hamlet?{{/user}}{{/chat}}")
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_retry_with_feedback() {
+        let llm = Forgetful::default();
+        let validated = ValidatedPipeline::new(pipeline(&llm)).with_validator(contains_any(&["source:"]));
+        let result = validated.execute("answer").await.unwrap();
+        assert_eq!(result.content(), "Shakespeare (source: Folger Library).");
+        assert_eq!(llm.calls.load(Ordering::SeqCst), 2);
+
+        // The wrapped pipeline is left untouched for the next execution.
+        validated.execute("answer").await.unwrap();
+        assert_eq!(llm.calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn test_retries_exhausted() {
+        let llm = Forgetful::default();
+        let validated = ValidatedPipeline::new(pipeline(&llm))
+            .with_predicate(|output| output.contains("Marlowe"), "The answer must mention Marlowe.")
+            .with_max_retries(1);
+        let error = validated.execute("answer").await.unwrap_err();
+        assert!(error.to_string().contains("Marlowe"));
+        assert_eq!(llm.calls.load(Ordering::SeqCst), 2);
+    }
+}
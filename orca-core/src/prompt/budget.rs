@@ -0,0 +1,252 @@
+//! Token budgeting across the parts of a prompt.
+//!
+//! A `TokenBudget` splits the context window of a model between the system prompt, the conversation
+//! memory, the retrieved documents and the user question, and trims each part to its share before the
+//! prompt is rendered. Parts that need less than their share give the rest to the other parts, so a
+//! short question leaves more room for documents. Token counts are estimated with [`estimate_tokens`].
+
+use super::chat::Message;
+use super::estimate_tokens;
+
+/// Number of tokens given to each part of a prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Allocation {
+    pub system: usize,
+    pub memory: usize,
+    pub documents: usize,
+    pub question: usize,
+}
+
+impl Allocation {
+    fn to_array(self) -> [usize; 4] {
+        [self.system, self.memory, self.documents, self.question]
+    }
+
+    fn from_array([system, memory, documents, question]: [usize; 4]) -> Self {
+        Allocation {
+            system,
+            memory,
+            documents,
+            question,
+        }
+    }
+
+    /// Total number of tokens.
+    pub fn total(&self) -> usize {
+        self.to_array().iter().sum()
+    }
+}
+
+/// The parts of a prompt that share the context window.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PromptParts {
+    /// The system prompt.
+    pub system: String,
+
+    /// Previous messages of the conversation, oldest first.
+    pub memory: Vec<Message>,
+
+    /// Retrieved documents, most relevant first.
+    pub documents: Vec<String>,
+
+    /// The user question.
+    pub question: String,
+}
+
+impl PromptParts {
+    /// Estimated number of tokens of each part.
+    pub fn tokens(&self) -> Allocation {
+        Allocation {
+            system: estimate_tokens(&self.system),
+            memory: self.memory.iter().map(|message| estimate_tokens(&message.content)).sum(),
+            documents: self.documents.iter().map(|document| estimate_tokens(document)).sum(),
+            question: estimate_tokens(&self.question),
+        }
+    }
+}
+
+/// Splits the context window of a model between the parts of a prompt.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenBudget {
+    /// Size of the context window of the model, in tokens.
+    context_window: usize,
+
+    /// Tokens kept free for the response.
+    reserved: usize,
+
+    /// Share of the system prompt, memory, documents and question, in this order.
+    ratios: [f32; 4],
+}
+
+impl TokenBudget {
+    /// Create a budget for a model with the given context window. By default, 10% of the window goes
+    /// to the system prompt, 30% to memory, 50% to documents and 10% to the question.
+    pub fn new(context_window: usize) -> Self {
+        TokenBudget {
+            context_window,
+            reserved: 0,
+            ratios: [0.1, 0.3, 0.5, 0.1],
+        }
+    }
+
+    /// Keep tokens free for the response, e.g. the maximum number of tokens the model may generate,
+    /// as well as for any template text around the budgeted parts.
+    pub fn with_reserved_tokens(mut self, reserved: usize) -> Self {
+        self.reserved = reserved;
+        self
+    }
+
+    /// Set the relative shares of the system prompt, memory, documents and question. Shares need not
+    /// add up to one; negative shares are treated as zero.
+    pub fn with_ratios(mut self, system: f32, memory: f32, documents: f32, question: f32) -> Self {
+        self.ratios = [system, memory, documents, question].map(|ratio| ratio.max(0.0));
+        self
+    }
+
+    /// Number of tokens that the prompt may use.
+    pub fn available(&self) -> usize {
+        self.context_window.saturating_sub(self.reserved)
+    }
+
+    /// Allocate the available tokens to parts needing the given number of tokens. Parts that need
+    /// less than their share get what they need, and the rest is split between the other parts
+    /// according to their shares.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::prompt::budget::{Allocation, TokenBudget};
+    ///
+    /// let budget = TokenBudget::new(1000);
+    /// let needs = Allocation { system: 20, memory: 0, documents: 5000, question: 30 };
+    /// let allocation = budget.allocate(needs);
+    /// assert_eq!(allocation, Allocation { system: 20, memory: 0, documents: 950, question: 30 });
+    /// ```
+    pub fn allocate(&self, needs: Allocation) -> Allocation {
+        let needs = needs.to_array();
+        let mut allocation = [0; 4];
+        let mut open: Vec<usize> = (0..4).filter(|&part| needs[part] > 0).collect();
+        let mut remaining = self.available();
+        while !open.is_empty() {
+            let total: f32 = open.iter().map(|&part| self.ratios[part]).sum();
+            let share = |part: usize| match total > 0.0 {
+                true => (remaining as f32 * self.ratios[part] / total) as usize,
+                false => 0,
+            };
+            let (satisfied, unsatisfied): (Vec<usize>, Vec<usize>) =
+                open.iter().copied().partition(|&part| needs[part] <= share(part));
+            if satisfied.is_empty() {
+                for part in unsatisfied {
+                    allocation[part] = share(part);
+                }
+                break;
+            }
+            for part in satisfied {
+                allocation[part] = needs[part];
+                remaining -= needs[part];
+            }
+            open = unsatisfied;
+        }
+        Allocation::from_array(allocation)
+    }
+
+    /// Trim each part of a prompt to its allocation. The system prompt and question are truncated,
+    /// the oldest messages of memory are dropped first, and the least relevant documents are dropped
+    /// first, the last document kept being truncated to fill the allocation.
+    pub fn fit(&self, parts: PromptParts) -> PromptParts {
+        let allocation = self.allocate(parts.tokens());
+
+        let mut memory_tokens = 0;
+        let kept = parts
+            .memory
+            .iter()
+            .rev()
+            .take_while(|message| {
+                memory_tokens += estimate_tokens(&message.content);
+                memory_tokens <= allocation.memory
+            })
+            .count();
+        let memory = parts.memory[parts.memory.len() - kept..].to_vec();
+
+        let mut documents = Vec::new();
+        let mut remaining = allocation.documents;
+        for document in parts.documents {
+            let tokens = estimate_tokens(&document);
+            if tokens <= remaining {
+                remaining -= tokens;
+                documents.push(document);
+            } else {
+                if remaining > 0 {
+                    documents.push(truncate_tokens(&document, remaining));
+                }
+                break;
+            }
+        }
+
+        PromptParts {
+            system: truncate_tokens(&parts.system, allocation.system),
+            memory,
+            documents,
+            question: truncate_tokens(&parts.question, allocation.question),
+        }
+    }
+}
+
+/// Truncate a text to at most the given number of estimated tokens.
+///
+/// # Example
+/// ```
+/// use orca_core::prompt::budget::truncate_tokens;
+///
+/// assert_eq!(truncate_tokens("Hello, world", 2), "Hello, w");
+/// ```
+pub fn truncate_tokens(text: &str, tokens: usize) -> String {
+    text.chars().take(tokens * 4).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prompt::chat::Role;
+
+    #[test]
+    fn test_allocate() {
+        let budget = TokenBudget::new(1100).with_reserved_tokens(100);
+        let needs = Allocation {
+            system: 50,
+            memory: 2000,
+            documents: 2000,
+            question: 40,
+        };
+        let allocation = budget.allocate(needs);
+        assert_eq!(allocation.system, 50);
+        assert_eq!(allocation.question, 40);
+        assert_eq!(allocation.memory, 341);
+        assert_eq!(allocation.documents, 568);
+        assert!(allocation.total() <= budget.available());
+
+        let needs = Allocation {
+            system: 10,
+            memory: 10,
+            documents: 10,
+            question: 10,
+        };
+        assert_eq!(budget.allocate(needs), needs);
+    }
+
+    #[test]
+    fn test_fit() {
+        let budget = TokenBudget::new(100).with_ratios(0.0, 1.0, 1.0, 0.0);
+        let parts = PromptParts {
+            system: "You are helpful.".to_string(),
+            memory: (0..10).map(|i| Message::new(Role::User, &format!("{:0>40}", i))).collect(),
+            documents: vec!["a".repeat(160), "b".repeat(160), "c".repeat(160)],
+            question: "What is in the documents?".to_string(),
+        };
+
+        let fitted = budget.fit(parts.clone());
+        assert_eq!(fitted.system, "");
+        assert_eq!(fitted.question, "");
+        assert_eq!(fitted.memory, parts.memory[5..].to_vec());
+        assert_eq!(fitted.documents, vec!["a".repeat(160), "b".repeat(40)]);
+    }
+}
@@ -1,7 +1,19 @@
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose, Engine};
 use handlebars::{Context, Handlebars as Registry, Helper, HelperDef, HelperResult, Output, RenderContext, Renderable};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value as JsonValue};
+
+use crate::llm::openai::OpenAIMessage;
+use crate::llm::tools::ToolCall;
 
 use std::fmt::{self, Display, Formatter};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Markers around an image rendered by the `image` helper, picked up by the enclosing role helper.
+const IMAGE_OPEN: &str = "<orca:image>";
+const IMAGE_CLOSE: &str = "</orca:image>";
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "lowercase")]
@@ -9,6 +21,8 @@ pub enum Role {
     System,
     User,
     Assistant,
+    /// Output of a tool, e.g. search results, named after the tool that produced it.
+    Tool,
 }
 
 #[derive(Default, Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -20,17 +34,31 @@ impl From<&str> for Role {
             "system" => Role::System,
             "user" => Role::User,
             "assistant" => Role::Assistant,
+            "tool" => Role::Tool,
             _ => Role::System,
         }
     }
 }
 
+impl Role {
+    /// Capitalized name of the role, used in transcripts.
+    pub fn title(&self) -> &'static str {
+        match self {
+            Role::System => "System",
+            Role::User => "User",
+            Role::Assistant => "Assistant",
+            Role::Tool => "Tool",
+        }
+    }
+}
+
 impl Display for Role {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
             Role::System => write!(f, "system"),
             Role::User => write!(f, "user"),
             Role::Assistant => write!(f, "assistant"),
+            Role::Tool => write!(f, "tool"),
         }
     }
 }
@@ -49,24 +77,346 @@ impl ChatPrompt {
     pub fn to_vec_ref(&self) -> &Vec<Message> {
         &self.0
     }
+
+    /// Mark the messages up to and including `index` as a cacheable prefix. Providers that support
+    /// prompt caching reuse the processed prefix across calls, so large static system prompts and
+    /// retrieved contexts should come first and be marked here.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::prompt::chat::ChatPrompt;
+    ///
+    /// let chat = ChatPrompt::from_openai_json(r#"[
+    ///     {"role": "system", "content": "You answer questions about the documents below..."},
+    ///     {"role": "user", "content": "Who wrote the first document?"}
+    /// ]"#).unwrap();
+    /// let chat = chat.cache_up_to(0);
+    /// assert_eq!(chat.cached_prefix_len(), 1);
+    /// ```
+    pub fn cache_up_to(mut self, index: usize) -> Self {
+        if let Some(message) = self.0.get_mut(index) {
+            message.cache_control = Some(CacheControl::Ephemeral);
+        }
+        self
+    }
+
+    /// The messages sent at or after a time, in milliseconds since the Unix epoch, e.g. to keep a
+    /// window of recent messages in memory. Messages without a timestamp are kept.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::prompt::chat::{ChatPrompt, Message, Role};
+    ///
+    /// let chat = ChatPrompt::from_openai_json(r#"[
+    ///     {"role": "user", "content": "Hi", "timestamp": 1000},
+    ///     {"role": "user", "content": "Are you there?", "timestamp": 5000}
+    /// ]"#).unwrap();
+    /// assert_eq!(chat.since(2000).to_vec()[0].content, "Are you there?");
+    /// ```
+    pub fn since(&self, timestamp: u64) -> ChatPrompt {
+        ChatPrompt(
+            self.0
+                .iter()
+                .filter(|message| message.timestamp.map_or(true, |sent| sent >= timestamp))
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// Number of messages in the cacheable prefix, i.e. up to the last message with a cache
+    /// breakpoint.
+    pub fn cached_prefix_len(&self) -> usize {
+        self.0.iter().rposition(|message| message.cache_control.is_some()).map_or(0, |i| i + 1)
+    }
+
+    /// Serialize the conversation to OpenAI-style JSON, i.e. `{"messages": [{"role": ..., "content": ...}]}`.
+    /// Messages are written as they are sent to the OpenAI API, so that the JSON can be sent as is: images
+    /// are content parts, tool calls are in the OpenAI shape, and ids, timestamps, metadata and cache
+    /// breakpoints are left out.
+    pub fn to_openai_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(&OpenAIConversation {
+            messages: self.0.iter().map(OpenAIMessage::from).collect(),
+        })?)
+    }
+
+    /// Deserialize a conversation from OpenAI-style JSON. Both a `{"messages": [...]}` object and a
+    /// bare array of messages are accepted. Tool messages are named after the call they answer, and
+    /// the ids, timestamps, metadata and cache breakpoints of messages are kept if present.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::prompt::chat::ChatPrompt;
+    ///
+    /// let chat = ChatPrompt::from_openai_json(r#"{"messages": [{"role": "user", "content": "Hi"}]}"#).unwrap();
+    /// assert_eq!(chat.to_vec().len(), 1);
+    /// ```
+    pub fn from_openai_json(json: &str) -> Result<ChatPrompt> {
+        // Parse by the top-level type, so that errors refer to the shape the JSON was meant to have.
+        let messages = match serde_json::from_str::<JsonValue>(json)? {
+            JsonValue::Array(messages) => serde_json::from_value::<Vec<ImportedMessage>>(JsonValue::Array(messages))?,
+            conversation => serde_json::from_value::<OpenAIConversation<ImportedMessage>>(conversation)?.messages,
+        };
+        let mut chat = ChatPrompt(Vec::with_capacity(messages.len()));
+        for imported in messages {
+            let mut message = Message {
+                cache_control: imported.cache_control,
+                id: imported.id,
+                timestamp: imported.timestamp,
+                metadata: imported.metadata,
+                ..Message::from(imported.message)
+            };
+            if message.role == Role::Tool && message.name.is_none() {
+                message.name = chat.tool_name(message.tool_call_id.as_deref());
+            }
+            chat.0.push(message);
+        }
+        Ok(chat)
+    }
+
+    /// Name of the tool called with the given id by an earlier message.
+    fn tool_name(&self, call_id: Option<&str>) -> Option<String> {
+        self.0
+            .iter()
+            .flat_map(|message| &message.tool_calls)
+            .find(|call| Some(call.id.as_str()) == call_id)
+            .map(|call| call.name.clone())
+    }
+
+    /// Render the conversation as a markdown transcript. Named messages are attributed to their
+    /// participant.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::prompt::chat::ChatPrompt;
+    ///
+    /// let chat = ChatPrompt::from_openai_json(r#"[
+    ///     {"role": "user", "content": "Hi"},
+    ///     {"role": "user", "name": "alice", "content": "Hello"}
+    /// ]"#).unwrap();
+    /// assert_eq!(chat.to_markdown(), "**User:** Hi\n\n**User (alice):** Hello\n");
+    /// ```
+    pub fn to_markdown(&self) -> String {
+        self.0
+            .iter()
+            .map(|message| match &message.name {
+                Some(name) => format!("**{} ({}):** {}\n", message.role.title(), name, message.content),
+                None => format!("**{}:** {}\n", message.role.title(), message.content),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// OpenAI-style conversation envelope used for export and import.
+#[derive(Serialize, Deserialize)]
+struct OpenAIConversation<M> {
+    messages: Vec<M>,
+}
+
+/// Message of an imported conversation: the fields of the OpenAI API, and those kept by orca if any.
+#[derive(Deserialize)]
+struct ImportedMessage {
+    #[serde(flatten)]
+    message: OpenAIMessage,
+    #[serde(default)]
+    cache_control: Option<CacheControl>,
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    timestamp: Option<u64>,
+    #[serde(default)]
+    metadata: Map<String, JsonValue>,
+}
+
+/// Provider-side prompt caching hint. A message with a cache control is a cache breakpoint: the
+/// prompt up to and including it may be cached by providers that support it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum CacheControl {
+    /// Short-lived cache, refreshed each time it is used.
+    Ephemeral,
+}
+
+/// Image attached to a message, given by URL or as a base64 data URL, for models with vision.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Image {
+    /// URL of the image, or a `data:` URL holding the image.
+    pub url: String,
+
+    /// Resolution at which the model looks at the image (low, high or auto)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl Image {
+    /// Create an image from a URL.
+    pub fn new(url: &str) -> Self {
+        Image {
+            url: url.to_string(),
+            detail: None,
+        }
+    }
+
+    /// Create an image from base64 encoded data of the given MIME type, e.g. `image/png`.
+    pub fn from_base64(mime_type: &str, data: &str) -> Self {
+        Image::new(&format!("data:{};base64,{}", mime_type, data))
+    }
+
+    /// Create an image from raw bytes of the given MIME type, e.g. a rendered PDF page.
+    pub fn from_bytes(mime_type: &str, bytes: &[u8]) -> Self {
+        Image::from_base64(mime_type, &general_purpose::STANDARD.encode(bytes))
+    }
+
+    /// Read an image from a PNG, JPEG, GIF or WebP file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or_default();
+        let mime_type = match extension.to_lowercase().as_str() {
+            "png" => "image/png",
+            "jpg" | "jpeg" => "image/jpeg",
+            "gif" => "image/gif",
+            "webp" => "image/webp",
+            _ => return Err(anyhow!("Unsupported image file: {}", path.display())),
+        };
+        Ok(Image::from_bytes(mime_type, &std::fs::read(path)?))
+    }
+
+    /// Set the resolution at which the model looks at the image.
+    pub fn with_detail(mut self, detail: &str) -> Self {
+        self.detail = Some(detail.to_string());
+        self
+    }
 }
 
+/// Message of a conversation. The id, timestamp and metadata are kept by orca, e.g. in memory and
+/// exported conversations, but are not sent to providers.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Message {
-    /// The message role (system, user, assistant)
+    /// The message role (system, user, assistant, tool)
     pub role: Role,
 
+    /// Name of the participant who wrote the message, or of the tool that produced it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
     /// The message text
     pub content: String,
+
+    /// Prompt caching breakpoint, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_control: Option<CacheControl>,
+
+    /// Images attached to the message
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<Image>,
+
+    /// Identifier of the message, e.g. to reference it from audit logs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+
+    /// Time the message was sent, in milliseconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+
+    /// Application-defined metadata
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub metadata: Map<String, JsonValue>,
+
+    /// Identifier of the tool call a tool message holds the output of
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+
+    /// Tools called by an assistant message, kept so that the calls can be sent back with their outputs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
 }
 
 impl Message {
     pub fn new(role: Role, content: &str) -> Message {
         Message {
             role,
+            name: None,
             content: content.to_string(),
+            cache_control: None,
+            images: Vec::new(),
+            id: None,
+            timestamp: None,
+            metadata: Map::new(),
+            tool_call_id: None,
+            tool_calls: Vec::new(),
         }
     }
+
+    /// Create a tool message holding the output of a tool call, named after the called tool.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::llm::tools::ToolCall;
+    /// use orca_core::prompt::chat::{Message, Role};
+    ///
+    /// let call = ToolCall::new("call_1", "search", r#"{"pod": "J"}"#);
+    /// let history = vec![
+    ///     Message::new(Role::Assistant, "").with_tool_calls(vec![call.clone()]),
+    ///     Message::tool_output(&call, "Orcas were seen near Seattle."),
+    /// ];
+    /// ```
+    pub fn tool_output(call: &ToolCall, output: &str) -> Message {
+        Message::new(Role::Tool, output).with_name(&call.name).with_tool_call_id(&call.id)
+    }
+
+    /// Set the identifier of the message.
+    pub fn with_id(mut self, id: &str) -> Self {
+        self.id = Some(id.to_string());
+        self
+    }
+
+    /// Set the time the message was sent, in milliseconds since the Unix epoch.
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Set the time the message was sent to now.
+    pub fn with_timestamp_now(self) -> Self {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        self.with_timestamp(now.as_millis() as u64)
+    }
+
+    /// Add a metadata entry to the message.
+    pub fn with_metadata<V: Into<JsonValue>>(mut self, key: &str, value: V) -> Self {
+        self.metadata.insert(key.to_string(), value.into());
+        self
+    }
+
+    /// Set the name of the participant who wrote the message, or of the tool that produced it.
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    /// Set the identifier of the tool call the message holds the output of.
+    pub fn with_tool_call_id(mut self, id: &str) -> Self {
+        self.tool_call_id = Some(id.to_string());
+        self
+    }
+
+    /// Set the tools called by the message.
+    pub fn with_tool_calls(mut self, calls: Vec<ToolCall>) -> Self {
+        self.tool_calls = calls;
+        self
+    }
+
+    /// Attach an image to the message.
+    pub fn with_image(mut self, image: Image) -> Self {
+        self.images.push(image);
+        self
+    }
+
+    /// Mark the message as a prompt caching breakpoint.
+    pub fn with_cache_control(mut self) -> Self {
+        self.cache_control = Some(CacheControl::Ephemeral);
+        self
+    }
 }
 
 impl Display for Message {
@@ -79,8 +429,13 @@ impl Display for Message {
 pub struct RoleHelper;
 #[derive(Clone)]
 pub struct ChatHelper;
+#[derive(Clone)]
+pub struct ImageHelper;
 
 impl HelperDef for RoleHelper {
+    /// Render the block as a message whose role is the name of the helper. A `name` parameter sets
+    /// the participant or tool the message comes from, e.g. `{{#tool name="search"}}...{{/tool}}`, and a
+    /// `call_id` parameter the tool call a tool message answers.
     fn call<'reg: 'rc, 'rc>(
         &self,
         h: &Helper<'reg, 'rc>,
@@ -91,17 +446,75 @@ impl HelperDef for RoleHelper {
     ) -> HelperResult {
         let role = h.name();
         let content = h.template().map_or(Ok(String::new()), |t| t.renders(_r, ctx, rc))?;
+        let (content, images) = extract_images(&content);
 
+        let images = if images.is_empty() {
+            String::new()
+        } else {
+            format!(r#", "images": {}"#, serde_json::to_string(&images).unwrap_or_default())
+        };
+        let name = match h.hash_get("name").and_then(|name| name.value().as_str()) {
+            Some(name) => format!(r#", "name": {}"#, serde_json::to_string(name).unwrap_or_default()),
+            None => String::new(),
+        };
+        let call_id = match h.hash_get("call_id").and_then(|id| id.value().as_str()) {
+            Some(id) => format!(r#", "tool_call_id": {}"#, serde_json::to_string(id).unwrap_or_default()),
+            None => String::new(),
+        };
         let json = format!(
-            r#"{{"role": "{}", "content": "{}"}},"#,
+            r#"{{"role": "{}"{}{}, "content": "{}"{}}},"#,
             role,
-            clean_string(content.trim())
+            name,
+            call_id,
+            clean_string(content.trim()),
+            images
         );
         out.write(&json)?;
         Ok(())
     }
 }
 
+impl HelperDef for ImageHelper {
+    /// Attach the image whose URL is the content of the block to the enclosing message. A `detail`
+    /// parameter sets the resolution, e.g. `{{#image detail="low"}}{{url}}{{/image}}`.
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _r: &'reg Registry<'reg>,
+        ctx: &'rc Context,
+        rc: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let url = h.template().map_or(Ok(String::new()), |t| t.renders(_r, ctx, rc))?;
+        let mut image = Image::new(url.trim());
+        if let Some(detail) = h.hash_get("detail").and_then(|detail| detail.value().as_str()) {
+            image = image.with_detail(detail);
+        }
+        let json = serde_json::to_string(&image).unwrap_or_default();
+        out.write(&format!("{}{}{}", IMAGE_OPEN, json, IMAGE_CLOSE))?;
+        Ok(())
+    }
+}
+
+/// Take the images rendered by the `image` helper out of the content of a message.
+fn extract_images(content: &str) -> (String, Vec<Image>) {
+    let mut text = String::new();
+    let mut images = Vec::new();
+    let mut rest = content;
+    while let Some(start) = rest.find(IMAGE_OPEN) {
+        let Some(len) = rest[start..].find(IMAGE_CLOSE) else {
+            break;
+        };
+        text.push_str(&rest[..start]);
+        if let Ok(image) = serde_json::from_str(&rest[start + IMAGE_OPEN.len()..start + len]) {
+            images.push(image);
+        }
+        rest = &rest[start + len + IMAGE_CLOSE.len()..];
+    }
+    text.push_str(rest);
+    (text, images)
+}
+
 impl HelperDef for ChatHelper {
     fn call<'reg: 'rc, 'rc>(
         &self,
@@ -121,6 +534,7 @@ impl HelperDef for ChatHelper {
 
 impl Copy for RoleHelper {}
 impl Copy for ChatHelper {}
+impl Copy for ImageHelper {}
 
 pub fn remove_last_comma(content: &str) -> String {
     content.trim().trim_end_matches(',').to_string()
@@ -160,7 +574,9 @@ mod test {
     static SYSTEM_HELPER: RoleHelper = RoleHelper;
     static USER_HELPER: RoleHelper = RoleHelper;
     static ASSISTANT_HELPER: RoleHelper = RoleHelper;
+    static TOOL_HELPER: RoleHelper = RoleHelper;
     static CHAT_HELPER: ChatHelper = ChatHelper;
+    static IMAGE_HELPER: ImageHelper = ImageHelper;
 
     #[test]
     fn test_chat() {
@@ -196,4 +612,164 @@ mod test {
         let messages: Vec<Message> = from_str(&rendered).unwrap();
         assert_eq!(messages.len(), 4);
     }
+
+    #[test]
+    fn test_named_messages() {
+        let mut handlebars = Handlebars::new();
+        handlebars.register_helper("user", Box::new(USER_HELPER));
+        handlebars.register_helper("tool", Box::new(TOOL_HELPER));
+        handlebars.register_helper("chat", Box::new(CHAT_HELPER));
+
+        let template = r#"
+            {{#chat}}
+            {{#user name=speaker}}
+            What happened today?
+            {{/user}}
+            {{#tool name="search" call_id="call_1"}}
+            {{results}}
+            {{/tool}}
+            {{/chat}}
+            "#;
+        let data = json!({ "speaker": "alice", "results": "Orcas were seen near Seattle." });
+
+        let rendered = handlebars.render_template(template, &data).unwrap();
+        let messages: Vec<Message> = from_str(&rendered).unwrap();
+        assert_eq!(
+            messages,
+            [
+                Message::new(Role::User, "What happened today?").with_name("alice"),
+                Message::tool_output(
+                    &ToolCall::new("call_1", "search", "{}"),
+                    "Orcas were seen near Seattle."
+                ),
+            ]
+        );
+        let json = serde_json::to_value(&messages).unwrap();
+        assert_eq!(
+            json[1],
+            json!({
+                "role": "tool",
+                "name": "search",
+                "content": "Orcas were seen near Seattle.",
+                "tool_call_id": "call_1"
+            })
+        );
+        assert!(serde_json::to_value(Message::new(Role::User, "Hi")).unwrap().get("name").is_none());
+    }
+
+    #[test]
+    fn test_openai_json_roundtrip() {
+        let chat = ChatPrompt(vec![
+            Message::new(Role::System, "You are an expert in world capitals."),
+            Message::new(Role::User, "What is the capital of France?"),
+            Message::new(Role::Assistant, "Paris"),
+        ]);
+        let json = chat.to_openai_json().unwrap();
+        assert_eq!(ChatPrompt::from_openai_json(&json).unwrap(), chat);
+
+        let bare = serde_json::to_string(&chat.to_vec()).unwrap();
+        assert_eq!(ChatPrompt::from_openai_json(&bare).unwrap(), chat);
+
+        // Errors of a malformed envelope are reported as such.
+        let error = ChatPrompt::from_openai_json(r#"{"messages": [{"content": "Hi"}]}"#).unwrap_err();
+        assert!(error.to_string().contains("missing field `role`"), "{}", error);
+        let error = ChatPrompt::from_openai_json(r#"{"message": []}"#).unwrap_err();
+        assert!(error.to_string().contains("missing field `messages`"), "{}", error);
+
+        // Fields that are not part of the OpenAI API are left out.
+        let chat = ChatPrompt(vec![Message::new(Role::User, "Hi")
+            .with_name("alice")
+            .with_id("msg-1")
+            .with_timestamp(1000)
+            .with_metadata("channel", "web")
+            .with_cache_control()]);
+        let json = serde_json::from_str::<serde_json::Value>(&chat.to_openai_json().unwrap()).unwrap();
+        assert_eq!(
+            json,
+            json!({"messages": [{"role": "user", "content": "Hi", "name": "alice"}]})
+        );
+    }
+
+    #[test]
+    fn test_to_markdown() {
+        let chat = ChatPrompt(vec![
+            Message::new(Role::User, "What is the capital of France?"),
+            Message::new(Role::Assistant, "Paris"),
+        ]);
+        assert_eq!(
+            chat.to_markdown(),
+            "**User:** What is the capital of France?\n\n**Assistant:** Paris\n"
+        );
+    }
+
+    #[test]
+    fn test_message_metadata() {
+        let message =
+            Message::new(Role::User, "Hi").with_id("msg-1").with_timestamp(1000).with_metadata("channel", "web");
+        let json = serde_json::to_value(&message).unwrap();
+        assert_eq!(
+            json,
+            json!({"role": "user", "content": "Hi", "id": "msg-1", "timestamp": 1000, "metadata": {"channel": "web"}})
+        );
+        assert_eq!(serde_json::from_value::<Message>(json).unwrap(), message);
+        assert!(Message::new(Role::User, "Hi").with_timestamp_now().timestamp.unwrap() > 1000);
+
+        let chat = ChatPrompt(vec![
+            Message::new(Role::System, "You are an expert in world capitals."),
+            message,
+            Message::new(Role::User, "What is the capital of France?").with_timestamp(5000),
+        ]);
+        let window = chat.since(2000).to_vec();
+        assert_eq!(window.len(), 2);
+        assert_eq!(window[1].timestamp, Some(5000));
+    }
+
+    #[test]
+    fn test_cache_up_to() {
+        let chat = ChatPrompt(vec![
+            Message::new(Role::System, "You are an expert in world capitals."),
+            Message::new(Role::User, "What is the capital of France?"),
+        ]);
+        assert_eq!(chat.cached_prefix_len(), 0);
+
+        let chat = chat.cache_up_to(0);
+        assert_eq!(chat.cached_prefix_len(), 1);
+        let json = serde_json::to_value(chat.to_vec()).unwrap();
+        assert_eq!(json[0]["cache_control"], serde_json::json!({"type": "ephemeral"}));
+        assert!(json[1].get("cache_control").is_none());
+    }
+
+    #[test]
+    fn test_image() {
+        let mut handlebars = Handlebars::new();
+        handlebars.register_escape_fn(handlebars::no_escape);
+        handlebars.register_helper("user", Box::new(USER_HELPER));
+        handlebars.register_helper("chat", Box::new(CHAT_HELPER));
+        handlebars.register_helper("image", Box::new(IMAGE_HELPER));
+
+        let template = r#"
+            {{#chat}}
+            {{#user}}
+            What is in this screenshot?
+            {{#image detail="low"}}{{url}}{{/image}}
+            {{/user}}
+            {{/chat}}
+            "#;
+        let data = json!({ "url": "https://example.com/screenshot.png?size=large&v=2" });
+
+        let rendered = handlebars.render_template(template, &data).unwrap();
+        let messages: Vec<Message> = from_str(&rendered).unwrap();
+        assert_eq!(
+            messages[0],
+            Message::new(Role::User, "What is in this screenshot?")
+                .with_image(Image::new("https://example.com/screenshot.png?size=large&v=2").with_detail("low"))
+        );
+    }
+
+    #[test]
+    fn test_image_from_bytes() {
+        let image = Image::from_bytes("image/png", b"png");
+        assert_eq!(image.url, "data:image/png;base64,cG5n");
+        assert!(Image::from_file("notes.txt").is_err());
+    }
 }
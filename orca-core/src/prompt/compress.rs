@@ -0,0 +1,176 @@
+//! Compression of long retrieved contexts.
+//!
+//! A `Compressor` drops the least informative words of a text until a target ratio of its words is
+//! left, keeping the rest in order, in the spirit of LLMLingua. Retrieved documents compressed before
+//! rendering cost fewer tokens while keeping the names, numbers and rare terms an answer depends on.
+//! Words are scored by a [`WordScorer`]: [`FrequencyScorer`] is a heuristic based on the frequency of
+//! words in the text, and a small language model can be plugged in by implementing the trait.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+
+/// Words carrying little information on their own, scored lowest by [`FrequencyScorer`].
+pub(crate) const STOPWORDS: &[&str] = &[
+    "a", "about", "after", "all", "also", "an", "and", "any", "are", "as", "at", "be", "been", "but", "by", "can",
+    "could", "did", "do", "does", "for", "from", "had", "has", "have", "he", "her", "his", "how", "i", "if", "in",
+    "into", "is", "it", "its", "just", "may", "more", "most", "of", "on", "or", "other", "our", "she", "so", "some",
+    "such", "than", "that", "the", "their", "them", "then", "there", "these", "they", "this", "those", "to", "very",
+    "was", "we", "were", "what", "when", "which", "while", "who", "will", "with", "would", "you", "your",
+];
+
+/// Scores the words of a text by how much information they carry. Higher scores are kept first.
+pub trait WordScorer: Send + Sync {
+    /// Score each of the given words, which make up a text in order.
+    fn score(&self, words: &[&str]) -> Result<Vec<f32>>;
+}
+
+/// Scores words by their self-information in the text, i.e. rare words score higher than frequent
+/// ones. Stopwords and punctuation score lowest, and words with digits or capitals, such as numbers
+/// and names, get a bonus.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrequencyScorer;
+
+impl WordScorer for FrequencyScorer {
+    fn score(&self, words: &[&str]) -> Result<Vec<f32>> {
+        let normalized: Vec<String> = words.iter().map(|word| normalize(word)).collect();
+        let mut counts = HashMap::new();
+        for word in &normalized {
+            *counts.entry(word.as_str()).or_insert(0usize) += 1;
+        }
+        let total = normalized.len() as f32;
+        Ok(words
+            .iter()
+            .zip(&normalized)
+            .map(|(word, normalized)| {
+                if normalized.is_empty() {
+                    return 0.0;
+                }
+                if STOPWORDS.contains(&normalized.as_str()) {
+                    return 0.1;
+                }
+                let mut score = -(counts[normalized.as_str()] as f32 / total).ln();
+                if word.chars().any(|c| c.is_ascii_digit()) {
+                    score += 1.0;
+                }
+                if word.chars().any(char::is_uppercase) {
+                    score += 0.5;
+                }
+                score
+            })
+            .collect())
+    }
+}
+
+/// Lowercase a word and strip the punctuation around it.
+fn normalize(word: &str) -> String {
+    word.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase()
+}
+
+/// Drops the least informative words of texts down to a target ratio.
+#[derive(Clone)]
+pub struct Compressor {
+    /// Ratio of the words of a text that are kept.
+    ratio: f32,
+
+    /// Texts with fewer words are not compressed.
+    min_words: usize,
+
+    /// Scores the information carried by the words.
+    scorer: Arc<dyn WordScorer>,
+}
+
+impl Compressor {
+    /// Create a compressor keeping the given ratio of the words of a text, e.g. `0.5` to halve it,
+    /// scoring words with a [`FrequencyScorer`]. Texts of fewer than 50 words are not compressed.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::prompt::compress::Compressor;
+    ///
+    /// let compressor = Compressor::new(0.5).unwrap().with_min_words(0);
+    /// let text = "The orca is the largest member of the dolphin family, weighing up to 6 tonnes.";
+    /// let compressed = compressor.compress(text).unwrap();
+    /// assert_eq!(compressed.split_whitespace().count(), 8);
+    /// assert!(compressed.contains("6"));
+    /// ```
+    pub fn new(ratio: f32) -> Result<Self> {
+        if !(ratio > 0.0 && ratio <= 1.0) {
+            return Err(anyhow!("Compression ratio must be in (0, 1], got {}", ratio));
+        }
+        Ok(Compressor {
+            ratio,
+            min_words: 50,
+            scorer: Arc::new(FrequencyScorer),
+        })
+    }
+
+    /// Set the number of words under which texts are not compressed.
+    pub fn with_min_words(mut self, min_words: usize) -> Self {
+        self.min_words = min_words;
+        self
+    }
+
+    /// Set the scorer of the words, e.g. one backed by a small language model.
+    pub fn with_scorer<S: WordScorer + 'static>(mut self, scorer: S) -> Self {
+        self.scorer = Arc::new(scorer);
+        self
+    }
+
+    /// Compress a text, keeping its highest scoring words in their original order. Ties are broken
+    /// in favour of earlier words.
+    pub fn compress(&self, text: &str) -> Result<String> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        if words.len() < self.min_words {
+            return Ok(text.to_string());
+        }
+        let scores = self.scorer.score(&words)?;
+        if scores.len() != words.len() {
+            return Err(anyhow!(
+                "Scorer returned {} scores for {} words",
+                scores.len(),
+                words.len()
+            ));
+        }
+        let keep = (words.len() as f32 * self.ratio).ceil() as usize;
+        let mut ranked: Vec<usize> = (0..words.len()).collect();
+        ranked.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]).then(a.cmp(&b)));
+        ranked.truncate(keep);
+        ranked.sort_unstable();
+        Ok(ranked.into_iter().map(|i| words[i]).collect::<Vec<_>>().join(" "))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_compress() {
+        let compressor = Compressor::new(0.5).unwrap().with_min_words(0);
+        let text = "In 2005 the Southern Resident orcas were listed as endangered, and the population is \
+                    still declining because of the decline of the Chinook salmon they feed on.";
+        assert_eq!(
+            compressor.compress(text).unwrap(),
+            "2005 Southern Resident orcas listed endangered, population still declining because decline Chinook \
+             salmon feed"
+        );
+
+        let short = "Orcas are dolphins.";
+        assert_eq!(Compressor::new(0.5).unwrap().compress(short).unwrap(), short);
+        assert!(Compressor::new(0.0).is_err());
+    }
+
+    #[test]
+    fn test_custom_scorer() {
+        struct Length;
+        impl WordScorer for Length {
+            fn score(&self, words: &[&str]) -> Result<Vec<f32>> {
+                Ok(words.iter().map(|word| word.len() as f32).collect())
+            }
+        }
+        let compressor = Compressor::new(0.25).unwrap().with_min_words(0).with_scorer(Length);
+        assert_eq!(compressor.compress("an orca hunts salmon").unwrap(), "salmon");
+    }
+}
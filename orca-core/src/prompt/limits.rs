@@ -0,0 +1,233 @@
+//! Limits for rendering untrusted templates.
+//!
+//! A `TemplateEngine` with limits rejects templates that are too large, nest blocks too deeply or use
+//! partials, which can recurse without bound, and stops rendering once the output exceeds a maximum
+//! size. Limits also enable strict mode, so that unknown helpers and missing variables are errors
+//! instead of being silently rendered as empty strings. Violations are reported as a
+//! [`TemplateLimitError`], wrapped in an `OrcaError::TemplateLimit` that can be recovered from an
+//! `anyhow::Error` with `downcast_ref`.
+
+use std::fmt::Display;
+use std::io::Write;
+
+use handlebars::template::{Template, TemplateElement};
+
+/// Limits applied to templates and their rendered output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TemplateLimits {
+    /// Maximum size of a template, in bytes.
+    pub max_template_size: usize,
+
+    /// Maximum size of a rendered prompt, in bytes.
+    pub max_rendered_size: usize,
+
+    /// Maximum nesting depth of blocks, e.g. `{{#each}}` inside `{{#if}}`.
+    pub max_depth: usize,
+}
+
+impl Default for TemplateLimits {
+    fn default() -> Self {
+        TemplateLimits {
+            max_template_size: 64 * 1024,
+            max_rendered_size: 1024 * 1024,
+            max_depth: 16,
+        }
+    }
+}
+
+impl TemplateLimits {
+    /// Create limits of 64 KiB per template, 1 MiB per rendered prompt and 16 nested blocks.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the maximum size of a template, in bytes.
+    pub fn with_max_template_size(mut self, max_template_size: usize) -> Self {
+        self.max_template_size = max_template_size;
+        self
+    }
+
+    /// Set the maximum size of a rendered prompt, in bytes.
+    pub fn with_max_rendered_size(mut self, max_rendered_size: usize) -> Self {
+        self.max_rendered_size = max_rendered_size;
+        self
+    }
+
+    /// Set the maximum nesting depth of blocks.
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Check a template against the limits before it is registered.
+    pub fn check(&self, name: &str, template: &str) -> Result<(), TemplateLimitError> {
+        if template.len() > self.max_template_size {
+            return Err(TemplateLimitError::TemplateTooLarge {
+                template: name.to_string(),
+                size: template.len(),
+                limit: self.max_template_size,
+            });
+        }
+        // Templates that do not compile are reported by handlebars when they are registered.
+        let compiled = match Template::compile(template) {
+            Ok(compiled) => compiled,
+            Err(_) => return Ok(()),
+        };
+        let depth = depth(name, &compiled)?;
+        if depth > self.max_depth {
+            return Err(TemplateLimitError::TooDeep {
+                template: name.to_string(),
+                depth,
+                limit: self.max_depth,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Nesting depth of the blocks of a template, failing on partials.
+fn depth(name: &str, template: &Template) -> Result<usize, TemplateLimitError> {
+    let mut max = 0;
+    for element in &template.elements {
+        let nested = match element {
+            TemplateElement::HelperBlock(helper) => [&helper.template, &helper.inverse]
+                .into_iter()
+                .flatten()
+                .map(|template| depth(name, template))
+                .try_fold(0, |max, depth| depth.map(|depth| max.max(depth)))?,
+            TemplateElement::DecoratorBlock(decorator) => match &decorator.template {
+                Some(template) => depth(name, template)?,
+                None => 0,
+            },
+            TemplateElement::PartialExpression(_) | TemplateElement::PartialBlock(_) => {
+                return Err(TemplateLimitError::PartialNotAllowed {
+                    template: name.to_string(),
+                })
+            }
+            _ => continue,
+        };
+        max = max.max(nested + 1);
+    }
+    Ok(max)
+}
+
+/// Error returned when a template or its rendered output exceeds a limit.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TemplateLimitError {
+    /// The template is larger than the maximum template size.
+    TemplateTooLarge {
+        template: String,
+        size: usize,
+        limit: usize,
+    },
+
+    /// The blocks of the template are nested deeper than the maximum depth.
+    TooDeep {
+        template: String,
+        depth: usize,
+        limit: usize,
+    },
+
+    /// The template uses a partial, which may recurse without bound.
+    PartialNotAllowed { template: String },
+
+    /// The rendered prompt is larger than the maximum rendered size.
+    RenderedTooLarge { template: String, limit: usize },
+}
+
+impl Display for TemplateLimitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TemplateLimitError::TemplateTooLarge { template, size, limit } => write!(
+                f,
+                "Template {} is {} bytes, more than the limit of {} bytes",
+                template, size, limit
+            ),
+            TemplateLimitError::TooDeep { template, depth, limit } => write!(
+                f,
+                "Template {} nests blocks {} deep, more than the limit of {}",
+                template, depth, limit
+            ),
+            TemplateLimitError::PartialNotAllowed { template } => {
+                write!(f, "Template {} uses a partial, which is not allowed", template)
+            }
+            TemplateLimitError::RenderedTooLarge { template, limit } => write!(
+                f,
+                "Rendered template {} is more than the limit of {} bytes",
+                template, limit
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TemplateLimitError {}
+
+/// Writer collecting rendered output, failing once it exceeds a maximum size.
+pub(crate) struct LimitedWriter {
+    buffer: Vec<u8>,
+    limit: usize,
+    exceeded: bool,
+}
+
+impl LimitedWriter {
+    pub(crate) fn new(limit: usize) -> Self {
+        LimitedWriter {
+            buffer: Vec::new(),
+            limit,
+            exceeded: false,
+        }
+    }
+
+    /// Whether rendering was stopped because the output exceeded the maximum size.
+    pub(crate) fn exceeded(&self) -> bool {
+        self.exceeded
+    }
+
+    pub(crate) fn into_string(self) -> String {
+        String::from_utf8_lossy(&self.buffer).into_owned()
+    }
+}
+
+impl Write for LimitedWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.buffer.len() + buf.len() > self.limit {
+            self.exceeded = true;
+            return Err(std::io::Error::other("rendered size limit exceeded"));
+        }
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let limits = TemplateLimits::new().with_max_template_size(64).with_max_depth(2);
+        assert!(limits.check("ok", "{{#if a}}{{#each b}}{{this}}{{/each}}{{else}}none{{/if}}").is_ok());
+        assert_eq!(
+            limits.check("deep", "{{#if a}}{{#if b}}{{#if c}}!{{/if}}{{/if}}{{/if}}"),
+            Err(TemplateLimitError::TooDeep {
+                template: "deep".to_string(),
+                depth: 3,
+                limit: 2
+            })
+        );
+        assert_eq!(
+            limits.check("partial", "{{> partial}}"),
+            Err(TemplateLimitError::PartialNotAllowed {
+                template: "partial".to_string()
+            })
+        );
+        assert!(matches!(
+            limits.check("large", &"a".repeat(65)),
+            Err(TemplateLimitError::TemplateTooLarge { size: 65, .. })
+        ));
+    }
+}
@@ -0,0 +1,69 @@
+//! Template helper to access the metadata of records.
+//!
+//! Records can be used directly as template context values, e.g. `{{record.header}}` or
+//! `{{#each records}}{{this.content}}{{/each}}`. Their structured metadata attributes, such as the page
+//! a PDF record was extracted from, are read with the `meta` helper, either on a record,
+//! `{{meta record "page"}}`, or on the current record of a block, `{{meta "page"}}`.
+
+use handlebars::{
+    Context, Handlebars, Helper, HelperDef, HelperResult, JsonRender, Output, RenderContext, RenderError,
+};
+
+/// Renders a metadata attribute of a record, given as the first parameter or, if there is only one
+/// parameter, taken from the current context.
+#[derive(Clone, Copy)]
+pub struct MetaHelper;
+
+impl HelperDef for MetaHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _r: &'reg Handlebars<'reg>,
+        ctx: &'rc Context,
+        rc: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let (record, key) = match (h.param(0), h.param(1)) {
+            (Some(record), Some(key)) => (record.value().clone(), key),
+            (Some(key), None) => (rc.evaluate(ctx, "this")?.as_json().clone(), key),
+            _ => return Err(RenderError::new("meta helper expects the name of an attribute")),
+        };
+        let key = key
+            .value()
+            .as_str()
+            .ok_or_else(|| RenderError::new("meta helper expects the name of an attribute"))?;
+        if let Some(value) = record.get("attributes").and_then(|attributes| attributes.get(key)) {
+            out.write(&value.render())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use crate::prompt::TemplateEngine;
+    use crate::record::{Content, Record};
+
+    #[test]
+    fn test_records_in_template() {
+        let records = vec![
+            Record::new(Content::String("Orcas are toothed whales.".to_string()))
+                .with_header("Orcas".to_string())
+                .with_attribute("page", 3),
+            Record::new(Content::String("They live in pods.".to_string())).with_header("Pods".to_string()),
+        ];
+        let prompt = TemplateEngine::new()
+            .register_template(
+                "template",
+                "{{record.header}} (p. {{meta record \"page\"}})\n{{#each records}}[{{this.header}}, p. {{meta \"page\"}}] {{this.content}}\n{{/each}}",
+            )
+            .unwrap();
+        let rendered = prompt.render_context("template", &json!({"record": records[0], "records": records})).unwrap();
+        assert_eq!(
+            rendered.to_string(),
+            "Orcas (p. 3)\n[Orcas, p. 3] Orcas are toothed whales.\n[Pods, p. ] They live in pods.\n"
+        );
+    }
+}
@@ -1,22 +1,70 @@
 pub mod context;
-use std::{any::Any, collections::HashMap, fmt::Display};
+use std::{any::Any, collections::HashMap, fmt::Display, sync::Arc};
 
 use serde;
 use serde::Serialize;
 
 use anyhow::Result;
-use handlebars::Handlebars;
+use handlebars::template::{Parameter, Template, TemplateElement};
+use handlebars::{Handlebars, Path};
 
-use chat::{remove_last_comma, ChatHelper, ChatPrompt, RoleHelper};
+use chat::{remove_last_comma, ChatHelper, ChatPrompt, ImageHelper, Role, RoleHelper};
+use limits::{LimitedWriter, TemplateLimitError, TemplateLimits};
+use meta::MetaHelper;
+use segment::{Segment, TemplateSegments};
 
+use crate::error::OrcaError;
 use crate::record::Record;
 
+pub mod budget;
 pub mod chat;
+pub mod compress;
+pub mod limits;
+pub mod meta;
+pub mod segment;
 
 static SYSTEM_HELPER: RoleHelper = RoleHelper;
 static USER_HELPER: RoleHelper = RoleHelper;
 static ASSISTANT_HELPER: RoleHelper = RoleHelper;
+static TOOL_HELPER: RoleHelper = RoleHelper;
 static CHAT_HELPER: ChatHelper = ChatHelper;
+static IMAGE_HELPER: ImageHelper = ImageHelper;
+static META_HELPER: MetaHelper = MetaHelper;
+
+/// How a rendered template was interpreted.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RenderOutcome {
+    /// The template rendered a chat with the given number of messages.
+    Chat(usize),
+
+    /// The template rendered plain text.
+    Text,
+
+    /// The template rendered JSON that is not a valid chat, and is used as plain text.
+    InvalidChat(String),
+
+    /// The template failed to render.
+    Failed(String),
+}
+
+/// Event emitted each time a template is rendered.
+#[derive(Debug, Clone)]
+pub struct RenderEvent<'a> {
+    /// Name of the rendered template.
+    pub template: &'a str,
+
+    /// The rendered prompt, empty if rendering failed.
+    pub rendered: &'a str,
+
+    /// Size of the rendered prompt, in bytes.
+    pub size: usize,
+
+    /// How the rendered template was interpreted.
+    pub outcome: RenderOutcome,
+}
+
+/// Callback receiving render events.
+pub type RenderHook = Arc<dyn Fn(&RenderEvent) + Send + Sync>;
 
 /// Represents a prompt engine that uses handlebars templates to render strings.
 pub struct TemplateEngine {
@@ -25,6 +73,15 @@ pub struct TemplateEngine {
 
     /// Registered templates
     pub templates: HashMap<String, String>,
+
+    /// Ordered segments of each registered template
+    segments: HashMap<String, TemplateSegments>,
+
+    /// Callback receiving an event each time a template is rendered
+    render_hook: Option<RenderHook>,
+
+    /// Limits applied to untrusted templates
+    limits: Option<TemplateLimits>,
 }
 
 impl Default for TemplateEngine {
@@ -50,54 +107,245 @@ impl TemplateEngine {
         reg.register_helper("system", Box::new(SYSTEM_HELPER));
         reg.register_helper("user", Box::new(USER_HELPER));
         reg.register_helper("assistant", Box::new(ASSISTANT_HELPER));
+        reg.register_helper("tool", Box::new(TOOL_HELPER));
         reg.register_helper("chat", Box::new(CHAT_HELPER));
+        reg.register_helper("image", Box::new(IMAGE_HELPER));
+        reg.register_helper("meta", Box::new(META_HELPER));
 
         TemplateEngine {
             reg,
             templates: HashMap::new(),
+            segments: HashMap::new(),
+            render_hook: None,
+            limits: None,
         }
     }
 
+    /// Applies limits to the templates registered from now on and to every rendered prompt, and enables
+    /// strict mode, so that untrusted templates, e.g. supplied by users of a server, cannot exhaust
+    /// memory or hang rendering. Violations are returned as an `OrcaError::TemplateLimit`.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::error::OrcaError;
+    /// use orca_core::prompt::TemplateEngine;
+    /// use orca_core::prompt::limits::{TemplateLimitError, TemplateLimits};
+    ///
+    /// let prompt = TemplateEngine::new()
+    ///     .with_limits(TemplateLimits::new().with_max_rendered_size(16))
+    ///     .register_template("template", "{{#each items}}{{this}}{{/each}}")
+    ///     .unwrap();
+    /// let data = serde_json::json!({"items": ["a long item", "another long item"]});
+    /// let error = prompt.render_context("template", &data).err().unwrap();
+    /// assert!(matches!(
+    ///     error.downcast_ref::<OrcaError>(),
+    ///     Some(OrcaError::TemplateLimit(TemplateLimitError::RenderedTooLarge { .. }))
+    /// ));
+    /// ```
+    pub fn with_limits(mut self, limits: TemplateLimits) -> Self {
+        self.reg.set_strict_mode(true);
+        self.limits = Some(limits);
+        self
+    }
+
+    /// Sets a callback receiving an event each time a template is rendered, with the template name,
+    /// the rendered prompt, its size and how it was interpreted. Rendered prompts are not logged by the
+    /// engine itself, so applications decide whether to log, sample or redact them.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::prompt::TemplateEngine;
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let sizes = Arc::new(Mutex::new(Vec::new()));
+    /// let recorded = sizes.clone();
+    /// let prompt = TemplateEngine::new()
+    ///     .on_render(move |event| recorded.lock().unwrap().push((event.template.to_string(), event.size)))
+    ///     .register_template("template", "Hello, world!")
+    ///     .unwrap();
+    /// prompt.render("template").unwrap();
+    /// assert_eq!(sizes.lock().unwrap()[0], ("template".to_string(), 13));
+    /// ```
+    pub fn on_render<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&RenderEvent) + Send + Sync + 'static,
+    {
+        self.render_hook = Some(Arc::new(hook));
+        self
+    }
+
     pub fn register_template(mut self, name: &str, template: &str) -> Result<Self> {
+        self.set_template(name, template)?;
+        Ok(self)
+    }
+
+    /// Registers a template in place, replacing the template of the same name if any.
+    pub(crate) fn set_template(&mut self, name: &str, template: &str) -> Result<()> {
+        self.register(name, template)?;
         self.templates.insert(name.to_string(), template.to_string());
+        self.segments.insert(name.to_string(), TemplateSegments::parse(template));
+        Ok(())
+    }
+
+    /// Registers a template with handlebars, checking it against the limits first.
+    fn register(&mut self, name: &str, template: &str) -> Result<()> {
+        if let Some(limits) = &self.limits {
+            limits.check(name, template).map_err(OrcaError::from)?;
+        }
         self.reg.register_template_string(name, template)?;
-        Ok(self)
+        Ok(())
     }
 
     pub fn get_template(&self, name: &str) -> Option<String> {
         self.templates.get(name).cloned()
     }
 
+    /// Get the variables of the context that a registered template reads, in order of appearance.
+    /// Variables read inside `each` and `with` blocks are relative to the items of the block, so only the
+    /// variable of the block itself is listed.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::prompt::TemplateEngine;
+    ///
+    /// let template = "{{#system}}{{instructions}}{{/system}}{{#each documents}}{{title}}{{/each}}{{question}}";
+    /// let prompt = TemplateEngine::new().register_template("qa", template).unwrap();
+    /// assert_eq!(prompt.variables("qa").unwrap(), vec!["instructions", "documents", "question"]);
+    /// ```
+    pub fn variables(&self, name: &str) -> Option<Vec<String>> {
+        let template = Template::compile(self.templates.get(name)?).ok()?;
+        let mut variables = Vec::new();
+        collect_variables(&template, &mut variables);
+        Some(variables)
+    }
+
+    /// Get the ordered segments of a registered template.
+    pub fn get_segments(&self, name: &str) -> Option<&[Segment]> {
+        self.segments.get(name).map(|segments| segments.segments.as_slice())
+    }
+
     /// Adds a new template to the prompt.
     ///
-    /// This function appends a new template to the existing prompt. The template
-    /// should be a string slice that holds the template to be added. The template
-    /// will be added to the prompt on a new line.
+    /// This function appends a new template to the end of an existing one. Role blocks in the
+    /// new template are added as structured segments, so a chat template stays a chat template.
     ///
     /// # Arguments
-    /// * `template` - A string slice that holds the template to be added.
+    /// * `name` - The name of the template to extend.
+    /// * `new_template` - A string slice that holds the template to be added.
     ///
     /// # Example
     /// ```
     /// use orca_core::prompt::TemplateEngine;
     ///
     /// let mut prompt = TemplateEngine::new().register_template("template", "Welcome!").unwrap();
-    /// prompt.add_to_template("template", "Hello, world!");
+    /// prompt.add_to_template("template", "Hello, world!").unwrap();
     /// assert_eq!(prompt.templates["template"], "Welcome!Hello, world!");
     /// ```
-    pub fn add_to_template(&mut self, name: &str, new_template: &str) {
-        let mut chat = false;
-        if let Some(template) = self.templates.get_mut(name) {
-            if template.contains("{{#chat}}") && template.contains("{{/chat}}") {
-                chat = true;
-                *template = template.replace("{{#chat}}", "").replace("{{/chat}}", "");
+    pub fn add_to_template(&mut self, name: &str, new_template: &str) -> Result<()> {
+        let new_segments = TemplateSegments::parse(new_template).segments;
+        self.update_segments(name, |segments| {
+            segments.extend(new_segments);
+            Ok(())
+        })
+    }
+
+    /// Appends a user message to the end of a template.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::prompt::TemplateEngine;
+    ///
+    /// let mut prompt = TemplateEngine::new()
+    ///     .register_template("template", "{{#chat}}{{#system}}Be brief.{{/system}}{{/chat}}")
+    ///     .unwrap();
+    /// prompt.append_user("template", "Hello!").unwrap();
+    /// assert_eq!(
+    ///     prompt.templates["template"],
+    ///     "{{#chat}}{{#system}}Be brief.{{/system}}{{#user}}Hello!{{/user}}{{/chat}}"
+    /// );
+    /// ```
+    pub fn append_user(&mut self, name: &str, content: &str) -> Result<()> {
+        self.append_message(name, Role::User, content)
+    }
+
+    /// Appends an assistant message to the end of a template.
+    pub fn append_assistant(&mut self, name: &str, content: &str) -> Result<()> {
+        self.append_message(name, Role::Assistant, content)
+    }
+
+    /// Appends a message with the given role to the end of a template.
+    pub fn append_message(&mut self, name: &str, role: Role, content: &str) -> Result<()> {
+        self.append_segment(name, Segment::Message(role, None, content.to_string()))
+    }
+
+    /// Appends a segment to the end of a template, e.g. a named message.
+    pub fn append_segment(&mut self, name: &str, segment: Segment) -> Result<()> {
+        self.update_segments(name, |segments| {
+            segments.push(segment);
+            Ok(())
+        })
+    }
+
+    /// Inserts a segment at the given position of a template.
+    ///
+    /// # Arguments
+    /// * `name` - The name of the template to modify.
+    /// * `index` - The position to insert the segment at, as returned by `get_segments`.
+    /// * `segment` - The segment to insert.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::prompt::TemplateEngine;
+    /// use orca_core::prompt::chat::Role;
+    /// use orca_core::prompt::segment::Segment;
+    ///
+    /// let mut prompt = TemplateEngine::new()
+    ///     .register_template("template", "{{#chat}}{{#user}}Hello!{{/user}}{{/chat}}")
+    ///     .unwrap();
+    /// prompt.insert_at("template", 0, Segment::Message(Role::System, None, "Be brief.".to_string())).unwrap();
+    /// assert_eq!(
+    ///     prompt.templates["template"],
+    ///     "{{#chat}}{{#system}}Be brief.{{/system}}{{#user}}Hello!{{/user}}{{/chat}}"
+    /// );
+    /// ```
+    pub fn insert_at(&mut self, name: &str, index: usize, segment: Segment) -> Result<()> {
+        self.update_segments(name, |segments| {
+            if index > segments.len() {
+                return Err(anyhow::anyhow!(
+                    "Index {} is out of bounds for template with {} segments",
+                    index,
+                    segments.len()
+                ));
             }
-            template.push_str(new_template);
-            if chat {
-                *template = format!("{{{{#chat}}}}{}{{{{/chat}}}}", template);
+            segments.insert(index, segment);
+            Ok(())
+        })
+    }
+
+    /// Applies a change to the segments of a template and re-registers the resulting template.
+    fn update_segments<F>(&mut self, name: &str, update: F) -> Result<()>
+    where
+        F: FnOnce(&mut Vec<Segment>) -> Result<()>,
+    {
+        let segments = match self.segments.get_mut(name) {
+            Some(segments) => segments,
+            None => {
+                return Err(OrcaError::Template {
+                    template: name.to_string(),
+                    message: "does not exist".to_string(),
+                }
+                .into())
             }
-            self.reg.register_template_string(name, (*template).clone()).unwrap();
+        };
+        update(&mut segments.segments)?;
+        let template = segments.to_template();
+        if let Err(e) = self.register(name, &template) {
+            // Keep the segments in sync with the template that is still registered.
+            self.segments.insert(name.to_string(), TemplateSegments::parse(&self.templates[name]));
+            return Err(e);
         }
+        self.templates.insert(name.to_string(), template);
+        Ok(())
     }
 
     /// Renders a Handlebars template and returns the result as a Boxed trait object.
@@ -119,11 +367,7 @@ impl TemplateEngine {
     /// assert_eq!(result.to_string(), "Hello, world!".to_string());
     /// ```
     pub fn render(&self, name: &str) -> Result<Box<dyn Prompt>> {
-        let rendered = self.reg.render(name, &HashMap::<String, String>::new())?;
-        match serde_json::from_str::<ChatPrompt>(&rendered) {
-            Ok(chat) => Ok(Box::new(chat)),
-            Err(_) => Ok(Box::new(rendered)),
-        }
+        self.render_context(name, &HashMap::<String, String>::new())
     }
 
     /// Renders a Handlebars template with the given data and returns the result as a Boxed trait object.
@@ -149,24 +393,63 @@ impl TemplateEngine {
     where
         T: Serialize,
     {
-        let rendered = self.reg.render(template_name, data)?;
-        log::info!("rendered: {}", rendered);
-        // Check if rendered is a valid JSON string
-        if let Ok(json_value) = serde_json::from_str::<serde_json::Value>(&rendered) {
-            match serde_json::from_value::<ChatPrompt>(json_value) {
-                Ok(chat) => {
-                    log::info!("Parsed as chat: {:?}", chat);
-                    Ok(Box::new(chat))
-                }
-                Err(e) => {
-                    log::info!("Failed to parse as chat, returning rendered string: {:?}", e);
-                    Ok(Box::new(rendered))
-                }
+        let rendered = match self.render_string(template_name, data) {
+            Ok(rendered) => rendered,
+            Err(e) => {
+                self.emit(template_name, "", RenderOutcome::Failed(e.to_string()));
+                return Err(e);
             }
-        } else {
-            // If rendered is not JSON, return it as a string
-            log::info!("rendered is not JSON, returning as string");
-            Ok(Box::new(rendered))
+        };
+        // A chat template renders a JSON list of messages; anything else is used as plain text.
+        let (prompt, outcome): (Box<dyn Prompt>, RenderOutcome) =
+            match serde_json::from_str::<serde_json::Value>(&rendered) {
+                Ok(json_value) => match serde_json::from_value::<ChatPrompt>(json_value) {
+                    Ok(chat) => {
+                        let messages = chat.0.len();
+                        (Box::new(chat), RenderOutcome::Chat(messages))
+                    }
+                    Err(e) => (Box::new(rendered.clone()), RenderOutcome::InvalidChat(e.to_string())),
+                },
+                Err(_) => (Box::new(rendered.clone()), RenderOutcome::Text),
+            };
+        self.emit(template_name, &rendered, outcome);
+        Ok(prompt)
+    }
+
+    /// Renders a template to a string, stopping once the output exceeds the maximum rendered size.
+    fn render_string<T: Serialize>(&self, name: &str, data: &T) -> Result<String> {
+        let limits = match &self.limits {
+            Some(limits) => limits,
+            None => return self.reg.render(name, data).map_err(|e| template_error(name, e)),
+        };
+        let mut writer = LimitedWriter::new(limits.max_rendered_size);
+        match self.reg.render_to_write(name, data, &mut writer) {
+            Ok(()) => Ok(writer.into_string()),
+            Err(_) if writer.exceeded() => Err(OrcaError::TemplateLimit(TemplateLimitError::RenderedTooLarge {
+                template: name.to_string(),
+                limit: limits.max_rendered_size,
+            })
+            .into()),
+            Err(e) => Err(template_error(name, e)),
+        }
+    }
+
+    /// Sends a render event to the render hook, or logs it without the rendered prompt.
+    fn emit(&self, template: &str, rendered: &str, outcome: RenderOutcome) {
+        let event = RenderEvent {
+            template,
+            rendered,
+            size: rendered.len(),
+            outcome,
+        };
+        match &self.render_hook {
+            Some(hook) => hook(&event),
+            None => log::debug!(
+                "Rendered template {} ({} bytes): {:?}",
+                template,
+                event.size,
+                event.outcome
+            ),
         }
     }
 
@@ -208,7 +491,64 @@ impl Clone for TemplateEngine {
         TemplateEngine {
             reg: self.reg.clone(),
             templates: self.templates.clone(),
+            segments: self.segments.clone(),
+            render_hook: self.render_hook.clone(),
+            limits: self.limits,
+        }
+    }
+}
+
+/// Error of a template failing to render.
+fn template_error(name: &str, error: handlebars::RenderError) -> anyhow::Error {
+    OrcaError::Template {
+        template: name.to_string(),
+        message: error.to_string(),
+    }
+    .into()
+}
+
+/// Add the variables read by the elements of a template to a list, skipping those already in it.
+fn collect_variables(template: &Template, variables: &mut Vec<String>) {
+    for element in &template.elements {
+        let helper = match element {
+            TemplateElement::Expression(helper)
+            | TemplateElement::HtmlExpression(helper)
+            | TemplateElement::HelperBlock(helper) => helper,
+            _ => continue,
+        };
+        // An expression without parameters, e.g. `{{question}}`, reads a variable of that name.
+        if helper.params.is_empty() && helper.hash.is_empty() && !helper.block {
+            collect_parameter(&helper.name, true, variables);
+        }
+        for parameter in helper.params.iter().chain(helper.hash.values()) {
+            collect_parameter(parameter, false, variables);
+        }
+        let scoped = matches!(&helper.name, Parameter::Name(name) if name == "each" || name == "with");
+        if !scoped {
+            for block in [&helper.template, &helper.inverse].into_iter().flatten() {
+                collect_variables(block, variables);
+            }
+        }
+    }
+}
+
+/// Add the variable read by a parameter of an expression to a list. Names are helpers, unless they are
+/// the name of an expression without parameters.
+fn collect_parameter(parameter: &Parameter, name_is_variable: bool, variables: &mut Vec<String>) {
+    let raw = match parameter {
+        Parameter::Name(name) if name_is_variable => name.as_str(),
+        Parameter::Path(Path::Relative((_, raw))) => raw.as_str(),
+        Parameter::Subexpression(subexpression) => {
+            for parameter in subexpression.params().into_iter().flatten() {
+                collect_parameter(parameter, false, variables);
+            }
+            return;
         }
+        _ => return,
+    };
+    let variable = raw.split(['.', '/', '[']).next().unwrap_or_default();
+    if !variable.is_empty() && variable != "this" && !variables.iter().any(|known| known == variable) {
+        variables.push(variable.to_string());
     }
 }
 
@@ -301,6 +641,19 @@ impl Prompt for Record {
     }
 }
 
+/// Rough estimate of the number of tokens in a text, assuming about four characters per token as is
+/// typical for English text with BPE tokenizers. Use this for budgeting decisions, not for exact limits.
+///
+/// # Example
+/// ```
+/// use orca_core::prompt::estimate_tokens;
+///
+/// assert_eq!(estimate_tokens("Hello, world"), 3);
+/// ```
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
 #[macro_export]
 macro_rules! prompt {
     ($e:expr) => {
@@ -420,4 +773,54 @@ mod test {
             )])
         );
     }
+
+    #[test]
+    fn test_render_hook() {
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let prompt_template = TemplateEngine::new()
+            .on_render(move |event| {
+                recorded.lock().unwrap().push((event.template.to_string(), event.size, event.outcome.clone()))
+            })
+            .register_template("text", "Hello, {{name}}!")
+            .unwrap()
+            .register_template("chat", "{{#chat}}{{#user}}Hello, {{name}}!{{/user}}{{/chat}}")
+            .unwrap()
+            .register_template("strict", "{{#each}}{{/each}}")
+            .unwrap();
+
+        let mut context = HashMap::new();
+        context.insert("name", "Orca");
+        prompt_template.render_context("text", &context).unwrap();
+        prompt_template.clone().render_context("chat", &context).unwrap();
+        assert!(prompt_template.render_context("strict", &context).is_err());
+
+        let events = events.lock().unwrap();
+        assert_eq!(events[0], ("text".to_string(), 12, RenderOutcome::Text));
+        assert_eq!(events[1].2, RenderOutcome::Chat(1));
+        assert!(matches!(events[2].2, RenderOutcome::Failed(_)));
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut prompt_template = TemplateEngine::new()
+            .with_limits(TemplateLimits::new().with_max_depth(0))
+            .register_template("template", "Hello, {{name}}!")
+            .unwrap();
+        assert!(TemplateEngine::new()
+            .with_limits(TemplateLimits::new())
+            .register_template("recursive", "{{> recursive}}")
+            .is_err());
+
+        // Appending a message adds a role block, which exceeds the depth of the limits.
+        let error = prompt_template.append_user("template", "Hi!").unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<OrcaError>(),
+            Some(OrcaError::TemplateLimit(TemplateLimitError::TooDeep { depth: 1, .. }))
+        ));
+        assert_eq!(prompt_template.get_segments("template").unwrap().len(), 1);
+
+        // Strict mode rejects missing variables.
+        assert!(prompt_template.render("template").is_err());
+    }
 }
@@ -0,0 +1,232 @@
+use super::chat::Role;
+
+const CHAT_OPEN: &str = "{{#chat}}";
+const CHAT_CLOSE: &str = "{{/chat}}";
+
+/// A single block of a template.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    /// A role block with its role, name and content, e.g. `{{#user}}...{{/user}}` or
+    /// `{{#tool name="search"}}...{{/tool}}`.
+    Message(Role, Option<String>, String),
+
+    /// Any template text that is not part of a role block.
+    Raw(String),
+}
+
+impl Segment {
+    /// Render the segment back into handlebars template syntax.
+    pub fn to_template(&self) -> String {
+        match self {
+            Segment::Message(role, None, content) => format!("{{{{#{}}}}}{}{{{{/{}}}}}", role, content, role),
+            Segment::Message(role, Some(name), content) => {
+                format!("{{{{#{} name=\"{}\"}}}}{}{{{{/{}}}}}", role, name, content, role)
+            }
+            Segment::Raw(text) => text.clone(),
+        }
+    }
+}
+
+/// The ordered segments of a template. Templates are stored structurally so that they can be
+/// extended without editing the template string by hand.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TemplateSegments {
+    /// Whether the segments are wrapped in a `{{#chat}}` block.
+    pub chat: bool,
+
+    /// Template text before the `{{#chat}}` block, e.g. an opening `{{#if}}` wrapping it.
+    pub prefix: String,
+
+    /// Ordered segments of the template, or of the body of its `{{#chat}}` block.
+    pub segments: Vec<Segment>,
+
+    /// Template text after the `{{#chat}}` block.
+    pub suffix: String,
+}
+
+impl TemplateSegments {
+    /// Parse a template string into ordered segments.
+    ///
+    /// If the template contains a `{{#chat}}` block, its body is split into segments, so that messages
+    /// added to the template end up in the conversation, and the text around the block is kept as is.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::prompt::chat::Role;
+    /// use orca_core::prompt::segment::{Segment, TemplateSegments};
+    ///
+    /// let segments = TemplateSegments::parse("{{#chat}}{{#user}}Hello{{/user}}{{/chat}}");
+    /// assert!(segments.chat);
+    /// assert_eq!(segments.segments, vec![Segment::Message(Role::User, None, "Hello".to_string())]);
+    /// ```
+    pub fn parse(template: &str) -> TemplateSegments {
+        match (template.find(CHAT_OPEN), template.rfind(CHAT_CLOSE)) {
+            (Some(start), Some(end)) if start < end => TemplateSegments {
+                chat: true,
+                prefix: template[..start].to_string(),
+                segments: parse_segments(&template[start + CHAT_OPEN.len()..end]),
+                suffix: template[end + CHAT_CLOSE.len()..].to_string(),
+            },
+            _ => TemplateSegments {
+                segments: parse_segments(template),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Render the segments back into a template string.
+    pub fn to_template(&self) -> String {
+        let body = self.segments.iter().map(|segment| segment.to_template()).collect::<String>();
+        if self.chat {
+            format!("{}{}{}{}{}", self.prefix, CHAT_OPEN, body, CHAT_CLOSE, self.suffix)
+        } else {
+            body
+        }
+    }
+}
+
+/// Split a template body into role blocks and the raw text between them.
+fn parse_segments(body: &str) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut rest = body;
+    while let Some((start, open_len, role, name)) = next_role_block(rest) {
+        let close = format!("{{{{/{}}}}}", role);
+        let content_start = start + open_len;
+        let len = match rest[content_start..].find(&close) {
+            Some(len) => len,
+            None => break,
+        };
+        if start > 0 {
+            segments.push(Segment::Raw(rest[..start].to_string()));
+        }
+        segments.push(Segment::Message(
+            role,
+            name,
+            rest[content_start..content_start + len].to_string(),
+        ));
+        rest = &rest[content_start + len + close.len()..];
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Raw(rest.to_string()));
+    }
+    segments
+}
+
+/// Find the first role block in the text, with the position and length of its opening tag, its role and
+/// its name.
+fn next_role_block(text: &str) -> Option<(usize, usize, Role, Option<String>)> {
+    let mut from = 0;
+    while let Some(i) = text[from..].find("{{#") {
+        let start = from + i;
+        if let Some((len, role, name)) = parse_opening_tag(&text[start..]) {
+            return Some((start, len, role, name));
+        }
+        from = start + 3;
+    }
+    None
+}
+
+/// Parse the opening tag of a role block at the start of the text, returning its length, role and name.
+/// Tags with arguments other than a `name` string, e.g. `call_id` or a variable name, are not parsed,
+/// so that their blocks are kept as raw text.
+fn parse_opening_tag(text: &str) -> Option<(usize, Role, Option<String>)> {
+    let end = text.find("}}")?;
+    let (helper, arguments) = match text[3..end].split_once(' ') {
+        Some((helper, arguments)) => (helper, arguments.trim()),
+        None => (&text[3..end], ""),
+    };
+    let role = match helper {
+        "system" => Role::System,
+        "user" => Role::User,
+        "assistant" => Role::Assistant,
+        "tool" => Role::Tool,
+        _ => return None,
+    };
+    let name = match arguments {
+        "" => None,
+        arguments => {
+            let name = arguments.strip_prefix("name=\"")?.strip_suffix('"')?;
+            if name.contains('"') {
+                return None;
+            }
+            Some(name.to_string())
+        }
+    };
+    Some((end + 2, role, name))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_chat() {
+        let template = "{{#chat}}{{#system}}You are {{name}}.{{/system}}\n{{#user}}Hi{{/user}}{{/chat}}";
+        let segments = TemplateSegments::parse(template);
+        assert!(segments.chat);
+        assert_eq!(
+            segments.segments,
+            vec![
+                Segment::Message(Role::System, None, "You are {{name}}.".to_string()),
+                Segment::Raw("\n".to_string()),
+                Segment::Message(Role::User, None, "Hi".to_string()),
+            ]
+        );
+        assert_eq!(segments.to_template(), template);
+    }
+
+    #[test]
+    fn test_parse_wrapped_chat() {
+        let template = "{{#if name}}{{#chat}}{{#user}}I am {{name}}{{/user}}{{/chat}}{{/if}}";
+        let mut segments = TemplateSegments::parse(template);
+        assert_eq!(segments.prefix, "{{#if name}}");
+        assert_eq!(
+            segments.segments,
+            vec![Segment::Message(Role::User, None, "I am {{name}}".to_string())]
+        );
+        assert_eq!(segments.suffix, "{{/if}}");
+        assert_eq!(segments.to_template(), template);
+
+        segments.segments.push(Segment::Message(Role::Assistant, None, "Hi!".to_string()));
+        assert_eq!(
+            segments.to_template(),
+            "{{#if name}}{{#chat}}{{#user}}I am {{name}}{{/user}}{{#assistant}}Hi!{{/assistant}}{{/chat}}{{/if}}"
+        );
+    }
+
+    #[test]
+    fn test_parse_plain() {
+        let template = "What is the capital of {{country}}?";
+        let segments = TemplateSegments::parse(template);
+        assert!(!segments.chat);
+        assert_eq!(segments.segments, vec![Segment::Raw(template.to_string())]);
+        assert_eq!(segments.to_template(), template);
+    }
+
+    #[test]
+    fn test_unclosed_block_is_raw() {
+        let template = "{{#user}}Hi";
+        let segments = TemplateSegments::parse(template);
+        assert_eq!(segments.segments, vec![Segment::Raw(template.to_string())]);
+    }
+
+    #[test]
+    fn test_parse_named() {
+        let template =
+            r#"{{#chat}}{{#user name="alice"}}J pod?{{/user}}{{#tool name="search"}}{{results}}{{/tool}}{{/chat}}"#;
+        let segments = TemplateSegments::parse(template);
+        assert_eq!(
+            segments.segments,
+            vec![
+                Segment::Message(Role::User, Some("alice".to_string()), "J pod?".to_string()),
+                Segment::Message(Role::Tool, Some("search".to_string()), "{{results}}".to_string()),
+            ]
+        );
+        assert_eq!(segments.to_template(), template);
+
+        // Blocks with other arguments are kept as is.
+        let template = r#"{{#tool name="search" call_id="call_1"}}{{results}}{{/tool}}"#;
+        let segments = TemplateSegments::parse(template);
+        assert_eq!(segments.segments, vec![Segment::Raw(template.to_string())]);
+    }
+}
@@ -0,0 +1,1525 @@
+pub mod tenant;
+#[cfg(feature = "testcontainers")]
+pub mod testing;
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+pub use qdrant_client::prelude::Value as QdrantValue;
+use qdrant_client::prelude::*;
+use qdrant_client::qdrant::alias_operations::Action;
+use qdrant_client::qdrant::payload_index_params::IndexParams;
+use qdrant_client::qdrant::point_id::PointIdOptions;
+use qdrant_client::qdrant::quantization_config::Quantization;
+use qdrant_client::qdrant::value::Kind;
+use qdrant_client::qdrant::vectors_config::Config;
+use qdrant_client::qdrant::{
+    AliasOperations, ChangeAliases, CreateAlias, CreateCollection, DeleteAlias, FieldType, Filter, PayloadIndexParams,
+    PointsSelector, QuantizationConfig, QuantizationType, Range as QdrantRange, ScalarQuantization, SearchPoints,
+    TextIndexParams, TokenizerType, VectorParams, VectorsConfig,
+};
+use serde::Serialize;
+
+use crate::error::OrcaError;
+use crate::llm::{Embedding, Embeddings, Precision};
+use crate::record::Record;
+use crate::vectorstore::{self as store, Point, SearchHit, SearchQuery, VectorStore, VectorStoreUnavailable};
+
+/// Trait to convert a type to a Qdrant payload.
+pub trait ToPayload {
+    fn to_payload(self) -> Result<Payload>;
+}
+
+impl<T> ToPayload for T
+where
+    T: Serialize,
+{
+    fn to_payload(self) -> Result<Payload> {
+        let value = serde_json::to_value(self)?;
+
+        if let serde_json::Value::Object(map) = value {
+            let converted_map: HashMap<String, QdrantValue> =
+                map.into_iter().map(|(k, v)| (k, QdrantValue::from(v))).collect();
+            Ok(Payload::new_from_hashmap(converted_map))
+        } else {
+            // If the value is not an object, wrap it in a map with a generic key.
+            let mut map = HashMap::new();
+            map.insert("value".to_string(), QdrantValue::from(value));
+            Ok(Payload::new_from_hashmap(map))
+        }
+    }
+}
+
+/// Represents a found point in the vector database.
+#[derive(Debug, Clone)]
+pub struct FoundPoint {
+    pub id: u64,
+    pub score: f32,
+    pub payload: Option<HashMap<String, QdrantValue>>, // assuming Value is from serde_json
+}
+
+impl FoundPoint {
+    /// Deserialize the payload of the point as a `Record`, if it was inserted from one. The similarity
+    /// score of the point is set as the `score` attribute of the record.
+    pub fn to_record(&self) -> Option<Record> {
+        self.payload.as_ref()?;
+        SearchHit::from(self.clone()).to_record()
+    }
+}
+
+impl From<FoundPoint> for SearchHit {
+    fn from(point: FoundPoint) -> Self {
+        SearchHit {
+            id: point.id,
+            score: point.score,
+            payload: point.payload.unwrap_or_default().into_iter().map(|(key, value)| (key, value.into())).collect(),
+        }
+    }
+}
+
+pub type Value = QdrantValue;
+
+/// Bounds of a `Condition::Range`. Bounds that are not set are ignored.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Range {
+    pub gt: Option<f64>,
+    pub gte: Option<f64>,
+    pub lt: Option<f64>,
+    pub lte: Option<f64>,
+}
+
+/// Represents search conditions for the Qdrant wrapper.
+#[derive(Debug, Clone)]
+pub enum Condition {
+    Matches(String, Value), // Assuming Value is from serde_json or your own type
+    /// The field matches any of the given keywords.
+    MatchesAny(String, Vec<String>),
+    /// The numeric field is within the given bounds.
+    Range(String, Range),
+    /// The inner condition does not hold.
+    Not(Box<Condition>),
+}
+
+impl Condition {
+    /// Matches points whose timestamp field, in seconds since the Unix epoch, is at or after the
+    /// given timestamp.
+    pub fn since(field: &str, timestamp: f64) -> Condition {
+        Condition::Range(
+            field.to_string(),
+            Range {
+                gte: Some(timestamp),
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Matches points whose timestamp field, in seconds since the Unix epoch, is within the last
+    /// `days` days.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::qdrant::Condition;
+    ///
+    /// let condition = Condition::within_days("published_at", 30);
+    /// ```
+    pub fn within_days(field: &str, days: u64) -> Condition {
+        Condition::since(field, now() - Duration::from_secs(days * 24 * 60 * 60).as_secs_f64())
+    }
+}
+
+/// Current time in seconds since the Unix epoch.
+fn now() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64()
+}
+
+/// Time decay used to favor recent points in `Qdrant::search_recent`.
+///
+/// The score of each point is `(1 - weight) * similarity + weight * 0.5^(age / half_life)`, where
+/// the age is computed from a timestamp field of the payload in seconds since the Unix epoch. Points
+/// without a timestamp get no recency score.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeDecay {
+    /// Payload field holding the timestamp. Nested fields are separated by dots, e.g.
+    /// `attributes.timestamp`.
+    pub field: String,
+
+    /// Age at which the recency score is halved.
+    pub half_life: Duration,
+
+    /// Weight of the recency score, between 0 and 1.
+    pub weight: f32,
+
+    /// Number of candidates fetched for each result, so that recent points slightly less similar
+    /// than the top results can still be returned.
+    pub oversample: usize,
+}
+
+impl TimeDecay {
+    /// Create a new time decay with a weight of 0.5 and an oversampling factor of 4.
+    pub fn new(field: &str, half_life: Duration) -> Self {
+        Self {
+            field: field.to_string(),
+            half_life,
+            weight: 0.5,
+            oversample: 4,
+        }
+    }
+
+    /// Set the weight of the recency score, between 0 and 1.
+    pub fn with_weight(mut self, weight: f32) -> Self {
+        self.weight = weight.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Set the number of candidates fetched for each result.
+    pub fn with_oversample(mut self, oversample: usize) -> Self {
+        self.oversample = oversample.max(1);
+        self
+    }
+
+    /// Recency score of a point with the given timestamp, between 0 and 1.
+    pub fn recency(&self, timestamp: f64, now: f64) -> f32 {
+        let age = (now - timestamp).max(0.0);
+        let half_life = self.half_life.as_secs_f64().max(1.0);
+        0.5_f64.powf(age / half_life) as f32
+    }
+
+    /// Rescore the points and sort them by descending score.
+    pub fn rerank(&self, points: Vec<FoundPoint>, now: f64) -> Vec<FoundPoint> {
+        let mut points = points
+            .into_iter()
+            .map(|mut point| {
+                let recency = point
+                    .payload
+                    .as_ref()
+                    .and_then(|payload| payload_number(payload, &self.field))
+                    .map_or(0.0, |timestamp| self.recency(timestamp, now));
+                point.score = (1.0 - self.weight) * point.score + self.weight * recency;
+                point
+            })
+            .collect::<Vec<_>>();
+        points.sort_by(|a, b| b.score.total_cmp(&a.score));
+        points
+    }
+}
+
+/// Read a numeric field from a payload, following dots into nested objects.
+fn payload_number(payload: &HashMap<String, Value>, path: &str) -> Option<f64> {
+    let mut parts = path.split('.');
+    let mut value = payload.get(parts.next()?)?;
+    for part in parts {
+        value = match &value.kind {
+            Some(Kind::StructValue(object)) => object.fields.get(part)?,
+            _ => return None,
+        };
+    }
+    match value.kind {
+        Some(Kind::IntegerValue(i)) => Some(i as f64),
+        Some(Kind::DoubleValue(d)) => Some(d),
+        _ => None,
+    }
+}
+
+/// Converts a `Value` to a `MatchValue` for use in a `Condition`.
+fn convert_to_match_value(value: qdrant_client::prelude::Value) -> qdrant_client::qdrant::r#match::MatchValue {
+    match value.kind {
+        Some(Kind::BoolValue(b)) => b.into(),
+        Some(Kind::IntegerValue(i)) => i.into(),
+        Some(Kind::StringValue(s)) => s.into(),
+        Some(Kind::DoubleValue(d)) => {
+            // You might decide to handle this differently since MatchValue doesn't seem to support f64 directly.
+            panic!("Unsupported double value: {}", d)
+        }
+        Some(Kind::StructValue(_)) => {
+            // This represents a complex structure and might need specialized handling.
+            panic!("Unsupported structured value")
+        }
+        Some(Kind::ListValue(_)) => {
+            // This represents a list and might need specialized handling.
+            panic!("Unsupported list value")
+        }
+        Some(Kind::NullValue(_)) | None => {
+            panic!("Null or unsupported value type")
+        }
+    }
+}
+
+impl Condition {
+    /// Converts a `Condition` to a `qdrant_client::qdrant::Condition`.
+    fn to_qdrant_condition(&self) -> qdrant_client::qdrant::Condition {
+        match self {
+            Condition::Matches(key, value) => {
+                let match_value = convert_to_match_value(value.clone());
+                qdrant_client::qdrant::Condition::matches(key, match_value)
+            }
+            Condition::MatchesAny(key, values) => qdrant_client::qdrant::Condition::matches(key, values.clone()),
+            Condition::Range(key, range) => qdrant_client::qdrant::Condition::range(
+                key,
+                QdrantRange {
+                    gt: range.gt,
+                    gte: range.gte,
+                    lt: range.lt,
+                    lte: range.lte,
+                },
+            ),
+            Condition::Not(condition) => Filter::none([condition.to_qdrant_condition()]).into(),
+        }
+    }
+}
+
+impl TryFrom<&store::Filter> for Condition {
+    type Error = anyhow::Error;
+
+    /// Converts a backend-neutral filter, erroring on values Qdrant cannot match on. Floats are matched
+    /// with a range, since Qdrant only matches booleans, integers and strings exactly.
+    fn try_from(filter: &store::Filter) -> Result<Self> {
+        match filter {
+            store::Filter::Matches(key, serde_json::Value::Number(number)) if number.as_i64().is_none() => {
+                let value = number.as_f64();
+                Ok(Condition::Range(
+                    key.clone(),
+                    Range {
+                        gte: value,
+                        lte: value,
+                        ..Default::default()
+                    },
+                ))
+            }
+            store::Filter::Matches(
+                key,
+                value @ (serde_json::Value::Bool(_) | serde_json::Value::Number(_) | serde_json::Value::String(_)),
+            ) => Ok(Condition::Matches(key.clone(), value.clone().into())),
+            store::Filter::Matches(key, value) => Err(anyhow::anyhow!(
+                "Cannot match field {} against {} in Qdrant",
+                key,
+                value
+            )),
+            store::Filter::MatchesAny(key, values) => values
+                .iter()
+                .map(|value| match value {
+                    serde_json::Value::String(value) => Ok(value.clone()),
+                    value => Err(anyhow::anyhow!(
+                        "Qdrant only matches keywords, got {} for field {}",
+                        value,
+                        key
+                    )),
+                })
+                .collect::<Result<_>>()
+                .map(|values| Condition::MatchesAny(key.clone(), values)),
+            store::Filter::Range(key, range) => Ok(Condition::Range(key.clone(), range.clone())),
+            store::Filter::Not(filter) => Ok(Condition::Not(Box::new(filter.as_ref().try_into()?))),
+        }
+    }
+}
+
+/// Kind of payload index, which speeds up searches filtering on the indexed field.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldIndex {
+    /// Exact matches on strings, e.g. `Condition::Matches` and `Condition::MatchesAny`.
+    Keyword,
+
+    /// Matches and ranges on integers.
+    Integer,
+
+    /// Ranges on floats, e.g. Unix timestamps with fractional seconds.
+    Float,
+
+    /// Ranges on RFC 3339 dates.
+    Datetime,
+
+    /// Full-text matches on strings, tokenized into lowercase words.
+    Text,
+}
+
+impl FieldIndex {
+    /// Qdrant field type and index parameters of the index.
+    fn to_qdrant(self) -> (FieldType, Option<PayloadIndexParams>) {
+        match self {
+            FieldIndex::Keyword => (FieldType::Keyword, None),
+            FieldIndex::Integer => (FieldType::Integer, None),
+            FieldIndex::Float => (FieldType::Float, None),
+            FieldIndex::Datetime => (FieldType::Datetime, None),
+            FieldIndex::Text => (
+                FieldType::Text,
+                Some(PayloadIndexParams {
+                    index_params: Some(IndexParams::TextIndexParams(TextIndexParams {
+                        tokenizer: TokenizerType::Word.into(),
+                        lowercase: Some(true),
+                        min_token_len: None,
+                        max_token_len: None,
+                    })),
+                }),
+            ),
+        }
+    }
+}
+
+/// Error out when a collection's vector size differs from the size of the embeddings.
+fn check_dimensions(collection_name: &str, collection_size: u64, embedding_size: usize) -> Result<()> {
+    if collection_size != embedding_size as u64 {
+        return Err(qdrant_error(format!(
+            "Collection {} stores vectors of {} dimensions, but the embeddings have {} dimensions",
+            collection_name, collection_size, embedding_size
+        )));
+    }
+    Ok(())
+}
+
+/// Quantization of a collection storing embeddings of the given precision. Reduced precision embeddings
+/// are kept in RAM as Qdrant's int8 scalar quantized vectors, with the original vectors on disk.
+fn quantization_config(precision: Precision) -> Option<QuantizationConfig> {
+    match precision {
+        Precision::F32 => None,
+        Precision::F16 | Precision::Int8 => Some(QuantizationConfig {
+            quantization: Some(Quantization::Scalar(ScalarQuantization {
+                r#type: QuantizationType::Int8.into(),
+                quantile: None,
+                always_ram: Some(true),
+            })),
+        }),
+    }
+}
+
+/// Backoff of the retries of requests failing because Qdrant is unreachable. Other errors, such as a
+/// missing collection, are not retried.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Number of retries after the first attempt.
+    pub max_retries: u32,
+
+    /// Wait before the first retry, doubled on each retry.
+    pub initial_backoff: Duration,
+
+    /// Maximum wait between retries.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    /// 3 retries, waiting 100ms, 200ms then 400ms.
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy making a single attempt.
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_retries: 0,
+            ..Default::default()
+        }
+    }
+
+    /// Wait before the given retry, starting from 0.
+    fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff.saturating_mul(2u32.saturating_pow(retry)).min(self.max_backoff)
+    }
+}
+
+/// Whether an error of the Qdrant client is due to the server being unreachable, as opposed to an
+/// invalid request. The client only exposes its errors as text, so transport failures are told apart
+/// by their message.
+fn is_unavailable(error: &anyhow::Error) -> bool {
+    let message = format!("{:#}", error);
+    [
+        "Unavailable",
+        "DeadlineExceeded",
+        "transport error",
+        "error trying to connect",
+        "Connection refused",
+        "Connection reset",
+    ]
+    .iter()
+    .any(|pattern| message.contains(pattern))
+}
+
+/// Whether an error is Qdrant staying unreachable after retries.
+fn is_outage(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<OrcaError>(),
+        Some(OrcaError::VectorStoreUnavailable(_))
+    )
+}
+
+/// Error of a request that Qdrant failed or rejected.
+fn qdrant_error(message: String) -> anyhow::Error {
+    OrcaError::VectorStore {
+        store: "Qdrant".to_string(),
+        message,
+    }
+    .into()
+}
+
+pub struct Qdrant {
+    client: QdrantClient,
+
+    /// Retries of the requests failing because Qdrant is unreachable.
+    retry: RetryPolicy,
+
+    /// Store serving searches while Qdrant is unreachable.
+    fallback: Option<Arc<dyn VectorStore>>,
+
+    /// Vector size of the collections written to, by collection or alias name, so that writes are
+    /// checked without asking Qdrant for the collection every time.
+    dimensions: Mutex<HashMap<String, u64>>,
+}
+
+impl Qdrant {
+    /// Creates a new `Qdrant` instance with the given `host` and `port`.
+    ///
+    /// # Arguments
+    /// * `host` - A string slice that holds the IP address or hostname of the Qdrant server.
+    /// * `port` - An unsigned 16-bit integer that holds the port number of the Qdrant server.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::qdrant::Qdrant;
+    ///
+    /// let client = Qdrant::new("http://localhost:6334").unwrap();
+    /// ```
+    pub fn new(url: &str) -> anyhow::Result<Self> {
+        let config = QdrantClientConfig::from_url(url);
+        let client = QdrantClient::new(Some(config))?;
+        Ok(Qdrant::from_client(client))
+    }
+
+    /// Creates a new `Qdrant` instance and checks that the server is reachable, retrying with the
+    /// given policy. Fails with `VectorStoreUnavailable` if it is not, rather than on the first request
+    /// of a pipeline.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use orca_core::qdrant::{Qdrant, RetryPolicy};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let client = Qdrant::connect("http://localhost:6334", RetryPolicy::default()).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect(url: &str, retry: RetryPolicy) -> Result<Self> {
+        let qdrant = Qdrant::new(url)?.with_retry(retry);
+        qdrant.health_check().await?;
+        Ok(qdrant)
+    }
+
+    /// Creates a new `Qdrant` instance given an existing `QdrantClient`.
+    /// This is useful if you want to use a custom `QdrantClient` instance.
+    ///
+    /// # Arguments
+    /// * `client` - An existing `QdrantClient` instance.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::qdrant::Qdrant;
+    /// use qdrant_client::prelude::QdrantClient;
+    ///
+    /// let client = QdrantClient::new(None).unwrap();
+    /// let qdrant = Qdrant::from_client(client);
+    /// ```
+    pub fn from_client(client: QdrantClient) -> Self {
+        Qdrant {
+            client,
+            retry: RetryPolicy::default(),
+            fallback: None,
+            dimensions: Mutex::default(),
+        }
+    }
+
+    /// Sets how requests failing because Qdrant is unreachable are retried. By default, they are
+    /// retried 3 times before failing with `VectorStoreUnavailable`.
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Sets a store serving searches while Qdrant is unreachable, e.g. a `MemoryStore` loaded with a
+    /// snapshot of the collections. The fallback is read-only: writes still fail with
+    /// `VectorStoreUnavailable`.
+    pub fn with_read_fallback(mut self, fallback: Arc<dyn VectorStore>) -> Self {
+        self.fallback = Some(fallback);
+        self
+    }
+
+    /// Checks that the server is reachable, returning its version.
+    pub async fn health_check(&self) -> Result<String> {
+        let reply = self.call("health check", || self.client.health_check()).await?;
+        Ok(reply.version)
+    }
+
+    /// Sends a request, retrying it while Qdrant is unreachable.
+    async fn call<T, F, Fut>(&self, operation: &str, request: F) -> Result<T>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut retry = 0;
+        loop {
+            match request().await {
+                Err(e) if is_unavailable(&e) => {
+                    if retry == self.retry.max_retries {
+                        return Err(OrcaError::VectorStoreUnavailable(VectorStoreUnavailable {
+                            store: "qdrant".to_string(),
+                            operation: operation.to_string(),
+                            attempts: retry + 1,
+                            reason: format!("{:#}", e),
+                        })
+                        .into());
+                    }
+                    let backoff = self.retry.backoff(retry);
+                    log::warn!(
+                        "Qdrant is unreachable, retrying {} in {:?}: {:#}",
+                        operation,
+                        backoff,
+                        e
+                    );
+                    tokio::time::sleep(backoff).await;
+                    retry += 1;
+                }
+                Err(e) => return Err(qdrant_error(format!("{} failed: {:#}", operation, e))),
+                result => return result,
+            }
+        }
+    }
+
+    /// Creates a new collection with the given name and vector size.
+    ///
+    /// # Arguments
+    /// * `collection_name` - A string slice that holds the name of the collection to be created.
+    /// * `vector_size` - An unsigned 64-bit integer that represents the size of the vectors in the collection.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use orca_core::qdrant::Qdrant;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let client = Qdrant::new("http://localhost:6334").unwrap();
+    /// let collection_name = "test_collection";
+    /// let vector_size = 128;
+    /// client.create_collection(collection_name, vector_size).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn create_collection(&self, collection_name: &str, vector_size: u64) -> Result<()> {
+        self.create_collection_with_precision(collection_name, vector_size, Precision::F32).await
+    }
+
+    /// Creates a new collection for embeddings of the given precision. `F16` and `Int8` collections
+    /// store quantized vectors in memory and keep the original vectors on disk.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use orca_core::qdrant::Qdrant;
+    /// # use orca_core::llm::Precision;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let client = Qdrant::new("http://localhost:6334").unwrap();
+    /// client.create_collection_with_precision("test_collection", 384, Precision::Int8).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn create_collection_with_precision(
+        &self,
+        collection_name: &str,
+        vector_size: u64,
+        precision: Precision,
+    ) -> Result<()> {
+        let quantization_config = quantization_config(precision);
+        let config = Some(Config::Params(VectorParams {
+            size: vector_size,
+            distance: Distance::Cosine.into(),
+            on_disk: quantization_config.as_ref().map(|_| true),
+            quantization_config,
+            ..Default::default()
+        }));
+        let vectors_config = VectorsConfig { config };
+        let create_collection = CreateCollection {
+            collection_name: collection_name.to_string(),
+            vectors_config: Some(vectors_config),
+            ..Default::default()
+        };
+        self.call("create collection", || {
+            self.client.create_collection(&create_collection)
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Creates a collection sized for the embeddings of the given model, quantized if the model produces
+    /// reduced precision embeddings, or checks that an existing collection has the same size. A mismatch
+    /// is reported here rather than at search time.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use orca_core::qdrant::Qdrant;
+    /// # use orca_core::llm::bert::Bert;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let client = Qdrant::new("http://localhost:6334").unwrap();
+    /// let bert = Bert::new().build_model_and_tokenizer().await?;
+    /// client.create_collection_for("test_collection", &bert).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn create_collection_for<E: Embedding>(&self, collection_name: &str, embedding: &E) -> Result<()> {
+        let vector_size = embedding.dimensions() as u64;
+        match self.collection_dimensions(collection_name).await? {
+            Some(size) => check_dimensions(collection_name, size, vector_size as usize),
+            None => self.create_collection_with_precision(collection_name, vector_size, embedding.precision()).await,
+        }
+    }
+
+    /// Creates a collection with the given vector size if it does not exist, or checks that the existing
+    /// collection has the same vector size.
+    pub async fn ensure_collection(&self, collection_name: &str, vector_size: u64) -> Result<()> {
+        match self.collection_dimensions(collection_name).await? {
+            Some(size) => check_dimensions(collection_name, size, vector_size as usize),
+            None => self.create_collection(collection_name, vector_size).await,
+        }
+    }
+
+    /// Returns the vector size of a collection, or of the collection an alias points to, or `None` if
+    /// neither exists.
+    pub async fn collection_dimensions(&self, collection_name: &str) -> Result<Option<u64>> {
+        let Some(collection_name) = self.resolve(collection_name).await? else {
+            return Ok(None);
+        };
+        let collection_name = collection_name.as_str();
+        let info = self.call("get collection", || self.client.collection_info(collection_name)).await?;
+        let vectors_config = info
+            .result
+            .and_then(|info| info.config)
+            .and_then(|config| config.params)
+            .and_then(|params| params.vectors_config)
+            .and_then(|vectors_config| vectors_config.config);
+        match vectors_config {
+            Some(Config::Params(params)) => Ok(Some(params.size)),
+            _ => Err(anyhow::anyhow!(
+                "Collection {} does not have a single unnamed vector configuration",
+                collection_name
+            )),
+        }
+    }
+
+    /// Returns the collection a name refers to, following aliases, or `None` if there is neither a
+    /// collection nor an alias with that name. `has_collection` only lists collections, so aliases are
+    /// looked up separately.
+    async fn resolve(&self, name: &str) -> Result<Option<String>> {
+        if self.call("check collection", || self.client.has_collection(name)).await? {
+            return Ok(Some(name.to_string()));
+        }
+        self.alias_target(name).await
+    }
+
+    /// Checks that the vectors have the same size as each other and as the collection. The size of the
+    /// collection is only looked up on the first write to it.
+    async fn check_vectors(&self, collection_name: &str, vectors: &[Vec<f32>]) -> Result<()> {
+        let Some(first) = vectors.first() else {
+            return Ok(());
+        };
+        if let Some((index, vector)) = vectors.iter().enumerate().find(|(_, vector)| vector.len() != first.len()) {
+            return Err(anyhow::anyhow!(
+                "Vector at index {} has {} dimensions, but the first vector has {}",
+                index,
+                vector.len(),
+                first.len()
+            ));
+        }
+        let cached = self.dimensions.lock().unwrap().get(collection_name).copied();
+        let size = match cached {
+            Some(size) => size,
+            None => {
+                let size = self
+                    .collection_dimensions(collection_name)
+                    .await?
+                    .ok_or_else(|| qdrant_error(format!("Collection {} does not exist", collection_name)))?;
+                self.dimensions.lock().unwrap().insert(collection_name.to_string(), size);
+                size
+            }
+        };
+        check_dimensions(collection_name, size, first.len())
+    }
+
+    /// Deletes a collection with the given name.
+    ///
+    /// # Arguments
+    ///
+    /// * `collection_name` - A string slice that holds the name of the collection to be deleted.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use orca_core::qdrant::Qdrant;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let client = Qdrant::new("http://localhost:6334").unwrap();
+    /// let collection_name = "test_collection";
+    /// client.delete_collection(collection_name).await?;
+    /// # Ok(())
+    /// # }
+    pub async fn delete_collection(&self, collection_name: &str) -> Result<()> {
+        self.call("delete collection", || self.client.delete_collection(collection_name)).await?;
+        self.dimensions.lock().unwrap().clear();
+        Ok(())
+    }
+
+    /// Creates a payload index on a field of a collection. Nested fields are given as dotted paths,
+    /// e.g. `attributes.page` for an attribute of the records stored in the collection.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use orca_core::qdrant::{FieldIndex, Qdrant};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let client = Qdrant::new("http://localhost:6334").unwrap();
+    /// client.create_field_index("papers", "attributes.year", FieldIndex::Integer).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn create_field_index(&self, collection_name: &str, field: &str, index: FieldIndex) -> Result<()> {
+        let (field_type, params) = index.to_qdrant();
+        self.call("create field index", || {
+            self.client.create_field_index(collection_name, field, field_type, params.as_ref(), None)
+        })
+        .await
+        .with_context(|| format!("Failed to index field {} of collection {}", field, collection_name))?;
+        Ok(())
+    }
+
+    /// Inserts a new point into the specified collection with the given vector and payload.
+    ///
+    /// # Arguments
+    /// * `collection_name` - A string slice that holds the name of the collection.
+    /// * `vector` - A vector of 32-bit floating point numbers that represents the point's vector.
+    /// * `payload` - A generic type that holds the payload to be associated with the point.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use orca_core::qdrant::Qdrant;
+    /// # use serde::{Serialize, Deserialize};
+    /// # #[derive(Serialize, Deserialize)]
+    /// # struct MyPayload {
+    /// #     name: String,
+    /// #     age: u8,
+    /// # }
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let client = Qdrant::new("http://localhost:6334").unwrap();
+    /// let collection_name = "my_collection";
+    /// let vector = vec![0.1, 0.2, 0.3];
+    /// let payload = MyPayload { name: "John".to_string(), age: 30 };
+    /// client.insert(collection_name, vector, payload).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn insert<T>(&self, collection_name: &str, vector: Vec<f32>, payload: T) -> Result<()>
+    where
+        T: ToPayload,
+    {
+        let payload: Payload = payload.to_payload()?;
+        self.check_vectors(collection_name, std::slice::from_ref(&vector)).await?;
+        let points = vec![PointStruct::new(0, vector, payload)];
+        self.call("upsert", || {
+            self.client.upsert_points_blocking(collection_name, None, points.clone(), None)
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Inserts multiple vectors and their corresponding payloads into the specified collection.
+    ///
+    /// # Arguments
+    /// * `collection_name` - The name of the collection to insert the vectors and payloads into.
+    /// * `vectors` - A vector of vectors, where each inner vector represents a vector to be inserted.
+    /// * `payloads` - A vector of payloads, where each payload corresponds to a vector to be inserted.
+    ///
+    /// # Returns
+    /// Returns a `Result` indicating whether the operation was successful or not.
+    ///
+    /// # Type Parameters
+    /// * `T` - The type of the payload.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use orca_core::qdrant::Qdrant;
+    /// # use std::error::Error;
+    /// #
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn Error>> {
+    /// # let client = Qdrant::new("http://localhost:6334").unwrap();
+    /// let vectors = vec![vec![1.0, 2.0, 3.0], vec![4.0, 5.0, 6.0]];
+    /// let payloads = vec!["payload1".to_string(), "payload2".to_string()];
+    /// client.insert_many("collection_name", vectors, payloads).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn insert_many<T>(
+        &self,
+        collection_name: &str,
+        vectors: Vec<Vec<f32>>,
+        payloads: Vec<T>,
+    ) -> anyhow::Result<()>
+    where
+        T: ToPayload,
+    {
+        let ids = (0..vectors.len() as u64).collect();
+        self.upsert_many(collection_name, ids, vectors, payloads).await
+    }
+
+    /// Inserts multiple vectors and their corresponding payloads with the given point ids, replacing
+    /// existing points with the same ids.
+    pub async fn upsert_many<T>(
+        &self,
+        collection_name: &str,
+        ids: Vec<u64>,
+        vectors: Vec<Vec<f32>>,
+        payloads: Vec<T>,
+    ) -> Result<()>
+    where
+        T: ToPayload,
+    {
+        self.check_vectors(collection_name, &vectors).await?;
+        let points_result: anyhow::Result<Vec<PointStruct>> = ids
+            .into_iter()
+            .zip(vectors.into_iter().zip(payloads.into_iter()))
+            .enumerate()
+            .map(|(index, (id, (vector, payload)))| {
+                let payload =
+                    payload.to_payload().with_context(|| format!("Failed to convert payload at index {}", index))?;
+                Ok(PointStruct::new(id, vector, payload))
+            })
+            .collect();
+
+        let points = points_result?;
+
+        self.call("upsert", || {
+            self.client.upsert_points_blocking(collection_name, None, points.clone(), None)
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Inserts embeddings with their payloads into a collection. Reduced precision embeddings are sent at
+    /// full precision and quantized by the collection.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use orca_core::qdrant::Qdrant;
+    /// # use orca_core::llm::{bert::Bert, Embedding, Precision};
+    /// # use orca_core::prompts;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let client = Qdrant::new("http://localhost:6334").unwrap();
+    /// let bert = Bert::new().with_precision(Precision::Int8).build_model_and_tokenizer().await?;
+    /// client.create_collection_for("collection_name", &bert).await?;
+    /// let embeddings = bert.generate_embeddings(prompts!("Hello World", "Goodbye World")).await?;
+    /// client.insert_embeddings("collection_name", embeddings, vec!["hello", "goodbye"]).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn insert_embeddings<T>(
+        &self,
+        collection_name: &str,
+        embeddings: Embeddings,
+        payloads: Vec<T>,
+    ) -> Result<()>
+    where
+        T: ToPayload,
+    {
+        self.insert_many(collection_name, embeddings.into_vectors(), payloads).await
+    }
+
+    /// Searches for points in a given collection that match the specified conditions.
+    ///
+    /// # Arguments
+    /// * `collection_name` - The name of the collection to search in.
+    /// * `vector` - The vector to search for.
+    /// * `limit` - The maximum number of results to return.
+    /// * `conditions` - Optional conditions to filter the search results.
+    /// * `score_threshold` - Optional minimum similarity score; points scoring below it are not returned.
+    ///
+    /// # Returns
+    /// A `Result` containing a `Vec` of `FoundPoint`s that match the search criteria.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use orca_core::qdrant::{Qdrant, Condition};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let client = Qdrant::new("http://localhost:6334").unwrap();
+    /// let conditions = vec![Condition::Matches(
+    ///    "age".into(),
+    ///     30.into(),
+    /// )];
+    /// let results = client
+    ///     .search("my_collection", vec![1.0, 2.0, 3.0], 10, Some(conditions), Some(0.5))
+    ///     .await?;
+    /// for result in results {
+    ///     println!("Found point with ID {} and score {}", result.id, result.score);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn search(
+        &self,
+        collection_name: &str,
+        vector: Vec<f32>,
+        limit: usize,
+        conditions: Option<Vec<Condition>>,
+        score_threshold: Option<f32>,
+    ) -> Result<Vec<FoundPoint>> {
+        let filter = conditions.map(|cond| Filter::all(cond.into_iter().map(|c| c.to_qdrant_condition())));
+        let search_request = SearchPoints {
+            collection_name: collection_name.into(),
+            vector,
+            filter,
+            limit: limit as u64,
+            with_payload: Some(true.into()),
+            score_threshold,
+            ..Default::default()
+        };
+
+        let response = self.call("search", || self.client.search_points(&search_request)).await?;
+
+        let results: Vec<FoundPoint> = response
+            .result
+            .into_iter()
+            .filter_map(|scored_point| {
+                let id = match scored_point.id {
+                    Some(point_id) => {
+                        match point_id.point_id_options {
+                            Some(PointIdOptions::Num(id)) => id,
+                            _ => return None, // Ignore other variants or if it's None
+                        }
+                    }
+                    None => return None, // Skip this point if it doesn't have an ID
+                };
+                let score = scored_point.score;
+                let payload = scored_point.payload;
+                Some(FoundPoint {
+                    id,
+                    score,
+                    payload: Some(payload),
+                })
+            })
+            .collect();
+
+        Ok(results)
+    }
+
+    /// Searches for points like `search`, but combines the similarity of each point with how
+    /// recent it is. See `TimeDecay` for how the scores are combined.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use orca_core::qdrant::{Condition, Qdrant, TimeDecay};
+    /// # use std::time::Duration;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let client = Qdrant::new("http://localhost:6334").unwrap();
+    /// let decay = TimeDecay::new("published_at", Duration::from_secs(7 * 24 * 60 * 60));
+    /// let conditions = vec![Condition::within_days("published_at", 30)];
+    /// let results = client
+    ///     .search_recent("news", vec![1.0, 2.0, 3.0], 10, Some(conditions), &decay)
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn search_recent(
+        &self,
+        collection_name: &str,
+        vector: Vec<f32>,
+        limit: usize,
+        conditions: Option<Vec<Condition>>,
+        decay: &TimeDecay,
+    ) -> Result<Vec<FoundPoint>> {
+        let candidates = self.search(collection_name, vector, limit * decay.oversample, conditions, None).await?;
+        let mut results = decay.rerank(candidates, now());
+        results.truncate(limit);
+        Ok(results)
+    }
+
+    /// Deletes the points of a collection matching all the conditions, e.g. the chunks of a document
+    /// being re-ingested.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use orca_core::qdrant::{Condition, Qdrant};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let client = Qdrant::new("http://localhost:6334").unwrap();
+    /// let conditions = vec![Condition::Matches("attributes.source".into(), "report.pdf".into())];
+    /// client.delete_by_filter("papers", conditions).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn delete_by_filter(&self, collection_name: &str, conditions: Vec<Condition>) -> Result<()> {
+        if conditions.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Refusing to delete the points of collection {} without conditions, delete the collection instead",
+                collection_name
+            ));
+        }
+        let filter = Filter::all(conditions.into_iter().map(|c| c.to_qdrant_condition()));
+        let selector = PointsSelector::from(filter);
+        self.call("delete points", || {
+            self.client.delete_points_blocking(collection_name, None, &selector, None)
+        })
+        .await
+        .with_context(|| format!("Failed to delete points of collection {}", collection_name))?;
+        Ok(())
+    }
+
+    /// Creates an alias for a collection, searchable and writable under the alias name.
+    pub async fn create_alias(&self, collection_name: &str, alias_name: &str) -> Result<()> {
+        self.call("create alias", || self.client.create_alias(collection_name, alias_name)).await?;
+        Ok(())
+    }
+
+    /// Deletes an alias, leaving its collection untouched.
+    pub async fn delete_alias(&self, alias_name: &str) -> Result<()> {
+        self.call("delete alias", || self.client.delete_alias(alias_name)).await?;
+        self.dimensions.lock().unwrap().remove(alias_name);
+        Ok(())
+    }
+
+    /// Returns the collection an alias points to, or `None` if the alias does not exist.
+    pub async fn alias_target(&self, alias_name: &str) -> Result<Option<String>> {
+        let response = self.call("list aliases", || self.client.list_aliases()).await?;
+        Ok(response
+            .aliases
+            .into_iter()
+            .find(|alias| alias.alias_name == alias_name)
+            .map(|alias| alias.collection_name))
+    }
+
+    /// Points an alias to a collection, creating the alias if needed, and returns the collection it
+    /// pointed to before. The switch is atomic: searches through the alias see either the previous
+    /// collection or the new one, which allows rebuilding a collection without downtime.
+    ///
+    /// Qdrant does not allow an alias named like a collection, so a collection with the alias name,
+    /// e.g. one ingested before the first reindex, is deleted before the alias is created. Searches
+    /// fail between the two, and `None` is returned since the collection no longer exists.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use orca_core::qdrant::Qdrant;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let client = Qdrant::new("http://localhost:6334").unwrap();
+    /// client.create_collection("papers_v2", 384).await?;
+    /// // ... ingest into papers_v2 ...
+    /// if let Some(previous) = client.switch_alias("papers", "papers_v2").await? {
+    ///     client.delete_collection(&previous).await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn switch_alias(&self, alias_name: &str, collection_name: &str) -> Result<Option<String>> {
+        let previous = self.alias_target(alias_name).await?;
+        if previous.is_none() && self.call("check collection", || self.client.has_collection(alias_name)).await? {
+            log::warn!("Deleting collection {} to replace it with an alias", alias_name);
+            self.delete_collection(alias_name).await?;
+        }
+        let delete = previous.as_ref().map(|_| AliasOperations {
+            action: Some(Action::DeleteAlias(DeleteAlias {
+                alias_name: alias_name.to_string(),
+            })),
+        });
+        let create = AliasOperations {
+            action: Some(Action::CreateAlias(CreateAlias {
+                collection_name: collection_name.to_string(),
+                alias_name: alias_name.to_string(),
+            })),
+        };
+        let change = ChangeAliases {
+            actions: delete.into_iter().chain([create]).collect(),
+            timeout: None,
+        };
+        self.call("switch alias", || self.client.update_aliases(change.clone()))
+            .await
+            .with_context(|| format!("Failed to point alias {} to collection {}", alias_name, collection_name))?;
+        self.dimensions.lock().unwrap().remove(alias_name);
+        Ok(previous)
+    }
+}
+
+#[async_trait::async_trait]
+impl VectorStore for Qdrant {
+    async fn ensure_collection(&self, collection: &str, dimensions: usize) -> Result<()> {
+        Qdrant::ensure_collection(self, collection, dimensions as u64).await
+    }
+
+    async fn insert(&self, collection: &str, points: Vec<Point>) -> Result<()> {
+        let (ids, vectors, payloads) = points.into_iter().fold(
+            (Vec::new(), Vec::new(), Vec::new()),
+            |(mut ids, mut vectors, mut payloads), point| {
+                ids.push(point.id);
+                vectors.push(point.vector);
+                payloads.push(point.payload);
+                (ids, vectors, payloads)
+            },
+        );
+        self.upsert_many(collection, ids, vectors, payloads).await
+    }
+
+    /// Searches the collection, or the fallback store if Qdrant is unreachable and one is set.
+    async fn search(&self, collection: &str, query: SearchQuery) -> Result<Vec<SearchHit>> {
+        let fallback_query = self.fallback.as_ref().map(|_| query.clone());
+        let conditions = match query.filters.is_empty() {
+            true => None,
+            false => Some(query.filters.iter().map(Condition::try_from).collect::<Result<Vec<_>>>()?),
+        };
+        let points = Qdrant::search(
+            self,
+            collection,
+            query.vector,
+            query.limit,
+            conditions,
+            query.score_threshold,
+        )
+        .await;
+        match (points, &self.fallback, fallback_query) {
+            (Ok(points), _, _) => Ok(points.into_iter().map(SearchHit::from).collect()),
+            (Err(e), Some(fallback), Some(query)) if is_outage(&e) => {
+                log::warn!("{}, searching collection {} in the fallback store", e, collection);
+                fallback.search(collection, query).await
+            }
+            (Err(e), _, _) => Err(e),
+        }
+    }
+
+    async fn ensure_collection_with_precision(
+        &self,
+        collection: &str,
+        dimensions: usize,
+        precision: Precision,
+    ) -> Result<()> {
+        match self.collection_dimensions(collection).await? {
+            Some(size) => check_dimensions(collection, size, dimensions),
+            None => self.create_collection_with_precision(collection, dimensions as u64, precision).await,
+        }
+    }
+
+    async fn create_field_index(&self, collection: &str, field: &str, index: FieldIndex) -> Result<()> {
+        Qdrant::create_field_index(self, collection, field, index).await
+    }
+
+    async fn delete_collection(&self, collection: &str) -> Result<()> {
+        Qdrant::delete_collection(self, collection).await
+    }
+
+    async fn delete_by_filter(&self, collection: &str, filters: Vec<store::Filter>) -> Result<()> {
+        let conditions = filters.iter().map(Condition::try_from).collect::<Result<Vec<_>>>()?;
+        Qdrant::delete_by_filter(self, collection, conditions).await
+    }
+
+    async fn switch_alias(&self, alias: &str, collection: &str) -> Result<Option<String>> {
+        Qdrant::switch_alias(self, alias, collection).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::distributions::Alphanumeric;
+    use rand::Rng;
+    use serde_json::json;
+
+    const URL: &str = "http://localhost:6334";
+
+    /// Server of the tests needing one: a disposable container with the `testcontainers` feature, or the
+    /// server at `URL` otherwise. The returned guard must live until the end of the test.
+    #[cfg(feature = "testcontainers")]
+    async fn server() -> (Qdrant, testing::QdrantTestInstance) {
+        let instance = testing::QdrantTestInstance::start().await.unwrap();
+        (instance.qdrant().unwrap(), instance)
+    }
+
+    #[cfg(not(feature = "testcontainers"))]
+    async fn server() -> (Qdrant, ()) {
+        (Qdrant::new(URL).unwrap(), ())
+    }
+
+    fn generate_unique_collection_name() -> String {
+        let rng = rand::thread_rng();
+        let suffix: String = rng.sample_iter(&Alphanumeric).take(8).map(char::from).collect();
+        format!("test_collection_{}", suffix)
+    }
+
+    async fn teardown(qdrant: &Qdrant, collection_name: &str) {
+        let _ = qdrant.delete_collection(collection_name).await;
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let retry = RetryPolicy {
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(2),
+        };
+        assert_eq!(retry.backoff(5), Duration::from_millis(2));
+        let qdrant = Qdrant::new(URL).unwrap().with_retry(retry);
+
+        let attempts = std::sync::atomic::AtomicU32::new(0);
+        let unreachable = || async {
+            attempts.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Err::<(), _>(anyhow::anyhow!("status: Unavailable, message: \"tcp connect error\""))
+        };
+        let error = qdrant.call("search", unreachable).await.unwrap_err();
+        let Some(OrcaError::VectorStoreUnavailable(unavailable)) = error.downcast_ref::<OrcaError>() else {
+            panic!("Unexpected error: {}", error);
+        };
+        assert_eq!((unavailable.operation.as_str(), unavailable.attempts), ("search", 3));
+        assert_eq!(attempts.load(std::sync::atomic::Ordering::SeqCst), 3);
+
+        let missing = || async { Err::<(), _>(anyhow::anyhow!("status: NotFound, message: \"Collection not found\"")) };
+        let error = qdrant.call("search", missing).await.unwrap_err();
+        assert!(!is_outage(&error));
+        assert!(matches!(
+            error.downcast_ref::<OrcaError>(),
+            Some(OrcaError::VectorStore { store, .. }) if store == "Qdrant"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_create_collection() {
+        let (qdrant, _server) = server().await;
+        let unique_collection_name = generate_unique_collection_name();
+
+        let result = qdrant.create_collection(&unique_collection_name, 128).await;
+        assert!(result.is_ok());
+
+        teardown(&qdrant, &unique_collection_name).await;
+    }
+
+    #[tokio::test]
+    async fn test_ensure_collection() {
+        let (qdrant, _server) = server().await;
+        let unique_collection_name = generate_unique_collection_name();
+
+        qdrant.ensure_collection(&unique_collection_name, 3).await.unwrap();
+        assert_eq!(
+            qdrant.collection_dimensions(&unique_collection_name).await.unwrap(),
+            Some(3)
+        );
+        assert!(qdrant.ensure_collection(&unique_collection_name, 3).await.is_ok());
+        assert!(qdrant.ensure_collection(&unique_collection_name, 384).await.is_err());
+        assert!(qdrant.insert(&unique_collection_name, vec![0.1; 384], "some_payload").await.is_err());
+
+        // The cached size of a deleted collection is forgotten.
+        qdrant.delete_collection(&unique_collection_name).await.unwrap();
+        qdrant.create_collection(&unique_collection_name, 384).await.unwrap();
+        assert!(qdrant.insert(&unique_collection_name, vec![0.1; 384], "some_payload").await.is_ok());
+        assert!(qdrant.insert(&unique_collection_name, vec![0.1; 3], "some_payload").await.is_err());
+
+        teardown(&qdrant, &unique_collection_name).await;
+    }
+
+    #[tokio::test]
+    async fn test_insert_through_alias() {
+        let (qdrant, _server) = server().await;
+        let unique_collection_name = generate_unique_collection_name();
+        let alias_name = format!("{}_alias", unique_collection_name);
+
+        qdrant.create_collection(&unique_collection_name, 3).await.unwrap();
+        qdrant.create_alias(&unique_collection_name, &alias_name).await.unwrap();
+        assert_eq!(qdrant.collection_dimensions(&alias_name).await.unwrap(), Some(3));
+        qdrant.ensure_collection(&alias_name, 3).await.unwrap();
+        assert!(qdrant.ensure_collection(&alias_name, 384).await.is_err());
+
+        let vector = vec![0.1, 0.2, 0.3];
+        qdrant.insert(&alias_name, vector.clone(), json!({ "name": "John" })).await.unwrap();
+        qdrant
+            .upsert_many(
+                &alias_name,
+                vec![1],
+                vec![vector.clone()],
+                vec![json!({ "name": "Jane" })],
+            )
+            .await
+            .unwrap();
+        let points = qdrant.search(&unique_collection_name, vector, 10, None, None).await.unwrap();
+        assert_eq!(points.len(), 2);
+
+        let _ = qdrant.delete_alias(&alias_name).await;
+        teardown(&qdrant, &unique_collection_name).await;
+    }
+
+    #[tokio::test]
+    async fn test_switch_alias_replaces_collection() {
+        let (qdrant, _server) = server().await;
+        let alias_name = generate_unique_collection_name();
+        let unique_collection_name = format!("{}_v2", alias_name);
+
+        qdrant.create_collection(&alias_name, 3).await.unwrap();
+        qdrant.create_collection(&unique_collection_name, 3).await.unwrap();
+        assert_eq!(
+            qdrant.switch_alias(&alias_name, &unique_collection_name).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            qdrant.alias_target(&alias_name).await.unwrap().as_deref(),
+            Some(unique_collection_name.as_str())
+        );
+
+        let _ = qdrant.delete_alias(&alias_name).await;
+        teardown(&qdrant, &unique_collection_name).await;
+    }
+
+    #[test]
+    fn test_field_index() {
+        assert_eq!(FieldIndex::Keyword.to_qdrant(), (FieldType::Keyword, None));
+        let (field_type, params) = FieldIndex::Text.to_qdrant();
+        assert_eq!(field_type, FieldType::Text);
+        match params.and_then(|params| params.index_params) {
+            Some(IndexParams::TextIndexParams(params)) => {
+                assert_eq!(params.tokenizer, i32::from(TokenizerType::Word));
+                assert_eq!(params.lowercase, Some(true));
+            }
+            _ => panic!("Text index without text parameters"),
+        }
+    }
+
+    #[test]
+    fn test_condition_from_filter() {
+        let condition = Condition::try_from(&store::Filter::Matches("score".to_string(), json!(0.5))).unwrap();
+        assert!(
+            matches!(condition, Condition::Range(_, Range { gte: Some(v), lte: Some(w), .. }) if v == 0.5 && w == 0.5)
+        );
+        let filter = store::Filter::Not(Box::new(store::Filter::MatchesAny(
+            "lang".to_string(),
+            vec![json!("eng")],
+        )));
+        assert!(matches!(Condition::try_from(&filter).unwrap(), Condition::Not(_)));
+        assert!(Condition::try_from(&store::Filter::Matches("tags".to_string(), json!(["a"]))).is_err());
+        assert!(Condition::try_from(&store::Filter::MatchesAny("year".to_string(), vec![json!(2020)])).is_err());
+    }
+
+    #[test]
+    fn test_quantization_config() {
+        assert!(quantization_config(Precision::F32).is_none());
+        for precision in [Precision::F16, Precision::Int8] {
+            match quantization_config(precision).and_then(|config| config.quantization) {
+                Some(Quantization::Scalar(scalar)) => {
+                    assert_eq!(scalar.r#type, i32::from(QuantizationType::Int8));
+                    assert_eq!(scalar.always_ram, Some(true));
+                }
+                other => panic!("expected scalar quantization, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_check_dimensions() {
+        assert!(check_dimensions("docs", 384, 384).is_ok());
+        let error = check_dimensions("docs", 384, 1536).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Qdrant request failed: Collection docs stores vectors of 384 dimensions, but the embeddings have 1536 \
+             dimensions"
+        );
+        assert!(matches!(
+            error.downcast_ref::<OrcaError>(),
+            Some(OrcaError::VectorStore { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_insert_point() {
+        let (qdrant, _server) = server().await;
+        let unique_collection_name = generate_unique_collection_name();
+
+        qdrant.create_collection(&unique_collection_name, 3).await.unwrap();
+
+        let vector = vec![0.1, 0.2, 0.3];
+        let payload = "some_payload";
+
+        let result = qdrant.insert(&unique_collection_name, vector, payload).await;
+        assert!(result.is_ok());
+
+        teardown(&qdrant, &unique_collection_name).await;
+    }
+
+    #[tokio::test]
+    async fn test_search_points() {
+        let (qdrant, _server) = server().await;
+        let unique_collection_name = generate_unique_collection_name();
+
+        qdrant.create_collection(&unique_collection_name, 3).await.unwrap();
+        let vector = vec![0.1, 0.2, 0.3];
+        let payload = json!(
+            {
+                "name": "John",
+                "age": 30
+            }
+        );
+        qdrant.insert(&unique_collection_name, vector.clone(), payload).await.unwrap();
+
+        let conditions = vec![Condition::Matches("name".to_string(), "John".into())];
+
+        let results = qdrant.search(&unique_collection_name, vector, 10, Some(conditions), None).await;
+        assert!(results.is_ok());
+
+        let points = results.unwrap();
+        assert_eq!(points.len(), 1);
+        assert_eq!(points[0].payload.as_ref().unwrap()["name"], "John".into());
+
+        teardown(&qdrant, &unique_collection_name).await;
+    }
+
+    #[test]
+    fn test_time_decay_rerank() {
+        let now = 1_700_000_000.0;
+        let day = 24.0 * 60.0 * 60.0;
+        let point = |id: u64, score: f32, timestamp: Option<f64>| FoundPoint {
+            id,
+            score,
+            payload: Some(match timestamp {
+                Some(timestamp) => {
+                    HashMap::from([("attributes".to_string(), Value::from(json!({ "timestamp": timestamp })))])
+                }
+                None => HashMap::new(),
+            }),
+        };
+        let points = vec![
+            point(1, 0.9, Some(now - 30.0 * day)),
+            point(2, 0.8, Some(now - day)),
+            point(3, 0.95, None),
+        ];
+
+        let decay = TimeDecay::new("attributes.timestamp", Duration::from_secs(7 * 24 * 60 * 60));
+        assert_eq!(decay.recency(now - 7.0 * day, now), 0.5);
+
+        let ids = decay.rerank(points.clone(), now).iter().map(|p| p.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![2, 1, 3]);
+
+        let ids = decay.with_weight(0.0).rerank(points, now).iter().map(|p| p.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![3, 1, 2]);
+    }
+
+    #[test]
+    fn test_within_days() {
+        match Condition::within_days("published_at", 1) {
+            Condition::Range(field, range) => {
+                assert_eq!(field, "published_at");
+                assert!((now() - 24.0 * 60.0 * 60.0 - range.gte.unwrap()).abs() < 60.0);
+            }
+            _ => panic!("expected a range condition"),
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Unsupported double value")]
+    fn test_unsupported_match_value() {
+        let _ = convert_to_match_value(Value {
+            kind: Some(Kind::DoubleValue(1.23)),
+        });
+    }
+}
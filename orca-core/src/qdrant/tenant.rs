@@ -0,0 +1,219 @@
+//! Multi-tenancy on top of the Qdrant wrapper.
+//!
+//! A `TenantScopedStore` confines every insert and search to one tenant, so that documents of one
+//! customer are never returned to another. Tenants either share collections, with the tenant id stored
+//! in a payload field that is set on every insert and matched on every search, or get a collection of
+//! their own, named after the shared collection and the tenant id.
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+
+use super::{Condition, FoundPoint, Qdrant};
+
+/// Payload field holding the tenant id when tenants share collections.
+pub const TENANT_FIELD: &str = "tenant_id";
+
+/// How documents of different tenants are kept apart.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tenancy {
+    /// Tenants share collections, and points are tagged with the tenant id in the given payload field.
+    Payload(String),
+
+    /// Each tenant has its own collections.
+    CollectionPerTenant,
+}
+
+/// Name of the collection of a tenant, e.g. `documents__acme`.
+///
+/// # Example
+/// ```
+/// use orca_core::qdrant::tenant::tenant_collection;
+///
+/// assert_eq!(tenant_collection("documents", "acme").unwrap(), "documents__acme");
+/// assert!(tenant_collection("documents", "../acme").is_err());
+/// ```
+pub fn tenant_collection(collection_name: &str, tenant_id: &str) -> Result<String> {
+    check_tenant_id(tenant_id)?;
+    Ok(format!("{}__{}", collection_name, tenant_id))
+}
+
+/// Error out on tenant ids that are empty or could escape their collection name.
+fn check_tenant_id(tenant_id: &str) -> Result<()> {
+    let valid = tenant_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if tenant_id.is_empty() || !valid {
+        return Err(anyhow!(
+            "Invalid tenant id {:?}: only ASCII letters, digits, '-' and '_' are allowed",
+            tenant_id
+        ));
+    }
+    Ok(())
+}
+
+/// Qdrant store whose inserts and searches are confined to a single tenant.
+pub struct TenantScopedStore<'a> {
+    qdrant: &'a Qdrant,
+    tenant_id: String,
+    tenancy: Tenancy,
+}
+
+impl<'a> TenantScopedStore<'a> {
+    /// Scope a store to a tenant, sharing collections with other tenants through the `tenant_id`
+    /// payload field.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use orca_core::qdrant::Qdrant;
+    /// # use orca_core::qdrant::tenant::TenantScopedStore;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let qdrant = Qdrant::new("http://localhost:6334").unwrap();
+    /// let store = TenantScopedStore::new(&qdrant, "acme")?;
+    /// store.insert("documents", vec![0.1, 0.2, 0.3], "Acme's pricing").await?;
+    /// let results = store.search("documents", vec![0.1, 0.2, 0.3], 10, None, None).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new(qdrant: &'a Qdrant, tenant_id: &str) -> Result<Self> {
+        check_tenant_id(tenant_id)?;
+        Ok(TenantScopedStore {
+            qdrant,
+            tenant_id: tenant_id.to_string(),
+            tenancy: Tenancy::Payload(TENANT_FIELD.to_string()),
+        })
+    }
+
+    /// Store the tenant id in the given payload field instead of `tenant_id`.
+    pub fn with_tenant_field(mut self, field: &str) -> Self {
+        self.tenancy = Tenancy::Payload(field.to_string());
+        self
+    }
+
+    /// Give the tenant collections of its own instead of sharing them.
+    pub fn with_collection_per_tenant(mut self) -> Self {
+        self.tenancy = Tenancy::CollectionPerTenant;
+        self
+    }
+
+    /// The id of the tenant.
+    pub fn tenant_id(&self) -> &str {
+        &self.tenant_id
+    }
+
+    /// Name of the underlying collection holding the tenant's points.
+    pub fn collection(&self, collection_name: &str) -> String {
+        match self.tenancy {
+            Tenancy::Payload(_) => collection_name.to_string(),
+            Tenancy::CollectionPerTenant => format!("{}__{}", collection_name, self.tenant_id),
+        }
+    }
+
+    /// Creates the collection with the given vector size if it does not exist.
+    pub async fn ensure_collection(&self, collection_name: &str, vector_size: u64) -> Result<()> {
+        self.qdrant.ensure_collection(&self.collection(collection_name), vector_size).await
+    }
+
+    /// Inserts a point tagged with the tenant.
+    pub async fn insert<T: Serialize>(&self, collection_name: &str, vector: Vec<f32>, payload: T) -> Result<()> {
+        self.insert_many(collection_name, vec![vector], vec![payload]).await
+    }
+
+    /// Inserts points tagged with the tenant. Points get random ids, so that tenants sharing a
+    /// collection do not replace each other's points.
+    pub async fn insert_many<T: Serialize>(
+        &self,
+        collection_name: &str,
+        vectors: Vec<Vec<f32>>,
+        payloads: Vec<T>,
+    ) -> Result<()> {
+        let payloads = payloads.into_iter().map(|payload| self.scope_payload(payload)).collect::<Result<Vec<_>>>()?;
+        let ids = vectors.iter().map(|_| uuid::Uuid::new_v4().as_u64_pair().0).collect();
+        self.qdrant.upsert_many(&self.collection(collection_name), ids, vectors, payloads).await
+    }
+
+    /// Searches the tenant's points. See `Qdrant::search`.
+    pub async fn search(
+        &self,
+        collection_name: &str,
+        vector: Vec<f32>,
+        limit: usize,
+        conditions: Option<Vec<Condition>>,
+        score_threshold: Option<f32>,
+    ) -> Result<Vec<FoundPoint>> {
+        let conditions = self.scope_conditions(conditions);
+        self.qdrant
+            .search(
+                &self.collection(collection_name),
+                vector,
+                limit,
+                conditions,
+                score_threshold,
+            )
+            .await
+    }
+
+    /// Tag a payload with the tenant, overwriting any tenant id it already holds.
+    fn scope_payload<T: Serialize>(&self, payload: T) -> Result<serde_json::Value> {
+        let mut payload = match serde_json::to_value(payload)? {
+            serde_json::Value::Object(map) => map,
+            // Same key as `ToPayload` uses for payloads that are not objects.
+            value => serde_json::Map::from_iter([("value".to_string(), value)]),
+        };
+        if let Tenancy::Payload(field) = &self.tenancy {
+            payload.insert(field.clone(), self.tenant_id.clone().into());
+        }
+        Ok(serde_json::Value::Object(payload))
+    }
+
+    /// Add a condition matching the tenant to the search conditions.
+    fn scope_conditions(&self, conditions: Option<Vec<Condition>>) -> Option<Vec<Condition>> {
+        match &self.tenancy {
+            Tenancy::Payload(field) => {
+                let mut conditions = conditions.unwrap_or_default();
+                conditions.push(Condition::Matches(field.clone(), self.tenant_id.as_str().into()));
+                Some(conditions)
+            }
+            Tenancy::CollectionPerTenant => conditions,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_payload_tenancy() {
+        let qdrant = Qdrant::new("http://localhost:6334").unwrap();
+        let store = TenantScopedStore::new(&qdrant, "acme").unwrap();
+        assert_eq!(store.collection("documents"), "documents");
+
+        let payload = store.scope_payload(json!({"text": "Pricing", "tenant_id": "globex"})).unwrap();
+        assert_eq!(payload, json!({"text": "Pricing", "tenant_id": "acme"}));
+        assert_eq!(
+            store.scope_payload("Pricing").unwrap(),
+            json!({"value": "Pricing", "tenant_id": "acme"})
+        );
+
+        let conditions = store.scope_conditions(Some(vec![Condition::since("published_at", 0.0)])).unwrap();
+        assert_eq!(conditions.len(), 2);
+        assert!(matches!(&conditions[1], Condition::Matches(field, _) if field == TENANT_FIELD));
+
+        assert!(TenantScopedStore::new(&qdrant, "").is_err());
+    }
+
+    #[test]
+    fn test_collection_per_tenant() {
+        let qdrant = Qdrant::new("http://localhost:6334").unwrap();
+        let store = TenantScopedStore::new(&qdrant, "acme").unwrap().with_collection_per_tenant();
+        assert_eq!(
+            store.collection("documents"),
+            tenant_collection("documents", "acme").unwrap()
+        );
+        assert_eq!(
+            store.scope_payload(json!({"text": "Pricing"})).unwrap(),
+            json!({"text": "Pricing"})
+        );
+        assert!(store.scope_conditions(None).is_none());
+    }
+}
@@ -0,0 +1,106 @@
+//! Disposable Qdrant servers for integration tests.
+//!
+//! `QdrantTestInstance::start` runs Qdrant in a Docker container with `testcontainers`, listening on a free
+//! port, waits until it serves requests, and removes the container when the instance is dropped, so that
+//! each test gets an empty server and nothing is left behind. Setting `QDRANT_URL` points the instances at
+//! an existing server instead, e.g. a service container in CI, in which case nothing is started or removed.
+//!
+//! # Example
+//! ```no_run
+//! use orca_core::qdrant::testing::QdrantTestInstance;
+//!
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! let instance = QdrantTestInstance::start().await?;
+//! let qdrant = instance.qdrant()?;
+//! qdrant.create_collection("documents", 384).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use testcontainers::core::IntoContainerPort;
+use testcontainers::runners::AsyncRunner;
+use testcontainers::{ContainerAsync, GenericImage};
+
+use super::{Qdrant, RetryPolicy};
+
+/// Image of the containers started by `QdrantTestInstance::start`.
+pub const QDRANT_IMAGE: &str = "qdrant/qdrant";
+
+/// Tag of the image of the containers started by `QdrantTestInstance::start`.
+pub const QDRANT_TAG: &str = "v1.7.0";
+
+/// Environment variable holding the URL of an existing Qdrant server to use instead of a container.
+pub const QDRANT_URL_VAR: &str = "QDRANT_URL";
+
+/// gRPC port of Qdrant in its container.
+const GRPC_PORT: u16 = 6334;
+
+/// Qdrant server started for a test, removed when dropped.
+#[derive(Debug)]
+pub struct QdrantTestInstance {
+    /// URL of the gRPC API of the server.
+    url: String,
+
+    /// The container of the server, or `None` if the server was not started by the instance. Dropping it
+    /// removes the container.
+    container: Option<ContainerAsync<GenericImage>>,
+}
+
+impl QdrantTestInstance {
+    /// Start a container of `QDRANT_IMAGE`, or use the server at `QDRANT_URL` if it is set.
+    pub async fn start() -> Result<Self> {
+        match std::env::var(QDRANT_URL_VAR) {
+            Ok(url) => QdrantTestInstance { url, container: None }.wait().await,
+            Err(_) => Self::start_image(QDRANT_IMAGE, QDRANT_TAG).await,
+        }
+    }
+
+    /// Start a container of the given Qdrant image, publishing its gRPC port on a free port of the host.
+    pub async fn start_image(image: &str, tag: &str) -> Result<Self> {
+        let container = GenericImage::new(image, tag)
+            .with_exposed_port(GRPC_PORT.tcp())
+            .start()
+            .await
+            .with_context(|| format!("Failed to start {}:{}, is Docker running?", image, tag))?;
+        let host = container.get_host().await?;
+        let port = container.get_host_port_ipv4(GRPC_PORT).await?;
+        QdrantTestInstance {
+            url: format!("http://{}:{}", host, port),
+            container: Some(container),
+        }
+        .wait()
+        .await
+    }
+
+    /// Wait until the server serves requests, for about a minute.
+    async fn wait(self) -> Result<Self> {
+        let retry = RetryPolicy {
+            max_retries: 17,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+        };
+        Qdrant::connect(&self.url, retry)
+            .await
+            .with_context(|| format!("Qdrant at {} did not come up", self.url))?;
+        Ok(self)
+    }
+
+    /// URL of the gRPC API of the server.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Identifier of the container of the server, or `None` if the server was not started by the instance.
+    pub fn container_id(&self) -> Option<&str> {
+        self.container.as_ref().map(|container| container.id())
+    }
+
+    /// Create a client of the server.
+    pub fn qdrant(&self) -> Result<Qdrant> {
+        Qdrant::new(&self.url)
+    }
+}
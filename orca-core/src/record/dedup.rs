@@ -0,0 +1,340 @@
+//! Record deduplication.
+//!
+//! `exact` removes records whose content is identical once whitespace and case are normalized.
+//! `near` removes records that are near-duplicates of an earlier record, such as a page crawled
+//! twice with a different footer, by estimating the Jaccard similarity of their word shingles with
+//! MinHash. Both keep the first occurrence and preserve the order of the records. `overlapping` merges
+//! retrieved chunks of the same record that overlap or follow each other into a single passage.
+
+use super::{Content, Record, Transform, END_ATTRIBUTE, START_ATTRIBUTE};
+use crate::docstore::PARENT_ID_ATTRIBUTE;
+use crate::hash::fnv1a_words;
+use crate::pipeline::assembler::SOURCE_ATTRIBUTE;
+
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+
+/// Number of words in each shingle.
+const SHINGLE_SIZE: usize = 3;
+
+/// Number of hash functions in a MinHash signature.
+const NUM_HASHES: usize = 128;
+
+/// Remove records with the same normalized content.
+///
+/// # Example
+/// ```
+/// use orca_core::record::{dedup, Content, Record};
+///
+/// let records = vec![
+///     Record::new(Content::String("Hello  World".to_string())),
+///     Record::new(Content::String("hello world".to_string())),
+/// ];
+/// assert_eq!(dedup::exact(records).len(), 1);
+/// ```
+pub fn exact(records: Vec<Record>) -> Vec<Record> {
+    let mut seen = HashSet::new();
+    records.into_iter().filter(|record| seen.insert(content_hash(record))).collect()
+}
+
+/// Remove records whose estimated Jaccard similarity with an earlier record is at least
+/// `threshold`, between 0 and 1.
+///
+/// # Example
+/// ```
+/// use orca_core::record::{dedup, Content, Record};
+///
+/// let page = "Orca is an LLM orchestration framework written in Rust. It lets you build pipelines.";
+/// let records = vec![
+///     Record::new(Content::String(page.to_string())),
+///     Record::new(Content::String(format!("{} Copyright 2023.", page))),
+/// ];
+/// assert_eq!(dedup::near(records, 0.7).len(), 1);
+/// ```
+pub fn near(records: Vec<Record>, threshold: f64) -> Vec<Record> {
+    let mut kept: Vec<(Record, Signature)> = Vec::with_capacity(records.len());
+    for record in records {
+        let signature = Signature::new(&record.content.to_string());
+        if !kept.iter().any(|(_, other)| signature.similarity(other) >= threshold) {
+            kept.push((record, signature));
+        }
+    }
+    kept.into_iter().map(|(record, _)| record).collect()
+}
+
+/// Merge chunks of the same record whose texts overlap, or are at most `max_gap` characters apart, into a
+/// single passage, so that the text they share is only kept once. Chunks are of the same record when they
+/// have the same `parent_id` attribute, or else the same header, or else the same `source` attribute, and
+/// are located in its text by their `char_start` and `char_end` attributes, as set by `Record::split`.
+/// Texts further apart than the gap, e.g. skipping trimmed whitespace, are joined with a space.
+///
+/// A passage takes the place and attributes of its most relevant chunk, i.e. the first one, with the
+/// offsets of the whole passage. Other records are kept as they are, in order.
+///
+/// # Example
+/// ```
+/// use orca_core::record::{dedup, Content, Record};
+///
+/// let chunk = |text: &str, start: usize| {
+///     Record::new(Content::String(text.to_string()))
+///         .with_header("orcas.txt".to_string())
+///         .with_attribute("char_start", start)
+///         .with_attribute("char_end", start + text.chars().count())
+/// };
+/// let records = vec![chunk("live in pods of up to 40.", 6), chunk("Orcas live in pods", 0)];
+/// let merged = dedup::overlapping(records, 1);
+/// assert_eq!(merged.len(), 1);
+/// assert_eq!(merged[0].content.to_string(), "Orcas live in pods of up to 40.");
+/// ```
+pub fn overlapping(records: Vec<Record>, max_gap: usize) -> Vec<Record> {
+    // Chunks of each record, by position in its text.
+    let mut groups: HashMap<String, Vec<(usize, usize, usize)>> = HashMap::new();
+    for (index, record) in records.iter().enumerate() {
+        if let Some((key, start, end)) = chunk_location(record) {
+            groups.entry(key).or_default().push((start, end, index));
+        }
+    }
+
+    // Passages by the index of their first chunk, and the indexes of the chunks merged into them.
+    let mut passages: HashMap<usize, Record> = HashMap::new();
+    let mut merged = HashSet::new();
+    for mut chunks in groups.into_values() {
+        chunks.sort_unstable();
+        let (mut run, mut run_end) = (Vec::new(), 0);
+        for chunk in chunks {
+            if !run.is_empty() && chunk.0 > run_end + max_gap {
+                merge_run(&records, &run, &mut passages, &mut merged);
+                run.clear();
+            }
+            run_end = if run.is_empty() { chunk.1 } else { run_end.max(chunk.1) };
+            run.push(chunk);
+        }
+        merge_run(&records, &run, &mut passages, &mut merged);
+    }
+
+    records
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, record)| match passages.remove(&index) {
+            Some(passage) => Some(passage),
+            None if merged.contains(&index) => None,
+            None => Some(record),
+        })
+        .collect()
+}
+
+/// Record, start and end of a chunk, if its location is known.
+fn chunk_location(record: &Record) -> Option<(String, usize, usize)> {
+    let attribute = |name: &str| record.attributes.get(name);
+    let key = match (
+        attribute(PARENT_ID_ATTRIBUTE),
+        &record.header,
+        attribute(SOURCE_ATTRIBUTE),
+    ) {
+        (Some(parent), _, _) => format!("parent:{}", parent),
+        (None, Some(header), _) => format!("header:{}", header),
+        (None, None, Some(source)) => format!("source:{}", source),
+        (None, None, None) => return None,
+    };
+    let start = attribute(START_ATTRIBUTE)?.as_u64()? as usize;
+    let end = attribute(END_ATTRIBUTE)?.as_u64()? as usize;
+    match &record.content {
+        Content::String(text) if text.chars().count() == end.saturating_sub(start) => Some((key, start, end)),
+        _ => None,
+    }
+}
+
+/// Merge a run of chunks sorted by position, each overlapping or close to the chunks before it, into a
+/// passage placed at its most relevant chunk.
+fn merge_run(
+    records: &[Record],
+    run: &[(usize, usize, usize)],
+    passages: &mut HashMap<usize, Record>,
+    merged: &mut HashSet<usize>,
+) {
+    if run.len() < 2 {
+        return;
+    }
+    let (start, mut end) = (run[0].0, run[0].1);
+    let mut text = records[run[0].2].content.to_string();
+    for &(chunk_start, chunk_end, index) in &run[1..] {
+        if chunk_end <= end {
+            continue;
+        }
+        let chunk = records[index].content.to_string();
+        match chunk_start > end {
+            true => {
+                text.push(' ');
+                text.push_str(&chunk);
+            }
+            false => text.extend(chunk.chars().skip(end - chunk_start)),
+        }
+        end = chunk_end;
+    }
+    let first = run.iter().map(|&(_, _, index)| index).min().unwrap_or_default();
+    let passage = records[first]
+        .clone()
+        .with_content(Content::String(text))
+        .with_attribute(START_ATTRIBUTE, start)
+        .with_attribute(END_ATTRIBUTE, end);
+    passages.insert(first, passage);
+    merged.extend(run.iter().map(|&(_, _, index)| index));
+}
+
+/// Hash of the record content, ignoring case and whitespace differences. The hash is stable across runs and
+/// Rust versions, so it can be stored.
+pub fn content_hash(record: &Record) -> u64 {
+    fnv1a_words(record.content.to_string().split_whitespace().map(str::to_lowercase))
+}
+
+/// MinHash signature of a text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature(Vec<u64>);
+
+impl Signature {
+    /// Compute the signature of the word shingles of a text.
+    pub fn new(text: &str) -> Self {
+        let words = text.split_whitespace().map(|word| word.to_lowercase()).collect::<Vec<_>>();
+        let shingles = if words.len() <= SHINGLE_SIZE {
+            vec![fnv1a_words(&words)]
+        } else {
+            words.windows(SHINGLE_SIZE).map(fnv1a_words).collect::<Vec<_>>()
+        };
+
+        let mut seed = 0;
+        let signature = (0..NUM_HASHES)
+            .map(|_| {
+                let a = splitmix64(&mut seed) | 1;
+                let b = splitmix64(&mut seed);
+                shingles.iter().map(|shingle| a.wrapping_mul(*shingle).wrapping_add(b)).min().unwrap_or(u64::MAX)
+            })
+            .collect();
+        Signature(signature)
+    }
+
+    /// Estimated Jaccard similarity of the shingles of two texts, between 0 and 1.
+    pub fn similarity(&self, other: &Signature) -> f64 {
+        let equal = self.0.iter().zip(&other.0).filter(|(a, b)| a == b).count();
+        equal as f64 / NUM_HASHES as f64
+    }
+}
+
+/// Deterministic pseudo-random numbers, so that signatures are comparable across runs.
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E3779B97F4A7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+/// Ingestion transform that removes exact duplicates.
+pub struct ExactDedup;
+
+#[async_trait::async_trait]
+impl Transform for ExactDedup {
+    async fn transform(&self, records: Vec<Record>) -> Result<Vec<Record>> {
+        Ok(exact(records))
+    }
+}
+
+/// Ingestion transform that removes near-duplicates.
+pub struct NearDedup {
+    /// Estimated Jaccard similarity above which a record is considered a duplicate.
+    pub threshold: f64,
+}
+
+impl NearDedup {
+    /// Create a new transform with the given similarity threshold.
+    pub fn new(threshold: f64) -> Self {
+        Self { threshold }
+    }
+}
+
+#[async_trait::async_trait]
+impl Transform for NearDedup {
+    async fn transform(&self, records: Vec<Record>) -> Result<Vec<Record>> {
+        Ok(near(records, self.threshold))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::record::Content;
+
+    fn record(text: &str) -> Record {
+        Record::new(Content::String(text.to_string()))
+    }
+
+    #[test]
+    fn test_overlapping() {
+        let chunk = |text: &str, start: usize, parent: &str| {
+            record(text)
+                .with_attribute(PARENT_ID_ATTRIBUTE, parent)
+                .with_attribute(START_ATTRIBUTE, start)
+                .with_attribute(END_ATTRIBUTE, start + text.chars().count())
+        };
+        let records = vec![
+            chunk("in pods. They hunt", 11, "orcas"),
+            record("Orcas are dolphins."),
+            chunk("Orcas live in pods.", 0, "orcas"),
+            chunk("seals.", 30, "orcas"),
+            chunk("Dolphins live in pods.", 0, "dolphins"),
+            chunk("the end.", 80, "orcas"),
+        ];
+        let merged = overlapping(records, 1);
+        let texts = merged.iter().map(|record| record.content.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            texts,
+            vec![
+                "Orcas live in pods. They hunt seals.",
+                "Orcas are dolphins.",
+                "Dolphins live in pods.",
+                "the end."
+            ]
+        );
+        assert_eq!(merged[0].attributes[START_ATTRIBUTE], 0);
+        assert_eq!(merged[0].attributes[END_ATTRIBUTE], 36);
+    }
+
+    #[test]
+    fn test_exact() {
+        let records = vec![record("a b c"), record("A  b\nc"), record("a b d")];
+        let records = exact(records);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].content.to_string(), "a b d");
+
+        // Hashes are stable, so they can be compared with stored ones.
+        assert_eq!(content_hash(&record("Hello  World")), 0x986b8f264475ec75);
+    }
+
+    #[test]
+    fn test_similarity() {
+        let text = "the quick brown fox jumps over the lazy dog near the river bank today";
+        let same = Signature::new(text);
+        assert_eq!(same.similarity(&Signature::new(text)), 1.0);
+
+        let edited = Signature::new(&text.replace("today", "yesterday"));
+        let similarity = same.similarity(&edited);
+        assert!(similarity > 0.6 && similarity < 1.0, "{}", similarity);
+
+        let other = Signature::new("rust is a systems programming language focused on safety and speed");
+        assert!(same.similarity(&other) < 0.1);
+    }
+
+    #[tokio::test]
+    async fn test_transforms() {
+        let text = "the quick brown fox jumps over the lazy dog near the river bank today";
+        let records = vec![
+            record(text),
+            record(text),
+            record(&format!("{} again", text)),
+            record("unrelated"),
+        ];
+        let records = ExactDedup.transform(records).await.unwrap();
+        assert_eq!(records.len(), 3);
+        let records = NearDedup::new(0.8).transform(records).await.unwrap();
+        assert_eq!(records.len(), 2);
+    }
+}
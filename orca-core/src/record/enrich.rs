@@ -0,0 +1,193 @@
+//! Entity enrichment at ingestion.
+//!
+//! An `EntityTagger` runs an `EntityExtractor` over each record and stores the entities it finds
+//! in the record attributes, grouped by label:
+//!
+//! ```json
+//! {"entities": {"person": ["Ada Lovelace"], "location": ["London"]}, "dates": ["1843"]}
+//! ```
+//!
+//! Once stored in a vector database, these attributes can be used in filters at query time, e.g. a
+//! Qdrant `Condition::Matches("attributes.entities.person", "Ada Lovelace")`.
+//!
+//! Two extractors are provided: the `Ner` token classification model in `crate::llm::ner`, and
+//! `LLMEntityExtractor`, a cheaper-to-set-up alternative that uses an extraction template.
+
+use super::{Record, Transform};
+use crate::llm::LLM;
+use crate::prompt::TemplateEngine;
+use crate::template;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::sync::Arc;
+
+/// Name of the template used to extract entities.
+pub const ENTITY_TEMPLATE: &str = "entities";
+
+static DEFAULT_ENTITIES: &str = r#"{{#chat}}
+{{#system}}Extract the named entities mentioned in the text given by the user. Label each entity as one of: person, organization, location, date, misc. Copy the text of each entity exactly as it appears.
+Respond only with a JSON array of the form [{"text": "<entity>", "label": "<label>"}]. If there are no entities, respond with an empty array.{{/system}}
+{{#user}}{{text}}{{/user}}
+{{/chat}}"#;
+
+/// Label of the entities stored under `dates` rather than `entities`.
+const DATE_LABEL: &str = "date";
+
+/// A named entity found in a text.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entity {
+    /// The entity as it appears in the text.
+    pub text: String,
+
+    /// The entity type, e.g. "person" or "location".
+    pub label: String,
+}
+
+impl Entity {
+    /// Create a new entity.
+    pub fn new(text: &str, label: &str) -> Self {
+        Self {
+            text: text.to_string(),
+            label: label.to_string(),
+        }
+    }
+}
+
+/// Finds named entities in a text.
+#[async_trait::async_trait]
+pub trait EntityExtractor: Send + Sync {
+    /// Extract the entities mentioned in the text.
+    async fn extract(&self, text: &str) -> Result<Vec<Entity>>;
+}
+
+/// Entity extractor that prompts an LLM.
+pub struct LLMEntityExtractor<M> {
+    /// The LLM used to extract entities.
+    llm: Arc<M>,
+
+    /// Template engine holding the entity template.
+    template_engine: TemplateEngine,
+}
+
+impl<M: LLM + Clone + 'static> LLMEntityExtractor<M> {
+    /// Creates a new extractor with the default entity template.
+    pub fn new(llm: &M) -> Self {
+        Self {
+            llm: Arc::new(llm.clone()),
+            template_engine: template!(ENTITY_TEMPLATE, DEFAULT_ENTITIES),
+        }
+    }
+
+    /// Override the entity template. The template receives `text`.
+    pub fn load_template(self, template: &str) -> Result<Self> {
+        Ok(Self {
+            template_engine: self.template_engine.register_template(ENTITY_TEMPLATE, template)?,
+            ..self
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl<M: LLM + Clone + 'static> EntityExtractor for LLMEntityExtractor<M> {
+    async fn extract(&self, text: &str) -> Result<Vec<Entity>> {
+        let prompt = self.template_engine.render_context(ENTITY_TEMPLATE, &json!({ "text": text }))?;
+        let response = self.llm.generate(prompt).await?.to_string();
+        let entities: Vec<Entity> = match (response.find('['), response.rfind(']')) {
+            (Some(start), Some(end)) if start < end => serde_json::from_str(&response[start..=end])?,
+            _ => return Err(anyhow::anyhow!("Unable to find entities in response: {}", response)),
+        };
+        Ok(entities)
+    }
+}
+
+/// Ingestion transform that tags each record with the entities found in its content.
+pub struct EntityTagger<X> {
+    /// The extractor used to find entities.
+    extractor: X,
+}
+
+impl<X: EntityExtractor> EntityTagger<X> {
+    /// Creates a new tagger using the given extractor.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use orca_core::llm::openai::OpenAI;
+    /// use orca_core::record::enrich::{EntityTagger, LLMEntityExtractor};
+    ///
+    /// let client = OpenAI::new();
+    /// let tagger = EntityTagger::new(LLMEntityExtractor::new(&client));
+    /// ```
+    pub fn new(extractor: X) -> Self {
+        Self { extractor }
+    }
+
+    /// Tag a single record.
+    pub async fn tag(&self, record: Record) -> Result<Record> {
+        let entities = self.extractor.extract(&record.content.to_string()).await?;
+        Ok(tag_record(record, entities))
+    }
+}
+
+#[async_trait::async_trait]
+impl<X: EntityExtractor> Transform for EntityTagger<X> {
+    async fn transform(&self, records: Vec<Record>) -> Result<Vec<Record>> {
+        let mut tagged = Vec::with_capacity(records.len());
+        for record in records {
+            tagged.push(self.tag(record).await?);
+        }
+        Ok(tagged)
+    }
+}
+
+/// Store entities in the record attributes, grouped by lowercase label and without duplicates.
+fn tag_record(record: Record, entities: Vec<Entity>) -> Record {
+    let mut groups: Map<String, Value> = Map::new();
+    let mut dates: Vec<Value> = Vec::new();
+    for entity in entities {
+        let text = entity.text.trim().to_string();
+        let label = entity.label.trim().to_lowercase();
+        if text.is_empty() || label.is_empty() {
+            continue;
+        }
+        let text = Value::String(text);
+        let values = if label == DATE_LABEL {
+            &mut dates
+        } else {
+            match groups.entry(label).or_insert_with(|| Value::Array(Vec::new())) {
+                Value::Array(values) => values,
+                _ => unreachable!("entity groups are always arrays"),
+            }
+        };
+        if !values.contains(&text) {
+            values.push(text);
+        }
+    }
+    record.with_attribute("entities", groups).with_attribute("dates", dates)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::record::Content;
+    use crate::testing::FixedLLM;
+
+    /// Entities answered by the LLM of the tagging tests.
+    const ENTITIES: &str = r#"[{"text": "Ada Lovelace", "label": "person"}, {"text": "London", "label": "Location"},
+        {"text": "1843", "label": "date"}, {"text": "Ada Lovelace", "label": "person"}]"#;
+
+    #[tokio::test]
+    async fn test_tagger() {
+        let tagger = EntityTagger::new(LLMEntityExtractor::new(&FixedLLM::new(ENTITIES)));
+        let records = vec![Record::new(Content::String(
+            "In 1843, Ada Lovelace published her notes in London.".to_string(),
+        ))];
+        let records = tagger.transform(records).await.unwrap();
+        assert_eq!(
+            records[0].attributes["entities"],
+            json!({"person": ["Ada Lovelace"], "location": ["London"]})
+        );
+        assert_eq!(records[0].attributes["dates"], json!(["1843"]));
+    }
+}
@@ -1,5 +1,6 @@
 use super::Spin;
 use super::{Content, Record};
+use crate::error::OrcaError;
 use anyhow::Result;
 use reqwest;
 use scraper::Selector;
@@ -28,13 +29,24 @@ impl HTML {
 
     /// Create a new HTML record from a file
     pub fn from_file(path: &str) -> Result<HTML> {
-        let body = fs::read_to_string(Path::new(path))?;
+        let body = fs::read_to_string(Path::new(path)).map_err(|e| OrcaError::Record {
+            source: path.to_string(),
+            message: e.to_string(),
+        })?;
         Ok(HTML {
             body,
             selectors: Self::DEFAULT_SELECTORS.to_string(),
         })
     }
 
+    /// Create a new HTML record from a page
+    pub fn from_string(body: &str) -> HTML {
+        HTML {
+            body: body.to_string(),
+            selectors: Self::DEFAULT_SELECTORS.to_string(),
+        }
+    }
+
     /// Set the selectors for the HTML record
     pub fn with_selectors(mut self, selectors: &str) -> HTML {
         self.selectors = selectors.to_string();
@@ -0,0 +1,243 @@
+//! Language detection and routing for mixed-language corpora.
+//!
+//! A `LanguageTagger` stores the language of each record in its `language` attribute, as an
+//! ISO 639-3 code such as `eng` or `spa`. A `LanguageRouter` then sends each record to the embedder
+//! and collection configured for its language, e.g. English records to a MiniLM collection and every
+//! other language to a multilingual one. Queries go through the same router so that they are embedded
+//! with the model of the collection they search.
+
+use super::{Record, Transform};
+use crate::llm::{Embedding, Embeddings};
+use crate::prompt::Prompt;
+
+use anyhow::Result;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Name of the record attribute holding the detected language.
+pub const LANGUAGE_ATTRIBUTE: &str = "language";
+
+/// Detect the language of a text, as an ISO 639-3 code. Returns `None` when the text is too short or
+/// ambiguous for the detection to be reliable.
+///
+/// # Example
+/// ```
+/// use orca_core::record::language::detect_language;
+///
+/// let language = detect_language("El rápido zorro marrón salta sobre el perro perezoso y la liebre.");
+/// assert_eq!(language.as_deref(), Some("spa"));
+/// ```
+pub fn detect_language(text: &str) -> Option<String> {
+    whatlang::detect(text).filter(|info| info.is_reliable()).map(|info| info.lang().code().to_string())
+}
+
+/// English name of a language given as an ISO 639-3 code, e.g. `Spanish` for `spa`.
+///
+/// # Example
+/// ```
+/// use orca_core::record::language::language_name;
+///
+/// assert_eq!(language_name("spa"), Some("Spanish"));
+/// assert_eq!(language_name("xyz"), None);
+/// ```
+pub fn language_name(code: &str) -> Option<&'static str> {
+    whatlang::Lang::from_code(code).map(|lang| lang.eng_name())
+}
+
+/// Language of a record, from its `language` attribute or detected from its content.
+fn record_language(record: &Record) -> Option<String> {
+    match record.attributes.get(LANGUAGE_ATTRIBUTE) {
+        Some(Value::String(language)) => Some(language.clone()),
+        _ => detect_language(&record.content.to_string()),
+    }
+}
+
+/// Ingestion transform that stores the detected language of each record in its `language` attribute.
+/// Records whose language cannot be detected reliably are left untagged.
+#[derive(Default)]
+pub struct LanguageTagger;
+
+impl LanguageTagger {
+    /// Creates a new language tagger.
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+#[async_trait::async_trait]
+impl Transform for LanguageTagger {
+    async fn transform(&self, records: Vec<Record>) -> Result<Vec<Record>> {
+        Ok(records
+            .into_iter()
+            .map(|record| match detect_language(&record.content.to_string()) {
+                Some(language) => record.with_attribute(LANGUAGE_ATTRIBUTE, language),
+                None => record,
+            })
+            .collect())
+    }
+}
+
+/// Embedder and collection that records of some languages are routed to.
+#[derive(Clone)]
+pub struct Route {
+    /// The embedder used for the records and queries of this route.
+    pub embedder: Arc<dyn Embedding + Send + Sync>,
+
+    /// The collection storing the records of this route.
+    pub collection: String,
+}
+
+impl Route {
+    /// Creates a new route.
+    pub fn new(embedder: Arc<dyn Embedding + Send + Sync>, collection: &str) -> Self {
+        Self {
+            embedder,
+            collection: collection.to_string(),
+        }
+    }
+}
+
+/// Records routed to the same collection, with their embeddings.
+pub struct RoutedRecords {
+    /// The collection the records are routed to.
+    pub collection: String,
+
+    /// The records, in their original order.
+    pub records: Vec<Record>,
+
+    /// The embeddings of the records, in the same order.
+    pub embeddings: Embeddings,
+}
+
+/// Routes records and queries to an embedder and collection according to their language.
+pub struct LanguageRouter {
+    /// Routes, the first one being the default route.
+    routes: Vec<Route>,
+
+    /// Index of the route of each language.
+    languages: HashMap<String, usize>,
+}
+
+impl LanguageRouter {
+    /// Creates a new router that sends every language to the default route.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use orca_core::llm::{bert::Bert, EmbeddingPreset};
+    /// # use orca_core::record::language::{LanguageRouter, Route};
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let english = Bert::new().build_model_and_tokenizer().await?;
+    /// let multilingual = Bert::new()
+    ///     .with_preset(EmbeddingPreset::ParaphraseMultilingualMiniLmL12V2)
+    ///     .build_model_and_tokenizer()
+    ///     .await?;
+    /// let router = LanguageRouter::new(Route::new(Arc::new(multilingual), "multilingual"))
+    ///     .with_route(&["eng"], Route::new(Arc::new(english), "english"));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new(default: Route) -> Self {
+        Self {
+            routes: vec![default],
+            languages: HashMap::new(),
+        }
+    }
+
+    /// Sends the given languages, as ISO 639-3 codes, to a route.
+    pub fn with_route(mut self, languages: &[&str], route: Route) -> Self {
+        self.routes.push(route);
+        let index = self.routes.len() - 1;
+        for language in languages {
+            self.languages.insert(language.to_string(), index);
+        }
+        self
+    }
+
+    /// Route of the given language, or the default route.
+    fn route_index(&self, language: Option<&str>) -> usize {
+        language.and_then(|language| self.languages.get(language)).copied().unwrap_or(0)
+    }
+
+    /// Route of a query, according to its detected language.
+    pub fn route(&self, query: &str) -> &Route {
+        &self.routes[self.route_index(detect_language(query).as_deref())]
+    }
+
+    /// Embed a query with the embedder of its route, returning the collection to search.
+    pub async fn embed_query(&self, query: &str) -> Result<(&str, Vec<f32>)> {
+        let route = self.route(query);
+        let embedding = route.embedder.generate_embedding(Box::new(query.to_string())).await?;
+        Ok((&route.collection, embedding.to_vec()?))
+    }
+
+    /// Group records by route, keeping their order within each route. Routes without records are
+    /// skipped.
+    pub fn group(&self, records: Vec<Record>) -> Vec<(&Route, Vec<Record>)> {
+        let mut groups: Vec<Vec<Record>> = vec![Vec::new(); self.routes.len()];
+        for record in records {
+            groups[self.route_index(record_language(&record).as_deref())].push(record);
+        }
+        self.routes.iter().zip(groups).filter(|(_, records)| !records.is_empty()).collect()
+    }
+
+    /// Embed records with the embedder of their route, grouped by collection.
+    pub async fn embed(&self, records: Vec<Record>) -> Result<Vec<RoutedRecords>> {
+        let mut routed = Vec::new();
+        for (route, records) in self.group(records) {
+            let prompts =
+                records.iter().map(|record| Box::new(record.content.to_string()) as Box<dyn Prompt>).collect();
+            let embeddings = route.embedder.generate_embeddings(prompts).await?;
+            routed.push(RoutedRecords {
+                collection: route.collection.clone(),
+                records,
+                embeddings,
+            });
+        }
+        Ok(routed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::record::Content;
+    use crate::testing::ConstantEmbedder;
+
+    fn record(text: &str) -> Record {
+        Record::new(Content::String(text.to_string()))
+    }
+
+    #[tokio::test]
+    async fn test_router() {
+        let router = LanguageRouter::new(Route::new(Arc::new(ConstantEmbedder(0.0)), "multilingual"))
+            .with_route(&["eng"], Route::new(Arc::new(ConstantEmbedder(1.0)), "english"));
+        let records = vec![
+            record("The quick brown fox jumps over the lazy dog and the hare."),
+            record("El rápido zorro marrón salta sobre el perro perezoso y la liebre."),
+            record("The dog is the best friend of the man."),
+            record("Untagged record").with_attribute(LANGUAGE_ATTRIBUTE, "eng"),
+        ];
+
+        let routed = router.embed(records).await.unwrap();
+        assert_eq!(routed.len(), 2);
+        assert_eq!(routed[0].collection, "multilingual");
+        assert_eq!(routed[0].records.len(), 1);
+        assert_eq!(routed[1].collection, "english");
+        assert_eq!(routed[1].records.len(), 3);
+        assert_eq!(routed[1].embeddings.to_vec2(), vec![vec![1.0]; 3]);
+
+        let (collection, embedding) = router.embed_query("Where is the dog and the fox?").await.unwrap();
+        assert_eq!(collection, "english");
+        assert_eq!(embedding, vec![1.0]);
+    }
+
+    #[tokio::test]
+    async fn test_tagger() {
+        let records = vec![record("Der Hund ist der beste Freund und der Mensch.")];
+        let records = LanguageTagger::new().transform(records).await.unwrap();
+        assert_eq!(records[0].attributes[LANGUAGE_ATTRIBUTE], "deu");
+    }
+}
@@ -1,17 +1,39 @@
+pub mod dedup;
+pub mod enrich;
 pub mod html;
+pub mod language;
 pub mod pdf;
+pub mod provenance;
+pub mod table;
 use std::{fmt::Display, path::Path};
 
 use anyhow::Result;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use text_splitter::TextSplitter;
-/// Content of a record which can be represented as either a string or a vector of strings.
-/// To get the string representation of the content, use the `to_string` method.
-#[derive(Serialize, Clone, PartialEq, Debug)]
+
+use crate::prompt::chat::Image;
+
+/// Separator of the strings of a `Content::Vec` in its text.
+const VEC_SEPARATOR: &str = "\n******************\n";
+
+/// Attribute holding the offset, in characters, of the start of a chunk in the text of the record it was
+/// split from.
+pub const START_ATTRIBUTE: &str = "char_start";
+
+/// Attribute holding the offset, in characters, past the end of a chunk in the text of the record it was
+/// split from.
+pub const END_ATTRIBUTE: &str = "char_end";
+
+/// Content of a record which can be represented as either a string, a vector of strings or an image.
+/// To get the string representation of the content, use the `to_string` method; for an image, this is
+/// its URL, which can be passed to the `image` template helper.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 #[serde(untagged)]
 pub enum Content {
     String(String),
     Vec(Vec<String>),
+    Image(Image),
 }
 
 impl Display for Content {
@@ -19,23 +41,29 @@ impl Display for Content {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Content::String(string) => write!(f, "{}", string),
-            Content::Vec(vec) => write!(f, "{}", vec.join("\n******************\n")),
+            Content::Vec(vec) => write!(f, "{}", vec.join(VEC_SEPARATOR)),
+            Content::Image(image) => write!(f, "{}", image.url),
         }
     }
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Record {
     /// Header information for the record.
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub header: Option<String>,
 
     /// Content of the record.
     pub content: Content,
 
     /// Metadata for the record (present in PDFs, for example).
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub metadata: Option<String>,
+
+    /// Structured metadata for the record, such as tags added at ingestion. Stored alongside the
+    /// record in vector databases so that it can be used in filters.
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub attributes: Map<String, Value>,
 }
 
 impl Display for Record {
@@ -52,6 +80,7 @@ impl Record {
             header: None,
             content,
             metadata: None,
+            attributes: Map::new(),
         }
     }
 
@@ -67,6 +96,12 @@ impl Record {
         self
     }
 
+    /// Set a structured metadata attribute of the record.
+    pub fn with_attribute(mut self, key: &str, value: impl Into<Value>) -> Self {
+        self.attributes.insert(key.to_string(), value.into());
+        self
+    }
+
     pub fn with_content(mut self, content: Content) -> Self {
         self.content = content;
         self
@@ -76,7 +111,9 @@ impl Record {
     ///
     /// This function divides the content of a `Record` into smaller chunks of approximately equal size.
     /// The chunks are determined by the maximum number of characters allowed per chunk. If the content
-    /// is a vector of strings, each string will be split into chunks separately.
+    /// is a vector of strings, each string will be split into chunks separately. Chunks keep the header
+    /// and attributes of the record, and the offsets of their text in the text of the record in their
+    /// `char_start` and `char_end` attributes, so that overlapping chunks can be merged back.
     ///
     /// # Arguments
     /// * `chunks` - The desired number of chunks the content should be split into.
@@ -93,25 +130,8 @@ impl Record {
     /// assert_eq!(records.len(), 2);
     /// ```
     pub fn split(&self, max_tokens: usize) -> Vec<Record> {
-        let mut records = Vec::new();
         let splitter = TextSplitter::default().with_trim_chunks(true);
-        match &self.content {
-            Content::String(string) => {
-                let chunks = splitter.chunks(string, max_tokens);
-                for chunk in chunks {
-                    records.push(Record::new(Content::String(chunk.to_string())));
-                }
-            }
-            Content::Vec(vec) => {
-                for string in vec {
-                    let chunks = splitter.chunks(string, max_tokens);
-                    for chunk in chunks {
-                        records.push(Record::new(Content::String(chunk.to_string())));
-                    }
-                }
-            }
-        }
-        records
+        self.split_texts(|text| splitter.chunks(text, max_tokens).collect())
     }
 
     /// Splits the content of a `Record` into multiple smaller records using a tokenizer.
@@ -150,27 +170,37 @@ impl Record {
         };
 
         let splitter = TextSplitter::new(tokenizer).with_trim_chunks(true);
+        Ok(self.split_texts(|text| splitter.chunks(text, chunks).collect()))
+    }
 
+    /// Split each text of the content with the given function, returning chunks with the header, attributes
+    /// and offsets of their text. Chunks must be substrings of the text, in order.
+    fn split_texts<'a, F: Fn(&'a str) -> Vec<&'a str>>(&'a self, split: F) -> Vec<Record> {
+        let texts = match &self.content {
+            Content::String(string) => vec![string],
+            Content::Vec(vec) => vec.iter().collect(),
+            Content::Image(_) => return vec![self.clone()],
+        };
         let mut records = Vec::new();
-
-        match &self.content {
-            Content::String(string) => {
-                let chunks = splitter.chunks(string, chunks);
-                for chunk in chunks {
-                    records.push(Record::new(Content::String(chunk.to_string())));
-                }
-            }
-            Content::Vec(vec) => {
-                for string in vec {
-                    let chunks = splitter.chunks(string, chunks);
-                    for chunk in chunks {
-                        records.push(Record::new(Content::String(chunk.to_string())));
-                    }
-                }
+        let mut base = 0;
+        for text in texts {
+            // Offsets of the chunks, found from the end of the previous chunk, in bytes then in characters.
+            let (mut cursor, mut chars) = (0, 0);
+            for chunk in split(text) {
+                let start = cursor + text[cursor..].find(chunk).unwrap_or(0);
+                chars += text[cursor..start].chars().count();
+                let length = chunk.chars().count();
+                let mut record = self.clone().with_content(Content::String(chunk.to_string()));
+                record.attributes.insert(START_ATTRIBUTE.to_string(), (base + chars).into());
+                record.attributes.insert(END_ATTRIBUTE.to_string(), (base + chars + length).into());
+                records.push(record);
+                cursor = start + chunk.len();
+                chars += length;
             }
+            chars += text[cursor..].chars().count();
+            base += chars + VEC_SEPARATOR.chars().count();
         }
-
-        Ok(records)
+        records
     }
 }
 
@@ -193,6 +223,22 @@ pub trait Spin {
     fn spin(&self) -> Result<Record>;
 }
 
+/// A step applied to records at ingestion, after they are spun and split and before they are
+/// embedded and stored. Transforms can enrich, filter or rewrite records.
+#[async_trait::async_trait]
+pub trait Transform: Send + Sync {
+    /// Transform a batch of records.
+    async fn transform(&self, records: Vec<Record>) -> Result<Vec<Record>>;
+}
+
+/// Apply transforms to records in order.
+pub async fn transform_all(mut records: Vec<Record>, transforms: &[Box<dyn Transform>]) -> Result<Vec<Record>> {
+    for transform in transforms {
+        records = transform.transform(records).await?;
+    }
+    Ok(records)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -206,6 +252,13 @@ mod tests {
         assert_eq!(content.to_string(), "Hello\n******************\nWorld");
     }
 
+    #[test]
+    fn test_image_content() {
+        let content = Content::Image(Image::from_base64("image/png", "cG5n"));
+        assert_eq!(content.to_string(), "data:image/png;base64,cG5n");
+        assert_eq!(Record::new(content.clone()).split(2)[0].content, content);
+    }
+
     #[test]
     fn test_record_new() {
         let content = Content::String("Hello".to_string());
@@ -235,6 +288,8 @@ mod tests {
         assert_eq!(chunks.len(), 2);
         assert_eq!(chunks[0].content.to_string(), "Hello");
         assert_eq!(chunks[1].content.to_string(), "World!");
+        assert_eq!(chunks[1].attributes[START_ATTRIBUTE], 6);
+        assert_eq!(chunks[1].attributes[END_ATTRIBUTE], 12);
     }
 
     // This test requires a valid tokenizer and a suitable setup, so it's more of a template
@@ -1,6 +1,10 @@
 use std::{fmt::Display, sync::Arc, vec};
 
+use super::table::{detect_tables, line_cells, PositionedWord, TableFormat};
 use super::{Content, Record, Spin};
+#[cfg(feature = "pdf-render")]
+use crate::error::OrcaError;
+use crate::prompt::chat::Image;
 use anyhow::Result;
 use pdf::{
     any::AnySync,
@@ -55,10 +59,91 @@ impl Pdf {
     pub fn from_file(path: &str, split: bool) -> Result<Pdf> {
         // convert buffer into file object
         Ok(Pdf {
-            file: FileOptions::cached().open(path)?,
+            file: FileOptions::cached().open(path).map_err(|e| OrcaError::Record {
+                source: path.to_string(),
+                message: e.to_string(),
+            })?,
             split,
         })
     }
+
+    /// Extract the tables of each page into their own records, written in the given format. Each
+    /// record has `table_index` (counting from 0 across the document), `page` (starting at 1), `rows`
+    /// and `columns` attributes. Index table records without splitting them, so that rows stay whole.
+    /// ```no_run
+    /// use orca_core::record::pdf::Pdf;
+    /// use orca_core::record::table::TableFormat;
+    ///
+    /// let pdf = Pdf::from_file("./tests/records/sample-resume.pdf", false).unwrap();
+    /// let tables = pdf.extract_tables(TableFormat::Markdown).unwrap();
+    /// ```
+    pub fn extract_tables(&self, format: TableFormat) -> Result<Vec<Record>> {
+        let resolver = self.file.resolver();
+        let mut records = Vec::new();
+        for (page_index, page) in self.file.pages().enumerate() {
+            let page = page?;
+            let flow = pdf_text::run(&self.file, &page, &resolver)?;
+            let lines: Vec<Vec<String>> = flow
+                .runs
+                .iter()
+                .flat_map(|run| &run.lines)
+                .map(|line| {
+                    let words: Vec<PositionedWord> = line
+                        .words
+                        .iter()
+                        .map(|word| PositionedWord {
+                            text: word.text.clone(),
+                            x: word.rect.x,
+                            width: word.rect.w,
+                            height: word.rect.h,
+                        })
+                        .collect();
+                    line_cells(&words)
+                })
+                .collect();
+            for (_, table) in detect_tables(&lines) {
+                let record = Record::new(Content::String(table.to_format(format)))
+                    .with_attribute("table_index", records.len())
+                    .with_attribute("page", page_index + 1)
+                    .with_attribute("rows", table.rows.len())
+                    .with_attribute("columns", table.columns());
+                records.push(record);
+            }
+        }
+        Ok(records)
+    }
+
+    /// Render each page to a PNG image at the given resolution, in dots per inch. Each page becomes a
+    /// record with image content and a `page` attribute (starting at 1), ready to be passed to a
+    /// vision model with the `image` template helper. Charts and tables that text extraction loses
+    /// are kept this way.
+    /// ```no_run
+    /// use orca_core::record::pdf::Pdf;
+    ///
+    /// let pages = Pdf::from_file("./tests/records/sample-resume.pdf", false).unwrap().render_pages(150.0).unwrap();
+    /// ```
+    #[cfg(feature = "pdf-render")]
+    pub fn render_pages(&self, dpi: f32) -> Result<Vec<Record>> {
+        use pathfinder_geometry::transform2d::Transform2F;
+        use pathfinder_rasterize::Rasterizer;
+        use pdf_render::{render_page, Cache, SceneBackend};
+
+        let resolver = self.file.resolver();
+        let mut cache = Cache::new();
+        let mut records = Vec::new();
+        for (index, page) in self.file.pages().enumerate() {
+            let page = page?;
+            let mut backend = SceneBackend::new(&mut cache);
+            // Pages are laid out in millimeters.
+            render_page(&mut backend, &resolver, &page, Transform2F::from_scale(dpi / 25.4))?;
+            let image = Rasterizer::new().rasterize(backend.finish(), None);
+            let mut png = std::io::Cursor::new(Vec::new());
+            image.write_to(&mut png, image::ImageOutputFormat::Png)?;
+            let image = Image::from_bytes("image/png", png.get_ref());
+            records.push(Record::new(Content::Image(image)).with_attribute("page", index + 1));
+        }
+        Ok(records)
+    }
 }
 
 pub enum PdfOutput {
@@ -0,0 +1,248 @@
+//! Provenance of records.
+//!
+//! A `Signer` adds a `provenance` attribute to records at ingestion, holding the SHA-256 hash of the
+//! record and an HMAC of it under a secret key, and checks it when the records are retrieved, so that
+//! regulated deployments can prove that the context cited by an answer was not modified after it was
+//! ingested. Records are hashed in a canonical JSON form, with object keys sorted, so that signatures
+//! survive stores that reorder payload fields. The `score` attribute set by searches is not signed.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use anyhow::Result;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use super::Record;
+use crate::error::OrcaError;
+use crate::pipeline::assembler::SCORE_ATTRIBUTE;
+
+/// Attribute of a record holding its provenance.
+pub const PROVENANCE_ATTRIBUTE: &str = "provenance";
+
+/// Provenance of a record, as stored in its `provenance` attribute.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Provenance {
+    /// Identifier of the key the record was signed with.
+    pub key_id: String,
+
+    /// SHA-256 hash of the canonical form of the record, in hexadecimal.
+    pub sha256: String,
+
+    /// HMAC-SHA256 of the canonical form of the record, in hexadecimal.
+    pub hmac: String,
+}
+
+/// Reason a record failed verification.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerificationError {
+    /// The record has no valid `provenance` attribute.
+    Unsigned,
+
+    /// The record was signed with a key the signer does not know.
+    UnknownKey(String),
+
+    /// The record was modified after it was signed.
+    Tampered,
+}
+
+impl Display for VerificationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerificationError::Unsigned => write!(f, "Record verification failed: the record is not signed"),
+            VerificationError::UnknownKey(key_id) => {
+                write!(f, "Record verification failed: unknown signing key {}", key_id)
+            }
+            VerificationError::Tampered => {
+                write!(
+                    f,
+                    "Record verification failed: the record was modified after it was signed"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for VerificationError {}
+
+/// Signs records and verifies their signatures with HMAC-SHA256.
+#[derive(Clone)]
+pub struct Signer {
+    /// Identifier of the key records are signed with.
+    key_id: String,
+
+    /// Keys signatures are verified with, by identifier, including the signing key.
+    keys: HashMap<String, Vec<u8>>,
+}
+
+impl std::fmt::Debug for Signer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Keys are secret.
+        f.debug_struct("Signer").field("key_id", &self.key_id).finish_non_exhaustive()
+    }
+}
+
+impl Signer {
+    /// Create a signer with a secret key and its identifier, recorded in the signatures.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::record::provenance::Signer;
+    /// use orca_core::record::{Content, Record};
+    ///
+    /// let signer = Signer::new("2024-01", b"secret key");
+    /// let record = signer.sign(Record::new(Content::String("Orcas live in pods.".into()))).unwrap();
+    /// assert!(signer.verify(&record).is_ok());
+    ///
+    /// let tampered = record.with_content(Content::String("Orcas live alone.".into()));
+    /// assert!(signer.verify(&tampered).is_err());
+    /// ```
+    pub fn new(key_id: &str, key: &[u8]) -> Self {
+        Signer {
+            key_id: key_id.to_string(),
+            keys: HashMap::from([(key_id.to_string(), key.to_vec())]),
+        }
+    }
+
+    /// Also accept signatures made with a retired key, so that keys can be rotated without re-ingesting.
+    pub fn with_verification_key(mut self, key_id: &str, key: &[u8]) -> Self {
+        self.keys.insert(key_id.to_string(), key.to_vec());
+        self
+    }
+
+    /// Set the `provenance` attribute of a record, replacing any previous signature.
+    pub fn sign(&self, mut record: Record) -> Result<Record> {
+        record.attributes.remove(PROVENANCE_ATTRIBUTE);
+        let canonical = canonical_form(&record)?;
+        let provenance = Provenance {
+            key_id: self.key_id.clone(),
+            sha256: hex::encode(Sha256::digest(&canonical)),
+            hmac: hex::encode(self.mac(&self.key_id, &canonical)?.finalize().into_bytes()),
+        };
+        record.attributes.insert(PROVENANCE_ATTRIBUTE.to_string(), serde_json::to_value(provenance)?);
+        Ok(record)
+    }
+
+    /// Check the `provenance` attribute of a record, failing with an `OrcaError::Verification` if the record is
+    /// unsigned, was signed with an unknown key or was modified since it was signed.
+    pub fn verify(&self, record: &Record) -> Result<()> {
+        let provenance: Provenance = record
+            .attributes
+            .get(PROVENANCE_ATTRIBUTE)
+            .and_then(|provenance| serde_json::from_value(provenance.clone()).ok())
+            .ok_or(OrcaError::Verification(VerificationError::Unsigned))?;
+        if !self.keys.contains_key(&provenance.key_id) {
+            return Err(OrcaError::Verification(VerificationError::UnknownKey(provenance.key_id)).into());
+        }
+        let canonical = canonical_form(record)?;
+        let hmac = hex::decode(&provenance.hmac).map_err(|_| OrcaError::Verification(VerificationError::Tampered))?;
+        let matches = self.mac(&provenance.key_id, &canonical)?.verify_slice(&hmac).is_ok();
+        if !matches || hex::encode(Sha256::digest(&canonical)) != provenance.sha256 {
+            return Err(OrcaError::Verification(VerificationError::Tampered).into());
+        }
+        Ok(())
+    }
+
+    /// HMAC-SHA256 keyed with the key of the given identifier.
+    fn mac(&self, key_id: &str, canonical: &[u8]) -> Result<Hmac<Sha256>> {
+        let key = self
+            .keys
+            .get(key_id)
+            .ok_or_else(|| OrcaError::Verification(VerificationError::UnknownKey(key_id.to_string())))?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+        mac.update(canonical);
+        Ok(mac)
+    }
+}
+
+/// Canonical JSON form of a record, without its provenance and score attributes.
+fn canonical_form(record: &Record) -> Result<Vec<u8>> {
+    let mut record = record.clone();
+    record.attributes.remove(PROVENANCE_ATTRIBUTE);
+    record.attributes.remove(SCORE_ATTRIBUTE);
+    let value = serde_json::to_value(&record)?;
+    let mut canonical = String::new();
+    write_canonical(&value, &mut canonical)?;
+    Ok(canonical.into_bytes())
+}
+
+/// Write a JSON value with the keys of its objects sorted.
+fn write_canonical(value: &Value, out: &mut String) -> Result<()> {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            out.push('{');
+            for (i, (key, value)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&serde_json::to_string(key)?);
+                out.push(':');
+                write_canonical(value, out)?;
+            }
+            out.push('}');
+        }
+        Value::Array(values) => {
+            out.push('[');
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(value, out)?;
+            }
+            out.push(']');
+        }
+        value => out.push_str(&serde_json::to_string(value)?),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::record::Content;
+    use serde_json::Map;
+
+    #[test]
+    fn test_provenance() {
+        let signer = Signer::new("2024-01", b"secret");
+        let record = Record::new(Content::String("Orcas live in pods.".to_string()))
+            .with_attribute("page", 2)
+            .with_attribute("source", "orcas.pdf");
+        let signed = signer.sign(record).unwrap();
+        signer.verify(&signed).unwrap();
+
+        // Scores added by searches and reordered attributes keep the signature valid.
+        let mut reordered = signed.clone().with_attribute(SCORE_ATTRIBUTE, 0.9);
+        reordered.attributes = Map::from_iter(reordered.attributes.into_iter().rev());
+        signer.verify(&reordered).unwrap();
+
+        let error = |record: &Record, signer: &Signer| {
+            let error = signer.verify(record).unwrap_err();
+            match error.downcast::<OrcaError>() {
+                Ok(OrcaError::Verification(error)) => error,
+                error => panic!("Unexpected error: {:?}", error),
+            }
+        };
+        assert_eq!(
+            error(&signed.clone().with_attribute("page", 3), &signer),
+            VerificationError::Tampered
+        );
+        let unsigned = Record::new(Content::String("Orcas live in pods.".to_string()));
+        assert_eq!(error(&unsigned, &signer), VerificationError::Unsigned);
+
+        // Rotated keys verify older records, and signers without the key reject them.
+        let rotated = Signer::new("2024-02", b"new secret").with_verification_key("2024-01", b"secret");
+        rotated.verify(&signed).unwrap();
+        let other = Signer::new("2024-02", b"new secret");
+        assert_eq!(
+            error(&signed, &other),
+            VerificationError::UnknownKey("2024-01".to_string())
+        );
+        let forged = Signer::new("2024-01", b"guessed").sign(signed.clone()).unwrap();
+        assert_eq!(error(&forged, &signer), VerificationError::Tampered);
+    }
+}
@@ -0,0 +1,205 @@
+//! Table detection in text laid out in lines of cells.
+//!
+//! Loaders that know where words are on a page, such as the PDF loader, group the words of each line
+//! into cells separated by wide gaps. Consecutive lines with the same number of cells are then read as
+//! a table, which can be written as CSV or markdown so that numeric data keeps its rows and columns
+//! once indexed.
+
+use serde::{Deserialize, Serialize};
+
+/// Minimum number of rows, including the header, for lines to be read as a table.
+const MIN_ROWS: usize = 2;
+
+/// Format in which tables are written in record content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TableFormat {
+    Csv,
+    #[default]
+    Markdown,
+}
+
+/// A table, the first row being the header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    /// Number of columns.
+    pub fn columns(&self) -> usize {
+        self.rows.first().map_or(0, |row| row.len())
+    }
+
+    /// Write the table in the given format.
+    pub fn to_format(&self, format: TableFormat) -> String {
+        match format {
+            TableFormat::Csv => self.to_csv(),
+            TableFormat::Markdown => self.to_markdown(),
+        }
+    }
+
+    /// Write the table as CSV, quoting cells that contain commas, quotes or line breaks.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::record::table::Table;
+    ///
+    /// let table = Table { rows: vec![vec!["Name".into(), "Revenue".into()], vec!["Acme".into(), "1,200".into()]] };
+    /// assert_eq!(table.to_csv(), "Name,Revenue\nAcme,\"1,200\"\n");
+    /// ```
+    pub fn to_csv(&self) -> String {
+        let escape = |cell: &String| {
+            if cell.contains([',', '"', '\n']) {
+                format!("\"{}\"", cell.replace('"', "\"\""))
+            } else {
+                cell.clone()
+            }
+        };
+        self.rows.iter().map(|row| row.iter().map(escape).collect::<Vec<_>>().join(",") + "\n").collect()
+    }
+
+    /// Write the table as a markdown table.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::record::table::Table;
+    ///
+    /// let table = Table { rows: vec![vec!["Name".into(), "Revenue".into()], vec!["Acme".into(), "1,200".into()]] };
+    /// assert_eq!(table.to_markdown(), "| Name | Revenue |\n| --- | --- |\n| Acme | 1,200 |\n");
+    /// ```
+    pub fn to_markdown(&self) -> String {
+        let row = |cells: &[String]| {
+            format!(
+                "| {} |\n",
+                cells.iter().map(|cell| cell.replace('|', "\\|")).collect::<Vec<_>>().join(" | ")
+            )
+        };
+        let mut markdown = String::new();
+        for (index, cells) in self.rows.iter().enumerate() {
+            markdown.push_str(&row(cells));
+            if index == 0 {
+                markdown.push_str(&row(&vec!["---".to_string(); cells.len()]));
+            }
+        }
+        markdown
+    }
+}
+
+/// A word and its horizontal position on the line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PositionedWord {
+    pub text: String,
+
+    /// Left edge of the word.
+    pub x: f32,
+
+    /// Width of the word.
+    pub width: f32,
+
+    /// Height of the word, used as the font size to tell a space between words from a gap between cells.
+    pub height: f32,
+}
+
+/// Group the words of a line into cells. Words separated by more than the height of the text, i.e.
+/// wider than a few spaces, are in different cells.
+pub fn line_cells(words: &[PositionedWord]) -> Vec<String> {
+    let mut cells: Vec<String> = Vec::new();
+    let mut end: Option<f32> = None;
+    for word in words {
+        match (end, cells.last_mut()) {
+            (Some(end), Some(cell)) if word.x - end <= word.height => {
+                cell.push(' ');
+                cell.push_str(&word.text);
+            }
+            _ => cells.push(word.text.clone()),
+        }
+        end = Some(word.x + word.width);
+    }
+    cells
+}
+
+/// Find the tables in lines of cells. Returns each table with the range of lines it spans; lines
+/// outside tables are left to text extraction.
+///
+/// # Example
+/// ```
+/// use orca_core::record::table::detect_tables;
+///
+/// let lines: Vec<Vec<String>> = vec![
+///     vec!["Quarterly results".into()],
+///     vec!["Quarter".into(), "Revenue".into()],
+///     vec!["Q1".into(), "120".into()],
+///     vec!["Q2".into(), "135".into()],
+/// ];
+/// let tables = detect_tables(&lines);
+/// assert_eq!(tables.len(), 1);
+/// assert_eq!(tables[0].0, 1..4);
+/// ```
+pub fn detect_tables(lines: &[Vec<String>]) -> Vec<(std::ops::Range<usize>, Table)> {
+    let mut tables = Vec::new();
+    let mut start = 0;
+    while start < lines.len() {
+        let columns = lines[start].len();
+        let end = start + lines[start..].iter().take_while(|line| line.len() == columns).count();
+        if columns >= 2 && end - start >= MIN_ROWS {
+            tables.push((
+                start..end,
+                Table {
+                    rows: lines[start..end].to_vec(),
+                },
+            ));
+        }
+        start = end;
+    }
+    tables
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn word(text: &str, x: f32) -> PositionedWord {
+        PositionedWord {
+            text: text.to_string(),
+            x,
+            width: text.len() as f32 * 5.0,
+            height: 10.0,
+        }
+    }
+
+    #[test]
+    fn test_line_cells() {
+        let words = vec![
+            word("Net", 0.0),
+            word("income", 18.0),
+            word("1,200", 100.0),
+            word("950", 150.0),
+        ];
+        assert_eq!(line_cells(&words), vec!["Net income", "1,200", "950"]);
+    }
+
+    #[test]
+    fn test_detect_tables() {
+        let lines: Vec<Vec<String>> = [
+            vec!["Results"],
+            vec!["Item", "2023", "2022"],
+            vec!["Net income", "1,200", "950"],
+            vec!["Paragraph between tables."],
+            vec!["A", "B"],
+            vec!["1", "2"],
+            vec!["3", "4"],
+            vec!["Single", "row"],
+        ]
+        .iter()
+        .map(|line| line.iter().map(|cell| cell.to_string()).collect())
+        .collect();
+
+        let tables = detect_tables(&lines);
+        assert_eq!(tables.len(), 2);
+        assert_eq!(tables[0].0, 1..3);
+        assert_eq!(tables[0].1.columns(), 3);
+        assert_eq!(tables[1].0, 4..8);
+        assert_eq!(tables[1].1.to_format(TableFormat::Csv), "A,B\n1,2\n3,4\nSingle,row\n");
+    }
+}
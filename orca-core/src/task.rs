@@ -0,0 +1,37 @@
+//! Helpers for the tasks orca spawns.
+//!
+//! Tasks are spawned in a `JoinSet` owned by the caller, so that they are aborted when the caller
+//! returns early or is cancelled, instead of running on detached and holding channel senders open.
+
+use anyhow::{anyhow, Result};
+use tokio::task::JoinError;
+
+/// The output of a joined task, resuming its panic on the caller if it panicked.
+pub(crate) fn joined<T>(result: Result<T, JoinError>) -> Result<T> {
+    match result {
+        Ok(output) => Ok(output),
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(e) => Err(anyhow!("Task was cancelled: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::task::JoinSet;
+
+    #[tokio::test]
+    async fn test_joined() {
+        let mut tasks = JoinSet::new();
+        tasks.spawn(async { 1 });
+        assert_eq!(joined(tasks.join_next().await.unwrap()).unwrap(), 1);
+
+        tasks.spawn(std::future::pending::<i32>());
+        tasks.abort_all();
+        assert!(joined(tasks.join_next().await.unwrap()).is_err());
+
+        tasks.spawn(async { panic!("worker failed") });
+        let panic = tokio::spawn(async move { joined(tasks.join_next().await.unwrap()) }).await.unwrap_err();
+        assert!(panic.is_panic());
+    }
+}
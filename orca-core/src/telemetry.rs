@@ -0,0 +1,413 @@
+//! Traces of pipeline executions.
+//!
+//! Pipelines, LLM calls, retrievals and local models emit [`tracing`] spans. A `TraceLayer` added to
+//! the subscriber of the application collects the spans of each execution, i.e. the spans under a
+//! root span such as `pipeline.execute`, into one `Trace`, and hands it to a `TraceExporter` once the
+//! root span closes: `ChromeExporter` writes it as a Chrome trace file, to open in `chrome://tracing`
+//! or Perfetto, and `OtlpExporter` sends it to an OpenTelemetry collector.
+//!
+//! Orca never sets a global subscriber itself. Applications without one call [`init`]; those with
+//! their own subscriber add a `TraceLayer` to it instead.
+//!
+//! # Example
+//! ```no_run
+//! use orca_core::telemetry::{self, ChromeExporter, TraceLayer};
+//! use tracing_subscriber::prelude::*;
+//!
+//! // Without a subscriber of your own
+//! telemetry::init(ChromeExporter::new("traces")).unwrap();
+//!
+//! // Or alongside your own layers
+//! tracing_subscriber::registry()
+//!     .with(tracing_subscriber::fmt::layer())
+//!     .with(TraceLayer::new(ChromeExporter::new("traces")))
+//!     .init();
+//! ```
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use serde_json::{json, Map, Value as JsonValue};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::Subscriber;
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Id of the next span, unique within the process.
+static NEXT_SPAN_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Finished span of a trace.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpanData {
+    /// Id of the span, unique within the process.
+    pub id: u64,
+
+    /// Id of the parent span, `None` for the root span.
+    pub parent_id: Option<u64>,
+
+    /// Name of the span, e.g. `llm.generate`.
+    pub name: String,
+
+    /// Target of the span, i.e. the module it was created in.
+    pub target: String,
+
+    /// Unix timestamp of the start of the span, in microseconds.
+    pub start: u64,
+
+    /// Unix timestamp of the end of the span, in microseconds.
+    pub end: u64,
+
+    /// Fields recorded on the span, e.g. the tokens used by an LLM call.
+    pub fields: Map<String, JsonValue>,
+}
+
+/// Spans of an execution, in the order they finished, the root span last.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trace {
+    /// Id of the trace, 32 hexadecimal digits.
+    pub trace_id: String,
+
+    /// The spans of the trace.
+    pub spans: Vec<SpanData>,
+}
+
+impl Trace {
+    /// The root span of the trace.
+    pub fn root(&self) -> Option<&SpanData> {
+        self.spans.iter().find(|span| span.parent_id.is_none())
+    }
+
+    /// The trace in the Chrome trace event format.
+    pub fn to_chrome_json(&self) -> JsonValue {
+        let events: Vec<JsonValue> = self
+            .spans
+            .iter()
+            .map(|span| {
+                json!({
+                    "name": span.name,
+                    "cat": span.target,
+                    "ph": "X",
+                    "ts": span.start,
+                    "dur": span.end.saturating_sub(span.start),
+                    "pid": 1,
+                    "tid": 1,
+                    "args": span.fields,
+                })
+            })
+            .collect();
+        json!({ "traceEvents": events, "displayTimeUnit": "ms", "otherData": { "trace_id": self.trace_id } })
+    }
+
+    /// The trace as an OTLP/JSON export request, with the given service name.
+    pub fn to_otlp_json(&self, service_name: &str) -> JsonValue {
+        let spans: Vec<JsonValue> = self
+            .spans
+            .iter()
+            .map(|span| {
+                let mut otlp = json!({
+                    "traceId": self.trace_id,
+                    "spanId": format!("{:016x}", span.id),
+                    "name": span.name,
+                    "kind": 1,
+                    "startTimeUnixNano": (span.start * 1000).to_string(),
+                    "endTimeUnixNano": (span.end * 1000).to_string(),
+                    "attributes": otlp_attributes(&span.fields),
+                });
+                if let Some(parent_id) = span.parent_id {
+                    otlp["parentSpanId"] = format!("{:016x}", parent_id).into();
+                }
+                otlp
+            })
+            .collect();
+        json!({
+            "resourceSpans": [{
+                "resource": { "attributes": [{ "key": "service.name", "value": { "stringValue": service_name } }] },
+                "scopeSpans": [{ "scope": { "name": "orca" }, "spans": spans }],
+            }]
+        })
+    }
+}
+
+/// Span fields as OTLP attributes.
+fn otlp_attributes(fields: &Map<String, JsonValue>) -> Vec<JsonValue> {
+    fields
+        .iter()
+        .map(|(key, value)| {
+            let value = match value {
+                JsonValue::Bool(value) => json!({ "boolValue": value }),
+                JsonValue::Number(value) if value.is_f64() => json!({ "doubleValue": value }),
+                JsonValue::Number(value) => json!({ "intValue": value.to_string() }),
+                JsonValue::String(value) => json!({ "stringValue": value }),
+                value => json!({ "stringValue": value.to_string() }),
+            };
+            json!({ "key": key, "value": value })
+        })
+        .collect()
+}
+
+/// Destination of the traces collected by a `TraceLayer`.
+pub trait TraceExporter: Send + Sync + 'static {
+    /// Export a finished trace. Called on the thread closing the root span, so slow exports should be
+    /// handed to another task.
+    fn export(&self, trace: Trace);
+}
+
+impl<F: Fn(Trace) + Send + Sync + 'static> TraceExporter for F {
+    fn export(&self, trace: Trace) {
+        self(trace)
+    }
+}
+
+/// Exporter writing each trace to a Chrome trace file, named after the root span and the trace id.
+pub struct ChromeExporter {
+    dir: PathBuf,
+}
+
+impl ChromeExporter {
+    /// Create an exporter writing to the given directory, created if needed.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        ChromeExporter { dir: dir.into() }
+    }
+}
+
+impl TraceExporter for ChromeExporter {
+    fn export(&self, trace: Trace) {
+        let name = trace.root().map_or("trace", |root| root.name.as_str()).replace(['/', '\\'], "_");
+        let path = self.dir.join(format!("trace-{}-{}.json", name, trace.trace_id));
+        let written =
+            std::fs::create_dir_all(&self.dir).and_then(|_| std::fs::write(&path, trace.to_chrome_json().to_string()));
+        if let Err(e) = written {
+            log::warn!("Failed to write trace {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Exporter sending each trace to an OpenTelemetry collector over OTLP/HTTP with JSON encoding. Traces
+/// are sent from a task of the current tokio runtime, and dropped outside of one.
+pub struct OtlpExporter {
+    client: reqwest::Client,
+    endpoint: String,
+    service_name: String,
+    headers: Vec<(String, String)>,
+}
+
+impl OtlpExporter {
+    /// Create an exporter sending to a collector at the given address, e.g. `http://localhost:4318`.
+    pub fn new(endpoint: &str) -> Self {
+        OtlpExporter {
+            client: reqwest::Client::new(),
+            endpoint: format!("{}/v1/traces", endpoint.trim_end_matches('/')),
+            service_name: "orca".to_string(),
+            headers: Vec::new(),
+        }
+    }
+
+    /// Set the service name of the traces, `orca` by default.
+    pub fn with_service_name(mut self, service_name: &str) -> Self {
+        self.service_name = service_name.to_string();
+        self
+    }
+
+    /// Add a header to the export requests, e.g. an API key of the collector.
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+impl TraceExporter for OtlpExporter {
+    fn export(&self, trace: Trace) {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            log::warn!("Dropped trace {}, exported outside of a tokio runtime", trace.trace_id);
+            return;
+        };
+        let request = self.headers.iter().fold(self.client.post(&self.endpoint), |request, (name, value)| {
+            request.header(name.as_str(), value.as_str())
+        });
+        let request = request.json(&trace.to_otlp_json(&self.service_name));
+        runtime.spawn(async move {
+            if let Err(e) = request.send().await.and_then(|response| response.error_for_status()) {
+                log::warn!("Failed to export trace {}: {}", trace.trace_id, e);
+            }
+        });
+    }
+}
+
+/// State of an open span, kept in the extensions of the span.
+struct SpanState {
+    data: SpanData,
+    trace_id: String,
+    finished: Arc<Mutex<Vec<SpanData>>>,
+}
+
+/// Layer collecting the spans under each root span into a trace, exported when the root span closes.
+pub struct TraceLayer {
+    exporter: Arc<dyn TraceExporter>,
+}
+
+impl TraceLayer {
+    /// Create a layer exporting traces with the given exporter.
+    pub fn new<E: TraceExporter>(exporter: E) -> Self {
+        TraceLayer {
+            exporter: Arc::new(exporter),
+        }
+    }
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for TraceLayer {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        let parent = span.parent().and_then(|parent| {
+            let extensions = parent.extensions();
+            let state = extensions.get::<SpanState>()?;
+            Some((state.data.id, state.trace_id.clone(), state.finished.clone()))
+        });
+        let (parent_id, trace_id, finished) = match parent {
+            Some((parent_id, trace_id, finished)) => (Some(parent_id), trace_id, finished),
+            None => (None, uuid::Uuid::new_v4().simple().to_string(), Arc::default()),
+        };
+        let mut data = SpanData {
+            id: NEXT_SPAN_ID.fetch_add(1, Ordering::Relaxed),
+            parent_id,
+            name: attrs.metadata().name().to_string(),
+            target: attrs.metadata().target().to_string(),
+            start: now_micros(),
+            end: 0,
+            fields: Map::new(),
+        };
+        attrs.record(&mut FieldVisitor(&mut data.fields));
+        span.extensions_mut().insert(SpanState {
+            data,
+            trace_id,
+            finished,
+        });
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(state) = span.extensions_mut().get_mut::<SpanState>() {
+                values.record(&mut FieldVisitor(&mut state.data.fields));
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(state) = ctx.span(&id).and_then(|span| span.extensions_mut().remove::<SpanState>()) else {
+            return;
+        };
+        let SpanState {
+            mut data,
+            trace_id,
+            finished,
+        } = state;
+        data.end = now_micros();
+        let is_root = data.parent_id.is_none();
+        let mut spans = finished.lock().unwrap_or_else(|e| e.into_inner());
+        spans.push(data);
+        if is_root {
+            let spans = std::mem::take(&mut *spans);
+            self.exporter.export(Trace { trace_id, spans });
+        }
+    }
+}
+
+/// Set a global subscriber exporting the traces of executions with the given exporter. Fails, rather
+/// than panicking, if a global subscriber is already set, in which case a `TraceLayer` should be added
+/// to that subscriber. Unlike `SubscriberInitExt::init`, this does not redirect `log` records, so it
+/// composes with loggers such as `env_logger`.
+pub fn init<E: TraceExporter>(exporter: E) -> Result<()> {
+    let subscriber = tracing_subscriber::registry().with(TraceLayer::new(exporter));
+    tracing::subscriber::set_global_default(subscriber)
+        .map_err(|e| anyhow!("Failed to set the global tracing subscriber: {}", e))
+}
+
+/// Visitor recording span fields as JSON values.
+struct FieldVisitor<'a>(&'a mut Map<String, JsonValue>);
+
+impl Visit for FieldVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0.insert(field.name().to_string(), format!("{:?}", value).into());
+    }
+}
+
+fn now_micros() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tracing::Instrument;
+
+    #[tokio::test]
+    async fn test_trace() {
+        let traces = Arc::new(Mutex::new(Vec::new()));
+        let collected = traces.clone();
+        let subscriber =
+            tracing_subscriber::registry().with(TraceLayer::new(move |trace| collected.lock().unwrap().push(trace)));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let execution = async {
+            let llm = tracing::info_span!("llm.generate", tokens = tracing::field::Empty);
+            async {
+                tracing::Span::current().record("tokens", 42);
+            }
+            .instrument(llm)
+            .await;
+            tracing::info_span!("retrieval", collection = "orcas").in_scope(|| {});
+        };
+        execution.instrument(tracing::info_span!("pipeline.execute", pipeline = "qa")).await;
+        tracing::info_span!("pipeline.execute", pipeline = "summary").in_scope(|| {});
+
+        let traces = traces.lock().unwrap();
+        assert_eq!(traces.len(), 2);
+        let trace = &traces[0];
+        let names: Vec<&str> = trace.spans.iter().map(|span| span.name.as_str()).collect();
+        assert_eq!(names, ["llm.generate", "retrieval", "pipeline.execute"]);
+        let root = trace.root().unwrap();
+        assert_eq!(root.fields["pipeline"], "qa");
+        assert!(trace.spans[..2].iter().all(|span| span.parent_id == Some(root.id)));
+        assert_eq!(trace.spans[0].fields["tokens"], 42);
+        assert_ne!(traces[1].trace_id, trace.trace_id);
+
+        let chrome = trace.to_chrome_json();
+        assert_eq!(chrome["traceEvents"][0]["ph"], "X");
+        let otlp = trace.to_otlp_json("orca-test");
+        let spans = &otlp["resourceSpans"][0]["scopeSpans"][0]["spans"];
+        assert_eq!(spans[0]["parentSpanId"], spans[2]["spanId"]);
+        assert_eq!(
+            spans[0]["attributes"][0],
+            json!({"key": "tokens", "value": {"intValue": "42"}})
+        );
+    }
+
+    #[test]
+    fn test_init() {
+        init(|_| {}).unwrap();
+        assert!(init(|_| {}).is_err());
+    }
+}
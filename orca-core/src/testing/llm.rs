@@ -0,0 +1,169 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::llm::{LLMResponse, LLM};
+use crate::prompt::Prompt;
+
+/// LLM answering with its prompt, so that tests can check what a pipeline sends to its model.
+///
+/// # Example
+/// ```
+/// use orca_core::llm::LLM;
+/// use orca_core::prompt::chat::ChatPrompt;
+/// use orca_core::testing::EchoLLM;
+///
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// let response = EchoLLM::new().generate(Box::new("Orcas".to_string())).await?;
+/// assert_eq!(response.to_string(), "Orcas");
+///
+/// let chat = ChatPrompt::from_openai_json(
+///     r#"[{"role": "system", "content": "Be brief."}, {"role": "user", "content": "Orcas"}]"#,
+/// )?;
+/// let response = EchoLLM::last_message().generate(Box::new(chat)).await?;
+/// assert_eq!(response.to_string(), "Orcas");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EchoLLM {
+    /// Whether chat prompts are answered with the content of their last message only.
+    last_message: bool,
+}
+
+impl EchoLLM {
+    /// Create an LLM answering with the whole prompt.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an LLM answering chat prompts with the content of their last message, and other prompts
+    /// with the whole prompt.
+    pub fn last_message() -> Self {
+        EchoLLM { last_message: true }
+    }
+}
+
+#[async_trait::async_trait]
+impl LLM for EchoLLM {
+    async fn generate(&self, prompt: Box<dyn Prompt>) -> Result<LLMResponse> {
+        let last = match self.last_message {
+            true => prompt.to_chat().ok().and_then(|chat| chat.to_vec().pop()),
+            false => None,
+        };
+        Ok(LLMResponse::Quantized(
+            last.map(|message| message.content).unwrap_or_else(|| prompt.to_string()),
+        ))
+    }
+}
+
+/// LLM giving the same answer to every prompt and recording the prompts it was given. Clones share the
+/// recorded prompts.
+///
+/// # Example
+/// ```
+/// use orca_core::llm::LLM;
+/// use orca_core::testing::FixedLLM;
+///
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// let llm = FixedLLM::new("Paris");
+/// let response = llm.generate(Box::new("What is the capital of France?".to_string())).await?;
+/// assert_eq!(response.to_string(), "Paris");
+/// assert_eq!(llm.prompts(), vec!["What is the capital of France?"]);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct FixedLLM {
+    /// The answer to every prompt.
+    response: String,
+
+    /// The answer of the deterministic copy of the LLM, if it has one.
+    deterministic: Option<String>,
+
+    /// The prompts given so far.
+    prompts: Arc<Mutex<Vec<String>>>,
+}
+
+impl FixedLLM {
+    /// Create an LLM answering every prompt with `response`.
+    pub fn new(response: &str) -> Self {
+        FixedLLM {
+            response: response.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Make `LLM::deterministic` return a copy answering with `response` instead, e.g. to check that a
+    /// pipeline uses the deterministic copy.
+    pub fn with_deterministic(mut self, response: &str) -> Self {
+        self.deterministic = Some(response.to_string());
+        self
+    }
+
+    /// The prompts given so far, in order.
+    pub fn prompts(&self) -> Vec<String> {
+        self.prompts.lock().unwrap().clone()
+    }
+}
+
+#[async_trait::async_trait]
+impl LLM for FixedLLM {
+    async fn generate(&self, prompt: Box<dyn Prompt>) -> Result<LLMResponse> {
+        self.prompts.lock().unwrap().push(prompt.to_string());
+        Ok(LLMResponse::Quantized(self.response.clone()))
+    }
+
+    fn deterministic(&self) -> Option<Self> {
+        let response = self.deterministic.as_ref()?;
+        Some(FixedLLM {
+            response: response.clone(),
+            deterministic: None,
+            prompts: self.prompts.clone(),
+        })
+    }
+}
+
+/// LLM answering with its prompt after a delay and recording the most calls it had running at once, so
+/// that tests can check how many calls a pipeline makes concurrently. Clones share the counts.
+#[derive(Debug, Clone, Default)]
+pub struct SlowLLM {
+    /// How long each call takes.
+    delay: Duration,
+
+    /// The calls running now.
+    running: Arc<AtomicUsize>,
+
+    /// The most calls running at once so far.
+    max_running: Arc<AtomicUsize>,
+}
+
+impl SlowLLM {
+    /// Create an LLM taking `delay` to answer each prompt.
+    pub fn new(delay: Duration) -> Self {
+        SlowLLM {
+            delay,
+            ..Default::default()
+        }
+    }
+
+    /// The most calls running at once so far.
+    pub fn max_running(&self) -> usize {
+        self.max_running.load(Ordering::SeqCst)
+    }
+}
+
+#[async_trait::async_trait]
+impl LLM for SlowLLM {
+    async fn generate(&self, prompt: Box<dyn Prompt>) -> Result<LLMResponse> {
+        let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_running.fetch_max(running, Ordering::SeqCst);
+        tokio::time::sleep(self.delay).await;
+        self.running.fetch_sub(1, Ordering::SeqCst);
+        Ok(LLMResponse::Quantized(prompt.to_string()))
+    }
+}
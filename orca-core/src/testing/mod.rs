@@ -0,0 +1,239 @@
+//! Embedded fixtures for tests and doctests.
+//!
+//! The fixtures are compiled into the crate, so that tests of pipelines run without network access
+//! or a Qdrant server: a 100-sentence corpus about orcas, salmon, Rust, cooking and astronomy, a small
+//! HTML page and a one-page PDF. `FixtureEmbedder` embeds texts as hashed bags of words with the 384
+//! dimensions of MiniLM, which is deterministic and needs no model weights, `ConstantEmbedder` embeds
+//! every text as the same vector, and `Fixtures` builds a `MemoryStore` preloaded with the embedded
+//! fixtures. `EchoLLM`, `FixedLLM` and `SlowLLM` stand in for models in tests of pipelines.
+//!
+//! # Example
+//! ```
+//! use orca_core::testing::{FixtureEmbedder, Fixtures, FIXTURES_COLLECTION};
+//! use orca_core::llm::Embedding;
+//! use orca_core::vectorstore::{SearchQuery, VectorStore};
+//!
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! let store = Fixtures::new().build().await?;
+//! let query = FixtureEmbedder.generate_embedding(Box::new("Which salmon is the largest?".to_string())).await?;
+//! let hits = store.search(FIXTURES_COLLECTION, SearchQuery::new(query.to_vec()?).with_limit(1)).await?;
+//! let record = hits[0].to_record().unwrap();
+//! assert_eq!(record.content.to_string(), "Chinook salmon are the largest species of Pacific salmon.");
+//! # Ok(())
+//! # }
+//! ```
+
+mod llm;
+
+use anyhow::Result;
+
+pub use llm::{EchoLLM, FixedLLM, SlowLLM};
+
+use crate::hash::fnv1a;
+use crate::llm::{Embedding, Embeddings};
+use crate::math;
+use crate::pipeline::ingest::IngestPipeline;
+use crate::prompt::compress::STOPWORDS;
+use crate::prompt::Prompt;
+use crate::record::html::HTML;
+use crate::record::pdf::Pdf;
+use crate::record::{Content, Record, Spin};
+use crate::vectorstore::memory::MemoryStore;
+
+/// Corpus of 100 sentences, one per line.
+pub const CORPUS: &str = include_str!("fixtures/corpus.txt");
+
+/// HTML page about the orcas of the Salish Sea, with its text in a `main` element.
+pub const HTML_PAGE: &str = include_str!("fixtures/page.html");
+
+/// One-page PDF fact sheet about orcas.
+pub const PDF: &[u8] = include_bytes!("fixtures/facts.pdf");
+
+/// Collection the fixtures are stored in by `Fixtures`.
+pub const FIXTURES_COLLECTION: &str = "fixtures";
+
+/// Number of dimensions of the embeddings of `FixtureEmbedder`, the same as MiniLM.
+pub const FIXTURE_DIMENSIONS: usize = 384;
+
+/// Records of the sentences of the corpus, each with its line number, from 0, in the `index`
+/// attribute.
+pub fn corpus() -> Vec<Record> {
+    CORPUS
+        .lines()
+        .enumerate()
+        .map(|(index, sentence)| Record::new(Content::String(sentence.to_string())).with_attribute("index", index))
+        .collect()
+}
+
+/// The HTML page.
+pub fn html_page() -> HTML {
+    HTML::from_string(HTML_PAGE)
+}
+
+/// The PDF fact sheet.
+pub fn pdf() -> Result<Pdf> {
+    Pdf::from_buffer(PDF.to_vec(), false)
+}
+
+/// Embeds texts as hashed bags of words: each word other than a stopword adds one to a dimension
+/// picked by its hash, and vectors are normalized. Texts sharing words are thus similar, without any
+/// model.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FixtureEmbedder;
+
+impl FixtureEmbedder {
+    /// Embed a text.
+    pub fn embed(text: &str) -> Vec<f32> {
+        let mut vector = vec![0.0; FIXTURE_DIMENSIONS];
+        let words = text.split(|c: char| !c.is_alphanumeric()).map(str::to_lowercase);
+        for word in words.filter(|word| !word.is_empty() && !STOPWORDS.contains(&word.as_str())) {
+            let hash = fnv1a(word.as_bytes());
+            vector[(hash % FIXTURE_DIMENSIONS as u64) as usize] += 1.0;
+        }
+        math::normalize(&mut vector);
+        vector
+    }
+}
+
+#[async_trait::async_trait]
+impl Embedding for FixtureEmbedder {
+    async fn generate_embedding(&self, prompt: Box<dyn Prompt>) -> Result<Embeddings> {
+        self.generate_embeddings(vec![prompt]).await
+    }
+
+    async fn generate_embeddings(&self, prompts: Vec<Box<dyn Prompt>>) -> Result<Embeddings> {
+        let vectors = prompts.iter().map(|prompt| Self::embed(&prompt.to_string())).collect();
+        Embeddings::new("fixtures", vectors)
+    }
+
+    fn dimensions(&self) -> usize {
+        FIXTURE_DIMENSIONS
+    }
+}
+
+/// Embeds every text as the same one-dimensional vector holding the given value, for tests where the
+/// similarity of the texts does not matter, e.g. of batching or retries.
+#[derive(Debug, Clone, Copy)]
+pub struct ConstantEmbedder(pub f32);
+
+#[async_trait::async_trait]
+impl Embedding for ConstantEmbedder {
+    async fn generate_embedding(&self, prompt: Box<dyn Prompt>) -> Result<Embeddings> {
+        self.generate_embeddings(vec![prompt]).await
+    }
+
+    async fn generate_embeddings(&self, prompts: Vec<Box<dyn Prompt>>) -> Result<Embeddings> {
+        Embeddings::new("constant", vec![vec![self.0]; prompts.len()])
+    }
+
+    fn dimensions(&self) -> usize {
+        1
+    }
+}
+
+/// Builds a `MemoryStore` preloaded with the fixtures, embedded with `FixtureEmbedder`.
+#[derive(Debug, Clone)]
+pub struct Fixtures {
+    /// Collection the fixtures are stored in.
+    collection: String,
+
+    /// Whether the sentences of the corpus are stored.
+    corpus: bool,
+
+    /// Whether the HTML page is stored.
+    html: bool,
+
+    /// Whether the PDF is stored.
+    pdf: bool,
+}
+
+impl Default for Fixtures {
+    fn default() -> Self {
+        Fixtures {
+            collection: FIXTURES_COLLECTION.to_string(),
+            corpus: true,
+            html: false,
+            pdf: false,
+        }
+    }
+}
+
+impl Fixtures {
+    /// Create a builder storing the sentences of the corpus in the `fixtures` collection.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the collection the fixtures are stored in.
+    pub fn with_collection(mut self, collection: &str) -> Self {
+        self.collection = collection.to_string();
+        self
+    }
+
+    /// Set whether the sentences of the corpus are stored.
+    pub fn with_corpus(mut self, corpus: bool) -> Self {
+        self.corpus = corpus;
+        self
+    }
+
+    /// Also store the HTML page, as a single record.
+    pub fn with_html(mut self) -> Self {
+        self.html = true;
+        self
+    }
+
+    /// Also store the PDF, as a single record.
+    pub fn with_pdf(mut self) -> Self {
+        self.pdf = true;
+        self
+    }
+
+    /// The records of the selected fixtures.
+    pub fn records(&self) -> Result<Vec<Record>> {
+        let mut records = Vec::new();
+        if self.corpus {
+            records.extend(corpus());
+        }
+        if self.html {
+            records.push(html_page().spin()?.with_attribute("source", "page.html"));
+        }
+        if self.pdf {
+            records.push(pdf()?.spin()?.with_attribute("source", "facts.pdf"));
+        }
+        Ok(records)
+    }
+
+    /// Build a store holding the selected fixtures.
+    pub async fn build(&self) -> Result<MemoryStore> {
+        let store = MemoryStore::new();
+        IngestPipeline::new(&store, &FixtureEmbedder, &self.collection).ingest(self.records()?).await?;
+        Ok(store)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vectorstore::{SearchQuery, VectorStore};
+
+    #[tokio::test]
+    async fn test_fixtures() {
+        assert_eq!(corpus().len(), 100);
+        let store = Fixtures::new().with_collection("test").with_html().build().await.unwrap();
+        assert_eq!(store.len("test"), Some(101));
+
+        let query = SearchQuery::new(FixtureEmbedder::embed("Rust package manager")).with_limit(1);
+        let hits = store.search("test", query).await.unwrap();
+        let record = hits[0].to_record().unwrap();
+        assert_eq!(
+            record.content.to_string(),
+            "Cargo is the package manager and build tool of Rust."
+        );
+        assert_eq!(record.attributes["index"], 42);
+
+        let query = SearchQuery::new(FixtureEmbedder::embed("J, K and L pods")).with_limit(1);
+        let hits = store.search("test", query).await.unwrap();
+        assert_eq!(hits[0].to_record().unwrap().attributes["source"], "page.html");
+    }
+}
@@ -0,0 +1,405 @@
+//! Elasticsearch and OpenSearch vector store, over their REST APIs.
+//!
+//! Each collection is an index whose documents are the payloads of the points, with the vector in a
+//! `vector` field mapped as a `dense_vector` on Elasticsearch or a `knn_vector` on OpenSearch. Vector
+//! searches use the approximate kNN search of each engine; hybrid searches add a BM25 match on the text
+//! field, `content` by default, and `Elasticsearch::bm25` runs the keyword search alone.
+//!
+//! Strings are indexed with the default dynamic mapping, as text with a `keyword` subfield, so filters
+//! matching strings compare against the `keyword` subfield.
+
+use anyhow::{anyhow, Result};
+use reqwest::{Client, RequestBuilder, StatusCode};
+use serde_json::{json, Map, Value as JsonValue};
+
+use super::{Filter, Point, SearchHit, SearchQuery, VectorStore};
+use crate::error::OrcaError;
+
+/// Field holding the vector of a point.
+pub const VECTOR_FIELD: &str = "vector";
+
+/// Search engine behind the REST API, whose vector mappings and queries differ.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Engine {
+    Elasticsearch,
+    OpenSearch,
+}
+
+/// Credentials of a cluster.
+#[derive(Debug, Clone)]
+enum Auth {
+    ApiKey(String),
+    Basic(String, String),
+}
+
+/// Client of an Elasticsearch or OpenSearch cluster.
+#[derive(Clone)]
+pub struct Elasticsearch {
+    client: Client,
+
+    /// Address of the cluster, e.g. `http://localhost:9200`.
+    url: String,
+
+    /// Engine of the cluster.
+    engine: Engine,
+
+    /// Credentials of the cluster.
+    auth: Option<Auth>,
+
+    /// Field matched by keyword searches.
+    text_field: String,
+}
+
+impl Elasticsearch {
+    /// Create a client of the Elasticsearch cluster at the given address.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use orca_core::vectorstore::elasticsearch::Elasticsearch;
+    /// # use orca_core::vectorstore::{Filter, SearchQuery, VectorStore};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let es = Elasticsearch::new("https://localhost:9200").with_api_key("a2V5OnNlY3JldA==");
+    /// let query = SearchQuery::new(vec![0.1, 0.2, 0.3])
+    ///     .with_hybrid("orca pods", 0.7)
+    ///     .with_filter(Filter::Matches("attributes.language".to_string(), "eng".into()));
+    /// let hits = es.search("papers", query).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new(url: &str) -> Self {
+        Elasticsearch {
+            client: Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+            engine: Engine::Elasticsearch,
+            auth: None,
+            text_field: "content".to_string(),
+        }
+    }
+
+    /// Talk to an OpenSearch cluster instead of an Elasticsearch one.
+    pub fn with_opensearch(mut self) -> Self {
+        self.engine = Engine::OpenSearch;
+        self
+    }
+
+    /// Authenticate with an Elasticsearch API key, encoded as returned by the API.
+    pub fn with_api_key(mut self, api_key: &str) -> Self {
+        self.auth = Some(Auth::ApiKey(api_key.to_string()));
+        self
+    }
+
+    /// Authenticate with a user name and password.
+    pub fn with_basic_auth(mut self, user: &str, password: &str) -> Self {
+        self.auth = Some(Auth::Basic(user.to_string(), password.to_string()));
+        self
+    }
+
+    /// Match keyword searches against the given field instead of `content`.
+    pub fn with_text_field(mut self, field: &str) -> Self {
+        self.text_field = field.to_string();
+        self
+    }
+
+    fn request(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.auth {
+            Some(Auth::ApiKey(api_key)) => request.header("Authorization", format!("ApiKey {}", api_key)),
+            Some(Auth::Basic(user, password)) => request.basic_auth(user, Some(password)),
+            None => request,
+        }
+    }
+
+    /// Send a request with a JSON body and return the JSON response.
+    async fn send(&self, request: RequestBuilder) -> Result<JsonValue> {
+        let response = self.request(request).send().await?;
+        let status = response.status();
+        let body: JsonValue = response.json().await?;
+        if !status.is_success() {
+            return Err(OrcaError::VectorStore {
+                store: format!("{:?}", self.engine),
+                message: format!("status {}: {}", status, body["error"]),
+            }
+            .into());
+        }
+        Ok(body)
+    }
+
+    /// Whether an index exists.
+    pub async fn has_index(&self, index: &str) -> Result<bool> {
+        let response = self.request(self.client.head(format!("{}/{}", self.url, index))).send().await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(false),
+            _ => response.error_for_status().map(|_| true).map_err(Into::into),
+        }
+    }
+
+    /// Search an index with BM25 alone, for keyword retrieval or as one side of a hybrid retriever.
+    pub async fn bm25(&self, index: &str, text: &str, limit: usize, filters: &[Filter]) -> Result<Vec<SearchHit>> {
+        let body = json!({
+            "size": limit,
+            "query": { "bool": { "must": self.text_match(text, 1.0), "filter": to_query(filters) } },
+        });
+        let response = self.send(self.client.post(format!("{}/{}/_search", self.url, index)).json(&body)).await?;
+        parse_hits(&response, |score| score)
+    }
+
+    /// Mapping of an index storing vectors of the given number of dimensions.
+    fn index_body(&self, dimensions: usize) -> JsonValue {
+        match self.engine {
+            Engine::Elasticsearch => json!({
+                "mappings": { "properties": {
+                    VECTOR_FIELD: { "type": "dense_vector", "dims": dimensions, "index": true, "similarity": "cosine" },
+                    self.text_field.as_str(): { "type": "text" },
+                }},
+            }),
+            Engine::OpenSearch => json!({
+                "settings": { "index": { "knn": true } },
+                "mappings": { "properties": {
+                    VECTOR_FIELD: {
+                        "type": "knn_vector",
+                        "dimension": dimensions,
+                        "method": { "name": "hnsw", "space_type": "cosinesimil", "engine": "lucene" },
+                    },
+                    self.text_field.as_str(): { "type": "text" },
+                }},
+            }),
+        }
+    }
+
+    /// Body of a kNN search, combined with a BM25 match for hybrid queries. Scores of the two
+    /// searches are summed, weighted by `alpha` and `1 - alpha`.
+    fn search_body(&self, query: &SearchQuery) -> JsonValue {
+        let filter = to_query(&query.filters);
+        let alpha = query.hybrid.as_ref().map(|hybrid| hybrid.alpha).unwrap_or(1.0);
+        let text_match = query.hybrid.as_ref().map(|hybrid| self.text_match(&hybrid.text, 1.0 - alpha));
+        match self.engine {
+            Engine::Elasticsearch => {
+                let mut body = json!({
+                    "size": query.limit,
+                    "knn": {
+                        "field": VECTOR_FIELD,
+                        "query_vector": query.vector,
+                        "k": query.limit,
+                        "num_candidates": (query.limit * 10).max(100),
+                        "filter": filter,
+                        "boost": alpha,
+                    },
+                    "_source": { "excludes": [VECTOR_FIELD] },
+                });
+                if let Some(text_match) = text_match {
+                    body["query"] = json!({ "bool": { "must": text_match, "filter": filter } });
+                }
+                body
+            }
+            Engine::OpenSearch => {
+                let knn = json!({ "knn": { VECTOR_FIELD: {
+                    "vector": query.vector,
+                    "k": query.limit,
+                    "filter": filter,
+                    "boost": alpha,
+                }}});
+                let query_body = match text_match {
+                    Some(text_match) => json!({ "bool": { "should": [knn, { "bool": {
+                        "must": text_match,
+                        "filter": filter,
+                    }}]}}),
+                    None => knn,
+                };
+                json!({ "size": query.limit, "query": query_body, "_source": { "excludes": [VECTOR_FIELD] } })
+            }
+        }
+    }
+
+    fn text_match(&self, text: &str, boost: f32) -> JsonValue {
+        json!({ "match": { self.text_field.as_str(): { "query": text, "boost": boost } } })
+    }
+}
+
+/// Translate filters to a Query DSL `bool` query, all filters having to hold.
+///
+/// # Example
+/// ```
+/// use orca_core::vectorstore::elasticsearch::to_query;
+/// use orca_core::vectorstore::Filter;
+/// use serde_json::json;
+///
+/// let query = to_query(&[Filter::Matches("attributes.venue".to_string(), "ACL".into())]);
+/// assert_eq!(query, json!({"bool": {"filter": [{"term": {"attributes.venue.keyword": "ACL"}}]}}));
+/// ```
+pub fn to_query(filters: &[Filter]) -> JsonValue {
+    json!({ "bool": { "filter": filters.iter().map(filter_query).collect::<Vec<_>>() } })
+}
+
+fn filter_query(filter: &Filter) -> JsonValue {
+    match filter {
+        Filter::Matches(field, value) => json!({ "term": { term_field(field, value): value } }),
+        Filter::MatchesAny(field, values) => {
+            let field = values.first().map(|value| term_field(field, value)).unwrap_or(field.clone());
+            json!({ "terms": { field: values } })
+        }
+        Filter::Range(field, range) => {
+            let bounds: Map<String, JsonValue> = [
+                ("gt", range.gt),
+                ("gte", range.gte),
+                ("lt", range.lt),
+                ("lte", range.lte),
+            ]
+            .into_iter()
+            .filter_map(|(name, bound)| bound.map(|bound| (name.to_string(), bound.into())))
+            .collect();
+            json!({ "range": { field.as_str(): bounds } })
+        }
+        Filter::Not(filter) => json!({ "bool": { "must_not": [filter_query(filter)] } }),
+    }
+}
+
+/// Field compared by a term query: the `keyword` subfield for strings, which are mapped as text.
+fn term_field(field: &str, value: &JsonValue) -> String {
+    match value {
+        JsonValue::String(_) => format!("{}.keyword", field),
+        _ => field.to_string(),
+    }
+}
+
+/// Body of a bulk request indexing points.
+fn bulk_body(index: &str, points: Vec<Point>) -> Result<String> {
+    let mut body = String::new();
+    for point in points {
+        let mut document = point.payload;
+        document.insert(VECTOR_FIELD.to_string(), point.vector.into());
+        body.push_str(&json!({ "index": { "_index": index, "_id": point.id.to_string() } }).to_string());
+        body.push('\n');
+        body.push_str(&serde_json::to_string(&document)?);
+        body.push('\n');
+    }
+    Ok(body)
+}
+
+/// Parse the hits of a search response, converting their scores with the given function.
+fn parse_hits(response: &JsonValue, score: impl Fn(f32) -> f32) -> Result<Vec<SearchHit>> {
+    let hits = response["hits"]["hits"].as_array().ok_or(anyhow!("Unexpected search response: {}", response))?;
+    hits.iter()
+        .map(|hit| {
+            let id = hit["_id"].as_str().ok_or(anyhow!("Hit without an id"))?;
+            let payload = match &hit["_source"] {
+                JsonValue::Object(source) => source.clone(),
+                _ => Map::new(),
+            };
+            Ok(SearchHit {
+                id: id.parse().map_err(|_| anyhow!("Hit {} does not have a numeric id", id))?,
+                score: score(hit["_score"].as_f64().unwrap_or_default() as f32),
+                payload,
+            })
+        })
+        .collect()
+}
+
+#[async_trait::async_trait]
+impl VectorStore for Elasticsearch {
+    async fn ensure_collection(&self, collection: &str, dimensions: usize) -> Result<()> {
+        if !self.has_index(collection).await? {
+            let request = self.client.put(format!("{}/{}", self.url, collection)).json(&self.index_body(dimensions));
+            self.send(request).await?;
+        }
+        Ok(())
+    }
+
+    async fn insert(&self, collection: &str, points: Vec<Point>) -> Result<()> {
+        if points.is_empty() {
+            return Ok(());
+        }
+        // Wait for the refresh, so that the points are searchable once inserted as with the other stores.
+        let request = self
+            .client
+            .post(format!("{}/_bulk?refresh=wait_for", self.url))
+            .header("Content-Type", "application/x-ndjson")
+            .body(bulk_body(collection, points)?);
+        let response = self.send(request).await?;
+        if response["errors"].as_bool().unwrap_or(false) {
+            let error = response["items"]
+                .as_array()
+                .and_then(|items| items.iter().find_map(|item| item["index"].get("error")))
+                .cloned()
+                .unwrap_or_default();
+            return Err(anyhow!("Bulk insert failed: {}", error));
+        }
+        Ok(())
+    }
+
+    /// Search with the kNN search of the engine. Scores of vector searches are the cosine similarity;
+    /// scores of hybrid searches are the weighted sums of the kNN and BM25 scores.
+    async fn search(&self, collection: &str, query: SearchQuery) -> Result<Vec<SearchHit>> {
+        let request = self.client.post(format!("{}/{}/_search", self.url, collection)).json(&self.search_body(&query));
+        let response = self.send(request).await?;
+        let hits = match query.hybrid {
+            // Both engines score cosine similarity as (1 + cosine) / 2.
+            None => parse_hits(&response, |score| 2.0 * score - 1.0)?,
+            Some(_) => parse_hits(&response, |score| score)?,
+        };
+        Ok(match query.score_threshold {
+            Some(threshold) => hits.into_iter().filter(|hit| hit.score >= threshold).collect(),
+            None => hits,
+        })
+    }
+
+    async fn delete_collection(&self, collection: &str) -> Result<()> {
+        self.send(self.client.delete(format!("{}/{}", self.url, collection))).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::qdrant::Range;
+
+    #[test]
+    fn test_to_query() {
+        let filters = vec![
+            Filter::MatchesAny("year".to_string(), vec![2022.into(), 2023.into()]),
+            Filter::Not(Box::new(Filter::Range(
+                "attributes.page".to_string(),
+                Range {
+                    lt: Some(3.0),
+                    ..Default::default()
+                },
+            ))),
+        ];
+        assert_eq!(
+            to_query(&filters),
+            json!({"bool": {"filter": [
+                {"terms": {"year": [2022, 2023]}},
+                {"bool": {"must_not": [{"range": {"attributes.page": {"lt": 3.0}}}]}}
+            ]}})
+        );
+    }
+
+    #[test]
+    fn test_search_body() {
+        let query = SearchQuery::new(vec![0.5]).with_limit(5).with_hybrid("orca", 0.75);
+        let body = Elasticsearch::new("http://localhost:9200").search_body(&query);
+        assert_eq!(body["knn"]["k"], 5);
+        assert_eq!(body["knn"]["boost"], 0.75);
+        assert_eq!(body["query"]["bool"]["must"]["match"]["content"]["boost"], 0.25);
+
+        let body = Elasticsearch::new("http://localhost:9200")
+            .with_opensearch()
+            .search_body(&SearchQuery::new(vec![0.5]));
+        assert_eq!(body["query"]["knn"]["vector"]["vector"], json!([0.5]));
+        assert!(body.get("knn").is_none());
+    }
+
+    #[test]
+    fn test_bulk_and_hits() {
+        let point = Point::new(7, vec![0.5], json!({"content": "Orcas"})).unwrap();
+        assert_eq!(
+            bulk_body("papers", vec![point]).unwrap(),
+            "{\"index\":{\"_id\":\"7\",\"_index\":\"papers\"}}\n{\"content\":\"Orcas\",\"vector\":[0.5]}\n"
+        );
+
+        let response = json!({"hits": {"hits": [{"_id": "7", "_score": 0.75, "_source": {"content": "Orcas"}}]}});
+        let hits = parse_hits(&response, |score| 2.0 * score - 1.0).unwrap();
+        assert_eq!((hits[0].id, hits[0].score), (7, 0.5));
+        assert_eq!(hits[0].payload["content"], "Orcas");
+    }
+}
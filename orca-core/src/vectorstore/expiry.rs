@@ -0,0 +1,206 @@
+//! Expiry of stored points.
+//!
+//! Indexes of fast-staling content, like news, should forget points after a while. None of the
+//! supported stores expire points natively, so `ExpiringStore` wraps a store with a convention: each
+//! inserted point gets an `expires_at` payload field, a Unix timestamp in seconds, searches skip the
+//! points past it, and `sweep` deletes them, periodically when run by `spawn_sweeper`.
+
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use serde_json::Value as JsonValue;
+use tokio::task::JoinHandle;
+
+use super::{Filter, Point, SearchHit, SearchQuery, VectorStore};
+use crate::llm::Precision;
+use crate::qdrant::{FieldIndex, Range};
+
+/// Payload field holding the expiry of a point, in seconds since the Unix epoch.
+pub const EXPIRES_AT_FIELD: &str = "expires_at";
+
+/// Store whose points expire a fixed time after they are inserted.
+pub struct ExpiringStore<S: ?Sized> {
+    /// The wrapped store.
+    store: Arc<S>,
+
+    /// Time a point lives after it is inserted.
+    ttl: Duration,
+
+    /// Payload field holding the expiry of the points.
+    field: String,
+}
+
+impl<S: VectorStore + ?Sized + 'static> ExpiringStore<S> {
+    /// Wrap a store, expiring its points `ttl` after they are inserted. Points inserted with an expiry
+    /// of their own keep it, and points without one, e.g. inserted before the store was wrapped, never
+    /// expire.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use orca_core::qdrant::Qdrant;
+    /// # use orca_core::vectorstore::expiry::ExpiringStore;
+    /// # use std::sync::Arc;
+    /// # use std::time::Duration;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let qdrant = Arc::new(Qdrant::new("http://localhost:6334").unwrap());
+    /// let news = Arc::new(ExpiringStore::new(qdrant, Duration::from_secs(2 * 24 * 60 * 60)));
+    /// let _sweeper = news.spawn_sweeper(vec!["news".to_string()], Duration::from_secs(60 * 60));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new(store: Arc<S>, ttl: Duration) -> Self {
+        ExpiringStore {
+            store,
+            ttl,
+            field: EXPIRES_AT_FIELD.to_string(),
+        }
+    }
+
+    /// Set the payload field holding the expiry of the points, `expires_at` by default.
+    pub fn with_field(mut self, field: &str) -> Self {
+        self.field = field.to_string();
+        self
+    }
+
+    /// The wrapped store.
+    pub fn inner(&self) -> &S {
+        &self.store
+    }
+
+    /// Delete the expired points of a collection.
+    pub async fn sweep(&self, collection: &str) -> Result<()> {
+        let expired = Range {
+            lte: Some(now()),
+            ..Default::default()
+        };
+        self.store.delete_by_filter(collection, vec![Filter::Range(self.field.clone(), expired)]).await
+    }
+
+    /// Sweep the given collections at the given interval, until the returned handle is dropped. Failed
+    /// sweeps are logged and retried at the next interval.
+    pub fn spawn_sweeper(self: &Arc<Self>, collections: Vec<String>, interval: Duration) -> Sweeper {
+        let store = self.clone();
+        let task = tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            loop {
+                ticks.tick().await;
+                for collection in &collections {
+                    if let Err(e) = store.sweep(collection).await {
+                        log::warn!(
+                            "Failed to delete the expired points of collection {}: {}",
+                            collection,
+                            e
+                        );
+                    }
+                }
+            }
+        });
+        Sweeper { task }
+    }
+}
+
+/// Handle on a task sweeping expired points, stopped when the handle is dropped.
+pub struct Sweeper {
+    task: JoinHandle<()>,
+}
+
+impl Drop for Sweeper {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[async_trait::async_trait]
+impl<S: VectorStore + ?Sized + 'static> VectorStore for ExpiringStore<S> {
+    /// Create the collection and index the expiry field, so that searches and sweeps stay fast.
+    async fn ensure_collection(&self, collection: &str, dimensions: usize) -> Result<()> {
+        self.store.ensure_collection(collection, dimensions).await?;
+        self.store.create_field_index(collection, &self.field, FieldIndex::Float).await
+    }
+
+    async fn ensure_collection_with_precision(
+        &self,
+        collection: &str,
+        dimensions: usize,
+        precision: Precision,
+    ) -> Result<()> {
+        self.store.ensure_collection_with_precision(collection, dimensions, precision).await?;
+        self.store.create_field_index(collection, &self.field, FieldIndex::Float).await
+    }
+
+    async fn create_field_index(&self, collection: &str, field: &str, index: FieldIndex) -> Result<()> {
+        self.store.create_field_index(collection, field, index).await
+    }
+
+    /// Insert points, setting the expiry of those without one.
+    async fn insert(&self, collection: &str, mut points: Vec<Point>) -> Result<()> {
+        let expires_at = JsonValue::from(now() + self.ttl.as_secs_f64());
+        for point in &mut points {
+            point.payload.entry(self.field.clone()).or_insert_with(|| expires_at.clone());
+        }
+        self.store.insert(collection, points).await
+    }
+
+    /// Search the points that have not expired yet, swept or not.
+    async fn search(&self, collection: &str, mut query: SearchQuery) -> Result<Vec<SearchHit>> {
+        let expired = Range {
+            lte: Some(now()),
+            ..Default::default()
+        };
+        query.filters.push(Filter::Not(Box::new(Filter::Range(self.field.clone(), expired))));
+        self.store.search(collection, query).await
+    }
+
+    async fn delete_collection(&self, collection: &str) -> Result<()> {
+        self.store.delete_collection(collection).await
+    }
+
+    async fn delete_by_filter(&self, collection: &str, filters: Vec<Filter>) -> Result<()> {
+        self.store.delete_by_filter(collection, filters).await
+    }
+
+    async fn switch_alias(&self, alias: &str, collection: &str) -> Result<Option<String>> {
+        self.store.switch_alias(alias, collection).await
+    }
+}
+
+fn now() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vectorstore::memory::MemoryStore;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_expiry() {
+        let memory = Arc::new(MemoryStore::new());
+        let store = Arc::new(ExpiringStore::new(memory.clone(), Duration::from_secs(3600)));
+        store.ensure_collection("news", 1).await.unwrap();
+        let points = vec![
+            Point::new(1, vec![1.0], json!({"title": "fresh"})).unwrap(),
+            Point::new(2, vec![1.0], json!({"title": "stale", "expires_at": now() - 1.0})).unwrap(),
+        ];
+        store.insert("news", points).await.unwrap();
+        memory
+            .insert(
+                "news",
+                vec![Point::new(3, vec![1.0], json!({"title": "legacy"})).unwrap()],
+            )
+            .await
+            .unwrap();
+
+        let titles = |hits: Vec<SearchHit>| hits.iter().map(|hit| hit.payload["title"].clone()).collect::<Vec<_>>();
+        let hits = store.search("news", SearchQuery::new(vec![1.0])).await.unwrap();
+        assert_eq!(titles(hits), [json!("fresh"), json!("legacy")]);
+        assert_eq!(memory.len("news"), Some(3));
+
+        let _sweeper = store.spawn_sweeper(vec!["news".to_string()], Duration::from_secs(3600));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(memory.len("news"), Some(2));
+    }
+}
@@ -0,0 +1,364 @@
+//! Hierarchical navigable small world graphs, the approximate nearest neighbor index of `MemoryStore`.
+//!
+//! Each vector is a node of a stack of proximity graphs: every node is on the bottom layer, and each layer
+//! above holds an exponentially decreasing share of the nodes. A search descends greedily from the top
+//! layer, then explores the `ef` best candidates of the bottom layer, which finds most of the nearest
+//! neighbors while comparing the query to a small fraction of the vectors. See Malkov and Yashunin,
+//! "Efficient and robust approximate nearest neighbor search using Hierarchical Navigable Small World
+//! graphs" (2016).
+//!
+//! Vectors are normalized when inserted, so that the similarity of two nodes is their dot product, equal
+//! to the cosine similarity of the original vectors. Removed vectors stay in the graph as tombstones,
+//! which keep it connected but are never returned, until the index is rebuilt.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use super::Point;
+use crate::math::{dot, normalize};
+
+/// Parameters of an HNSW index.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct HnswConfig {
+    /// Number of neighbors of each node on the upper layers, twice as many on the bottom layer. Higher
+    /// values improve recall on high-dimensional vectors, at the cost of memory and insertion time.
+    pub m: usize,
+
+    /// Number of candidates explored when inserting a vector. Higher values build a better graph, more
+    /// slowly.
+    pub ef_construction: usize,
+
+    /// Number of candidates explored when searching, raised to the limit of the search if lower. Higher
+    /// values improve recall, at the cost of search time.
+    pub ef_search: usize,
+}
+
+impl Default for HnswConfig {
+    fn default() -> Self {
+        HnswConfig {
+            m: 16,
+            ef_construction: 200,
+            ef_search: 64,
+        }
+    }
+}
+
+/// Vector of the index, with its neighbors on each layer it is on.
+#[derive(Debug, Clone, Deserialize)]
+struct Node {
+    /// Identifier of the point.
+    id: u64,
+
+    /// Normalized vector. Not serialized for live nodes, whose vectors are restored from the points.
+    #[serde(default)]
+    vector: Vec<f32>,
+
+    /// Indexes of the neighbors of the node on each layer, from the bottom one.
+    layers: Vec<Vec<u32>>,
+
+    /// Whether the point was removed.
+    deleted: bool,
+}
+
+impl Serialize for Node {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct NodeRef<'a> {
+            id: u64,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            vector: Option<&'a [f32]>,
+            layers: &'a [Vec<u32>],
+            deleted: bool,
+        }
+        NodeRef {
+            id: self.id,
+            vector: self.deleted.then_some(self.vector.as_slice()),
+            layers: &self.layers,
+            deleted: self.deleted,
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Node with its similarity to a vector, ordered by similarity.
+#[derive(Debug, Clone, Copy)]
+struct Scored {
+    score: f32,
+    node: u32,
+}
+
+impl PartialEq for Scored {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score.total_cmp(&other.score).then(other.node.cmp(&self.node))
+    }
+}
+
+/// HNSW index of the vectors of a collection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Hnsw {
+    config: HnswConfig,
+
+    nodes: Vec<Node>,
+
+    /// Index of the live node of each point.
+    positions: HashMap<u64, u32>,
+
+    /// Node the searches start from, on the top layer.
+    entry: Option<u32>,
+
+    /// State of the generator of the layers of the nodes, so that an index is built the same way every time.
+    seed: u64,
+}
+
+impl Hnsw {
+    /// Create an empty index.
+    pub(crate) fn new(config: HnswConfig) -> Self {
+        Hnsw {
+            config,
+            nodes: Vec::new(),
+            positions: HashMap::new(),
+            entry: None,
+            seed: 0x9e3779b97f4a7c15,
+        }
+    }
+
+    /// Parameters of the index.
+    pub(crate) fn config(&self) -> HnswConfig {
+        self.config
+    }
+
+    /// Identifiers of the points of the index.
+    pub(crate) fn ids(&self) -> impl Iterator<Item = u64> + '_ {
+        self.positions.keys().copied()
+    }
+
+    /// Number of nodes of removed points.
+    pub(crate) fn tombstones(&self) -> usize {
+        self.nodes.len() - self.positions.len()
+    }
+
+    /// Restore the vectors of the live nodes from the points of the collection, after deserialization.
+    pub(crate) fn restore_vectors(&mut self, points: &BTreeMap<u64, Point>) {
+        for (id, &position) in &self.positions {
+            if let Some(point) = points.get(id) {
+                let node = &mut self.nodes[position as usize];
+                node.vector = point.vector.clone();
+                normalize(&mut node.vector);
+            }
+        }
+    }
+
+    /// Insert a vector, replacing the vector of the point if it was already inserted.
+    pub(crate) fn insert(&mut self, id: u64, vector: &[f32]) {
+        self.remove(id);
+        let mut vector = vector.to_vec();
+        normalize(&mut vector);
+        let level = self.random_level();
+        let index = self.nodes.len() as u32;
+        self.nodes.push(Node {
+            id,
+            vector,
+            layers: vec![Vec::new(); level + 1],
+            deleted: false,
+        });
+        self.positions.insert(id, index);
+        let Some(entry) = self.entry else {
+            self.entry = Some(index);
+            return;
+        };
+
+        let query = self.nodes[index as usize].vector.clone();
+        let top = self.level(entry);
+        let mut entries = vec![entry];
+        for layer in (level + 1..=top).rev() {
+            entries = vec![self.search_layer(&query, &entries, 1, layer)[0].node];
+        }
+        for layer in (0..=level.min(top)).rev() {
+            let found = self.search_layer(&query, &entries, self.config.ef_construction, layer);
+            let neighbors = found.iter().take(self.max_neighbors(layer)).map(|scored| scored.node).collect::<Vec<_>>();
+            for &neighbor in &neighbors {
+                self.connect(neighbor, index, layer);
+            }
+            self.nodes[index as usize].layers[layer] = neighbors;
+            entries = found.iter().map(|scored| scored.node).collect();
+        }
+        if level > top {
+            self.entry = Some(index);
+        }
+    }
+
+    /// Remove the vector of a point, leaving its node as a tombstone.
+    pub(crate) fn remove(&mut self, id: u64) {
+        if let Some(position) = self.positions.remove(&id) {
+            self.nodes[position as usize].deleted = true;
+        }
+    }
+
+    /// Approximate nearest points to a vector, with their cosine similarity, the most similar first.
+    /// Returns up to `ef_search`, or `limit` if higher, points.
+    pub(crate) fn search(&self, vector: &[f32], limit: usize) -> Vec<(u64, f32)> {
+        let Some(entry) = self.entry else {
+            return Vec::new();
+        };
+        let mut query = vector.to_vec();
+        normalize(&mut query);
+        let mut entries = vec![entry];
+        for layer in (1..=self.level(entry)).rev() {
+            entries = vec![self.search_layer(&query, &entries, 1, layer)[0].node];
+        }
+        self.search_layer(&query, &entries, self.config.ef_search.max(limit), 0)
+            .into_iter()
+            .map(|scored| &self.nodes[scored.node as usize])
+            .filter(|node| !node.deleted)
+            .map(|node| (node.id, dot(&query, &node.vector)))
+            .collect()
+    }
+
+    /// Top layer of a node.
+    fn level(&self, node: u32) -> usize {
+        self.nodes[node as usize].layers.len() - 1
+    }
+
+    /// Maximum number of neighbors of a node on a layer.
+    fn max_neighbors(&self, layer: usize) -> usize {
+        match layer {
+            0 => self.config.m * 2,
+            _ => self.config.m,
+        }
+    }
+
+    /// Draw the top layer of a new node, from an exponential distribution so that each layer holds about
+    /// `1 / m` of the nodes of the layer below.
+    fn random_level(&mut self) -> usize {
+        // SplitMix64.
+        self.seed = self.seed.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^= z >> 31;
+        let uniform = (z >> 11) as f64 / (1u64 << 53) as f64;
+        let scale = 1.0 / (self.config.m.max(2) as f64).ln();
+        (-(1.0 - uniform).ln() * scale) as usize
+    }
+
+    /// Link a node to a new neighbor on a layer, dropping its least similar neighbor if it has too many.
+    fn connect(&mut self, node: u32, neighbor: u32, layer: usize) {
+        let max = self.max_neighbors(layer);
+        let neighbors = &self.nodes[node as usize].layers[layer];
+        if neighbors.len() < max {
+            self.nodes[node as usize].layers[layer].push(neighbor);
+            return;
+        }
+        let vector = &self.nodes[node as usize].vector;
+        let mut scored = neighbors
+            .iter()
+            .chain([&neighbor])
+            .map(|&other| Scored {
+                score: dot(vector, &self.nodes[other as usize].vector),
+                node: other,
+            })
+            .collect::<Vec<_>>();
+        scored.sort_unstable_by(|a, b| b.cmp(a));
+        self.nodes[node as usize].layers[layer] = scored.into_iter().take(max).map(|scored| scored.node).collect();
+    }
+
+    /// Explore a layer from the entry nodes, returning the `ef` nodes found most similar to the query, the
+    /// most similar first.
+    fn search_layer(&self, query: &[f32], entries: &[u32], ef: usize, layer: usize) -> Vec<Scored> {
+        let ef = ef.max(1);
+        let score = |node: u32| Scored {
+            score: dot(query, &self.nodes[node as usize].vector),
+            node,
+        };
+        let mut visited = entries.iter().copied().collect::<HashSet<_>>();
+        let mut candidates = entries.iter().map(|&node| score(node)).collect::<BinaryHeap<_>>();
+        let mut found = candidates.iter().map(|&scored| Reverse(scored)).collect::<BinaryHeap<_>>();
+        while found.len() > ef {
+            found.pop();
+        }
+        while let Some(candidate) = candidates.pop() {
+            let worst = found.peek().map(|Reverse(worst)| *worst);
+            if worst.is_some_and(|worst| candidate < worst) && found.len() >= ef {
+                break;
+            }
+            for &neighbor in &self.nodes[candidate.node as usize].layers[layer] {
+                if !visited.insert(neighbor) {
+                    continue;
+                }
+                let scored = score(neighbor);
+                let worst = found.peek().map(|Reverse(worst)| *worst);
+                if found.len() < ef || worst.is_some_and(|worst| scored > worst) {
+                    candidates.push(scored);
+                    found.push(Reverse(scored));
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+        let mut found = found.into_iter().map(|Reverse(scored)| scored).collect::<Vec<_>>();
+        found.sort_unstable_by(|a, b| b.cmp(a));
+        found
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::math::cosine;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn test_hnsw() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let vectors = (0..1000)
+            .map(|_| (0..32).map(|_| rng.gen_range(-1.0..1.0)).collect::<Vec<f32>>())
+            .collect::<Vec<_>>();
+        let config = HnswConfig {
+            m: 8,
+            ef_construction: 100,
+            ef_search: 50,
+        };
+        let mut index = Hnsw::new(config);
+        for (id, vector) in vectors.iter().enumerate() {
+            index.insert(id as u64, vector);
+        }
+
+        let mut recalled = 0;
+        for query in vectors.iter().take(50) {
+            let mut exact = (0..vectors.len()).map(|id| (id as u64, cosine(query, &vectors[id]))).collect::<Vec<_>>();
+            exact.sort_by(|a, b| b.1.total_cmp(&a.1));
+            let found = index.search(query, 10).into_iter().take(10).map(|(id, _)| id).collect::<HashSet<_>>();
+            recalled += exact.iter().take(10).filter(|(id, _)| found.contains(id)).count();
+        }
+        assert!(recalled >= 450, "recall of {} / 500", recalled);
+
+        let (id, score) = index.search(&vectors[7], 1)[0];
+        assert_eq!(id, 7);
+        assert!((score - 1.0).abs() < 1e-5);
+
+        // Replaced and removed points are never returned.
+        index.insert(7, &vectors[8]);
+        index.remove(8);
+        let hits = index.search(&vectors[8], 10);
+        assert_eq!(hits[0].0, 7);
+        assert!(hits.iter().all(|(id, _)| *id != 8));
+        assert_eq!(index.tombstones(), 2);
+    }
+}
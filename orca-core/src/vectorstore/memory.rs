@@ -0,0 +1,405 @@
+//! In-memory vector store.
+//!
+//! `MemoryStore` keeps its collections in process memory and searches them exhaustively by cosine
+//! similarity. It suits tests, small corpora, and serving a snapshot of a collection while the main
+//! store is unreachable (see `Qdrant::with_read_fallback`). With `MemoryStore::with_hnsw`, collections
+//! are also indexed in an HNSW graph, which keeps searches fast on hundreds of thousands of vectors at
+//! the cost of approximate results, and `MemoryStore::save` snapshots the store, index included, for
+//! warm restarts.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::RwLock;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value as JsonValue};
+
+use super::hnsw::{Hnsw, HnswConfig};
+use super::{Filter, Point, SearchHit, SearchQuery, VectorStore};
+use crate::math::cosine;
+
+/// How many times the limit of a filtered search is taken from the index, so that enough of the points
+/// found match the filters without scanning the collection.
+pub const FILTER_OVERSAMPLING: usize = 4;
+
+/// Collection of a `MemoryStore`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Collection {
+    dimensions: usize,
+    points: BTreeMap<u64, Point>,
+
+    /// Approximate nearest neighbor index of the points, if the store indexes its collections.
+    #[serde(default)]
+    index: Option<Hnsw>,
+}
+
+/// Vector store keeping its collections in memory.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    collections: RwLock<HashMap<String, Collection>>,
+
+    /// Collections pointed to by aliases.
+    aliases: RwLock<HashMap<String, String>>,
+
+    /// Parameters of the index of the collections created, `None` to search them exhaustively.
+    index: Option<HnswConfig>,
+}
+
+/// Contents of a store saved by `MemoryStore::save`.
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    index: Option<HnswConfig>,
+    aliases: &'a HashMap<String, String>,
+    collections: &'a HashMap<String, Collection>,
+}
+
+/// Contents of a store loaded by `MemoryStore::load`.
+#[derive(Deserialize)]
+struct Snapshot {
+    index: Option<HnswConfig>,
+    aliases: HashMap<String, String>,
+    collections: HashMap<String, Collection>,
+}
+
+impl MemoryStore {
+    /// Create an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Index the collections created from now on in an HNSW graph with the given parameters, searched
+    /// instead of scanning every point. Filtered searches take `FILTER_OVERSAMPLING` times more points from
+    /// the index, and fall back to a scan if too few of them match the filters.
+    ///
+    /// # Example
+    /// ```
+    /// use orca_core::vectorstore::hnsw::HnswConfig;
+    /// use orca_core::vectorstore::memory::MemoryStore;
+    ///
+    /// let store = MemoryStore::new().with_hnsw(HnswConfig { ef_search: 128, ..Default::default() });
+    /// ```
+    pub fn with_hnsw(mut self, config: HnswConfig) -> Self {
+        self.index = Some(config);
+        self
+    }
+
+    /// Save the collections, aliases and indexes of the store to a JSON file. The file is replaced
+    /// atomically, so that a store interrupted while saving leaves the previous snapshot intact.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let temporary = path.with_extension("tmp");
+        let aliases = self.aliases.read().unwrap();
+        let collections = self.collections.read().unwrap();
+        let snapshot = SnapshotRef {
+            index: self.index,
+            aliases: &aliases,
+            collections: &collections,
+        };
+        let mut writer = BufWriter::new(File::create(&temporary)?);
+        serde_json::to_writer(&mut writer, &snapshot)?;
+        writer.flush()?;
+        std::fs::rename(temporary, path)?;
+        Ok(())
+    }
+
+    /// Load a store saved by `MemoryStore::save`, without rebuilding its indexes.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let snapshot: Snapshot = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        let mut collections = snapshot.collections;
+        for collection in collections.values_mut() {
+            if let Some(index) = &mut collection.index {
+                index.restore_vectors(&collection.points);
+            }
+        }
+        Ok(MemoryStore {
+            collections: RwLock::new(collections),
+            aliases: RwLock::new(snapshot.aliases),
+            index: snapshot.index,
+        })
+    }
+
+    /// Number of points in a collection, `None` if the collection does not exist.
+    pub fn len(&self, collection: &str) -> Option<usize> {
+        let collection = self.resolve(collection);
+        let collections = self.collections.read().unwrap();
+        collections.get(&collection).map(|collection| collection.points.len())
+    }
+
+    /// Number of dimensions of the vectors of a collection, `None` if the collection does not exist.
+    pub fn dimensions(&self, collection: &str) -> Option<usize> {
+        let collection = self.resolve(collection);
+        let collections = self.collections.read().unwrap();
+        collections.get(&collection).map(|collection| collection.dimensions)
+    }
+
+    /// The collection a name refers to, following aliases.
+    fn resolve(&self, name: &str) -> String {
+        let aliases = self.aliases.read().unwrap();
+        aliases.get(name).cloned().unwrap_or_else(|| name.to_string())
+    }
+}
+
+#[async_trait::async_trait]
+impl VectorStore for MemoryStore {
+    async fn ensure_collection(&self, collection: &str, dimensions: usize) -> Result<()> {
+        let collection = &self.resolve(collection);
+        let mut collections = self.collections.write().unwrap();
+        let existing = collections.entry(collection.to_string()).or_insert_with(|| Collection {
+            dimensions,
+            points: BTreeMap::new(),
+            index: self.index.map(Hnsw::new),
+        });
+        if existing.dimensions != dimensions {
+            return Err(anyhow!(
+                "Collection {} stores vectors of {} dimensions, not {}",
+                collection,
+                existing.dimensions,
+                dimensions
+            ));
+        }
+        Ok(())
+    }
+
+    async fn insert(&self, collection: &str, points: Vec<Point>) -> Result<()> {
+        let collection = &self.resolve(collection);
+        let mut collections = self.collections.write().unwrap();
+        let existing =
+            collections.get_mut(collection).ok_or_else(|| anyhow!("Collection {} does not exist", collection))?;
+        if let Some(point) = points.iter().find(|point| point.vector.len() != existing.dimensions) {
+            return Err(anyhow!(
+                "Point {} has {} dimensions, but collection {} has {}",
+                point.id,
+                point.vector.len(),
+                collection,
+                existing.dimensions
+            ));
+        }
+        if let Some(index) = &mut existing.index {
+            points.iter().for_each(|point| index.insert(point.id, &point.vector));
+        }
+        existing.points.extend(points.into_iter().map(|point| (point.id, point)));
+        Ok(())
+    }
+
+    async fn search(&self, collection: &str, query: SearchQuery) -> Result<Vec<SearchHit>> {
+        let collection = &self.resolve(collection);
+        let collections = self.collections.read().unwrap();
+        let existing =
+            collections.get(collection).ok_or_else(|| anyhow!("Collection {} does not exist", collection))?;
+        let selected = |point: &Point| query.filters.iter().all(|filter| matches(filter, &point.payload));
+        let hit = |point: &Point, score: f32| SearchHit {
+            id: point.id,
+            score,
+            payload: point.payload.clone(),
+        };
+        let candidates = match query.filters.is_empty() {
+            true => query.limit,
+            false => query.limit.saturating_mul(FILTER_OVERSAMPLING),
+        };
+        let mut hits: Vec<SearchHit> = match &existing.index {
+            Some(index) => index
+                .search(&query.vector, candidates)
+                .into_iter()
+                .filter_map(|(id, score)| existing.points.get(&id).map(|point| (point, score)))
+                .filter(|(point, _)| selected(point))
+                .map(|(point, score)| hit(point, score))
+                .collect(),
+            None => Vec::new(),
+        };
+        if existing.index.is_none() || (hits.len() < query.limit && !query.filters.is_empty()) {
+            hits = existing
+                .points
+                .values()
+                .filter(|point| selected(point))
+                .map(|point| hit(point, cosine(&query.vector, &point.vector)))
+                .collect();
+        }
+        hits.retain(|hit| query.score_threshold.map_or(true, |threshold| hit.score >= threshold));
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hits.truncate(query.limit);
+        Ok(hits)
+    }
+
+    async fn delete_collection(&self, collection: &str) -> Result<()> {
+        let mut aliases = self.aliases.write().unwrap();
+        let collection = aliases.get(collection).cloned().unwrap_or_else(|| collection.to_string());
+        self.collections.write().unwrap().remove(&collection);
+        aliases.retain(|_, target| *target != collection);
+        Ok(())
+    }
+
+    async fn delete_by_filter(&self, collection: &str, filters: Vec<Filter>) -> Result<()> {
+        let collection = &self.resolve(collection);
+        let mut collections = self.collections.write().unwrap();
+        let existing =
+            collections.get_mut(collection).ok_or_else(|| anyhow!("Collection {} does not exist", collection))?;
+        existing.points.retain(|_, point| !filters.iter().all(|filter| matches(filter, &point.payload)));
+        if let Some(index) = &mut existing.index {
+            let removed = index.ids().filter(|id| !existing.points.contains_key(id)).collect::<Vec<_>>();
+            removed.into_iter().for_each(|id| index.remove(id));
+            // Tombstones slow down searches, so the index is rebuilt once they outnumber the points.
+            if index.tombstones() > existing.points.len() {
+                let mut rebuilt = Hnsw::new(index.config());
+                existing.points.values().for_each(|point| rebuilt.insert(point.id, &point.vector));
+                *index = rebuilt;
+            }
+        }
+        Ok(())
+    }
+
+    async fn switch_alias(&self, alias: &str, collection: &str) -> Result<Option<String>> {
+        let mut aliases = self.aliases.write().unwrap();
+        let mut collections = self.collections.write().unwrap();
+        if !collections.contains_key(collection) {
+            return Err(anyhow!("Collection {} does not exist", collection));
+        }
+        if !aliases.contains_key(alias) {
+            collections.remove(alias);
+        }
+        Ok(aliases.insert(alias.to_string(), collection.to_string()))
+    }
+}
+
+/// Whether a payload satisfies a filter. Fields missing from the payload match no condition.
+fn matches(filter: &Filter, payload: &Map<String, JsonValue>) -> bool {
+    let field = |path: &str| {
+        let mut parts = path.split('.');
+        let first = payload.get(parts.next()?)?;
+        parts.try_fold(first, |value, part| value.get(part))
+    };
+    match filter {
+        Filter::Matches(path, expected) => field(path) == Some(expected),
+        Filter::MatchesAny(path, expected) => field(path).is_some_and(|value| expected.contains(value)),
+        Filter::Range(path, range) => field(path).and_then(JsonValue::as_f64).is_some_and(|value| {
+            range.gt.map_or(true, |gt| value > gt)
+                && range.gte.map_or(true, |gte| value >= gte)
+                && range.lt.map_or(true, |lt| value < lt)
+                && range.lte.map_or(true, |lte| value <= lte)
+        }),
+        Filter::Not(inner) => !matches(inner, payload),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::qdrant::Range;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_search() {
+        let store = MemoryStore::new();
+        store.ensure_collection("orcas", 2).await.unwrap();
+        let points = vec![
+            Point::new(1, vec![1.0, 0.0], json!({"pod": "J", "attributes": {"age": 40}})).unwrap(),
+            Point::new(2, vec![0.7, 0.7], json!({"pod": "K", "attributes": {"age": 12}})).unwrap(),
+            Point::new(3, vec![0.0, 1.0], json!({"pod": "L", "attributes": {"age": 25}})).unwrap(),
+        ];
+        store.insert("orcas", points).await.unwrap();
+        assert_eq!(store.len("orcas"), Some(3));
+        assert!(store.insert("orcas", vec![Point::new(4, vec![1.0], "x").unwrap()]).await.is_err());
+        assert!(store.ensure_collection("orcas", 3).await.is_err());
+
+        let hits = store.search("orcas", SearchQuery::new(vec![1.0, 0.0]).with_limit(2)).await.unwrap();
+        assert_eq!(hits.iter().map(|hit| hit.id).collect::<Vec<_>>(), [1, 2]);
+
+        let range = Range {
+            gte: Some(20.0),
+            ..Default::default()
+        };
+        let query = SearchQuery::new(vec![1.0, 0.0])
+            .with_filter(Filter::Range("attributes.age".to_string(), range))
+            .with_filter(Filter::Not(Box::new(Filter::Matches("pod".to_string(), json!("J")))));
+        let hits = store.search("orcas", query).await.unwrap();
+        assert_eq!(hits.iter().map(|hit| hit.id).collect::<Vec<_>>(), [3]);
+
+        let young = Filter::Range(
+            "attributes.age".to_string(),
+            Range {
+                lt: Some(30.0),
+                ..Default::default()
+            },
+        );
+        store.delete_by_filter("orcas", vec![young]).await.unwrap();
+        assert_eq!(store.len("orcas"), Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_alias() {
+        let store = MemoryStore::new();
+        store.ensure_collection("orcas_v1", 1).await.unwrap();
+        store.ensure_collection("orcas_v2", 1).await.unwrap();
+        store.insert("orcas_v2", vec![Point::new(1, vec![1.0], "J").unwrap()]).await.unwrap();
+        assert!(store.switch_alias("orcas", "orcas_v3").await.is_err());
+
+        assert_eq!(store.switch_alias("orcas", "orcas_v1").await.unwrap(), None);
+        assert_eq!(store.len("orcas"), Some(0));
+        assert_eq!(
+            store.switch_alias("orcas", "orcas_v2").await.unwrap().as_deref(),
+            Some("orcas_v1")
+        );
+        let hits = store.search("orcas", SearchQuery::new(vec![1.0])).await.unwrap();
+        assert_eq!(hits[0].payload["value"], "J");
+
+        store.ensure_collection("whales", 1).await.unwrap();
+        assert_eq!(store.switch_alias("whales", "orcas_v1").await.unwrap(), None);
+        assert!(!store.collections.read().unwrap().contains_key("whales"));
+
+        // Deleting through an alias deletes its collection and the aliases pointing to it.
+        store.delete_collection("whales").await.unwrap();
+        assert_eq!(store.len("orcas_v1"), None);
+        assert_eq!(store.len("orcas"), Some(1));
+        assert!(!store.aliases.read().unwrap().contains_key("whales"));
+    }
+
+    #[tokio::test]
+    async fn test_hnsw() {
+        let store = MemoryStore::new().with_hnsw(HnswConfig {
+            m: 4,
+            ef_construction: 32,
+            ef_search: 8,
+        });
+        store.ensure_collection("orcas", 2).await.unwrap();
+        let points = (0..200)
+            .map(|i| {
+                let angle = i as f32 / 100.0;
+                Point::new(
+                    i,
+                    vec![angle.cos(), angle.sin()],
+                    json!({"pod": if i == 150 { "L" } else { "J" }}),
+                )
+                .unwrap()
+            })
+            .collect();
+        store.insert("orcas", points).await.unwrap();
+        let query = || SearchQuery::new(vec![1.0, 0.0]).with_limit(3);
+        let ids = |hits: Vec<SearchHit>| hits.iter().map(|hit| hit.id).collect::<Vec<_>>();
+        assert_eq!(ids(store.search("orcas", query()).await.unwrap()), [0, 1, 2]);
+
+        // Filters that no nearby point matches fall back to a scan.
+        let pod = |pod: &str| Filter::Matches("pod".to_string(), json!(pod));
+        assert_eq!(
+            ids(store.search("orcas", query().with_filter(pod("L"))).await.unwrap()),
+            [150]
+        );
+
+        store.delete_by_filter("orcas", vec![pod("J")]).await.unwrap();
+        assert_eq!(ids(store.search("orcas", query()).await.unwrap()), [150]);
+
+        store.insert("orcas", vec![Point::new(7, vec![1.0, 0.1], "K").unwrap()]).await.unwrap();
+        let path = std::env::temp_dir().join(format!("orca-memory-{}.json", uuid::Uuid::new_v4()));
+        store.save(&path).unwrap();
+        let loaded = MemoryStore::load(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(
+            loaded.search("orcas", query()).await.unwrap(),
+            store.search("orcas", query()).await.unwrap()
+        );
+        assert!(loaded.collections.read().unwrap()["orcas"].index.is_some());
+        loaded.ensure_collection("dolphins", 2).await.unwrap();
+        assert!(loaded.collections.read().unwrap()["dolphins"].index.is_some());
+    }
+}
@@ -0,0 +1,385 @@
+//! Milvus and Zilliz Cloud vector store, over the Milvus RESTful API (v2).
+//!
+//! Collections are created with an `Int64` primary key named `id`, a float vector field named
+//! `vector` compared by cosine similarity, and dynamic fields holding the payload of each point. Points
+//! with ids above `i64::MAX`, or with payload keys named like the fields of the collection or the
+//! `distance` of the search hits, are rejected.
+//! Searches can be filtered with `Filter`s, which are translated to Milvus boolean expressions, or
+//! with raw expressions through `Milvus::search_expr`. Inserts and searches can be scoped to a
+//! partition of the collection.
+
+use anyhow::{anyhow, Result};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value as JsonValue};
+
+use super::{Filter, Point, SearchHit, SearchQuery, VectorStore};
+use crate::error::OrcaError;
+
+/// Name of the primary key field.
+pub const ID_FIELD: &str = "id";
+
+/// Name of the vector field.
+pub const VECTOR_FIELD: &str = "vector";
+
+/// Name of the similarity of the hits of a search.
+const DISTANCE_FIELD: &str = "distance";
+
+/// Keys that the payload of a point cannot have, since Milvus stores it next to these fields.
+const RESERVED_FIELDS: [&str; 3] = [ID_FIELD, VECTOR_FIELD, DISTANCE_FIELD];
+
+/// Envelope of the responses of the Milvus RESTful API.
+#[derive(Deserialize, Debug)]
+struct MilvusResponse {
+    code: i64,
+
+    #[serde(default)]
+    message: Option<String>,
+
+    #[serde(default)]
+    data: JsonValue,
+}
+
+impl MilvusResponse {
+    /// The data of a successful response, or the error message of a failed one.
+    fn into_data(self) -> Result<JsonValue> {
+        match self.code {
+            0 | 200 => Ok(self.data),
+            code => Err(OrcaError::VectorStore {
+                store: "Milvus".to_string(),
+                message: format!("code {}: {}", code, self.message.unwrap_or_default()),
+            }
+            .into()),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SearchPayload<'a> {
+    collection_name: &'a str,
+    data: Vec<&'a [f32]>,
+    anns_field: &'a str,
+    limit: usize,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    filter: &'a str,
+    output_fields: Vec<&'a str>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    partition_names: Vec<&'a str>,
+}
+
+/// Client of a Milvus or Zilliz Cloud deployment.
+#[derive(Clone)]
+pub struct Milvus {
+    client: Client,
+
+    /// Address of the deployment, e.g. `http://localhost:19530`.
+    url: String,
+
+    /// Token, either `user:password` or a Zilliz Cloud API key.
+    token: Option<String>,
+
+    /// Database holding the collections.
+    database: Option<String>,
+
+    /// Partition inserts and searches are scoped to.
+    partition: Option<String>,
+}
+
+impl Milvus {
+    /// Create a client of the deployment at the given address.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use orca_core::vectorstore::milvus::Milvus;
+    /// # use orca_core::vectorstore::{Filter, SearchQuery, VectorStore};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let milvus = Milvus::new("http://localhost:19530").with_token("root:Milvus");
+    /// let query = SearchQuery::new(vec![0.1, 0.2, 0.3])
+    ///     .with_limit(5)
+    ///     .with_filter(Filter::Matches("attributes.language".to_string(), "eng".into()));
+    /// let hits = milvus.search("papers", query).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new(url: &str) -> Self {
+        Milvus {
+            client: Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+            token: None,
+            database: None,
+            partition: None,
+        }
+    }
+
+    /// Authenticate with a token, either `user:password` or a Zilliz Cloud API key.
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.token = Some(token.to_string());
+        self
+    }
+
+    /// Use the given database instead of the default one.
+    pub fn with_database(mut self, database: &str) -> Self {
+        self.database = Some(database.to_string());
+        self
+    }
+
+    /// Scope inserts and searches to a partition, e.g. one per tenant or per source. The partition
+    /// must have been created with `create_partition`.
+    pub fn with_partition(mut self, partition: &str) -> Self {
+        self.partition = Some(partition.to_string());
+        self
+    }
+
+    /// Send a request to an endpoint of the RESTful API and return the data of the response.
+    async fn post(&self, endpoint: &str, mut body: JsonValue) -> Result<JsonValue> {
+        if let (Some(database), JsonValue::Object(body)) = (&self.database, &mut body) {
+            body.insert("dbName".to_string(), database.clone().into());
+        }
+        let mut request = self.client.post(format!("{}/v2/vectordb/{}", self.url, endpoint)).json(&body);
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        let response: MilvusResponse = request.send().await?.error_for_status()?.json().await?;
+        response.into_data()
+    }
+
+    /// Whether a collection exists.
+    pub async fn has_collection(&self, collection: &str) -> Result<bool> {
+        let data = self.post("collections/has", json!({ "collectionName": collection })).await?;
+        Ok(data["has"].as_bool().unwrap_or(false))
+    }
+
+    /// Create a partition of a collection, if it does not exist.
+    pub async fn create_partition(&self, collection: &str, partition: &str) -> Result<()> {
+        let body = json!({ "collectionName": collection, "partitionName": partition });
+        let data = self.post("partitions/has", body.clone()).await?;
+        if !data["has"].as_bool().unwrap_or(false) {
+            self.post("partitions/create", body).await?;
+        }
+        Ok(())
+    }
+
+    /// Search a collection with a raw Milvus boolean expression, e.g.
+    /// `attributes["year"] >= 2020 and attributes["venue"] in ["ACL", "EMNLP"]`.
+    pub async fn search_expr(
+        &self,
+        collection: &str,
+        vector: &[f32],
+        limit: usize,
+        expression: &str,
+    ) -> Result<Vec<SearchHit>> {
+        let payload = SearchPayload {
+            collection_name: collection,
+            data: vec![vector],
+            anns_field: VECTOR_FIELD,
+            limit,
+            filter: expression,
+            output_fields: vec!["*"],
+            partition_names: self.partition.iter().map(String::as_str).collect(),
+        };
+        let data = self.post("entities/search", serde_json::to_value(payload)?).await?;
+        parse_hits(data)
+    }
+}
+
+/// Translate filters to a Milvus boolean expression, all filters having to hold.
+///
+/// # Example
+/// ```
+/// use orca_core::qdrant::Range;
+/// use orca_core::vectorstore::milvus::to_expression;
+/// use orca_core::vectorstore::Filter;
+///
+/// let filters = vec![
+///     Filter::Matches("attributes.venue".to_string(), "ACL".into()),
+///     Filter::Range("attributes.year".to_string(), Range { gte: Some(2020.0), ..Default::default() }),
+/// ];
+/// assert_eq!(to_expression(&filters), r#"attributes["venue"] == "ACL" and attributes["year"] >= 2020"#);
+/// ```
+pub fn to_expression(filters: &[Filter]) -> String {
+    filters.iter().map(filter_expression).collect::<Vec<_>>().join(" and ")
+}
+
+fn filter_expression(filter: &Filter) -> String {
+    match filter {
+        Filter::Matches(field, value) => format!("{} == {}", field_expression(field), value),
+        Filter::MatchesAny(field, values) => {
+            format!("{} in {}", field_expression(field), JsonValue::from(values.clone()))
+        }
+        Filter::Range(field, range) => {
+            let field = field_expression(field);
+            let bounds = [(">", range.gt), (">=", range.gte), ("<", range.lt), ("<=", range.lte)];
+            let conditions: Vec<String> = bounds
+                .iter()
+                .filter_map(|(operator, bound)| bound.map(|bound| format!("{} {} {}", field, operator, bound)))
+                .collect();
+            match conditions.len() {
+                0 => "true".to_string(),
+                1 => conditions.concat(),
+                _ => format!("({})", conditions.join(" and ")),
+            }
+        }
+        Filter::Not(filter) => format!("not ({})", filter_expression(filter)),
+    }
+}
+
+/// Expression of a dotted field path, keys of JSON fields being given in brackets.
+fn field_expression(field: &str) -> String {
+    let mut parts = field.split('.');
+    let mut expression = parts.next().unwrap_or_default().to_string();
+    for part in parts {
+        expression.push_str(&format!("[{}]", JsonValue::from(part)));
+    }
+    expression
+}
+
+/// Parse the hits of a search response, whose `distance` is the cosine similarity.
+fn parse_hits(data: JsonValue) -> Result<Vec<SearchHit>> {
+    let hits = match data {
+        JsonValue::Array(hits) => hits,
+        _ => return Err(anyhow!("Unexpected Milvus search response: {}", data)),
+    };
+    hits.into_iter()
+        .map(|hit| {
+            let mut payload = match hit {
+                JsonValue::Object(payload) => payload,
+                hit => return Err(anyhow!("Unexpected Milvus search hit: {}", hit)),
+            };
+            let id = payload.remove(ID_FIELD).and_then(|id| id.as_u64()).ok_or(anyhow!("Hit without an id"))?;
+            let score = payload.remove(DISTANCE_FIELD).and_then(|score| score.as_f64()).unwrap_or_default() as f32;
+            payload.remove(VECTOR_FIELD);
+            Ok(SearchHit { id, score, payload })
+        })
+        .collect()
+}
+
+/// Entity of a point, with its payload as dynamic fields.
+fn to_entity(point: Point) -> Result<Map<String, JsonValue>> {
+    let rejected = |message: String| -> anyhow::Error {
+        OrcaError::VectorStore {
+            store: "Milvus".to_string(),
+            message,
+        }
+        .into()
+    };
+    let id = i64::try_from(point.id)
+        .map_err(|_| rejected(format!("Point id {} does not fit in an Int64 primary key", point.id)))?;
+    if let Some(field) = RESERVED_FIELDS.iter().find(|field| point.payload.contains_key(**field)) {
+        return Err(rejected(format!(
+            "Payload of point {} has a {} key, which is reserved",
+            point.id, field
+        )));
+    }
+    let mut entity = point.payload;
+    entity.insert(ID_FIELD.to_string(), id.into());
+    entity.insert(VECTOR_FIELD.to_string(), point.vector.into());
+    Ok(entity)
+}
+
+#[async_trait::async_trait]
+impl VectorStore for Milvus {
+    async fn ensure_collection(&self, collection: &str, dimensions: usize) -> Result<()> {
+        if self.has_collection(collection).await? {
+            return Ok(());
+        }
+        let body = json!({
+            "collectionName": collection,
+            "dimension": dimensions,
+            "metricType": "COSINE",
+            "primaryFieldName": ID_FIELD,
+            "idType": "Int64",
+            "vectorFieldName": VECTOR_FIELD,
+            "autoId": false,
+        });
+        self.post("collections/create", body).await?;
+        Ok(())
+    }
+
+    async fn insert(&self, collection: &str, points: Vec<Point>) -> Result<()> {
+        let data = points.into_iter().map(to_entity).collect::<Result<Vec<_>>>()?;
+        let mut body = json!({ "collectionName": collection, "data": data });
+        if let Some(partition) = &self.partition {
+            body["partitionName"] = partition.clone().into();
+        }
+        // Upsert, so that points with existing ids are replaced as with the other stores.
+        self.post("entities/upsert", body).await?;
+        Ok(())
+    }
+
+    async fn search(&self, collection: &str, query: SearchQuery) -> Result<Vec<SearchHit>> {
+        let hits = self.search_expr(collection, &query.vector, query.limit, &to_expression(&query.filters)).await?;
+        Ok(match query.score_threshold {
+            Some(threshold) => hits.into_iter().filter(|hit| hit.score >= threshold).collect(),
+            None => hits,
+        })
+    }
+
+    async fn delete_collection(&self, collection: &str) -> Result<()> {
+        self.post("collections/drop", json!({ "collectionName": collection })).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::qdrant::Range;
+
+    #[test]
+    fn test_to_expression() {
+        let filters = vec![
+            Filter::MatchesAny("attributes.venue".to_string(), vec!["ACL".into(), "EMNLP".into()]),
+            Filter::Range(
+                "year".to_string(),
+                Range {
+                    gt: Some(2019.0),
+                    lte: Some(2023.5),
+                    ..Default::default()
+                },
+            ),
+            Filter::Not(Box::new(Filter::Matches("draft".to_string(), true.into()))),
+        ];
+        assert_eq!(
+            to_expression(&filters),
+            r#"attributes["venue"] in ["ACL","EMNLP"] and (year > 2019 and year <= 2023.5) and not (draft == true)"#
+        );
+        assert_eq!(to_expression(&[]), "");
+    }
+
+    #[test]
+    fn test_to_entity() {
+        let entity = to_entity(Point::new(7, vec![0.5], json!({"content": "Orcas"})).unwrap()).unwrap();
+        assert_eq!(
+            JsonValue::Object(entity),
+            json!({"id": 7, "vector": [0.5], "content": "Orcas"})
+        );
+
+        assert!(to_entity(Point::new(u64::MAX, vec![0.5], "Orcas").unwrap()).is_err());
+        for key in RESERVED_FIELDS {
+            let point = Point::new(7, vec![0.5], Map::from_iter([(key.to_string(), json!(1))])).unwrap();
+            assert!(matches!(
+                to_entity(point).unwrap_err().downcast_ref::<OrcaError>(),
+                Some(OrcaError::VectorStore { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_parse_hits() {
+        let response: MilvusResponse = serde_json::from_value(json!({
+            "code": 0,
+            "data": [{"id": 3, "distance": 0.92, "content": "Orcas live in pods.", "attributes": {"page": 2}}]
+        }))
+        .unwrap();
+        let hits = parse_hits(response.into_data().unwrap()).unwrap();
+        assert_eq!(hits[0].id, 3);
+        assert!((hits[0].score - 0.92).abs() < 1e-6);
+        assert_eq!(hits[0].to_record().unwrap().attributes["page"], 2);
+
+        let error: MilvusResponse =
+            serde_json::from_value(json!({"code": 1100, "message": "collection not found"})).unwrap();
+        assert!(error.into_data().unwrap_err().to_string().contains("collection not found"));
+    }
+}
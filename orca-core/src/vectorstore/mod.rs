@@ -0,0 +1,250 @@
+//! Vector stores behind a common interface.
+//!
+//! The `VectorStore` trait lets pipelines store and search embeddings without depending on a specific
+//! database. Points carry a JSON payload, usually a serialized `Record`, and searches are filtered with
+//! backend-neutral `Filter`s that each backend translates to its own query language. Qdrant implements
+//! the trait in the `qdrant` module; other backends live in submodules of this one.
+
+pub mod elasticsearch;
+pub mod expiry;
+pub mod hnsw;
+pub mod memory;
+pub mod milvus;
+pub mod weaviate;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value as JsonValue};
+
+use crate::llm::Precision;
+use crate::pipeline::assembler::SCORE_ATTRIBUTE;
+use crate::qdrant::{FieldIndex, Range};
+use crate::record::Record;
+
+/// Point stored in a vector store.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Point {
+    pub id: u64,
+    pub vector: Vec<f32>,
+    pub payload: Map<String, JsonValue>,
+}
+
+impl Point {
+    /// Create a point whose payload is the serialized value. Values that are not objects are stored
+    /// under the `value` key.
+    pub fn new<T: Serialize>(id: u64, vector: Vec<f32>, payload: T) -> Result<Self> {
+        let payload = match serde_json::to_value(payload)? {
+            JsonValue::Object(map) => map,
+            value => Map::from_iter([("value".to_string(), value)]),
+        };
+        Ok(Point { id, vector, payload })
+    }
+}
+
+/// Point found by a search, with its similarity to the query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    pub id: u64,
+    pub score: f32,
+    pub payload: Map<String, JsonValue>,
+}
+
+impl SearchHit {
+    /// Deserialize the payload as a `Record`, if the point was inserted from one. The similarity score
+    /// is set as the `score` attribute of the record.
+    pub fn to_record(&self) -> Option<Record> {
+        let record: Record = serde_json::from_value(JsonValue::Object(self.payload.clone())).ok()?;
+        Some(record.with_attribute(SCORE_ATTRIBUTE, self.score))
+    }
+}
+
+/// Condition on the payload of the points, with nested fields given as dotted paths, e.g.
+/// `attributes.page`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    /// The field equals the value.
+    Matches(String, JsonValue),
+
+    /// The field equals any of the values.
+    MatchesAny(String, Vec<JsonValue>),
+
+    /// The numeric field is within the given bounds.
+    Range(String, Range),
+
+    /// The inner condition does not hold.
+    Not(Box<Filter>),
+}
+
+/// Search for the points nearest to a vector.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchQuery {
+    pub vector: Vec<f32>,
+
+    /// Maximum number of results.
+    pub limit: usize,
+
+    /// Conditions the results must all satisfy.
+    pub filters: Vec<Filter>,
+
+    /// Minimum similarity score of the results.
+    pub score_threshold: Option<f32>,
+
+    /// Keyword query combined with the vector search, for stores supporting hybrid search.
+    pub hybrid: Option<Hybrid>,
+}
+
+/// Keyword (BM25) query combined with a vector search.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hybrid {
+    /// Text matched against the stored documents.
+    pub text: String,
+
+    /// Weight of the vector search in the combined score, from 0 (keywords only) to 1 (vector only).
+    pub alpha: f32,
+}
+
+impl SearchQuery {
+    /// Create a query returning the 10 points nearest to the vector.
+    pub fn new(vector: Vec<f32>) -> Self {
+        SearchQuery {
+            vector,
+            limit: 10,
+            filters: Vec::new(),
+            score_threshold: None,
+            hybrid: None,
+        }
+    }
+
+    /// Set the maximum number of results.
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Add a condition the results must satisfy.
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    /// Drop results with a similarity score below the threshold.
+    pub fn with_score_threshold(mut self, score_threshold: f32) -> Self {
+        self.score_threshold = Some(score_threshold);
+        self
+    }
+
+    /// Combine the vector search with a keyword search on the text, weighting the vector search by
+    /// `alpha` between 0 and 1. Stores without keyword search ignore the text.
+    pub fn with_hybrid(mut self, text: &str, alpha: f32) -> Self {
+        self.hybrid = Some(Hybrid {
+            text: text.to_string(),
+            alpha: alpha.clamp(0.0, 1.0),
+        });
+        self
+    }
+}
+
+/// Error of a request to a vector store that could not be reached, after retrying. Stores fail with it
+/// wrapped in an `OrcaError::VectorStoreUnavailable`, so that pipelines can tell an outage of the store
+/// from an invalid request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VectorStoreUnavailable {
+    /// Name of the store, e.g. `qdrant`.
+    pub store: String,
+
+    /// The failed operation, e.g. `search`.
+    pub operation: String,
+
+    /// Number of attempts made.
+    pub attempts: u32,
+
+    /// The error of the last attempt.
+    pub reason: String,
+}
+
+impl std::fmt::Display for VectorStoreUnavailable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Vector store {} is unavailable, {} failed after {} attempts: {}",
+            self.store, self.operation, self.attempts, self.reason
+        )
+    }
+}
+
+impl std::error::Error for VectorStoreUnavailable {}
+
+/// Database storing embeddings and searching them by similarity.
+#[async_trait::async_trait]
+pub trait VectorStore: Send + Sync {
+    /// Create a collection storing vectors of the given number of dimensions, if it does not exist.
+    async fn ensure_collection(&self, collection: &str, dimensions: usize) -> Result<()>;
+
+    /// Create a collection storing embeddings of the given precision, if it does not exist. Stores
+    /// without quantization store them at full precision.
+    async fn ensure_collection_with_precision(
+        &self,
+        collection: &str,
+        dimensions: usize,
+        _precision: Precision,
+    ) -> Result<()> {
+        self.ensure_collection(collection, dimensions).await
+    }
+
+    /// Index a payload field, given as a dotted path, so that searches filtering on it stay fast.
+    /// Stores that index every field ignore it.
+    async fn create_field_index(&self, _collection: &str, _field: &str, _index: FieldIndex) -> Result<()> {
+        Ok(())
+    }
+
+    /// Insert points into a collection, replacing existing points with the same ids.
+    async fn insert(&self, collection: &str, points: Vec<Point>) -> Result<()>;
+
+    /// Search a collection for the points nearest to a vector, most similar first.
+    async fn search(&self, collection: &str, query: SearchQuery) -> Result<Vec<SearchHit>>;
+
+    /// Delete a collection and its points.
+    async fn delete_collection(&self, collection: &str) -> Result<()>;
+
+    /// Delete the points of a collection matching all the filters.
+    async fn delete_by_filter(&self, collection: &str, _filters: Vec<Filter>) -> Result<()> {
+        Err(anyhow!(
+            "This vector store does not support deleting points of collection {}",
+            collection
+        ))
+    }
+
+    /// Atomically point an alias to a collection, creating the alias if needed, and return the
+    /// collection it pointed to before. Searches and inserts can then use the alias in place of the
+    /// collection name. A collection named like the alias is deleted and replaced by the alias.
+    async fn switch_alias(&self, alias: &str, _collection: &str) -> Result<Option<String>> {
+        Err(anyhow!(
+            "This vector store does not support aliases, cannot switch alias {}",
+            alias
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::record::Content;
+    use serde_json::json;
+
+    #[test]
+    fn test_point_and_hit() {
+        let record = Record::new(Content::String("Orcas live in pods.".to_string())).with_attribute("page", 2);
+        let point = Point::new(7, vec![0.1, 0.2], &record).unwrap();
+        assert_eq!(point.payload["attributes"], json!({"page": 2}));
+        assert_eq!(Point::new(1, vec![], "text").unwrap().payload["value"], "text");
+
+        let hit = SearchHit {
+            id: point.id,
+            score: 0.75,
+            payload: point.payload,
+        };
+        let found = hit.to_record().unwrap();
+        assert_eq!(found.content, record.content);
+        assert_eq!(found.attributes[SCORE_ATTRIBUTE], json!(0.75));
+    }
+}
@@ -0,0 +1,430 @@
+//! Weaviate vector store, over the Weaviate REST and GraphQL APIs.
+//!
+//! Each collection is a Weaviate class, named after the collection with its first letter capitalized
+//! as Weaviate requires. Points are stored as objects whose UUID is derived from the point id, with
+//! the full payload kept as JSON in the `payload` property and its fields flattened into properties
+//! of their own, e.g. `attributes_page` for `attributes.page`, so that they can be filtered on and
+//! searched with BM25. Searches are either vector searches or, when the query has a keyword part,
+//! hybrid searches fusing BM25 and vector scores.
+//!
+//! A client scoped to a tenant with `Weaviate::with_tenant` uses multi-tenant classes, with each
+//! tenant's objects stored in a shard of its own.
+
+use anyhow::{anyhow, Result};
+use reqwest::{Client, RequestBuilder, StatusCode};
+use serde_json::{json, Map, Value as JsonValue};
+use uuid::Uuid;
+
+use super::{Filter, Hybrid, Point, SearchHit, SearchQuery, VectorStore};
+use crate::error::OrcaError;
+
+/// Property holding the payload of a point as JSON.
+pub const PAYLOAD_PROPERTY: &str = "payload";
+
+/// Client of a Weaviate instance.
+#[derive(Clone)]
+pub struct Weaviate {
+    client: Client,
+
+    /// Address of the instance, e.g. `http://localhost:8080`.
+    url: String,
+
+    /// API key of the instance.
+    api_key: Option<String>,
+
+    /// Tenant all requests are scoped to.
+    tenant: Option<String>,
+}
+
+impl Weaviate {
+    /// Create a client of the instance at the given address.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use orca_core::vectorstore::weaviate::Weaviate;
+    /// # use orca_core::vectorstore::{Filter, SearchQuery, VectorStore};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let weaviate = Weaviate::new("http://localhost:8080").with_tenant("acme");
+    /// let query = SearchQuery::new(vec![0.1, 0.2, 0.3])
+    ///     .with_hybrid("orca pods", 0.5)
+    ///     .with_filter(Filter::Matches("attributes.language".to_string(), "eng".into()));
+    /// let hits = weaviate.search("papers", query).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new(url: &str) -> Self {
+        Weaviate {
+            client: Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+            api_key: None,
+            tenant: None,
+        }
+    }
+
+    /// Authenticate with an API key.
+    pub fn with_api_key(mut self, api_key: &str) -> Self {
+        self.api_key = Some(api_key.to_string());
+        self
+    }
+
+    /// Scope all requests to a tenant. Collections are created as multi-tenant classes, and the
+    /// tenant is added to them by `ensure_collection`.
+    pub fn with_tenant(mut self, tenant: &str) -> Self {
+        self.tenant = Some(tenant.to_string());
+        self
+    }
+
+    fn request(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.api_key {
+            Some(api_key) => request.bearer_auth(api_key),
+            None => request,
+        }
+    }
+
+    /// Whether the class of a collection exists.
+    pub async fn has_collection(&self, collection: &str) -> Result<bool> {
+        let url = format!("{}/v1/schema/{}", self.url, class_name(collection));
+        let response = self.request(self.client.get(url)).send().await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(false),
+            _ => response.error_for_status().map(|_| true).map_err(Into::into),
+        }
+    }
+
+    /// Add the tenant of the client to a multi-tenant collection, if it is not there yet.
+    async fn ensure_tenant(&self, collection: &str, tenant: &str) -> Result<()> {
+        let url = format!("{}/v1/schema/{}/tenants", self.url, class_name(collection));
+        let tenants: Vec<JsonValue> =
+            self.request(self.client.get(&url)).send().await?.error_for_status()?.json().await?;
+        if !tenants.iter().any(|existing| existing["name"] == tenant) {
+            let body = json!([{ "name": tenant }]);
+            self.request(self.client.post(&url).json(&body)).send().await?.error_for_status()?;
+        }
+        Ok(())
+    }
+
+    /// Run a GraphQL query and return its data.
+    async fn graphql(&self, query: String) -> Result<JsonValue> {
+        let url = format!("{}/v1/graphql", self.url);
+        let request = self.client.post(url).json(&json!({ "query": query }));
+        let response: JsonValue = self.request(request).send().await?.error_for_status()?.json().await?;
+        if let Some(errors) = response.get("errors") {
+            return Err(OrcaError::VectorStore {
+                store: "Weaviate".to_string(),
+                message: format!("query failed: {}", errors),
+            }
+            .into());
+        }
+        Ok(response["data"].clone())
+    }
+
+    /// GraphQL query of a search.
+    fn search_query(&self, collection: &str, query: &SearchQuery) -> Result<String> {
+        let mut arguments = vec![format!("limit: {}", query.limit)];
+        let vector = JsonValue::from(query.vector.clone());
+        match &query.hybrid {
+            Some(Hybrid { text, alpha }) => arguments.push(format!(
+                "hybrid: {{query: {}, vector: {}, alpha: {}}}",
+                JsonValue::from(text.as_str()),
+                vector,
+                alpha
+            )),
+            None => {
+                let distance = query
+                    .score_threshold
+                    .map(|threshold| format!(", distance: {}", 1.0 - threshold))
+                    .unwrap_or_default();
+                arguments.push(format!("nearVector: {{vector: {}{}}}", vector, distance))
+            }
+        }
+        if !query.filters.is_empty() {
+            arguments.push(format!("where: {}", to_where(&query.filters)?));
+        }
+        if let Some(tenant) = &self.tenant {
+            arguments.push(format!("tenant: {}", JsonValue::from(tenant.as_str())));
+        }
+        Ok(format!(
+            "{{ Get {{ {}({}) {{ {} _additional {{ id distance score }} }} }} }}",
+            class_name(collection),
+            arguments.join(", "),
+            PAYLOAD_PROPERTY
+        ))
+    }
+}
+
+/// Name of the class of a collection, which must start with an uppercase letter.
+///
+/// # Example
+/// ```
+/// use orca_core::vectorstore::weaviate::class_name;
+///
+/// assert_eq!(class_name("papers"), "Papers");
+/// ```
+pub fn class_name(collection: &str) -> String {
+    let mut chars = collection.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Name of the property holding a payload field given as a dotted path.
+fn property_name(field: &str) -> String {
+    field.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect()
+}
+
+/// Flatten the nested objects of a payload into properties, e.g. `attributes.page` into
+/// `attributes_page`.
+fn flatten(prefix: &str, payload: &Map<String, JsonValue>, properties: &mut Map<String, JsonValue>) {
+    for (key, value) in payload {
+        let name = match prefix {
+            "" => property_name(key),
+            prefix => format!("{}_{}", prefix, property_name(key)),
+        };
+        match value {
+            JsonValue::Object(object) => flatten(&name, object, properties),
+            JsonValue::Null => {}
+            value => {
+                properties.insert(name, value.clone());
+            }
+        }
+    }
+}
+
+/// Properties of the object storing a point.
+fn properties(payload: &Map<String, JsonValue>) -> Map<String, JsonValue> {
+    let mut properties = Map::new();
+    flatten("", payload, &mut properties);
+    properties.insert(
+        PAYLOAD_PROPERTY.to_string(),
+        JsonValue::Object(payload.clone()).to_string().into(),
+    );
+    properties
+}
+
+/// UUID of the object storing the point with the given id.
+fn object_id(id: u64) -> Uuid {
+    Uuid::from_u64_pair(0, id)
+}
+
+/// Translate filters to a GraphQL `where` filter, all filters having to hold.
+pub fn to_where(filters: &[Filter]) -> Result<String> {
+    let operands = filters.iter().map(|filter| where_filter(filter, false)).collect::<Result<Vec<_>>>()?;
+    Ok(combine("And", operands))
+}
+
+/// Translate a filter, or its negation since Weaviate has no `Not` operator.
+fn where_filter(filter: &Filter, negate: bool) -> Result<String> {
+    let (all, any) = if negate { ("Or", "And") } else { ("And", "Or") };
+    match filter {
+        Filter::Matches(field, value) => operand(field, if negate { "NotEqual" } else { "Equal" }, value),
+        Filter::MatchesAny(field, values) => {
+            let operator = if negate { "NotEqual" } else { "Equal" };
+            let operands = values.iter().map(|value| operand(field, operator, value)).collect::<Result<Vec<_>>>()?;
+            Ok(combine(any, operands))
+        }
+        Filter::Range(field, range) => {
+            let bounds = [
+                (range.gt, "GreaterThan", "LessThanEqual"),
+                (range.gte, "GreaterThanEqual", "LessThan"),
+                (range.lt, "LessThan", "GreaterThanEqual"),
+                (range.lte, "LessThanEqual", "GreaterThan"),
+            ];
+            let operands = bounds
+                .iter()
+                .filter_map(|(bound, operator, negated)| {
+                    bound.map(|bound| operand(field, if negate { negated } else { operator }, &bound.into()))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            if operands.is_empty() {
+                return Err(anyhow!("Range on field {} has no bounds", field));
+            }
+            Ok(combine(all, operands))
+        }
+        Filter::Not(filter) => where_filter(filter, !negate),
+    }
+}
+
+/// Condition comparing a property to a value.
+fn operand(field: &str, operator: &str, value: &JsonValue) -> Result<String> {
+    let value_type = match value {
+        JsonValue::String(_) => "valueText",
+        JsonValue::Bool(_) => "valueBoolean",
+        JsonValue::Number(number) if number.is_i64() || number.is_u64() => "valueInt",
+        JsonValue::Number(_) => "valueNumber",
+        value => return Err(anyhow!("Cannot compare field {} to {} in Weaviate", field, value)),
+    };
+    Ok(format!(
+        "{{path: [{}], operator: {}, {}: {}}}",
+        JsonValue::from(property_name(field)),
+        operator,
+        value_type,
+        value
+    ))
+}
+
+fn combine(operator: &str, mut operands: Vec<String>) -> String {
+    match operands.len() {
+        1 => operands.remove(0),
+        _ => format!("{{operator: {}, operands: [{}]}}", operator, operands.join(", ")),
+    }
+}
+
+/// Parse the objects returned by a search of a collection.
+fn parse_hits(collection: &str, data: &JsonValue) -> Result<Vec<SearchHit>> {
+    let objects = data["Get"][class_name(collection)]
+        .as_array()
+        .ok_or(anyhow!("Unexpected Weaviate search response: {}", data))?;
+    objects
+        .iter()
+        .map(|object| {
+            let additional = &object["_additional"];
+            let id: Uuid = additional["id"].as_str().ok_or(anyhow!("Object without an id"))?.parse()?;
+            // Vector searches return the cosine distance, hybrid searches a fused score as a string.
+            let score = match (additional["distance"].as_f64(), &additional["score"]) {
+                (Some(distance), _) => 1.0 - distance as f32,
+                (None, JsonValue::String(score)) => score.parse()?,
+                (None, score) => score.as_f64().unwrap_or_default() as f32,
+            };
+            let payload = match object[PAYLOAD_PROPERTY].as_str().map(serde_json::from_str) {
+                Some(Ok(JsonValue::Object(payload))) => payload,
+                _ => return Err(anyhow!("Object {} without a payload", id)),
+            };
+            Ok(SearchHit {
+                id: id.as_u64_pair().1,
+                score,
+                payload,
+            })
+        })
+        .collect()
+}
+
+#[async_trait::async_trait]
+impl VectorStore for Weaviate {
+    /// Create the class of the collection if it does not exist. Weaviate sets the number of
+    /// dimensions from the first inserted vector.
+    async fn ensure_collection(&self, collection: &str, _dimensions: usize) -> Result<()> {
+        if !self.has_collection(collection).await? {
+            let class = json!({
+                "class": class_name(collection),
+                "vectorizer": "none",
+                "vectorIndexConfig": { "distance": "cosine" },
+                "multiTenancyConfig": { "enabled": self.tenant.is_some() },
+                "properties": [{
+                    "name": PAYLOAD_PROPERTY,
+                    "dataType": ["text"],
+                    "indexFilterable": false,
+                    "indexSearchable": false,
+                }],
+            });
+            let url = format!("{}/v1/schema", self.url);
+            self.request(self.client.post(url).json(&class)).send().await?.error_for_status()?;
+        }
+        match &self.tenant {
+            Some(tenant) => self.ensure_tenant(collection, tenant).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn insert(&self, collection: &str, points: Vec<Point>) -> Result<()> {
+        let objects: Vec<JsonValue> = points
+            .into_iter()
+            .map(|point| {
+                json!({
+                    "class": class_name(collection),
+                    "id": object_id(point.id).to_string(),
+                    "vector": point.vector,
+                    "properties": properties(&point.payload),
+                    "tenant": self.tenant,
+                })
+            })
+            .collect();
+        let url = format!("{}/v1/batch/objects", self.url);
+        let request = self.client.post(url).json(&json!({ "objects": objects }));
+        let results: Vec<JsonValue> = self.request(request).send().await?.error_for_status()?.json().await?;
+        match results.iter().find_map(|result| result["result"].get("errors")) {
+            Some(errors) => Err(OrcaError::VectorStore {
+                store: "Weaviate".to_string(),
+                message: format!("insert failed: {}", errors),
+            }
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    async fn search(&self, collection: &str, query: SearchQuery) -> Result<Vec<SearchHit>> {
+        let data = self.graphql(self.search_query(collection, &query)?).await?;
+        let hits = parse_hits(collection, &data)?;
+        Ok(match (&query.hybrid, query.score_threshold) {
+            // Vector searches apply the threshold as a maximum distance.
+            (Some(_), Some(threshold)) => hits.into_iter().filter(|hit| hit.score >= threshold).collect(),
+            _ => hits,
+        })
+    }
+
+    async fn delete_collection(&self, collection: &str) -> Result<()> {
+        let url = format!("{}/v1/schema/{}", self.url, class_name(collection));
+        self.request(self.client.delete(url)).send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::qdrant::Range;
+
+    #[test]
+    fn test_to_where() {
+        let filters = vec![
+            Filter::Matches("attributes.page".to_string(), 2.into()),
+            Filter::Not(Box::new(Filter::Range(
+                "year".to_string(),
+                Range {
+                    gte: Some(2020.0),
+                    lt: Some(2023.0),
+                    ..Default::default()
+                },
+            ))),
+        ];
+        assert_eq!(
+            to_where(&filters).unwrap(),
+            "{operator: And, operands: [{path: [\"attributes_page\"], operator: Equal, valueInt: 2}, \
+             {operator: Or, operands: [{path: [\"year\"], operator: LessThan, valueNumber: 2020.0}, \
+             {path: [\"year\"], operator: GreaterThanEqual, valueNumber: 2023.0}]}]}"
+        );
+        assert!(to_where(&[Filter::Matches("tags".to_string(), json!(["a"]))]).is_err());
+    }
+
+    #[test]
+    fn test_properties() {
+        let payload = json!({"content": "Orcas live in pods.", "attributes": {"page": 2, "source-url": "a.pdf"}});
+        let properties = properties(payload.as_object().unwrap());
+        assert_eq!(properties["content"], "Orcas live in pods.");
+        assert_eq!(properties["attributes_page"], 2);
+        assert_eq!(properties["attributes_source_url"], "a.pdf");
+        assert_eq!(properties[PAYLOAD_PROPERTY], payload.to_string());
+        assert_eq!(object_id(7).as_u64_pair().1, 7);
+    }
+
+    #[test]
+    fn test_search() {
+        let weaviate = Weaviate::new("http://localhost:8080").with_tenant("acme");
+        let query = SearchQuery::new(vec![0.5]).with_limit(3).with_hybrid("orca pods", 0.25);
+        assert_eq!(
+            weaviate.search_query("papers", &query).unwrap(),
+            "{ Get { Papers(limit: 3, hybrid: {query: \"orca pods\", vector: [0.5], alpha: 0.25}, tenant: \"acme\") \
+             { payload _additional { id distance score } } } }"
+        );
+
+        let data = json!({"Get": {"Papers": [
+            {"payload": "{\"content\":\"Orcas\"}", "_additional": {"id": object_id(4).to_string(), "distance": 0.25}},
+            {"payload": "{\"content\":\"Pods\"}", "_additional": {"id": object_id(5).to_string(), "score": "0.5"}}
+        ]}});
+        let hits = parse_hits("papers", &data).unwrap();
+        assert_eq!((hits[0].id, hits[0].score), (4, 0.75));
+        assert_eq!((hits[1].id, hits[1].score), (5, 0.5));
+        assert_eq!(hits[1].payload["content"], "Pods");
+    }
+}
@@ -34,8 +34,20 @@ impl Bert {
         Ok(Self { bert, tokenizer })
     }
 
+    /// Load a model from the Hugging Face Hub through the global `ModelSource` of orca, so that its cache
+    /// directory, offline mode, mirror and token apply.
     #[cfg(feature = "async")]
     pub async fn from_api(model_id: Option<String>, revision: Option<String>) -> anyhow::Result<Self> {
+        Self::from_source(&orca_core::llm::source::ModelSource::global(), model_id, revision).await
+    }
+
+    /// Load a model from the Hugging Face Hub through the given source.
+    #[cfg(feature = "async")]
+    pub async fn from_source(
+        source: &orca_core::llm::source::ModelSource,
+        model_id: Option<String>,
+        revision: Option<String>,
+    ) -> anyhow::Result<Self> {
         let device = &Device::Cpu;
         let default_model = "sentence-transformers/all-MiniLM-L6-v2".to_string();
         let default_revision = "refs/pr/21".to_string();
@@ -47,11 +59,9 @@ impl Bert {
         };
 
         let repo = hf_hub::Repo::with_revision(model_id, hf_hub::RepoType::Model, revision);
-        let api = hf_hub::api::tokio::Api::new()?;
-        let api = api.repo(repo);
-        let config_filename = api.get("config.json").await?;
-        let tokenizer_filename = api.get("tokenizer.json").await?;
-        let weights_filename = api.get("model.safetensors").await?;
+        let config_filename = source.get(&repo, "config.json").await?;
+        let tokenizer_filename = source.get(&repo, "tokenizer.json").await?;
+        let weights_filename = source.get(&repo, "model.safetensors").await?;
 
         let config = std::fs::read_to_string(config_filename)?;
         let config: Config = serde_json::from_str(&config)?;
@@ -1,3 +1,4 @@
+use crate::quantized::TEST_SEED;
 use crate::utils::text_generation::{Model, TextGeneration};
 use candle::Device;
 use candle_transformers::models::mistral;
@@ -67,6 +68,28 @@ impl Default for Config {
     }
 }
 
+impl Config {
+    /// Samples the most likely token at each step, so that the same prompt always gets the same
+    /// completion.
+    pub fn greedy(mut self) -> Self {
+        self.temperature = 0.0;
+        self.top_p = None;
+        self
+    }
+
+    /// Configuration for tests pinning the generated text: greedy sampling with the seed `TEST_SEED`
+    /// and no repeat penalty.
+    pub fn test_profile() -> Self {
+        Self {
+            seed: TEST_SEED,
+            repeat_penalty: 1.0,
+            repeat_last_n: 1,
+            ..Self::default()
+        }
+        .greedy()
+    }
+}
+
 impl Mistral {
     pub fn from_path<P>(weights: P, tokenizer: P, config: Config) -> anyhow::Result<Self>
     where
@@ -103,16 +126,23 @@ impl Mistral {
         })
     }
 
+    /// Load the model from the Hugging Face Hub through the global `ModelSource` of orca, so that its cache
+    /// directory, offline mode, mirror and token apply.
     #[cfg(feature = "async")]
     pub async fn from_api(config: Config) -> anyhow::Result<Self> {
-        let api = hf_hub::api::tokio::Api::new()?;
-        let repo = api.repo(hf_hub::Repo::with_revision(
+        Self::from_source(&orca_core::llm::source::ModelSource::global(), config).await
+    }
+
+    /// Load the model from the Hugging Face Hub through the given source.
+    #[cfg(feature = "async")]
+    pub async fn from_source(source: &orca_core::llm::source::ModelSource, config: Config) -> anyhow::Result<Self> {
+        let repo = hf_hub::Repo::with_revision(
             config.model_id.unwrap_or_else(|| "lmz/candle-mistral".to_string()),
             hf_hub::RepoType::Model,
             config.revision.unwrap_or_else(|| "main".to_string()),
-        ));
-        let tokenizer = repo.get("tokenizer.json").await?;
-        let model_path = repo.get("model-q4k.gguf").await?;
+        );
+        let tokenizer = source.get(&repo, "tokenizer.json").await?;
+        let model_path = source.get(&repo, "model-q4k.gguf").await?;
         let vb = candle_transformers::quantized_var_builder::VarBuilder::from_gguf(model_path, &Device::Cpu)?;
         let model = quantized_mistral::Model::new(&mistral::Config::config_7b_v0_1(config.flash_attn), vb)?;
         let tokenizer = tokenizers::Tokenizer::from_file(tokenizer).map_err(anyhow::Error::msg)?;
@@ -135,7 +165,7 @@ impl Mistral {
             Model::Mistral(self.model.clone()),
             self.tokenizer.clone(),
             self.seed,
-            Some(self.temperature),
+            (self.temperature > 0.0).then_some(self.temperature),
             self.top_p,
             self.repeat_penalty,
             self.repeat_last_n,
@@ -184,74 +184,29 @@ impl OpenAI {
     }
 
     async fn generate_embeddings(&self, prompts: Vec<String>) -> Result<Vec<Response>> {
-        let num_prompts = prompts.len();
-        let mut embeddings = vec![Response::default(); num_prompts];
-
-        let (sender, mut receiver) = tokio::sync::mpsc::channel(num_prompts);
+        let mut embeddings = vec![Response::default(); prompts.len()];
 
+        // Dropping the set on an error aborts the requests still running.
+        let mut requests = tokio::task::JoinSet::new();
         for (i, prompt) in prompts.into_iter().enumerate() {
-            let sender = sender.clone();
             let client = self.client.clone();
             let req = self.generate_request(&prompt)?;
-
-            tokio::spawn(async move {
+            requests.spawn(async move {
                 let result = async {
                     let res = client.execute(req).await.map_err(|e| format!("Failed to execute request: {}", e))?;
-                    let response = match res.json::<Response>().await {
-                        Ok(response) => response,
-                        Err(e) => {
-                            return Err(format!("Failed to parse response: {}", e));
-                        }
-                    };
-                    Ok(response)
+                    res.json::<Response>().await.map_err(|e| format!("Failed to parse response: {}", e))
                 }
                 .await;
-
-                // Send back the result (success or error) via the channel.
-                if let Err(e) = send_with_exponential_backoff(&sender, (i, result)).await {
-                    // Log the error or take appropriate action
-                    log::error!("Error sending message: {}", e);
-                }
+                (i, result)
             });
         }
 
-        drop(sender);
-
-        while let Some((i, result)) = receiver.recv().await {
-            match result {
-                Ok(response) => {
-                    embeddings[i] = response;
-                }
-                Err(e) => {
-                    return Err(anyhow::anyhow!("Failed to generate embedding index {}: {}", i, e));
-                }
-            }
+        while let Some(joined) = requests.join_next().await {
+            let (i, result) = joined?;
+            embeddings[i] =
+                result.map_err(|e| anyhow::anyhow!("Failed to generate embedding index {}: {}", i, e))?;
         }
 
         Ok(embeddings)
     }
 }
-
-const MAX_RETRIES: u32 = 5;
-const INITIAL_BACKOFF: tokio::time::Duration = tokio::time::Duration::from_millis(100);
-const MAX_BACKOFF: tokio::time::Duration = tokio::time::Duration::from_secs(10);
-
-async fn send_with_exponential_backoff<T>(sender: &tokio::sync::mpsc::Sender<T>, message: T) -> Result<(), String>
-where
-    T: Clone + Send + 'static,
-{
-    let mut attempts = 0;
-    let mut backoff = INITIAL_BACKOFF;
-
-    loop {
-        match sender.send(message.clone()).await {
-            Ok(_) => return Ok(()),
-            Err(_) if attempts < MAX_RETRIES => {
-                attempts += 1;
-                tokio::time::sleep(backoff).await;
-                backoff = std::cmp::min(backoff * 2, MAX_BACKOFF);
-            }
-            Err(e) => return Err(format!("Failed to send message: {}", e)),
-        }
-    }
-}
@@ -9,6 +9,9 @@ use candle_transformers::models::quantized_llama::ModelWeights;
 
 use crate::utils::text_generation::{Model, TextGeneration};
 
+/// Seed used by the test profile of the models.
+pub const TEST_SEED: u64 = 42;
+
 pub struct Config {
     /// The temperature used to generate samples, use 0 for greedy sampling.
     pub temperature: f64,
@@ -38,6 +41,28 @@ impl Default for Config {
     }
 }
 
+impl Config {
+    /// Samples the most likely token at each step, so that the same prompt always gets the same
+    /// completion.
+    pub fn greedy(mut self) -> Self {
+        self.temperature = 0.0;
+        self.top_p = None;
+        self
+    }
+
+    /// Configuration for tests pinning the generated text: greedy sampling with the seed `TEST_SEED`
+    /// and no repeat penalty.
+    pub fn test_profile() -> Self {
+        Self {
+            seed: TEST_SEED,
+            repeat_penalty: 1.0,
+            repeat_last_n: 1,
+            ..Self::default()
+        }
+        .greedy()
+    }
+}
+
 pub struct Quantized {
     /// The model weights.
     model: ModelWeights,
@@ -102,7 +127,7 @@ impl Quantized {
             Model::Quantized(self.model.clone()),
             self.tokenizer.clone(),
             self.seed,
-            Some(self.temperature),
+            (self.temperature > 0.0).then_some(self.temperature),
             self.top_p,
             self.repeat_penalty,
             self.repeat_last_n,